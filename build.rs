@@ -20,12 +20,35 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .file_descriptor_set_path(format!("{}/secret_descriptor.bin", proto_root.display()))
         .compile_with_config(config, &["proto/secret.proto"], &["proto"])?;
 
+    // Client for shipping captured output to the aggregator
+    let log_proto = proto_root.join("log_service.proto");
+    println!("cargo:rerun-if-changed={}", log_proto.display());
+    tonic_build::configure()
+        .build_server(false)
+        .out_dir("src/log_shipping")
+        .compile(&["proto/log_service.proto"], &["proto"])?;
+
+    // Server the Artisan manager queries runners through, plus its client
+    let status_proto = proto_root.join("status_service.proto");
+    println!("cargo:rerun-if-changed={}", status_proto.display());
+    tonic_build::configure()
+        .out_dir("src/status_server")
+        .compile(&["proto/status_service.proto"], &["proto"])?;
+
     // Copy files to the out dir
     let binding = env::var("OUT_DIR")?;
     let out_dir = Path::new(&binding);
     let generated = Path::new("src/secrets/secret_service.rs");
     let dest = out_dir.join("secret_service.rs");
     fs::copy(generated, dest)?;
+    fs::copy(
+        Path::new("src/log_shipping/log_service.rs"),
+        out_dir.join("log_service.rs"),
+    )?;
+    fs::copy(
+        Path::new("src/status_server/status_service.rs"),
+        out_dir.join("status_service.rs"),
+    )?;
 
     Ok(())
 }
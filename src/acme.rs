@@ -0,0 +1,677 @@
+//! ACME (Let's Encrypt) certificates for the built-in server.
+//!
+//! With `[app_specific.acme] enabled = true` the runner obtains a
+//! certificate for `domains` using the HTTP-01 challenge, answered by the
+//! built-in static server, and renews it once it gets within
+//! `renew_before_days` of expiring. The certificate is kept in `cert_dir`,
+//! optionally mirrored to the secret server so a fresh host doesn't need to
+//! request a new one, swapped into the HTTPS listener without a restart and
+//! announced to anything else (e.g. a reverse proxy) through `renew_hook`.
+
+use artisan_middleware::dusa_collection_utils;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use dusa_collection_utils::core::logger::LogLevel;
+use ring::{
+    digest,
+    rand::SystemRandom,
+    signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair, KeyPair},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::process::Command;
+use tokio_rustls::rustls::ServerConfig;
+
+use crate::global_child::{
+    GLOBAL_ACME_CHALLENGES, GLOBAL_CLINENT_CONNECTION, GLOBAL_TLS_CONFIG, get_query,
+};
+use crate::log;
+
+const LETS_ENCRYPT: &str = "https://acme-v02.api.letsencrypt.org/directory";
+
+/// How often the certificate's expiry is checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// Polling of pending authorizations and orders.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const POLL_ATTEMPTS: u32 = 30;
+
+/// `[app_specific.acme]`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AcmeConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Names the certificate is issued for, the first is the primary one.
+    #[serde(default)]
+    pub domains: Vec<String>,
+    /// Account contact, e.g. `mailto:ops@example.com`.
+    #[serde(default)]
+    pub contact: Option<String>,
+    /// ACME directory, defaults to Let's Encrypt production.
+    #[serde(default = "default_acme_directory")]
+    pub directory: String,
+    /// Where the account key and certificate are kept, defaults to
+    /// `/var/lib/ais_runner/<app_name>/acme`.
+    #[serde(default)]
+    pub cert_dir: Option<String>,
+    #[serde(default = "default_renew_before_days")]
+    pub renew_before_days: u64,
+    /// Serve the static site over HTTPS on this address.
+    #[serde(default)]
+    pub tls_bind: Option<String>,
+    /// Command run after every new certificate, e.g. to reload a proxy.
+    #[serde(default)]
+    pub renew_hook: Option<String>,
+    /// Mirror the certificate and key to the secret server.
+    #[serde(default)]
+    pub store_in_secrets: bool,
+}
+
+impl Default for AcmeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            domains: Vec::new(),
+            contact: None,
+            directory: default_acme_directory(),
+            cert_dir: None,
+            renew_before_days: default_renew_before_days(),
+            tls_bind: None,
+            renew_hook: None,
+            store_in_secrets: false,
+        }
+    }
+}
+
+fn default_acme_directory() -> String {
+    String::from(LETS_ENCRYPT)
+}
+
+fn default_renew_before_days() -> u64 {
+    30
+}
+
+impl AcmeConfig {
+    pub fn cert_dir(&self, app_name: &str) -> PathBuf {
+        match &self.cert_dir {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(format!("/var/lib/ais_runner/{}/acme", app_name)),
+        }
+    }
+
+    /// Check the definition without contacting the ACME server.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.domains.is_empty() {
+            return Err(String::from("acme needs at least one domain"));
+        }
+        if let Some(hook) = &self.renew_hook {
+            shell_words::split(hook)
+                .map_err(|err| format!("acme renew_hook can't be parsed: {}", err))?;
+        }
+        Ok(())
+    }
+
+    fn secret_key(&self, part: &str) -> String {
+        let domain = self.domains.first().map(String::as_str).unwrap_or_default();
+        format!("acme_{}_{}", domain.replace(['.', '-', '*'], "_"), part)
+    }
+}
+
+/// Certificate, key and ACME account key on disk.
+pub struct CertStore {
+    dir: PathBuf,
+}
+
+impl CertStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    pub fn cert_path(&self) -> PathBuf {
+        self.dir.join("cert.pem")
+    }
+
+    pub fn key_path(&self) -> PathBuf {
+        self.dir.join("key.pem")
+    }
+
+    fn account_key_path(&self) -> PathBuf {
+        self.dir.join("account.key")
+    }
+
+    /// The directory and every file kept in it.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        vec![
+            self.dir.clone(),
+            self.cert_path(),
+            self.key_path(),
+            self.account_key_path(),
+        ]
+    }
+
+    /// The stored certificate chain and key, if both exist.
+    pub fn load(&self) -> Option<(String, String)> {
+        let cert = fs::read_to_string(self.cert_path()).ok()?;
+        let key = fs::read_to_string(self.key_path()).ok()?;
+        Some((cert, key))
+    }
+
+    pub fn save(&self, cert: &str, key: &str) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        write_private(&self.key_path(), key.as_bytes())?;
+        write_private(&self.cert_path(), cert.as_bytes())
+    }
+
+    fn account_key(&self, rng: &SystemRandom) -> Result<EcdsaKeyPair, String> {
+        let path = self.account_key_path();
+        let pkcs8 = match fs::read(&path) {
+            Ok(pkcs8) => pkcs8,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                let generated = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, rng)
+                    .map_err(|_| String::from("Failed to generate an ACME account key"))?;
+                fs::create_dir_all(&self.dir)
+                    .and_then(|_| write_private(&path, generated.as_ref()))
+                    .map_err(|err| format!("Failed to store the ACME account key: {}", err))?;
+                generated.as_ref().to_vec()
+            }
+            Err(err) => return Err(format!("Failed to read the ACME account key: {}", err)),
+        };
+
+        EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &pkcs8, rng)
+            .map_err(|err| format!("The ACME account key is unusable: {}", err))
+    }
+}
+
+/// Write a file only the runner's user can read, replacing it atomically.
+fn write_private(path: &Path, content: &[u8]) -> io::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    // A leftover from an interrupted write may have other permissions
+    match fs::remove_file(&temp) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+        _ => {}
+    }
+    // Created with its final mode, never readable by others in between
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&temp)?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    fs::rename(&temp, path)
+}
+
+/// Unix time the first certificate in `pem` stops being valid.
+pub fn not_after(pem: &str) -> Option<i64> {
+    let (_, pem) = x509_parser::pem::parse_x509_pem(pem.as_bytes()).ok()?;
+    let cert = pem.parse_x509().ok()?;
+    Some(cert.validity().not_after.timestamp())
+}
+
+/// Whether `cert` is missing, unreadable or expires within `days`.
+pub fn needs_renewal(cert: Option<&str>, days: u64, now: i64) -> bool {
+    match cert.and_then(not_after) {
+        Some(expiry) => expiry - now < (days * 24 * 60 * 60) as i64,
+        None => true,
+    }
+}
+
+/// rustls config serving `cert` with `key`.
+pub fn server_config(cert: &str, key: &str) -> Result<Arc<ServerConfig>, String> {
+    let certs = rustls_pemfile::certs(&mut cert.as_bytes())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| format!("Invalid certificate: {}", err))?;
+    let key = rustls_pemfile::private_key(&mut key.as_bytes())
+        .map_err(|err| format!("Invalid private key: {}", err))?
+        .ok_or_else(|| String::from("No private key found"))?;
+
+    ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map(Arc::new)
+        .map_err(|err| format!("Unusable certificate: {}", err))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+/// Minimal RFC 8555 client, just enough for HTTP-01 issuance.
+struct AcmeClient {
+    http: reqwest::Client,
+    directory: Directory,
+    rng: SystemRandom,
+    key: EcdsaKeyPair,
+    kid: Option<String>,
+    nonce: Option<String>,
+}
+
+fn b64(data: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(data)
+}
+
+impl AcmeClient {
+    async fn connect(directory: &str, store: &CertStore) -> Result<Self, String> {
+        let http = reqwest::Client::new();
+        let directory: Directory = http
+            .get(directory)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| format!("Can't reach ACME directory {}: {}", directory, err))?
+            .json()
+            .await
+            .map_err(|err| format!("Invalid ACME directory: {}", err))?;
+
+        let rng = SystemRandom::new();
+        let key = store.account_key(&rng)?;
+        Ok(Self {
+            http,
+            directory,
+            rng,
+            key,
+            kid: None,
+            nonce: None,
+        })
+    }
+
+    fn jwk(&self) -> Value {
+        // Uncompressed point, 0x04 || x || y
+        let point = self.key.public_key().as_ref();
+        json!({
+            "crv": "P-256",
+            "kty": "EC",
+            "x": b64(&point[1..33]),
+            "y": b64(&point[33..65]),
+        })
+    }
+
+    /// RFC 7638 thumbprint, part of every key authorization.
+    fn thumbprint(&self) -> String {
+        let jwk = self.jwk();
+        // Members in lexicographic order without whitespace
+        let canonical = format!(
+            r#"{{"crv":"P-256","kty":"EC","x":"{}","y":"{}"}}"#,
+            jwk["x"].as_str().unwrap_or_default(),
+            jwk["y"].as_str().unwrap_or_default()
+        );
+        b64(digest::digest(&digest::SHA256, canonical.as_bytes()).as_ref())
+    }
+
+    async fn nonce(&mut self) -> Result<String, String> {
+        if let Some(nonce) = self.nonce.take() {
+            return Ok(nonce);
+        }
+
+        let response = self
+            .http
+            .head(&self.directory.new_nonce)
+            .send()
+            .await
+            .map_err(|err| format!("Failed to get an ACME nonce: {}", err))?;
+        replay_nonce(&response).ok_or_else(|| String::from("ACME server sent no nonce"))
+    }
+
+    /// Signed POST, `None` as payload is a POST-as-GET.
+    async fn post(
+        &mut self,
+        url: &str,
+        payload: Option<&Value>,
+    ) -> Result<reqwest::Response, String> {
+        // One retry, servers may reject a nonce that went stale
+        let mut retried = false;
+        loop {
+            let mut protected = json!({
+                "alg": "ES256",
+                "nonce": self.nonce().await?,
+                "url": url,
+            });
+            match &self.kid {
+                Some(kid) => protected["kid"] = json!(kid),
+                None => protected["jwk"] = self.jwk(),
+            }
+
+            let protected = b64(protected.to_string().as_bytes());
+            let payload = payload
+                .map(|payload| b64(payload.to_string().as_bytes()))
+                .unwrap_or_default();
+            let signature = self
+                .key
+                .sign(&self.rng, format!("{}.{}", protected, payload).as_bytes())
+                .map_err(|_| String::from("Failed to sign ACME request"))?;
+            let body = json!({
+                "protected": protected,
+                "payload": payload,
+                "signature": b64(signature.as_ref()),
+            });
+
+            let response = self
+                .http
+                .post(url)
+                .header("Content-Type", "application/jose+json")
+                .body(body.to_string())
+                .send()
+                .await
+                .map_err(|err| format!("ACME request to {} failed: {}", url, err))?;
+            self.nonce = replay_nonce(&response);
+
+            if response.status().is_success() {
+                return Ok(response);
+            }
+
+            let problem: Value = response.json().await.unwrap_or_default();
+            let kind = problem["type"].as_str().unwrap_or_default();
+            if kind.ends_with(":badNonce") && !retried {
+                retried = true;
+                continue;
+            }
+            return Err(format!(
+                "ACME server refused {}: {} {}",
+                url,
+                kind,
+                problem["detail"].as_str().unwrap_or_default()
+            ));
+        }
+    }
+
+    async fn post_json(
+        &mut self,
+        url: &str,
+        payload: Option<&Value>,
+    ) -> Result<(Value, Option<String>), String> {
+        let response = self.post(url, payload).await?;
+        let location = response
+            .headers()
+            .get("Location")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+        let body = response
+            .json()
+            .await
+            .map_err(|err| format!("Invalid ACME response from {}: {}", url, err))?;
+        Ok((body, location))
+    }
+
+    async fn register(&mut self, contact: Option<&String>) -> Result<(), String> {
+        let mut payload = json!({ "termsOfServiceAgreed": true });
+        if let Some(contact) = contact {
+            payload["contact"] = json!([contact]);
+        }
+
+        let url = self.directory.new_account.clone();
+        let (_, location) = self.post_json(&url, Some(&payload)).await?;
+        self.kid = Some(location.ok_or_else(|| String::from("ACME account has no location"))?);
+        Ok(())
+    }
+
+    /// POST-as-GET `url` until `status` leaves `pending`/`processing`.
+    async fn poll(&mut self, url: &str) -> Result<Value, String> {
+        for _ in 0..POLL_ATTEMPTS {
+            let (body, _) = self.post_json(url, None).await?;
+            match body["status"].as_str() {
+                Some("pending") | Some("processing") => tokio::time::sleep(POLL_INTERVAL).await,
+                _ => return Ok(body),
+            }
+        }
+        Err(format!("ACME object {} is still pending", url))
+    }
+
+    async fn authorize(&mut self, url: &str) -> Result<(), String> {
+        let (authorization, _) = self.post_json(url, None).await?;
+        if authorization["status"] == "valid" {
+            return Ok(());
+        }
+
+        let challenge = authorization["challenges"]
+            .as_array()
+            .and_then(|challenges| {
+                challenges
+                    .iter()
+                    .find(|challenge| challenge["type"] == "http-01")
+            })
+            .ok_or_else(|| String::from("ACME server offered no http-01 challenge"))?;
+        let token = challenge["token"].as_str().unwrap_or_default().to_owned();
+        let challenge_url = challenge["url"].as_str().unwrap_or_default().to_owned();
+
+        let key_authorization = format!("{}.{}", token, self.thumbprint());
+        GLOBAL_ACME_CHALLENGES
+            .lock()
+            .await
+            .insert(token.clone(), key_authorization);
+
+        let result = async {
+            self.post_json(&challenge_url, Some(&json!({}))).await?;
+            let authorization = self.poll(url).await?;
+            match authorization["status"].as_str() {
+                Some("valid") => Ok(()),
+                status => Err(format!(
+                    "ACME authorization for {} is {}",
+                    authorization["identifier"]["value"],
+                    status.unwrap_or("unknown")
+                )),
+            }
+        }
+        .await;
+
+        GLOBAL_ACME_CHALLENGES.lock().await.remove(&token);
+        result
+    }
+}
+
+fn replay_nonce(response: &reqwest::Response) -> Option<String> {
+    response
+        .headers()
+        .get("Replay-Nonce")
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned)
+}
+
+/// Request a new certificate, returning the PEM chain and key.
+pub async fn issue(config: &AcmeConfig, store: &CertStore) -> Result<(String, String), String> {
+    let mut client = AcmeClient::connect(&config.directory, store).await?;
+    client.register(config.contact.as_ref()).await?;
+
+    let identifiers: Vec<Value> = config
+        .domains
+        .iter()
+        .map(|domain| json!({ "type": "dns", "value": domain }))
+        .collect();
+    let new_order = client.directory.new_order.clone();
+    let (order, order_url) = client
+        .post_json(&new_order, Some(&json!({ "identifiers": identifiers })))
+        .await?;
+    let order_url = order_url.ok_or_else(|| String::from("ACME order has no location"))?;
+
+    let authorizations: Vec<String> = order["authorizations"]
+        .as_array()
+        .map(|urls| {
+            urls.iter()
+                .filter_map(|url| url.as_str().map(str::to_owned))
+                .collect()
+        })
+        .unwrap_or_default();
+    for authorization in &authorizations {
+        client.authorize(authorization).await?;
+    }
+
+    let key_pair =
+        rcgen::KeyPair::generate().map_err(|err| format!("Failed to generate a key: {}", err))?;
+    let csr = rcgen::CertificateParams::new(config.domains.clone())
+        .and_then(|params| params.serialize_request(&key_pair))
+        .map_err(|err| format!("Failed to create a CSR: {}", err))?;
+
+    let finalize = order["finalize"].as_str().unwrap_or_default().to_owned();
+    client
+        .post_json(&finalize, Some(&json!({ "csr": b64(csr.der()) })))
+        .await?;
+
+    let order = client.poll(&order_url).await?;
+    let certificate = match (order["status"].as_str(), order["certificate"].as_str()) {
+        (Some("valid"), Some(url)) => url.to_owned(),
+        (status, _) => {
+            return Err(format!(
+                "ACME order ended as {}",
+                status.unwrap_or("unknown")
+            ));
+        }
+    };
+
+    let chain = client
+        .post(&certificate, None)
+        .await?
+        .text()
+        .await
+        .map_err(|err| format!("Failed to download the certificate: {}", err))?;
+    Ok((chain, key_pair.serialize_pem()))
+}
+
+async fn load_from_secrets(config: &AcmeConfig) -> Option<(String, String)> {
+    let client = GLOBAL_CLINENT_CONNECTION.lock().await.clone()?;
+    let query = get_query().ok()?;
+
+    let cert = query
+        .get_val(client.clone(), &config.secret_key("cert"))
+        .await
+        .ok()??;
+    let key = query
+        .get_val(client, &config.secret_key("key"))
+        .await
+        .ok()??;
+    Some((String::from_utf8(cert).ok()?, String::from_utf8(key).ok()?))
+}
+
+async fn store_in_secrets(config: &AcmeConfig, cert: &str, key: &str) -> Result<(), String> {
+    let client = GLOBAL_CLINENT_CONNECTION
+        .lock()
+        .await
+        .clone()
+        .ok_or_else(|| String::from("not connected to a secret server"))?;
+    let query = get_query().map_err(|_| String::from("no secret query loaded"))?;
+
+    for (part, value) in [("cert", cert), ("key", key)] {
+        query
+            .put_val(client.clone(), &config.secret_key(part), value)
+            .await
+            .map_err(|err| err.to_string())?;
+    }
+    Ok(())
+}
+
+async fn run_renew_hook(hook: &str) {
+    let parts = match shell_words::split(hook) {
+        Ok(parts) => parts,
+        Err(err) => {
+            log!(LogLevel::Error, "acme renew_hook can't be parsed: {}", err);
+            return;
+        }
+    };
+    let (program, args) = match parts.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+
+    match Command::new(program).args(args).status().await {
+        Ok(status) if status.success() => log!(LogLevel::Debug, "acme renew_hook finished"),
+        Ok(status) => log!(LogLevel::Warn, "acme renew_hook exited with {}", status),
+        Err(err) => log!(LogLevel::Error, "acme renew_hook failed to start: {}", err),
+    }
+}
+
+/// Make sure a valid certificate is installed, renewing it if needed.
+async fn refresh(
+    config: &AcmeConfig,
+    store: &CertStore,
+    installed: &mut bool,
+) -> Result<(), String> {
+    let mut current = store.load();
+    if current.is_none() && config.store_in_secrets {
+        current = load_from_secrets(config).await;
+        if let Some((cert, key)) = &current {
+            log!(
+                LogLevel::Info,
+                "Restored certificate from the secret server"
+            );
+            store.save(cert, key).map_err(|err| err.to_string())?;
+        }
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or_default();
+    let renew = needs_renewal(
+        current.as_ref().map(|(cert, _)| cert.as_str()),
+        config.renew_before_days,
+        now,
+    );
+
+    if renew {
+        log!(
+            LogLevel::Info,
+            "Requesting certificate for {}",
+            config.domains.join(", ")
+        );
+        let (cert, key) = issue(config, store).await?;
+        store
+            .save(&cert, &key)
+            .map_err(|err| format!("Failed to store the certificate: {}", err))?;
+        log!(
+            LogLevel::Info,
+            "New certificate stored in {}",
+            store.dir.display()
+        );
+
+        let mirrored = match config.store_in_secrets {
+            true => store_in_secrets(config, &cert, &key).await,
+            false => Ok(()),
+        };
+        if let Err(err) = mirrored {
+            log!(
+                LogLevel::Warn,
+                "Failed to mirror the certificate to the secret server: {}",
+                err
+            );
+        }
+        current = Some((cert, key));
+    }
+
+    match &current {
+        Some((cert, key)) if renew || !*installed => {
+            *GLOBAL_TLS_CONFIG.lock().await = Some(server_config(cert, key)?);
+            *installed = true;
+        }
+        _ => (),
+    }
+
+    match &config.renew_hook {
+        Some(hook) if renew => run_renew_hook(hook).await,
+        _ => (),
+    }
+    Ok(())
+}
+
+/// Keep the certificate valid in the background.
+pub fn watch_certificates(config: AcmeConfig, app_name: String) {
+    let store = CertStore::new(config.cert_dir(&app_name));
+    tokio::spawn(async move {
+        let mut installed = false;
+        loop {
+            if let Err(err) = refresh(&config, &store, &mut installed).await {
+                log!(LogLevel::Error, "Certificate renewal failed: {}", err);
+            }
+            tokio::time::sleep(CHECK_INTERVAL).await;
+        }
+    });
+}
@@ -0,0 +1,150 @@
+//! Status fields contributed by the child.
+//!
+//! The state only carries a free form `data` line, which says little about
+//! how the app itself is doing. The child can add its own key/value pairs,
+//! e.g. `queue_depth`, in two ways:
+//!
+//! - `APP_<key>=<value>` over the heartbeat socket (see [`crate::heartbeat`]),
+//!   an empty value removes the key,
+//! - a JSON object in the file named by `[app_specific.app_status] file`,
+//!   re-read on every periodic check. Its values override those sent over
+//!   the socket.
+//!
+//! The merged fields are kept in the runner state sidecar for `status` and
+//! appended to `data`, which is what reaches the aggregator.
+
+use artisan_middleware::dusa_collection_utils;
+use dusa_collection_utils::core::logger::LogLevel;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::log;
+
+/// Fields kept at most, later keys are dropped.
+pub const MAX_FIELDS: usize = 32;
+/// Longer values are cut, the state is written often.
+pub const MAX_VALUE_LEN: usize = 256;
+
+/// Status fields by key.
+pub type AppStatus = BTreeMap<String, String>;
+
+/// `[app_specific.app_status]`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct AppStatusConfig {
+    /// JSON status file written by the child, relative to `project_path`.
+    #[serde(default)]
+    pub file: Option<String>,
+}
+
+impl AppStatusConfig {
+    /// Where the status file of the project in `project_path` is.
+    pub fn path(&self, project_path: &str) -> Option<PathBuf> {
+        let file = self.file.as_ref()?;
+        Some(Path::new(project_path).join(file))
+    }
+}
+
+/// Set `key` to `value` within the limits, an empty value removes it.
+pub fn set(fields: &mut AppStatus, key: &str, value: &str) {
+    let key = key.trim();
+    if key.is_empty() {
+        return;
+    }
+    if value.is_empty() {
+        fields.remove(key);
+        return;
+    }
+    if fields.len() >= MAX_FIELDS && !fields.contains_key(key) {
+        return;
+    }
+
+    let end = value
+        .char_indices()
+        .map(|(index, _)| index)
+        .nth(MAX_VALUE_LEN)
+        .unwrap_or(value.len());
+    fields.insert(key.to_owned(), value[..end].to_owned());
+}
+
+/// Read a status file, a missing file has no fields.
+///
+/// Strings are taken as they are, other values as their JSON.
+pub fn read_file(path: &Path) -> Result<AppStatus, String> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(AppStatus::new()),
+        Err(err) => return Err(format!("Failed to read {}: {}", path.display(), err)),
+    };
+
+    let object: serde_json::Map<String, serde_json::Value> = serde_json::from_str(&content)
+        .map_err(|err| format!("{} isn't a JSON object: {}", path.display(), err))?;
+
+    let mut fields = AppStatus::new();
+    for (key, value) in &object {
+        match value {
+            serde_json::Value::String(value) => set(&mut fields, key, value),
+            serde_json::Value::Null => (),
+            value => set(&mut fields, key, &value.to_string()),
+        }
+    }
+    Ok(fields)
+}
+
+/// `data` with the fields appended, e.g. `Nominal (queue_depth=12)`.
+pub fn describe(data: &str, fields: &AppStatus) -> String {
+    if fields.is_empty() {
+        return data.to_owned();
+    }
+
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect();
+    format!("{} ({})", data, fields.join(", "))
+}
+
+/// Merges what the child reported over the socket with its status file.
+#[derive(Debug, Default)]
+pub struct AppStatusTracker {
+    file: Option<PathBuf>,
+    /// Last good read, kept while the child rewrites the file.
+    from_file: AppStatus,
+    current: AppStatus,
+}
+
+impl AppStatusTracker {
+    pub fn new(file: Option<PathBuf>) -> Self {
+        Self {
+            file,
+            ..Self::default()
+        }
+    }
+
+    pub fn current(&self) -> &AppStatus {
+        &self.current
+    }
+
+    /// Re-read the status file and merge it over `from_child`, returning
+    /// whether the fields changed.
+    pub fn update(&mut self, from_child: &AppStatus) -> bool {
+        if let Some(file) = &self.file {
+            match read_file(file) {
+                Ok(fields) => self.from_file = fields,
+                Err(err) => log!(LogLevel::Debug, "Keeping the previous app status: {}", err),
+            }
+        }
+
+        let mut merged = from_child.clone();
+        for (key, value) in &self.from_file {
+            set(&mut merged, key, value);
+        }
+
+        let changed = merged != self.current;
+        self.current = merged;
+        changed
+    }
+}
@@ -0,0 +1,210 @@
+//! Files that only mean something while the runner runs.
+//!
+//! The child's pid file, the env file and the heartbeat socket are recorded
+//! in a manifest at `<state file>.artifacts` as they are created, and removed
+//! again on a graceful shutdown. A runner that crashed or was killed leaves
+//! its manifest behind, the next start removes what it lists. Pid files of
+//! children that are gone are swept from `/tmp` as well, they piled up there
+//! before the manifest existed.
+//!
+//! The state, its `.runner` sidecar, the capture files, the output journal
+//! and queued notifications are kept across restarts on purpose and aren't
+//! tracked.
+
+use artisan_middleware::dusa_collection_utils;
+use dusa_collection_utils::core::{logger::LogLevel, types::pathtype::PathType};
+use nix::{sys::signal, unistd::Pid};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeSet,
+    ffi::OsString,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use crate::{global_child::GLOBAL_ARTIFACTS, log};
+
+/// Suffix of the pid files written for children.
+const PID_FILE_SUFFIX: &str = "_pg.pid";
+
+/// Manifest location for the state file at `state_path`.
+pub fn manifest_path(state_path: &PathType) -> PathBuf {
+    PathBuf::from(format!("{}.artifacts", state_path))
+}
+
+/// Pid file of `app_name`'s child.
+pub fn pid_file(app_name: &str) -> PathBuf {
+    PathBuf::from(format!("/tmp/.{}{}", app_name, PID_FILE_SUFFIX))
+}
+
+/// What a run created, as written to the manifest.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct Manifest {
+    /// Runner that created the files.
+    pub pid: u32,
+    pub paths: BTreeSet<PathBuf>,
+}
+
+impl Manifest {
+    /// Load a manifest, `None` if there is none or it's unreadable.
+    pub fn load(path: &Path) -> Option<Self> {
+        let content = fs::read_to_string(path).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    /// Write the manifest atomically.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let temp = temp_sibling(path);
+        fs::write(&temp, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(&temp, path)
+    }
+}
+
+/// `<path>.tmp`, used by the atomic writes throughout the runner.
+fn temp_sibling(path: &Path) -> PathBuf {
+    let mut temp: OsString = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    PathBuf::from(temp)
+}
+
+fn pid_alive(pid: u32) -> bool {
+    signal::kill(Pid::from_raw(pid as i32), None).is_ok()
+}
+
+/// Remove `path` and what an interrupted atomic write of it left, returning
+/// how many files went away.
+pub fn remove(path: &Path) -> usize {
+    [path.to_path_buf(), temp_sibling(path)]
+        .iter()
+        .filter(|path| match fs::remove_file(path) {
+            Ok(()) => true,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => false,
+            Err(err) => {
+                log!(
+                    LogLevel::Warn,
+                    "Failed to remove {}: {}",
+                    path.display(),
+                    err
+                );
+                false
+            }
+        })
+        .count()
+}
+
+/// Remove what the run that wrote `manifest` left behind, returning how
+/// many files went away.
+///
+/// Nothing is touched while that runner is still alive.
+pub fn sweep_previous(manifest: &Path) -> Result<usize, String> {
+    let previous = match Manifest::load(manifest) {
+        Some(previous) => previous,
+        None => return Ok(remove(manifest)),
+    };
+    if previous.pid != std::process::id() && pid_alive(previous.pid) {
+        return Err(format!(
+            "Runner {} still owns {}, leaving its files alone",
+            previous.pid,
+            manifest.display()
+        ));
+    }
+
+    let removed = previous.paths.iter().map(|path| remove(path)).sum();
+    remove(manifest);
+    Ok(removed)
+}
+
+/// Remove pid files in `dir` whose process is gone, returning how many went
+/// away.
+pub fn sweep_pid_files(dir: &Path) -> usize {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return 0,
+    };
+
+    entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with('.') && name.ends_with(PID_FILE_SUFFIX))
+        })
+        .filter(|path| {
+            let pid = fs::read_to_string(path)
+                .ok()
+                .and_then(|content| content.trim().parse::<u32>().ok());
+            !pid.is_some_and(pid_alive)
+        })
+        .map(|path| remove(&path))
+        .sum()
+}
+
+/// Files created by this run.
+#[derive(Debug)]
+pub struct Artifacts {
+    manifest: PathBuf,
+    tracked: Mutex<Manifest>,
+}
+
+impl Artifacts {
+    pub fn new(manifest: PathBuf) -> Self {
+        Self {
+            manifest,
+            tracked: Mutex::new(Manifest {
+                pid: std::process::id(),
+                paths: BTreeSet::new(),
+            }),
+        }
+    }
+
+    /// Record `path`, the manifest is written right away so a crash doesn't
+    /// lose it.
+    pub fn track(&self, path: &Path) {
+        let mut tracked = match self.tracked.lock() {
+            Ok(tracked) => tracked,
+            Err(_) => return,
+        };
+        if !tracked.paths.insert(path.to_path_buf()) {
+            return;
+        }
+        if let Err(err) = tracked.save(&self.manifest) {
+            log!(
+                LogLevel::Warn,
+                "Failed to record {} in {}: {}",
+                path.display(),
+                self.manifest.display(),
+                err
+            );
+        }
+    }
+
+    /// Remove every tracked file and the manifest, returning how many files
+    /// went away.
+    pub fn clean_up(&self) -> usize {
+        let mut tracked = match self.tracked.lock() {
+            Ok(tracked) => tracked,
+            Err(_) => return 0,
+        };
+        let removed = tracked.paths.iter().map(|path| remove(path)).sum();
+        tracked.paths.clear();
+        remove(&self.manifest);
+        removed
+    }
+}
+
+/// Record `path` as created by this run.
+pub fn track(path: &Path) {
+    if let Some(artifacts) = GLOBAL_ARTIFACTS.get() {
+        artifacts.track(path);
+    }
+}
+
+/// Remove what this run created, called on a graceful shutdown.
+pub fn clean_up() {
+    if let Some(artifacts) = GLOBAL_ARTIFACTS.get() {
+        let removed = artifacts.clean_up();
+        log!(LogLevel::Debug, "Removed {} runtime files", removed);
+    }
+}
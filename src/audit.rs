@@ -0,0 +1,104 @@
+//! Audit log of control commands.
+//!
+//! Operator subcommands that act on a running instance (`restart`,
+//! `annotate`, reading its state or output) are appended to a JSON lines
+//! log at `<state path>.audit` together with the credentials of the process
+//! that issued them and how they ended. The file is only ever opened for
+//! appending, `audit` prints it.
+
+use artisan_middleware::dusa_collection_utils::core::types::pathtype::PathType;
+use nix::unistd::{Gid, Pid, Uid, User};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::PathBuf,
+};
+
+/// One control command.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AuditEntry {
+    pub timestamp: u64,
+    pub uid: u32,
+    pub gid: u32,
+    pub pid: i32,
+    /// Login name of `uid`, if it resolves.
+    #[serde(default)]
+    pub user: Option<String>,
+    /// `SUDO_USER` of the caller, the person behind a `sudo ais_runner ...`.
+    #[serde(default)]
+    pub sudo_user: Option<String>,
+    /// The subcommand with its arguments, e.g. `restart --note "..."`.
+    pub command: String,
+    pub success: bool,
+    /// Error message of a failed command.
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+impl AuditEntry {
+    /// Entry for `command` issued by the current process.
+    pub fn new(timestamp: u64, command: String, result: &Result<(), String>) -> Self {
+        let uid = Uid::current();
+        Self {
+            timestamp,
+            uid: uid.as_raw(),
+            gid: Gid::current().as_raw(),
+            pid: Pid::this().as_raw(),
+            user: User::from_uid(uid).ok().flatten().map(|user| user.name),
+            sudo_user: std::env::var("SUDO_USER").ok(),
+            command,
+            success: result.is_ok(),
+            error: result.as_ref().err().cloned(),
+        }
+    }
+
+    /// Who issued the command, for display.
+    pub fn caller(&self) -> String {
+        let user = match &self.user {
+            Some(user) => format!("{}({})", user, self.uid),
+            None => self.uid.to_string(),
+        };
+        match &self.sudo_user {
+            Some(sudo_user) => format!("{} via sudo by {}", user, sudo_user),
+            None => user,
+        }
+    }
+}
+
+/// Audit log location for the state file at `state_path`.
+pub fn path(state_path: &PathType) -> PathBuf {
+    PathBuf::from(format!("{}.audit", state_path))
+}
+
+/// Append `entry` to the audit log next to `state_path`.
+pub fn append(state_path: &PathType, entry: &AuditEntry) -> io::Result<()> {
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+
+    // A single write to an O_APPEND file, so concurrent commands never
+    // interleave within a line
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o640)
+        .open(path(state_path))?
+        .write_all(&line)
+}
+
+/// Read the audit log next to `state_path`, oldest first.
+///
+/// A missing log is empty and lines that don't parse are skipped.
+pub fn read(state_path: &PathType) -> io::Result<Vec<AuditEntry>> {
+    let content = match fs::read_to_string(path(state_path)) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+
+    Ok(content
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
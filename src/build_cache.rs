@@ -0,0 +1,238 @@
+//! Skipping build steps whose inputs didn't change.
+//!
+//! A change anywhere under the watched directories used to run the whole
+//! build again, even when it only touched files the build doesn't read.
+//! Steps can declare the files they read, as globs relative to
+//! `project_path`, and the files they produce:
+//!
+//! ```toml
+//! [app_specific]
+//! build_command = "npm run build"
+//! build_inputs = ["src/**", "package.json", "package-lock.json"]
+//! build_outputs = ["dist"]
+//!
+//! [[app_specific.steps]]
+//! name = "backend"
+//! command = "cargo build --release"
+//! inputs = ["backend/src/**", "backend/Cargo.*"]
+//! outputs = ["backend/target/release/server"]
+//! ```
+//!
+//! Before such a step runs the matching files are hashed, together with
+//! the command, its directory, `env` and the build executor. When the hash
+//! is the one of the step's last successful run, and every output is still
+//! there, the step is skipped. A matched directory counts with everything
+//! in it. The hashes are kept in the runner state, `ais_runner restart
+//! --no-cache` makes the next build run every step.
+
+use artisan_middleware::dusa_collection_utils::core::{
+    logger::LogLevel, types::pathtype::PathType,
+};
+use ring::digest::{Context, SHA256};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use crate::config::AppSpecificConfig;
+use crate::log;
+use crate::runner_state::{RunnerState, update_runner_state};
+
+/// Whether a step has to run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lookup {
+    /// The step declares no inputs, or they couldn't be hashed.
+    Uncached,
+    /// The inputs are unchanged since the step last succeeded and its
+    /// outputs are there.
+    Fresh,
+    /// The step runs, the hash of its inputs is remembered once it
+    /// succeeded.
+    Stale(String),
+}
+
+/// What a step is cached by.
+#[derive(Debug, Clone, Copy)]
+pub struct CacheKey<'a> {
+    pub name: &'a str,
+    /// `None` for steps the executor runs without a command.
+    pub command: Option<&'a str>,
+    pub cwd: Option<&'a str>,
+    pub inputs: &'a [String],
+    pub outputs: &'a [String],
+}
+
+/// Hashes of the inputs of the last successful run of every step.
+#[derive(Debug, Clone)]
+pub struct BuildCache {
+    state_path: PathType,
+    hashes: BTreeMap<String, String>,
+    /// Set for a build asked to run every step.
+    bypass: bool,
+}
+
+impl BuildCache {
+    /// The cache kept for the state file at `state_path`, taking a pending
+    /// `--no-cache` request along.
+    pub fn open(state_path: &PathType) -> Self {
+        let runner_state = RunnerState::load(state_path);
+        if runner_state.no_cache_requested {
+            log!(
+                LogLevel::Info,
+                "Running every build step, the cache was bypassed"
+            );
+            update_runner_state(state_path, |runner_state| {
+                runner_state.no_cache_requested = false
+            });
+        }
+        Self {
+            state_path: state_path.clone(),
+            hashes: runner_state.build_cache,
+            bypass: runner_state.no_cache_requested,
+        }
+    }
+
+    /// Whether `step` of the project in `settings` has to run.
+    pub fn lookup(&self, settings: &AppSpecificConfig, step: &CacheKey) -> Lookup {
+        let command = match step.command {
+            Some(command) if !step.inputs.is_empty() => command,
+            _ => return Lookup::Uncached,
+        };
+        let project = Path::new(&settings.project_path);
+        let salt = salt(settings, command, step.cwd);
+        let hash = match fingerprint(project, step.inputs, &salt) {
+            Ok(hash) => hash,
+            Err(err) => {
+                log!(
+                    LogLevel::Warn,
+                    "Not caching the {} step: {}",
+                    step.name,
+                    err
+                );
+                return Lookup::Uncached;
+            }
+        };
+
+        let unchanged = self.hashes.get(step.name) == Some(&hash);
+        match !self.bypass && unchanged && outputs_present(project, step.outputs) {
+            true => Lookup::Fresh,
+            false => Lookup::Stale(hash),
+        }
+    }
+
+    /// Remember how the step `name` went after `lookup` had it run.
+    pub fn remember(&mut self, name: &str, lookup: Lookup, success: bool) {
+        let hash = match (lookup, success) {
+            (Lookup::Stale(hash), true) => Some(hash),
+            (Lookup::Stale(_), false) => None,
+            _ => return,
+        };
+        // A failure forgets the last success, the same inputs run again
+        match &hash {
+            Some(hash) => self.hashes.insert(name.to_owned(), hash.clone()),
+            None => self.hashes.remove(name),
+        };
+        update_runner_state(&self.state_path, |runner_state| match hash {
+            Some(hash) => {
+                runner_state.build_cache.insert(name.to_owned(), hash);
+            }
+            None => {
+                runner_state.build_cache.remove(name);
+            }
+        });
+    }
+}
+
+/// What else decides the outcome of a step besides its inputs.
+fn salt(settings: &AppSpecificConfig, command: &str, cwd: Option<&str>) -> String {
+    let executor = serde_json::to_string(&settings.build_executor).unwrap_or_default();
+    format!(
+        "{}\0{}\0{:?}\0{}",
+        command,
+        cwd.unwrap_or_default(),
+        settings.env,
+        executor
+    )
+}
+
+/// Paths matching `pattern` relative to `project`.
+fn expand(project: &Path, pattern: &str) -> Result<Vec<PathBuf>, String> {
+    let full = project.join(pattern);
+    let paths = glob::glob(&full.to_string_lossy())
+        .map_err(|err| format!("{:?} isn't a valid pattern: {}", pattern, err))?;
+    Ok(paths.filter_map(Result::ok).collect())
+}
+
+/// Files at or below `path`, symlinked directories aren't followed.
+fn collect_files(path: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(path)?;
+    match metadata.is_dir() {
+        true => {
+            for entry in fs::read_dir(path)? {
+                collect_files(&entry?.path(), files)?;
+            }
+        }
+        false => files.push(path.to_path_buf()),
+    }
+    Ok(())
+}
+
+/// SHA-256 over the files matching `inputs` below `project`, their paths
+/// and `salt`, as hex.
+pub fn fingerprint(project: &Path, inputs: &[String], salt: &str) -> Result<String, String> {
+    let mut files = Vec::new();
+    for pattern in inputs {
+        for path in expand(project, pattern)? {
+            collect_files(&path, &mut files)
+                .map_err(|err| format!("{} can't be read: {}", path.display(), err))?;
+        }
+    }
+    files.sort();
+    files.dedup();
+
+    let mut context = Context::new(&SHA256);
+    context.update(salt.as_bytes());
+    let mut buffer = vec![0; 64 * 1024];
+    for path in files {
+        let relative = path.strip_prefix(project).unwrap_or(&path);
+        context.update(b"\0");
+        context.update(relative.to_string_lossy().as_bytes());
+        context.update(b"\0");
+        let mut file = File::open(&path)
+            .map_err(|err| format!("{} can't be read: {}", path.display(), err))?;
+        loop {
+            let read = file
+                .read(&mut buffer)
+                .map_err(|err| format!("{} can't be read: {}", path.display(), err))?;
+            if read == 0 {
+                break;
+            }
+            context.update(&buffer[..read]);
+        }
+    }
+
+    let digest = context.finish();
+    Ok(digest
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+/// Whether every pattern of `outputs` matches something below `project`.
+pub fn outputs_present(project: &Path, outputs: &[String]) -> bool {
+    outputs.iter().all(|pattern| {
+        expand(project, pattern)
+            .map(|paths| !paths.is_empty())
+            .unwrap_or(false)
+    })
+}
+
+/// Whether `pattern` is a glob the cache can expand.
+pub fn check_pattern(pattern: &str) -> Result<(), String> {
+    glob::Pattern::new(pattern)
+        .map(|_| ())
+        .map_err(|err| format!("{:?} isn't a valid pattern: {}", pattern, err))
+}
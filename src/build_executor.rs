@@ -0,0 +1,245 @@
+//! Where install and build commands run.
+//!
+//! By default the commands run straight on the host, which leaves toolchains
+//! and caches behind and makes builds depend on whatever is installed there.
+//! `[app_specific.build_executor]` picks a [`BuildExecutor`] instead:
+//!
+//! - `local` runs the command as is (the default).
+//! - `docker` runs it in a throwaway container with the project mounted.
+//! - `nix` replaces the build with `nix-build` of an expression in the
+//!   project, dependencies come from the expression so install is skipped.
+//!
+//! Executors only decide what gets spawned, timeouts, retries and output
+//! capture stay the same for all of them.
+
+use nix::unistd::{getgid, getuid};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+/// Turns an install or build step into the command line that runs it.
+pub trait BuildExecutor {
+    /// Short name for log messages.
+    fn name(&self) -> &'static str;
+
+    /// Program and arguments running `command` for `step` (`install`,
+    /// `build` or the name of one of the build `steps`) of the project in
+    /// `project`, `None` skips the step.
+    ///
+    /// `env` holds the names of the variables set for the step, the values
+    /// are in the environment of the spawned program.
+    fn argv(
+        &self,
+        step: &str,
+        command: Option<&[String]>,
+        project: &Path,
+        env: &BTreeMap<String, String>,
+    ) -> Option<Vec<String>>;
+
+    /// Directory to spawn `argv` in for a step running in `cwd`, relative
+    /// to the project. Executors running the command somewhere else point
+    /// `argv` there instead and return `None`.
+    fn working_dir(&self, _argv: &mut [String], project: &Path, cwd: &str) -> Option<PathBuf> {
+        Some(project.join(cwd))
+    }
+
+    /// Command cleaning up after `step` was killed for running into its
+    /// timeout, for work killing the spawned program doesn't stop.
+    fn cleanup(&self, _step: &str) -> Option<Vec<String>> {
+        None
+    }
+}
+
+/// `build_executor` setting.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum BuildExecutorConfig {
+    #[default]
+    Local,
+    Docker(DockerExecutor),
+    Nix(NixExecutor),
+}
+
+impl BuildExecutorConfig {
+    pub fn executor(&self) -> &dyn BuildExecutor {
+        match self {
+            BuildExecutorConfig::Local => &LocalExecutor,
+            BuildExecutorConfig::Docker(docker) => docker,
+            BuildExecutorConfig::Nix(nix) => nix,
+        }
+    }
+}
+
+/// Runs commands directly on the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalExecutor;
+
+impl BuildExecutor for LocalExecutor {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    fn argv(
+        &self,
+        _step: &str,
+        command: Option<&[String]>,
+        _project: &Path,
+        _env: &BTreeMap<String, String>,
+    ) -> Option<Vec<String>> {
+        command.map(<[String]>::to_vec)
+    }
+}
+
+/// Runs commands with `docker run` in `image`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct DockerExecutor {
+    pub image: String,
+    /// Where the project is mounted in the container.
+    #[serde(default = "default_workdir")]
+    pub workdir: String,
+    /// Run as the runner's uid and gid, so build output on the mount isn't
+    /// owned by root.
+    #[serde(default = "default_true")]
+    pub match_user: bool,
+    /// Passed to `docker run` before the image, e.g. `["--network", "host"]`.
+    #[serde(default)]
+    pub extra_args: Vec<String>,
+    /// Docker compatible client, e.g. `podman`.
+    #[serde(default = "default_docker_binary")]
+    pub binary: String,
+}
+
+fn default_workdir() -> String {
+    String::from("/workspace")
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_docker_binary() -> String {
+    String::from("docker")
+}
+
+impl DockerExecutor {
+    /// Name of the container for `step`, unique per runner.
+    pub fn container_name(&self, step: &str) -> String {
+        format!("ais-runner-{}-{}", std::process::id(), step)
+    }
+}
+
+impl BuildExecutor for DockerExecutor {
+    fn name(&self) -> &'static str {
+        "docker"
+    }
+
+    fn argv(
+        &self,
+        step: &str,
+        command: Option<&[String]>,
+        project: &Path,
+        env: &BTreeMap<String, String>,
+    ) -> Option<Vec<String>> {
+        let command = command?;
+        let mut argv = vec![
+            self.binary.clone(),
+            String::from("run"),
+            String::from("--rm"),
+            String::from("--name"),
+            self.container_name(step),
+            String::from("-v"),
+            format!("{}:{}", project.display(), self.workdir),
+            String::from("-w"),
+            self.workdir.clone(),
+        ];
+        if self.match_user {
+            argv.push(String::from("--user"));
+            argv.push(format!("{}:{}", getuid(), getgid()));
+        }
+        // Only the names, docker takes the values from its own environment
+        // so they don't show up in the process list
+        for key in env.keys() {
+            argv.push(String::from("-e"));
+            argv.push(key.clone());
+        }
+        argv.extend(self.extra_args.iter().cloned());
+        argv.push(self.image.clone());
+        argv.extend(command.iter().cloned());
+        Some(argv)
+    }
+
+    fn working_dir(&self, argv: &mut [String], _project: &Path, cwd: &str) -> Option<PathBuf> {
+        let workdir = argv
+            .iter()
+            .position(|arg| arg == "-w")
+            .map(|index| index + 1);
+        if let Some(workdir) = workdir.and_then(|index| argv.get_mut(index)) {
+            *workdir = format!("{}/{}", self.workdir.trim_end_matches('/'), cwd);
+        }
+        None
+    }
+
+    fn cleanup(&self, step: &str) -> Option<Vec<String>> {
+        // Killing the client leaves the container running
+        Some(vec![
+            self.binary.clone(),
+            String::from("rm"),
+            String::from("--force"),
+            self.container_name(step),
+        ])
+    }
+}
+
+/// Builds the project with `nix-build`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct NixExecutor {
+    /// Expression to build, relative to `project_path`.
+    #[serde(default = "default_expression")]
+    pub expression: String,
+    /// Attribute of the expression to build, the whole expression when unset.
+    #[serde(default)]
+    pub attribute: Option<String>,
+    /// Symlink to the build result, relative to `project_path`.
+    #[serde(default = "default_out_link")]
+    pub out_link: String,
+}
+
+fn default_expression() -> String {
+    String::from("default.nix")
+}
+
+fn default_out_link() -> String {
+    String::from("result")
+}
+
+impl BuildExecutor for NixExecutor {
+    fn name(&self) -> &'static str {
+        "nix"
+    }
+
+    fn argv(
+        &self,
+        step: &str,
+        _command: Option<&[String]>,
+        project: &Path,
+        _env: &BTreeMap<String, String>,
+    ) -> Option<Vec<String>> {
+        if step != "build" {
+            return None;
+        }
+
+        let mut argv = vec![
+            String::from("nix-build"),
+            project.join(&self.expression).display().to_string(),
+        ];
+        if let Some(attribute) = &self.attribute {
+            argv.push(String::from("--attr"));
+            argv.push(attribute.clone());
+        }
+        argv.push(String::from("--out-link"));
+        argv.push(project.join(&self.out_link).display().to_string());
+        Some(argv)
+    }
+}
@@ -0,0 +1,294 @@
+//! Named build steps, `[[app_specific.steps]]`.
+//!
+//! `install_command` and `build_command` leave room for two commands,
+//! anything more used to be chained in a shell. Steps list any number of
+//! commands instead, run one after the other on every build:
+//!
+//! ```toml
+//! [[app_specific.steps]]
+//! name = "install"
+//! command = "npm ci"
+//!
+//! [[app_specific.steps]]
+//! name = "build"
+//! command = "npm run build"
+//! timeout_seconds = 600
+//!
+//! [[app_specific.steps]]
+//! name = "migrate"
+//! command = "npx prisma migrate deploy"
+//! cwd = "backend"
+//! continue_on_failure = true
+//! ```
+//!
+//! Every step runs the way the build step does, through the build executor
+//! and with `build_timeout_action`. Its output is captured under its name
+//! (`migrate_stdout`, `migrate_stderr`) and how long it took is kept with
+//! the restart history. A failing step ends the build unless it has
+//! `continue_on_failure` set.
+//!
+//! Steps run in the order they are listed, each after the one before it.
+//! A step with `depends_on` waits for the named steps instead, so steps
+//! independent of each other run side by side, up to `max_parallel_steps`
+//! at a time:
+//!
+//! ```toml
+//! [[app_specific.steps]]
+//! name = "frontend"
+//! command = "npm run build"
+//! cwd = "frontend"
+//! depends_on = []
+//!
+//! [[app_specific.steps]]
+//! name = "backend"
+//! command = "cargo build --release"
+//! cwd = "backend"
+//! depends_on = []
+//!
+//! [[app_specific.steps]]
+//! name = "migrate"
+//! command = "./backend/target/release/migrate"
+//! depends_on = ["backend"]
+//! ```
+//!
+//! Once a step failed no further steps are started, the running ones are
+//! waited for and every failure is reported together.
+
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt, time::Duration};
+
+/// An entry of `[[app_specific.steps]]`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct BuildStep {
+    /// Shown in logs and the history, letters, digits, `-` and `_`.
+    pub name: String,
+    pub command: String,
+    /// Directory to run in, relative to `project_path`.
+    #[serde(default)]
+    pub cwd: Option<String>,
+    /// Kill the command if it runs longer than this, `build_timeout_seconds`
+    /// when unset.
+    #[serde(default)]
+    pub timeout_seconds: Option<u64>,
+    /// Carry on with the next step if this one fails.
+    #[serde(default)]
+    pub continue_on_failure: bool,
+    /// Steps that have to finish first, the step listed before this one
+    /// when unset.
+    #[serde(default)]
+    pub depends_on: Option<Vec<String>>,
+    /// Files the step reads, it's skipped while they are unchanged, see
+    /// [`crate::build_cache`].
+    #[serde(default)]
+    pub inputs: Vec<String>,
+    /// Files the step produces, it runs again when one is gone.
+    #[serde(default)]
+    pub outputs: Vec<String>,
+}
+
+/// How a step of a build went.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct StepRun {
+    pub name: String,
+    pub duration_ms: u64,
+    pub success: bool,
+    /// Skipped as its inputs were unchanged.
+    #[serde(default)]
+    pub cached: bool,
+}
+
+impl StepRun {
+    pub fn new(name: &str, duration: Duration, success: bool) -> Self {
+        Self {
+            name: name.to_owned(),
+            duration_ms: duration.as_millis() as u64,
+            success,
+            cached: false,
+        }
+    }
+
+    /// A step skipped by the build cache.
+    pub fn cached(name: &str) -> Self {
+        Self {
+            cached: true,
+            ..Self::new(name, Duration::ZERO, true)
+        }
+    }
+}
+
+impl fmt::Display for StepRun {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let seconds = self.duration_ms as f64 / 1000.0;
+        if self.cached {
+            return write!(f, "{} cached", self.name);
+        }
+        match self.success {
+            true => write!(f, "{} {:.1}s", self.name, seconds),
+            false => write!(f, "{} failed after {:.1}s", self.name, seconds),
+        }
+    }
+}
+
+/// Whether `name` can name a step, it ends up in event and container
+/// names.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Whether `steps` run one after the other, none of them has `depends_on`.
+pub fn is_sequential(steps: &[BuildStep]) -> bool {
+    steps.iter().all(|step| step.depends_on.is_none())
+}
+
+/// Steps running side by side at most, the number of CPUs when
+/// `max_parallel_steps` is unset.
+pub fn parallelism(max_parallel_steps: Option<usize>) -> usize {
+    max_parallel_steps
+        .unwrap_or_else(|| {
+            std::thread::available_parallelism()
+                .map(|count| count.get())
+                .unwrap_or(1)
+        })
+        .max(1)
+}
+
+/// Indices of the steps each step waits for. Unknown names are left out,
+/// [`check_graph`] reports them.
+pub fn dependencies(steps: &[BuildStep]) -> Vec<Vec<usize>> {
+    let indices: HashMap<&str, usize> = steps
+        .iter()
+        .enumerate()
+        .map(|(index, step)| (step.name.as_str(), index))
+        .collect();
+    steps
+        .iter()
+        .enumerate()
+        .map(|(index, step)| match &step.depends_on {
+            Some(names) => names
+                .iter()
+                .filter_map(|name| indices.get(name.as_str()).copied())
+                .collect(),
+            None => index.checked_sub(1).into_iter().collect(),
+        })
+        .collect()
+}
+
+/// What's wrong with the `depends_on` of `steps`, as the index of the step
+/// and a message.
+pub fn check_graph(steps: &[BuildStep]) -> Vec<(usize, String)> {
+    let mut problems = Vec::new();
+    for (index, step) in steps.iter().enumerate() {
+        for name in step.depends_on.iter().flatten() {
+            match steps.iter().position(|other| &other.name == name) {
+                None => problems.push((index, format!("{:?} isn't a step", name))),
+                Some(other) if other == index => {
+                    problems.push((index, String::from("can't name the step itself")))
+                }
+                Some(_) => (),
+            }
+        }
+    }
+    if !problems.is_empty() {
+        return problems;
+    }
+
+    // Take out steps without anything left to wait for, what remains waits
+    // on itself in a cycle
+    let dependencies = dependencies(steps);
+    let mut removed = vec![false; steps.len()];
+    let mut progress = true;
+    while progress {
+        progress = false;
+        for index in 0..steps.len() {
+            if !removed[index] && dependencies[index].iter().all(|&dep| removed[dep]) {
+                removed[index] = true;
+                progress = true;
+            }
+        }
+    }
+    let cycle: Vec<&str> = steps
+        .iter()
+        .zip(&removed)
+        .filter(|(_, removed)| !**removed)
+        .map(|(step, _)| step.name.as_str())
+        .collect();
+    if let Some(index) = removed.iter().position(|removed| !removed) {
+        problems.push((
+            index,
+            format!("{} wait for each other in a cycle", cycle.join(", ")),
+        ));
+    }
+    problems
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Progress {
+    Pending,
+    Running,
+    /// Whether the steps waiting for it may run.
+    Finished(bool),
+}
+
+/// Which steps of a build can start, as steps finish.
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    dependencies: Vec<Vec<usize>>,
+    progress: Vec<Progress>,
+    halted: bool,
+}
+
+impl Schedule {
+    pub fn new(steps: &[BuildStep]) -> Self {
+        Self {
+            dependencies: dependencies(steps),
+            progress: vec![Progress::Pending; steps.len()],
+            halted: false,
+        }
+    }
+
+    /// Up to `slots` steps whose dependencies all finished, in the order
+    /// they are listed, now counted as running.
+    pub fn start(&mut self, slots: usize) -> Vec<usize> {
+        if self.halted {
+            return Vec::new();
+        }
+        let ready: Vec<usize> = (0..self.progress.len())
+            .filter(|&index| self.progress[index] == Progress::Pending)
+            .filter(|&index| {
+                self.dependencies[index]
+                    .iter()
+                    .all(|&dep| self.progress[dep] == Progress::Finished(true))
+            })
+            .take(slots)
+            .collect();
+        for &index in &ready {
+            self.progress[index] = Progress::Running;
+        }
+        ready
+    }
+
+    /// Note that the step at `index` is done, `ok` when it succeeded or may
+    /// fail. Nothing else is started after a step that wasn't.
+    pub fn finish(&mut self, index: usize, ok: bool) {
+        self.progress[index] = Progress::Finished(ok);
+        self.halted |= !ok;
+    }
+
+    pub fn running(&self) -> Vec<usize> {
+        self.indices(Progress::Running)
+    }
+
+    /// Steps that haven't started, after the build those that never will.
+    pub fn pending(&self) -> Vec<usize> {
+        self.indices(Progress::Pending)
+    }
+
+    fn indices(&self, progress: Progress) -> Vec<usize> {
+        (0..self.progress.len())
+            .filter(|&index| self.progress[index] == progress)
+            .collect()
+    }
+}
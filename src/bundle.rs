@@ -0,0 +1,219 @@
+//! Configuration bundles for replicating a runner setup (`export-bundle` /
+//! `import-bundle`).
+//!
+//! A bundle is a single JSON file holding `Config.toml`, `Overrides.toml`
+//! and the env template if there are any. Restart schedules, notification
+//! webhooks and everything else configured lives in those files, so
+//! nothing is left behind. The env template only references secrets by
+//! key, the rendered env file with their values is never bundled.
+//!
+//! Bundles are signed with HMAC-SHA256 over their contents using a key
+//! shared between the hosts, and are only imported when the signature
+//! matches.
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Bumped when the layout changes.
+pub const BUNDLE_VERSION: u32 = 1;
+
+/// What a bundled file is, which decides where it's installed.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// `Config.toml` in the config directory.
+    Config,
+    /// `Overrides.toml` in the config directory.
+    Overrides,
+    /// The `env_template` of the bundled config.
+    EnvTemplate,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct BundleFile {
+    pub role: Role,
+    /// Base64 of the file.
+    pub content: String,
+}
+
+impl BundleFile {
+    fn new(role: Role, content: &[u8]) -> Self {
+        Self {
+            role,
+            content: STANDARD.encode(content),
+        }
+    }
+
+    pub fn bytes(&self) -> Result<Vec<u8>, String> {
+        STANDARD
+            .decode(&self.content)
+            .map_err(|err| format!("Bundled {:?} isn't valid base64: {}", self.role, err))
+    }
+}
+
+/// Everything the signature covers.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Contents {
+    pub version: u32,
+    pub created: u64,
+    /// Version of the runner that exported it.
+    pub runner_version: String,
+    pub files: Vec<BundleFile>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Bundle {
+    pub contents: Contents,
+    /// Base64 HMAC-SHA256 of the serialized contents.
+    pub signature: String,
+}
+
+fn signing_key(key: &[u8]) -> hmac::Key {
+    hmac::Key::new(hmac::HMAC_SHA256, key)
+}
+
+/// Sign `contents` with `key`.
+pub fn sign(contents: Contents, key: &[u8]) -> Result<Bundle, String> {
+    let payload = serde_json::to_vec(&contents).map_err(|err| err.to_string())?;
+    let signature = hmac::sign(&signing_key(key), &payload);
+    Ok(Bundle {
+        contents,
+        signature: STANDARD.encode(signature.as_ref()),
+    })
+}
+
+/// The contents of `bundle` if it was signed with `key` and is a version
+/// this runner understands.
+pub fn verify<'a>(bundle: &'a Bundle, key: &[u8]) -> Result<&'a Contents, String> {
+    let payload = serde_json::to_vec(&bundle.contents).map_err(|err| err.to_string())?;
+    let signature = STANDARD
+        .decode(&bundle.signature)
+        .map_err(|_| String::from("The bundle signature isn't valid base64"))?;
+    hmac::verify(&signing_key(key), &payload, &signature).map_err(|_| {
+        String::from("The bundle signature doesn't match, wrong key or modified bundle")
+    })?;
+    if bundle.contents.version != BUNDLE_VERSION {
+        return Err(format!(
+            "Bundle version {} isn't supported, expected {}",
+            bundle.contents.version, BUNDLE_VERSION
+        ));
+    }
+    Ok(&bundle.contents)
+}
+
+/// Where the env template of `config` (the text of a Config.toml) lives,
+/// relative paths resolved against `dir`.
+fn env_template_path(config: &str, dir: &Path) -> Result<Option<PathBuf>, String> {
+    let config: toml::Value =
+        toml::from_str(config).map_err(|err| format!("Invalid Config.toml: {}", err))?;
+    let app_specific = config.get("app_specific");
+    let template = match app_specific
+        .and_then(|app_specific| app_specific.get("env_template"))
+        .and_then(toml::Value::as_str)
+    {
+        Some(template) => template,
+        None => return Ok(None),
+    };
+    let project_path = app_specific
+        .and_then(|app_specific| app_specific.get("project_path"))
+        .and_then(toml::Value::as_str)
+        .unwrap_or(".");
+    Ok(Some(dir.join(project_path).join(template)))
+}
+
+/// Read the files of the runner configured in `dir`.
+pub fn collect(dir: &Path) -> Result<Vec<BundleFile>, String> {
+    let read = |path: &Path| {
+        fs::read(path).map_err(|err| format!("Failed to read {}: {}", path.display(), err))
+    };
+
+    let config = read(&dir.join("Config.toml"))?;
+    let mut files = vec![BundleFile::new(Role::Config, &config)];
+
+    let overrides = dir.join("Overrides.toml");
+    if overrides.exists() {
+        files.push(BundleFile::new(Role::Overrides, &read(&overrides)?));
+    }
+
+    let template = env_template_path(&String::from_utf8_lossy(&config), dir)?;
+    if let Some(template) = template {
+        files.push(BundleFile::new(Role::EnvTemplate, &read(&template)?));
+    }
+    Ok(files)
+}
+
+/// Where each of `contents`' files goes for a runner configured in `dir`.
+pub fn destinations(contents: &Contents, dir: &Path) -> Result<Vec<(PathBuf, Vec<u8>)>, String> {
+    let mut destinations = Vec::new();
+    let mut template = None;
+    for file in &contents.files {
+        match file.role {
+            Role::Config => {
+                let config = file.bytes()?;
+                template = env_template_path(&String::from_utf8_lossy(&config), dir)?;
+                destinations.push((dir.join("Config.toml"), config));
+            }
+            Role::Overrides => destinations.push((dir.join("Overrides.toml"), file.bytes()?)),
+            Role::EnvTemplate => (),
+        }
+    }
+    if destinations.is_empty() {
+        return Err(String::from("The bundle has no Config.toml"));
+    }
+
+    let bundled_template = contents
+        .files
+        .iter()
+        .find(|file| file.role == Role::EnvTemplate);
+    if let (Some(path), Some(file)) = (template, bundled_template) {
+        destinations.push((path, file.bytes()?));
+    }
+    Ok(destinations)
+}
+
+/// Write the files of `contents` for a runner configured in `dir`. Existing
+/// files are only replaced with `force`. Returns the written paths.
+pub fn install(contents: &Contents, dir: &Path, force: bool) -> Result<Vec<PathBuf>, String> {
+    let destinations = destinations(contents, dir)?;
+    if !force {
+        let existing: Vec<String> = destinations
+            .iter()
+            .filter(|(path, _)| path.exists())
+            .map(|(path, _)| path.display().to_string())
+            .collect();
+        if !existing.is_empty() {
+            return Err(format!(
+                "{} already exist, pass --force to replace them",
+                existing.join(", ")
+            ));
+        }
+    }
+
+    let mut written = Vec::new();
+    for (path, content) in destinations {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|err| format!("Failed to create {}: {}", parent.display(), err))?;
+        }
+        fs::write(&path, content)
+            .map_err(|err| format!("Failed to write {}: {}", path.display(), err))?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// Read a signing key, surrounding whitespace isn't part of it.
+pub fn read_key(path: &Path) -> Result<Vec<u8>, String> {
+    let key = fs::read(path)
+        .map_err(|err| format!("Failed to read the key {}: {}", path.display(), err))?;
+    let key = key.trim_ascii().to_vec();
+    if key.is_empty() {
+        return Err(format!("The key {} is empty", path.display()));
+    }
+    Ok(key)
+}
@@ -0,0 +1,129 @@
+//! How often the main loop does each of its periodic jobs.
+//!
+//! Checking the child's health, sampling its metrics and cleaning up the
+//! state used to share one 5 second tick, so a slow metrics call held up
+//! noticing a crash. Each now has its own interval:
+//!
+//! ```toml
+//! [app_specific.cadence]
+//! health_seconds = 5    # exits, probes, heartbeats and log rules
+//! metrics_seconds = 5   # usage, limits and the metrics history
+//! cleanup_seconds = 5   # trimming the error log, reset requests
+//! output_millis = 1000  # moving output into the state and journal
+//! ```
+//!
+//! Health, cleanup and output run on ticks of the main loop. Metrics are sampled
+//! by a task of their own that hands each [`Sample`] to the main loop, so
+//! the loop never waits for the sampling.
+
+use artisan_middleware::process_manager::Metrics;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::{
+    sync::mpsc,
+    time::{Instant, interval_at},
+};
+
+use crate::child_manager;
+use crate::global_child::GLOBAL_ADOPTED;
+
+/// `[app_specific.cadence]`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CadenceConfig {
+    #[serde(default = "default_cadence")]
+    pub health_seconds: u64,
+    #[serde(default = "default_cadence")]
+    pub metrics_seconds: u64,
+    #[serde(default = "default_cadence")]
+    pub cleanup_seconds: u64,
+    /// How often the child's output is read off the capture files into
+    /// the state and the journal.
+    #[serde(default = "default_output_millis")]
+    pub output_millis: u64,
+}
+
+impl Default for CadenceConfig {
+    fn default() -> Self {
+        Self {
+            health_seconds: default_cadence(),
+            metrics_seconds: default_cadence(),
+            cleanup_seconds: default_cadence(),
+            output_millis: default_output_millis(),
+        }
+    }
+}
+
+fn default_cadence() -> u64 {
+    5
+}
+
+fn default_output_millis() -> u64 {
+    1000
+}
+
+impl CadenceConfig {
+    pub fn health(&self) -> Duration {
+        Duration::from_secs(self.health_seconds)
+    }
+
+    pub fn metrics(&self) -> Duration {
+        Duration::from_secs(self.metrics_seconds)
+    }
+
+    pub fn cleanup(&self) -> Duration {
+        Duration::from_secs(self.cleanup_seconds)
+    }
+
+    pub fn output(&self) -> Duration {
+        Duration::from_millis(self.output_millis)
+    }
+}
+
+/// The child's usage at one point in time.
+#[derive(Debug)]
+pub struct Sample {
+    /// Only a spawned child has these to save with the state.
+    pub metrics: Option<Metrics>,
+    /// CPU percent and memory in MB, `None` when nothing could be read.
+    pub usage: Option<(f32, f64)>,
+}
+
+/// Read the usage of the spawned or adopted child, if there's one.
+pub async fn sample() -> Option<Sample> {
+    if child_manager::running().await.is_some() {
+        let metrics = child_manager::metrics().await;
+        let usage = metrics
+            .as_ref()
+            .map(|metrics| (metrics.cpu_usage, metrics.memory_usage));
+        return Some(Sample { metrics, usage });
+    }
+    // Read from /proc, there's no Metrics to save for an adopted child
+    GLOBAL_ADOPTED.lock().await.as_mut().map(|adopted| Sample {
+        metrics: None,
+        usage: adopted
+            .usage()
+            .map(|usage| (usage.cpu_percent, usage.memory_mb)),
+    })
+}
+
+/// Sample the child every `period`, first after one `period`, until `tx`
+/// is closed. A sample is dropped while the main loop hasn't taken the
+/// previous one yet.
+pub fn spawn_sampler(period: Duration, tx: mpsc::Sender<Sample>) {
+    tokio::spawn(async move {
+        let mut ticks = interval_at(Instant::now() + period, period);
+        loop {
+            ticks.tick().await;
+            if tx.is_closed() {
+                return;
+            }
+            let sample = match sample().await {
+                Some(sample) => sample,
+                None => continue,
+            };
+            if let Err(mpsc::error::TrySendError::Closed(_)) = tx.try_send(sample) {
+                return;
+            }
+        }
+    });
+}
@@ -0,0 +1,282 @@
+//! Files the child's stdout and stderr are written to.
+//!
+//! The child used to write into pipes the runner read from. A runner that
+//! died took the read ends along, so the orphan it left behind got `EPIPE`
+//! on its next write and a runner adopting it had nothing to read. Now the
+//! child writes to `stdout.log` and `stderr.log` in [`dir`], which outlive
+//! the runner, and the runner follows them by byte offset: the same way for
+//! a child it spawned and for one it adopted, and across respawns. An
+//! offset only ever grows, so no line is read twice or skipped however
+//! often it repeats. The offset a line starts at is its ID, see
+//! [`CaptureFile::read_numbered`].
+//!
+//! The directory is `<state file>.output`, only its owner can get in. The
+//! default output journal is kept there as well. Nothing in it is opened
+//! through a symlink, and a directory owned by someone else is refused, so
+//! no other user can read the child's output or have a file of their
+//! choosing handed to `run_as_user`.
+//!
+//! What the runner read is punched out of the files as it goes, they keep
+//! their size but give back the disk space. Where the filesystem can't
+//! punch holes a file is truncated instead, once everything in it was read
+//! and it's past [`TRUNCATE_AT`].
+
+use artisan_middleware::dusa_collection_utils::core::types::pathtype::PathType;
+use nix::{fcntl::OFlag, unistd::geteuid};
+use std::{
+    fs::{self, DirBuilder, File, OpenOptions, Permissions},
+    io::{self, BufRead, BufReader, Seek, SeekFrom},
+    os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+use crate::output::Stream;
+
+/// What is punched out at once. As much is kept before the runner's offset
+/// for followers a little behind it.
+const CHUNK: u64 = 1024 * 1024;
+
+/// Size past which a file that can't have holes punched is truncated.
+pub const TRUNCATE_AT: u64 = 64 * CHUNK;
+
+/// The private directory of the runner with its state at `state_path`.
+pub fn dir(state_path: &PathType) -> PathBuf {
+    PathBuf::from(format!("{}.output", state_path))
+}
+
+/// Where the child of the runner with its state at `state_path` writes
+/// `stream`.
+pub fn path(state_path: &PathType, stream: Stream) -> PathBuf {
+    dir(state_path).join(format!("{}.log", stream))
+}
+
+/// Create [`dir`] if it's missing and make sure it's a directory of ours
+/// only we can get into.
+pub fn create_dir(state_path: &PathType) -> io::Result<PathBuf> {
+    let dir = dir(state_path);
+    match DirBuilder::new().mode(0o700).create(&dir) {
+        Ok(()) => (),
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => (),
+        Err(err) => return Err(err),
+    }
+    // Not following a symlink someone put in its place
+    let meta = fs::symlink_metadata(&dir)?;
+    if !meta.is_dir() || meta.uid() != geteuid().as_raw() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} isn't a directory of the runner's user", dir.display()),
+        ));
+    }
+    if meta.mode() & 0o077 != 0 {
+        fs::set_permissions(&dir, Permissions::from_mode(0o700))?;
+    }
+    Ok(dir)
+}
+
+/// Open `path` without following a symlink at it.
+fn open_nofollow(options: &mut OpenOptions, path: &Path) -> io::Result<File> {
+    options
+        .custom_flags((OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC).bits())
+        .open(path)
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    open_nofollow(
+        OpenOptions::new().append(true).create(true).mode(0o640),
+        path,
+    )
+}
+
+/// Create the directory and the files that don't exist yet, so they can
+/// be handed over and followed before the first child is spawned.
+pub fn create(state_path: &PathType) -> io::Result<()> {
+    create_dir(state_path)?;
+    for stream in [Stream::Stdout, Stream::Stderr] {
+        open_append(&path(state_path, stream))?;
+    }
+    Ok(())
+}
+
+/// The file the child gets as its `stream`.
+pub fn child_stdio(state_path: &PathType, stream: Stream) -> io::Result<File> {
+    open_append(&path(state_path, stream))
+}
+
+/// A capture file read on from an offset.
+#[derive(Debug)]
+pub struct CaptureFile {
+    path: PathBuf,
+    reader: BufReader<File>,
+    position: u64,
+    /// Where the part that wasn't punched out yet starts.
+    kept: u64,
+    /// A line still being written.
+    partial: Vec<u8>,
+}
+
+impl CaptureFile {
+    /// Follow `path` from where it ends now.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut file = open_nofollow(OpenOptions::new().read(true), path)?;
+        let position = file.seek(SeekFrom::End(0))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            reader: BufReader::new(file),
+            position,
+            kept: position,
+            partial: Vec::new(),
+        })
+    }
+
+    fn len(&self) -> u64 {
+        self.reader
+            .get_ref()
+            .metadata()
+            .map_or(0, |meta| meta.len())
+    }
+
+    /// Lines completed since the last read.
+    pub fn read_lines(&mut self) -> Vec<String> {
+        self.read_numbered()
+            .into_iter()
+            .map(|(_, line)| line)
+            .collect()
+    }
+
+    /// Lines completed since the last read with the offset each starts at.
+    /// Offsets only grow, so no two lines of a file share one, unless it's
+    /// truncated where holes can't be punched.
+    pub fn read_numbered(&mut self) -> Vec<(u64, String)> {
+        // Truncated, by a runner that can't punch holes or by hand
+        if self.len() < self.position && self.reader.seek(SeekFrom::Start(0)).is_ok() {
+            self.position = 0;
+            self.kept = 0;
+            self.partial.clear();
+        }
+
+        let mut lines = Vec::new();
+        loop {
+            match self.reader.read_until(b'\n', &mut self.partial) {
+                Ok(0) | Err(_) => break,
+                Ok(read) => self.position += read as u64,
+            }
+            if self.partial.last() != Some(&b'\n') {
+                break;
+            }
+            let start = self.position - self.partial.len() as u64;
+            let line = std::mem::take(&mut self.partial);
+            let line = String::from_utf8_lossy(&line);
+            // A follower that fell behind into a punched out part reads zeros
+            let line = line.trim_start_matches('\0');
+            lines.push((start, line.trim_end_matches(['\r', '\n']).to_string()));
+        }
+        lines
+    }
+
+    /// Give back the disk space of what was read, but the last chunk.
+    pub fn release(&mut self) -> io::Result<()> {
+        let until = self.position.saturating_sub(CHUNK) / CHUNK * CHUNK;
+        if until <= self.kept {
+            return Ok(());
+        }
+        let file = open_nofollow(OpenOptions::new().write(true), &self.path)?;
+        if punch_hole(&file, self.kept, until - self.kept).is_ok() {
+            self.kept = until;
+            return Ok(());
+        }
+
+        // Whatever the child writes between the check and truncating is lost
+        if self.position < TRUNCATE_AT || !self.partial.is_empty() || self.len() > self.position {
+            return Ok(());
+        }
+        file.set_len(0)?;
+        self.reader.seek(SeekFrom::Start(0))?;
+        self.position = 0;
+        self.kept = 0;
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn punch_hole(file: &File, offset: u64, len: u64) -> io::Result<()> {
+    use nix::fcntl::{FallocateFlags, fallocate};
+    use std::os::fd::AsRawFd;
+
+    let flags = FallocateFlags::FALLOC_FL_PUNCH_HOLE | FallocateFlags::FALLOC_FL_KEEP_SIZE;
+    fallocate(file.as_raw_fd(), flags, offset as i64, len as i64).map_err(io::Error::from)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn punch_hole(_file: &File, _offset: u64, _len: u64) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "only supported on Linux",
+    ))
+}
+
+/// Both capture files of a child, followed from where they ended when
+/// following started. A file that can't be opened reads as empty.
+#[derive(Debug)]
+pub struct OutputCapture {
+    stdout: Option<CaptureFile>,
+    stderr: Option<CaptureFile>,
+}
+
+impl OutputCapture {
+    pub fn follow(state_path: &PathType) -> Self {
+        Self {
+            stdout: CaptureFile::open(&path(state_path, Stream::Stdout)).ok(),
+            stderr: CaptureFile::open(&path(state_path, Stream::Stderr)).ok(),
+        }
+    }
+
+    /// Like [`follow`](Self::follow), but failing when a file can't be
+    /// opened, for followers outside the runner.
+    pub fn open(state_path: &PathType) -> io::Result<Self> {
+        Ok(Self {
+            stdout: Some(CaptureFile::open(&path(state_path, Stream::Stdout))?),
+            stderr: Some(CaptureFile::open(&path(state_path, Stream::Stderr))?),
+        })
+    }
+
+    fn file(&mut self, stream: Stream) -> Option<&mut CaptureFile> {
+        match stream {
+            Stream::Stdout => self.stdout.as_mut(),
+            Stream::Stderr => self.stderr.as_mut(),
+        }
+    }
+
+    /// Lines written to `stream` since the last call.
+    pub fn read_lines(&mut self, stream: Stream) -> Vec<String> {
+        self.file(stream)
+            .map(CaptureFile::read_lines)
+            .unwrap_or_default()
+    }
+
+    /// Lines written to `stream` since the last call with their IDs, see
+    /// [`CaptureFile::read_numbered`].
+    pub fn read_numbered(&mut self, stream: Stream) -> Vec<(u64, String)> {
+        self.file(stream)
+            .map(CaptureFile::read_numbered)
+            .unwrap_or_default()
+    }
+
+    /// Lines written to `stream` since the last call, keyed by `timestamp`.
+    pub fn read_keyed(&mut self, stream: Stream, timestamp: u64) -> Vec<(u64, String)> {
+        self.read_lines(stream)
+            .into_iter()
+            .map(|line| (timestamp, line))
+            .collect()
+    }
+
+    /// Give back the disk space of what was read from both files. Only the
+    /// runner does, other followers may be behind it.
+    pub fn release(&mut self) -> io::Result<()> {
+        for stream in [Stream::Stdout, Stream::Stderr] {
+            if let Some(file) = self.file(stream) {
+                file.release()?;
+            }
+        }
+        Ok(())
+    }
+}
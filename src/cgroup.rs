@@ -0,0 +1,337 @@
+//! cgroup v2 resource enforcement for the child.
+//!
+//! `max_ram_usage` used to only produce an error after the child had already
+//! exceeded it. With `[app_specific.cgroup] enabled = true` the runner places
+//! the child in its own cgroup with `memory.max` / `cpu.max` applied by the
+//! kernel, and reports OOM kills from `memory.events` into the error log.
+//!
+//! By default the cgroup is created below the runner's own cgroup, which
+//! requires `Delegate=yes` in the systemd unit. Because cgroup v2 forbids
+//! processes in inner nodes, the runner moves itself into a `supervisor`
+//! leaf and the child goes into a `child` leaf next to it. The child joins
+//! its leaf between fork and exec, so neither it nor anything it forks
+//! ever runs outside the limits.
+//!
+//! `ais_runner limits --memory-mb 768 --cpu-percent 200` tunes a running
+//! child: the limits are written to `Config.toml`, keeping its comments and
+//! layout, and the runner is asked to write them to the cgroup right away.
+
+use artisan_middleware::dusa_collection_utils;
+use dusa_collection_utils::core::errors::{ErrorArrayItem, Errors};
+use dusa_collection_utils::core::logger::LogLevel;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+use tokio::process::Command;
+
+use crate::log;
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// `[app_specific.cgroup]`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct CgroupConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Parent cgroup, absolute or relative to `/sys/fs/cgroup`. Defaults to
+    /// the cgroup the runner was started in.
+    #[serde(default)]
+    pub parent: Option<String>,
+    /// Memory limit in MB, defaults to `max_ram_usage` from the main config.
+    #[serde(default)]
+    pub memory_max_mb: Option<u64>,
+    /// CPU limit in percent of one core, e.g. `150` for one and a half cores.
+    #[serde(default)]
+    pub cpu_max_percent: Option<u32>,
+}
+
+/// Period used for `cpu.max`, the kernel default.
+const CPU_PERIOD_US: u64 = 100_000;
+
+/// Handle to the cgroup the child is placed in.
+#[derive(Debug, Clone)]
+pub struct ChildCgroup {
+    path: PathBuf,
+    oom_kills_seen: u64,
+}
+
+fn own_cgroup() -> io::Result<PathBuf> {
+    // cgroup v2 only has the single `0::/path` entry
+    let content = fs::read_to_string("/proc/self/cgroup")?;
+    let relative = content
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, "not a cgroup v2 host"))?;
+    Ok(PathBuf::from(CGROUP_ROOT).join(relative.trim_start_matches('/')))
+}
+
+fn to_error(context: &str, err: io::Error) -> ErrorArrayItem {
+    ErrorArrayItem::new(Errors::InputOutput, format!("{}: {}", context, err))
+}
+
+/// Whether the host exposes a unified (v2) hierarchy.
+pub fn cgroup_v2_available() -> bool {
+    PathBuf::from(CGROUP_ROOT)
+        .join("cgroup.controllers")
+        .exists()
+}
+
+impl ChildCgroup {
+    /// Create (or reuse) the child cgroup and apply the configured limits.
+    pub fn setup(config: &CgroupConfig, default_memory_mb: u64) -> Result<Self, ErrorArrayItem> {
+        if !cgroup_v2_available() {
+            return Err(ErrorArrayItem::new(
+                Errors::GeneralError,
+                "cgroup v2 isn't mounted at /sys/fs/cgroup",
+            ));
+        }
+
+        let parent = match &config.parent {
+            Some(parent) => PathBuf::from(CGROUP_ROOT).join(
+                parent
+                    .trim_start_matches(CGROUP_ROOT)
+                    .trim_start_matches('/'),
+            ),
+            None => {
+                let own = own_cgroup().map_err(|err| to_error("Reading own cgroup", err))?;
+                // Leave the inner node so controllers can be enabled for the children
+                let supervisor = own.join("supervisor");
+                fs::create_dir_all(&supervisor)
+                    .map_err(|err| to_error("Creating supervisor cgroup", err))?;
+                fs::write(
+                    supervisor.join("cgroup.procs"),
+                    std::process::id().to_string(),
+                )
+                .map_err(|err| to_error("Moving runner into supervisor cgroup", err))?;
+                own
+            }
+        };
+
+        fs::write(parent.join("cgroup.subtree_control"), "+memory +cpu")
+            .map_err(|err| to_error("Enabling memory and cpu controllers", err))?;
+
+        let path = parent.join("child");
+        fs::create_dir_all(&path).map_err(|err| to_error("Creating child cgroup", err))?;
+
+        let mut cgroup = Self {
+            path,
+            oom_kills_seen: 0,
+        };
+        cgroup.apply_limits(config, default_memory_mb)?;
+        // Only report kills that happen from now on
+        cgroup.oom_kills_seen = cgroup.oom_kills().unwrap_or(0);
+        Ok(cgroup)
+    }
+
+    /// Write `memory.max` and `cpu.max`.
+    pub fn apply_limits(
+        &self,
+        config: &CgroupConfig,
+        default_memory_mb: u64,
+    ) -> Result<(), ErrorArrayItem> {
+        let memory_mb = config.memory_max_mb.unwrap_or(default_memory_mb);
+        let memory = match memory_mb {
+            0 => String::from("max"),
+            mb => (mb * 1024 * 1024).to_string(),
+        };
+        fs::write(self.path.join("memory.max"), &memory)
+            .map_err(|err| to_error("Writing memory.max", err))?;
+
+        let cpu = match config.cpu_max_percent {
+            Some(percent) if percent > 0 => {
+                format!("{} {}", CPU_PERIOD_US * percent as u64 / 100, CPU_PERIOD_US)
+            }
+            _ => format!("max {}", CPU_PERIOD_US),
+        };
+        fs::write(self.path.join("cpu.max"), &cpu)
+            .map_err(|err| to_error("Writing cpu.max", err))?;
+
+        log!(
+            LogLevel::Debug,
+            "Applied cgroup limits memory.max={} cpu.max={} to {}",
+            memory,
+            cpu,
+            self.path.display()
+        );
+        Ok(())
+    }
+
+    /// Have the child `command` spawns join the cgroup before it execs.
+    /// Hooks run in the order they were added, this has to come before the
+    /// child drops root.
+    pub fn join_on_spawn(&self, command: &mut Command) -> Result<(), ErrorArrayItem> {
+        platform::join_on_spawn(&self.path.join("cgroup.procs"), command)
+            .map_err(|err| to_error("Preparing the child's cgroup", err))
+    }
+
+    /// Move `pid` into the child cgroup, nothing changes for a child that
+    /// joined on spawn.
+    pub fn attach(&self, pid: u32) -> Result<(), ErrorArrayItem> {
+        fs::write(self.path.join("cgroup.procs"), pid.to_string())
+            .map_err(|err| to_error("Moving child into cgroup", err))
+    }
+
+    /// SIGKILL everything in the child cgroup with `cgroup.kill`, which
+    /// needs Linux 5.14.
+    pub fn kill(&self) -> Result<(), ErrorArrayItem> {
+        let file = self.path.join("cgroup.kill");
+        if !file.exists() {
+            return Err(ErrorArrayItem::new(
+                Errors::GeneralError,
+                "cgroup.kill needs Linux 5.14 or later",
+            ));
+        }
+        fs::write(file, "1").map_err(|err| to_error("Writing cgroup.kill", err))
+    }
+
+    /// Hand the cgroup to `uid`:`gid`, so the runner can keep moving the
+    /// child in and adjusting limits after dropping root.
+    ///
+    /// Moving a process also takes write access to `cgroup.procs` of the
+    /// closest cgroup holding both the runner and the child, the parent.
+    pub fn delegate(&self, uid: u32, gid: u32) -> Result<(), ErrorArrayItem> {
+        let mut paths = vec![self.path.clone()];
+        for file in ["cgroup.procs", "memory.max", "cpu.max"] {
+            paths.push(self.path.join(file));
+        }
+        if let Some(parent) = self.path.parent() {
+            paths.push(parent.join("cgroup.procs"));
+        }
+        // Only there from Linux 5.14 on
+        let kill = self.path.join("cgroup.kill");
+        if kill.exists() {
+            paths.push(kill);
+        }
+        for path in paths {
+            platform::chown(&path, uid, gid)
+                .map_err(|err| to_error(&format!("Handing over {}", path.display()), err))?;
+        }
+        Ok(())
+    }
+
+    /// Total OOM kills recorded for the cgroup.
+    pub fn oom_kills(&self) -> Result<u64, ErrorArrayItem> {
+        let events = fs::read_to_string(self.path.join("memory.events"))
+            .map_err(|err| to_error("Reading memory.events", err))?;
+        Ok(events
+            .lines()
+            .find_map(|line| line.strip_prefix("oom_kill "))
+            .and_then(|count| count.trim().parse().ok())
+            .unwrap_or(0))
+    }
+
+    /// OOM kills since the last call, as an error ready for the error log.
+    pub fn new_oom_kills(&mut self) -> Option<ErrorArrayItem> {
+        let total = self.oom_kills().ok()?;
+        if total <= self.oom_kills_seen {
+            return None;
+        }
+
+        let new_kills = total - self.oom_kills_seen;
+        self.oom_kills_seen = total;
+        Some(ErrorArrayItem::new(
+            Errors::OverRamLimit,
+            format!(
+                "Child was OOM killed {} time(s) by the cgroup memory limit ({} total)",
+                new_kills, total
+            ),
+        ))
+    }
+}
+
+/// Set `memory_max_mb` and `cpu_max_percent` of `[app_specific.cgroup]` in
+/// the config at `path`, leaving the rest of the file as it is. `None`
+/// keeps the current value, `0` removes the limit.
+pub fn persist_limits(
+    path: &Path,
+    memory_max_mb: Option<u64>,
+    cpu_max_percent: Option<u32>,
+) -> Result<(), String> {
+    let content = fs::read_to_string(path)
+        .map_err(|err| format!("Failed to read {}: {}", path.display(), err))?;
+    let mut document: toml_edit::DocumentMut = content
+        .parse()
+        .map_err(|err| format!("Failed to parse {}: {}", path.display(), err))?;
+
+    let cgroup = document
+        .as_table_mut()
+        .entry("app_specific")
+        .or_insert(toml_edit::table())
+        .as_table_mut()
+        .and_then(|app_specific| {
+            app_specific
+                .entry("cgroup")
+                .or_insert(toml_edit::table())
+                .as_table_mut()
+        })
+        .ok_or_else(|| format!("[app_specific.cgroup] in {} isn't a table", path.display()))?;
+    if let Some(mb) = memory_max_mb {
+        cgroup["memory_max_mb"] = toml_edit::value(mb as i64);
+    }
+    if let Some(percent) = cpu_max_percent {
+        cgroup["cpu_max_percent"] = toml_edit::value(percent as i64);
+    }
+
+    fs::write(path, document.to_string())
+        .map_err(|err| format!("Failed to write {}: {}", path.display(), err))
+}
+
+#[cfg(unix)]
+mod platform {
+    use nix::{fcntl::OFlag, sys::stat::Mode};
+    use std::{
+        ffi::CString,
+        io,
+        os::{
+            fd::{FromRawFd, OwnedFd},
+            unix::ffi::OsStrExt,
+        },
+        path::Path,
+    };
+    use tokio::process::Command;
+
+    pub fn chown(path: &Path, uid: u32, gid: u32) -> io::Result<()> {
+        std::os::unix::fs::chown(path, Some(uid), Some(gid))
+    }
+
+    pub fn join_on_spawn(procs: &Path, command: &mut Command) -> io::Result<()> {
+        // Built before fork, the hook itself only calls open and write
+        let procs = CString::new(procs.as_os_str().as_bytes())?;
+        unsafe {
+            command.pre_exec(move || {
+                let flags = OFlag::O_WRONLY | OFlag::O_CLOEXEC;
+                // A child that can't join still starts, attach reports why
+                if let Ok(fd) = nix::fcntl::open(procs.as_c_str(), flags, Mode::empty()) {
+                    let fd = OwnedFd::from_raw_fd(fd);
+                    // 0 is whoever writes it, the forked child
+                    _ = nix::unistd::write(&fd, b"0");
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::{io, path::Path};
+
+    use tokio::process::Command;
+
+    pub fn chown(_path: &Path, _uid: u32, _gid: u32) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "cgroups don't exist on Windows",
+        ))
+    }
+
+    pub fn join_on_spawn(_procs: &Path, _command: &mut Command) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "cgroups don't exist on Windows",
+        ))
+    }
+}
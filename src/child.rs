@@ -2,54 +2,222 @@
 
 use artisan_middleware::dusa_collection_utils::core::errors::Errors;
 use artisan_middleware::dusa_collection_utils::core::functions::current_timestamp;
-use artisan_middleware::dusa_collection_utils::log;
 use artisan_middleware::process_manager::{
     SupervisedChild, spawn_complex_process, spawn_simple_process,
 };
-use artisan_middleware::state_persistence::{log_error, update_state, wind_down_state};
+use artisan_middleware::state_persistence::{log_error, wind_down_state};
 use artisan_middleware::{
     dusa_collection_utils::{
         core::errors::ErrorArrayItem, core::logger::LogLevel, core::types::pathtype::PathType,
     },
     state_persistence::AppState,
 };
+use serde::{Deserialize, Serialize};
 use shell_words::split;
+use std::collections::BTreeMap;
+use std::fmt;
 use std::fs;
-use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::process::Command;
+use std::future::poll_fn;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::process::{ExitStatus, Stdio};
+use std::sync::atomic::Ordering;
+use std::task::Poll;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::{Child, Command};
+use tokio::sync::mpsc;
+use tokio::time::timeout;
 
-use crate::config::AppSpecificConfig;
+use crate::artifacts;
+use crate::build_cache::{BuildCache, CacheKey, Lookup};
+use crate::build_steps::{BuildStep, Schedule, StepRun, is_sequential, parallelism};
+use crate::capture;
+use crate::command_vars::{CommandVars, resolve_secrets};
+use crate::config::{AppSpecificConfig, new_application_state};
+use crate::events::{self, Event};
+use crate::global_child::{
+    GLOBAL_CGROUP, GLOBAL_CHILD_PID, GLOBAL_HEARTBEAT, GLOBAL_LAUNCH, GLOBAL_LISTEN_FDS,
+    GLOBAL_STDIN,
+};
+use crate::journal;
+use crate::listen_fds::ListenSockets;
+use crate::log;
+use crate::logging::emit_output;
+use crate::output::{Stream, retain_newest};
+use crate::secrets::retry::{RetryConfig, with_retry};
+use crate::state;
+use crate::timestamps::line_timestamp;
+
+/// Exactly what a child was spawned with.
+///
+/// The launch of the last child that became ready is kept in the runner
+/// state, so `restore-last-known-good` can respawn it without going through
+/// config, install and build again.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ChildLaunch {
+    pub argv: Vec<String>,
+    /// Variables set on top of the runner's environment.
+    pub env: BTreeMap<String, String>,
+    pub cwd: PathBuf,
+    #[serde(default)]
+    pub run_as_user: Option<String>,
+    #[serde(default)]
+    pub run_as_group: Option<String>,
+}
+
+impl ChildLaunch {
+    /// The launch `settings` describe, with `run_command` split into its
+    /// arguments, its variables filled in and the working directory
+    /// resolved. Secrets are only filled in when spawning.
+    pub fn resolve(settings: &AppSpecificConfig) -> Self {
+        Self {
+            argv: CommandVars::current(settings).argv(&settings.run_command),
+            env: settings.env.clone(),
+            cwd: settings.working_dir().to_path_buf(),
+            run_as_user: settings.run_as_user.clone(),
+            run_as_group: settings.run_as_group.clone(),
+        }
+    }
+}
+
+/// Split `command` the way a shell would.
+///
+/// Commands a shell couldn't parse either, e.g. with an unbalanced quote,
+/// fall back to splitting on whitespace.
+pub fn split_command(command: &str) -> Vec<String> {
+    split(command).unwrap_or_else(|_| command.split_whitespace().map(|s| s.to_string()).collect())
+}
 
 /// Spawn the main child process defined in [`AppSpecificConfig`].
 ///
 /// The spawned process is wrapped in [`SupervisedChild`] so that
 /// stdout/stderr and metrics can be monitored.
 pub async fn create_child(
-    mut state: &mut AppState,
+    state: &mut AppState,
     state_path: &PathType,
     settings: &AppSpecificConfig,
-) -> SupervisedChild {
+) -> Result<SupervisedChild, ErrorArrayItem> {
+    let launch = ChildLaunch::resolve(settings);
+    launch_child(state, state_path, launch, &settings.secret_retry).await
+}
+
+/// Spawn a child exactly as `launch` describes it and remember the launch
+/// in [`GLOBAL_LAUNCH`].
+///
+/// The secrets of the command are fetched with `retry`. Once its attempts
+/// are used up the error is returned and nothing is spawned, the caller
+/// decides when to try again.
+pub async fn launch_child(
+    mut state: &mut AppState,
+    state_path: &PathType,
+    launch: ChildLaunch,
+    retry: &RetryConfig,
+) -> Result<SupervisedChild, ErrorArrayItem> {
     log!(LogLevel::Trace, "Creating child process...");
 
-    let parts = split(&settings.run_command).unwrap_or_else(|_| {
-        settings
-            .run_command
-            .split_whitespace()
-            .map(|s| s.to_string())
-            .collect()
-    });
-    let mut iter = parts.into_iter();
-    let program = iter.next().unwrap();
+    let mut attempts: Vec<ErrorArrayItem> = Vec::new();
+    let resolved = with_retry(retry, "fetch the command's secrets", &mut attempts, || {
+        resolve_secrets(&launch.argv)
+    })
+    .await;
+    state.error_log.append(&mut attempts);
+    let argv = match resolved? {
+        argv if GLOBAL_LISTEN_FDS.get().is_some() => ListenSockets::wrap(&argv),
+        argv => argv,
+    };
+    let (program, args) = match argv.split_first() {
+        Some(parts) => parts,
+        None => {
+            let error = ErrorArrayItem::new(Errors::GeneralError, "run_command is empty");
+            log_error(state, error, state_path).await;
+            wind_down_state(state, state_path).await;
+            std::process::exit(100);
+        }
+    };
     let mut command: Command = Command::new(program);
-    for arg in iter {
-        command.arg(arg);
+    command.args(args);
+
+    // Before the identity's hook, joining takes the runner's rights
+    let joined = match GLOBAL_CGROUP.lock().await.as_ref() {
+        Some(cgroup) => cgroup.join_on_spawn(&mut command),
+        None => Ok(()),
+    };
+    if let Err(error) = joined {
+        log!(LogLevel::Warn, "{}", error);
     }
 
-    match spawn_complex_process(&mut command, Some(settings.project_path()), false, true).await {
+    let user = launch.run_as_user.as_deref();
+    match platform::lookup_identity(user, launch.run_as_group.as_deref()) {
+        Ok(Some(identity)) => {
+            log!(
+                LogLevel::Info,
+                "Dropping child privileges to {}:{}",
+                identity.user_name,
+                identity.gid
+            );
+            identity.apply(&mut command);
+        }
+        Ok(None) => (),
+        Err(error) => {
+            log!(LogLevel::Error, "{}", error);
+            log_error(state, error, state_path).await;
+            wind_down_state(state, state_path).await;
+            std::process::exit(100);
+        }
+    }
+    command.envs(&launch.env);
+    if let Some(heartbeat) = GLOBAL_HEARTBEAT.get() {
+        heartbeat.reset();
+        command.env("NOTIFY_SOCKET", heartbeat.path());
+        if let Some(timeout) = heartbeat.timeout() {
+            command.env("WATCHDOG_USEC", timeout.as_micros().to_string());
+        }
+    }
+    if let Some(stdin) = GLOBAL_STDIN.get() {
+        match stdin.attach() {
+            Ok(reader) => {
+                command.stdin(reader);
+            }
+            Err(err) => log!(LogLevel::Warn, "Child's stdin isn't piped: {}", err),
+        }
+    }
+    if let Some(sockets) = GLOBAL_LISTEN_FDS.get() {
+        sockets.pass(&mut command);
+    }
+    // Files instead of pipes, so a child outliving the runner can keep
+    // writing and the next runner follow it
+    for stream in [Stream::Stdout, Stream::Stderr] {
+        let file = match capture::child_stdio(state_path, stream) {
+            Ok(file) => file,
+            Err(err) => {
+                let path = capture::path(state_path, stream);
+                let error = ErrorArrayItem::new(
+                    Errors::InputOutput,
+                    format!("Failed to open {}: {}", path.display(), err),
+                );
+                log_error(state, error, state_path).await;
+                wind_down_state(state, state_path).await;
+                std::process::exit(100);
+            }
+        };
+        match stream {
+            Stream::Stdout => command.stdout(file),
+            Stream::Stderr => command.stderr(file),
+        };
+    }
+
+    // Its own process group, so kill_mode can reach what it spawns
+    platform::isolate(&mut command);
+
+    let cwd = PathType::PathBuf(launch.cwd.clone());
+    *GLOBAL_LAUNCH.lock().await = Some(launch);
+
+    // Not captured, the runner follows the files
+    match spawn_complex_process(&mut command, Some(cwd), false, false).await {
         Ok(mut spawned_child) => {
             // initialize monitor loop.
             spawned_child.monitor_usage().await;
-            spawned_child.monitor_stdx().await;
             // read the pid from the state
             let pid: u32 = match spawned_child.get_pid().await {
                 Ok(xid) => xid,
@@ -64,9 +232,12 @@ pub async fn create_child(
                 }
             };
 
+            GLOBAL_CHILD_PID.store(pid, Ordering::Relaxed);
+            events::record(Event::ChildSpawned { pid });
+
             // save the pid somewhere
-            let pid_file: PathType =
-                PathType::Content(format!("/tmp/.{}_pg.pid", state.config.app_name));
+            let pid_file = artifacts::pid_file(&state.config.app_name.to_string());
+            artifacts::track(&pid_file);
 
             if let Err(error) = fs::write(pid_file, pid.to_string()) {
                 let error_ref = error.get_ref().unwrap_or_else(|| {
@@ -81,10 +252,25 @@ pub async fn create_child(
             }
             log!(LogLevel::Info, "Child process spawned, pid info saved");
 
+            // Catches a child that couldn't join its cgroup on spawn
+            if let Some(cgroup) = GLOBAL_CGROUP.lock().await.as_ref() {
+                match cgroup.attach(pid) {
+                    Ok(_) => log!(LogLevel::Debug, "Child {} is in its cgroup", pid),
+                    Err(error) => {
+                        log!(
+                            LogLevel::Warn,
+                            "Child is running without cgroup limits: {}",
+                            error
+                        );
+                        state.error_log.push(error);
+                    }
+                }
+            }
+
             if let Ok(metrics) = spawned_child.get_metrics().await {
-                update_state(&mut state, &state_path, Some(metrics)).await;
+                state::save(&mut state, &state_path, Some(metrics)).await;
             }
-            return spawned_child;
+            Ok(spawned_child)
         }
         Err(error) => {
             log_error(&mut state, error, &state_path).await;
@@ -94,157 +280,1015 @@ pub async fn create_child(
     }
 }
 
-/// Execute the optional build command defined in the configuration.
+/// User and group the child should run as.
+#[derive(Debug, Clone)]
+pub struct ChildIdentity {
+    pub user_name: String,
+    pub uid: u32,
+    pub gid: u32,
+    /// Supplementary groups, looked up while resolving the identity so
+    /// nothing reads the group database between fork and exec.
+    pub groups: Vec<u32>,
+    pub home: PathBuf,
+}
+
+impl ChildIdentity {
+    /// Configure `command` to switch to this identity before exec.
+    pub fn apply(&self, command: &mut Command) {
+        platform::switch_identity(self, command);
+    }
+}
+
+/// Resolve `run_as_user` / `run_as_group` into a [`ChildIdentity`].
 ///
-/// Any output produced by the process is stored in the [`AppState`] buffers.
-pub async fn run_one_shot_process(
+/// Returns `Ok(None)` when neither option is set.
+pub fn resolve_identity(
     settings: &AppSpecificConfig,
-    state: &mut AppState,
-    state_path: &PathType,
-) -> Result<(), ErrorArrayItem> {
-    let build_cmd = match &settings.build_command {
-        Some(cmd) => cmd,
+) -> Result<Option<ChildIdentity>, ErrorArrayItem> {
+    platform::lookup_identity(
+        settings.run_as_user.as_deref(),
+        settings.run_as_group.as_deref(),
+    )
+}
+
+/// Forward every line of `pipe` to `sender` until the pipe closes.
+fn forward_lines<R>(
+    pipe: Option<R>,
+    stream: Stream,
+    sender: mpsc::UnboundedSender<(Stream, String)>,
+) where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    let pipe = match pipe {
+        Some(pipe) => pipe,
         None => {
             log!(
-                LogLevel::Info,
-                "No build command specified, skipping build step"
+                LogLevel::Error,
+                "Failed to capture {} of one shot process",
+                stream
             );
-            return Ok(());
+            return;
         }
     };
 
-    let parts = split(build_cmd).unwrap_or_else(|_| {
-        build_cmd
-            .split_whitespace()
-            .map(|s| s.to_string())
-            .collect()
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(pipe).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if sender.send((stream, line)).is_err() {
+                break;
+            }
+        }
     });
-    let mut iter = parts.into_iter();
-    let program = match iter.next() {
-        Some(p) => p,
-        None => {
-            log!(LogLevel::Warn, "Exting build pre-maturly");
-            return Ok(());
+}
+
+/// Stream the output of a one shot `process` into `state` as it arrives and
+/// wait for it to exit.
+///
+/// Each pipe is read by its own task so a process filling its stderr pipe
+/// can't stall on an unread stdout (or the other way around), and lines keep
+/// the order they were written in across both streams. Every line is also
+/// passed to the json log sink as `<step>_stdout` / `<step>_stderr`, like
+/// the child's output and whatever the log level, the state
+/// keeps the newest `max_log_lines` / `max_log_bytes` of each stream, and
+/// `state.data` shows a "`progress`… N lines" indicator while it runs.
+async fn stream_output(
+    process: &mut Child,
+    step: &str,
+    progress: &str,
+    settings: &AppSpecificConfig,
+    state: &mut AppState,
+    state_path: &PathType,
+) -> std::io::Result<ExitStatus> {
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    forward_lines(process.stdout.take(), Stream::Stdout, sender.clone());
+    forward_lines(process.stderr.take(), Stream::Stderr, sender);
+
+    let mut line_count: usize = 0;
+    let mut progress_tick = tokio::time::interval(Duration::from_secs(1));
+
+    loop {
+        tokio::select! {
+            received = receiver.recv() => {
+                let (stream, line) = match received {
+                    Some(received) => received,
+                    // Both pipes closed
+                    None => break,
+                };
+                line_count += 1;
+                let event = format!("{}_{}", step, stream);
+                let entry = (line_timestamp(&line, current_timestamp(), settings), line);
+                emit_output(&event, entry.0, &entry.1);
+                journal::record(&event, [(None, entry.0, entry.1.as_str())]).await;
+                let buffer = match stream {
+                    Stream::Stdout => &mut state.stdout,
+                    Stream::Stderr => &mut state.stderr,
+                };
+                buffer.push(entry);
+                retain_newest(buffer, settings.max_log_lines, settings.max_log_bytes);
+            }
+            _ = progress_tick.tick() => {
+                journal::commit().await;
+                state.data = format!("{}… {} lines", progress, line_count);
+                state::save(state, state_path, None).await;
+            }
         }
+    }
+
+    journal::commit().await;
+    state.data = format!("{}… {} lines", progress, line_count);
+    state::save(state, state_path, None).await;
+    process.wait().await
+}
+
+/// What happens when an install or build command hits its timeout,
+/// `build_timeout_action`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeoutAction {
+    /// Run the command again, up to `build_timeout_retries` times.
+    Retry,
+    /// Give up on the step and keep running the previous build.
+    #[default]
+    Keep,
+}
+
+/// How the child is replaced after a change, `restart_strategy`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartStrategy {
+    /// Kill the child, build, then spawn the new one.
+    #[default]
+    KillFirst,
+    /// Build while the old child keeps running and only swap children once
+    /// the build succeeded. A failed build leaves the old child in place.
+    BuildFirst,
+}
+
+/// What is killed along with the child, `kill_mode`.
+///
+/// The child runs in its own process group, so `sh -c 'npm start'` or a
+/// server forking workers can be taken down as a whole.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum KillMode {
+    /// Only the child itself, grandchildren are left running.
+    Process,
+    /// Everything in the child's process group.
+    #[default]
+    Group,
+    /// Everything in the child's cgroup with `cgroup.kill`, including
+    /// processes that started a session of their own. Needs
+    /// `[app_specific.cgroup]` and Linux 5.14, the process group is killed
+    /// when either is missing.
+    Cgroup,
+}
+
+/// Kill `child` and, depending on `mode`, whatever it spawned.
+pub async fn kill_child(child: &mut SupervisedChild, mode: KillMode) -> Result<(), ErrorArrayItem> {
+    let pid = child.get_pid().await.ok();
+    let killed = kill(child, pid, mode).await;
+    events::record(Event::ChildKilled {
+        pid,
+        error: killed.as_ref().err().map(|err| err.err_mesg.to_string()),
+    });
+    killed
+}
+
+async fn kill(
+    child: &mut SupervisedChild,
+    pid: Option<u32>,
+    mode: KillMode,
+) -> Result<(), ErrorArrayItem> {
+    let killed = child.kill().await;
+    let pid = match (mode, pid) {
+        (KillMode::Process, _) | (_, None) => return killed,
+        (_, Some(pid)) => pid,
     };
 
-    let mut command = Command::new(program);
-    for arg in iter {
-        command.arg(arg);
+    if mode == KillMode::Cgroup {
+        let cgroup = GLOBAL_CGROUP.lock().await;
+        match cgroup.as_ref().map(|cgroup| cgroup.kill()) {
+            Some(Ok(())) => return killed,
+            Some(Err(err)) => log!(
+                LogLevel::Debug,
+                "Falling back to the process group: {}",
+                err
+            ),
+            None => log!(
+                LogLevel::Debug,
+                "No cgroup for the child, killing its process group"
+            ),
+        }
     }
+    // The group outlives its leader as long as anything is left in it
+    if let Err(err) = platform::kill_tree(pid) {
+        log!(
+            LogLevel::Debug,
+            "Nothing left in process group {}: {}",
+            pid,
+            err
+        );
+    }
+    killed
+}
 
-    let mut process = spawn_simple_process(&mut command, true, state, state_path)
-        .await
-        .map_err(ErrorArrayItem::from)?;
+/// How the child ended.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChildExit {
+    Code(i32),
+    Signal(i32),
+}
 
-    if let Some(std) = process.stdout.take() {
-        let buffer = BufReader::new(std);
-        let mut lines = buffer.lines();
-        while let Ok(Some(line)) = lines.next_line().await {
-            state.stdout.push((current_timestamp(), line));
+impl ChildExit {
+    /// Exit code as a shell reports it, `128 + signal` for a killed child,
+    /// so `137` matches a SIGKILL.
+    pub fn code(&self) -> i32 {
+        match self {
+            ChildExit::Code(code) => *code,
+            ChildExit::Signal(signal) => 128 + signal,
         }
-    } else {
-        log!(LogLevel::Error, "Failed to capture stddout for npm install");
     }
+}
 
-    if let Some(std) = process.stderr.take() {
-        let buffer = BufReader::new(std);
-        let mut lines = buffer.lines();
-        while let Ok(Some(line)) = lines.next_line().await {
-            state.stderr.push((current_timestamp(), line));
+impl fmt::Display for ChildExit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChildExit::Code(code) => write!(f, "exited with {}", code),
+            ChildExit::Signal(signal) => match platform::signal_name(*signal) {
+                Some(name) => write!(f, "was killed by {}", name),
+                None => write!(f, "was killed by signal {}", signal),
+            },
         }
-    } else {
-        log!(LogLevel::Error, "Failed to capture stddout for npm install");
     }
+}
 
-    match process.wait().await {
-        Ok(status) => {
-            if status.success() {
-                log!(LogLevel::Debug, "build exited as expected");
-                Ok(())
-            } else {
-                Err(ErrorArrayItem::new(
-                    Errors::GeneralError,
-                    format!("Build command exited with status: {}", status),
-                ))
-            }
+/// Exit status of the child `pid`, if it has exited.
+///
+/// The status is only peeked at (`WNOWAIT`), the zombie is left for the
+/// [`SupervisedChild`] to reap, so this has to run before the child is
+/// polled. Once it was reaped the status is gone and `None` is returned.
+pub fn peek_exit(pid: u32) -> Option<ChildExit> {
+    platform::peek_exit(pid)
+}
+
+/// Whether a child that ended with `exit` should be respawned, according to
+/// `no_restart_on` and `restart_on_exit_codes`.
+///
+/// An unknown exit status is always restarted.
+pub fn should_restart(settings: &AppSpecificConfig, exit: Option<ChildExit>) -> bool {
+    let code = match exit {
+        Some(exit) => exit.code(),
+        None => return true,
+    };
+
+    if settings.no_restart_on.contains(&code) {
+        return false;
+    }
+    settings.restart_on_exit_codes.is_empty() || settings.restart_on_exit_codes.contains(&code)
+}
+
+/// A one shot command run ahead of the child, i.e. install, build or one
+/// of the named `steps`.
+struct OneShotStep<'a> {
+    /// `install`, `build` or the step's name, used for log events and
+    /// messages.
+    name: &'a str,
+    /// Shown in `state.data` while the command runs.
+    progress: &'a str,
+    /// The configured command, the executor may run the step without one.
+    command: Option<&'a str>,
+    /// Directory to run in, relative to the project.
+    cwd: Option<&'a str>,
+    timeout_seconds: Option<u64>,
+    /// What the step reads and produces, for the build cache.
+    inputs: &'a [String],
+    outputs: &'a [String],
+}
+
+impl<'a> OneShotStep<'a> {
+    /// The named build step `step`.
+    fn named(step: &'a BuildStep, progress: &'a str, settings: &AppSpecificConfig) -> Self {
+        Self {
+            name: &step.name,
+            progress,
+            command: Some(&step.command),
+            cwd: step.cwd.as_deref(),
+            timeout_seconds: step.timeout_seconds.or(settings.build_timeout_seconds),
+            inputs: &step.inputs,
+            outputs: &step.outputs,
+        }
+    }
+
+    /// Whether the step has to run, always without a cache.
+    fn lookup(&self, settings: &AppSpecificConfig, cache: Option<&BuildCache>) -> Lookup {
+        let key = CacheKey {
+            name: self.name,
+            command: self.command,
+            cwd: self.cwd,
+            inputs: self.inputs,
+            outputs: self.outputs,
+        };
+        match cache {
+            Some(cache) => cache.lookup(settings, &key),
+            None => Lookup::Uncached,
         }
-        Err(err) => Err(ErrorArrayItem::new(Errors::GeneralError, err.to_string())),
     }
 }
 
-/// Optionally run an install command before building the project.
+fn log_cached(name: &str) {
+    log!(
+        LogLevel::Info,
+        "Skipping the {} step, its inputs are unchanged",
+        name
+    );
+}
+
+/// Spawn a single attempt of `step` and stream its output.
 ///
-/// This is useful for fetching dependencies such as `npm install` prior to
-/// spawning the main child process.
-pub async fn run_install_process(
+/// Returns `Ok(None)` when the command was killed for hitting its timeout.
+/// The command runs in its own process group so a timeout also takes down
+/// anything it spawned (`npm` running `node`, `cargo` running `rustc`).
+async fn attempt_step(
+    step: &OneShotStep<'_>,
+    program: &str,
+    args: &[String],
+    dir: Option<&Path>,
+    settings: &AppSpecificConfig,
+    state: &mut AppState,
+    state_path: &PathType,
+) -> Result<Option<ExitStatus>, ErrorArrayItem> {
+    let mut command = Command::new(program);
+    command.args(args).envs(&settings.env);
+    if let Some(dir) = dir {
+        command.current_dir(dir);
+    }
+    platform::isolate(&mut command);
+
+    let mut process = spawn_simple_process(&mut command, true, state, state_path).await?;
+    let pid = process.id();
+
+    let streamed = stream_output(
+        &mut process,
+        step.name,
+        step.progress,
+        settings,
+        state,
+        state_path,
+    );
+    let result = match step.timeout_seconds {
+        Some(seconds) => match timeout(Duration::from_secs(seconds), streamed).await {
+            Ok(result) => result,
+            Err(_) => {
+                // No pid means it exited just as the timeout hit
+                let killed = pid.map_or(Ok(()), platform::kill_tree);
+                if let Err(err) = killed {
+                    log!(
+                        LogLevel::Warn,
+                        "Failed to kill {} command: {}",
+                        step.name,
+                        err
+                    );
+                }
+                // Reap it so it doesn't linger as a zombie
+                _ = process.wait().await;
+                if let Some(cleanup) = settings.build_executor.executor().cleanup(step.name) {
+                    clean_up_step(step, &cleanup).await;
+                }
+                return Ok(None);
+            }
+        },
+        None => streamed.await,
+    };
+
+    result
+        .map(Some)
+        .map_err(|err| ErrorArrayItem::new(Errors::GeneralError, err.to_string()))
+}
+
+/// Run the executor's `cleanup` command after `step` timed out.
+async fn clean_up_step(step: &OneShotStep<'_>, cleanup: &[String]) {
+    let (program, args) = match cleanup.split_first() {
+        Some(parts) => parts,
+        None => return,
+    };
+    let status = Command::new(program)
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .await;
+    if let Err(err) = status {
+        log!(
+            LogLevel::Warn,
+            "Failed to clean up after the {} command: {}",
+            step.name,
+            err
+        );
+    }
+}
+
+/// Run `step`, applying its timeout and `build_timeout_action`.
+async fn run_step(
+    step: OneShotStep<'_>,
     settings: &AppSpecificConfig,
     state: &mut AppState,
     state_path: &PathType,
 ) -> Result<(), ErrorArrayItem> {
-    let install_cmd = match &settings.install_command {
-        Some(cmd) => cmd,
-        None => {
+    let parts = match step.command {
+        Some(command) => {
+            Some(resolve_secrets(&CommandVars::current(settings).argv(command)).await?)
+        }
+        None => None,
+    };
+
+    let executor = settings.build_executor.executor();
+    let project = fs::canonicalize(&settings.project_path)
+        .unwrap_or_else(|_| PathBuf::from(&settings.project_path));
+    let argv = executor.argv(step.name, parts.as_deref(), &project, &settings.env);
+    let mut argv = match (argv, step.command) {
+        (Some(argv), _) => argv,
+        (None, None) => {
+            log!(
+                LogLevel::Info,
+                "No {} command specified, skipping {} step",
+                step.name,
+                step.name
+            );
+            return Ok(());
+        }
+        (None, Some(_)) => {
             log!(
                 LogLevel::Info,
-                "No install command specified, skipping install step"
+                "The {} build executor skips the {} step",
+                executor.name(),
+                step.name
             );
             return Ok(());
         }
     };
+    let dir = step
+        .cwd
+        .and_then(|cwd| executor.working_dir(&mut argv, &project, cwd));
+    let (program, args) = match argv.split_first() {
+        Some(parts) => parts,
+        None => {
+            log!(LogLevel::Warn, "{} command is empty, skipping", step.name);
+            return Ok(());
+        }
+    };
 
-    let parts = split(install_cmd).unwrap_or_else(|_| {
-        install_cmd
-            .split_whitespace()
-            .map(|s| s.to_string())
-            .collect()
-    });
-    let mut iter = parts.into_iter();
-    let program = match iter.next() {
-        Some(p) => p,
-        None => return Ok(()),
+    let attempts = match settings.build_timeout_action {
+        TimeoutAction::Retry => settings.build_timeout_retries + 1,
+        TimeoutAction::Keep => 1,
     };
 
-    let mut command = Command::new(program);
-    for arg in iter {
-        command.arg(arg);
+    for attempt in 1..=attempts {
+        let status = match attempt_step(
+            &step,
+            program,
+            args,
+            dir.as_deref(),
+            settings,
+            state,
+            state_path,
+        )
+        .await?
+        {
+            Some(status) => status,
+            None => {
+                let error = ErrorArrayItem::new(
+                    Errors::TimedOut,
+                    format!(
+                        "{} command timed out after {}s (attempt {} of {})",
+                        step.name,
+                        step.timeout_seconds.unwrap_or_default(),
+                        attempt,
+                        attempts
+                    ),
+                );
+                log!(LogLevel::Warn, "{}", error);
+                log_error(state, error, state_path).await;
+                continue;
+            }
+        };
+
+        if status.success() {
+            log!(LogLevel::Debug, "{} exited as expected", step.name);
+            return Ok(());
+        }
+        return Err(ErrorArrayItem::new(
+            Errors::GeneralError,
+            format!("{} command exited with status: {}", step.name, status),
+        ));
+    }
+
+    match settings.build_timeout_action {
+        // Not a success, the step didn't finish and its cache entry must
+        // not claim otherwise, see kept_after_timeout
+        TimeoutAction::Keep => Err(ErrorArrayItem::new(
+            Errors::TimedOut,
+            format!(
+                "{} command timed out, keeping the previous build",
+                step.name
+            ),
+        )),
+        TimeoutAction::Retry => Err(ErrorArrayItem::new(
+            Errors::TimedOut,
+            format!(
+                "{} command timed out {} times, giving up",
+                step.name, attempts
+            ),
+        )),
+    }
+}
+
+/// Run `step` unless `cache` has it unchanged, `Ok(false)` when it was
+/// skipped.
+async fn run_cached_step(
+    step: OneShotStep<'_>,
+    settings: &AppSpecificConfig,
+    state: &mut AppState,
+    state_path: &PathType,
+    cache: Option<&mut BuildCache>,
+) -> Result<bool, ErrorArrayItem> {
+    let name = step.name;
+    let lookup = step.lookup(settings, cache.as_deref());
+    if lookup == Lookup::Fresh {
+        log_cached(name);
+        return Ok(false);
     }
+    let result = run_step(step, settings, state, state_path).await;
+    // Only a run to exit 0 is Ok, a step kept after its timeout isn't
+    if let Some(cache) = cache {
+        cache.remember(name, lookup, result.is_ok());
+    }
+    result.map(|_| true)
+}
 
-    let mut process = spawn_simple_process(&mut command, true, state, state_path)
+/// Execute the optional build command defined in the configuration.
+///
+/// Output is streamed into the [`AppState`] buffers while the process runs.
+/// With `build_inputs` set and a `cache`, the command is skipped while its
+/// inputs are unchanged.
+pub async fn run_one_shot_process(
+    settings: &AppSpecificConfig,
+    state: &mut AppState,
+    state_path: &PathType,
+    cache: Option<&mut BuildCache>,
+) -> Result<(), ErrorArrayItem> {
+    let step = OneShotStep {
+        name: "build",
+        progress: "building",
+        command: settings.build_command.as_deref(),
+        cwd: None,
+        timeout_seconds: settings.build_timeout_seconds,
+        inputs: &settings.build_inputs,
+        outputs: &settings.build_outputs,
+    };
+    run_cached_step(step, settings, state, state_path, cache)
         .await
-        .map_err(ErrorArrayItem::from)?;
+        .map(|_| ())
+}
 
-    if let Some(std) = process.stdout.take() {
-        let buffer = BufReader::new(std);
-        let mut lines = buffer.lines();
-        while let Ok(Some(line)) = lines.next_line().await {
-            state.stdout.push((current_timestamp(), line));
+/// Optionally run an install command before building the project.
+///
+/// This is useful for fetching dependencies such as `npm install` prior to
+/// spawning the main child process.
+pub async fn run_install_process(
+    settings: &AppSpecificConfig,
+    state: &mut AppState,
+    state_path: &PathType,
+) -> Result<(), ErrorArrayItem> {
+    let step = OneShotStep {
+        name: "install",
+        progress: "installing",
+        command: settings.install_command.as_deref(),
+        cwd: None,
+        timeout_seconds: settings.install_timeout_seconds,
+        inputs: &[],
+        outputs: &[],
+    };
+    run_step(step, settings, state, state_path).await
+}
+
+/// Run the named `steps`, adding how each went to `runs`.
+///
+/// A failed step ends the build unless it may fail, the steps after it
+/// don't run then. Steps with `depends_on` run along their dependencies,
+/// see [`run_step_graph`]. Steps `cache` has unchanged are skipped.
+pub async fn run_build_steps(
+    settings: &AppSpecificConfig,
+    state: &mut AppState,
+    state_path: &PathType,
+    runs: &mut Vec<StepRun>,
+    mut cache: Option<&mut BuildCache>,
+) -> Result<(), ErrorArrayItem> {
+    if !is_sequential(&settings.steps) {
+        return run_step_graph(settings, state, state_path, runs, cache).await;
+    }
+
+    let total = settings.steps.len();
+    for (index, configured) in settings.steps.iter().enumerate() {
+        let progress = format!("{} ({}/{})", configured.name, index + 1, total);
+        let step = OneShotStep::named(configured, &progress, settings);
+
+        let started = Instant::now();
+        let result = run_cached_step(step, settings, state, state_path, cache.as_deref_mut()).await;
+        let run = match result {
+            Ok(false) => StepRun::cached(&configured.name),
+            _ => StepRun::new(&configured.name, started.elapsed(), result.is_ok()),
+        };
+        log!(LogLevel::Info, "Step {}", run);
+        runs.push(run);
+
+        if let Err(err) = result {
+            match configured.continue_on_failure {
+                true => {
+                    log!(
+                        LogLevel::Warn,
+                        "Continuing after the {} step failed: {}",
+                        configured.name,
+                        err
+                    );
+                    log_error(state, err, state_path).await;
+                }
+                false => return Err(err),
+            }
         }
-    } else {
-        log!(LogLevel::Error, "Failed to capture stddout for npm install");
     }
+    Ok(())
+}
 
-    if let Some(std) = process.stderr.take() {
-        let buffer = BufReader::new(std);
-        let mut lines = buffer.lines();
-        while let Ok(Some(line)) = lines.next_line().await {
-            state.stderr.push((current_timestamp(), line));
+/// A build step running side by side with others: its index, how long it
+/// took, the state it streamed into and how it went.
+type StepFuture<'a> =
+    Pin<Box<dyn Future<Output = (usize, Duration, AppState, Result<(), ErrorArrayItem>)> + 'a>>;
+
+/// Wait for the first of `running` to finish and take it out.
+///
+/// The steps are polled in place, so they can borrow the settings and
+/// don't have to be `Send`.
+async fn first_finished<'a>(
+    running: &mut Vec<StepFuture<'a>>,
+) -> (usize, Duration, AppState, Result<(), ErrorArrayItem>) {
+    poll_fn(|cx| {
+        let finished =
+            running
+                .iter_mut()
+                .enumerate()
+                .find_map(|(index, step)| match step.as_mut().poll(cx) {
+                    Poll::Ready(output) => Some((index, output)),
+                    Poll::Pending => None,
+                });
+        match finished {
+            Some((index, output)) => {
+                _ = running.swap_remove(index);
+                Poll::Ready(output)
+            }
+            None => Poll::Pending,
+        }
+    })
+    .await
+}
+
+/// State file a step running side by side with others writes to.
+fn scratch_state_path(state_path: &PathType, step: &str) -> PathType {
+    PathType::PathBuf(PathBuf::from(format!("{}.step-{}", state_path, step)))
+}
+
+/// Run the named `steps` along their `depends_on`, up to
+/// `max_parallel_steps` at a time.
+///
+/// Every step streams into a state of its own, its output and errors are
+/// moved into `state` once it finished. After a failed step nothing else
+/// is started, the running steps are waited for and all failures are
+/// returned as one error. Steps `cache` has unchanged finish right away.
+async fn run_step_graph(
+    settings: &AppSpecificConfig,
+    state: &mut AppState,
+    state_path: &PathType,
+    runs: &mut Vec<StepRun>,
+    mut cache: Option<&mut BuildCache>,
+) -> Result<(), ErrorArrayItem> {
+    let steps = &settings.steps;
+    let slots = parallelism(settings.max_parallel_steps);
+    let mut schedule = Schedule::new(steps);
+    let mut lookups = vec![Lookup::Uncached; steps.len()];
+    let mut running: Vec<StepFuture> = Vec::new();
+    let mut failures = Vec::new();
+
+    loop {
+        let mut skipped = false;
+        for index in schedule.start(slots.saturating_sub(running.len())) {
+            let configured = &steps[index];
+            let progress = configured.name.clone();
+            let lookup = OneShotStep::named(configured, &progress, settings)
+                .lookup(settings, cache.as_deref());
+            if lookup == Lookup::Fresh {
+                // Its slot is free again, the steps waiting for it can start
+                log_cached(&configured.name);
+                runs.push(StepRun::cached(&configured.name));
+                schedule.finish(index, true);
+                skipped = true;
+                continue;
+            }
+            lookups[index] = lookup;
+            let path = scratch_state_path(state_path, &configured.name);
+            let mut scratch = new_application_state(&state.config);
+            running.push(Box::pin(async move {
+                let progress = configured.name.clone();
+                let step = OneShotStep::named(configured, &progress, settings);
+                let started = Instant::now();
+                let result = run_step(step, settings, &mut scratch, &path).await;
+                _ = path.delete();
+                (index, started.elapsed(), scratch, result)
+            }));
+        }
+        if skipped {
+            continue;
+        }
+        if running.is_empty() {
+            break;
+        }
+        let names: Vec<&str> = schedule
+            .running()
+            .into_iter()
+            .map(|index| steps[index].name.as_str())
+            .collect();
+        state.data = format!("building {}", names.join(", "));
+        state::save(state, state_path, None).await;
+
+        let (index, elapsed, mut scratch, result) = first_finished(&mut running).await;
+        let configured = &steps[index];
+        state.stdout.append(&mut scratch.stdout);
+        state.stderr.append(&mut scratch.stderr);
+        for err in scratch.error_log.drain(..) {
+            log_error(state, err, state_path).await;
+        }
+
+        if let Some(cache) = cache.as_deref_mut() {
+            let lookup = std::mem::replace(&mut lookups[index], Lookup::Uncached);
+            cache.remember(&configured.name, lookup, result.is_ok());
+        }
+        let run = StepRun::new(&configured.name, elapsed, result.is_ok());
+        log!(LogLevel::Info, "Step {}", run);
+        runs.push(run);
+        schedule.finish(index, result.is_ok() || configured.continue_on_failure);
+        if let Err(err) = result {
+            match configured.continue_on_failure {
+                true => {
+                    log!(
+                        LogLevel::Warn,
+                        "Continuing after the {} step failed: {}",
+                        configured.name,
+                        err
+                    );
+                    log_error(state, err, state_path).await;
+                }
+                false => failures.push(err),
+            }
+        }
+    }
+
+    for index in schedule.pending() {
+        log!(
+            LogLevel::Info,
+            "Skipped the {} step, the build failed before it could run",
+            steps[index].name
+        );
+    }
+    match failures.len() {
+        0 => Ok(()),
+        1 => Err(failures.remove(0)),
+        count => {
+            // Only timeouts still keep the current child
+            let kind = match failures
+                .iter()
+                .all(|err| matches!(err.err_type, Errors::TimedOut))
+            {
+                true => Errors::TimedOut,
+                false => Errors::GeneralError,
+            };
+            let failures: Vec<String> = failures.iter().map(|err| err.to_string()).collect();
+            Err(ErrorArrayItem::new(
+                kind,
+                format!("{} build steps failed: {}", count, failures.join("; ")),
+            ))
+        }
+    }
+}
+
+/// Whether a failed build only ran past its timeout under
+/// `build_timeout_action = "keep"`, so the current child keeps serving
+/// instead of being replaced by a half finished build.
+pub fn kept_after_timeout(err: &ErrorArrayItem, settings: &AppSpecificConfig) -> bool {
+    settings.build_timeout_action == TimeoutAction::Keep && matches!(err.err_type, Errors::TimedOut)
+}
+
+/// Unix process handling: process groups, `waitid` and switching users.
+#[cfg(unix)]
+mod platform {
+    use artisan_middleware::dusa_collection_utils::core::errors::{ErrorArrayItem, Errors};
+    use nix::sys::signal::{Signal, killpg};
+    use nix::sys::wait::{Id, WaitPidFlag, WaitStatus, waitid};
+    use nix::unistd::{Gid, Group, Pid, Uid, User, getgrouplist, setgid, setgroups, setuid};
+    use std::ffi::CString;
+    use tokio::process::Command;
+
+    use super::{ChildExit, ChildIdentity};
+
+    /// Run `command` in its own process group.
+    pub fn isolate(command: &mut Command) {
+        command.process_group(0);
+    }
+
+    /// Kill the process group led by `pid`.
+    pub fn kill_tree(pid: u32) -> Result<(), String> {
+        killpg(Pid::from_raw(pid as i32), Signal::SIGKILL).map_err(|err| err.to_string())
+    }
+
+    pub fn signal_name(signal: i32) -> Option<String> {
+        Signal::try_from(signal)
+            .ok()
+            .map(|signal| signal.to_string())
+    }
+
+    pub fn peek_exit(pid: u32) -> Option<ChildExit> {
+        let flags = WaitPidFlag::WEXITED | WaitPidFlag::WNOHANG | WaitPidFlag::WNOWAIT;
+        match waitid(Id::Pid(Pid::from_raw(pid as i32)), flags) {
+            Ok(WaitStatus::Exited(_, code)) => Some(ChildExit::Code(code)),
+            Ok(WaitStatus::Signaled(_, signal, _)) => Some(ChildExit::Signal(signal as i32)),
+            _ => None,
         }
-    } else {
-        log!(LogLevel::Error, "Failed to capture stddout for npm install");
     }
 
-    match process.wait().await {
-        Ok(status) => {
-            if status.success() {
+    pub fn switch_identity(identity: &ChildIdentity, command: &mut Command) {
+        command
+            .env("USER", &identity.user_name)
+            .env("LOGNAME", &identity.user_name)
+            .env("HOME", &identity.home);
+        // After drop_privileges the runner already is the identity and
+        // couldn't set the groups anyway
+        if !Uid::effective().is_root() {
+            return;
+        }
+
+        let groups: Vec<Gid> = identity
+            .groups
+            .iter()
+            .map(|gid| Gid::from_raw(*gid))
+            .collect();
+        let gid = Gid::from_raw(identity.gid);
+        let uid = Uid::from_raw(identity.uid);
+        // Not through Command::uid/gid: std switches the user before it runs
+        // pre_exec hooks, and only root may set the supplementary groups,
+        // otherwise the child keeps root's. Nothing but syscalls after fork.
+        unsafe {
+            command.pre_exec(move || {
+                setgroups(&groups)?;
+                setgid(gid)?;
+                setuid(uid)?;
                 Ok(())
-            } else {
-                Err(ErrorArrayItem::new(
+            });
+        }
+    }
+
+    pub fn lookup_identity(
+        run_as_user: Option<&str>,
+        run_as_group: Option<&str>,
+    ) -> Result<Option<ChildIdentity>, ErrorArrayItem> {
+        if run_as_user.is_none() && run_as_group.is_none() {
+            return Ok(None);
+        }
+
+        let user = match run_as_user {
+            Some(name) => match User::from_name(name) {
+                Ok(Some(user)) => user,
+                Ok(None) => {
+                    return Err(ErrorArrayItem::new(
+                        Errors::GeneralError,
+                        format!("run_as_user {} doesn't exist on this host", name),
+                    ));
+                }
+                Err(err) => {
+                    return Err(ErrorArrayItem::new(
+                        Errors::GeneralError,
+                        format!("Failed to look up run_as_user {}: {}", name, err),
+                    ));
+                }
+            },
+            None => match User::from_uid(Uid::current()) {
+                Ok(Some(user)) => user,
+                _ => {
+                    return Err(ErrorArrayItem::new(
+                        Errors::GeneralError,
+                        "Failed to look up the current user",
+                    ));
+                }
+            },
+        };
+
+        let gid = match run_as_group {
+            Some(name) => match Group::from_name(name) {
+                Ok(Some(group)) => group.gid,
+                Ok(None) => {
+                    return Err(ErrorArrayItem::new(
+                        Errors::GeneralError,
+                        format!("run_as_group {} doesn't exist on this host", name),
+                    ));
+                }
+                Err(err) => {
+                    return Err(ErrorArrayItem::new(
+                        Errors::GeneralError,
+                        format!("Failed to look up run_as_group {}: {}", name, err),
+                    ));
+                }
+            },
+            None => user.gid,
+        };
+
+        if !Uid::effective().is_root() && (user.uid != Uid::effective() || gid != Gid::effective())
+        {
+            return Err(ErrorArrayItem::new(
+                Errors::GeneralError,
+                format!(
+                    "The runner must run as root to start the child as {}",
+                    user.name
+                ),
+            ));
+        }
+
+        let groups = match CString::new(user.name.clone()) {
+            Ok(name) => getgrouplist(&name, gid).map_err(|err| {
+                ErrorArrayItem::new(
                     Errors::GeneralError,
-                    format!("Install command exited with status: {}", status),
-                ))
-            }
+                    format!("Failed to look up the groups of {}: {}", user.name, err),
+                )
+            })?,
+            Err(_) => vec![gid],
+        };
+
+        Ok(Some(ChildIdentity {
+            user_name: user.name,
+            uid: user.uid.as_raw(),
+            gid: gid.as_raw(),
+            groups: groups.iter().map(|gid| gid.as_raw()).collect(),
+            home: user.dir,
+        }))
+    }
+}
+
+/// Windows process handling: a new process group per child, trees killed
+/// with `taskkill`. Exit statuses can't be peeked at and children can't be
+/// started as another user.
+#[cfg(windows)]
+mod platform {
+    use artisan_middleware::dusa_collection_utils::core::errors::{ErrorArrayItem, Errors};
+    use tokio::process::Command;
+
+    use super::{ChildExit, ChildIdentity};
+
+    /// `CREATE_NEW_PROCESS_GROUP`
+    const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+
+    /// Run `command` in its own process group.
+    pub fn isolate(command: &mut Command) {
+        command.creation_flags(CREATE_NEW_PROCESS_GROUP);
+    }
+
+    /// Kill `pid` and every process it started.
+    pub fn kill_tree(pid: u32) -> Result<(), String> {
+        let status = std::process::Command::new("taskkill")
+            .args(["/T", "/F", "/PID", &pid.to_string()])
+            .status()
+            .map_err(|err| err.to_string())?;
+        match status.success() {
+            true => Ok(()),
+            false => Err(format!("taskkill {}", status)),
+        }
+    }
+
+    pub fn signal_name(_signal: i32) -> Option<String> {
+        None
+    }
+
+    pub fn peek_exit(_pid: u32) -> Option<ChildExit> {
+        None
+    }
+
+    pub fn switch_identity(_identity: &ChildIdentity, _command: &mut Command) {}
+
+    pub fn lookup_identity(
+        run_as_user: Option<&str>,
+        run_as_group: Option<&str>,
+    ) -> Result<Option<ChildIdentity>, ErrorArrayItem> {
+        match run_as_user.is_none() && run_as_group.is_none() {
+            true => Ok(None),
+            false => Err(ErrorArrayItem::new(
+                Errors::GeneralError,
+                "run_as_user and run_as_group aren't supported on Windows",
+            )),
         }
-        Err(err) => Err(ErrorArrayItem::new(Errors::GeneralError, err.to_string())),
     }
 }
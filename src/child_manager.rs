@@ -0,0 +1,140 @@
+//! The current child, owned by a task of its own.
+//!
+//! The main loop, restarts, ready checks, secret rotation and the status
+//! server all need the child. They used to lock one shared
+//! `Mutex<Option<SupervisedChild>>`, waiting on whoever held it across a
+//! slow call, and could keep a handle to a child that was replaced
+//! meanwhile. Now the manager task is the only owner of the child. Everyone
+//! else sends it a [`Command`] through the functions below and gets the
+//! answer back, so no handle outlives the child it belongs to.
+//!
+//! The manager is started on first use. Without a child every query
+//! answers `None`.
+
+use artisan_middleware::dusa_collection_utils::core::errors::ErrorArrayItem;
+use artisan_middleware::process_manager::{Metrics, SupervisedChild};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::child::{KillMode, kill_child};
+use crate::global_child::GLOBAL_CHILD;
+
+/// Commands queued while the manager is busy before senders wait.
+const COMMAND_QUEUE: usize = 32;
+
+/// What the manager is asked to do with the child.
+pub enum Command {
+    /// Supervise a new child, dropping the previous one.
+    Replace(SupervisedChild),
+    /// Hand the child over, leaving none.
+    Take(oneshot::Sender<Option<SupervisedChild>>),
+    Running(oneshot::Sender<Option<bool>>),
+    Pid(oneshot::Sender<Option<u32>>),
+    Metrics(oneshot::Sender<Option<Metrics>>),
+    /// Kill the child in place, its output stays readable.
+    Kill(
+        KillMode,
+        oneshot::Sender<Option<Result<(), ErrorArrayItem>>>,
+    ),
+}
+
+/// Sends commands to the manager task.
+#[derive(Debug, Clone)]
+pub struct ChildManager {
+    commands: mpsc::Sender<Command>,
+}
+
+impl ChildManager {
+    /// Start a manager task without a child.
+    pub fn start() -> Self {
+        let (commands, receiver) = mpsc::channel(COMMAND_QUEUE);
+        tokio::spawn(run(receiver));
+        Self { commands }
+    }
+
+    pub async fn send(&self, command: Command) {
+        // Only fails once the runtime is gone, with the child dropped along
+        let _ = self.commands.send(command).await;
+    }
+
+    /// Send the command `ask` builds and wait for the answer, `None` if
+    /// the manager is gone.
+    async fn ask<T>(&self, ask: impl FnOnce(oneshot::Sender<Option<T>>) -> Command) -> Option<T> {
+        let (reply, answer) = oneshot::channel();
+        self.send(ask(reply)).await;
+        answer.await.ok().flatten()
+    }
+}
+
+async fn run(mut commands: mpsc::Receiver<Command>) {
+    let mut child: Option<SupervisedChild> = None;
+    while let Some(command) = commands.recv().await {
+        // Answers nobody waits for anymore are dropped
+        match command {
+            Command::Replace(new) => child = Some(new),
+            Command::Take(reply) => {
+                let _ = reply.send(child.take());
+            }
+            Command::Running(reply) => {
+                let running = match &child {
+                    Some(child) => Some(child.running().await),
+                    None => None,
+                };
+                let _ = reply.send(running);
+            }
+            Command::Pid(reply) => {
+                let pid = match &child {
+                    Some(child) => child.get_pid().await.ok(),
+                    None => None,
+                };
+                let _ = reply.send(pid);
+            }
+            Command::Metrics(reply) => {
+                let metrics = match &child {
+                    Some(child) => child.get_metrics().await.ok(),
+                    None => None,
+                };
+                let _ = reply.send(metrics);
+            }
+            Command::Kill(mode, reply) => {
+                let killed = match child.as_mut() {
+                    Some(child) => Some(kill_child(child, mode).await),
+                    None => None,
+                };
+                let _ = reply.send(killed);
+            }
+        }
+    }
+}
+
+fn manager() -> &'static ChildManager {
+    GLOBAL_CHILD.get_or_init(ChildManager::start)
+}
+
+/// Supervise `child` from now on. The previous child is dropped, which
+/// kills it if it's still around.
+pub async fn replace(child: SupervisedChild) {
+    manager().send(Command::Replace(child)).await;
+}
+
+/// Take the child away from the manager, to stop it for good.
+pub async fn take() -> Option<SupervisedChild> {
+    manager().ask(Command::Take).await
+}
+
+/// Whether the child still runs, `None` without a child.
+pub async fn running() -> Option<bool> {
+    manager().ask(Command::Running).await
+}
+
+pub async fn pid() -> Option<u32> {
+    manager().ask(Command::Pid).await
+}
+
+pub async fn metrics() -> Option<Metrics> {
+    manager().ask(Command::Metrics).await
+}
+
+/// Kill the child with `mode`, `None` without a child.
+pub async fn kill(mode: KillMode) -> Option<Result<(), ErrorArrayItem>> {
+    manager().ask(|reply| Command::Kill(mode, reply)).await
+}
@@ -0,0 +1,1300 @@
+//! Command line interface.
+//!
+//! Running the binary without a subcommand (or with `run`) starts the
+//! supervisor. The remaining subcommands are small operator helpers that work
+//! against the persisted [`AppState`] of an instance started from the same
+//! configuration directory.
+
+use artisan_middleware::{
+    config::AppConfig,
+    dusa_collection_utils::core::types::pathtype::PathType,
+    state_persistence::{AppState, StatePersistence, update_state},
+    timestamp::current_timestamp,
+};
+use clap::{Parser, Subcommand};
+use colored::Colorize;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    audit::{self, AuditEntry},
+    bundle::{self, BUNDLE_VERSION, Bundle, Contents},
+    capture::{self, OutputCapture},
+    cgroup,
+    config::{AppSpecificConfig, get_config, specific_config},
+    config_schema,
+    deploy_trace::Timeline,
+    dump, env_overrides, log_level,
+    maintenance::{FLAG_FILE, MaintenanceSource, flag_path},
+    metrics_history::{self, MetricsHistory, sparkline},
+    migrate::{SystemdUnit, export, migrate},
+    output::{OutputLine, Stream, merged, merged_tail},
+    reset::{self, ResetScope},
+    runner_state::{RestartRecord, RunnerState},
+    signals::{self, Control},
+    stdin, uptime, validation,
+};
+
+/// Artisan process runner.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Directory holding Config.toml and Overrides.toml, defaults to the
+    /// current working directory.
+    #[arg(short = 'C', long, global = true)]
+    pub dir: Option<PathBuf>,
+
+    /// Run config, secrets, install and build, then exit with a report
+    /// instead of spawning run_command.
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Print every app_specific option with its type and default, then
+    /// exit.
+    #[arg(long)]
+    pub explain_config: bool,
+
+    /// Act as the init process of a container: reap orphaned zombies,
+    /// forward signals to the child and shut down on SIGTERM.
+    #[arg(long)]
+    pub init: bool,
+
+    /// Exit with the child's exit code when it exits instead of respawning
+    /// it.
+    #[arg(long)]
+    pub oneshot: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Start supervising the configured application (default).
+    Run,
+    /// Print the persisted state of the running instance.
+    Status,
+    /// Print the newest state of every runner publishing to the configured
+    /// state backend.
+    States {
+        /// Print the states as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the captured stdout/stderr of the running instance.
+    Logs {
+        /// Number of lines to print from each stream.
+        #[arg(short = 'n', long, default_value_t = 50)]
+        lines: usize,
+        /// Only print stderr.
+        #[arg(long)]
+        stderr: bool,
+        /// Keep polling the state file for new lines.
+        #[arg(short, long)]
+        follow: bool,
+    },
+    /// Ask the running instance to rebuild and respawn its child.
+    Restart {
+        /// Note recorded with this restart in the restart history.
+        #[arg(short, long)]
+        note: Option<String>,
+        /// Run every build step, even those whose inputs are unchanged.
+        #[arg(long)]
+        no_cache: bool,
+    },
+    /// Print the restart history: why the child restarted, how long the
+    /// build took and whether the new child came up.
+    History {
+        /// Number of restarts to print.
+        #[arg(short = 'n', long, default_value_t = 20)]
+        lines: usize,
+        /// Print the records as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print how long each stage of the last restarts took, from the
+    /// triggering event until the new child was ready.
+    Trace {
+        /// Number of restarts to print.
+        #[arg(short = 'n', long, default_value_t = 10)]
+        deploys: usize,
+        /// Print the timelines as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Summarize uptime, outages, restarts by cause and deploys over a
+    /// period.
+    Report {
+        /// How far back to look, e.g. `30d`, `12h`, `2w`.
+        #[arg(long, default_value = "30d")]
+        period: String,
+        /// Print the report as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Attach a note to the next restart, explaining why it happens.
+    Annotate {
+        note: String,
+        /// Attach it to the most recent restart instead.
+        #[arg(long)]
+        last: bool,
+    },
+    /// Respawn the last child that became ready exactly as it was started,
+    /// skipping config reload, install and build.
+    RestoreLastKnownGood,
+    /// Stop deploying changes and restarting or respawning the child while
+    /// it's fixed by hand.
+    Maintenance {
+        #[command(subcommand)]
+        action: MaintenanceAction,
+    },
+    /// Change the runner's log level without touching the child.
+    LogLevel {
+        /// Trace, debug, info, warn or error, or `reset` to go back to the
+        /// level in Config.toml.
+        level: String,
+    },
+    /// Change the child's cgroup limits without restarting it, and keep
+    /// them in Config.toml.
+    Limits {
+        /// Memory limit in MB, 0 for none.
+        #[arg(long)]
+        memory_mb: Option<u64>,
+        /// CPU limit in percent of one core, 0 for none.
+        #[arg(long)]
+        cpu_percent: Option<u32>,
+    },
+    /// Have the runner write what it's doing to `<state file>.dump` and
+    /// print it, without touching the child.
+    Dump,
+    /// Write a line to the child's stdin, for apps that take admin commands
+    /// there. Needs `stdin_pipe = true`.
+    SendStdin {
+        text: String,
+        /// Don't end the text with a newline.
+        #[arg(long)]
+        no_newline: bool,
+    },
+    /// Clear parts of the persisted state without losing the rest.
+    Reset {
+        /// Clear the error log.
+        #[arg(long)]
+        errors: bool,
+        /// Clear the captured stdout and stderr.
+        #[arg(long)]
+        output: bool,
+        /// Reset the event counter.
+        #[arg(long)]
+        counters: bool,
+        /// Clear all of the above.
+        #[arg(long)]
+        all: bool,
+    },
+    /// Print the audit log of control commands.
+    Audit {
+        /// Number of entries to print.
+        #[arg(short = 'n', long, default_value_t = 50)]
+        lines: usize,
+    },
+    /// Parse Config.toml and report any errors without starting anything.
+    ValidateConfig,
+    /// Generate a starter Config.toml from an existing systemd unit file.
+    MigrateFromSystemd {
+        /// Path to the unit file, e.g. /etc/systemd/system/my_app.service.
+        unit: PathBuf,
+        /// Write the config here instead of printing it.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Render the current config as a standalone systemd unit and env file.
+    ExportSystemd {
+        /// Directory to write `<app_name>.service` and `<app_name>.env` to,
+        /// both are printed when omitted.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Path the unit should load its environment from, defaults to
+        /// `/etc/default/<app_name>`.
+        #[arg(long)]
+        env_file: Option<PathBuf>,
+    },
+    /// Package Config.toml, Overrides.toml and the env template into a
+    /// signed bundle for setting up the runner on another host.
+    ExportBundle {
+        /// File holding the key the bundle is signed with.
+        #[arg(long)]
+        key: PathBuf,
+        /// Write the bundle here instead of printing it.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Install the files of a bundle made with `export-bundle`.
+    ImportBundle {
+        bundle: PathBuf,
+        /// File holding the key the bundle was signed with.
+        #[arg(long)]
+        key: PathBuf,
+        /// Replace files that already exist.
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Subcommand)]
+pub enum MaintenanceAction {
+    /// Switch maintenance mode on.
+    On,
+    /// Switch maintenance mode off, whether a flag file or `SIGUSR2` turned
+    /// it on.
+    Off,
+}
+
+impl Command {
+    /// How the command shows up in the audit log, `None` for commands that
+    /// don't touch a running instance.
+    pub fn audit_description(&self) -> Option<String> {
+        match self {
+            Command::Status => Some(String::from("status")),
+            Command::Logs {
+                lines,
+                stderr,
+                follow,
+            } => {
+                let mut description = format!("logs -n {}", lines);
+                if *stderr {
+                    description.push_str(" --stderr");
+                }
+                if *follow {
+                    description.push_str(" --follow");
+                }
+                Some(description)
+            }
+            Command::History { lines, json } => match json {
+                true => Some(format!("history -n {} --json", lines)),
+                false => Some(format!("history -n {}", lines)),
+            },
+            Command::Trace { deploys, json } => match json {
+                true => Some(format!("trace -n {} --json", deploys)),
+                false => Some(format!("trace -n {}", deploys)),
+            },
+            Command::Report { period, json } => match json {
+                true => Some(format!("report --period {} --json", period)),
+                false => Some(format!("report --period {}", period)),
+            },
+            Command::Restart { note, no_cache } => {
+                let mut description = String::from("restart");
+                if let Some(note) = note {
+                    description.push_str(&format!(" --note {:?}", note));
+                }
+                if *no_cache {
+                    description.push_str(" --no-cache");
+                }
+                Some(description)
+            }
+            Command::Annotate { note, last: false } => Some(format!("annotate {:?}", note)),
+            Command::Annotate { note, last: true } => Some(format!("annotate --last {:?}", note)),
+            Command::RestoreLastKnownGood => Some(String::from("restore-last-known-good")),
+            Command::Maintenance {
+                action: MaintenanceAction::On,
+            } => Some(String::from("maintenance on")),
+            Command::Maintenance {
+                action: MaintenanceAction::Off,
+            } => Some(String::from("maintenance off")),
+            Command::LogLevel { level } => Some(format!("log-level {}", level)),
+            Command::Limits {
+                memory_mb,
+                cpu_percent,
+            } => {
+                let mut description = String::from("limits");
+                if let Some(mb) = memory_mb {
+                    description.push_str(&format!(" --memory-mb {}", mb));
+                }
+                if let Some(percent) = cpu_percent {
+                    description.push_str(&format!(" --cpu-percent {}", percent));
+                }
+                Some(description)
+            }
+            Command::Dump => Some(String::from("dump")),
+            Command::SendStdin { text, no_newline } => match no_newline {
+                true => Some(format!("send-stdin --no-newline {:?}", text)),
+                false => Some(format!("send-stdin {:?}", text)),
+            },
+            Command::Reset {
+                errors,
+                output,
+                counters,
+                all,
+            } => {
+                let mut description = String::from("reset");
+                for (set, flag) in [
+                    (errors, " --errors"),
+                    (output, " --output"),
+                    (counters, " --counters"),
+                    (all, " --all"),
+                ] {
+                    if *set {
+                        description.push_str(flag);
+                    }
+                }
+                Some(description)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Cli {
+    /// Apply global options, must run before any config is loaded.
+    pub fn apply_globals(&self) -> Result<(), String> {
+        if let Some(dir) = &self.dir {
+            std::env::set_current_dir(dir)
+                .map_err(|err| format!("Failed to enter {}: {}", dir.display(), err))?;
+        }
+        Ok(())
+    }
+}
+
+async fn load_state() -> Result<(AppConfig, PathType, AppState), String> {
+    let config: AppConfig = get_config();
+    let state_path: PathType = StatePersistence::get_state_path(&config);
+    match StatePersistence::load_state(&state_path).await {
+        Ok(state) => Ok((config, state_path, state)),
+        Err(err) => Err(format!(
+            "Failed to load state from {}: {}",
+            state_path, err
+        )),
+    }
+}
+
+fn pid_alive(pid: u32) -> bool {
+    signals::is_alive(pid)
+}
+
+/// `status` subcommand.
+pub async fn status() -> Result<(), String> {
+    let (_, state_path, state) = load_state().await?;
+
+    let liveness = if pid_alive(state.pid) {
+        "alive".green()
+    } else {
+        "not running".red()
+    };
+
+    println!("{} {}", "State file:".bold(), state_path);
+    println!("{} {} ({})", "Runner pid:".bold(), state.pid, liveness);
+    println!("{}", state);
+
+    let runner_state = RunnerState::load(&state_path);
+    if let Some(host) = &runner_state.host {
+        let flag = |available: bool| match available {
+            true => "yes".green(),
+            false => "no".yellow(),
+        };
+        println!("{}", "Host:".bold());
+        println!("  arch {} kernel {}", host.arch, host.kernel);
+        println!(
+            "  cgroup v2: {}  pidfd: {}  seccomp: {}",
+            flag(host.cgroup_v2),
+            flag(host.pidfd),
+            flag(host.seccomp)
+        );
+        println!(
+            "  inotify watches: {}",
+            host.inotify_max_watches
+                .map_or_else(|| String::from("unknown"), |watches| watches.to_string())
+        );
+    }
+
+    let restarts = &runner_state.restart_history;
+    if !restarts.is_empty() {
+        println!("{}", "Recent restarts:".bold());
+        for record in &restarts[restarts.len().saturating_sub(5)..] {
+            print_restart(record);
+        }
+    }
+    if let Some((timestamp, exit)) = &runner_state.last_exit {
+        println!(
+            "{} {} ({})",
+            "Last child exit:".bold(),
+            exit,
+            timestamp.to_string().dimmed()
+        );
+    }
+    if let Some(report) = &runner_state.crash_loop {
+        println!("{} {}", "Crash loop:".red().bold(), report);
+        for line in &report.stderr {
+            println!("  {}", line);
+        }
+        if let Some(path) = &report.diagnostics {
+            println!("{} {}", "Diagnostics:".bold(), path);
+        }
+        println!("Run `ais_runner restart` once the cause is fixed");
+    }
+    if let Some(note) = &runner_state.pending_note {
+        println!("{} {}", "Note for the next restart:".bold(), note);
+    }
+    if let Some(level) = &runner_state.log_level {
+        println!("{} {} (set with log-level)", "Log level:".bold(), level);
+    }
+    if let Some(port) = runner_state.port {
+        println!("{} {}", "Port:".bold(), port);
+    }
+    if let Some(maintenance) = &runner_state.maintenance {
+        let source = match maintenance.source {
+            MaintenanceSource::Signal => Control::Maintenance.describe(),
+            MaintenanceSource::FlagFile => FLAG_FILE,
+        };
+        println!(
+            "{} on since {} ({})",
+            "Maintenance mode:".yellow().bold(),
+            maintenance.since,
+            source
+        );
+    }
+    if !runner_state.app_status.is_empty() {
+        println!("{}", "App status:".bold());
+        for (key, value) in &runner_state.app_status {
+            println!("  {} = {}", key, value);
+        }
+    }
+    if let Some((timestamp, launch)) = &runner_state.last_known_good {
+        println!(
+            "{} {} ({})",
+            "Last known good:".bold(),
+            launch.argv.join(" "),
+            timestamp.to_string().dimmed()
+        );
+    }
+
+    let history_config = specific_config()
+        .map(|settings| settings.metrics_history)
+        .unwrap_or_default();
+    print_metrics(&MetricsHistory::load(
+        &metrics_history::path(&state_path),
+        &history_config,
+    ));
+
+    let recent = merged_tail(&state.stdout, &state.stderr, 10);
+    if !recent.is_empty() {
+        println!("{}", "Recent output:".bold());
+        print_lines(&recent);
+    }
+    Ok(())
+}
+
+/// Usage over the metrics history window, for `status`.
+fn print_metrics(history: &MetricsHistory) {
+    let buckets = history.buckets();
+    let (first, latest) = match (buckets.first(), buckets.last()) {
+        (Some(first), Some(latest)) => (first, latest),
+        _ => return,
+    };
+    let count = buckets.len() as f64;
+    let average = |value: fn(&metrics_history::Bucket) -> f32| {
+        buckets
+            .iter()
+            .map(|bucket| value(bucket) as f64)
+            .sum::<f64>()
+            / count
+    };
+    let peak = buckets
+        .iter()
+        .map(|bucket| bucket.memory_max)
+        .fold(0.0, f32::max);
+    let hours = (latest.start - first.start + history.resolution_seconds()) as f64 / 3600.0;
+
+    println!("{} last {:.1}h", "Metrics:".bold(), hours);
+    println!(
+        "  memory {:.1} MB now, {:.1} MB average, {:.1} MB peak",
+        latest.memory,
+        average(|bucket| bucket.memory),
+        peak
+    );
+    if let Some(trend) = history.memory_trend() {
+        println!("  memory trend {:+.1} MB per hour", trend);
+    }
+    println!(
+        "  cpu {:.1}% now, {:.1}% average",
+        latest.cpu,
+        average(|bucket| bucket.cpu)
+    );
+    let memory: Vec<f64> = history
+        .report(Some(48))
+        .buckets
+        .iter()
+        .map(|bucket| bucket.memory as f64)
+        .collect();
+    println!("  {}", sparkline(&memory));
+}
+
+fn print_lines(lines: &[OutputLine]) {
+    for line in lines {
+        let stream = match line.stream {
+            Stream::Stdout => line.stream.to_string().normal(),
+            Stream::Stderr => line.stream.to_string().red(),
+        };
+        println!(
+            "{} [{}] {}",
+            line.timestamp.to_string().dimmed(),
+            stream,
+            line.line
+        );
+    }
+}
+
+/// `logs` subcommand.
+pub async fn logs(lines: usize, stderr_only: bool, follow: bool) -> Result<(), String> {
+    let (_, state_path, state) = load_state().await?;
+    let stdout: &[(u64, String)] = if stderr_only { &[] } else { &state.stdout };
+
+    print_lines(&merged_tail(stdout, &state.stderr, lines));
+
+    if !follow {
+        return Ok(());
+    }
+
+    // Read on from the capture files, the state's buffers are trimmed from
+    // the front and can't tell which lines are new
+    let mut capture = OutputCapture::open(&state_path).map_err(|err| {
+        format!(
+            "Can't follow the output in {}: {}",
+            capture::dir(&state_path).display(),
+            err
+        )
+    })?;
+    loop {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let now = current_timestamp();
+        let stdout = match stderr_only {
+            true => Vec::new(),
+            false => capture.read_keyed(Stream::Stdout, now),
+        };
+        print_lines(&merged(&stdout, &capture.read_keyed(Stream::Stderr, now)));
+    }
+}
+
+/// `states` subcommand.
+pub fn states(json: bool) -> Result<(), String> {
+    let config: AppConfig = get_config();
+    let state_path: PathType = StatePersistence::get_state_path(&config);
+    let settings = specific_config().map_err(|err| format!("Invalid Config.toml: {}", err))?;
+    let backend = settings.state_backend.backend(&state_path)?;
+    let records = backend.records()?;
+
+    if json {
+        let rendered = serde_json::to_string_pretty(&records)
+            .map_err(|err| format!("Failed to render the states: {}", err))?;
+        println!("{}", rendered);
+        return Ok(());
+    }
+
+    if records.is_empty() {
+        println!("No runner published to {} yet", backend.name());
+    }
+    for record in &records {
+        println!(
+            "{} {} pid {} updated {} {}",
+            record.runner().bold(),
+            record.state.status,
+            record.state.pid,
+            record.state.last_updated,
+            record.state.data.dimmed()
+        );
+    }
+    Ok(())
+}
+
+/// `restart` subcommand, sends `SIGHUP` to the running instance.
+pub async fn restart(note: Option<String>, no_cache: bool) -> Result<(), String> {
+    let (_, state_path, state) = load_state().await?;
+
+    if !pid_alive(state.pid) {
+        return Err(format!("Runner pid {} is not running", state.pid));
+    }
+
+    if let Some(note) = note {
+        annotate_next(&state_path, note)?;
+    }
+    if no_cache {
+        let mut runner_state = RunnerState::load(&state_path);
+        runner_state.no_cache_requested = true;
+        runner_state
+            .save(&state_path)
+            .map_err(|err| format!("Failed to request a build without cache: {}", err))?;
+    }
+
+    signals::send(state.pid, Control::Reload)
+        .map_err(|err| format!("Failed to signal pid {}: {}", state.pid, err))?;
+
+    println!("Sent reload request to pid {}", state.pid);
+    Ok(())
+}
+
+fn annotate_next(state_path: &PathType, note: String) -> Result<(), String> {
+    let mut runner_state = RunnerState::load(state_path);
+    runner_state.pending_note = Some(note);
+    runner_state
+        .save(state_path)
+        .map_err(|err| format!("Failed to save the note: {}", err))
+}
+
+/// One line of the restart history.
+fn print_restart(record: &RestartRecord) {
+    let outcome = match record.success {
+        Some(true) => record.outcome().green(),
+        Some(false) => record.outcome().red(),
+        None => record.outcome().yellow(),
+    };
+    let mut line = format!(
+        "  {} {} {}",
+        record.timestamp.to_string().dimmed(),
+        outcome,
+        record.reason
+    );
+    if let Some(code) = record.exit_code {
+        line.push_str(&format!(", exit code {}", code));
+    }
+    if let Some(build_ms) = record.build_ms {
+        line.push_str(&format!(", built in {:.1}s", build_ms as f64 / 1000.0));
+    }
+    if !record.steps.is_empty() {
+        let steps: Vec<String> = record.steps.iter().map(|step| step.to_string()).collect();
+        line.push_str(&format!(" ({})", steps.join(", ")));
+    }
+    if let Some(note) = &record.note {
+        line.push_str(&format!(" - {}", note));
+    }
+    println!("{}", line);
+}
+
+/// `history` subcommand.
+pub async fn history(lines: usize, json: bool) -> Result<(), String> {
+    let (_, state_path, _) = load_state().await?;
+    let restarts = RunnerState::load(&state_path).restart_history;
+    let restarts = &restarts[restarts.len().saturating_sub(lines)..];
+
+    if json {
+        let rendered = serde_json::to_string_pretty(restarts)
+            .map_err(|err| format!("Failed to render the history: {}", err))?;
+        println!("{}", rendered);
+        return Ok(());
+    }
+
+    if restarts.is_empty() {
+        println!("No restart recorded yet");
+    }
+    for record in restarts {
+        print_restart(record);
+    }
+    Ok(())
+}
+
+/// `trace` subcommand.
+pub async fn trace(deploys: usize, json: bool) -> Result<(), String> {
+    let (_, state_path, _) = load_state().await?;
+    let timelines: Vec<Timeline> = RunnerState::load(&state_path)
+        .restart_history
+        .iter()
+        .filter_map(Timeline::of)
+        .collect();
+    let timelines = &timelines[timelines.len().saturating_sub(deploys)..];
+
+    if json {
+        let rendered = serde_json::to_string_pretty(timelines)
+            .map_err(|err| format!("Failed to render the timelines: {}", err))?;
+        println!("{}", rendered);
+        return Ok(());
+    }
+
+    if timelines.is_empty() {
+        println!("No restart timeline recorded yet");
+    }
+    for timeline in timelines {
+        println!(
+            "  {} {} in {:.1}s",
+            timeline.timestamp.to_string().dimmed(),
+            timeline.reason,
+            timeline.total_ms as f64 / 1000.0
+        );
+        for span in &timeline.stages {
+            println!(
+                "    {:<9} +{:>8}ms {:>8}ms",
+                span.stage.name(),
+                span.start_ms,
+                span.duration_ms
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Seconds as e.g. `2h 5m` or `40s`.
+pub fn format_seconds(seconds: u64) -> String {
+    match seconds {
+        0..60 => format!("{}s", seconds),
+        60..3600 => format!("{}m {}s", seconds / 60, seconds % 60),
+        _ => format!("{}h {}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
+
+/// `report` subcommand.
+pub async fn report(period: &str, json: bool) -> Result<(), String> {
+    let seconds = uptime::parse_period(period)?;
+    let (config, state_path, _) = load_state().await?;
+    let log = uptime::path(&state_path);
+    let transitions =
+        uptime::read(&log).map_err(|err| format!("Failed to read {}: {}", log.display(), err))?;
+    let now = current_timestamp();
+    let report = uptime::report(
+        &transitions,
+        &RunnerState::load(&state_path).restart_history,
+        now.saturating_sub(seconds),
+        now,
+    );
+
+    if json {
+        let rendered = serde_json::to_string_pretty(&report)
+            .map_err(|err| format!("Failed to render the report: {}", err))?;
+        println!("{}", rendered);
+        return Ok(());
+    }
+
+    println!(
+        "{} {} over the last {}",
+        "Report:".bold(),
+        config.app_name,
+        period
+    );
+    match report.uptime_percent {
+        Some(percent) => println!(
+            "  uptime {:.3}% ({} serving of {} recorded)",
+            percent,
+            format_seconds(report.serving_seconds),
+            format_seconds(report.covered_seconds)
+        ),
+        None => println!("  uptime unknown, no transitions recorded yet"),
+    }
+    if report.covered_seconds < seconds && report.covered_seconds > 0 {
+        println!("  {}", "transitions only cover part of the period".yellow());
+    }
+    let mttr = report
+        .mttr_seconds
+        .map_or_else(|| String::from("-"), format_seconds);
+    println!(
+        "  outages {}, mean time to recovery {}",
+        report.outages, mttr
+    );
+    if let Some(since) = report.down_since {
+        println!("  {} since {}", "down".red(), since);
+    }
+
+    let restarts: usize = report.restarts.values().sum();
+    println!(
+        "  restarts {} ({} failed)",
+        restarts, report.failed_restarts
+    );
+    for (cause, count) in &report.restarts {
+        println!("    {:<10} {}", cause, count);
+    }
+    println!(
+        "  deploys {} ({:.1} per week)",
+        report.deploys, report.deploys_per_week
+    );
+    if let Some(since) = report.restarts_since {
+        println!(
+            "  {}",
+            format!("restart history only reaches back to {}", since).yellow()
+        );
+    }
+    Ok(())
+}
+
+/// `annotate` subcommand.
+pub async fn annotate(note: String, last: bool) -> Result<(), String> {
+    let (_, state_path, _) = load_state().await?;
+
+    if !last {
+        annotate_next(&state_path, note)?;
+        println!("Note will be attached to the next restart");
+        return Ok(());
+    }
+
+    let mut runner_state = RunnerState::load(&state_path);
+    let record = runner_state
+        .restart_history
+        .last_mut()
+        .ok_or_else(|| String::from("No restart recorded yet"))?;
+    record.note = Some(note);
+    let reason = record.reason.clone();
+    runner_state
+        .save(&state_path)
+        .map_err(|err| format!("Failed to save the note: {}", err))?;
+
+    println!("Note attached to the restart for {}", reason);
+    Ok(())
+}
+
+/// `restore-last-known-good` subcommand.
+///
+/// A runner that isn't up picks the request up when it's started next,
+/// e.g. by systemd after a failed build took it down.
+pub async fn restore_last_known_good() -> Result<(), String> {
+    let (_, state_path, state) = load_state().await?;
+
+    let mut runner_state = RunnerState::load(&state_path);
+    let launch = match &runner_state.last_known_good {
+        Some((_, launch)) => launch.argv.join(" "),
+        None => {
+            return Err(String::from(
+                "No child became ready yet, nothing to restore",
+            ));
+        }
+    };
+    runner_state.restore_requested = true;
+    runner_state
+        .save(&state_path)
+        .map_err(|err| format!("Failed to save the restore request: {}", err))?;
+
+    if !pid_alive(state.pid) {
+        println!(
+            "Runner pid {} is not running, `{}` is restored when it starts",
+            state.pid, launch
+        );
+        return Ok(());
+    }
+
+    signals::send(state.pid, Control::Reload)
+        .map_err(|err| format!("Failed to signal pid {}: {}", state.pid, err))?;
+
+    println!("Asked pid {} to restore `{}`", state.pid, launch);
+    Ok(())
+}
+
+/// `log-level` subcommand.
+///
+/// A runner that isn't up applies the level when it's started next.
+pub async fn log_level(level: String) -> Result<(), String> {
+    let (config, state_path, state) = load_state().await?;
+
+    let level = match level.eq_ignore_ascii_case("reset") {
+        true => None,
+        false => Some(log_level::parse(&level)?),
+    };
+    let mut runner_state = RunnerState::load(&state_path);
+    runner_state.log_level = level.map(log_level::name);
+    runner_state
+        .save(&state_path)
+        .map_err(|err| format!("Failed to save the log level: {}", err))?;
+
+    let description = match level {
+        Some(level) => log_level::name(level),
+        None => format!("{} from Config.toml", config.log_level),
+    };
+    if !pid_alive(state.pid) {
+        println!(
+            "Runner pid {} is not running, logging at {} when it starts",
+            state.pid, description
+        );
+        return Ok(());
+    }
+
+    signals::send(state.pid, Control::LogLevel)
+        .map_err(|err| format!("Failed to signal pid {}: {}", state.pid, err))?;
+
+    println!("Asked pid {} to log at {}", state.pid, description);
+    Ok(())
+}
+
+/// `dump` subcommand.
+///
+/// The runner answers from a task of its own, so this works while it's
+/// busy with a build or a hung ready check.
+pub async fn dump() -> Result<(), String> {
+    let (_, state_path, state) = load_state().await?;
+    if !pid_alive(state.pid) {
+        return Err(format!("Runner pid {} is not running", state.pid));
+    }
+
+    let path = dump::path(&state_path);
+    let modified = |path: &Path| fs::metadata(path).and_then(|meta| meta.modified()).ok();
+    let before = modified(&path);
+    signals::send(state.pid, Control::Dump)
+        .map_err(|err| format!("Failed to signal pid {}: {}", state.pid, err))?;
+
+    let deadline = tokio::time::Instant::now() + DUMP_TIMEOUT;
+    while modified(&path) == before {
+        if tokio::time::Instant::now() >= deadline {
+            return Err(format!(
+                "Pid {} didn't write {} within {}s, its log may say why",
+                state.pid,
+                path.display(),
+                DUMP_TIMEOUT.as_secs()
+            ));
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    let text = fs::read_to_string(&path)
+        .map_err(|err| format!("Failed to read {}: {}", path.display(), err))?;
+    print!("{}", text);
+    Ok(())
+}
+
+/// How long `dump` waits for the runner to answer.
+const DUMP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `send-stdin` subcommand.
+pub async fn send_stdin(text: String, no_newline: bool) -> Result<(), String> {
+    let (_, state_path, state) = load_state().await?;
+    if !pid_alive(state.pid) {
+        return Err(format!("Runner pid {} is not running", state.pid));
+    }
+    let path = stdin::socket_path(&state_path);
+    if !path.exists() {
+        return Err(format!(
+            "Pid {} doesn't take text for the child's stdin, set stdin_pipe = true",
+            state.pid
+        ));
+    }
+
+    let mut text = text;
+    if !no_newline {
+        text.push('\n');
+    }
+    match tokio::time::timeout(SEND_STDIN_TIMEOUT, stdin::send(&path, text.as_bytes())).await {
+        Ok(result) => result?,
+        Err(_) => {
+            return Err(format!(
+                "The child of pid {} didn't take the text within {}s, it may not read its stdin",
+                state.pid,
+                SEND_STDIN_TIMEOUT.as_secs()
+            ));
+        }
+    }
+    println!(
+        "Sent {} bytes to the child of pid {}",
+        text.len(),
+        state.pid
+    );
+    Ok(())
+}
+
+/// How long `send-stdin` waits for the child to take the text.
+const SEND_STDIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `limits` subcommand.
+///
+/// The limits are kept in Config.toml, a runner that isn't up applies them
+/// when it's started next.
+pub async fn limits(memory_mb: Option<u64>, cpu_percent: Option<u32>) -> Result<(), String> {
+    if memory_mb.is_none() && cpu_percent.is_none() {
+        return Err(String::from(
+            "Nothing to change, pass --memory-mb and/or --cpu-percent",
+        ));
+    }
+    let settings = specific_config().map_err(|err| format!("Invalid Config.toml: {}", err))?;
+    if !settings.cgroup.enabled {
+        return Err(String::from(
+            "Limits are only enforced with `enabled = true` in [app_specific.cgroup]",
+        ));
+    }
+    let (_, _, state) = load_state().await?;
+
+    cgroup::persist_limits(Path::new("Config.toml"), memory_mb, cpu_percent)?;
+
+    let mut changes = Vec::new();
+    match memory_mb {
+        Some(0) => changes.push(String::from("no memory limit")),
+        Some(mb) => changes.push(format!("{} MB of memory", mb)),
+        None => (),
+    }
+    match cpu_percent {
+        Some(0) => changes.push(String::from("no cpu limit")),
+        Some(percent) => changes.push(format!("{}% cpu", percent)),
+        None => (),
+    }
+    let description = changes.join(" and ");
+    if !pid_alive(state.pid) {
+        println!(
+            "Runner pid {} is not running, the child gets {} when it starts",
+            state.pid, description
+        );
+        return Ok(());
+    }
+
+    signals::send(state.pid, Control::Limits)
+        .map_err(|err| format!("Failed to signal pid {}: {}", state.pid, err))?;
+
+    println!("Asked pid {} to give the child {}", state.pid, description);
+    Ok(())
+}
+
+/// `reset` subcommand.
+pub async fn reset(errors: bool, output: bool, counters: bool, all: bool) -> Result<(), String> {
+    let scope = match all {
+        true => ResetScope::all(),
+        false => ResetScope {
+            errors,
+            output,
+            counters,
+        },
+    };
+    if scope.is_empty() {
+        return Err(String::from(
+            "Nothing to reset, pass --errors, --output, --counters or --all",
+        ));
+    }
+    let (_, state_path, mut state) = load_state().await?;
+
+    if pid_alive(state.pid) {
+        reset::request(&state_path, scope)
+            .map_err(|err| format!("Failed to save the reset request: {}", err))?;
+        println!(
+            "Asked pid {} to clear {}, done on its next check",
+            state.pid,
+            scope.describe()
+        );
+        return Ok(());
+    }
+
+    scope.apply(&mut state);
+    update_state(&mut state, &state_path, None).await;
+    println!("Cleared {} in {}", scope.describe(), state_path);
+    Ok(())
+}
+
+/// `maintenance` subcommand.
+pub async fn maintenance(action: MaintenanceAction) -> Result<(), String> {
+    let settings = specific_config().map_err(|err| format!("Invalid Config.toml: {}", err))?;
+    let flag = flag_path(&settings.watch_paths()[0].path);
+
+    if let MaintenanceAction::On = action {
+        fs::write(&flag, format!("{}\n", current_timestamp()))
+            .map_err(|err| format!("Failed to create {}: {}", flag.display(), err))?;
+        println!(
+            "Maintenance mode on until `ais_runner maintenance off` or {} is removed",
+            flag.display()
+        );
+        return Ok(());
+    }
+
+    match fs::remove_file(&flag) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            return Err(format!("Failed to remove {}: {}", flag.display(), err));
+        }
+        _ => (),
+    }
+
+    // Toggled on by a signal, so toggle it back the same way
+    let (_, state_path, state) = load_state().await?;
+    let source = RunnerState::load(&state_path)
+        .maintenance
+        .map(|maintenance| maintenance.source);
+    if source == Some(MaintenanceSource::Signal) && pid_alive(state.pid) {
+        signals::send(state.pid, Control::Maintenance)
+            .map_err(|err| format!("Failed to signal pid {}: {}", state.pid, err))?;
+    }
+
+    println!("Maintenance mode off");
+    Ok(())
+}
+
+/// Append a finished control command to the audit log.
+///
+/// Failing to do so is reported but doesn't change the command's outcome.
+pub fn record_audit(command: String, result: &Result<(), String>) {
+    let config: AppConfig = get_config();
+    let state_path: PathType = StatePersistence::get_state_path(&config);
+    let entry = AuditEntry::new(current_timestamp(), command, result);
+    if let Err(err) = audit::append(&state_path, &entry) {
+        eprintln!(
+            "{} failed to write the audit log {}: {}",
+            "Warning:".yellow(),
+            audit::path(&state_path).display(),
+            err
+        );
+    }
+}
+
+/// `audit` subcommand.
+pub fn audit(lines: usize) -> Result<(), String> {
+    let config: AppConfig = get_config();
+    let state_path: PathType = StatePersistence::get_state_path(&config);
+    let entries = audit::read(&state_path).map_err(|err| {
+        format!(
+            "Failed to read the audit log {}: {}",
+            audit::path(&state_path).display(),
+            err
+        )
+    })?;
+
+    for entry in &entries[entries.len().saturating_sub(lines)..] {
+        let outcome = match &entry.error {
+            None => "ok".green(),
+            Some(err) => err.red(),
+        };
+        println!(
+            "{} {} pid {} {} {}",
+            entry.timestamp.to_string().dimmed(),
+            entry.caller().bold(),
+            entry.pid,
+            entry.command,
+            outcome
+        );
+    }
+    Ok(())
+}
+
+/// `validate-config` subcommand.
+/// Reference of the `[app_specific]` options, for `--explain-config`.
+pub fn explain_config() -> String {
+    config_schema::explain(
+        &config_schema::schema::<AppSpecificConfig>(),
+        "app_specific",
+    )
+}
+
+pub fn validate_config() -> Result<(), String> {
+    let settings = specific_config().map_err(|err| format!("Invalid Config.toml: {}", err))?;
+
+    let problems = validation::validate(&settings);
+    if !problems.is_empty() {
+        return Err(validation::report(&problems));
+    }
+
+    println!("{}", settings);
+    let schema = config_schema::schema::<AppSpecificConfig>();
+    for item in env_overrides::from_env(&schema).unwrap_or_default() {
+        println!(
+            "{}",
+            format!("app_specific.{} is set by {}", item.key, item.var).dimmed()
+        );
+    }
+    println!("{}", "Config.toml is valid".green());
+    Ok(())
+}
+
+/// `migrate-from-systemd` subcommand.
+pub fn migrate_from_systemd(unit: &Path, output: Option<&Path>) -> Result<(), String> {
+    let content = fs::read_to_string(unit)
+        .map_err(|err| format!("Failed to read {}: {}", unit.display(), err))?;
+    let migration = migrate(&SystemdUnit::parse(&content), &unit.display().to_string())?;
+
+    match output {
+        Some(path) => {
+            if path.exists() {
+                return Err(format!(
+                    "{} already exists, not overwriting it",
+                    path.display()
+                ));
+            }
+            fs::write(path, &migration.config)
+                .map_err(|err| format!("Failed to write {}: {}", path.display(), err))?;
+            println!("{} {}", "Wrote".green(), path.display());
+        }
+        None => print!("{}", migration.config),
+    }
+
+    for note in &migration.notes {
+        eprintln!("{} {}", "note:".yellow(), note);
+    }
+    Ok(())
+}
+
+fn write_new(path: &Path, content: &str) -> Result<(), String> {
+    if path.exists() {
+        return Err(format!(
+            "{} already exists, not overwriting it",
+            path.display()
+        ));
+    }
+    fs::write(path, content)
+        .map_err(|err| format!("Failed to write {}: {}", path.display(), err))?;
+    println!("{} {}", "Wrote".green(), path.display());
+    Ok(())
+}
+
+/// `export-systemd` subcommand.
+pub fn export_systemd(output: Option<&Path>, env_file: Option<&Path>) -> Result<(), String> {
+    let config: AppConfig = get_config();
+    let settings = specific_config().map_err(|err| format!("Invalid Config.toml: {}", err))?;
+    let app_name = config.app_name.to_string();
+
+    let env_file = match env_file {
+        Some(path) => path.to_path_buf(),
+        None => PathBuf::from(format!("/etc/default/{}", app_name)),
+    };
+    let exported = export(&config, &settings, &env_file.display().to_string());
+
+    match output {
+        Some(dir) => {
+            write_new(&dir.join(format!("{}.service", app_name)), &exported.unit)?;
+            write_new(&dir.join(format!("{}.env", app_name)), &exported.env)?;
+            println!(
+                "Install the env file as {} before enabling the unit",
+                env_file.display()
+            );
+        }
+        None => {
+            println!("{}", format!("# {}.service", app_name).dimmed());
+            print!("{}", exported.unit);
+            println!();
+            println!("{}", format!("# {}", env_file.display()).dimmed());
+            print!("{}", exported.env);
+        }
+    }
+
+    for note in &exported.notes {
+        eprintln!("{} {}", "note:".yellow(), note);
+    }
+    Ok(())
+}
+
+/// `export-bundle` subcommand.
+pub fn export_bundle(key: &Path, output: Option<&Path>) -> Result<(), String> {
+    let key = bundle::read_key(key)?;
+    let contents = Contents {
+        version: BUNDLE_VERSION,
+        created: current_timestamp(),
+        runner_version: env!("CARGO_PKG_VERSION").to_string(),
+        files: bundle::collect(Path::new("."))?,
+    };
+    let roles: Vec<String> = contents
+        .files
+        .iter()
+        .map(|file| format!("{:?}", file.role))
+        .collect();
+    let bundle = bundle::sign(contents, &key)?;
+    let json = serde_json::to_string_pretty(&bundle)
+        .map_err(|err| format!("Failed to serialize the bundle: {}", err))?;
+
+    match output {
+        Some(path) => {
+            write_new(path, &json)?;
+            eprintln!("Bundled {}", roles.join(", "));
+        }
+        None => println!("{}", json),
+    }
+    Ok(())
+}
+
+/// `import-bundle` subcommand.
+pub fn import_bundle(path: &Path, key: &Path, force: bool) -> Result<(), String> {
+    let key = bundle::read_key(key)?;
+    let content = fs::read_to_string(path)
+        .map_err(|err| format!("Failed to read {}: {}", path.display(), err))?;
+    let bundle: Bundle = serde_json::from_str(&content)
+        .map_err(|err| format!("{} isn't a bundle: {}", path.display(), err))?;
+    let contents = bundle::verify(&bundle, &key)?;
+
+    for written in bundle::install(contents, Path::new("."), force)? {
+        println!("{} {}", "Wrote".green(), written.display());
+    }
+    println!(
+        "Bundled by ais_runner {}, run `ais_runner validate-config` to check it against this host",
+        contents.runner_version
+    );
+    Ok(())
+}
@@ -0,0 +1,188 @@
+//! Placeholders in `run_command`, `build_command`, `install_command` and
+//! the commands of the build `steps`.
+//!
+//! Instead of a wrapper script per environment, commands can refer to
+//! variables that are filled in at spawn time:
+//!
+//! ```toml
+//! run_command = "./server --root {project_path} --port {port} --env {env}"
+//! build_command = "./build.sh --token {secret:registry_token}"
+//!
+//! [app_specific.command_vars]
+//! region = "eu-west"
+//! ```
+//!
+//! `{project_path}`, `{monitor_path}` and `{env}` (the environment the
+//! secrets are fetched for) are always set, `{port}` is the first of the
+//! reserved ports, and `[app_specific.command_vars]` adds to or overrides
+//! them. `{secret:<key>}` is fetched from the secret server right before
+//! the command runs and never stored in the runner state. Placeholders are
+//! replaced within an argument after the command is split, so a value with
+//! spaces stays one argument. Anything else in braces, such as `${HOME}`
+//! or unknown names, is passed through as is.
+
+use artisan_middleware::dusa_collection_utils;
+use dusa_collection_utils::core::errors::{ErrorArrayItem, Errors};
+use std::collections::BTreeMap;
+
+use crate::child::split_command;
+use crate::config::AppSpecificConfig;
+use crate::global_child::get_query;
+use crate::secrets::{AllSecrets, template::fetch_keys};
+
+const SECRET: &str = "secret:";
+
+/// Whether `name` can be a placeholder, `secret:<key>` or an identifier.
+fn is_placeholder(name: &str) -> bool {
+    let name = name.strip_prefix(SECRET).unwrap_or(name);
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+/// Replace every `{name}` in `arg` that `lookup` has a value for.
+fn substitute(arg: &str, mut lookup: impl FnMut(&str) -> Option<String>) -> String {
+    let mut expanded = String::with_capacity(arg.len());
+    let mut rest = arg;
+    while let Some(start) = rest.find('{') {
+        let (before, from) = rest.split_at(start);
+        expanded.push_str(before);
+
+        let value = match from[1..].find('}') {
+            // `${VAR}` belongs to the shell the command may run
+            Some(_) if expanded.ends_with('$') => None,
+            Some(end) if is_placeholder(&from[1..=end]) => {
+                lookup(&from[1..=end]).map(|value| (value, end + 2))
+            }
+            _ => None,
+        };
+        match value {
+            Some((value, length)) => {
+                expanded.push_str(&value);
+                rest = &from[length..];
+            }
+            None => {
+                expanded.push('{');
+                rest = &from[1..];
+            }
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// The variables commands can refer to.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CommandVars {
+    vars: BTreeMap<String, String>,
+}
+
+impl CommandVars {
+    /// The variables of `settings` for `environment`.
+    pub fn new(settings: &AppSpecificConfig, environment: &str) -> Self {
+        let mut vars = BTreeMap::from([
+            (String::from("project_path"), settings.project_path.clone()),
+            (String::from("monitor_path"), settings.monitor_path.clone()),
+            (String::from("env"), environment.to_owned()),
+        ]);
+        if let Some(port) = settings.reservation.ports.first() {
+            vars.insert(String::from("port"), port.to_string());
+        }
+        vars.extend(settings.command_vars.clone());
+        Self { vars }
+    }
+
+    /// The variables of `settings` for the environment the secrets are
+    /// fetched for.
+    pub fn current(settings: &AppSpecificConfig) -> Self {
+        let environment = get_query()
+            .map(|query| query.enviornment_id)
+            .unwrap_or_default();
+        Self::new(settings, &environment)
+    }
+
+    /// `arg` with its variables filled in, secrets are left for
+    /// [`fill_secrets`].
+    pub fn expand(&self, arg: &str) -> String {
+        substitute(arg, |name| self.vars.get(name).cloned())
+    }
+
+    /// Split `command` and fill in the variables of every argument.
+    pub fn argv(&self, command: &str) -> Vec<String> {
+        split_command(command)
+            .iter()
+            .map(|arg| self.expand(arg))
+            .collect()
+    }
+
+    /// Placeholders in `command` that are neither a variable nor a secret,
+    /// most likely typos.
+    pub fn unknown(&self, command: &str) -> Vec<String> {
+        let mut unknown: Vec<String> = Vec::new();
+        substitute(command, |name| {
+            let known = name.starts_with(SECRET) || self.vars.contains_key(name);
+            if !known && !unknown.iter().any(|seen| seen == name) {
+                unknown.push(name.to_owned());
+            }
+            None
+        });
+        unknown
+    }
+}
+
+/// Secret keys `argv` refers to, in order of first use.
+pub fn secret_keys(argv: &[String]) -> Vec<String> {
+    let mut keys: Vec<String> = Vec::new();
+    for arg in argv {
+        substitute(arg, |name| {
+            if let Some(key) = name
+                .strip_prefix(SECRET)
+                .filter(|key| !keys.iter().any(|known| known == key))
+            {
+                keys.push(key.to_owned());
+            }
+            None
+        });
+    }
+    keys
+}
+
+/// `argv` with its secret placeholders filled from `secrets`, failing with
+/// all of the keys that are missing.
+pub fn fill_secrets(argv: &[String], secrets: &AllSecrets) -> Result<Vec<String>, String> {
+    let mut missing: Vec<String> = Vec::new();
+    let filled = argv
+        .iter()
+        .map(|arg| {
+            substitute(arg, |name| {
+                let key = name.strip_prefix(SECRET)?;
+                match secrets.iter().find(|(name, _)| name == key) {
+                    Some((_, value)) => Some(String::from_utf8_lossy(value).into_owned()),
+                    None => {
+                        if !missing.iter().any(|known| known == key) {
+                            missing.push(key.to_owned());
+                        }
+                        None
+                    }
+                }
+            })
+        })
+        .collect();
+
+    match missing.is_empty() {
+        true => Ok(filled),
+        false => Err(format!(
+            "Secrets missing for the command: {}",
+            missing.join(", ")
+        )),
+    }
+}
+
+/// Fetch the secrets `argv` refers to and fill them in.
+pub async fn resolve_secrets(argv: &[String]) -> Result<Vec<String>, ErrorArrayItem> {
+    let keys = secret_keys(argv);
+    let secrets = fetch_keys(&keys, "the command").await?;
+    fill_secrets(argv, &secrets).map_err(|err| ErrorArrayItem::new(Errors::GeneralError, err))
+}
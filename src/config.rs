@@ -17,16 +17,58 @@ use artisan_middleware::{
     version::{aml_version, str_to_version},
 };
 use colored::Colorize;
-use config::{Config, ConfigError, File};
+use config::{Config, ConfigError, File, Source};
 use dusa_collection_utils::{
     core::logger::{LogLevel, set_log_level},
     core::types::pathtype::PathType,
-    log,
 };
-use serde::Deserialize;
-use std::fmt;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt, path::Path};
 
-use crate::{global_child::GLOBAL_SECRET_QUERY, secrets::SecretQuery};
+use crate::{
+    acme::AcmeConfig,
+    app_status::AppStatusConfig,
+    build_executor::BuildExecutorConfig,
+    build_steps::BuildStep,
+    cadence::CadenceConfig,
+    cgroup::CgroupConfig,
+    child::{KillMode, RestartStrategy, TimeoutAction},
+    config_schema,
+    cpu_limit::CpuLimitConfig,
+    crash_loop::CrashLoopConfig,
+    env_overrides,
+    events::EventsConfig,
+    global_child::GLOBAL_SECRET_QUERY,
+    heartbeat::HeartbeatConfig,
+    host::host_arch,
+    journal::JournalConfig,
+    listen_fds::ListenFdsConfig,
+    log,
+    log_rules::{LogRule, ready_pattern},
+    log_shipping::LogShippingConfig,
+    logging::LogFormat,
+    metrics_history::MetricsHistoryConfig,
+    notifications::NotifyConfig,
+    orphans::OrphanConfig,
+    polling::PollingConfig,
+    ports::PortConfig,
+    probes::ProbeConfig,
+    profiles,
+    proxy::ProxyConfig,
+    ready::ReadyCheck,
+    reservations::ReservationConfig,
+    runner::RunMode,
+    secrets::{RetryConfig, RotationAction, SecretQuery, SecretReloadConfig, SecretTlsConfig},
+    shutdown::ShutdownConfig,
+    state::StateWritesConfig,
+    state_backend::StateBackendConfig,
+    state_sync::StateSyncConfig,
+    static_server::StaticServerConfig,
+    status_server::StatusServerConfig,
+    timestamps::{TimestampFormat, TimestampSource, default_timestamp_formats},
+    validation,
+    watch::WatchPath,
+};
 
 /// Load the base [`AppConfig`] and populate fields derived from Cargo
 /// environment variables.
@@ -79,32 +121,7 @@ pub async fn generate_application_state(state_path: &PathType, config: &AppConfi
         Err(e) => {
             log!(LogLevel::Warn, "No previous state loaded, creating new one");
             log!(LogLevel::Debug, "Error loading previous state: {}", e);
-            let mut state = AppState {
-                data: String::new(),
-                stared_at: current_timestamp(),
-                last_updated: current_timestamp(),
-                event_counter: 0,
-                error_log: vec![],
-                config: config.clone(),
-                name: config.app_name.to_string(),
-                pid: std::process::id(),
-                // stdout: Vec::new(),
-                version: {
-                    // defining the version
-                    let library_version: Version = aml_version();
-                    let software_version: Version =
-                        str_to_version(env!("CARGO_PKG_VERSION"), Some(VersionCode::Production));
-
-                    SoftwareVersion {
-                        application: software_version,
-                        library: library_version,
-                    }
-                },
-                system_application: false,
-                status: Status::Starting,
-                stdout: Vec::new(),
-                stderr: Vec::new(),
-            };
+            let mut state = new_application_state(config);
             state.data = String::from("Initializing");
             state.config.debug_mode = config.debug_mode;
             state.last_updated = current_timestamp();
@@ -128,34 +145,530 @@ pub async fn generate_application_state(state_path: &PathType, config: &AppConfi
     }
 }
 
-/// Read additional application specific configuration from `Config.toml`.
+/// A state structure for `config` that was never saved.
+pub fn new_application_state(config: &AppConfig) -> AppState {
+    AppState {
+        data: String::new(),
+        stared_at: current_timestamp(),
+        last_updated: current_timestamp(),
+        event_counter: 0,
+        error_log: vec![],
+        config: config.clone(),
+        name: config.app_name.to_string(),
+        pid: std::process::id(),
+        // stdout: Vec::new(),
+        version: {
+            // defining the version
+            let library_version: Version = aml_version();
+            let software_version: Version =
+                str_to_version(env!("CARGO_PKG_VERSION"), Some(VersionCode::Production));
+
+            SoftwareVersion {
+                application: software_version,
+                library: library_version,
+            }
+        },
+        system_application: false,
+        status: Status::Starting,
+        stdout: Vec::new(),
+        stderr: Vec::new(),
+    }
+}
+
+/// Read additional application specific configuration from `Config.toml`,
+/// with the profile of the app's environment and the `AIS_*` environment
+/// variables laid over it.
 pub fn specific_config() -> Result<AppSpecificConfig, ConfigError> {
+    let environment = AppConfig::new()
+        .map(|config| config.environment)
+        .unwrap_or_default();
+    load_specific(File::with_name("Config").required(false), &environment)
+}
+
+/// [`specific_config`] read from `file`, running in `environment`.
+pub fn load_specific<S>(file: S, environment: &str) -> Result<AppSpecificConfig, ConfigError>
+where
+    S: Source + Clone + Send + Sync + 'static,
+{
+    let schema = config_schema::schema::<AppSpecificConfig>();
+    let overrides = env_overrides::from_env(&schema)
+        .map_err(|problems| ConfigError::Message(problems.join("; ")))?;
+    for item in &overrides {
+        log!(
+            LogLevel::Debug,
+            "{} overrides app_specific.{}",
+            item.var,
+            item.key
+        );
+    }
+    let explain = |err: ConfigError| ConfigError::Message(validation::explain_load_error(&err));
+
+    // The profile goes between the file and the variables
+    let base = Config::builder()
+        .add_source(file.clone())
+        .build()
+        .map_err(explain)?;
     let mut builder = Config::builder();
-    builder = builder.add_source(File::with_name("Config").required(false));
+    builder = builder.add_source(file);
+    let profile = match base.get::<serde_json::Value>("app_specific") {
+        Ok(mut raw) => profiles::take(&mut raw, environment, &schema),
+        Err(_) => None,
+    };
+    if let Some(profile) = profile {
+        log!(
+            LogLevel::Debug,
+            "Applying the {} profile of app_specific",
+            environment
+        );
+        builder = builder.add_source(profiles::source(profile));
+    }
+    builder = env_overrides::apply(builder, &overrides)?;
+
+    let settings = builder.build().map_err(explain)?;
+    let mut raw: serde_json::Value = settings.get("app_specific").map_err(explain)?;
+    // Every profile is left out of the options, the selected one is merged
+    profiles::take(&mut raw, environment, &schema);
+
+    let app_specific: AppSpecificConfig = match settings.get("app_specific") {
+        Ok(app_specific) => app_specific,
+        Err(err) => {
+            // The loader only names the table, the schema finds the options
+            let problems = config_schema::mismatched_keys(&schema, &raw, "app_specific");
+            return Err(match problems.is_empty() {
+                true => explain(err),
+                false => ConfigError::Message(problems.join("; ")),
+            });
+        }
+    };
 
-    let settings = builder.build()?;
-    let app_specific: AppSpecificConfig = settings.get("app_specific")?;
+    // serde drops keys it doesn't know, a misspelled option would go unnoticed
+    let unknown = config_schema::unknown_keys(&schema, &raw, "app_specific");
+    if !unknown.is_empty() {
+        return Err(ConfigError::Message(unknown.join("; ")));
+    }
 
     Ok(app_specific)
 }
 
+/// A command that can differ per architecture.
+///
+/// Either a plain string, or a table keyed by `uname -m` with an optional
+/// `default`:
+///
+/// ```toml
+/// [app_specific.build_command]
+/// x86_64 = "cargo build --release"
+/// aarch64 = "cargo build --release --no-default-features"
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ArchCommand {
+    Single(String),
+    PerArch(BTreeMap<String, String>),
+}
+
+impl ArchCommand {
+    /// Pick the variant for `arch`, trying the compile time name (`arm` for
+    /// `armv7l`) and `default` after the exact match.
+    fn select(self, arch: &str) -> Result<String, String> {
+        let mut variants = match self {
+            ArchCommand::Single(command) => return Ok(command),
+            ArchCommand::PerArch(variants) => variants,
+        };
+
+        [arch, std::env::consts::ARCH, "default"]
+            .into_iter()
+            .find_map(|key| variants.remove(key))
+            .ok_or_else(|| {
+                format!(
+                    "no variant for {} (have: {})",
+                    arch,
+                    variants.keys().cloned().collect::<Vec<_>>().join(", ")
+                )
+            })
+    }
+}
+
+fn arch_command<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    ArchCommand::deserialize(deserializer)?
+        .select(&host_arch())
+        .map_err(serde::de::Error::custom)
+}
+
+fn optional_arch_command<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    match Option::<ArchCommand>::deserialize(deserializer)? {
+        Some(command) => command
+            .select(&host_arch())
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+        None => Ok(None),
+    }
+}
+
 /// Configuration section located under `[app_specific]` in `Config.toml`.
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AppSpecificConfig {
     pub interval_seconds: u32,
+    /// Directory watched for changes, unused when `monitor_paths` is set.
+    #[serde(default)]
     pub monitor_path: String,
     pub project_path: String,
     pub changes_needed: i32,
     pub ignored_subdirs: Vec<String>, // Add ignored subdirectories as strings
-    #[serde(default)]
+    #[serde(default, deserialize_with = "optional_arch_command")]
     pub install_command: Option<String>,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "optional_arch_command")]
     pub build_command: Option<String>,
+    #[serde(deserialize_with = "arch_command")]
     pub run_command: String,
     #[serde(default = "default_secret_server")]
     pub secret_server_addr: String,
     #[serde(default = "default_env_location")]
     pub env_file_location: String,
+    /// Seconds to wait for the child to exit during a graceful shutdown.
+    #[serde(default = "default_shutdown_timeout")]
+    pub shutdown_timeout_seconds: u64,
+    /// `text` (default) or `json` for structured log output.
+    #[serde(default)]
+    pub log_format: LogFormat,
+    /// Must pass once after every spawn before liveness checks begin.
+    #[serde(default)]
+    pub startup_probe: Option<ProbeConfig>,
+    /// Periodic health check, the child is restarted once its failure
+    /// budget is exhausted.
+    #[serde(default)]
+    pub liveness_probe: Option<ProbeConfig>,
+    /// Key captured lines by `capture` time or by a `child` provided prefix.
+    #[serde(default)]
+    pub timestamp_source: TimestampSource,
+    /// Formats tried, in order, when `timestamp_source = "child"`.
+    #[serde(default = "default_timestamp_formats")]
+    pub timestamp_formats: Vec<TimestampFormat>,
+    /// Run the child as this user, requires the runner to be root.
+    #[serde(default)]
+    pub run_as_user: Option<String>,
+    /// Run the child with this primary group, defaults to the user's group.
+    #[serde(default)]
+    pub run_as_group: Option<String>,
+    /// Working directory of the child, relative paths are resolved against
+    /// `project_path`. Defaults to `project_path`.
+    #[serde(default)]
+    pub working_dir: Option<String>,
+    /// cgroup v2 limits for the child.
+    #[serde(default)]
+    pub cgroup: CgroupConfig,
+    /// Crash-safe journal of captured output.
+    #[serde(default)]
+    pub journal: JournalConfig,
+    /// Kill `install_command` if it runs longer than this.
+    #[serde(default)]
+    pub install_timeout_seconds: Option<u64>,
+    /// Kill `build_command` if it runs longer than this.
+    #[serde(default)]
+    pub build_timeout_seconds: Option<u64>,
+    /// What to do once install or build timed out.
+    #[serde(default)]
+    pub build_timeout_action: TimeoutAction,
+    /// Extra attempts with `build_timeout_action = "retry"`.
+    #[serde(default = "default_build_timeout_retries")]
+    pub build_timeout_retries: u32,
+    /// Extra environment variables for the child and the install/build
+    /// commands, `[app_specific.env]`.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// `kill-first` or `build-first`, see [`RestartStrategy`].
+    #[serde(default)]
+    pub restart_strategy: RestartStrategy,
+    /// Gate before a new child is reported as `Running`.
+    #[serde(default)]
+    pub ready_check: Option<ReadyCheck>,
+    /// Secrets that must exist on the secret server, checked at start up.
+    #[serde(default)]
+    pub required_secrets: Vec<String>,
+    /// Only write `required_secrets` to the env file instead of every secret
+    /// of the environment.
+    #[serde(default)]
+    pub only_required_secrets: bool,
+    /// Host level resource reservation, see [`ReservationConfig`].
+    #[serde(default)]
+    pub reservation: ReservationConfig,
+    /// Subscribe to secret rotations, see [`RotationAction`].
+    #[serde(default)]
+    pub secret_rotation: Option<RotationAction>,
+    /// Serve the build output directly, see [`StaticServerConfig`].
+    #[serde(default)]
+    pub static_server: StaticServerConfig,
+    /// TLS for the secret server channel, plaintext when unset.
+    #[serde(default)]
+    pub secret_tls: Option<SecretTlsConfig>,
+    /// Backoff for connecting to and fetching from the secret server.
+    #[serde(default)]
+    pub secret_retry: RetryConfig,
+    /// Certificates for the built-in server, see [`AcmeConfig`].
+    #[serde(default)]
+    pub acme: AcmeConfig,
+    /// When to stop respawning a crashing child, see [`CrashLoopConfig`].
+    #[serde(default)]
+    pub crash_loop: CrashLoopConfig,
+    /// Exit codes the child is respawned on, any when empty. A child killed
+    /// by a signal exits with `128 + signal`.
+    #[serde(default)]
+    pub restart_on_exit_codes: Vec<i32>,
+    /// Exit codes after which the child is left stopped and the runner goes
+    /// idle, e.g. `[0]` for one shot jobs. Wins over `restart_on_exit_codes`.
+    #[serde(default)]
+    pub no_restart_on: Vec<i32>,
+    /// Webhooks told about restarts and failures, see [`NotifyConfig`].
+    #[serde(default)]
+    pub notifications: NotifyConfig,
+    /// Cron expression (UTC) to restart the child on regardless of
+    /// changes, e.g. `"0 3 * * *"`. See [`crate::schedule`].
+    #[serde(default)]
+    pub restart_schedule: Option<String>,
+    /// Hot reload rotated secrets instead of restarting, see
+    /// [`SecretReloadConfig`].
+    #[serde(default)]
+    pub secret_reload: Option<SecretReloadConfig>,
+    /// Address to accept rebuild webhooks on, e.g. `"0.0.0.0:9000"`. See
+    /// [`crate::webhook`].
+    #[serde(default)]
+    pub webhook_addr: Option<String>,
+    /// Shared secret webhook requests are signed with, required when
+    /// `webhook_addr` is set.
+    #[serde(default)]
+    pub webhook_secret: Option<String>,
+    /// Where install and build run, see [`BuildExecutorConfig`].
+    #[serde(default)]
+    pub build_executor: BuildExecutorConfig,
+    /// Heartbeats and status reports from the child, see
+    /// [`HeartbeatConfig`].
+    #[serde(default)]
+    pub heartbeat: HeartbeatConfig,
+    /// Several directories to watch instead of `monitor_path`, see
+    /// [`crate::watch`].
+    #[serde(default)]
+    pub monitor_paths: Vec<WatchPath>,
+    /// Status fields written by the child, see [`crate::app_status`].
+    #[serde(default)]
+    pub app_status: AppStatusConfig,
+    /// Template the env file is rendered from before every spawn, relative
+    /// to `project_path`, see [`crate::secrets::template`].
+    #[serde(default)]
+    pub env_template: Option<String>,
+    /// Output lines that mark the child ready, report a warning or restart
+    /// it, see [`crate::log_rules`].
+    #[serde(default)]
+    pub log_rules: Vec<LogRule>,
+    /// Delta sync endpoint for fleet collectors, see [`crate::state_sync`].
+    #[serde(default)]
+    pub state_sync: StateSyncConfig,
+    /// Variables for placeholders in the commands, see
+    /// [`crate::command_vars`].
+    #[serde(default)]
+    pub command_vars: BTreeMap<String, String>,
+    /// Port handed to the child, fixed or `"auto"`, see [`crate::ports`].
+    #[serde(default)]
+    pub port: Option<PortConfig>,
+    /// Switch the runner itself to `run_as_user` after the privileged
+    /// start up, see [`crate::privileges`].
+    #[serde(default)]
+    pub drop_privileges: bool,
+    /// How often the state file is written, see [`crate::state`].
+    #[serde(default)]
+    pub state_writes: StateWritesConfig,
+    /// Rolling CPU and memory history, see [`crate::metrics_history`].
+    #[serde(default)]
+    pub metrics_history: MetricsHistoryConfig,
+    /// Alerting on sustained CPU usage, see [`crate::cpu_limit`].
+    #[serde(default)]
+    pub cpu_limit: CpuLimitConfig,
+    /// What happens to a child a crashed runner left running, see
+    /// [`crate::orphans`].
+    #[serde(default)]
+    pub orphans: OrphanConfig,
+    /// `group` (default), `process` or `cgroup`, what is killed along
+    /// with the child, see [`KillMode`].
+    #[serde(default)]
+    pub kill_mode: KillMode,
+    /// Named build steps run in place of `install_command` and
+    /// `build_command`, see [`crate::build_steps`].
+    #[serde(default)]
+    pub steps: Vec<BuildStep>,
+    /// Build steps running side by side at most, the number of CPUs when
+    /// unset.
+    #[serde(default)]
+    pub max_parallel_steps: Option<usize>,
+    /// Files `build_command` reads, globs relative to `project_path`. The
+    /// build is skipped while they are unchanged, see
+    /// [`crate::build_cache`].
+    #[serde(default)]
+    pub build_inputs: Vec<String>,
+    /// Files `build_command` produces, the build runs again when one is
+    /// gone.
+    #[serde(default)]
+    pub build_outputs: Vec<String>,
+    /// Where crash diagnostic bundles are written, `<state file>.diagnostics`
+    /// when unset, see [`crate::diagnostics`].
+    #[serde(default)]
+    pub diagnostics_dir: Option<String>,
+    /// stdout and stderr lines of the child put in a diagnostic bundle.
+    #[serde(default = "default_diagnostics_lines")]
+    pub diagnostics_lines: usize,
+    /// Sending the child's output to the aggregator, see
+    /// [`crate::log_shipping`].
+    #[serde(default)]
+    pub log_shipping: LogShippingConfig,
+    /// OTLP/gRPC collector restart traces are exported to, e.g.
+    /// `http://otel-collector:4317`, see [`crate::otel`].
+    #[serde(default)]
+    pub otel_endpoint: Option<String>,
+    /// Timeouts of the shutdown stages, see [`crate::shutdown`].
+    #[serde(default)]
+    pub shutdown: ShutdownConfig,
+    /// Keep the child's stdin on a pipe `ais_runner send-stdin` writes to,
+    /// see [`crate::stdin`].
+    #[serde(default)]
+    pub stdin_pipe: bool,
+    /// A listener in front of the child that holds connections while it
+    /// restarts, see [`ProxyConfig`].
+    #[serde(default)]
+    pub proxy: ProxyConfig,
+    /// Listening sockets the runner binds once and passes to every child,
+    /// see [`crate::listen_fds`].
+    #[serde(default)]
+    pub listen_fds: ListenFdsConfig,
+    /// Journal of what the supervisor did, see [`crate::events`].
+    #[serde(default)]
+    pub events: EventsConfig,
+    /// gRPC service the manager queries and restarts the runner through,
+    /// see [`crate::status_server`].
+    #[serde(default)]
+    pub status_server: StatusServerConfig,
+    /// Captured stdout and stderr lines each kept in the state at most,
+    /// the oldest are evicted first. 0 keeps every line.
+    #[serde(default = "default_max_log_lines")]
+    pub max_log_lines: usize,
+    /// Bytes of captured text each of stdout and stderr keeps in the state
+    /// at most. 0 lifts the limit.
+    #[serde(default = "default_max_log_bytes")]
+    pub max_log_bytes: u64,
+    /// Where states are published besides the state file, see
+    /// [`crate::state_backend`].
+    #[serde(default)]
+    pub state_backend: StateBackendConfig,
+    /// Milliseconds file events are coalesced for after the first one of a
+    /// batch, see [`crate::watch`]. 0 handles every event on its own.
+    #[serde(default = "default_change_batch_ms")]
+    pub change_batch_ms: u64,
+    /// Scanning directories inotify can't watch, see [`crate::polling`].
+    #[serde(default)]
+    pub polling: PollingConfig,
+    /// Watch for changes and rebuild on them. Off the runner only
+    /// supervises the child, deploying on webhooks, the schedule and
+    /// `restart` alone.
+    #[serde(default = "default_monitoring_enabled")]
+    pub monitoring_enabled: bool,
+    /// `service` or `oneshot`, see [`RunMode`].
+    #[serde(default)]
+    pub mode: RunMode,
+    /// How often the child's health, metrics and the state are looked
+    /// after, see [`crate::cadence`].
+    #[serde(default)]
+    pub cadence: CadenceConfig,
+}
+
+impl Default for AppSpecificConfig {
+    fn default() -> Self {
+        Self {
+            interval_seconds: 1,
+            monitor_path: String::from("./"),
+            project_path: String::from("./"),
+            changes_needed: 1,
+            ignored_subdirs: Vec::new(),
+            install_command: None,
+            build_command: None,
+            run_command: String::new(),
+            secret_server_addr: default_secret_server(),
+            env_file_location: default_env_location(),
+            shutdown_timeout_seconds: default_shutdown_timeout(),
+            log_format: LogFormat::default(),
+            startup_probe: None,
+            liveness_probe: None,
+            timestamp_source: TimestampSource::default(),
+            timestamp_formats: default_timestamp_formats(),
+            run_as_user: None,
+            run_as_group: None,
+            working_dir: None,
+            cgroup: CgroupConfig::default(),
+            journal: JournalConfig::default(),
+            install_timeout_seconds: None,
+            build_timeout_seconds: None,
+            build_timeout_action: TimeoutAction::default(),
+            build_timeout_retries: default_build_timeout_retries(),
+            env: BTreeMap::new(),
+            restart_strategy: RestartStrategy::default(),
+            ready_check: None,
+            required_secrets: Vec::new(),
+            only_required_secrets: false,
+            reservation: ReservationConfig::default(),
+            secret_rotation: None,
+            static_server: StaticServerConfig::default(),
+            secret_tls: None,
+            secret_retry: RetryConfig::default(),
+            acme: AcmeConfig::default(),
+            crash_loop: CrashLoopConfig::default(),
+            restart_on_exit_codes: Vec::new(),
+            no_restart_on: Vec::new(),
+            notifications: NotifyConfig::default(),
+            restart_schedule: None,
+            secret_reload: None,
+            webhook_addr: None,
+            webhook_secret: None,
+            build_executor: BuildExecutorConfig::default(),
+            heartbeat: HeartbeatConfig::default(),
+            monitor_paths: Vec::new(),
+            app_status: AppStatusConfig::default(),
+            env_template: None,
+            log_rules: Vec::new(),
+            state_sync: StateSyncConfig::default(),
+            command_vars: BTreeMap::new(),
+            port: None,
+            drop_privileges: false,
+            state_writes: StateWritesConfig::default(),
+            metrics_history: MetricsHistoryConfig::default(),
+            cpu_limit: CpuLimitConfig::default(),
+            orphans: OrphanConfig::default(),
+            kill_mode: KillMode::default(),
+            steps: Vec::new(),
+            max_parallel_steps: None,
+            build_inputs: Vec::new(),
+            build_outputs: Vec::new(),
+            diagnostics_dir: None,
+            diagnostics_lines: default_diagnostics_lines(),
+            log_shipping: LogShippingConfig::default(),
+            otel_endpoint: None,
+            shutdown: ShutdownConfig::default(),
+            stdin_pipe: false,
+            proxy: ProxyConfig::default(),
+            listen_fds: ListenFdsConfig::default(),
+            events: EventsConfig::default(),
+            status_server: StatusServerConfig::default(),
+            max_log_lines: default_max_log_lines(),
+            max_log_bytes: default_max_log_bytes(),
+            state_backend: StateBackendConfig::default(),
+            change_batch_ms: default_change_batch_ms(),
+            polling: PollingConfig::default(),
+            monitoring_enabled: default_monitoring_enabled(),
+            mode: RunMode::default(),
+            cadence: CadenceConfig::default(),
+        }
+    }
 }
 
 #[allow(dead_code)]
@@ -164,8 +677,9 @@ impl AppSpecificConfig {
         let self_cloned = self.clone();
         let path = PathType::Content(self_cloned.monitor_path);
         if !path.exists() {
+            // Caught by validation before start up, the watcher reports it
             log!(LogLevel::Error, "The path {} doesn't exist", path);
-            std::process::exit(0)
+            path
         } else {
             match path.canonicalize() {
                 Ok(canon_path) => PathType::PathBuf(canon_path),
@@ -186,8 +700,9 @@ impl AppSpecificConfig {
         let self_cloned = self.clone();
         let path = PathType::Content(self_cloned.project_path);
         if !path.exists() {
+            // Caught by validation before start up, whatever uses it fails
             log!(LogLevel::Error, "The path {} doesn't exist", path);
-            std::process::exit(0)
+            path
         } else {
             match path.canonicalize() {
                 Ok(canon_path) => PathType::PathBuf(canon_path),
@@ -204,6 +719,76 @@ impl AppSpecificConfig {
         }
     }
 
+    /// Whether there is a build step to run, either a `build_command`,
+    /// named `steps` or an executor that builds on its own.
+    pub fn has_build_step(&self) -> bool {
+        self.build_command.is_some()
+            || !self.steps.is_empty()
+            || matches!(self.build_executor, BuildExecutorConfig::Nix(_))
+    }
+
+    /// Whether a build that runs past its timeout leaves the current child
+    /// serving: `build_timeout_action = "keep"` with a timeout to hit.
+    pub fn keeps_child_on_timeout(&self) -> bool {
+        self.build_timeout_action == TimeoutAction::Keep
+            && (self.build_timeout_seconds.is_some()
+                || self.steps.iter().any(|step| step.timeout_seconds.is_some()))
+    }
+
+    /// Resolved working directory for the child.
+    pub fn working_dir(&self) -> PathType {
+        let project_path = self.project_path();
+        let working_dir = match &self.working_dir {
+            Some(dir) => PathType::PathBuf(project_path.join(dir)),
+            None => return project_path,
+        };
+
+        match working_dir.canonicalize() {
+            Ok(canon_path) => PathType::PathBuf(canon_path),
+            Err(e) => {
+                log!(
+                    LogLevel::Error,
+                    "The working_dir {} is unusable: {}",
+                    working_dir,
+                    e
+                );
+                working_dir
+            }
+        }
+    }
+
+    /// The gate before a new child is `Running`, `ready_check` or else the
+    /// `ready` log rules.
+    pub fn ready_gate(&self) -> Option<ReadyCheck> {
+        match &self.ready_check {
+            Some(check) => Some(check.clone()),
+            None => ready_pattern(&self.log_rules).map(ReadyCheck::on_log_line),
+        }
+    }
+
+    /// Directories to watch, `monitor_paths` with relative entries resolved
+    /// against `project_path`, or `monitor_path` when that list is empty.
+    pub fn watch_paths(&self) -> Vec<WatchPath> {
+        if self.monitor_paths.is_empty() {
+            return vec![WatchPath {
+                path: self.monitor_path.clone(),
+                ignored_subdirs: self.ignored_subdirs.clone(),
+                changes_needed: None,
+            }];
+        }
+
+        self.monitor_paths
+            .iter()
+            .map(|watch| WatchPath {
+                path: Path::new(&self.project_path)
+                    .join(&watch.path)
+                    .display()
+                    .to_string(),
+                ..watch.clone()
+            })
+            .collect()
+    }
+
     /// Converts ignored_subdirs strings into PathType objects relative to the monitor_path
     pub fn ignored_paths(&self) -> Vec<PathType> {
         let base_path = self.safe_path(); // Canonicalize the monitor path
@@ -235,6 +820,7 @@ impl fmt::Display for AppSpecificConfig {
              \t{}: {:?},\n\
              \t{}: {:?},\n\
              \t{}: {},\n\
+             \t{}: {},\n\
              }}",
             "AppSpecificConfig".cyan().bold(),
             "interval_seconds".yellow(),
@@ -252,10 +838,37 @@ impl fmt::Display for AppSpecificConfig {
             "build_command".yellow(),
             self.build_command,
             "run_command".yellow(),
-            self.run_command.clone().green()
+            self.run_command.clone().green(),
+            "shutdown_timeout_seconds".yellow(),
+            self.shutdown_timeout_seconds.to_string().green()
         )
     }
 }
 
-pub fn default_secret_server() -> String { String::from("localhost:50051") }
-pub fn default_env_location() -> String { String::from("/tmp/.trash") }
\ No newline at end of file
+pub fn default_secret_server() -> String {
+    String::from("localhost:50051")
+}
+pub fn default_env_location() -> String {
+    String::from("/tmp/.trash")
+}
+pub fn default_shutdown_timeout() -> u64 {
+    5
+}
+pub fn default_build_timeout_retries() -> u32 {
+    1
+}
+pub fn default_diagnostics_lines() -> usize {
+    200
+}
+pub fn default_max_log_lines() -> usize {
+    10_000
+}
+pub fn default_max_log_bytes() -> u64 {
+    8 * 1024 * 1024
+}
+pub fn default_change_batch_ms() -> u64 {
+    500
+}
+pub fn default_monitoring_enabled() -> bool {
+    true
+}
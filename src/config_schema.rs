@@ -0,0 +1,810 @@
+//! Schema of `[app_specific]`, read off the config structs.
+//!
+//! Nothing describes the options besides the structs in [`crate::config`],
+//! so the schema is taken from them through serde: [`schema`] deserializes
+//! a config from a probe that answers every request with a placeholder and
+//! notes what was asked for. Struct fields, enum variants, lists and maps
+//! all show up that way; fields that accept several shapes (untagged or
+//! internally tagged enums) can't be told apart and become [`Kind::Any`].
+//! Leaving out every field that isn't needed tells the required ones
+//! apart, and serializing the config built from only those gives the
+//! defaults.
+//!
+//! The schema backs `--explain-config`, the check rejecting unknown keys,
+//! which serde would otherwise ignore silently, and naming the option a
+//! config that doesn't deserialize trips over.
+
+use serde::{
+    Serialize,
+    de::{
+        self, DeserializeOwned, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess,
+        SeqAccess, VariantAccess, Visitor,
+    },
+};
+use serde_json::Value;
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+/// Upper bound on deserialization attempts, each one learns one thing.
+const MAX_PROBES: usize = 256;
+
+/// Column the comments of [`explain`] line up at.
+const COMMENT_COLUMN: usize = 44;
+
+/// What an option holds.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Kind {
+    Bool,
+    Integer,
+    Float,
+    String,
+    List(Box<Kind>),
+    /// A table with keys of the user's choosing.
+    Map(Box<Kind>),
+    Table(Vec<Field>),
+    /// One of these strings.
+    Choice(Vec<&'static str>),
+    Optional(Box<Kind>),
+    /// Several shapes are accepted.
+    Any,
+}
+
+impl Kind {
+    /// Whether a table is somewhere inside.
+    fn has_table(&self) -> bool {
+        match self {
+            Kind::Table(_) => true,
+            Kind::List(inner) | Kind::Map(inner) | Kind::Optional(inner) => inner.has_table(),
+            _ => false,
+        }
+    }
+
+    /// Name of the type as shown by [`explain`].
+    fn describe(&self) -> String {
+        match self {
+            Kind::Bool => String::from("bool"),
+            Kind::Integer => String::from("integer"),
+            Kind::Float => String::from("float"),
+            Kind::String => String::from("string"),
+            Kind::List(inner) => format!("[{}]", inner.describe()),
+            Kind::Map(inner) => format!("{{ <key> = {} }}", inner.describe()),
+            Kind::Table(_) => String::from("table"),
+            Kind::Choice(variants) => variants
+                .iter()
+                .map(|variant| format!("{:?}", variant))
+                .collect::<Vec<_>>()
+                .join(" | "),
+            Kind::Optional(inner) => inner.describe(),
+            Kind::Any => String::from("value"),
+        }
+    }
+}
+
+/// An option of a table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub name: &'static str,
+    pub kind: Kind,
+    pub required: bool,
+    /// Value used when the option is left out, if it has one worth
+    /// showing.
+    pub default: Option<toml::Value>,
+}
+
+/// The schema of `T`, a [`Kind::Table`] for config structs.
+pub fn schema<T: DeserializeOwned + Serialize>() -> Kind {
+    let recorder = Recorder::default();
+    let mut plan = Plan::default();
+
+    // Every field, leaving out those that reject their placeholder
+    for _ in 0..MAX_PROBES {
+        match T::deserialize(Probe::root(&recorder, &plan)) {
+            Err(ProbeError::Custom(_)) if learn(&mut plan.skip, recorder.failed.take()) => {
+                continue;
+            }
+            _ => break,
+        }
+    }
+
+    // Only what has to be there, everything else takes its default
+    plan.minimal = true;
+    let mut defaults = None;
+    for _ in 0..MAX_PROBES {
+        match T::deserialize(Probe::root(&recorder, &plan)) {
+            Ok(config) => {
+                defaults = toml::Value::try_from(&config).ok();
+                break;
+            }
+            Err(ProbeError::MissingAt(path)) if plan.required.insert(path.clone()) => continue,
+            Err(ProbeError::Custom(_)) if learn(&mut plan.skip, recorder.failed.take()) => {
+                continue;
+            }
+            Err(_) => break,
+        }
+    }
+
+    recorder.kind("", &plan.required, defaults.as_ref())
+}
+
+/// Add `path` to `paths`, whether it is new.
+fn learn(paths: &mut HashSet<String>, path: Option<String>) -> bool {
+    match path {
+        Some(path) => paths.insert(path),
+        None => false,
+    }
+}
+
+/// Keys in `value` that `kind` doesn't know, with the closest known one
+/// when there is one that is likely meant. `section` prefixes the keys in
+/// the messages.
+pub fn unknown_keys(kind: &Kind, value: &Value, section: &str) -> Vec<String> {
+    let mut problems = Vec::new();
+    check(kind, value, section, &mut problems);
+    problems
+}
+
+fn check(kind: &Kind, value: &Value, path: &str, problems: &mut Vec<String>) {
+    match (kind, value) {
+        (Kind::Table(fields), Value::Object(table)) => {
+            for (key, value) in table {
+                match fields.iter().find(|field| field.name == key) {
+                    Some(field) => check(&field.kind, value, &join(path, key), problems),
+                    None => problems.push(unknown(fields, key, path)),
+                }
+            }
+        }
+        (Kind::Optional(inner), value) => check(inner, value, path, problems),
+        (Kind::List(inner), Value::Array(items)) => {
+            for (index, item) in items.iter().enumerate() {
+                check(inner, item, &format!("{}[{}]", path, index), problems);
+            }
+        }
+        (Kind::Map(inner), Value::Object(table)) => {
+            for (key, value) in table {
+                check(inner, value, &join(path, key), problems);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// The kind of the option at the dotted `path` below `kind`.
+pub fn lookup<'a>(kind: &'a Kind, path: &str) -> Option<&'a Kind> {
+    path.split('.').try_fold(kind, |kind, name| {
+        let kind = match kind {
+            Kind::Optional(inner) => inner,
+            kind => kind,
+        };
+        match kind {
+            Kind::Table(fields) => fields
+                .iter()
+                .find(|field| field.name == name)
+                .map(|field| &field.kind),
+            Kind::Map(inner) => Some(inner.as_ref()),
+            _ => None,
+        }
+    })
+}
+
+/// Options in `value` that `kind` can't take and required ones that are
+/// missing. Strings holding a number or a bool pass for one, as the config
+/// loader converts them. `section` prefixes the keys in the messages.
+pub fn mismatched_keys(kind: &Kind, value: &Value, section: &str) -> Vec<String> {
+    let mut problems = Vec::new();
+    mismatches(kind, value, section, &mut problems);
+    problems
+}
+
+fn mismatches(kind: &Kind, value: &Value, path: &str, problems: &mut Vec<String>) {
+    match (kind, value) {
+        (Kind::Table(fields), Value::Object(table)) => {
+            for field in fields {
+                match table.get(field.name) {
+                    Some(value) => {
+                        mismatches(&field.kind, value, &join(path, field.name), problems)
+                    }
+                    None if field.required => {
+                        problems.push(format!("{} is required", join(path, field.name)))
+                    }
+                    None => (),
+                }
+            }
+        }
+        (Kind::Optional(_), Value::Null) => (),
+        (Kind::Optional(inner), value) => mismatches(inner, value, path, problems),
+        (Kind::List(inner), Value::Array(items)) => {
+            for (index, item) in items.iter().enumerate() {
+                mismatches(inner, item, &format!("{}[{}]", path, index), problems);
+            }
+        }
+        (Kind::Map(inner), Value::Object(table)) => {
+            for (key, value) in table {
+                mismatches(inner, value, &join(path, key), problems);
+            }
+        }
+        (kind, value) if !accepts(kind, value) => problems.push(format!(
+            "{}: expected {}, found {}",
+            path,
+            kind.describe(),
+            found(value)
+        )),
+        _ => (),
+    }
+}
+
+/// Whether a leaf `kind` takes `value`.
+fn accepts(kind: &Kind, value: &Value) -> bool {
+    match (kind, value) {
+        (Kind::Any, _) => true,
+        (Kind::Bool, Value::Bool(_)) => true,
+        (Kind::Bool, Value::String(text)) => matches!(
+            text.to_lowercase().as_str(),
+            "true" | "false" | "on" | "off" | "yes" | "no" | "1" | "0"
+        ),
+        (Kind::Integer, Value::Number(number)) => number.is_i64() || number.is_u64(),
+        (Kind::Integer, Value::String(text)) => text.trim().parse::<i64>().is_ok(),
+        (Kind::Float, Value::Number(_)) => true,
+        (Kind::Float, Value::String(text)) => text.trim().parse::<f64>().is_ok(),
+        (Kind::String, Value::String(_) | Value::Number(_) | Value::Bool(_)) => true,
+        (Kind::Choice(variants), Value::String(text)) => variants.contains(&text.as_str()),
+        _ => false,
+    }
+}
+
+/// `value` as told in a message.
+fn found(value: &Value) -> String {
+    match value {
+        Value::Null => String::from("nothing"),
+        Value::Bool(value) => format!("bool {}", value),
+        Value::Number(number) => format!("number {}", number),
+        Value::String(text) => format!("string {:?}", text),
+        Value::Array(_) => String::from("a list"),
+        Value::Object(_) => String::from("a table"),
+    }
+}
+
+fn unknown(fields: &[Field], key: &str, path: &str) -> String {
+    let closest = fields
+        .iter()
+        .map(|field| (distance(key, field.name), field.name))
+        .filter(|(distance, name)| *distance <= (name.len() / 3).max(2))
+        .min();
+    match closest {
+        Some((_, name)) => format!("Unknown key {}, did you mean {}?", join(path, key), name),
+        None => format!("Unknown key {}", join(path, key)),
+    }
+}
+
+/// Levenshtein distance between `a` and `b`.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(a != *b);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// A reference of every option in `kind`, written as the `section` of a
+/// `Config.toml`.
+pub fn explain(kind: &Kind, section: &str) -> String {
+    let mut out = String::new();
+    explain_table(kind, section, false, None, &mut out);
+    out
+}
+
+fn explain_table(kind: &Kind, section: &str, list: bool, note: Option<&str>, out: &mut String) {
+    let fields = match kind {
+        Kind::Table(fields) => fields,
+        _ => return,
+    };
+    let header = match list {
+        true => format!("[[{}]]", section),
+        false => format!("[{}]", section),
+    };
+    match note {
+        Some(note) => out.push_str(&commented(&header, note)),
+        None => out.push_str(&format!("{}\n", header)),
+    }
+
+    let mut nested: Vec<(&Field, &Kind, bool)> = Vec::new();
+    for field in fields {
+        let inner = match &field.kind {
+            Kind::Optional(inner) => inner.as_ref(),
+            kind => kind,
+        };
+        match inner {
+            Kind::Table(_) => nested.push((field, inner, false)),
+            Kind::List(item) if item.has_table() => nested.push((field, item, true)),
+            _ => {
+                let note = match (&field.default, field.required) {
+                    (_, true) => String::from("required"),
+                    (Some(default), _) => format!("default: {}", default),
+                    (None, _) => String::from("optional"),
+                };
+                let line = format!("{} = <{}>", field.name, inner.describe());
+                out.push_str(&commented(&line, &note));
+            }
+        }
+    }
+
+    for (field, kind, list) in nested {
+        out.push('\n');
+        let note = match (&field.kind, list, field.required) {
+            (_, _, true) => Some("required"),
+            (Kind::Optional(_), _, _) => Some("optional"),
+            (_, true, _) => Some("any number of"),
+            _ => None,
+        };
+        explain_table(kind, &join(section, field.name), list, note, out);
+    }
+}
+
+/// `line` followed by `note` as a comment, lined up at [`COMMENT_COLUMN`].
+fn commented(line: &str, note: &str) -> String {
+    let padding = COMMENT_COLUMN.saturating_sub(line.len()).max(1);
+    format!("{}{}# {}\n", line, " ".repeat(padding), note)
+}
+
+fn join(path: &str, name: &str) -> String {
+    match path.is_empty() {
+        true => name.to_owned(),
+        false => format!("{}.{}", path, name),
+    }
+}
+
+/// What the probe was asked for at a path.
+#[derive(Debug, Clone)]
+enum Shape {
+    Leaf(Kind),
+    Optional,
+    List,
+    Map,
+    Table(&'static [&'static str]),
+}
+
+/// Shapes seen by the probes, by path. Optional values continue at
+/// `<path>?`, list items at `<path>[]` and map values at `<path>.*`.
+#[derive(Debug, Default)]
+struct Recorder {
+    shapes: RefCell<HashMap<String, Shape>>,
+    /// Field whose placeholder was rejected, the innermost one.
+    failed: RefCell<Option<String>>,
+}
+
+impl Recorder {
+    fn fail(&self, path: &str, err: &ProbeError) {
+        let mut failed = self.failed.borrow_mut();
+        if failed.is_none() && matches!(err, ProbeError::Custom(_)) {
+            *failed = Some(path.to_owned());
+        }
+    }
+
+    fn has_table(&self, path: &str) -> bool {
+        match self.shapes.borrow().get(path) {
+            Some(Shape::Table(_)) => true,
+            Some(Shape::Optional) => self.has_table(&format!("{}?", path)),
+            Some(Shape::List) => self.has_table(&format!("{}[]", path)),
+            Some(Shape::Map) => self.has_table(&format!("{}.*", path)),
+            _ => false,
+        }
+    }
+
+    fn kind(&self, path: &str, required: &HashSet<String>, default: Option<&toml::Value>) -> Kind {
+        let shape = self.shapes.borrow().get(path).cloned();
+        match shape {
+            None => Kind::Any,
+            Some(Shape::Leaf(kind)) => kind,
+            Some(Shape::Optional) => Kind::Optional(Box::new(self.kind(
+                &format!("{}?", path),
+                required,
+                default,
+            ))),
+            Some(Shape::List) => {
+                let item = default.and_then(|list| list.as_array()?.first());
+                Kind::List(Box::new(self.kind(&format!("{}[]", path), required, item)))
+            }
+            Some(Shape::Map) => {
+                let value = default.and_then(|map| map.get(""));
+                Kind::Map(Box::new(self.kind(&format!("{}.*", path), required, value)))
+            }
+            Some(Shape::Table(fields)) => Kind::Table(
+                fields
+                    .iter()
+                    .map(|name| {
+                        let field_path = join(path, name);
+                        let value = default.and_then(|table| table.get(*name));
+                        let kind = self.kind(&field_path, required, value);
+                        let required = required.contains(&field_path);
+                        // Placeholders stand in for required values and tables
+                        let default = match required || kind.has_table() {
+                            true => None,
+                            false => value.cloned(),
+                        };
+                        Field {
+                            name,
+                            kind,
+                            required,
+                            default,
+                        }
+                    })
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// Which fields the probe hands out.
+#[derive(Debug, Default)]
+struct Plan {
+    /// Fields whose placeholder was rejected.
+    skip: HashSet<String>,
+    /// Only hand out required fields and those with tables inside.
+    minimal: bool,
+    required: HashSet<String>,
+}
+
+#[derive(Debug)]
+enum ProbeError {
+    Custom(String),
+    Missing(&'static str),
+    /// A required field, by path.
+    MissingAt(String),
+}
+
+impl fmt::Display for ProbeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProbeError::Custom(message) => write!(f, "{}", message),
+            ProbeError::Missing(field) => write!(f, "missing field {}", field),
+            ProbeError::MissingAt(path) => write!(f, "missing field {}", path),
+        }
+    }
+}
+
+impl std::error::Error for ProbeError {}
+
+impl de::Error for ProbeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        ProbeError::Custom(msg.to_string())
+    }
+
+    fn missing_field(field: &'static str) -> Self {
+        ProbeError::Missing(field)
+    }
+}
+
+/// Deserializer answering with placeholders and recording the requests.
+#[derive(Debug, Clone)]
+struct Probe<'a> {
+    recorder: &'a Recorder,
+    plan: &'a Plan,
+    path: String,
+}
+
+impl<'a> Probe<'a> {
+    fn root(recorder: &'a Recorder, plan: &'a Plan) -> Self {
+        Self {
+            recorder,
+            plan,
+            path: String::new(),
+        }
+    }
+
+    fn at(&self, path: String) -> Self {
+        Self {
+            recorder: self.recorder,
+            plan: self.plan,
+            path,
+        }
+    }
+
+    fn record(&self, shape: Shape) {
+        self.recorder
+            .shapes
+            .borrow_mut()
+            .insert(self.path.clone(), shape);
+    }
+
+    /// Whether the field at `path` is handed out.
+    fn hands_out(&self, path: &str) -> bool {
+        let plan = self.plan;
+        !plan.skip.contains(path)
+            && (!plan.minimal || plan.required.contains(path) || self.recorder.has_table(path))
+    }
+}
+
+macro_rules! leaf {
+    ($($method:ident => $kind:expr, $visit:ident($value:expr);)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ProbeError> {
+                self.record(Shape::Leaf($kind));
+                visitor.$visit($value)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for Probe<'_> {
+    type Error = ProbeError;
+
+    leaf! {
+        deserialize_any => Kind::Any, visit_str("");
+        deserialize_bool => Kind::Bool, visit_bool(false);
+        deserialize_i8 => Kind::Integer, visit_i64(0);
+        deserialize_i16 => Kind::Integer, visit_i64(0);
+        deserialize_i32 => Kind::Integer, visit_i64(0);
+        deserialize_i64 => Kind::Integer, visit_i64(0);
+        deserialize_u8 => Kind::Integer, visit_u64(0);
+        deserialize_u16 => Kind::Integer, visit_u64(0);
+        deserialize_u32 => Kind::Integer, visit_u64(0);
+        deserialize_u64 => Kind::Integer, visit_u64(0);
+        deserialize_f32 => Kind::Float, visit_f64(0.0);
+        deserialize_f64 => Kind::Float, visit_f64(0.0);
+        deserialize_char => Kind::String, visit_char('a');
+        deserialize_str => Kind::String, visit_str("");
+        deserialize_string => Kind::String, visit_str("");
+        deserialize_bytes => Kind::String, visit_bytes(&[]);
+        deserialize_byte_buf => Kind::String, visit_bytes(&[]);
+        deserialize_identifier => Kind::String, visit_str("");
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ProbeError> {
+        self.record(Shape::Optional);
+        let inner = self.at(format!("{}?", self.path));
+        visitor.visit_some(inner)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ProbeError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, ProbeError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, ProbeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ProbeError> {
+        self.record(Shape::List);
+        let item = self.at(format!("{}[]", self.path));
+        visitor.visit_seq(Items {
+            probe: item,
+            remaining: 1,
+        })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, ProbeError> {
+        self.record(Shape::List);
+        let item = self.at(format!("{}[]", self.path));
+        visitor.visit_seq(Items {
+            probe: item,
+            remaining: len,
+        })
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, ProbeError> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ProbeError> {
+        self.record(Shape::Map);
+        let value = self.at(format!("{}.*", self.path));
+        visitor.visit_map(Entry {
+            probe: value,
+            done: false,
+        })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ProbeError> {
+        self.record(Shape::Table(fields));
+        let handed_out: Vec<&'static str> = fields
+            .iter()
+            .copied()
+            .filter(|field| self.hands_out(&join(&self.path, field)))
+            .collect();
+        let path = self.path.clone();
+        visitor
+            .visit_map(Fields {
+                probe: self,
+                fields: handed_out.into_iter(),
+                current: "",
+            })
+            .map_err(|err| match err {
+                ProbeError::Missing(field) => ProbeError::MissingAt(join(&path, field)),
+                err => err,
+            })
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ProbeError> {
+        self.record(Shape::Leaf(Kind::Choice(variants.to_vec())));
+        let variant = variants
+            .first()
+            .copied()
+            .ok_or_else(|| ProbeError::Custom(String::from("enum without variants")))?;
+        visitor.visit_enum(Variant {
+            probe: self,
+            variant,
+        })
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ProbeError> {
+        visitor.visit_unit()
+    }
+}
+
+/// The fields of a struct, each with a placeholder.
+struct Fields<'a> {
+    probe: Probe<'a>,
+    fields: std::vec::IntoIter<&'static str>,
+    current: &'static str,
+}
+
+impl<'de> MapAccess<'de> for Fields<'_> {
+    type Error = ProbeError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, ProbeError> {
+        match self.fields.next() {
+            Some(field) => {
+                self.current = field;
+                seed.deserialize(field.into_deserializer()).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, ProbeError> {
+        let path = join(&self.probe.path, self.current);
+        seed.deserialize(self.probe.at(path.clone()))
+            .inspect_err(|err| self.probe.recorder.fail(&path, err))
+    }
+}
+
+/// A single map entry with an empty key.
+struct Entry<'a> {
+    probe: Probe<'a>,
+    done: bool,
+}
+
+impl<'de> MapAccess<'de> for Entry<'_> {
+    type Error = ProbeError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, ProbeError> {
+        match self.done {
+            true => Ok(None),
+            false => {
+                self.done = true;
+                seed.deserialize("".into_deserializer()).map(Some)
+            }
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, ProbeError> {
+        let probe = self.probe.clone();
+        seed.deserialize(probe)
+            .inspect_err(|err| self.probe.recorder.fail(&self.probe.path, err))
+    }
+}
+
+/// `remaining` list items.
+struct Items<'a> {
+    probe: Probe<'a>,
+    remaining: usize,
+}
+
+impl<'de> SeqAccess<'de> for Items<'_> {
+    type Error = ProbeError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, ProbeError> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        seed.deserialize(self.probe.clone())
+            .map(Some)
+            .inspect_err(|err| self.probe.recorder.fail(&self.probe.path, err))
+    }
+}
+
+/// The first variant of an enum.
+struct Variant<'a> {
+    probe: Probe<'a>,
+    variant: &'static str,
+}
+
+impl<'de, 'a> EnumAccess<'de> for Variant<'a> {
+    type Error = ProbeError;
+    type Variant = Probe<'a>;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Probe<'a>), ProbeError> {
+        let variant = seed.deserialize(self.variant.into_deserializer())?;
+        Ok((variant, self.probe))
+    }
+}
+
+impl<'de> VariantAccess<'de> for Probe<'_> {
+    type Error = ProbeError;
+
+    fn unit_variant(self) -> Result<(), ProbeError> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, ProbeError> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, ProbeError> {
+        de::Deserializer::deserialize_tuple(self, len, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ProbeError> {
+        de::Deserializer::deserialize_struct(self, "", fields, visitor)
+    }
+}
@@ -0,0 +1,129 @@
+//! Alerting on sustained CPU usage of the child.
+//!
+//! `max_ram_usage` has always been checked against the child's metrics, CPU
+//! wasn't. With `max_cpu_percent` set the runner keeps a rolling average of
+//! the CPU usage over `window_seconds`, so short spikes don't count, and
+//! acts once the average goes above the limit:
+//!
+//! ```toml
+//! [app_specific.cpu_limit]
+//! max_cpu_percent = 150      # percent of one core
+//! window_seconds = 60
+//! action = "warn"            # or "notify", or "throttle" to apply cpu.max
+//! ```
+//!
+//! Every action logs a warning and records an error. `notify` also sends a
+//! `cpu_limit` notification, `throttle` writes the limit to `cpu.max` of the
+//! child's cgroup so the kernel holds the child to it from then on.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// What happens when the average goes above the limit.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CpuLimitAction {
+    #[default]
+    Warn,
+    Notify,
+    Throttle,
+}
+
+/// `[app_specific.cpu_limit]`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CpuLimitConfig {
+    /// Limit in percent of one core, off when unset.
+    #[serde(default)]
+    pub max_cpu_percent: Option<f32>,
+    /// Length of the rolling average.
+    #[serde(default = "default_window")]
+    pub window_seconds: u64,
+    #[serde(default)]
+    pub action: CpuLimitAction,
+}
+
+impl Default for CpuLimitConfig {
+    fn default() -> Self {
+        Self {
+            max_cpu_percent: None,
+            window_seconds: default_window(),
+            action: CpuLimitAction::default(),
+        }
+    }
+}
+
+fn default_window() -> u64 {
+    60
+}
+
+/// The average crossed the limit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CpuChange {
+    /// Went above, with the average.
+    Exceeded(f32),
+    /// Came back below, with the average.
+    Recovered(f32),
+}
+
+/// Rolling average of the child's CPU usage.
+#[derive(Debug)]
+pub struct CpuMonitor {
+    limit: f32,
+    window: Duration,
+    samples: VecDeque<(Instant, f32)>,
+    exceeded: bool,
+}
+
+impl CpuMonitor {
+    /// `None` when no limit is configured.
+    pub fn new(config: &CpuLimitConfig) -> Option<Self> {
+        let limit = config.max_cpu_percent?;
+        Some(Self {
+            limit,
+            window: Duration::from_secs(config.window_seconds),
+            samples: VecDeque::new(),
+            exceeded: false,
+        })
+    }
+
+    /// Average over the window, `None` until samples cover all of it.
+    pub fn average(&self) -> Option<f32> {
+        let (oldest, _) = self.samples.front()?;
+        let (newest, _) = self.samples.back()?;
+        if newest.duration_since(*oldest) < self.window {
+            return None;
+        }
+        let sum: f32 = self.samples.iter().map(|(_, cpu)| cpu).sum();
+        Some(sum / self.samples.len() as f32)
+    }
+
+    /// Take in the usage measured at `now`, telling when the average
+    /// crosses the limit in either direction.
+    pub fn observe(&mut self, now: Instant, cpu: f32) -> Option<CpuChange> {
+        self.samples.push_back((now, cpu));
+        // Keep the one sample that reaches back to the start of the window
+        while self
+            .samples
+            .get(1)
+            .is_some_and(|(at, _)| now.duration_since(*at) >= self.window)
+        {
+            self.samples.pop_front();
+        }
+
+        let average = self.average()?;
+        match (self.exceeded, average > self.limit) {
+            (false, true) => {
+                self.exceeded = true;
+                Some(CpuChange::Exceeded(average))
+            }
+            (true, false) => {
+                self.exceeded = false;
+                Some(CpuChange::Recovered(average))
+            }
+            _ => None,
+        }
+    }
+}
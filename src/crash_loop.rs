@@ -0,0 +1,182 @@
+//! Circuit breaker for children that crash right after being spawned.
+//!
+//! A child that dies on start used to be respawned on every periodic check,
+//! forever. [`CrashLoopBreaker`] counts those respawns and, once
+//! `max_restarts` happened within `window_minutes`, opens: the runner stops
+//! respawning, reports `Warning` and keeps a [`CrashLoopReport`] in the
+//! runner state until an operator restarts the instance.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    fmt,
+    time::{Duration, Instant},
+};
+
+/// stderr lines kept in a [`CrashLoopReport`].
+pub const REPORT_STDERR_LINES: usize = 20;
+
+/// `[app_specific.crash_loop]`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CrashLoopConfig {
+    #[serde(default = "default_crash_loop_enabled")]
+    pub enabled: bool,
+    /// Respawns of a crashed child tolerated within `window_minutes`.
+    #[serde(default = "default_max_restarts")]
+    pub max_restarts: usize,
+    #[serde(default = "default_window_minutes")]
+    pub window_minutes: u64,
+}
+
+impl Default for CrashLoopConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_crash_loop_enabled(),
+            max_restarts: default_max_restarts(),
+            window_minutes: default_window_minutes(),
+        }
+    }
+}
+
+fn default_crash_loop_enabled() -> bool {
+    true
+}
+
+fn default_max_restarts() -> usize {
+    5
+}
+
+fn default_window_minutes() -> u64 {
+    5
+}
+
+impl CrashLoopConfig {
+    pub fn window(&self) -> Duration {
+        Duration::from_secs(self.window_minutes * 60)
+    }
+}
+
+/// Tracks respawns of crashed children.
+#[derive(Debug)]
+pub struct CrashLoopBreaker {
+    config: CrashLoopConfig,
+    restarts: VecDeque<Instant>,
+    open: bool,
+}
+
+impl CrashLoopBreaker {
+    pub fn new(config: CrashLoopConfig) -> Self {
+        Self {
+            config,
+            restarts: VecDeque::new(),
+            open: false,
+        }
+    }
+
+    /// Whether respawning is currently suspended.
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /// Record a crashed child at `now`.
+    ///
+    /// Returns `true` when it may be respawned and `false` once this crash
+    /// tripped the breaker, or it already was open.
+    pub fn allow_restart(&mut self, now: Instant) -> bool {
+        if self.open {
+            return false;
+        }
+        if !self.config.enabled {
+            return true;
+        }
+
+        let window = self.config.window();
+        while let Some(oldest) = self.restarts.front() {
+            if now.duration_since(*oldest) < window {
+                break;
+            }
+            self.restarts.pop_front();
+        }
+
+        if self.restarts.len() >= self.config.max_restarts {
+            self.open = true;
+            return false;
+        }
+        self.restarts.push_back(now);
+        true
+    }
+
+    /// Respawns counted in the current window.
+    pub fn recent_restarts(&self) -> usize {
+        self.restarts.len()
+    }
+
+    /// Apply a reloaded `config`, crashes already counted stay counted.
+    pub fn reconfigure(&mut self, config: CrashLoopConfig) {
+        self.config = config;
+    }
+
+    /// Close the breaker and forget past crashes, after an operator stepped
+    /// in or a new build was deployed.
+    pub fn reset(&mut self) {
+        self.restarts.clear();
+        self.open = false;
+    }
+}
+
+/// What was known about the child when the breaker opened.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CrashLoopReport {
+    pub timestamp: u64,
+    /// Respawns within the window before giving up.
+    pub restarts: usize,
+    pub window_minutes: u64,
+    /// Exit code of the last child, when it could be determined.
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+    /// Last stderr lines of the child, oldest first.
+    #[serde(default)]
+    pub stderr: Vec<String>,
+    /// Diagnostic bundle written for the crash loop, see
+    /// [`crate::diagnostics`].
+    #[serde(default)]
+    pub diagnostics: Option<String>,
+}
+
+impl CrashLoopReport {
+    /// Build a report from the breaker's window and the child's captured
+    /// stderr.
+    pub fn new(
+        timestamp: u64,
+        breaker: &CrashLoopBreaker,
+        exit_code: Option<i32>,
+        stderr: &[(u64, String)],
+    ) -> Self {
+        Self {
+            timestamp,
+            restarts: breaker.recent_restarts(),
+            window_minutes: breaker.config.window_minutes,
+            exit_code,
+            stderr: stderr[stderr.len().saturating_sub(REPORT_STDERR_LINES)..]
+                .iter()
+                .map(|(_, line)| line.clone())
+                .collect(),
+            diagnostics: None,
+        }
+    }
+}
+
+impl fmt::Display for CrashLoopReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Child crashed {} times within {} minutes, respawning stopped",
+            self.restarts + 1,
+            self.window_minutes
+        )?;
+        if let Some(code) = self.exit_code {
+            write!(f, " (last exit code {})", code)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,142 @@
+//! Stage timings of a single restart.
+//!
+//! Every recorded restart keeps a timeline of the stages it went through,
+//! each as an offset from the event that triggered it and a duration:
+//!
+//! - `debounce`, from the first file change until enough piled up
+//! - `install`, the install step, when the restart runs it
+//! - `build`, the build step and publishing its static output
+//! - `stop`, stopping the current child
+//! - `start`, spawning the new one
+//! - `ready`, waiting for the ready check
+//!
+//! Kill-first restarts stop before they build, build-first ones after. The
+//! timelines are stored with the restart history and exported by the
+//! `trace` subcommand.
+
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+use crate::restart::RestartKind;
+use crate::runner_state::RestartRecord;
+
+/// A stage of a restart.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    Debounce,
+    Install,
+    Build,
+    Stop,
+    Start,
+    Ready,
+}
+
+impl Stage {
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Debounce => "debounce",
+            Stage::Install => "install",
+            Stage::Build => "build",
+            Stage::Stop => "stop",
+            Stage::Start => "start",
+            Stage::Ready => "ready",
+        }
+    }
+}
+
+/// One stage on the timeline of a restart.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct StageSpan {
+    pub stage: Stage,
+    /// Since the triggering event.
+    pub start_ms: u64,
+    pub duration_ms: u64,
+}
+
+/// Times the stages of a running restart, each lasting until the next one
+/// begins.
+#[derive(Debug, Clone)]
+pub struct DeployTrace {
+    origin: Instant,
+    spans: Vec<StageSpan>,
+    open: Option<(Stage, Instant)>,
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis() as u64
+}
+
+impl DeployTrace {
+    /// Start timing a restart handled at `now`, for an event at
+    /// `triggered` when it was debounced.
+    pub fn new(triggered: Option<Instant>, now: Instant) -> Self {
+        let origin = triggered.unwrap_or(now);
+        let mut trace = Self {
+            origin,
+            spans: Vec::new(),
+            open: None,
+        };
+        if triggered.is_some() {
+            trace.spans.push(StageSpan {
+                stage: Stage::Debounce,
+                start_ms: 0,
+                duration_ms: millis(now.saturating_duration_since(origin)),
+            });
+        }
+        trace
+    }
+
+    /// Begin `stage` at `now`, ending the one before.
+    pub fn begin(&mut self, stage: Stage, now: Instant) {
+        self.end(now);
+        self.open = Some((stage, now));
+    }
+
+    /// End the current stage at `now`.
+    pub fn end(&mut self, now: Instant) {
+        if let Some((stage, started)) = self.open.take() {
+            self.spans.push(StageSpan {
+                stage,
+                start_ms: millis(started.saturating_duration_since(self.origin)),
+                duration_ms: millis(now.saturating_duration_since(started)),
+            });
+        }
+    }
+
+    /// The stages ended so far.
+    pub fn spans(&self) -> &[StageSpan] {
+        &self.spans
+    }
+}
+
+/// A restart as exported by `trace --json`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Timeline {
+    pub timestamp: u64,
+    pub reason: String,
+    pub kind: Option<RestartKind>,
+    pub success: Option<bool>,
+    /// From the triggering event to the end of the last stage.
+    pub total_ms: u64,
+    pub stages: Vec<StageSpan>,
+}
+
+impl Timeline {
+    /// The timeline of `record`, `None` for restarts recorded without one.
+    pub fn of(record: &RestartRecord) -> Option<Self> {
+        let total_ms = record
+            .stages
+            .iter()
+            .map(|span| span.start_ms + span.duration_ms)
+            .max()?;
+        Some(Self {
+            timestamp: record.timestamp,
+            reason: record.reason.clone(),
+            kind: record.kind,
+            success: record.success,
+            total_ms,
+            stages: record.stages.clone(),
+        })
+    }
+}
@@ -0,0 +1,331 @@
+//! Dry-run of the deployment pipeline.
+//!
+//! Walks the same steps as a real start (config, validation,
+//! prerequisites, paths, secrets, install, build or the named build
+//! steps) but stops short of spawning `run_command`, printing a report of
+//! every step instead. State is written to a scratch file so a live
+//! instance using the same configuration isn't disturbed.
+
+use artisan_middleware::{
+    config::AppConfig, dusa_collection_utils::core::types::pathtype::PathType,
+    state_persistence::AppState,
+};
+use colored::Colorize;
+use std::time::Instant;
+
+use crate::{
+    build_steps::StepRun,
+    child::{run_build_steps, run_install_process, run_one_shot_process},
+    config::{
+        AppSpecificConfig, default_secret_server, generate_application_state, get_config,
+        specific_config,
+    },
+    global_child::get_query,
+    prerequisites,
+    secrets::SecretClient,
+    validation,
+};
+
+/// Outcome of a single pipeline step.
+#[derive(Debug)]
+pub enum StepResult {
+    Passed(String),
+    Skipped(String),
+    Failed(String),
+}
+
+/// Collected results of a dry run.
+#[derive(Default)]
+pub struct DryRunReport {
+    steps: Vec<(String, StepResult, u128)>,
+}
+
+impl DryRunReport {
+    fn record(&mut self, step: &str, started: Instant, result: StepResult) {
+        self.steps
+            .push((step.to_owned(), result, started.elapsed().as_millis()));
+    }
+
+    fn record_run(&mut self, run: &StepRun, result: StepResult) {
+        self.steps.push((
+            format!("step {}", run.name),
+            result,
+            run.duration_ms as u128,
+        ));
+    }
+
+    /// Every step in the order it ran, with how it went.
+    pub fn steps(&self) -> impl Iterator<Item = (&str, &StepResult)> {
+        self.steps
+            .iter()
+            .map(|(step, result, _)| (step.as_str(), result))
+    }
+
+    /// `true` when no step failed.
+    pub fn passed(&self) -> bool {
+        !self
+            .steps
+            .iter()
+            .any(|(_, result, _)| matches!(result, StepResult::Failed(_)))
+    }
+
+    pub fn print(&self) {
+        println!("{}", "Dry run report".cyan().bold());
+        for (step, result, millis) in &self.steps {
+            let (marker, detail) = match result {
+                StepResult::Passed(detail) => ("PASS".green(), detail),
+                StepResult::Skipped(detail) => ("SKIP".yellow(), detail),
+                StepResult::Failed(detail) => ("FAIL".red(), detail),
+            };
+            println!("  [{}] {:<16} {} ({}ms)", marker, step, detail, millis);
+        }
+
+        match self.passed() {
+            true => println!(
+                "{}",
+                "Pipeline is ready, run_command was not started".green()
+            ),
+            false => println!("{}", "Pipeline has failing steps".red()),
+        }
+    }
+}
+
+/// Deletes the scratch state file when the dry run ends, however it ends.
+struct ScratchState(PathType);
+
+impl Drop for ScratchState {
+    fn drop(&mut self) {
+        _ = self.0.delete();
+    }
+}
+
+/// Where a dry run of `app_name` keeps its state.
+pub fn scratch_path(app_name: &str) -> PathType {
+    PathType::PathBuf(std::env::temp_dir().join(format!(".{}_dry_run.state", app_name)))
+}
+
+fn check_path(report: &mut DryRunReport, name: &str, path: &str) -> bool {
+    let started = Instant::now();
+    let path = PathType::Content(path.to_owned());
+    match path.canonicalize() {
+        Ok(resolved) => {
+            report.record(
+                name,
+                started,
+                StepResult::Passed(resolved.display().to_string()),
+            );
+            true
+        }
+        Err(err) => {
+            report.record(
+                name,
+                started,
+                StepResult::Failed(format!("{}: {}", path, err)),
+            );
+            false
+        }
+    }
+}
+
+async fn check_steps(
+    report: &mut DryRunReport,
+    settings: &AppSpecificConfig,
+    state: &mut AppState,
+    state_path: &PathType,
+) {
+    let mut runs = Vec::new();
+    let result = run_build_steps(settings, state, state_path, &mut runs, None).await;
+    for step in &settings.steps {
+        let run = match runs.iter().find(|run| run.name == step.name) {
+            Some(run) => run,
+            None => {
+                report.record(
+                    &format!("step {}", step.name),
+                    Instant::now(),
+                    StepResult::Skipped(String::from("the build failed before it")),
+                );
+                continue;
+            }
+        };
+        let result = match (run.success, step.continue_on_failure) {
+            (true, _) => StepResult::Passed(step.command.clone()),
+            (false, true) => {
+                StepResult::Failed(String::from("failed, continued with the next step"))
+            }
+            (false, false) => StepResult::Failed(match &result {
+                Err(err) => err.to_string(),
+                Ok(()) => String::from("failed"),
+            }),
+        };
+        report.record_run(run, result);
+    }
+}
+
+async fn check_secrets(report: &mut DryRunReport, settings: &AppSpecificConfig) {
+    let started = Instant::now();
+    if settings.secret_server_addr == default_secret_server() {
+        report.record(
+            "secrets",
+            started,
+            StepResult::Skipped(String::from("no secret_server_addr configured")),
+        );
+        return;
+    }
+
+    let tls = settings.secret_tls.as_ref();
+    let client = match SecretClient::connect(&settings.secret_server_addr, tls).await {
+        Ok(client) => client,
+        Err(err) => {
+            report.record(
+                "secrets",
+                started,
+                StepResult::Failed(format!(
+                    "can't reach {}: {}",
+                    settings.secret_server_addr, err
+                )),
+            );
+            return;
+        }
+    };
+
+    let query = match get_query() {
+        Ok(query) => query,
+        Err(_) => {
+            report.record(
+                "secrets",
+                started,
+                StepResult::Failed(String::from("secret query wasn't initialized")),
+            );
+            return;
+        }
+    };
+
+    if let Err(err) = query
+        .get_required(client.clone(), &settings.required_secrets)
+        .await
+    {
+        report.record("secrets", started, StepResult::Failed(err.to_string()));
+        return;
+    }
+
+    let result = match query.get_all(client).await {
+        Ok(secrets) => StepResult::Passed(format!("{} secrets available", secrets.len())),
+        Err(err) => StepResult::Failed(err.to_string()),
+    };
+    report.record("secrets", started, result);
+}
+
+/// Run the pipeline up to, but not including, spawning the child.
+pub async fn execute() -> DryRunReport {
+    let mut report = DryRunReport::default();
+
+    let started = Instant::now();
+    let config: AppConfig = get_config();
+    let settings = match specific_config() {
+        Ok(settings) => {
+            report.record(
+                "config",
+                started,
+                StepResult::Passed(format!("{} loaded", config.app_name)),
+            );
+            settings
+        }
+        Err(err) => {
+            report.record("config", started, StepResult::Failed(err.to_string()));
+            return report;
+        }
+    };
+
+    rehearse(&mut report, &config, &settings).await;
+    report
+}
+
+/// Run the pipeline after the config step with `settings`, adding every
+/// step to `report`.
+pub async fn rehearse(report: &mut DryRunReport, config: &AppConfig, settings: &AppSpecificConfig) {
+    let started = Instant::now();
+    let problems = validation::validate(settings);
+    let result = match problems.len() {
+        0 => StepResult::Passed(String::from("no problems")),
+        _ => StepResult::Failed(validation::report(&problems)),
+    };
+    report.record("validation", started, result);
+
+    let started = Instant::now();
+    let missing = prerequisites::check(settings, std::env::var_os("PATH").as_deref());
+    let result = match missing.len() {
+        0 => StepResult::Passed(String::from("everything the commands need is there")),
+        _ => StepResult::Failed(prerequisites::report(&missing)),
+    };
+    report.record("prerequisites", started, result);
+
+    let mut monitor_ok = true;
+    for watch in settings.watch_paths() {
+        monitor_ok &= check_path(report, "monitor_path", &watch.path);
+    }
+    let project_ok = check_path(report, "project_path", &settings.project_path);
+
+    // Scratch state so we never touch the file of a live instance
+    let state_path = scratch_path(&config.app_name.to_string());
+    let _scratch = ScratchState(state_path.clone());
+    let mut state: AppState = generate_application_state(&state_path, config).await;
+
+    check_secrets(report, settings).await;
+
+    if !(monitor_ok && project_ok) {
+        report.record(
+            "install",
+            Instant::now(),
+            StepResult::Skipped(String::from("project path is unavailable")),
+        );
+        return;
+    }
+
+    if !settings.steps.is_empty() {
+        check_steps(report, settings, &mut state, &state_path).await;
+        report.record(
+            "run_command",
+            Instant::now(),
+            StepResult::Skipped(format!("would spawn: {}", settings.run_command)),
+        );
+        return;
+    }
+
+    let started = Instant::now();
+    let result = match &settings.install_command {
+        None => StepResult::Skipped(String::from("no install_command")),
+        Some(command) => match run_install_process(settings, &mut state, &state_path).await {
+            Ok(_) => StepResult::Passed(command.clone()),
+            Err(err) => StepResult::Failed(err.to_string()),
+        },
+    };
+    report.record("install", started, result);
+
+    let started = Instant::now();
+    let build_command = match &settings.build_command {
+        Some(command) => Some(command.clone()),
+        None if settings.has_build_step() => Some(String::from("nix-build")),
+        None => None,
+    };
+    let result = match build_command {
+        None => StepResult::Skipped(String::from("no build_command")),
+        Some(command) => {
+            match run_one_shot_process(settings, &mut state, &state_path, None).await {
+                Ok(_) => StepResult::Passed(command),
+                Err(err) => {
+                    for (_, line) in state.stderr.iter().rev().take(10).rev() {
+                        eprintln!("  {}", line);
+                    }
+                    StepResult::Failed(err.to_string())
+                }
+            }
+        }
+    };
+    report.record("build", started, result);
+
+    report.record(
+        "run_command",
+        Instant::now(),
+        StepResult::Skipped(format!("would spawn: {}", settings.run_command)),
+    );
+}
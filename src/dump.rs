@@ -0,0 +1,207 @@
+//! On-demand dumps of what the runner is doing.
+//!
+//! A runner that seems stuck, one that doesn't deploy or never gets the
+//! child ready, can be asked what it's up to without touching the child:
+//! `ais_runner dump` sends it `SIGURG` and it writes
+//!
+//! - its status and the data that came with it,
+//! - its pid, the child's and whether that one is still alive,
+//! - how long it has been up,
+//! - how long ago the main loop last went round, a busy build or a hung
+//!   ready check keeps it from doing so,
+//! - the file changes counted towards the next deploy,
+//! - the directory monitors and whether they're paused,
+//! - the last [`LINES`] lines of the child's output,
+//!
+//! to `<state file>.dump` and the log. The dump is taken by a task of its
+//! own, so it's answered while the main loop is busy; status and output
+//! are read from the state file, as the runner last wrote them.
+
+use artisan_middleware::dusa_collection_utils::core::{
+    logger::LogLevel, types::pathtype::PathType,
+};
+use artisan_middleware::state_persistence::{AppState, StatePersistence};
+use artisan_middleware::timestamp::current_timestamp;
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    sync::{Arc, atomic::Ordering},
+    time::{Duration, Instant},
+};
+use tokio::sync::Notify;
+
+use crate::cli::format_seconds;
+use crate::global_child::{
+    GLOBAL_CHILD_PID, GLOBAL_LOOP_ROUND, GLOBAL_MONITORS, GLOBAL_MONITORS_PAUSED,
+    GLOBAL_PENDING_CHANGES,
+};
+use crate::log;
+use crate::output::merged_tail;
+use crate::shutdown::{self, Stage};
+use crate::signals;
+
+/// Lines of the child's output in a dump.
+pub const LINES: usize = 50;
+
+/// Where the dumps of the state file at `state_path` are written.
+pub fn path(state_path: &PathType) -> PathBuf {
+    PathBuf::from(format!("{}.dump", state_path))
+}
+
+/// Directory monitors as a dump shows them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Monitors {
+    /// `None` while they're being replaced.
+    pub count: Option<usize>,
+    pub paused: bool,
+}
+
+/// What the runner was doing when asked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dump {
+    pub timestamp: u64,
+    pub status: String,
+    pub data: String,
+    pub runner_pid: u32,
+    pub child_pid: Option<u32>,
+    pub child_alive: bool,
+    pub uptime: Duration,
+    /// Since the main loop last went round.
+    pub main_loop: Option<Duration>,
+    pub pending_changes: u32,
+    pub monitors: Monitors,
+    pub lines: Vec<String>,
+}
+
+impl Dump {
+    /// A dump of `state`, for a runner up for `uptime`.
+    pub fn of(state: &AppState, uptime: Duration, now: u64) -> Self {
+        let child_pid = match GLOBAL_CHILD_PID.load(Ordering::Relaxed) {
+            0 => None,
+            pid => Some(pid),
+        };
+        let main_loop = match GLOBAL_LOOP_ROUND.load(Ordering::Relaxed) {
+            0 => None,
+            round => Some(Duration::from_secs(now.saturating_sub(round))),
+        };
+        Self {
+            timestamp: now,
+            status: state.status.to_string(),
+            data: state.data.clone(),
+            runner_pid: std::process::id(),
+            child_pid,
+            child_alive: child_pid.is_some_and(signals::is_alive),
+            uptime,
+            main_loop,
+            pending_changes: GLOBAL_PENDING_CHANGES.load(Ordering::Relaxed),
+            monitors: Monitors {
+                count: GLOBAL_MONITORS
+                    .try_lock()
+                    .ok()
+                    .map(|monitors| monitors.len()),
+                paused: GLOBAL_MONITORS_PAUSED.load(Ordering::Relaxed),
+            },
+            lines: merged_tail(&state.stdout, &state.stderr, LINES)
+                .iter()
+                .map(|line| format!("{} {} {}", line.timestamp, line.stream, line.line))
+                .collect(),
+        }
+    }
+
+    /// The dump as it's written and logged.
+    pub fn render(&self) -> String {
+        let child = match (self.child_pid, self.child_alive) {
+            (Some(pid), true) => format!("{}", pid),
+            (Some(pid), false) => format!("{} (gone)", pid),
+            (None, _) => String::from("none"),
+        };
+        let main_loop = match self.main_loop {
+            Some(since) => format!("last went round {} ago", format_seconds(since.as_secs())),
+            None => String::from("not started"),
+        };
+        let monitors = match self.monitors.count {
+            Some(count) => format!("{} watching", count),
+            None => String::from("being replaced"),
+        };
+        let paused = match self.monitors.paused {
+            true => ", paused",
+            false => "",
+        };
+
+        let mut text = format!(
+            "Dump at {}\n\
+             status: {}\n\
+             data: {}\n\
+             runner pid: {}\n\
+             child pid: {}\n\
+             uptime: {}\n\
+             main loop: {}\n\
+             pending changes: {}\n\
+             monitors: {}{}\n\
+             last {} lines:\n",
+            self.timestamp,
+            self.status,
+            self.data,
+            self.runner_pid,
+            child,
+            format_seconds(self.uptime.as_secs()),
+            main_loop,
+            self.pending_changes,
+            monitors,
+            paused,
+            self.lines.len(),
+        );
+        for line in &self.lines {
+            text.push_str("  ");
+            text.push_str(line);
+            text.push('\n');
+        }
+        text
+    }
+}
+
+/// Write `text` to `path`, readable by the runner's user only since it
+/// holds the child's output.
+pub fn write(path: &Path, text: &str) -> io::Result<()> {
+    let temp = path.with_extension("dump.tmp");
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&temp)?;
+    file.write_all(text.as_bytes())?;
+    fs::rename(&temp, path)
+}
+
+/// Take a dump of the runner whose state is at `state_path` and write it.
+pub async fn take(state_path: &PathType, started: Instant) -> Result<PathBuf, String> {
+    let state = StatePersistence::load_state(state_path)
+        .await
+        .map_err(|err| format!("Failed to load state from {}: {}", state_path, err))?;
+    let dump = Dump::of(&state, started.elapsed(), current_timestamp());
+    let text = dump.render();
+    for line in text.lines() {
+        log!(LogLevel::Info, "{}", line);
+    }
+    let path = path(state_path);
+    write(&path, &text).map_err(|err| format!("Failed to write {}: {}", path.display(), err))?;
+    Ok(path)
+}
+
+/// Take a dump whenever `requests` is notified, until the runner shuts
+/// down.
+pub fn watch(requests: Arc<Notify>, state_path: PathType) {
+    let started = Instant::now();
+    shutdown::spawn(Stage::Health, async move {
+        loop {
+            requests.notified().await;
+            match take(&state_path, started).await {
+                Ok(path) => log!(LogLevel::Info, "Dump written to {}", path.display()),
+                Err(err) => log!(LogLevel::Warn, "{}", err),
+            }
+        }
+    });
+}
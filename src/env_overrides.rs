@@ -0,0 +1,158 @@
+//! Environment variables overriding `[app_specific]` options.
+//!
+//! The same artifact deployed to several environments can keep a single
+//! `Config.toml` and set what differs in the environment instead. Every
+//! option has a variable, `AIS_` and its key in upper case, with `__`
+//! between the keys of nested tables:
+//!
+//! ```sh
+//! AIS_RUN_COMMAND="./server --port 8080"
+//! AIS_CHANGES_NEEDED=10
+//! AIS_CGROUP__MEMORY_MAX_MB=512
+//! AIS_IGNORED_SUBDIRS=node_modules,dist
+//! ```
+//!
+//! Variables win over `Config.toml`. Lists take a TOML array or comma
+//! separated values, tables an inline TOML table that is merged into the
+//! one from the file. Anything else is taken as it is and converted the way
+//! a string in `Config.toml` would be. A variable that doesn't name an
+//! option is rejected, like an unknown key in the file.
+
+use config::{ConfigBuilder, ConfigError, Map, builder::DefaultState};
+use serde_json::Value;
+
+use crate::config_schema::{Kind, lookup, unknown_keys};
+
+/// What the variables start with.
+pub const PREFIX: &str = "AIS_";
+
+/// An option set by a variable.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnvOverride {
+    /// The variable, e.g. `AIS_RUN_COMMAND`.
+    pub var: String,
+    /// The option below `[app_specific]`, e.g. `run_command`.
+    pub key: String,
+    pub value: toml::Value,
+}
+
+/// The option `var` overrides, `None` for variables without the prefix.
+pub fn key(var: &str) -> Option<String> {
+    match var.strip_prefix(PREFIX) {
+        Some(name) if !name.is_empty() => Some(name.to_lowercase().replace("__", ".")),
+        _ => None,
+    }
+}
+
+/// `raw` as an inline TOML value.
+fn inline(raw: &str) -> Option<toml::Value> {
+    toml::from_str::<toml::Table>(&format!("value = {}", raw))
+        .ok()?
+        .remove("value")
+}
+
+/// `raw` as a value for an option of `kind`.
+fn parse(kind: &Kind, raw: &str) -> Result<toml::Value, String> {
+    match kind {
+        Kind::Optional(inner) => parse(inner, raw),
+        Kind::List(inner) => match inline(raw) {
+            Some(array @ toml::Value::Array(_)) => Ok(array),
+            _ if matches!(**inner, Kind::Table(_)) => Err(String::from(
+                "expected a TOML array like [{ key = \"value\" }]",
+            )),
+            _ => Ok(toml::Value::Array(
+                raw.split(',')
+                    .map(str::trim)
+                    .filter(|item| !item.is_empty())
+                    .map(|item| toml::Value::String(item.to_owned()))
+                    .collect(),
+            )),
+        },
+        Kind::Table(_) | Kind::Map(_) => match inline(raw) {
+            Some(table @ toml::Value::Table(_)) => Ok(table),
+            _ => Err(String::from(
+                "expected an inline TOML table like { key = \"value\" }",
+            )),
+        },
+        Kind::Any => Ok(inline(raw).unwrap_or_else(|| toml::Value::String(raw.to_owned()))),
+        _ => Ok(toml::Value::String(raw.to_owned())),
+    }
+}
+
+/// The overrides among `vars`, in the order of their names, or what's
+/// wrong with them.
+pub fn collect(
+    schema: &Kind,
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Result<Vec<EnvOverride>, Vec<String>> {
+    let mut vars: Vec<(String, String)> = vars
+        .into_iter()
+        .filter(|(var, _)| key(var).is_some())
+        .collect();
+    vars.sort();
+
+    let mut overrides = Vec::new();
+    let mut problems = Vec::new();
+    for (var, raw) in vars {
+        let key = key(&var).unwrap_or_default();
+        let kind = match lookup(schema, &key) {
+            Some(kind) => kind,
+            None => {
+                // A table with just this key, for the suggestion
+                let value = key.rsplit('.').fold(Value::Null, |value, name| {
+                    Value::Object([(name.to_owned(), value)].into_iter().collect())
+                });
+                for problem in unknown_keys(schema, &value, "app_specific") {
+                    problems.push(format!("{}: {}", var, problem));
+                }
+                continue;
+            }
+        };
+        match parse(kind, &raw) {
+            Ok(value) => overrides.push(EnvOverride { var, key, value }),
+            Err(err) => problems.push(format!("{}: {}", var, err)),
+        }
+    }
+
+    match problems.is_empty() {
+        true => Ok(overrides),
+        false => Err(problems),
+    }
+}
+
+/// The overrides set in the runner's environment.
+pub fn from_env(schema: &Kind) -> Result<Vec<EnvOverride>, Vec<String>> {
+    collect(
+        schema,
+        std::env::vars_os()
+            .filter_map(|(var, value)| Some((var.into_string().ok()?, value.into_string().ok()?))),
+    )
+}
+
+fn to_config(value: &toml::Value) -> config::Value {
+    match value {
+        toml::Value::String(text) => text.clone().into(),
+        toml::Value::Integer(number) => (*number).into(),
+        toml::Value::Float(number) => (*number).into(),
+        toml::Value::Boolean(flag) => (*flag).into(),
+        toml::Value::Datetime(datetime) => datetime.to_string().into(),
+        toml::Value::Array(items) => items.iter().map(to_config).collect::<Vec<_>>().into(),
+        toml::Value::Table(table) => table
+            .iter()
+            .map(|(key, value)| (key.clone(), to_config(value)))
+            .collect::<Map<String, config::Value>>()
+            .into(),
+    }
+}
+
+/// Layer `overrides` over the sources of `builder`.
+pub fn apply(
+    mut builder: ConfigBuilder<DefaultState>,
+    overrides: &[EnvOverride],
+) -> Result<ConfigBuilder<DefaultState>, ConfigError> {
+    for item in overrides {
+        builder =
+            builder.set_override(format!("app_specific.{}", item.key), to_config(&item.value))?;
+    }
+    Ok(builder)
+}
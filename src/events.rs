@@ -0,0 +1,248 @@
+//! Append-only journal of what the supervisor did.
+//!
+//! The log says what happened, but it's at whatever level was configured
+//! and often long gone by the time an incident is looked into. With
+//! `[app_specific.events] enabled = true` every significant action is
+//! appended to `<state file>.events` as one JSON object per line:
+//!
+//! ```json
+//! {"timestamp":1760000000,"event":"build_finished","step":"build","success":true,"duration_ms":5120}
+//! {"timestamp":1760000001,"event":"child_spawned","pid":4242}
+//! ```
+//!
+//! Recorded are the batches of file changes that start a deploy, restarts,
+//! builds starting and finishing, children being spawned, adopted, killed
+//! and exiting, control signals and config reloads. The journal is rotated to
+//! `<path>.1` once it reaches `max_size_mb`, older files move up to
+//! `<path>.<keep>` and the oldest is dropped.
+
+use artisan_middleware::dusa_collection_utils::core::{
+    functions::current_timestamp, logger::LogLevel, types::pathtype::PathType,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::global_child::GLOBAL_EVENTS;
+use crate::log;
+
+/// `[app_specific.events]`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct EventsConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Defaults to `<state file>.events`.
+    #[serde(default)]
+    pub path: Option<String>,
+    #[serde(default = "default_events_size")]
+    pub max_size_mb: u64,
+    /// Rotated files kept besides the current one.
+    #[serde(default = "default_keep")]
+    pub keep: u32,
+}
+
+impl Default for EventsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: None,
+            max_size_mb: default_events_size(),
+            keep: default_keep(),
+        }
+    }
+}
+
+fn default_events_size() -> u64 {
+    10
+}
+
+fn default_keep() -> u32 {
+    5
+}
+
+impl EventsConfig {
+    /// Where the journal of the runner with its state at `state_path` is
+    /// written.
+    pub fn path(&self, state_path: &PathType) -> PathBuf {
+        match &self.path {
+            Some(path) => PathBuf::from(path),
+            None => PathBuf::from(format!("{}.events", state_path)),
+        }
+    }
+}
+
+/// Something the supervisor did.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A batch of file changes is deployed.
+    Changes {
+        count: u32,
+        detail: String,
+    },
+    /// The child is replaced.
+    Restart {
+        reason: String,
+    },
+    /// `step` is `install` or `build`.
+    BuildStarted {
+        step: String,
+    },
+    BuildFinished {
+        step: String,
+        success: bool,
+        duration_ms: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    ChildSpawned {
+        pid: u32,
+    },
+    /// A child a previous run left running is supervised in place of a new
+    /// one.
+    ChildAdopted {
+        pid: u32,
+    },
+    ChildKilled {
+        pid: Option<u32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    /// The child exited on its own, `exit` as in `exited with 1`.
+    ChildExited {
+        exit: String,
+    },
+    /// `signal` was received and `action` taken on it.
+    Signal {
+        signal: String,
+        action: String,
+    },
+    /// `Config.toml` was reloaded: `live` options took effect at once,
+    /// `child` ones with the next child and `runner` ones need a restart of
+    /// the runner.
+    ConfigReload {
+        live: Vec<String>,
+        child: Vec<String>,
+        runner: Vec<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+}
+
+/// A single journal record.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct EventEntry {
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+/// Open handle to the event journal.
+#[derive(Debug)]
+pub struct EventJournal {
+    path: PathBuf,
+    file: File,
+    max_bytes: u64,
+    keep: u32,
+    written: u64,
+}
+
+impl EventJournal {
+    /// Open (or create) the journal, appending to whatever a previous run
+    /// left.
+    pub fn open(path: &Path, max_size_mb: u64, keep: u32) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            max_bytes: max_size_mb.saturating_mul(1024 * 1024),
+            keep,
+            written,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn append(&mut self, entry: &EventEntry) -> io::Result<()> {
+        let mut record = serde_json::to_vec(entry)?;
+        record.push(b'\n');
+
+        if self.max_bytes > 0 && self.written + record.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        // One write per record so a crash can't leave half a line behind
+        self.file.write_all(&record)?;
+        self.written += record.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        for generation in (1..self.keep).rev() {
+            let older = rotated(&self.path, generation);
+            if older.exists() {
+                fs::rename(&older, rotated(&self.path, generation + 1))?;
+            }
+        }
+        match self.keep {
+            0 => fs::remove_file(&self.path)?,
+            _ => fs::rename(&self.path, rotated(&self.path, 1))?,
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+/// `<path>.<generation>`
+pub fn rotated(path: &Path, generation: u32) -> PathBuf {
+    let mut rotated = OsString::from(path.as_os_str());
+    rotated.push(format!(".{}", generation));
+    PathBuf::from(rotated)
+}
+
+/// The entries in the journal at `path`, oldest first, skipping lines that
+/// don't parse.
+pub fn read(path: &Path) -> io::Result<Vec<EventEntry>> {
+    let file = File::open(path)?;
+    let mut entries = Vec::new();
+    for line in BufReader::new(file).lines() {
+        if let Ok(entry) = serde_json::from_str(&line?) {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+/// Append `event` to the global journal, if one is open.
+pub fn record(event: Event) {
+    let journal = match GLOBAL_EVENTS.get() {
+        Some(journal) => journal,
+        None => return,
+    };
+    let mut journal = match journal.lock() {
+        Ok(journal) => journal,
+        Err(_) => return,
+    };
+    let entry = EventEntry {
+        timestamp: current_timestamp(),
+        event,
+    };
+    if let Err(err) = journal.append(&entry) {
+        log!(
+            LogLevel::Warn,
+            "Failed to append to event journal {}: {}",
+            journal.path().display(),
+            err
+        );
+    }
+}
@@ -1,28 +1,145 @@
 //! Global handles to the running child process and directory monitor.
 //!
 //! These are wrapped in [`Arc`] and [`Mutex`] so that various tasks in the
-//! application can access the latest child or monitor instance.
+//! application can access the latest monitor instance. The child itself is
+//! only reached through its [`ChildManager`].
 
-use artisan_middleware::process_manager::SupervisedChild;
 use dir_watcher::RawFileMonitor;
 use once_cell::sync::{Lazy, OnceCell};
-use std::sync::Arc;
-use tokio::sync::Mutex;
+#[cfg(feature = "otel")]
+use opentelemetry_sdk::trace::Tracer;
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Mutex as StdMutex,
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+    },
+};
+use tokio::{
+    sync::{Mutex, watch},
+    task::JoinHandle,
+};
+use tokio_rustls::rustls::ServerConfig;
 
+use crate::artifacts::Artifacts;
+use crate::cgroup::ChildCgroup;
+use crate::child::ChildLaunch;
+use crate::child_manager::ChildManager;
+use crate::events::EventJournal;
+use crate::heartbeat::Heartbeat;
+use crate::journal::OutputJournal;
+use crate::listen_fds::ListenSockets;
+use crate::log_shipping::LogSender;
+use crate::notifications::Notifier;
+use crate::orphans::AdoptedChild;
+use crate::proxy::Activity;
 use crate::secrets::{SecretClient, SecretQuery};
+use crate::shutdown::Stage;
+use crate::state_backend::StatePublisher;
+use crate::stdin::StdinPipe;
 
-/// Globally available reference to the current [`SupervisedChild`].
-/// It is wrapped in an [`Arc`] and [`Mutex`] so it can be safely
-/// shared and modified across threads.
-pub static GLOBAL_CHILD: Lazy<Arc<Mutex<Option<SupervisedChild>>>> =
+/// The task owning the current child, see [`crate::child_manager`].
+pub static GLOBAL_CHILD: OnceCell<ChildManager> = OnceCell::new();
+
+/// Pid of the current child, `0` before the first spawn. Readable from
+/// signal handler threads without locking [`GLOBAL_CHILD`].
+pub static GLOBAL_CHILD_PID: AtomicU32 = AtomicU32::new(0);
+
+/// The child of a previous run while it's supervised in place of our own,
+/// only set when `orphans.action` is `adopt` and one was found.
+pub static GLOBAL_ADOPTED: Lazy<Arc<Mutex<Option<AdoptedChild>>>> =
+    Lazy::new(|| Arc::new(Mutex::new(None)));
+
+/// How the current child was spawned, promoted to the last known good
+/// launch once it became ready.
+pub static GLOBAL_LAUNCH: Lazy<Arc<Mutex<Option<ChildLaunch>>>> =
     Lazy::new(|| Arc::new(Mutex::new(None)));
 
-/// Globally available reference to the current [`RawFileMonitor`].
-/// It is wrapped in an [`Arc`] and [`Mutex`] so it can be safely
+/// Globally available references to the current [`RawFileMonitor`]s, one
+/// per watched directory.
+/// They are wrapped in an [`Arc`] and [`Mutex`] so they can be safely
 /// shared and modified across threads.
-pub static GLOBAL_MONITOR: Lazy<Arc<Mutex<Option<RawFileMonitor>>>> =
+pub static GLOBAL_MONITORS: Lazy<Arc<Mutex<Vec<RawFileMonitor>>>> =
+    Lazy::new(|| Arc::new(Mutex::new(Vec::new())));
+
+/// Whether the directory monitors are paused, for dumps.
+pub static GLOBAL_MONITORS_PAUSED: AtomicBool = AtomicBool::new(false);
+
+/// File changes counted towards the next deploy, for dumps.
+pub static GLOBAL_PENDING_CHANGES: AtomicU32 = AtomicU32::new(0);
+
+/// When the main loop last went round, `0` before it started.
+pub static GLOBAL_LOOP_ROUND: AtomicU64 = AtomicU64::new(0);
+
+/// Globally available reference to the cgroup the child is placed in, only
+/// set when cgroup enforcement is enabled and could be set up.
+pub static GLOBAL_CGROUP: Lazy<Arc<Mutex<Option<ChildCgroup>>>> =
+    Lazy::new(|| Arc::new(Mutex::new(None)));
+
+/// Globally available reference to the output journal, only set when the
+/// journal is enabled and could be opened.
+pub static GLOBAL_JOURNAL: Lazy<Arc<Mutex<Option<OutputJournal>>>> =
+    Lazy::new(|| Arc::new(Mutex::new(None)));
+
+/// Pending ACME HTTP-01 challenges, token to key authorization, answered by
+/// the built-in static server.
+pub static GLOBAL_ACME_CHALLENGES: Lazy<Arc<Mutex<HashMap<String, String>>>> =
+    Lazy::new(|| Arc::new(Mutex::new(HashMap::new())));
+
+/// TLS config of the built-in HTTPS listener, swapped whenever the ACME
+/// certificate is renewed.
+pub static GLOBAL_TLS_CONFIG: Lazy<Arc<Mutex<Option<Arc<ServerConfig>>>>> =
     Lazy::new(|| Arc::new(Mutex::new(None)));
 
+/// Socket the child reports heartbeats and status on, only set when
+/// `heartbeat` is enabled and the socket could be bound.
+pub static GLOBAL_HEARTBEAT: OnceCell<Heartbeat> = OnceCell::new();
+
+/// Pipe the child's stdin is fed from, only set when `stdin_pipe` is
+/// enabled and its socket could be bound.
+pub static GLOBAL_STDIN: OnceCell<StdinPipe> = OnceCell::new();
+
+/// Traffic passed on to the child, only set when `proxy.listen` is
+/// configured and could be bound.
+pub static GLOBAL_PROXY: OnceCell<Arc<Activity>> = OnceCell::new();
+
+/// Journal of supervisor actions, only set when `events` is enabled and it
+/// could be opened.
+pub static GLOBAL_EVENTS: OnceCell<StdMutex<EventJournal>> = OnceCell::new();
+
+/// Sockets passed to every child, only set when `listen_fds` is enabled and
+/// they could be bound.
+pub static GLOBAL_LISTEN_FDS: OnceCell<ListenSockets> = OnceCell::new();
+
+/// Publishes states to the state backend, only set when `state_backend`
+/// isn't `file`.
+pub static GLOBAL_STATE_BACKEND: OnceCell<StatePublisher> = OnceCell::new();
+
+/// Files created by this run, removed again on a graceful shutdown.
+pub static GLOBAL_ARTIFACTS: OnceCell<Artifacts> = OnceCell::new();
+
+/// Notifier for outgoing events, only set when webhooks are configured.
+pub static GLOBAL_NOTIFIER: OnceCell<Arc<Notifier>> = OnceCell::new();
+
+/// Hands captured lines to the log shipper, only set when `log_shipping`
+/// is configured.
+pub static GLOBAL_LOG_SHIPPER: OnceCell<LogSender> = OnceCell::new();
+
+/// Exports restart traces, only set when `otel_endpoint` is configured.
+#[cfg(feature = "otel")]
+pub static GLOBAL_TRACER: OnceCell<Tracer> = OnceCell::new();
+
+/// The stage the shutdown reached, `None` until the runner exits.
+pub static GLOBAL_SHUTDOWN: Lazy<watch::Sender<Option<Stage>>> =
+    Lazy::new(|| watch::channel(None).0);
+
+/// A background task and the stage of the shutdown that waits for it.
+pub type Subsystem = (Stage, JoinHandle<()>);
+
+/// Background tasks the stages of the shutdown wait for.
+pub static GLOBAL_SUBSYSTEMS: Lazy<StdMutex<Vec<Subsystem>>> =
+    Lazy::new(|| StdMutex::new(Vec::new()));
+
 /// Globally available refrence to the current [`SecretQuery`].
 pub static GLOBAL_SECRET_QUERY: OnceCell<SecretQuery> = OnceCell::new();
 
@@ -30,25 +147,36 @@ pub static GLOBAL_SECRET_QUERY: OnceCell<SecretQuery> = OnceCell::new();
 pub static GLOBAL_CLINENT_CONNECTION: Lazy<Arc<Mutex<Option<SecretClient>>>> =
     Lazy::new(|| Arc::new(Mutex::const_new(None)));
 
-/// Initialize the global child value. This is typically called once
-/// at start up after the first child is spawned.
-pub async fn init_child(child: SupervisedChild) {
-    let mut lock = GLOBAL_CHILD.lock().await;
-    *lock = Some(child);
+/// Initialize the global monitors. This is typically called once
+/// at start up after the monitors were started.
+pub async fn init_monitors(monitors: Vec<RawFileMonitor>) {
+    let mut lock = GLOBAL_MONITORS.lock().await;
+    *lock = monitors;
+}
+
+/// Pause every directory monitor.
+pub async fn pause_monitors() {
+    for monitor in GLOBAL_MONITORS.lock().await.iter() {
+        monitor.pause();
+    }
+    GLOBAL_MONITORS_PAUSED.store(true, Ordering::Relaxed);
 }
 
-/// Replace the currently stored child with a new one. This allows
-/// other threads to always access the latest child handle.
-pub async fn replace_child(child: SupervisedChild) {
-    let mut lock = GLOBAL_CHILD.lock().await;
-    *lock = Some(child);
+/// Stop every directory monitor for good.
+pub async fn stop_monitors() {
+    let monitors = std::mem::take(&mut *GLOBAL_MONITORS.lock().await);
+    for monitor in &monitors {
+        monitor.pause();
+    }
+    GLOBAL_MONITORS_PAUSED.store(true, Ordering::Relaxed);
 }
 
-/// Initialize the global child value. This is typically called once
-/// at start up after the first child is spawned.
-pub async fn init_monitor(monitor: RawFileMonitor) {
-    let mut lock = GLOBAL_MONITOR.lock().await;
-    *lock = Some(monitor);
+/// Resume every directory monitor.
+pub async fn resume_monitors() {
+    for monitor in GLOBAL_MONITORS.lock().await.iter() {
+        monitor.resume();
+    }
+    GLOBAL_MONITORS_PAUSED.store(false, Ordering::Relaxed);
 }
 
 pub fn get_query() -> Result<SecretQuery, ()> {
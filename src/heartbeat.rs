@@ -0,0 +1,203 @@
+//! Heartbeats and status reports from the child.
+//!
+//! Probing from the outside only tells whether a port answers. With
+//! `[app_specific.heartbeat]` enabled the runner binds a datagram socket and
+//! hands it to the child as `NOTIFY_SOCKET`, speaking the `sd_notify`
+//! protocol: newline separated `KEY=VALUE` pairs per datagram. The child can
+//! report
+//!
+//! - `READY=1` once it accepts work (see `ready_check.notify`),
+//! - `WATCHDOG=1` periodically, a child that stays silent for longer than
+//!   `timeout_seconds` is restarted,
+//! - `STATUS=<text>` which is shown as the state's data,
+//! - `APP_<key>=<value>` status fields, see [`crate::app_status`].
+//!
+//! Existing `sd_notify` client libraries work unchanged, `WATCHDOG_USEC` is
+//! set when a timeout is configured so they know how often to ping.
+
+use artisan_middleware::dusa_collection_utils;
+use dusa_collection_utils::core::{logger::LogLevel, types::pathtype::PathType};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    os::unix::fs::{PermissionsExt, chown},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::net::UnixDatagram;
+
+use crate::shutdown::{self, Stage};
+use crate::{app_status, log};
+
+/// Largest datagram read, `sd_notify` messages are tiny.
+const MAX_MESSAGE: usize = 4096;
+/// Prefix of keys carrying status fields.
+const FIELD_PREFIX: &str = "APP_";
+
+/// `[app_specific.heartbeat]`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct HeartbeatConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Restart the child when no `WATCHDOG=1` arrived for this long, counted
+    /// from the spawn. Heartbeats are optional when unset.
+    #[serde(default)]
+    pub timeout_seconds: Option<u64>,
+}
+
+/// Socket the child reports to, next to the state file.
+pub fn socket_path(state_path: &PathType) -> PathBuf {
+    PathBuf::from(format!("{}.notify", state_path))
+}
+
+/// What the current child reported so far.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChildReport {
+    pub ready: bool,
+    pub spawned_at: Option<Instant>,
+    pub last_beat: Option<Instant>,
+    /// Latest `STATUS=`.
+    pub status: Option<String>,
+    /// `APP_<key>=` fields by key.
+    pub fields: BTreeMap<String, String>,
+}
+
+impl ChildReport {
+    /// Start over for a child spawned at `now`.
+    pub fn reset(&mut self, now: Instant) {
+        *self = Self {
+            spawned_at: Some(now),
+            ..Self::default()
+        };
+    }
+
+    /// Apply one datagram received at `now`, unknown keys are ignored.
+    pub fn apply(&mut self, message: &str, now: Instant) {
+        for line in message.lines() {
+            match line.split_once('=') {
+                Some(("READY", "1")) => self.ready = true,
+                Some(("WATCHDOG", "1")) => self.last_beat = Some(now),
+                Some(("STATUS", status)) => self.status = Some(status.to_owned()),
+                Some((key, value)) => {
+                    if let Some(key) = key.strip_prefix(FIELD_PREFIX) {
+                        app_status::set(&mut self.fields, key, value);
+                    }
+                }
+                None => (),
+            }
+        }
+    }
+
+    /// How long the child has been silent at `now`, if that's longer than
+    /// `timeout`.
+    pub fn overdue(&self, timeout: Duration, now: Instant) -> Option<Duration> {
+        let since = self.last_beat.or(self.spawned_at)?;
+        let silent = now.saturating_duration_since(since);
+        match silent > timeout {
+            true => Some(silent),
+            false => None,
+        }
+    }
+}
+
+/// The listening socket and what arrived on it.
+#[derive(Debug, Clone)]
+pub struct Heartbeat {
+    path: PathBuf,
+    timeout: Option<Duration>,
+    report: Arc<Mutex<ChildReport>>,
+}
+
+impl Heartbeat {
+    /// Bind `path` and start collecting reports.
+    ///
+    /// `owner` is the uid and gid the child runs as, the socket is handed to
+    /// them so a child with dropped privileges can still write to it.
+    pub fn listen(
+        path: &Path,
+        timeout: Option<Duration>,
+        owner: Option<(u32, u32)>,
+    ) -> io::Result<Self> {
+        // Left behind by a previous run
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        let socket = UnixDatagram::bind(path)?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o620))?;
+        if let Some((uid, gid)) = owner {
+            chown(path, Some(uid), Some(gid))?;
+        }
+
+        let heartbeat = Self {
+            path: path.to_path_buf(),
+            timeout,
+            report: Arc::new(Mutex::new(ChildReport::default())),
+        };
+
+        let report = heartbeat.report.clone();
+        shutdown::spawn(Stage::Health, async move {
+            let mut buffer = vec![0u8; MAX_MESSAGE];
+            loop {
+                let read = match socket.recv(&mut buffer).await {
+                    Ok(read) => read,
+                    Err(err) => {
+                        log!(LogLevel::Warn, "Heartbeat socket failed: {}", err);
+                        return;
+                    }
+                };
+                let message = String::from_utf8_lossy(&buffer[..read]);
+                log!(LogLevel::Trace, "Child notification: {:?}", message);
+                if let Ok(mut report) = report.lock() {
+                    report.apply(&message, Instant::now());
+                }
+            }
+        });
+
+        Ok(heartbeat)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The configured `timeout_seconds`.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Forget what the previous child reported, called on every spawn.
+    pub fn reset(&self) {
+        if let Ok(mut report) = self.report.lock() {
+            report.reset(Instant::now());
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.report.lock().is_ok_and(|report| report.ready)
+    }
+
+    /// Latest status of the current child.
+    pub fn status(&self) -> Option<String> {
+        self.report.lock().ok()?.status.clone()
+    }
+
+    /// Status fields of the current child.
+    pub fn fields(&self) -> BTreeMap<String, String> {
+        self.report
+            .lock()
+            .map(|report| report.fields.clone())
+            .unwrap_or_default()
+    }
+
+    /// Why the child should be restarted, `None` while it keeps beating.
+    pub fn overdue(&self) -> Option<String> {
+        let timeout = self.timeout?;
+        let silent = self.report.lock().ok()?.overdue(timeout, Instant::now())?;
+        Some(format!(
+            "No heartbeat from the child for {}s",
+            silent.as_secs()
+        ))
+    }
+}
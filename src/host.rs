@@ -0,0 +1,120 @@
+//! Facts about the host the runner is deployed on.
+//!
+//! [`HostCapabilities::detect`] runs once at start up. Subsystems that depend
+//! on a kernel feature check [`capabilities`] and fall back (with a warning)
+//! instead of failing on older kernels.
+
+use nix::sys::utsname::uname;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+use crate::cgroup::cgroup_v2_available;
+use crate::config::AppSpecificConfig;
+
+/// Below this many inotify watches recursive monitoring of a typical
+/// `node_modules` style tree starts failing.
+const LOW_INOTIFY_WATCHES: u64 = 65536;
+
+static CAPABILITIES: OnceCell<HostCapabilities> = OnceCell::new();
+
+/// Machine hardware name as reported by `uname -m`, e.g. `x86_64`,
+/// `aarch64` or `armv7l`.
+///
+/// Falls back to the architecture the runner was compiled for if `uname`
+/// fails.
+pub fn host_arch() -> String {
+    match uname() {
+        Ok(info) => info.machine().to_string_lossy().into_owned(),
+        Err(_) => std::env::consts::ARCH.to_owned(),
+    }
+}
+
+/// Kernel features the runner cares about.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct HostCapabilities {
+    pub arch: String,
+    pub kernel: String,
+    /// Unified cgroup hierarchy mounted at `/sys/fs/cgroup`.
+    pub cgroup_v2: bool,
+    /// `pidfd_open(2)`, Linux 5.3+.
+    pub pidfd: bool,
+    /// `fs.inotify.max_user_watches`, if readable.
+    pub inotify_max_watches: Option<u64>,
+    /// `fs.inotify.max_user_instances`, if readable.
+    pub inotify_max_instances: Option<u64>,
+    /// The kernel supports seccomp filters.
+    pub seccomp: bool,
+}
+
+/// Parse the leading `major.minor` of a kernel release string.
+pub fn kernel_version(release: &str) -> Option<(u32, u32)> {
+    let mut parts = release.split(|c: char| !c.is_ascii_digit());
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+fn read_number(path: &str) -> Option<u64> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+impl HostCapabilities {
+    /// Probe the running kernel.
+    pub fn detect() -> Self {
+        let kernel = uname()
+            .map(|info| info.release().to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        // The Seccomp line is only present when the kernel was built with it
+        let seccomp = fs::read_to_string("/proc/self/status")
+            .map(|status| status.lines().any(|line| line.starts_with("Seccomp:")))
+            .unwrap_or(false);
+
+        Self {
+            arch: host_arch(),
+            pidfd: kernel_version(&kernel).is_some_and(|version| version >= (5, 3)),
+            kernel,
+            cgroup_v2: cgroup_v2_available(),
+            inotify_max_watches: read_number("/proc/sys/fs/inotify/max_user_watches"),
+            inotify_max_instances: read_number("/proc/sys/fs/inotify/max_user_instances"),
+            seccomp,
+        }
+    }
+
+    /// Configured features that will be degraded on this host.
+    pub fn warnings(&self, settings: &AppSpecificConfig) -> Vec<String> {
+        let mut warnings = Vec::new();
+
+        if settings.cgroup.enabled && !self.cgroup_v2 {
+            warnings.push(String::from(
+                "cgroup enforcement is enabled but the host has no cgroup v2 hierarchy, only monitoring max_ram_usage",
+            ));
+        }
+
+        match self.inotify_max_watches {
+            Some(watches) if watches < LOW_INOTIFY_WATCHES => warnings.push(format!(
+                "fs.inotify.max_user_watches is {}, large monitor_path trees may miss changes, consider raising it to at least {}",
+                watches, LOW_INOTIFY_WATCHES
+            )),
+            Some(_) => (),
+            None => warnings.push(String::from(
+                "inotify limits can't be read, directory monitoring may not work",
+            )),
+        }
+
+        if !self.pidfd {
+            warnings.push(format!(
+                "Kernel {} has no pidfd support, child exits are detected by polling",
+                self.kernel
+            ));
+        }
+
+        warnings
+    }
+}
+
+/// Detect capabilities once and cache them for the rest of the process.
+pub fn capabilities() -> &'static HostCapabilities {
+    CAPABILITIES.get_or_init(HostCapabilities::detect)
+}
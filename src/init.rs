@@ -0,0 +1,216 @@
+//! Container entrypoint mode (`--init`).
+//!
+//! As the entrypoint of a container the runner is PID 1, which comes with
+//! duties a host process doesn't have: processes orphaned inside the child's
+//! tree are reparented to it and have to be reaped, and signals sent by the
+//! container runtime only arrive if it handles them. With `--init` the
+//! runner
+//!
+//! - becomes a child subreaper, so orphans are reparented to it even when
+//!   it isn't PID 1, and reaps those that exited,
+//! - treats `SIGTERM` (what `docker stop` sends) like `SIGUSR1`, the
+//!   graceful shutdown,
+//! - forwards [`FORWARDED`] signals to the child's process group.
+//!
+//! Combined with `--oneshot` the runner exits with the child's exit code
+//! instead of respawning it, like a plain entrypoint would.
+//!
+//! A zombie is only reaped once it has been seen on two passes in a row and
+//! isn't the supervised child: processes the runner spawns itself are
+//! waited for right away, a zombie still around a second later has nobody
+//! waiting for it.
+
+use artisan_middleware::dusa_collection_utils;
+use dusa_collection_utils::core::logger::LogLevel;
+use std::{
+    collections::BTreeSet,
+    sync::{Arc, atomic::AtomicBool},
+    time::Duration,
+};
+
+use crate::log;
+
+/// How often orphaned zombies are looked for.
+pub const REAP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Signals passed on to the child's process group.
+/// `SIGTTIN`, `SIGTTOU` and `SIGURG` aren't among them, they set the
+/// runner's own log level and the child's resource limits, and ask for a
+/// dump.
+pub const FORWARDED: &[&str] = &["SIGQUIT", "SIGWINCH", "SIGCONT"];
+
+/// Pid, state and parent pid of a `/proc/<pid>/stat` line.
+pub fn parse_stat(stat: &str) -> Option<(u32, char, u32)> {
+    let pid = stat.split_whitespace().next()?.parse().ok()?;
+    // The command name is in parentheses and may contain anything
+    let mut fields = stat[stat.rfind(')')? + 1..].split_whitespace();
+    let state = fields.next()?.chars().next()?;
+    let ppid = fields.next()?.parse().ok()?;
+    Some((pid, state, ppid))
+}
+
+/// Decides which zombies nobody else is going to reap.
+#[derive(Debug, Default)]
+pub struct Reaper {
+    suspects: BTreeSet<u32>,
+}
+
+impl Reaper {
+    /// The zombies among `zombies` to reap now, those that were already
+    /// there last time and aren't the supervised `child`.
+    pub fn select(&mut self, zombies: &[u32], child: u32) -> Vec<u32> {
+        let orphans: BTreeSet<u32> = zombies
+            .iter()
+            .copied()
+            .filter(|pid| *pid != child)
+            .collect();
+        let due: Vec<u32> = orphans.intersection(&self.suspects).copied().collect();
+        self.suspects = orphans
+            .into_iter()
+            .filter(|pid| !due.contains(pid))
+            .collect();
+        due
+    }
+}
+
+/// Set up the entrypoint duties, `exit` is the graceful shutdown flag.
+pub fn start(exit: Arc<AtomicBool>) {
+    // PID 1 gets the orphans anyway
+    let subreaper = match std::process::id() {
+        1 => Ok(()),
+        _ => platform::become_subreaper(),
+    };
+    if let Err(err) = subreaper {
+        log!(
+            LogLevel::Warn,
+            "Failed to become a child subreaper: {}",
+            err
+        );
+    }
+    platform::watch_signals(exit);
+
+    tokio::spawn(async {
+        let mut reaper = Reaper::default();
+        let mut tick = tokio::time::interval(REAP_INTERVAL);
+        loop {
+            tick.tick().await;
+            platform::reap(&mut reaper);
+        }
+    });
+    log!(LogLevel::Info, "Running as the container init");
+}
+
+#[cfg(unix)]
+mod platform {
+    use artisan_middleware::dusa_collection_utils;
+    use dusa_collection_utils::core::logger::LogLevel;
+    use nix::sys::signal::{Signal, killpg};
+    use nix::sys::wait::{WaitPidFlag, waitpid};
+    use nix::unistd::Pid;
+    use signal_hook::iterator::Signals;
+    use std::{
+        fs,
+        str::FromStr,
+        sync::{
+            Arc,
+            atomic::{AtomicBool, Ordering},
+        },
+        thread,
+    };
+
+    use super::{FORWARDED, Reaper, parse_stat};
+    use crate::events::{self, Event};
+    use crate::global_child::GLOBAL_CHILD_PID;
+    use crate::log;
+
+    #[cfg(target_os = "linux")]
+    pub fn become_subreaper() -> Result<(), String> {
+        nix::sys::prctl::set_child_subreaper(true).map_err(|err| err.to_string())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn become_subreaper() -> Result<(), String> {
+        Err(String::from("only supported on Linux"))
+    }
+
+    pub fn watch_signals(exit: Arc<AtomicBool>) {
+        thread::spawn(move || {
+            let mut signals =
+                Signals::new([Signal::SIGTERM as i32]).expect("Failed to register SIGTERM");
+            for _ in signals.forever() {
+                exit.store(true, Ordering::Relaxed);
+                log!(LogLevel::Info, "Received SIGTERM, exiting");
+                events::record(Event::Signal {
+                    signal: String::from("SIGTERM"),
+                    action: String::from("exit"),
+                });
+            }
+        });
+
+        let forwarded: Vec<Signal> = FORWARDED
+            .iter()
+            .filter_map(|name| Signal::from_str(name).ok())
+            .collect();
+        thread::spawn(move || {
+            let mut signals = Signals::new(forwarded.iter().map(|signal| *signal as i32))
+                .expect("Failed to register forwarded signals");
+            for received in signals.forever() {
+                let pid = GLOBAL_CHILD_PID.load(Ordering::Relaxed);
+                let signal = match Signal::try_from(received) {
+                    Ok(signal) if pid != 0 => signal,
+                    _ => continue,
+                };
+                events::record(Event::Signal {
+                    signal: signal.to_string(),
+                    action: String::from("forward"),
+                });
+                if let Err(err) = killpg(Pid::from_raw(pid as i32), signal) {
+                    log!(LogLevel::Debug, "Failed to forward {}: {}", signal, err);
+                }
+            }
+        });
+    }
+
+    /// Zombie children of this process.
+    fn zombies() -> Vec<u32> {
+        let own = std::process::id();
+        let entries = match fs::read_dir("/proc") {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+        entries
+            .flatten()
+            .filter_map(|entry| fs::read_to_string(entry.path().join("stat")).ok())
+            .filter_map(|stat| parse_stat(&stat))
+            .filter(|(_, state, ppid)| *state == 'Z' && *ppid == own)
+            .map(|(pid, _, _)| pid)
+            .collect()
+    }
+
+    pub fn reap(reaper: &mut Reaper) {
+        let child = GLOBAL_CHILD_PID.load(Ordering::Relaxed);
+        for pid in reaper.select(&zombies(), child) {
+            match waitpid(Pid::from_raw(pid as i32), Some(WaitPidFlag::WNOHANG)) {
+                Ok(status) => log!(LogLevel::Debug, "Reaped orphan {}: {:?}", pid, status),
+                Err(err) => log!(LogLevel::Debug, "Failed to reap orphan {}: {}", pid, err),
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::sync::{Arc, atomic::AtomicBool};
+
+    use super::Reaper;
+
+    pub fn become_subreaper() -> Result<(), String> {
+        Err(String::from("not supported on Windows"))
+    }
+
+    /// Console events already reach the runner through the signals module.
+    pub fn watch_signals(_exit: Arc<AtomicBool>) {}
+
+    /// Windows has no zombies to reap.
+    pub fn reap(_reaper: &mut Reaper) {}
+}
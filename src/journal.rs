@@ -0,0 +1,234 @@
+//! Append-only journal of captured output.
+//!
+//! Captured lines only reach the state file when it is rewritten, so a runner
+//! crash used to take the child's last words with it. With
+//! `[app_specific.journal] enabled = true` every line is appended to a
+//! journal file the moment the runner sees it. Each append is a single
+//! `write(2)`, so the data survives the runner dying; the `fsync` policy
+//! decides how much of it also survives the host going down.
+//!
+//! The runner sees lines when it drains the capture files, every
+//! `cadence.output_millis`. Lines the child wrote since the last drain
+//! aren't journaled when the runner dies, they're only in the capture
+//! files, which the next runner follows from their end.
+//!
+//! The journal is JSON lines, one `{timestamp, event, line}` object per line,
+//! rotated to `<path>.1` once it reaches `max_size_mb`.
+
+use artisan_middleware::dusa_collection_utils::core::{
+    logger::LogLevel, types::pathtype::PathType,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::capture;
+use crate::global_child::GLOBAL_JOURNAL;
+use crate::log;
+
+/// When the journal is flushed to stable storage.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum JournalFsync {
+    /// `fsync` after every line.
+    Always,
+    /// `fsync` once per drain of the child's output.
+    #[default]
+    Batch,
+    /// Leave flushing to the kernel.
+    Never,
+}
+
+/// `[app_specific.journal]`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct JournalConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Defaults to `output.journal` in the runner's private
+    /// [output directory](crate::capture::dir).
+    #[serde(default)]
+    pub path: Option<String>,
+    #[serde(default)]
+    pub fsync: JournalFsync,
+    #[serde(default = "default_journal_size")]
+    pub max_size_mb: u64,
+}
+
+impl Default for JournalConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: None,
+            fsync: JournalFsync::default(),
+            max_size_mb: default_journal_size(),
+        }
+    }
+}
+
+impl JournalConfig {
+    /// Where the journal of the runner with its state at `state_path` is
+    /// written.
+    pub fn path(&self, state_path: &PathType) -> PathBuf {
+        match &self.path {
+            Some(path) => PathBuf::from(path),
+            None => capture::dir(state_path).join("output.journal"),
+        }
+    }
+}
+
+pub fn default_journal_size() -> u64 {
+    10
+}
+
+/// A single journal record.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct JournalEntry {
+    /// Where the line starts in its capture file, so the same line recorded
+    /// or shipped twice can be told apart from one the child repeated.
+    /// `None` for install and build output, which isn't captured to a file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    pub timestamp: u64,
+    /// Same names as the json log sink, e.g. `child_stderr`, `build_stdout`.
+    pub event: String,
+    pub line: String,
+}
+
+/// Open handle to the output journal.
+#[derive(Debug)]
+pub struct OutputJournal {
+    path: PathBuf,
+    file: File,
+    fsync: JournalFsync,
+    max_bytes: u64,
+    written: u64,
+    dirty: bool,
+}
+
+impl OutputJournal {
+    /// Open (or create) the journal, appending to whatever a previous run left.
+    pub fn open(path: &Path, fsync: JournalFsync, max_size_mb: u64) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let written = file.metadata()?.len();
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            fsync,
+            max_bytes: max_size_mb.saturating_mul(1024 * 1024),
+            written,
+            dirty: false,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append a single line.
+    pub fn append(
+        &mut self,
+        id: Option<u64>,
+        timestamp: u64,
+        event: &str,
+        line: &str,
+    ) -> io::Result<()> {
+        let entry = JournalEntry {
+            id,
+            timestamp,
+            event: event.to_owned(),
+            line: line.to_owned(),
+        };
+        let mut record = serde_json::to_vec(&entry)?;
+        record.push(b'\n');
+
+        if self.max_bytes > 0 && self.written + record.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+
+        // One write per record so a crash can't leave half a line behind
+        self.file.write_all(&record)?;
+        self.written += record.len() as u64;
+        self.dirty = true;
+
+        if self.fsync == JournalFsync::Always {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    /// End of a batch of appends, `fsync`s under [`JournalFsync::Batch`].
+    pub fn commit(&mut self) -> io::Result<()> {
+        match self.fsync {
+            JournalFsync::Batch if self.dirty => self.sync(),
+            _ => Ok(()),
+        }
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.file.sync_data()?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.fsync != JournalFsync::Never {
+            self.file.sync_data()?;
+        }
+
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(".1");
+        fs::rename(&self.path, rotated)?;
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.written = 0;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+/// Append `lines`, `(id, timestamp, line)`, to the global journal if one is
+/// open.
+pub async fn record<'a>(event: &str, lines: impl IntoIterator<Item = (Option<u64>, u64, &'a str)>) {
+    let mut journal = GLOBAL_JOURNAL.lock().await;
+    let journal = match journal.as_mut() {
+        Some(journal) => journal,
+        None => return,
+    };
+
+    for (id, timestamp, line) in lines {
+        if let Err(err) = journal.append(id, timestamp, event, line) {
+            log!(
+                LogLevel::Warn,
+                "Failed to append to output journal {}: {}",
+                journal.path().display(),
+                err
+            );
+            return;
+        }
+    }
+}
+
+/// Commit the lines recorded since the last call, see [`OutputJournal::commit`].
+pub async fn commit() {
+    let mut journal = GLOBAL_JOURNAL.lock().await;
+    let journal = match journal.as_mut() {
+        Some(journal) => journal,
+        None => return,
+    };
+
+    if let Err(err) = journal.commit() {
+        log!(
+            LogLevel::Warn,
+            "Failed to sync output journal {}: {}",
+            journal.path().display(),
+            err
+        );
+    }
+}
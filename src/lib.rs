@@ -1,5 +1,77 @@
+pub mod acme;
+pub mod app_status;
+pub mod artifacts;
+pub mod audit;
+pub mod build_cache;
+pub mod build_executor;
+pub mod build_steps;
+pub mod bundle;
+pub mod cadence;
+pub mod capture;
+pub mod cgroup;
 pub mod child;
+pub mod child_manager;
+pub mod cli;
+pub mod command_vars;
 pub mod config;
+pub mod config_schema;
+pub mod cpu_limit;
+pub mod crash_loop;
+pub mod deploy_trace;
+pub mod diagnostics;
+pub mod dry_run;
+pub mod dump;
+pub mod env_overrides;
+pub mod events;
 pub mod global_child;
+pub mod heartbeat;
+pub mod host;
+pub mod init;
+pub mod journal;
+pub mod lifecycle;
+pub mod listen_fds;
+pub mod log_level;
+pub mod log_rules;
+pub mod log_shipping;
+pub mod logging;
+pub mod maintenance;
+pub mod metrics_history;
+pub mod migrate;
+pub mod notifications;
+pub mod notify_throttle;
+pub mod orphans;
+pub mod otel;
+pub mod outbox;
+pub mod output;
+pub mod polling;
+pub mod ports;
+pub mod prerequisites;
+pub mod privileges;
+pub mod probes;
+pub mod profiles;
+pub mod proxy;
+pub mod ready;
+pub mod reload;
+pub mod reservations;
+pub mod reset;
+pub mod restart;
+pub mod runner;
+pub mod runner_state;
+pub mod schedule;
+pub mod secrets;
+pub mod shutdown;
 pub mod signals;
-pub (crate) mod secrets;
\ No newline at end of file
+pub mod sim;
+pub mod state;
+pub mod state_backend;
+pub mod state_sync;
+pub mod static_server;
+pub mod status_server;
+pub mod stdin;
+pub mod supervisor;
+pub mod systemd;
+pub mod timestamps;
+pub mod uptime;
+pub mod validation;
+pub mod watch;
+pub mod webhook;
@@ -0,0 +1,200 @@
+//! Lifecycle of the supervised child.
+//!
+//! The aggregator only sees a coarse [`Status`], which used to be flipped
+//! wherever something happened, so e.g. the periodic check could mark a
+//! runner `Running` in the middle of a deploy. Every status change now goes
+//! through [`Lifecycle::transition`], which only allows the moves below,
+//! logs each one as a `lifecycle` event and writes the matching [`Status`].
+//!
+//! ```text
+//! Idle -> Installing -> Building -> Starting -> Running <-> Degraded
+//! ```
+//!
+//! Running and degraded children can be rebuilt or respawned, any phase can
+//! go back to idle (the child exited for good, a reload) and stopping is
+//! final.
+//!
+//! The runner's lifecycle also appends each transition to the log the
+//! uptime report is made from, see [`crate::uptime`].
+
+use artisan_middleware::{aggregator::Status, dusa_collection_utils, state_persistence::AppState};
+use dusa_collection_utils::core::{functions::current_timestamp, logger::LogLevel};
+use serde::{Deserialize, Serialize};
+use std::{fmt, path::PathBuf};
+
+use crate::{
+    log,
+    logging::dispatch,
+    uptime::{self, Transition},
+};
+
+/// Where the child is in its lifecycle.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Phase {
+    /// Nothing running, or not yet started.
+    Idle,
+    Installing,
+    Building,
+    /// Spawned, waiting for it to become ready.
+    Starting,
+    Running,
+    /// Running, but not as it should: not ready in time, a failed build,
+    /// missing metrics or crash looping.
+    Degraded,
+    /// The runner is shutting down.
+    Stopping,
+}
+
+impl Phase {
+    /// What the aggregator is told.
+    pub fn status(self) -> Status {
+        match self {
+            Phase::Idle => Status::Idle,
+            Phase::Installing | Phase::Building => Status::Building,
+            Phase::Starting => Status::Starting,
+            Phase::Running => Status::Running,
+            Phase::Degraded => Status::Warning,
+            Phase::Stopping => Status::Stopping,
+        }
+    }
+
+    /// Whether the lifecycle may move from `self` to `next`.
+    pub fn can_move_to(self, next: Phase) -> bool {
+        use Phase::*;
+
+        match (self, next) {
+            (Stopping, _) => false,
+            (_, Stopping | Idle) => true,
+            (Idle, Installing | Building | Starting) => true,
+            (Installing, Building | Starting) => true,
+            (Building, Starting | Degraded) => true,
+            (Starting, Running | Degraded) => true,
+            (Running | Degraded, Installing | Building | Starting | Running | Degraded) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Phase::Idle => "idle",
+            Phase::Installing => "installing",
+            Phase::Building => "building",
+            Phase::Starting => "starting",
+            Phase::Running => "running",
+            Phase::Degraded => "degraded",
+            Phase::Stopping => "stopping",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The current [`Phase`], owned by the main loop.
+#[derive(Debug)]
+pub struct Lifecycle {
+    phase: Phase,
+    /// Whether a child is answering, a build-first deploy builds while the
+    /// old child keeps serving.
+    serving: bool,
+    /// Transition log, see [`crate::uptime`].
+    history: Option<PathBuf>,
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Lifecycle {
+    pub fn new() -> Self {
+        Self {
+            phase: Phase::Idle,
+            serving: false,
+            history: None,
+        }
+    }
+
+    /// A lifecycle that appends its transitions to the log at `path`,
+    /// starting with the idle one it begins in.
+    pub fn recording(path: PathBuf) -> Self {
+        let lifecycle = Self {
+            history: Some(path),
+            ..Self::new()
+        };
+        lifecycle.record();
+        lifecycle
+    }
+
+    fn record(&self) {
+        let path = match &self.history {
+            Some(path) => path,
+            None => return,
+        };
+        let transition = Transition {
+            timestamp: current_timestamp(),
+            phase: self.phase,
+            serving: self.serving,
+        };
+        if let Err(err) = uptime::append(path, &transition) {
+            log!(
+                LogLevel::Warn,
+                "Failed to record the transition in {}: {}",
+                path.display(),
+                err
+            );
+        }
+    }
+
+    /// The child was stopped without a transition, e.g. before the build of
+    /// a stop-first deploy.
+    pub fn child_stopped(&mut self) {
+        if self.serving {
+            self.serving = false;
+            self.record();
+        }
+    }
+
+    /// Move to `next` and write its status into `state`.
+    ///
+    /// Invalid moves are logged and leave both untouched, staying in the
+    /// current phase is allowed and not logged.
+    pub fn transition(&mut self, next: Phase, state: &mut AppState) {
+        if next == self.phase {
+            state.status = next.status();
+            return;
+        }
+
+        if !self.phase.can_move_to(next) {
+            log!(
+                LogLevel::Warn,
+                "Rejected lifecycle transition {} -> {}",
+                self.phase,
+                next
+            );
+            return;
+        }
+
+        dispatch(
+            LogLevel::Info,
+            "lifecycle",
+            format!("Lifecycle {} -> {}", self.phase, next),
+        );
+        self.serving = match next {
+            Phase::Running | Phase::Degraded => true,
+            // Build-first deploys build while the old child serves
+            Phase::Building => self.serving,
+            _ => false,
+        };
+        self.phase = next;
+        state.status = next.status();
+        self.record();
+    }
+
+    /// Write the current status into a freshly loaded `state`.
+    pub fn sync(&self, state: &mut AppState) {
+        state.status = self.phase.status();
+    }
+}
@@ -0,0 +1,136 @@
+//! Listening sockets handed down to the child (`LISTEN_FDS`).
+//!
+//! Children that support socket activation, like systemd's, can have the
+//! runner own their listening sockets:
+//!
+//! ```toml
+//! [app_specific]
+//! port = 8080
+//!
+//! [app_specific.listen_fds]
+//! enabled = true
+//! addrs = ["0.0.0.0:8080", "[::]:8080"]   # 0.0.0.0:<port> if left out
+//! ```
+//!
+//! The runner binds `addrs` once at start up and passes them to every child
+//! it spawns as descriptors 3, 4, …, with `LISTEN_FDS` set to their count
+//! and `LISTEN_PID` to the child's pid. The sockets are never closed while
+//! the runner lives, so connections arriving while one child is replaced by
+//! the next wait in the socket's backlog instead of being refused, and with
+//! `build_first` both children accept on the same socket for a moment.
+//!
+//! The child has to take its sockets from the descriptors rather than bind
+//! the port itself, it gets `EADDRINUSE` otherwise.
+
+use nix::fcntl::{FcntlArg, fcntl};
+use nix::unistd::dup2;
+use serde::{Deserialize, Serialize};
+use std::{
+    io,
+    net::TcpListener,
+    os::fd::{AsRawFd, RawFd},
+};
+use tokio::process::Command;
+
+use crate::privileges;
+
+/// The first descriptor the sockets are passed as.
+pub const FIRST_FD: RawFd = 3;
+
+/// Exports the shell's pid, the child's once it `exec`s, as `LISTEN_PID`.
+const WRAPPER: &str = "LISTEN_PID=$$; export LISTEN_PID; exec \"$@\"";
+
+/// `[app_specific.listen_fds]`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct ListenFdsConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Addresses to bind, in the order the child gets them. Just
+    /// `0.0.0.0:<port>` while empty.
+    #[serde(default)]
+    pub addrs: Vec<String>,
+}
+
+impl ListenFdsConfig {
+    /// The addresses to bind with the child's `port`, none while disabled.
+    pub fn addrs(&self, port: Option<u16>) -> Vec<String> {
+        match (self.enabled, self.addrs.is_empty(), port) {
+            (false, _, _) => Vec::new(),
+            (true, false, _) => self.addrs.clone(),
+            (true, true, Some(port)) => vec![format!("0.0.0.0:{}", port)],
+            (true, true, None) => Vec::new(),
+        }
+    }
+}
+
+/// The sockets every child gets.
+#[derive(Debug)]
+pub struct ListenSockets {
+    listeners: Vec<(String, TcpListener)>,
+}
+
+impl ListenSockets {
+    /// Bind `addrs`, taking the ones bound during the privileged phase.
+    pub async fn bind(addrs: &[String]) -> io::Result<Self> {
+        let mut listeners = Vec::new();
+        for addr in addrs {
+            let listener = privileges::bind(addr)
+                .await
+                .and_then(|listener| listener.into_std())
+                .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", addr, err)))?;
+            // The runner never accepts on them, and children expect the
+            // blocking sockets systemd hands out
+            listener.set_nonblocking(false)?;
+            listeners.push((addr.clone(), listener));
+        }
+        Ok(Self { listeners })
+    }
+
+    pub fn addrs(&self) -> Vec<&str> {
+        self.listeners
+            .iter()
+            .map(|(addr, _)| addr.as_str())
+            .collect()
+    }
+
+    /// `argv` wrapped so the child learns its own pid as `LISTEN_PID`,
+    /// which can't be set before the fork.
+    pub fn wrap(argv: &[String]) -> Vec<String> {
+        let mut wrapped: Vec<String> = ["/bin/sh", "-c", WRAPPER, "sh"]
+            .iter()
+            .map(|part| part.to_string())
+            .collect();
+        wrapped.extend(argv.iter().cloned());
+        wrapped
+    }
+
+    /// Have `command` start with the sockets as descriptors 3, 4, … and
+    /// `LISTEN_FDS` set. Its program has to be wrapped with [`Self::wrap`].
+    pub fn pass(&self, command: &mut Command) {
+        let fds: Vec<RawFd> = self
+            .listeners
+            .iter()
+            .map(|(_, listener)| listener.as_raw_fd())
+            .collect();
+        command.env("LISTEN_FDS", fds.len().to_string());
+        command.env_remove("LISTEN_FDNAMES");
+        // Nothing may allocate after the fork, so `moved` only ever fills
+        // its capacity. The sockets are first moved above the targets, so
+        // none is overwritten before it's placed, then put in place without
+        // close-on-exec
+        let above = FIRST_FD + fds.len() as RawFd;
+        let mut moved = Vec::with_capacity(fds.len());
+        unsafe {
+            command.pre_exec(move || {
+                moved.clear();
+                for fd in &fds {
+                    moved.push(fcntl(*fd, FcntlArg::F_DUPFD_CLOEXEC(above))?);
+                }
+                for (target, fd) in (FIRST_FD..).zip(&moved) {
+                    dup2(*fd, target)?;
+                }
+                Ok(())
+            });
+        }
+    }
+}
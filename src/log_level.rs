@@ -0,0 +1,69 @@
+//! Changing the log level of a running runner.
+//!
+//! `ais_runner log-level <level>` records the level in the runner state and
+//! asks the runner to apply it (`SIGTTIN` on Unix), which switches the
+//! global log level without touching the child. The level is written to the
+//! config in the [`AppState`] so `status` shows it, and keeps overriding the
+//! one of `Config.toml` across reloads and restarts of the runner until
+//! `ais_runner log-level reset`.
+//!
+//! The override is kept by name since [`LogLevel`] can't be compared.
+
+use artisan_middleware::{
+    dusa_collection_utils::core::{
+        logger::{LogLevel, set_log_level},
+        types::pathtype::PathType,
+    },
+    state_persistence::AppState,
+};
+
+use crate::runner_state::RunnerState;
+
+/// Every level, most verbose first.
+pub const LEVELS: [LogLevel; 5] = [
+    LogLevel::Trace,
+    LogLevel::Debug,
+    LogLevel::Info,
+    LogLevel::Warn,
+    LogLevel::Error,
+];
+
+/// Name `level` is stored and shown under, e.g. `Debug`.
+pub fn name(level: LogLevel) -> String {
+    format!("{:?}", level)
+}
+
+/// The level called `name`, ignoring case.
+pub fn parse(name: &str) -> Result<LogLevel, String> {
+    LEVELS
+        .into_iter()
+        .find(|level| self::name(*level).eq_ignore_ascii_case(name))
+        .ok_or_else(|| {
+            format!(
+                "Unknown log level {:?}, expected one of {}",
+                name,
+                LEVELS.map(self::name).join(", ")
+            )
+        })
+}
+
+/// The level to log at: the override if there is a valid one, otherwise
+/// `configured`.
+pub fn effective(override_level: Option<&str>, configured: LogLevel) -> LogLevel {
+    override_level
+        .and_then(|name| parse(name).ok())
+        .unwrap_or(configured)
+}
+
+/// Switch to the level recorded next to `state_path`, or `configured`
+/// without an override, and note it in `state`. Saving `state` is left to
+/// the caller.
+pub fn apply(state: &mut AppState, state_path: &PathType, configured: LogLevel) -> LogLevel {
+    let level = effective(
+        RunnerState::load(state_path).log_level.as_deref(),
+        configured,
+    );
+    set_log_level(level);
+    state.config.log_level = level;
+    level
+}
@@ -0,0 +1,147 @@
+//! Rules turning lines of the child's output into supervision signals.
+//!
+//! Every line the runner captures from the child's stdout or stderr is
+//! matched against `[[app_specific.log_rules]]`:
+//!
+//! ```toml
+//! [[app_specific.log_rules]]
+//! pattern = "FATAL"
+//! action = "warning"
+//!
+//! [[app_specific.log_rules]]
+//! pattern = "listening on"
+//! action = "ready"
+//! ```
+//!
+//! A `warning` match reports the app as `Warning` for [`WARNING_HOLD`]
+//! after the last matching line, a `restart` match restarts the child the
+//! way a failed probe does, and `ready` rules gate a new child the way a
+//! `ready_check.log_line` does. The first rule a line matches wins.
+
+use artisan_middleware::dusa_collection_utils;
+use dusa_collection_utils::core::logger::LogLevel;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+use crate::log;
+
+/// How long a `warning` match keeps the app in `Warning`.
+pub const WARNING_HOLD: Duration = Duration::from_secs(300);
+
+/// What a matching line does.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogAction {
+    /// Report the app as `Warning`.
+    Warning,
+    /// Mark a new child as ready.
+    Ready,
+    /// Restart the child.
+    Restart,
+}
+
+/// `[[app_specific.log_rules]]`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LogRule {
+    /// Regex matched against each line.
+    pub pattern: String,
+    pub action: LogAction,
+}
+
+/// Check every rule without running any of them.
+pub fn validate(rules: &[LogRule]) -> Result<(), String> {
+    for rule in rules {
+        Regex::new(&rule.pattern).map_err(|err| {
+            format!(
+                "log_rules pattern /{}/ is not a valid regex: {}",
+                rule.pattern, err
+            )
+        })?;
+    }
+    Ok(())
+}
+
+/// One regex matching a line any of the `ready` rules match.
+pub fn ready_pattern(rules: &[LogRule]) -> Option<String> {
+    let patterns: Vec<String> = rules
+        .iter()
+        .filter(|rule| rule.action == LogAction::Ready)
+        .map(|rule| format!("(?:{})", rule.pattern))
+        .collect();
+    match patterns.is_empty() {
+        true => None,
+        false => Some(patterns.join("|")),
+    }
+}
+
+/// Compiled rules and what their matches left pending.
+#[derive(Debug, Default)]
+pub struct LogRules {
+    rules: Vec<(Regex, LogAction)>,
+    /// The last `warning` line and when it was seen.
+    warning: Option<(Instant, String)>,
+    /// The first `restart` line since the last restart.
+    restart: Option<String>,
+}
+
+impl LogRules {
+    /// Compile `rules`, invalid patterns are logged and skipped.
+    pub fn new(rules: &[LogRule]) -> Self {
+        let rules = rules
+            .iter()
+            .filter_map(|rule| match Regex::new(&rule.pattern) {
+                Ok(regex) => Some((regex, rule.action)),
+                Err(err) => {
+                    log!(
+                        LogLevel::Warn,
+                        "Ignoring log rule /{}/: {}",
+                        rule.pattern,
+                        err
+                    );
+                    None
+                }
+            })
+            .collect();
+        Self {
+            rules,
+            ..Self::default()
+        }
+    }
+
+    /// Match `line` seen at `now`, returning the action of the first rule
+    /// it matches.
+    pub fn check(&mut self, line: &str, now: Instant) -> Option<LogAction> {
+        let action = self
+            .rules
+            .iter()
+            .find(|(regex, _)| regex.is_match(line))
+            .map(|(_, action)| *action)?;
+
+        match action {
+            LogAction::Warning => self.warning = Some((now, line.to_owned())),
+            LogAction::Restart if self.restart.is_none() => self.restart = Some(line.to_owned()),
+            LogAction::Restart | LogAction::Ready => (),
+        }
+        Some(action)
+    }
+
+    /// The line keeping the app in `Warning` at `now`, if any.
+    pub fn warning(&self, now: Instant) -> Option<&str> {
+        self.warning
+            .as_ref()
+            .filter(|(seen, _)| now.saturating_duration_since(*seen) < WARNING_HOLD)
+            .map(|(_, line)| line.as_str())
+    }
+
+    /// Take the line asking for a restart, if one was seen.
+    pub fn take_restart(&mut self) -> Option<String> {
+        self.restart.take()
+    }
+
+    /// Forget pending matches, for a new child.
+    pub fn reset(&mut self) {
+        self.warning = None;
+        self.restart = None;
+    }
+}
@@ -0,0 +1,407 @@
+//! Shipping the child's output to the Artisan aggregator.
+//!
+//! Captured lines only end up in the state file and the journal on the
+//! host. With `[app_specific.log_shipping]` set every line of the child is
+//! also sent to a central aggregator over gRPC, on the same tonic stack the
+//! secret server client uses:
+//!
+//! ```toml
+//! [app_specific.log_shipping]
+//! addr = "logs.artisan.internal:50052"
+//! batch_lines = 500
+//! queue_limit = 1000
+//!
+//! [app_specific.log_shipping.tls]
+//! ca_cert = "/etc/artisan/ca.pem"
+//! ```
+//!
+//! Lines are collected into batches of `batch_lines`, or whatever came in
+//! within `flush_interval_seconds`, and spooled to disk in an [`Outbox`]
+//! before they are sent. A batch leaves the spool once the aggregator
+//! acknowledged it and the next one is only sent after that, so a slow
+//! aggregator holds lines back on disk rather than in memory. While it
+//! can't be reached batches pile up in the spool, the oldest are dropped
+//! beyond `queue_limit`, and are sent in order once it's back. Capturing
+//! output never waits on the aggregator, lines coming in faster than they
+//! can be spooled are dropped and counted. When the runner shuts down the
+//! lines still in memory are spooled and delivered one last time.
+
+mod log_service {
+    tonic::include_proto!("log_service");
+}
+
+use artisan_middleware::dusa_collection_utils::core::logger::LogLevel;
+use artisan_middleware::timestamp::current_timestamp;
+use serde::{Deserialize, Serialize};
+use std::{
+    io,
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+use tokio::{
+    sync::mpsc,
+    time::{Instant, interval, timeout},
+};
+use tonic::transport::{Channel, Endpoint};
+
+use crate::global_child::{GLOBAL_LOG_SHIPPER, get_query};
+use crate::journal::JournalEntry;
+use crate::log;
+use crate::outbox::{Outbox, QueuedMessage};
+use crate::output::CapturedLine;
+use crate::secrets::{SecretTlsConfig, https_endpoint};
+use crate::shutdown::{self, Stage};
+use log_service::{LogLine, ShipRequest, log_aggregator_client::LogAggregatorClient};
+
+/// Lines waiting to be spooled, beyond that they are dropped.
+const CHANNEL_LINES: usize = 10_000;
+
+/// Longest wait between attempts to reach the aggregator.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// `[app_specific.log_shipping]`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LogShippingConfig {
+    /// Address of the aggregator, shipping is off when unset.
+    #[serde(default)]
+    pub addr: Option<String>,
+    /// TLS for the channel, the same keys as `secret_tls`.
+    #[serde(default)]
+    pub tls: Option<SecretTlsConfig>,
+    /// Lines sent together at most.
+    #[serde(default = "default_batch_lines")]
+    pub batch_lines: usize,
+    /// How long lines are collected before a smaller batch is sent.
+    #[serde(default = "default_flush_interval")]
+    pub flush_interval_seconds: u64,
+    /// Where batches wait for delivery, defaults to
+    /// `/tmp/.<app_name>_logspool`.
+    #[serde(default)]
+    pub queue_dir: Option<String>,
+    /// Batches kept while the aggregator is unreachable, the oldest are
+    /// dropped beyond that.
+    #[serde(default = "default_queue_limit")]
+    pub queue_limit: usize,
+    #[serde(default = "default_timeout")]
+    pub timeout_seconds: u64,
+}
+
+impl Default for LogShippingConfig {
+    fn default() -> Self {
+        Self {
+            addr: None,
+            tls: None,
+            batch_lines: default_batch_lines(),
+            flush_interval_seconds: default_flush_interval(),
+            queue_dir: None,
+            queue_limit: default_queue_limit(),
+            timeout_seconds: default_timeout(),
+        }
+    }
+}
+
+fn default_batch_lines() -> usize {
+    500
+}
+
+fn default_flush_interval() -> u64 {
+    1
+}
+
+fn default_queue_limit() -> usize {
+    1000
+}
+
+fn default_timeout() -> u64 {
+    10
+}
+
+impl LogShippingConfig {
+    pub fn enabled(&self) -> bool {
+        self.addr.is_some()
+    }
+
+    pub fn queue_dir(&self, app_name: &str) -> PathBuf {
+        match &self.queue_dir {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(format!("/tmp/.{}_logspool", app_name)),
+        }
+    }
+}
+
+/// Spools batches of lines and sends them to the aggregator.
+pub struct LogShipper {
+    config: LogShippingConfig,
+    app_name: String,
+    outbox: Outbox,
+    client: Option<LogAggregatorClient<Channel>>,
+}
+
+impl LogShipper {
+    /// Open the spool, picking up batches a previous run didn't deliver.
+    pub fn new(config: &LogShippingConfig, app_name: &str) -> io::Result<Self> {
+        Ok(Self {
+            config: config.clone(),
+            app_name: app_name.to_owned(),
+            outbox: Outbox::open(&config.queue_dir(app_name), config.queue_limit)?,
+            client: None,
+        })
+    }
+
+    /// Put `lines` in the spool as one batch.
+    pub fn spool(&self, lines: Vec<JournalEntry>, now: u64) {
+        if lines.is_empty() {
+            return;
+        }
+        let message = QueuedMessage {
+            endpoint: self.config.addr.clone().unwrap_or_default(),
+            payload: serde_json::to_value(&lines).unwrap_or_default(),
+            queued_at: now,
+        };
+        match self.outbox.push(&message) {
+            Ok(0) => (),
+            Ok(dropped) => log!(
+                LogLevel::Warn,
+                "Log spool is full, dropped the {} oldest batches",
+                dropped
+            ),
+            Err(err) => log!(LogLevel::Warn, "Failed to spool output: {}", err),
+        }
+    }
+
+    /// Batches waiting for the aggregator.
+    pub fn spooled(&self) -> usize {
+        self.outbox.count().unwrap_or_default()
+    }
+
+    async fn connect(&self) -> Result<LogAggregatorClient<Channel>, String> {
+        let addr = self.config.addr.clone().unwrap_or_default();
+        let invalid = |err: tonic::transport::Error| {
+            format!("{} isn't a valid aggregator address: {}", addr, err)
+        };
+        let endpoint = match &self.config.tls {
+            Some(tls) => {
+                let config = tls
+                    .client_config()
+                    .map_err(|err| err.err_mesg.to_string())?;
+                Endpoint::from_shared(https_endpoint(&addr))
+                    .map_err(invalid)?
+                    .tls_config(config)
+                    .map_err(invalid)?
+            }
+            None => Endpoint::from_shared(plain_endpoint(&addr)).map_err(invalid)?,
+        };
+        let channel = endpoint
+            .connect_timeout(Duration::from_secs(self.config.timeout_seconds))
+            .connect()
+            .await
+            .map_err(|err| format!("Can't reach the aggregator at {}: {}", addr, err))?;
+        Ok(LogAggregatorClient::new(channel))
+    }
+
+    /// Send the spooled batches in order, stopping at the first one the
+    /// aggregator didn't acknowledge.
+    ///
+    /// Returns how many were delivered.
+    pub async fn deliver(&mut self) -> Result<usize, String> {
+        let pending = self
+            .outbox
+            .pending()
+            .map_err(|err| format!("Failed to read the log spool: {}", err))?;
+        if pending.is_empty() {
+            return Ok(0);
+        }
+        let mut client = match &self.client {
+            Some(client) => client.clone(),
+            None => {
+                let client = self.connect().await?;
+                self.client = Some(client.clone());
+                client
+            }
+        };
+
+        let runner_id = get_query().map(|query| query.runner_id).unwrap_or_default();
+        let mut delivered = 0;
+        for (sequence, message) in pending {
+            let lines: Vec<JournalEntry> =
+                serde_json::from_value(message.payload).unwrap_or_default();
+            let request = ShipRequest {
+                runner_id: runner_id.clone(),
+                app_name: self.app_name.clone(),
+                lines: lines
+                    .into_iter()
+                    .map(|entry| LogLine {
+                        timestamp: entry.timestamp,
+                        event: entry.event,
+                        line: entry.line,
+                        id: entry.id.unwrap_or_default(),
+                    })
+                    .collect(),
+                queued_at: message.queued_at,
+            };
+            let limit = Duration::from_secs(self.config.timeout_seconds);
+            let failure = match timeout(limit, client.ship(request)).await {
+                Ok(Ok(_)) => None,
+                Ok(Err(status)) => Some(format!(
+                    "The aggregator refused a batch: {}",
+                    status.message()
+                )),
+                Err(_) => Some(format!(
+                    "The aggregator didn't answer within {}s",
+                    limit.as_secs()
+                )),
+            };
+            if let Some(failure) = failure {
+                // Connect again next time, the channel may be gone
+                self.client = None;
+                return Err(failure);
+            }
+            self.outbox
+                .remove(sequence)
+                .map_err(|err| format!("Failed to dequeue a shipped batch: {}", err))?;
+            delivered += 1;
+        }
+        Ok(delivered)
+    }
+}
+
+/// `addr` with a scheme tonic accepts for a plaintext channel.
+fn plain_endpoint(addr: &str) -> String {
+    match addr.contains("://") {
+        true => addr.to_owned(),
+        false => format!("http://{}", addr),
+    }
+}
+
+/// Where captured lines are handed to the shipper.
+#[derive(Debug)]
+pub struct LogSender {
+    sender: mpsc::Sender<JournalEntry>,
+    /// Lines dropped since the shipper last reported them.
+    dropped: Arc<AtomicU64>,
+}
+
+/// Spool what comes in through `receiver` and deliver it, backing off
+/// while the aggregator is unreachable.
+async fn run(
+    mut shipper: LogShipper,
+    mut receiver: mpsc::Receiver<JournalEntry>,
+    dropped: Arc<AtomicU64>,
+) {
+    let batch_lines = shipper.config.batch_lines.max(1);
+    let mut flush = interval(Duration::from_secs(
+        shipper.config.flush_interval_seconds.max(1),
+    ));
+    let mut batch = Vec::new();
+    let mut backoff = Duration::ZERO;
+    let mut retry_at = Instant::now();
+    let stopped = shutdown::reached(Stage::Logs);
+    tokio::pin!(stopped);
+    let mut stopping = false;
+
+    loop {
+        tokio::select! {
+            received = receiver.recv() => match received {
+                Some(line) => {
+                    batch.push(line);
+                    if batch.len() < batch_lines {
+                        continue;
+                    }
+                }
+                None => break,
+            },
+            _ = flush.tick() => (),
+            _ = &mut stopped, if !stopping => {
+                stopping = true;
+                while let Ok(line) = receiver.try_recv() {
+                    batch.push(line);
+                }
+            }
+        }
+
+        shipper.spool(std::mem::take(&mut batch), current_timestamp());
+        let lost = dropped.swap(0, Ordering::Relaxed);
+        if lost > 0 {
+            log!(
+                LogLevel::Warn,
+                "Dropped {} lines of output, they came in faster than they could be spooled",
+                lost
+            );
+        }
+
+        if stopping {
+            // One last try, whatever isn't delivered waits for the next run
+            if let Err(err) = shipper.deliver().await {
+                log!(
+                    LogLevel::Warn,
+                    "{}, {} batches stay spooled",
+                    err,
+                    shipper.spooled()
+                );
+            }
+            return;
+        }
+        if Instant::now() < retry_at {
+            continue;
+        }
+        match shipper.deliver().await {
+            Ok(_) => {
+                if !backoff.is_zero() {
+                    log!(
+                        LogLevel::Info,
+                        "Reached the aggregator again, shipping spooled output"
+                    );
+                }
+                backoff = Duration::ZERO;
+            }
+            Err(err) => {
+                if backoff.is_zero() {
+                    log!(LogLevel::Warn, "{}, spooling output until it's back", err);
+                }
+                backoff = (backoff * 2).clamp(Duration::from_secs(1), MAX_BACKOFF);
+                retry_at = Instant::now() + backoff;
+            }
+        }
+    }
+}
+
+/// Start shipping lines passed to [`ship`], delivering whatever a previous
+/// run left in the spool first.
+pub fn start(config: &LogShippingConfig, app_name: &str) -> io::Result<()> {
+    let shipper = LogShipper::new(config, app_name)?;
+    let (sender, receiver) = mpsc::channel(CHANNEL_LINES);
+    let dropped = Arc::new(AtomicU64::new(0));
+    let handle = LogSender {
+        sender,
+        dropped: dropped.clone(),
+    };
+    if GLOBAL_LOG_SHIPPER.set(handle).is_err() {
+        return Ok(());
+    }
+    shutdown::track(Stage::Logs, tokio::spawn(run(shipper, receiver, dropped)));
+    Ok(())
+}
+
+/// Hand `lines` of `event` to the shipper if log shipping is on, never
+/// waiting for it.
+pub fn ship(event: &str, lines: &[CapturedLine]) {
+    let shipper = match GLOBAL_LOG_SHIPPER.get() {
+        Some(shipper) => shipper,
+        None => return,
+    };
+    for line in lines {
+        let entry = JournalEntry {
+            id: Some(line.id),
+            timestamp: line.timestamp,
+            event: event.to_owned(),
+            line: line.line.clone(),
+        };
+        if shipper.sender.try_send(entry).is_err() {
+            shipper.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
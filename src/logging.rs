@@ -0,0 +1,137 @@
+//! Runner logging front end.
+//!
+//! Wraps the `log!` macro from `dusa_collection_utils` so the output format
+//! can be switched at runtime. In `text` mode lines are handed to the
+//! middleware logger unchanged, in `json` mode every line (including captured
+//! child output) is written to stdout as a single JSON object.
+
+use artisan_middleware::dusa_collection_utils;
+use dusa_collection_utils::core::logger::{LogLevel, get_log_level};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+use artisan_middleware::timestamp::current_timestamp;
+
+/// Output format for runner logging, configured with `log_format`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+static LOG_FORMAT: OnceCell<LogFormat> = OnceCell::new();
+static APP_NAME: OnceCell<String> = OnceCell::new();
+
+/// Log a formatted message through the runner logger.
+///
+/// Drop-in replacement for the middleware `log!` macro.
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => {
+        $crate::logging::dispatch($level, "runner", format!($($arg)*))
+    };
+}
+
+/// Select the output format. Only the first call has any effect, lines logged
+/// before this is called are always emitted as text.
+pub fn init_logging(format: LogFormat, app_name: &str) {
+    _ = LOG_FORMAT.set(format);
+    _ = APP_NAME.set(app_name.to_owned());
+}
+
+/// Currently active output format.
+pub fn log_format() -> LogFormat {
+    LOG_FORMAT.get().copied().unwrap_or_default()
+}
+
+/// Emit a single log line tagged with `event`.
+///
+/// `event` is only surfaced in json mode, e.g. `runner`, `child_stdout`.
+pub fn dispatch(level: LogLevel, event: &str, message: String) {
+    match log_format() {
+        LogFormat::Text => {
+            dusa_collection_utils::log!(level, "{}", message);
+        }
+        LogFormat::Json => {
+            let line = runner_line(
+                level,
+                get_log_level(),
+                current_timestamp(),
+                app_name(),
+                event,
+                &message,
+            );
+            if let Some(line) = line {
+                write_line(&line);
+            }
+        }
+    }
+}
+
+/// Emit a line captured from the child, in json mode only and whatever the
+/// log level. `timestamp` is the line's key, not when it's emitted.
+pub fn emit_output(event: &str, timestamp: u64, line: &str) {
+    if log_format() == LogFormat::Json {
+        write_line(&output_line(timestamp, app_name(), event, line));
+    }
+}
+
+/// The json line [`dispatch`] writes, `None` when `level` is below the log
+/// level `threshold`.
+pub fn runner_line(
+    level: LogLevel,
+    threshold: LogLevel,
+    timestamp: u64,
+    app_name: &str,
+    event: &str,
+    message: &str,
+) -> Option<String> {
+    if level_rank(level) < level_rank(threshold) {
+        return None;
+    }
+    let level = format!("{:?}", level).to_lowercase();
+    Some(json_line(timestamp, &level, app_name, event, message))
+}
+
+/// The json line [`emit_output`] writes. Child output isn't the runner's
+/// to filter, so there's no log level to pass.
+pub fn output_line(timestamp: u64, app_name: &str, event: &str, line: &str) -> String {
+    json_line(timestamp, "info", app_name, event, line)
+}
+
+fn json_line(timestamp: u64, level: &str, app_name: &str, event: &str, message: &str) -> String {
+    serde_json::json!({
+        "timestamp": timestamp,
+        "level": level,
+        "app_name": app_name,
+        "pid": std::process::id(),
+        "event": event,
+        "message": message,
+    })
+    .to_string()
+}
+
+fn app_name() -> &'static str {
+    APP_NAME
+        .get()
+        .map(String::as_str)
+        .unwrap_or(env!("CARGO_PKG_NAME"))
+}
+
+fn write_line(line: &str) {
+    let mut stdout = std::io::stdout().lock();
+    _ = writeln!(stdout, "{}", line);
+}
+
+fn level_rank(level: LogLevel) -> u8 {
+    match format!("{:?}", level).as_str() {
+        "Trace" => 0,
+        "Debug" => 1,
+        "Info" => 2,
+        "Warn" => 3,
+        _ => 4,
+    }
+}
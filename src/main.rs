@@ -1,536 +1,95 @@
 //! Executable entrypoint for the process manager.
 //!
 //! This binary spawns a supervised child process, monitors a directory for
-//! changes and restarts the child when necessary.  High level state is
-//! persisted across restarts using [`AppState`].
+//! changes and restarts the child when necessary. The supervision itself
+//! lives in [`ais_runner::runner`], this only parses the command line.
 
-use crate::{
-    config::{default_env_location, default_secret_server}, global_child::{
-        get_query, init_child, init_monitor, replace_child, GLOBAL_CHILD, GLOBAL_CLINENT_CONNECTION, GLOBAL_MONITOR
-    }, secrets::{SecretClient, SecretQuery}
+use ais_runner::{
+    cli::{self, Cli, Command},
+    dry_run, log,
+    runner::Runner,
 };
-use artisan_middleware::{
-    aggregator::Status,
-    config::AppConfig,
-    dusa_collection_utils::{
-        self,
-        core::{
-            errors::ErrorArray,
-            logger::{get_log_level, set_log_level},
-        },
-    },
-    process_manager::SupervisedChild,
-    state_persistence::{AppState, StatePersistence, log_error, update_state, wind_down_state},
-};
-use child::{create_child, run_install_process, run_one_shot_process};
-use config::{generate_application_state, get_config, specific_config};
-use std::io::Write;
-
-use dir_watcher::{MonitorMode, Options, RawFileMonitor, RecursiveMode};
-use dusa_collection_utils::{
-    core::errors::{ErrorArrayItem, Errors},
-    core::logger::LogLevel,
-    core::types::pathtype::PathType,
-    log,
-};
-use signals::{sighup_watch, sigusr_watch};
-use std::{
-    fs::OpenOptions,
-    sync::{
-        Arc,
-        atomic::{AtomicBool, Ordering},
-    },
-    time::Duration,
-};
-use tokio::time::{sleep, timeout};
-
-mod child;
-mod config;
-mod global_child;
-mod secrets;
-mod signals;
+use artisan_middleware::dusa_collection_utils::core::logger::LogLevel;
+use clap::Parser;
 
 /// Application entrypoint.
 ///
-/// Initializes configuration, loads any persisted state and then enters the monitoring loop.
-
+/// Parses the command line and dispatches to the requested subcommand,
+/// defaulting to [`Runner::run`].
 #[tokio::main]
 async fn main() {
-    // Initialization
-
-    // reading config files
-    log!(LogLevel::Trace, "Initializing application...");
-    let mut config: AppConfig = get_config();
-    let state_path: PathType = StatePersistence::get_state_path(&config);
-
-    log!(LogLevel::Trace, "Loading specific configuration...");
-    let settings = match specific_config() {
-        Ok(loaded_data) => {
-            log!(
-                LogLevel::Trace,
-                "Loaded specific configuration successfully"
-            );
-            loaded_data
-        }
-        Err(e) => {
-            log!(LogLevel::Error, "Error loading settings: {}", e);
-            std::process::exit(0)
-        }
-    };
-
-    // Setting up the state of the application
-    log!(LogLevel::Trace, "Setting up the application state...");
-    let mut state: AppState = generate_application_state(&state_path, &config).await;
-
-    // Listening for the sighup
-    let reload: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
-    let exit_graceful: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
-
-    sighup_watch(reload.clone());
-    sigusr_watch(exit_graceful.clone());
-
-    log!(LogLevel::Trace, "Setting state as active...");
-    update_state(&mut state, &state_path, None).await;
-
-    if config.debug_mode {
-        log!(LogLevel::Info, "Application State: {}", state);
-        log!(LogLevel::Info, "Application State: {}", settings);
-        log!(LogLevel::Info, "Log Level: {}", config.log_level);
-    }
-
-    // requesting enviornment data
-    let env_path: PathType = PathType::Content(settings.env_file_location.clone());
-    let env_dummy: PathType = PathType::Content(default_env_location());
-    if env_dummy == env_path {
-        log!(LogLevel::Warn, "No env file location specified skipping...");
-        return;
-    }
-    _ = env_path.delete();
-
-    let query: SecretQuery = match get_query() {
-        Ok(q) => q,
-        Err(_) => {
-            log!(LogLevel::Error, "Error loading env query");
-            std::process::exit(0)
-        }
-    };
-
-    if &settings.secret_server_addr == &default_secret_server() {
-        log!(LogLevel::Warn, "No secret server address defined, skipping ...");
-        return
+    let cli = Cli::parse();
+    if let Err(err) = cli.apply_globals() {
+        log!(LogLevel::Error, "{}", err);
+        std::process::exit(1)
     }
 
-    let client = match SecretClient::connect(&settings.secret_server_addr).await {
-        Ok(c) => c,
-        Err(err) => {
-            log!(
-                LogLevel::Error,
-                "Error dialing secret server: {}",
-                err.to_string()
-            );
-            std::process::exit(0)
+    let command = cli.command.unwrap_or(Command::Run);
+    let audited = command.audit_description();
+    let result = match command {
+        Command::Run if cli.explain_config => {
+            print!("{}", cli::explain_config());
+            Ok(())
         }
-    };
-
-    match query.get_all(client.clone()).await {
-        Ok(results) => {
-            if results.is_empty() {
-                log!(
-                    LogLevel::Debug,
-                    "No env data for current runtime: id: {} env: {}",
-                    query.runner_id,
-                    query.enviornment_id
-                );
-
-                return;
-            }
-
-            // formatting results to write
-            let mut lines: Vec<String> = Vec::new();
-            results.iter().for_each(|item| {
-                lines.push(format!("{}={}\n", item.0, str::from_utf8(&item.1).unwrap()));
-            });
-
-            // Opening file
-            let mut options = OpenOptions::new();
-            options.create_new(true);
-            let mut file = match options.open(env_path) {
-                Ok(file) => file,
-                Err(err) => {
-                    log!(
-                        LogLevel::Error,
-                        "Failed to open env file: {}",
-                        err.to_string()
-                    );
-                    std::process::exit(100);
-                }
-            };
-
-            // Writing
-            lines.iter().for_each(|line| {
-                if let Err(err) = write!(file, "{}", line) {
-                    log!(
-                        LogLevel::Warn,
-                        "Lines maybe missing from the env file: {}",
-                        err.to_string()
-                    )
-                }
-            });
-
-            // Closing file
-            _ = file.flush();
-        }
-        Err(err) => ErrorArray::from(err).display(true),
-    }
-
-    match GLOBAL_CLINENT_CONNECTION.try_lock() {
-        Ok(mut store) => *store = Some(client),
-        Err(err) => {
-            log!(
-                LogLevel::Error,
-                "Error storing secret server connection: {}",
-                err.to_string()
-            );
-            std::process::exit(0)
+        Command::Run if cli.dry_run => {
+            let report = dry_run::execute().await;
+            report.print();
+            std::process::exit(if report.passed() { 0 } else { 1 })
         }
-    }
-
-    log!(LogLevel::Debug, "Copied secret data from the server");
-
-    log!(LogLevel::Info, "{} Started", config.app_name);
-
-    state.status = Status::Building;
-    log!(LogLevel::Debug, "Application status: {}", state.status);
-    update_state(&mut state, &state_path, None).await;
-    if settings.install_command.is_some() {
-        log!(LogLevel::Trace, "Running install step");
-        if let Err(err) = run_install_process(&settings, &mut state, &state_path).await {
-            log!(LogLevel::Error, "{}", err)
+        Command::Run => {
+            Runner::builder()
+                .init(cli.init)
+                .oneshot(cli.oneshot)
+                .build()
+                .run()
+                .await;
+            Ok(())
         }
-    }
-
-    // Spawn child process
-    log!(LogLevel::Trace, "Running one shot pre child");
-    if settings.build_command.is_some() {
-        log!(LogLevel::Trace, "Running build step");
-        if let Err(err) = run_one_shot_process(&settings, &mut state, &state_path).await {
-            log!(LogLevel::Error, "One-shot process failed: {}", err);
-            log_error(&mut state, err, &state_path).await;
-            return;
+        Command::Status => cli::status().await,
+        Command::States { json } => cli::states(json),
+        Command::Logs {
+            lines,
+            stderr,
+            follow,
+        } => cli::logs(lines, stderr, follow).await,
+        Command::Restart { note, no_cache } => cli::restart(note, no_cache).await,
+        Command::History { lines, json } => cli::history(lines, json).await,
+        Command::Trace { deploys, json } => cli::trace(deploys, json).await,
+        Command::Report { period, json } => cli::report(&period, json).await,
+        Command::Annotate { note, last } => cli::annotate(note, last).await,
+        Command::RestoreLastKnownGood => cli::restore_last_known_good().await,
+        Command::Maintenance { action } => cli::maintenance(action).await,
+        Command::LogLevel { level } => cli::log_level(level).await,
+        Command::Limits {
+            memory_mb,
+            cpu_percent,
+        } => cli::limits(memory_mb, cpu_percent).await,
+        Command::Dump => cli::dump().await,
+        Command::SendStdin { text, no_newline } => cli::send_stdin(text, no_newline).await,
+        Command::Reset {
+            errors,
+            output,
+            counters,
+            all,
+        } => cli::reset(errors, output, counters, all).await,
+        Command::Audit { lines } => cli::audit(lines),
+        Command::ValidateConfig => cli::validate_config(),
+        Command::MigrateFromSystemd { unit, output } => {
+            cli::migrate_from_systemd(&unit, output.as_deref())
         }
-    }
-
-    log!(LogLevel::Trace, "Spawning child process...");
-
-    let mut child: SupervisedChild = create_child(&mut state, &state_path, &settings).await;
-    child.monitor_stdx().await;
-    child.monitor_usage().await;
-    init_child(child.clone().await).await;
-
-    let mut change_count = 0;
-    let trigger_count = settings.changes_needed;
-    state.status = Status::Running;
-    log!(LogLevel::Debug, "Application status: {}", state.status);
-    update_state(&mut state, &state_path, None).await;
-
-    // Start monitoring the directory and get the asynchronous receiver
-    log!(LogLevel::Debug, "Starting directory monitoring...");
-    let options: Options = Options::default()
-        .set_mode(RecursiveMode::Recursive)
-        .set_monitor_mode(MonitorMode::Modify)
-        .add_ignored_dirs(settings.ignored_paths())
-        .set_target_dir(settings.safe_path())
-        .set_interval(settings.interval_seconds.into())
-        .set_validation(true);
-
-    let monitor: RawFileMonitor = RawFileMonitor::new(options.clone()).await;
-    monitor.start().await;
-
-    let mut event_rx = match monitor.subscribe().await {
-        Some(rx) => rx,
-        None => {
-            log!(LogLevel::Error, "Failed to subscribe to the dir monitor");
-            state.error_log.push(ErrorArrayItem::new(
-                Errors::GeneralError,
-                "Failed to subscribe to the dir monitor",
-            ));
-            wind_down_state(&mut state, &state_path).await;
-            std::process::exit(100);
+        Command::ExportSystemd { output, env_file } => {
+            cli::export_systemd(output.as_deref(), env_file.as_deref())
         }
+        Command::ExportBundle { key, output } => cli::export_bundle(&key, output.as_deref()),
+        Command::ImportBundle { bundle, key, force } => cli::import_bundle(&bundle, &key, force),
     };
 
-    init_monitor(monitor).await;
-
-    log!(LogLevel::Trace, "Entering main loop...");
-    state.status = Status::Running;
-    update_state(&mut state, &state_path, None).await;
-    loop {
-        tokio::select! {
-            Some(event) = event_rx.recv() => {
-                log!(LogLevel::Trace, "Received directory change event: {:?}", event);
-                change_count += 1;
-                log!(LogLevel::Info, "Change detected: {} out of {}", change_count, trigger_count);
-                log!(LogLevel::Debug, "Event details: {:?}", event);
-
-                if change_count >= trigger_count {
-                    if let Some(monitor) = GLOBAL_MONITOR.lock().await.as_mut() {
-                        monitor.pause();
-                    }
-
-                    // monitor;
-                    log!(LogLevel::Info, "Reached {} changes, handling event", trigger_count);
-                    state.event_counter += 1;
-                    state.status = Status::Building;
-                    log!(LogLevel::Debug, "Application status: {}", state.status);
-                    update_state(&mut state, &state_path, None).await;
-
-                    if let Some(child) = GLOBAL_CHILD.lock().await.as_mut() {
-                        if let Err(err) = child.kill().await {
-                            log!(LogLevel::Error, "Error killing child: {}, requesting reload", err.err_mesg);
-                            reload.store(true, Ordering::Relaxed);
-                        }
-                    }
-
-                    { // This coupled with kill_on_drop ensures that even if we don't properly kill the application it get's nuked
-                        let mut _raw_child = GLOBAL_CHILD.lock().await.as_mut();
-                        _raw_child = None;
-                        sleep(Duration::from_millis(20)).await;
-                    }
-
-                    if !child.running().await {
-                        log!(LogLevel::Info, "Killed the child!");
-                    }
-
-                    // Spawn child process
-                    log!(LogLevel::Trace, "Running one shot pre child");
-                    if settings.build_command.is_some() {
-                        log!(LogLevel::Info, "Running build step");
-                        if let Err(err) = run_one_shot_process(&settings, &mut state, &state_path).await {
-                            log!(LogLevel::Error, "One-shot process failed: {}", err);
-                            log_error(&mut state, err, &state_path).await;
-                            return;
-                        }
-                    }
-
-                    replace_child(create_child(&mut state, &state_path, &settings).await).await;
-                    if let Some(child) = GLOBAL_CHILD.lock().await.as_mut() {
-                        child.monitor_stdx().await;
-                        child.monitor_usage().await;
-                    };
-
-                    if let Some(monitor) = GLOBAL_MONITOR.lock().await.as_mut() {
-                        monitor.resume();
-                    }
-
-                    change_count = 0; // Reset count
-                    state.status = Status::Running;
-                    log!(LogLevel::Debug, "Application status: {}", state.status);
-                }
-            }
-            _ = tokio::time::sleep(Duration::from_secs(5)) => {
-                log!(LogLevel::Trace, "Periodic task triggered - checking child process status...");
-
-                let mut respawn_child = false;
-
-                // Getting stds from child and cheking it's pulse
-                if let Some(child) = GLOBAL_CHILD.lock().await.as_mut() {
-                    // Getting the stds out
-
-                    { // Standard Out
-                        let current_std_out = if let Ok(stdout) = child.get_std_out().await {
-                            stdout
-                        } else {
-                            Vec::new()
-                        };
-
-                        if !current_std_out.is_empty() {
-                            let new_values: Vec<(u64, String)> = current_std_out
-                                .into_iter()
-                                .filter(|val| !state.stdout.contains(val))
-                                .collect();
-
-                            state.stdout.extend(new_values);
-                            state.stdout.sort_by_key(|val| val.0);
-                            state.stdout.dedup();
-                        }
-                    }
-
-                    { // Standard Err
-                        let current_std_err = if let Ok(stderr) = child.get_std_err().await {
-                            stderr
-                        } else {
-                            Vec::new()
-                        };
-
-                        if !current_std_err.is_empty() {
-                            let new_values: Vec<(u64, String)> = current_std_err
-                                .into_iter()
-                                .filter(|val| !state.stderr.contains(val))
-                                .collect();
-
-                            state.stderr.extend(new_values);
-                            state.stderr.sort_by_key(|val| val.0);
-                            state.stderr.dedup();
-                        }
-                    }
-
-                    if !child.running().await {
-                        respawn_child = true;
-                    }
-                } else {
-                    log!(LogLevel::Warn, "Failed to lock child for periodic checks skipping");
-                }
-
-                // Handling re-spawning child.
-                if respawn_child {
-                    log!(LogLevel::Warn, "Child process {:?} is not running. Restarting...", child.get_pid().await);
-
-                    if let Ok(_) = child.kill().await {
-                        log!(LogLevel::Info, "Executed the previous child")
-                    }
-
-                    if settings.build_command.is_some() {
-                        if let Err(err) = run_one_shot_process(&settings, &mut state, &state_path).await {
-                            log!(LogLevel::Error, "One-shot process failed: {}", err);
-                            log_error(&mut state, err, &state_path).await;
-                            return;
-                        }
-                    }
-
-                    log!(LogLevel::Info, "One shot finished, Spawning new child");
-
-                    replace_child(create_child(&mut state, &state_path, &settings).await).await;
-                    if let Some(child) = GLOBAL_CHILD.lock().await.as_mut() {
-                        child.monitor_stdx().await;
-                        child.monitor_usage().await;
-                    };
-
-                    // logging
-                    let message = "New child process spawned";
-                    log!(LogLevel::Info, "{message}");
-                    state.data = message.to_string();
-                    state.status = Status::Running;
-                    log!(LogLevel::Debug, "Application status: {}", state.status);
-                    update_state(&mut state, &state_path, None).await;
-                }
-
-
-                // Cleaning up the state file
-                state.error_log.dedup();
-                if state.error_log.len() >= 5 {
-                    state.error_log.remove(0);
-                }
-
-                { // Collecting metrics data to add to state
-                    state.data = String::from("Nominal");
-                    if let Ok(metrics) = child.get_metrics().await {
-                        // Ensuring we are within the specified limits
-                        if metrics.memory_usage >= state.config.max_ram_usage as f64 {
-                            state.error_log.push(ErrorArrayItem::new(Errors::OverRamLimit, "Application has exceeded ram limit"))
-                        }
-                        state.status = Status::Running;
-                        log!(LogLevel::Debug, "Application status: {}", state.status);
-                        update_state(&mut state, &state_path, Some(metrics)).await;
-                    } else {
-                        state.data = String::from("Failed to get metric data");
-                        state.error_log.push(ErrorArrayItem::new(Errors::GeneralError, "Failed to get metric data from the child"));
-                        state.status = Status::Warning;
-                        log!(LogLevel::Debug, "Application status: {}", state.status);
-                        update_state(&mut state, &state_path, None).await;
-                    }
-                }
-            }
-
-            _ = tokio::signal::ctrl_c() => {
-                log!(LogLevel::Info, "CTRL + C recieved");
-                exit_graceful.store(true, Ordering::Relaxed);
-            }
-        }
-
-        if reload.load(Ordering::Relaxed) {
-            log!(LogLevel::Debug, "Reloading");
-            state.status = Status::Idle;
-            log!(LogLevel::Debug, "Application status: {}", state.status);
-
-            // reload config file
-            config = get_config();
-
-            // Updating state data
-            state = generate_application_state(&state_path, &config).await;
-
-            // Killing and redrawing the process
-            if let Err(err) = child.kill().await {
-                log_error(&mut state, err, &state_path).await;
-                wind_down_state(&mut state, &state_path).await;
-                // We're in a weird state kys and let systemd try again.
-                std::process::exit(100)
-            }
-
-            // running one shot again if configured
-            if settings.build_command.is_some() {
-                if let Err(err) = run_one_shot_process(&settings, &mut state, &state_path).await {
-                    log!(LogLevel::Error, "One-shot process failed: {}", err);
-                    log_error(&mut state, err, &state_path).await;
-                    return;
-                }
-            }
-
-            // creating new service
-            replace_child(create_child(&mut state, &state_path, &settings).await).await;
-            if let Some(child) = GLOBAL_CHILD.lock().await.as_mut() {
-                child.monitor_stdx().await;
-                child.monitor_usage().await;
-            };
-
-            log!(LogLevel::Info, "New child process spawned.");
-            reload.store(false, Ordering::Relaxed);
-            state.status = Status::Running;
-            log!(LogLevel::Debug, "Application status: {}", state.status);
-        }
-
-        if exit_graceful.load(Ordering::Relaxed) {
-            log!(LogLevel::Debug, "Exiting gracefully");
-            match timeout(Duration::from_secs(5), child.kill()).await {
-                Ok(execution_result) => match execution_result {
-                    Ok(_) => {
-                        state.status = Status::Stopping;
-                        wind_down_state(&mut state, &state_path).await;
-                        std::process::exit(0);
-                    }
-                    Err(err) => {
-                        state.status = Status::Stopping;
-                        log!(LogLevel::Error, "{}", err);
-                        state.error_log.push(err);
-                        wind_down_state(&mut state, &state_path).await;
-                        std::process::exit(100);
-                    }
-                },
-                Err(err) => {
-                    log!(LogLevel::Error, "{}", err);
-                    log!(
-                        LogLevel::Error,
-                        "We hit the timeout while gracefully shutting down. You might have to run systemctl kill ais_xxx to ensure you start correctly nextime"
-                    );
-                    log_error(
-                        &mut state,
-                        ErrorArrayItem::new(Errors::TimedOut, err.to_string()),
-                        &state_path,
-                    )
-                    .await;
-                    wind_down_state(&mut state, &state_path).await;
-                    std::process::exit(100);
-                }
-            }
-        }
+    if let Some(description) = audited {
+        cli::record_audit(description, &result);
+    }
 
-        if state.config.debug_mode {
-            let log_level = get_log_level();
-            set_log_level(LogLevel::Trace);
-            log!(LogLevel::Trace, "printing std out");
-            for lines in &state.stdout {
-                log!(LogLevel::Debug, "{}", lines.1);
-            }
-            set_log_level(log_level);
-        }
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        std::process::exit(1)
     }
 }
@@ -0,0 +1,105 @@
+//! Maintenance mode.
+//!
+//! Operators sometimes have to edit files under `monitor_path` without every
+//! save triggering a rebuild, or the runner respawning a child they stopped
+//! on purpose. While maintenance mode is on the directory monitor is paused,
+//! changes, scheduled restarts and webhooks are ignored, failing liveness
+//! probes don't restart the child, a child that exits is left down and
+//! restarts for rotated secrets wait until it's off. Logs and metrics are
+//! still collected.
+//!
+//! It is switched on by a `.ais_maintenance` file in `monitor_path`, or the
+//! first of `monitor_paths` (which `ais_runner maintenance on|off` manages),
+//! or toggled with `SIGUSR2`, and stays on while either is set. Both survive
+//! a restart of the runner, the toggle through the runner state.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+/// Flag file switching maintenance mode on, relative to `monitor_path`.
+pub const FLAG_FILE: &str = ".ais_maintenance";
+
+/// Where the flag file for `monitor_path` lives.
+pub fn flag_path(monitor_path: &str) -> PathBuf {
+    Path::new(monitor_path).join(FLAG_FILE)
+}
+
+/// What switched maintenance mode on.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MaintenanceSource {
+    Signal,
+    FlagFile,
+}
+
+/// Maintenance mode as recorded in the runner state.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct MaintenanceInfo {
+    pub since: u64,
+    pub source: MaintenanceSource,
+}
+
+/// A change noticed by [`Maintenance::poll`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MaintenanceChange {
+    Entered(MaintenanceSource),
+    Left,
+}
+
+/// Tracks whether maintenance mode is on.
+#[derive(Debug)]
+pub struct Maintenance {
+    flag: PathBuf,
+    toggled: Arc<AtomicBool>,
+    active: Option<MaintenanceSource>,
+}
+
+impl Maintenance {
+    /// `toggled` is flipped by the `SIGUSR2` handler.
+    pub fn new(flag: PathBuf, toggled: Arc<AtomicBool>) -> Self {
+        Self {
+            flag,
+            toggled,
+            active: None,
+        }
+    }
+
+    /// Switch the signal toggle back on if it was what put the last run in
+    /// maintenance, the flag file survives restarts by itself.
+    pub fn restore(&self, previous: Option<&MaintenanceInfo>) {
+        if previous.is_some_and(|info| info.source == MaintenanceSource::Signal) {
+            self.toggled.store(true, Ordering::Relaxed);
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active.is_some()
+    }
+
+    /// Look at the signal toggle and the flag file again, returning what
+    /// changed since the last call.
+    pub fn poll(&mut self) -> Option<MaintenanceChange> {
+        let source = match (self.toggled.load(Ordering::Relaxed), self.flag.exists()) {
+            (true, _) => Some(MaintenanceSource::Signal),
+            (false, true) => Some(MaintenanceSource::FlagFile),
+            (false, false) => None,
+        };
+
+        // A change of source counts as entering again so it gets recorded
+        let change = match (self.active, source) {
+            (active, Some(source)) if active != Some(source) => {
+                Some(MaintenanceChange::Entered(source))
+            }
+            (Some(_), None) => Some(MaintenanceChange::Left),
+            _ => None,
+        };
+        self.active = source;
+        change
+    }
+}
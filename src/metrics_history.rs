@@ -0,0 +1,410 @@
+//! Rolling history of the child's CPU and memory usage.
+//!
+//! The metrics in the state file are overwritten on every check, which is
+//! enough to see how the child is doing now but not whether its memory has
+//! been creeping up all day. The runner downsamples every check into
+//! buckets of `resolution_seconds` (average CPU, average and peak memory)
+//! and keeps the last `window_hours` of them in a ring, written after every
+//! closed bucket to a compact binary `<state file>.metrics` next to the
+//! state. `status` shows the trend and the state sync listener serves the
+//! buckets at `/metrics`.
+//!
+//! ```toml
+//! [app_specific.metrics_history]
+//! window_hours = 24              # 0 keeps no history
+//! resolution_seconds = 60
+//! leak_warn_mb_per_hour = 20     # warn while memory grows faster
+//! ```
+
+use artisan_middleware::dusa_collection_utils::core::types::pathtype::PathType;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+/// `[app_specific.metrics_history]`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct MetricsHistoryConfig {
+    /// How far back the history goes.
+    #[serde(default = "default_window_hours")]
+    pub window_hours: u64,
+    /// Length of a bucket.
+    #[serde(default = "default_resolution_seconds")]
+    pub resolution_seconds: u64,
+    /// Log a warning while memory grows faster than this over the window.
+    #[serde(default)]
+    pub leak_warn_mb_per_hour: Option<f64>,
+}
+
+impl Default for MetricsHistoryConfig {
+    fn default() -> Self {
+        Self {
+            window_hours: default_window_hours(),
+            resolution_seconds: default_resolution_seconds(),
+            leak_warn_mb_per_hour: None,
+        }
+    }
+}
+
+fn default_window_hours() -> u64 {
+    24
+}
+
+fn default_resolution_seconds() -> u64 {
+    60
+}
+
+impl MetricsHistoryConfig {
+    pub fn enabled(&self) -> bool {
+        self.window_hours > 0
+    }
+
+    fn resolution(&self) -> u64 {
+        self.resolution_seconds.max(1)
+    }
+
+    /// Number of buckets in the window.
+    fn capacity(&self) -> usize {
+        (self.window_hours * 3600 / self.resolution()).max(1) as usize
+    }
+}
+
+/// History location for the state file at `state_path`.
+pub fn path(state_path: &PathType) -> PathBuf {
+    PathBuf::from(format!("{}.metrics", state_path))
+}
+
+/// Usage over one bucket.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct Bucket {
+    /// Unix time the bucket starts at.
+    pub start: u64,
+    /// Number of checks that went into the bucket.
+    pub samples: u32,
+    /// Average CPU usage in percent.
+    pub cpu: f32,
+    /// Average memory usage in MB.
+    pub memory: f32,
+    /// Peak memory usage in MB.
+    pub memory_max: f32,
+}
+
+/// Merge `buckets` into one starting at `start`, weighting by samples.
+fn combine(start: u64, buckets: &[Bucket]) -> Bucket {
+    let samples: u32 = buckets.iter().map(|bucket| bucket.samples).sum();
+    let weight = samples.max(1) as f64;
+    let average = |value: fn(&Bucket) -> f32| {
+        buckets
+            .iter()
+            .map(|bucket| value(bucket) as f64 * bucket.samples as f64)
+            .sum::<f64>()
+            / weight
+    };
+    Bucket {
+        start,
+        samples,
+        cpu: average(|bucket| bucket.cpu) as f32,
+        memory: average(|bucket| bucket.memory) as f32,
+        memory_max: buckets
+            .iter()
+            .map(|bucket| bucket.memory_max)
+            .fold(0.0, f32::max),
+    }
+}
+
+/// Downsample `buckets` (oldest first) into buckets `span` seconds long.
+pub fn regroup(buckets: &[Bucket], span: u64) -> Vec<Bucket> {
+    let span = span.max(1);
+    let mut grouped = Vec::new();
+    let mut group: Vec<Bucket> = Vec::new();
+    for bucket in buckets {
+        let start = bucket.start - bucket.start % span;
+        if group
+            .first()
+            .is_some_and(|first| first.start - first.start % span != start)
+        {
+            let first = group[0].start;
+            grouped.push(combine(first - first % span, &group));
+            group.clear();
+        }
+        group.push(*bucket);
+    }
+    if let Some(first) = group.first() {
+        grouped.push(combine(first.start - first.start % span, &group));
+    }
+    grouped
+}
+
+/// The bucket still taking samples.
+#[derive(Debug, Clone)]
+struct Open {
+    start: u64,
+    samples: u32,
+    cpu_sum: f64,
+    memory_sum: f64,
+    memory_max: f64,
+}
+
+impl Open {
+    fn new(start: u64) -> Self {
+        Self {
+            start,
+            samples: 0,
+            cpu_sum: 0.0,
+            memory_sum: 0.0,
+            memory_max: 0.0,
+        }
+    }
+
+    fn close(&self) -> Bucket {
+        let samples = self.samples.max(1) as f64;
+        Bucket {
+            start: self.start,
+            samples: self.samples,
+            cpu: (self.cpu_sum / samples) as f32,
+            memory: (self.memory_sum / samples) as f32,
+            memory_max: self.memory_max as f32,
+        }
+    }
+}
+
+/// What `/metrics` answers with.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Report {
+    /// Length of the returned buckets in seconds.
+    pub resolution_seconds: u64,
+    /// Memory growth over the window, see [`MetricsHistory::memory_trend`].
+    pub memory_trend_mb_per_hour: Option<f64>,
+    pub buckets: Vec<Bucket>,
+}
+
+const MAGIC: &[u8; 4] = b"AISM";
+const VERSION: u8 = 1;
+const HEADER_LEN: usize = 9;
+const RECORD_LEN: usize = 24;
+/// Leak warnings are repeated at most this often.
+const LEAK_WARN_INTERVAL: u64 = 3600;
+
+/// The ring of closed buckets plus the one being filled.
+#[derive(Debug, Clone)]
+pub struct MetricsHistory {
+    resolution: u64,
+    capacity: usize,
+    buckets: VecDeque<Bucket>,
+    open: Option<Open>,
+    last_leak_warning: Option<u64>,
+}
+
+/// The history shared between the main loop and the state sync listener.
+pub type SharedMetricsHistory = Arc<Mutex<MetricsHistory>>;
+
+impl MetricsHistory {
+    pub fn new(config: &MetricsHistoryConfig) -> Self {
+        Self {
+            resolution: config.resolution(),
+            capacity: config.capacity(),
+            buckets: VecDeque::new(),
+            open: None,
+            last_leak_warning: None,
+        }
+    }
+
+    pub fn resolution_seconds(&self) -> u64 {
+        self.resolution
+    }
+
+    /// Closed buckets, oldest first.
+    pub fn buckets(&self) -> Vec<Bucket> {
+        self.buckets.iter().copied().collect()
+    }
+
+    /// Take in a check at `now` (unix seconds). Returns whether a bucket
+    /// was closed, which is when the history is worth saving.
+    pub fn record(&mut self, now: u64, cpu: f32, memory: f64) -> bool {
+        let start = now - now % self.resolution;
+        let (mut open, closed) = match self.open.take() {
+            Some(open) if open.start == start => (open, None),
+            previous => (Open::new(start), previous),
+        };
+        open.samples += 1;
+        open.cpu_sum += cpu as f64;
+        open.memory_sum += memory;
+        open.memory_max = open.memory_max.max(memory);
+        self.open = Some(open);
+
+        match closed {
+            Some(previous) => {
+                self.push(previous.close());
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn push(&mut self, bucket: Bucket) {
+        self.buckets.push_back(bucket);
+        // Buckets older than the window are dropped with gaps taken into account
+        let window = self.resolution * self.capacity as u64;
+        while self
+            .buckets
+            .front()
+            .is_some_and(|front| front.start + window <= bucket.start)
+            || self.buckets.len() > self.capacity
+        {
+            self.buckets.pop_front();
+        }
+    }
+
+    /// Memory growth in MB per hour, the least squares slope over the
+    /// window. `None` until the history spans an hour.
+    pub fn memory_trend(&self) -> Option<f64> {
+        let first = self.buckets.front()?.start;
+        let last = self.buckets.back()?.start;
+        if last - first < 3600 {
+            return None;
+        }
+
+        let points: Vec<(f64, f64)> = self
+            .buckets
+            .iter()
+            .map(|bucket| ((bucket.start - first) as f64 / 3600.0, bucket.memory as f64))
+            .collect();
+        let count = points.len() as f64;
+        let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / count;
+        let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / count;
+        let covariance: f64 = points
+            .iter()
+            .map(|(x, y)| (x - mean_x) * (y - mean_y))
+            .sum();
+        let variance: f64 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+        Some(covariance / variance)
+    }
+
+    /// The trend when it's above `threshold` and no warning was given in
+    /// the last hour before `now`.
+    pub fn leak(&mut self, now: u64, threshold: f64) -> Option<f64> {
+        let trend = self.memory_trend().filter(|trend| *trend > threshold)?;
+        if self
+            .last_leak_warning
+            .is_some_and(|last| now.saturating_sub(last) < LEAK_WARN_INTERVAL)
+        {
+            return None;
+        }
+        self.last_leak_warning = Some(now);
+        Some(trend)
+    }
+
+    /// The window in at most `points` buckets, all of them without a limit.
+    pub fn report(&self, points: Option<usize>) -> Report {
+        let buckets = self.buckets();
+        let span = match points {
+            Some(points) => {
+                let window = self.resolution * self.capacity as u64;
+                let span = window.div_ceil(points.max(1) as u64);
+                span.div_ceil(self.resolution) * self.resolution
+            }
+            None => self.resolution,
+        };
+        Report {
+            resolution_seconds: span,
+            memory_trend_mb_per_hour: self.memory_trend(),
+            buckets: match span == self.resolution {
+                true => buckets,
+                false => regroup(&buckets, span),
+            },
+        }
+    }
+
+    /// The closed buckets in the on-disk format: a header with the
+    /// resolution, then a fixed size record per bucket.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN + RECORD_LEN * self.buckets.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&(self.resolution as u32).to_le_bytes());
+        for bucket in &self.buckets {
+            bytes.extend_from_slice(&bucket.start.to_le_bytes());
+            bytes.extend_from_slice(&bucket.samples.to_le_bytes());
+            bytes.extend_from_slice(&bucket.cpu.to_le_bytes());
+            bytes.extend_from_slice(&bucket.memory.to_le_bytes());
+            bytes.extend_from_slice(&bucket.memory_max.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Read back [`encode`](Self::encode)d buckets into a history for
+    /// `config`, downsampling them if they were recorded at a finer
+    /// resolution.
+    pub fn decode(bytes: &[u8], config: &MetricsHistoryConfig) -> Result<Self, String> {
+        if bytes.len() < HEADER_LEN || &bytes[..4] != MAGIC {
+            return Err(String::from("not a metrics history"));
+        }
+        if bytes[4] != VERSION {
+            return Err(format!("unsupported metrics history version {}", bytes[4]));
+        }
+        let records = &bytes[HEADER_LEN..];
+        if !records.len().is_multiple_of(RECORD_LEN) {
+            return Err(String::from("truncated metrics history"));
+        }
+        let resolution = u32::from_le_bytes(bytes[5..HEADER_LEN].try_into().unwrap_or_default());
+
+        let field = |record: &[u8], at: usize| -> [u8; 4] {
+            record[at..at + 4].try_into().unwrap_or_default()
+        };
+        let buckets: Vec<Bucket> = records
+            .chunks_exact(RECORD_LEN)
+            .map(|record| Bucket {
+                start: u64::from_le_bytes(record[..8].try_into().unwrap_or_default()),
+                samples: u32::from_le_bytes(field(record, 8)),
+                cpu: f32::from_le_bytes(field(record, 12)),
+                memory: f32::from_le_bytes(field(record, 16)),
+                memory_max: f32::from_le_bytes(field(record, 20)),
+            })
+            .collect();
+
+        let mut history = Self::new(config);
+        let buckets = match resolution as u64 == history.resolution {
+            true => buckets,
+            false => regroup(&buckets, history.resolution),
+        };
+        for bucket in buckets {
+            history.push(bucket);
+        }
+        Ok(history)
+    }
+
+    /// Load the history at `path`, an unreadable or missing file gives an
+    /// empty one.
+    pub fn load(path: &Path, config: &MetricsHistoryConfig) -> Self {
+        fs::read(path)
+            .ok()
+            .and_then(|bytes| Self::decode(&bytes, config).ok())
+            .unwrap_or_else(|| Self::new(config))
+    }
+
+    /// Write the history atomically.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut temp = path.to_path_buf().into_os_string();
+        temp.push(".tmp");
+
+        fs::write(&temp, self.encode())?;
+        fs::rename(&temp, path)
+    }
+}
+
+/// Render `values` as a line of block characters scaled to their range.
+pub fn sparkline(values: &[f64]) -> String {
+    const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let low = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let high = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    values
+        .iter()
+        .map(|value| match high > low {
+            true => BLOCKS[((value - low) / (high - low) * 7.0).round() as usize],
+            false => BLOCKS[0],
+        })
+        .collect()
+}
@@ -0,0 +1,496 @@
+//! Migration between plain systemd services and the runner.
+//!
+//! `migrate-from-systemd <unit>` reads a unit file and renders a starter
+//! Config.toml for it. Only the settings that have a direct equivalent are
+//! carried over (`ExecStart`, `WorkingDirectory`, `Environment=`, `User=`,
+//! resource limits, ...), everything else is listed as a note so the
+//! operator knows what to check by hand. When a listening port can be
+//! guessed, startup and liveness probes are suggested as well.
+//!
+//! `export-systemd` goes the other way and renders the current config as a
+//! standalone unit plus environment file, with the same kind of notes for
+//! runner features that systemd can't provide.
+
+use artisan_middleware::config::AppConfig;
+use std::{collections::BTreeMap, path::Path};
+
+use crate::config::{AppSpecificConfig, default_env_location};
+
+/// A parsed unit file, keeping every assignment in file order.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SystemdUnit {
+    entries: Vec<(String, String, String)>,
+}
+
+impl SystemdUnit {
+    /// Parse the contents of a unit file.
+    ///
+    /// Handles comments, line continuations and repeated keys. An empty
+    /// assignment (`Environment=`) resets the list like systemd does.
+    pub fn parse(content: &str) -> Self {
+        let mut unit = SystemdUnit::default();
+        let mut section = String::new();
+        let mut pending = String::new();
+
+        for raw in content.lines() {
+            let line = raw.trim();
+            if pending.is_empty() && (line.is_empty() || line.starts_with(['#', ';'])) {
+                continue;
+            }
+
+            if let Some(continued) = line.strip_suffix('\\') {
+                pending.push_str(continued.trim_end());
+                pending.push(' ');
+                continue;
+            }
+            pending.push_str(line);
+            let line = std::mem::take(&mut pending);
+
+            if line.starts_with('[') && line.ends_with(']') {
+                section = line[1..line.len() - 1].to_owned();
+                continue;
+            }
+
+            if let Some((key, value)) = line.split_once('=') {
+                let key = key.trim().to_owned();
+                let value = value.trim().to_owned();
+                if value.is_empty() {
+                    unit.entries
+                        .retain(|(s, k, _)| !(s == &section && k == &key));
+                } else {
+                    unit.entries.push((section.clone(), key, value));
+                }
+            }
+        }
+
+        unit
+    }
+
+    /// Last value of `key` in `section`.
+    pub fn get(&self, section: &str, key: &str) -> Option<&str> {
+        self.get_all(section, key).pop()
+    }
+
+    /// Every value of `key` in `section`, in file order.
+    pub fn get_all(&self, section: &str, key: &str) -> Vec<&str> {
+        self.entries
+            .iter()
+            .filter(|(s, k, _)| s == section && k == key)
+            .map(|(_, _, value)| value.as_str())
+            .collect()
+    }
+
+    /// Every key set in `section`, without duplicates.
+    pub fn keys(&self, section: &str) -> Vec<&str> {
+        let mut keys: Vec<&str> = Vec::new();
+        for (s, key, _) in &self.entries {
+            if s == section && !keys.contains(&key.as_str()) {
+                keys.push(key);
+            }
+        }
+        keys
+    }
+}
+
+/// Split an `Environment=` value into its assignments.
+pub fn parse_environment(value: &str) -> Vec<(String, String)> {
+    shell_words::split(value)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|assignment| {
+            let (key, value) = assignment.split_once('=')?;
+            Some((key.to_owned(), value.to_owned()))
+        })
+        .collect()
+}
+
+/// Strip the special executable prefixes (`-`, `@`, `+`, `!`, `:`).
+fn strip_exec_prefix(command: &str) -> &str {
+    command.trim_start_matches(['-', '@', '+', '!', ':'])
+}
+
+/// Best effort guess of the port the service listens on.
+pub fn detect_port(command: &str, env: &BTreeMap<String, String>) -> Option<u16> {
+    let from_env = env
+        .iter()
+        .filter(|(key, _)| key.as_str() == "PORT" || key.ends_with("_PORT"))
+        .find_map(|(_, value)| value.parse().ok());
+    if from_env.is_some() {
+        return from_env;
+    }
+
+    let args = shell_words::split(command).unwrap_or_default();
+    let mut iter = args.iter().peekable();
+    while let Some(arg) = iter.next() {
+        let port = match arg.as_str() {
+            "--port" | "-p" => iter.peek().and_then(|next| next.parse().ok()),
+            _ => match arg.strip_prefix("--port=") {
+                Some(port) => port.parse().ok(),
+                // host:port style bind addresses
+                None => arg
+                    .rsplit_once(':')
+                    .filter(|(host, _)| !host.contains('/'))
+                    .and_then(|(_, port)| port.parse().ok()),
+            },
+        };
+        if port.is_some() {
+            return port;
+        }
+    }
+
+    None
+}
+
+/// Parse a systemd size (`512M`, `2G`, `infinity`) into MB.
+fn size_to_mb(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let number: u64 = value[..split].parse().ok()?;
+    match value[split..].to_ascii_uppercase().as_str() {
+        "" => Some(number / (1024 * 1024)),
+        "K" => Some(number / 1024),
+        "M" => Some(number),
+        "G" => Some(number * 1024),
+        "T" => Some(number * 1024 * 1024),
+        _ => None,
+    }
+}
+
+/// Parse a systemd time span (`30`, `30s`, `2min`) into seconds.
+fn timespan_to_seconds(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let number: u64 = value[..split].parse().ok()?;
+    match value[split..].trim() {
+        "" | "s" | "sec" => Some(number),
+        "m" | "min" => Some(number * 60),
+        "h" | "hr" => Some(number * 3600),
+        _ => None,
+    }
+}
+
+fn quote(value: &str) -> String {
+    toml::Value::String(value.to_owned()).to_string()
+}
+
+/// Result of translating a unit file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Migration {
+    /// Rendered Config.toml.
+    pub config: String,
+    /// Settings that couldn't be carried over.
+    pub notes: Vec<String>,
+}
+
+/// `[Service]` keys that are either carried over by [`migrate`] or covered by
+/// the runner itself (it always restarts the child).
+const HANDLED_KEYS: &[&str] = &[
+    "ExecStart",
+    "ExecStartPre",
+    "WorkingDirectory",
+    "Environment",
+    "User",
+    "Group",
+    "TimeoutStopSec",
+    "MemoryMax",
+    "MemoryLimit",
+    "CPUQuota",
+    "Type",
+    "Restart",
+    "RestartSec",
+];
+
+/// Translate `unit` into a starter Config.toml.
+pub fn migrate(unit: &SystemdUnit, source: &str) -> Result<Migration, String> {
+    let mut notes: Vec<String> = Vec::new();
+
+    let exec_start = unit
+        .get_all("Service", "ExecStart")
+        .first()
+        .map(|command| strip_exec_prefix(command).to_owned())
+        .ok_or_else(|| format!("{} has no ExecStart", source))?;
+    if unit.get_all("Service", "ExecStart").len() > 1 {
+        notes.push(String::from(
+            "Only the first ExecStart was used, the runner supervises a single command",
+        ));
+    }
+    if exec_start.contains('%') {
+        notes.push(String::from(
+            "ExecStart uses systemd specifiers (%i, %h, ...), replace them with literal values",
+        ));
+    }
+
+    let project_path = match unit.get("Service", "WorkingDirectory") {
+        // A leading `-` only makes a missing directory non-fatal
+        Some(dir) => dir.trim_start_matches('-').to_owned(),
+        None => {
+            notes.push(String::from(
+                "No WorkingDirectory set, project_path defaults to the current directory",
+            ));
+            String::from("./")
+        }
+    };
+
+    let mut env: BTreeMap<String, String> = BTreeMap::new();
+    for value in unit.get_all("Service", "Environment") {
+        env.extend(parse_environment(value));
+    }
+    for file in unit.get_all("Service", "EnvironmentFile") {
+        notes.push(format!(
+            "EnvironmentFile {} isn't imported, move its variables into [app_specific.env] or the secret server",
+            file
+        ));
+    }
+
+    let mut lines: Vec<String> = vec![
+        format!(
+            "# Generated by `ais_runner migrate-from-systemd` from {}",
+            source
+        ),
+        String::from("[app_specific]"),
+        String::from("interval_seconds = 1"),
+        format!("monitor_path = {}", quote(&project_path)),
+        format!("project_path = {}", quote(&project_path)),
+        String::from("changes_needed = 1"),
+        String::from("ignored_subdirs = [\".git\"]"),
+        format!("run_command = {}", quote(&exec_start)),
+    ];
+
+    for (index, pre) in unit.get_all("Service", "ExecStartPre").iter().enumerate() {
+        let pre = quote(strip_exec_prefix(pre));
+        match index {
+            0 => lines.push(format!("build_command = {}", pre)),
+            _ => notes.push(format!(
+                "Additional ExecStartPre {} wasn't carried over, chain it into build_command",
+                pre
+            )),
+        }
+    }
+
+    if let Some(user) = unit.get("Service", "User") {
+        lines.push(format!("run_as_user = {}", quote(user)));
+    }
+    if let Some(group) = unit.get("Service", "Group") {
+        lines.push(format!("run_as_group = {}", quote(group)));
+    }
+    if let Some(timeout) = unit.get("Service", "TimeoutStopSec") {
+        match timespan_to_seconds(timeout) {
+            Some(seconds) => lines.push(format!("shutdown_timeout_seconds = {}", seconds)),
+            None => notes.push(format!("TimeoutStopSec={} couldn't be converted", timeout)),
+        }
+    }
+
+    if !env.is_empty() {
+        lines.push(String::new());
+        lines.push(String::from("[app_specific.env]"));
+        for (key, value) in &env {
+            lines.push(format!("{} = {}", key, quote(value)));
+        }
+    }
+
+    let memory = unit
+        .get("Service", "MemoryMax")
+        .or_else(|| unit.get("Service", "MemoryLimit"));
+    let cpu = unit.get("Service", "CPUQuota");
+    if memory.is_some() || cpu.is_some() {
+        lines.push(String::new());
+        lines.push(String::from("[app_specific.cgroup]"));
+        lines.push(String::from("enabled = true"));
+        if let Some(memory) = memory {
+            match size_to_mb(memory) {
+                Some(mb) => lines.push(format!("memory_max_mb = {}", mb)),
+                None => notes.push(format!("MemoryMax={} couldn't be converted", memory)),
+            }
+        }
+        if let Some(cpu) = cpu {
+            match cpu.trim_end_matches('%').parse::<u32>() {
+                Ok(percent) => lines.push(format!("cpu_max_percent = {}", percent)),
+                Err(_) => notes.push(format!("CPUQuota={} couldn't be converted", cpu)),
+            }
+        }
+    }
+
+    lines.push(String::new());
+    match detect_port(&exec_start, &env) {
+        Some(port) => {
+            lines.push(format!(
+                "# Suggested health checks, the service appears to listen on port {}",
+                port
+            ));
+            lines.push(String::from("[app_specific.startup_probe]"));
+            lines.push(format!("tcp = \"127.0.0.1:{}\"", port));
+            lines.push(String::from("period_seconds = 2"));
+            lines.push(String::from("failure_threshold = 30"));
+            lines.push(String::new());
+            lines.push(String::from("[app_specific.liveness_probe]"));
+            lines.push(format!("tcp = \"127.0.0.1:{}\"", port));
+        }
+        None => {
+            lines.push(String::from(
+                "# Suggested health check, point it at the port or endpoint the service exposes",
+            ));
+            lines.push(String::from("# [app_specific.liveness_probe]"));
+            lines.push(String::from("# http = \"http://127.0.0.1:8080/health\""));
+        }
+    }
+
+    for key in unit.keys("Service") {
+        if !HANDLED_KEYS.contains(&key) && key != "EnvironmentFile" {
+            notes.push(format!("{} has no runner equivalent and was skipped", key));
+        }
+    }
+
+    lines.push(String::new());
+    Ok(Migration {
+        config: lines.join("\n"),
+        notes,
+    })
+}
+
+/// Result of rendering a config as a standalone unit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Export {
+    /// Rendered `<app_name>.service`.
+    pub unit: String,
+    /// Rendered environment file referenced by the unit.
+    pub env: String,
+    /// Runner behavior that is lost or changes under plain systemd.
+    pub notes: Vec<String>,
+}
+
+/// Quote a value for an `EnvironmentFile`.
+fn env_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Render `settings` as a unit file that runs the child without the runner.
+///
+/// `env_file` is the path the unit references for its environment.
+pub fn export(config: &AppConfig, settings: &AppSpecificConfig, env_file: &str) -> Export {
+    let mut notes: Vec<String> = Vec::new();
+    let app_name = config.app_name.to_string();
+
+    let working_dir = match &settings.working_dir {
+        Some(dir) => Path::new(&settings.project_path).join(dir),
+        None => Path::new(&settings.project_path).to_path_buf(),
+    };
+
+    let mut unit: Vec<String> = vec![
+        format!(
+            "# Generated by `ais_runner export-systemd` for {}",
+            app_name
+        ),
+        String::from("[Unit]"),
+        format!("Description={}", app_name),
+        String::from("After=network.target"),
+        String::new(),
+        String::from("[Service]"),
+        String::from("Type=simple"),
+        format!("WorkingDirectory={}", working_dir.display()),
+        format!("EnvironmentFile=-{}", env_file),
+    ];
+
+    if settings.env_file_location != default_env_location() {
+        unit.push(format!("EnvironmentFile=-{}", settings.env_file_location));
+        notes.push(format!(
+            "{} was written from the secret server by the runner, it has to be provisioned some other way now",
+            settings.env_file_location
+        ));
+    }
+
+    for command in [&settings.install_command, &settings.build_command]
+        .into_iter()
+        .flatten()
+    {
+        unit.push(format!("ExecStartPre={}", command));
+    }
+    if settings.install_command.is_some() || settings.build_command.is_some() {
+        notes.push(String::from(
+            "install_command and build_command run as ExecStartPre on every start",
+        ));
+        let timeout = settings
+            .install_timeout_seconds
+            .unwrap_or_default()
+            .saturating_add(settings.build_timeout_seconds.unwrap_or_default());
+        match timeout {
+            0 => unit.push(String::from("TimeoutStartSec=infinity")),
+            seconds => unit.push(format!("TimeoutStartSec={}", seconds)),
+        }
+    }
+
+    unit.push(format!("ExecStart={}", settings.run_command));
+    if !settings
+        .run_command
+        .split_whitespace()
+        .next()
+        .is_some_and(|program| program.starts_with('/'))
+    {
+        notes.push(String::from(
+            "ExecStart isn't an absolute path, older systemd versions require one",
+        ));
+    }
+
+    if let Some(user) = &settings.run_as_user {
+        unit.push(format!("User={}", user));
+    }
+    if let Some(group) = &settings.run_as_group {
+        unit.push(format!("Group={}", group));
+    }
+
+    unit.push(String::from("Restart=on-failure"));
+    unit.push(String::from("RestartSec=5"));
+    unit.push(format!(
+        "TimeoutStopSec={}",
+        settings.shutdown_timeout_seconds
+    ));
+
+    if settings.cgroup.enabled {
+        match settings
+            .cgroup
+            .memory_max_mb
+            .unwrap_or(config.max_ram_usage as u64)
+        {
+            0 => (),
+            mb => unit.push(format!("MemoryMax={}M", mb)),
+        }
+        if let Some(percent) = settings.cgroup.cpu_max_percent {
+            unit.push(format!("CPUQuota={}%", percent));
+        }
+    }
+
+    unit.push(String::new());
+    unit.push(String::from("[Install]"));
+    unit.push(String::from("WantedBy=multi-user.target"));
+    unit.push(String::new());
+
+    let mut env: Vec<String> = vec![format!("# Environment for {}", app_name)];
+    for (key, value) in &settings.env {
+        env.push(format!("{}={}", key, env_quote(value)));
+    }
+    env.push(String::new());
+
+    notes.push(format!(
+        "Changes under {} no longer trigger a rebuild, restart the unit after deploying",
+        settings.monitor_path
+    ));
+    if settings.startup_probe.is_some() || settings.liveness_probe.is_some() {
+        notes.push(String::from(
+            "Health probes have no systemd equivalent, consider WatchdogSec= with sd_notify in the app",
+        ));
+    }
+    if settings.journal.enabled {
+        notes.push(String::from(
+            "The output journal is replaced by journald, see journalctl -u",
+        ));
+    }
+
+    Export {
+        unit: unit.join("\n"),
+        env: env.join("\n"),
+        notes,
+    }
+}
@@ -0,0 +1,505 @@
+//! Outgoing notifications about what the runner does.
+//!
+//! With `[app_specific.notifications] webhooks = [...]` set, restarts, failed
+//! builds, crash loops and a child going idle are POSTed as JSON to every
+//! webhook. Events go through the [`Outbox`] first and are replayed in order
+//! once an unreachable endpoint comes back.
+//!
+//! Chat and paging services want their own payloads, so an endpoint can
+//! shape its body with a [Handlebars](https://handlebarsjs.com) template
+//! rendering to JSON. The template sees the event: `app`, `kind`,
+//! `message`, `timestamp` and, for restarts and crash loops, `details`
+//! (the restart record or the crash loop report).
+//!
+//! ```toml
+//! [[app_specific.notifications.endpoints]]
+//! url = "https://hooks.slack.com/services/T000/B000/XXXX"
+//! template = '{"text": "*{{app}}* {{kind}}: {{message}}"}'
+//! kinds = ["restart", "crash_loop"]   # all kinds when empty
+//! ```
+//!
+//! Bursts are held back and summarized, see [`crate::notify_throttle`].
+
+use artisan_middleware::dusa_collection_utils;
+use artisan_middleware::timestamp::current_timestamp;
+use dusa_collection_utils::core::logger::LogLevel;
+use handlebars::Handlebars;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex as StdMutex, RwLock},
+    time::Duration,
+};
+use tokio::sync::Mutex;
+
+use crate::global_child::GLOBAL_NOTIFIER;
+use crate::log;
+use crate::notify_throttle::{Severity, Throttle, ThrottleConfig, Verdict};
+use crate::outbox::{Outbox, QueuedMessage};
+
+/// `[app_specific.notifications]`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct NotifyConfig {
+    /// Urls every event is POSTed to.
+    #[serde(default)]
+    pub webhooks: Vec<String>,
+    /// Where undelivered events are kept, defaults to
+    /// `/tmp/.<app_name>_outbox`. Put it on persistent storage to survive
+    /// reboots.
+    #[serde(default)]
+    pub queue_dir: Option<String>,
+    /// Events kept while endpoints are unreachable, the oldest are dropped
+    /// beyond that.
+    #[serde(default = "default_queue_limit")]
+    pub queue_limit: usize,
+    /// How often delivery of queued events is retried.
+    #[serde(default = "default_replay_interval")]
+    pub replay_interval_seconds: u64,
+    #[serde(default = "default_notify_timeout")]
+    pub timeout_seconds: u64,
+    /// Endpoints with their own payload template.
+    #[serde(default)]
+    pub endpoints: Vec<NotifyEndpoint>,
+    #[serde(default)]
+    pub throttle: ThrottleConfig,
+}
+
+/// `[[app_specific.notifications.endpoints]]`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct NotifyEndpoint {
+    pub url: String,
+    /// Handlebars template of the JSON body, the plain event when unset.
+    #[serde(default)]
+    pub template: Option<String>,
+    /// File holding the template instead, relative to the config directory.
+    #[serde(default)]
+    pub template_file: Option<String>,
+    /// Kinds of events sent to the endpoint, all when empty.
+    #[serde(default)]
+    pub kinds: Vec<EventKind>,
+}
+
+impl NotifyEndpoint {
+    fn template_source(&self) -> Result<Option<String>, String> {
+        match (&self.template, &self.template_file) {
+            (Some(_), Some(_)) => Err(format!(
+                "Notification endpoint {} sets both template and template_file",
+                self.url
+            )),
+            (Some(template), None) => Ok(Some(template.clone())),
+            (None, Some(path)) => fs::read_to_string(path).map(Some).map_err(|err| {
+                format!("Failed to read the notification template {}: {}", path, err)
+            }),
+            (None, None) => Ok(None),
+        }
+    }
+
+    fn accepts(&self, kind: EventKind) -> bool {
+        self.kinds.is_empty() || self.kinds.contains(&kind)
+    }
+}
+
+impl Default for NotifyConfig {
+    fn default() -> Self {
+        Self {
+            webhooks: Vec::new(),
+            queue_dir: None,
+            queue_limit: default_queue_limit(),
+            replay_interval_seconds: default_replay_interval(),
+            timeout_seconds: default_notify_timeout(),
+            endpoints: Vec::new(),
+            throttle: ThrottleConfig::default(),
+        }
+    }
+}
+
+fn default_queue_limit() -> usize {
+    1000
+}
+
+fn default_replay_interval() -> u64 {
+    30
+}
+
+fn default_notify_timeout() -> u64 {
+    10
+}
+
+impl NotifyConfig {
+    pub fn enabled(&self) -> bool {
+        !self.webhooks.is_empty() || !self.endpoints.is_empty()
+    }
+
+    /// Every endpoint, plain webhooks included.
+    fn targets(&self) -> Vec<NotifyEndpoint> {
+        let plain = self.webhooks.iter().map(|url| NotifyEndpoint {
+            url: url.clone(),
+            template: None,
+            template_file: None,
+            kinds: Vec::new(),
+        });
+        plain.chain(self.endpoints.iter().cloned()).collect()
+    }
+
+    pub fn queue_dir(&self, app_name: &str) -> PathBuf {
+        match &self.queue_dir {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(format!("/tmp/.{}_outbox", app_name)),
+        }
+    }
+}
+
+/// What happened.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Started,
+    Restart,
+    BuildFailed,
+    CrashLoop,
+    Idle,
+    CpuLimit,
+}
+
+impl EventKind {
+    pub fn severity(self) -> Severity {
+        match self {
+            EventKind::Started | EventKind::Idle => Severity::Info,
+            EventKind::Restart | EventKind::CpuLimit => Severity::Warning,
+            EventKind::BuildFailed | EventKind::CrashLoop => Severity::Critical,
+        }
+    }
+}
+
+/// Body of a notification.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Event {
+    pub app: String,
+    pub kind: EventKind,
+    pub message: String,
+    pub timestamp: u64,
+    /// What the event is about, e.g. the restart record.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<Value>,
+}
+
+/// Templates render JSON, so values are escaped as JSON string content
+/// rather than HTML.
+fn escape_json(value: &str) -> String {
+    let quoted = serde_json::to_string(value).unwrap_or_default();
+    quoted
+        .strip_prefix('"')
+        .and_then(|quoted| quoted.strip_suffix('"'))
+        .unwrap_or_default()
+        .to_owned()
+}
+
+/// Compile the templates of `config`, registered by their endpoint's index
+/// in [`NotifyConfig::endpoints`] plus the number of plain webhooks.
+pub fn templates(config: &NotifyConfig) -> Result<Handlebars<'static>, String> {
+    let mut templates = Handlebars::new();
+    templates.set_strict_mode(true);
+    templates.register_escape_fn(escape_json);
+    for (index, endpoint) in config.targets().iter().enumerate() {
+        if let Some(source) = endpoint.template_source()? {
+            templates
+                .register_template_string(&index.to_string(), source)
+                .map_err(|err| {
+                    format!(
+                        "Invalid notification template for {}: {}",
+                        endpoint.url, err
+                    )
+                })?;
+        }
+    }
+    Ok(templates)
+}
+
+/// The body `event` is sent with to the endpoint whose template is
+/// registered as `name`.
+pub fn render(templates: &Handlebars, name: &str, event: &Event) -> Result<Value, String> {
+    let rendered = templates
+        .render(name, event)
+        .map_err(|err| format!("Failed to render notification template: {}", err))?;
+    serde_json::from_str(&rendered)
+        .map_err(|err| format!("Notification template didn't render JSON: {}", err))
+}
+
+/// The webhooks events go to and their templates.
+#[derive(Debug)]
+struct Routes {
+    targets: Vec<NotifyEndpoint>,
+    templates: Handlebars<'static>,
+}
+
+impl Routes {
+    fn new(config: &NotifyConfig) -> Result<Self, String> {
+        Ok(Self {
+            targets: config.targets(),
+            templates: templates(config)?,
+        })
+    }
+}
+
+/// Delivers events to the configured webhooks through the outbox.
+#[derive(Debug)]
+pub struct Notifier {
+    app_name: String,
+    /// Swapped when the config is reloaded.
+    routes: RwLock<Routes>,
+    outbox: Outbox,
+    /// Swapped along with the routes, keeping its counts.
+    throttle: StdMutex<Throttle>,
+    http: reqwest::Client,
+    /// Held while delivering so events leave in queue order.
+    flushing: Mutex<()>,
+}
+
+impl Notifier {
+    pub fn new(config: &NotifyConfig, app_name: &str) -> std::io::Result<Self> {
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_seconds))
+            .build()
+            .map_err(std::io::Error::other)?;
+
+        Ok(Self {
+            app_name: app_name.to_owned(),
+            routes: RwLock::new(Routes::new(config).map_err(std::io::Error::other)?),
+            outbox: Outbox::open(&config.queue_dir(app_name), config.queue_limit)?,
+            throttle: StdMutex::new(Throttle::new(&config.throttle)),
+            http,
+            flushing: Mutex::new(()),
+        })
+    }
+
+    /// Queue an event for every webhook.
+    pub fn enqueue(&self, kind: EventKind, message: String) {
+        self.enqueue_with(kind, message, None)
+    }
+
+    /// Queue an event with `details` for every webhook taking its kind,
+    /// unless the throttle holds it back.
+    pub fn enqueue_with(&self, kind: EventKind, message: String, details: Option<Value>) {
+        let now = current_timestamp();
+        let verdict = {
+            let mut throttle = match self.throttle.lock() {
+                Ok(throttle) => throttle,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            // Whatever was held back goes out before what follows it
+            for summary in throttle.due(now) {
+                self.queue(
+                    summary.kind,
+                    summary.to_string(),
+                    serde_json::to_value(&summary).ok(),
+                    now,
+                );
+            }
+            throttle.offer(kind, &message, now)
+        };
+        match verdict {
+            Verdict::Send => self.queue(kind, message, details, now),
+            Verdict::Hold => log!(LogLevel::Debug, "Holding back notification: {}", message),
+            Verdict::Drop => (),
+        }
+    }
+
+    /// Queue the summaries of held back events that are due, returning how
+    /// many there were.
+    pub fn release_held(&self) -> usize {
+        let now = current_timestamp();
+        let due = match self.throttle.lock() {
+            Ok(mut throttle) => throttle.due(now),
+            Err(poisoned) => poisoned.into_inner().due(now),
+        };
+        for summary in &due {
+            self.queue(
+                summary.kind,
+                summary.to_string(),
+                serde_json::to_value(summary).ok(),
+                now,
+            );
+        }
+        due.len()
+    }
+
+    fn queue(&self, kind: EventKind, message: String, details: Option<Value>, timestamp: u64) {
+        let event = Event {
+            app: self.app_name.clone(),
+            kind,
+            message,
+            timestamp,
+            details,
+        };
+        let payload = match serde_json::to_value(&event) {
+            Ok(payload) => payload,
+            Err(err) => {
+                log!(LogLevel::Warn, "Failed to encode notification: {}", err);
+                return;
+            }
+        };
+
+        let routes = match self.routes.read() {
+            Ok(routes) => routes,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        for (index, target) in routes.targets.iter().enumerate() {
+            if !target.accepts(kind) {
+                continue;
+            }
+            let name = index.to_string();
+            let payload = match routes.templates.has_template(&name) {
+                true => match render(&routes.templates, &name, &event) {
+                    Ok(payload) => payload,
+                    Err(err) => {
+                        log!(LogLevel::Warn, "Not notifying {}: {}", target.url, err);
+                        continue;
+                    }
+                },
+                false => payload.clone(),
+            };
+            let message = QueuedMessage {
+                endpoint: target.url.clone(),
+                payload,
+                queued_at: event.timestamp,
+            };
+            match self.outbox.push(&message) {
+                Ok(0) => (),
+                Ok(dropped) => log!(
+                    LogLevel::Warn,
+                    "Notification queue is full, dropped {} undelivered events",
+                    dropped
+                ),
+                Err(err) => log!(LogLevel::Warn, "Failed to queue notification: {}", err),
+            }
+        }
+    }
+
+    /// Deliver queued events in order, stopping at the first one that
+    /// can't be delivered.
+    ///
+    /// Returns how many were delivered.
+    pub async fn flush(&self) -> usize {
+        let _flushing = self.flushing.lock().await;
+        let pending = match self.outbox.pending() {
+            Ok(pending) => pending,
+            Err(err) => {
+                log!(LogLevel::Warn, "Failed to read notification queue: {}", err);
+                return 0;
+            }
+        };
+
+        let mut delivered = 0;
+        for (sequence, message) in pending {
+            let sent = self
+                .http
+                .post(&message.endpoint)
+                .json(&message.payload)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+
+            if let Err(err) = sent {
+                log!(
+                    LogLevel::Debug,
+                    "Notification to {} not delivered, keeping it queued: {}",
+                    message.endpoint,
+                    err
+                );
+                break;
+            }
+            if let Err(err) = self.outbox.remove(sequence) {
+                log!(LogLevel::Warn, "Failed to dequeue notification: {}", err);
+                break;
+            }
+            delivered += 1;
+        }
+        delivered
+    }
+
+    /// Send future events to the endpoints of `config`. Events already
+    /// queued still go where they were queued for.
+    pub fn retarget(&self, config: &NotifyConfig) -> Result<(), String> {
+        let routes = Routes::new(config)?;
+        match self.routes.write() {
+            Ok(mut current) => *current = routes,
+            Err(poisoned) => *poisoned.into_inner() = routes,
+        }
+        match self.throttle.lock() {
+            Ok(mut throttle) => throttle.reconfigure(&config.throttle),
+            Err(poisoned) => poisoned.into_inner().reconfigure(&config.throttle),
+        }
+        Ok(())
+    }
+
+    pub fn queued(&self) -> usize {
+        self.outbox.count().unwrap_or_default()
+    }
+}
+
+/// Set up the global notifier and retry undelivered events every
+/// `replay_interval_seconds`, starting with whatever the last run left.
+pub fn start(config: &NotifyConfig, app_name: &str) -> std::io::Result<()> {
+    let notifier = Arc::new(Notifier::new(config, app_name)?);
+    if GLOBAL_NOTIFIER.set(notifier.clone()).is_err() {
+        return Ok(());
+    }
+
+    let interval = Duration::from_secs(config.replay_interval_seconds.max(1));
+    tokio::spawn(async move {
+        loop {
+            notifier.release_held();
+            let queued = notifier.queued();
+            if queued > 0 {
+                let delivered = notifier.flush().await;
+                if delivered > 0 {
+                    log!(
+                        LogLevel::Info,
+                        "Delivered {} of {} queued notifications",
+                        delivered,
+                        queued
+                    );
+                }
+            }
+            tokio::time::sleep(interval).await;
+        }
+    });
+    Ok(())
+}
+
+/// Apply a reloaded `config`, starting the notifier if it wasn't before.
+pub fn reconfigure(config: &NotifyConfig, app_name: &str) -> Result<(), String> {
+    match GLOBAL_NOTIFIER.get() {
+        Some(notifier) => notifier.retarget(config),
+        None if config.enabled() => start(config, app_name).map_err(|err| err.to_string()),
+        None => Ok(()),
+    }
+}
+
+/// Send an event if notifications are configured.
+pub fn notify(kind: EventKind, message: impl Into<String>) {
+    notify_with(kind, message, None)
+}
+
+/// Send an event with `details` for templates if notifications are
+/// configured.
+pub fn notify_with(kind: EventKind, message: impl Into<String>, details: Option<Value>) {
+    let notifier = match GLOBAL_NOTIFIER.get() {
+        Some(notifier) => notifier.clone(),
+        None => return,
+    };
+
+    notifier.enqueue_with(kind, message.into(), details);
+    tokio::spawn(async move {
+        notifier.flush().await;
+    });
+}
+
+/// Try to deliver queued events right away, e.g. before the runner exits.
+pub async fn flush() {
+    if let Some(notifier) = GLOBAL_NOTIFIER.get() {
+        notifier.flush().await;
+    }
+}
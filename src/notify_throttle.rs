@@ -0,0 +1,268 @@
+//! Keeping a misbehaving child from paging someone hundreds of times.
+//!
+//! Every notification passes the [`Throttle`] before it's queued:
+//!
+//! ```toml
+//! [app_specific.notifications.throttle]
+//! max_per_kind = 5              # per window and kind of event, 0 lifts the limit
+//! window_minutes = 10
+//! min_severity = "info"         # "warning" or "critical" drop the events below
+//! quiet_hours = "22:00-07:00"   # UTC, optional
+//! quiet_min_severity = "critical"
+//! ```
+//!
+//! Past `max_per_kind` events of a kind in a window, and during quiet hours
+//! for events below `quiet_min_severity`, events are held back and counted.
+//! Once the window is over and quiet hours have ended they're sent as one
+//! [`Summary`] of that kind, e.g. `14 restart events held back over 9m, the
+//! last: ...`. Events below `min_severity` are dropped without a trace.
+
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use crate::notifications::EventKind;
+
+/// How urgent an event is, see [`EventKind::severity`].
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Critical,
+}
+
+/// `[app_specific.notifications.throttle]`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ThrottleConfig {
+    #[serde(default = "default_max_per_kind")]
+    pub max_per_kind: u32,
+    #[serde(default = "default_window_minutes")]
+    pub window_minutes: u64,
+    #[serde(default = "default_min_severity")]
+    pub min_severity: Severity,
+    /// `HH:MM-HH:MM` in UTC, may wrap past midnight.
+    #[serde(default)]
+    pub quiet_hours: Option<String>,
+    #[serde(default = "default_quiet_min_severity")]
+    pub quiet_min_severity: Severity,
+}
+
+impl Default for ThrottleConfig {
+    fn default() -> Self {
+        Self {
+            max_per_kind: default_max_per_kind(),
+            window_minutes: default_window_minutes(),
+            min_severity: default_min_severity(),
+            quiet_hours: None,
+            quiet_min_severity: default_quiet_min_severity(),
+        }
+    }
+}
+
+fn default_max_per_kind() -> u32 {
+    5
+}
+
+fn default_window_minutes() -> u64 {
+    10
+}
+
+fn default_min_severity() -> Severity {
+    Severity::Info
+}
+
+fn default_quiet_min_severity() -> Severity {
+    Severity::Critical
+}
+
+/// A daily stretch of time, minutes since midnight UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuietHours {
+    start: u32,
+    end: u32,
+}
+
+impl QuietHours {
+    /// Whether the unix `timestamp` falls into the quiet hours.
+    pub fn contains(&self, timestamp: u64) -> bool {
+        let minute = ((timestamp % 86_400) / 60) as u32;
+        match self.start <= self.end {
+            true => self.start <= minute && minute < self.end,
+            // Past midnight
+            false => minute >= self.start || minute < self.end,
+        }
+    }
+}
+
+fn parse_time(time: &str) -> Result<u32, String> {
+    let (hours, minutes) = time
+        .trim()
+        .split_once(':')
+        .ok_or_else(|| format!("{:?} isn't HH:MM", time))?;
+    match (hours.parse::<u32>(), minutes.parse::<u32>()) {
+        (Ok(hours), Ok(minutes)) if hours < 24 && minutes < 60 => Ok(hours * 60 + minutes),
+        _ => Err(format!("{:?} isn't a time between 00:00 and 23:59", time)),
+    }
+}
+
+impl FromStr for QuietHours {
+    type Err = String;
+
+    fn from_str(hours: &str) -> Result<Self, Self::Err> {
+        let (start, end) = hours
+            .split_once('-')
+            .ok_or_else(|| format!("{:?} isn't HH:MM-HH:MM", hours))?;
+        let (start, end) = (parse_time(start)?, parse_time(end)?);
+        if start == end {
+            return Err(format!("{:?} starts where it ends", hours));
+        }
+        Ok(Self { start, end })
+    }
+}
+
+/// What to do with an event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Send,
+    /// Counted towards the next summary of its kind.
+    Hold,
+    Drop,
+}
+
+/// Events of one kind held back over a window.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Summary {
+    pub kind: EventKind,
+    pub count: u32,
+    /// When the first and the last of them happened.
+    pub first: u64,
+    pub last: u64,
+    pub last_message: String,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = serde_json::to_value(self.kind)
+            .ok()
+            .and_then(|kind| kind.as_str().map(str::to_owned))
+            .unwrap_or_default()
+            .replace('_', " ");
+        write!(
+            f,
+            "{} {} events held back over {}m, the last: {}",
+            self.count,
+            kind,
+            (self.last - self.first) / 60 + 1,
+            self.last_message
+        )
+    }
+}
+
+/// Where one kind of event stands in its current window.
+#[derive(Debug, Clone, Default)]
+struct Window {
+    started: u64,
+    sent: u32,
+    held: Option<Summary>,
+}
+
+/// Decides which events go out, see the module docs.
+#[derive(Debug, Clone)]
+pub struct Throttle {
+    config: ThrottleConfig,
+    quiet_hours: Option<QuietHours>,
+    windows: BTreeMap<EventKind, Window>,
+}
+
+impl Throttle {
+    /// Quiet hours that don't parse are reported by validation and ignored.
+    pub fn new(config: &ThrottleConfig) -> Self {
+        Self {
+            config: config.clone(),
+            quiet_hours: config
+                .quiet_hours
+                .as_deref()
+                .and_then(|hours| hours.parse().ok()),
+            windows: BTreeMap::new(),
+        }
+    }
+
+    /// Apply a reloaded `config`, keeping what was counted so far.
+    pub fn reconfigure(&mut self, config: &ThrottleConfig) {
+        let windows = std::mem::take(&mut self.windows);
+        *self = Self::new(config);
+        self.windows = windows;
+    }
+
+    fn window_seconds(&self) -> u64 {
+        self.config.window_minutes.saturating_mul(60)
+    }
+
+    fn is_quiet(&self, severity: Severity, now: u64) -> bool {
+        severity < self.config.quiet_min_severity
+            && self.quiet_hours.is_some_and(|hours| hours.contains(now))
+    }
+
+    /// Whether an event of `kind` with `message` happening `now` is sent.
+    pub fn offer(&mut self, kind: EventKind, message: &str, now: u64) -> Verdict {
+        let severity = kind.severity();
+        if severity < self.config.min_severity {
+            return Verdict::Drop;
+        }
+        let quiet = self.is_quiet(severity, now);
+        let window_seconds = self.window_seconds();
+        let max = self.config.max_per_kind;
+
+        let window = self.windows.entry(kind).or_default();
+        // Held events keep their window until their summary went out
+        if window.held.is_none() && now >= window.started.saturating_add(window_seconds) {
+            *window = Window {
+                started: now,
+                ..Window::default()
+            };
+        }
+        if !quiet && window.held.is_none() && (max == 0 || window.sent < max) {
+            window.sent += 1;
+            return Verdict::Send;
+        }
+
+        let held = window.held.get_or_insert(Summary {
+            kind,
+            count: 0,
+            first: now,
+            last: now,
+            last_message: String::new(),
+        });
+        held.count += 1;
+        held.last = now;
+        held.last_message = message.to_owned();
+        Verdict::Hold
+    }
+
+    /// The summaries of held events whose window is over, outside of quiet
+    /// hours. Each starts a new window it counts towards.
+    pub fn due(&mut self, now: u64) -> Vec<Summary> {
+        let window_seconds = self.window_seconds();
+        let quiet: Vec<EventKind> = self
+            .windows
+            .keys()
+            .copied()
+            .filter(|kind| self.is_quiet(kind.severity(), now))
+            .collect();
+
+        let mut due = Vec::new();
+        for (kind, window) in self.windows.iter_mut() {
+            let over = now >= window.started.saturating_add(window_seconds);
+            if !over || window.held.is_none() || quiet.contains(kind) {
+                continue;
+            }
+            due.extend(window.held.take());
+            *window = Window {
+                started: now,
+                sent: 1,
+                held: None,
+            };
+        }
+        due
+    }
+}
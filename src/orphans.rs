@@ -0,0 +1,249 @@
+//! Children left running by a runner that crashed.
+//!
+//! A runner that was killed never stops its child, which keeps running and
+//! holding its port, so the child spawned by the next start fails to bind.
+//! On start up the pid in `/tmp/.<app>_pg.pid` is checked before anything
+//! else: if that process is still alive and its command line is the
+//! `run_command` of the config, it's the orphan and is dealt with before a
+//! new child is spawned:
+//!
+//! ```toml
+//! [app_specific.orphans]
+//! action = "kill"               # or "adopt"
+//! adopt_timeout_seconds = 3600  # adopt only, wait forever when unset
+//! ```
+//!
+//! `kill` sends `SIGTERM` and `SIGKILL` after `shutdown_timeout_seconds`,
+//! like a graceful shutdown would, to the orphan's whole process group.
+//! `adopt` leaves the orphan serving and supervises it as an
+//! [`AdoptedChild`] in place of spawning a new one: its usage is read from
+//! `/proc`, its output keeps going to the [capture files](crate::capture)
+//! the runner follows, health probes and restarts apply as usual, and a new
+//! child is spawned once it exits. Once the timeout passes it's replaced
+//! after all.
+//!
+//! A pid that was reused by an unrelated process is left alone.
+
+use nix::{
+    sys::signal::{self, Signal},
+    unistd::{self, Pid},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::command_vars::secret_keys;
+use crate::output::Stream;
+
+/// How often an orphan is checked on while waiting for it to exit.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// What happens to an orphan found on start up.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OrphanAction {
+    #[default]
+    Kill,
+    Adopt,
+}
+
+/// `[app_specific.orphans]`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct OrphanConfig {
+    #[serde(default)]
+    pub action: OrphanAction,
+    /// How long an adopted orphan may keep running before it's replaced.
+    #[serde(default)]
+    pub adopt_timeout_seconds: Option<u64>,
+}
+
+impl OrphanConfig {
+    pub fn adopt_timeout(&self) -> Option<Duration> {
+        self.adopt_timeout_seconds.map(Duration::from_secs)
+    }
+}
+
+/// What the pid file points at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Previous {
+    /// No pid file, or its process is gone.
+    Gone,
+    /// The pid was reused by another process.
+    Unrelated(u32),
+    /// The previous child is still running.
+    Orphan(u32),
+}
+
+/// The arguments of a `/proc/<pid>/cmdline`.
+pub fn parse_cmdline(cmdline: &[u8]) -> Vec<String> {
+    cmdline
+        .split(|byte| *byte == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect()
+}
+
+/// Whether a process running `cmdline` was spawned from `argv`. Arguments
+/// with secrets were filled in when spawning and match anything.
+pub fn cmdline_matches(cmdline: &[String], argv: &[String]) -> bool {
+    cmdline.len() == argv.len()
+        && cmdline.iter().zip(argv).all(|(running, arg)| {
+            running == arg || !secret_keys(std::slice::from_ref(arg)).is_empty()
+        })
+}
+
+fn alive(pid: u32) -> bool {
+    signal::kill(Pid::from_raw(pid as i32), None).is_ok()
+}
+
+/// Look at the process `pid_file` names, expecting it to run `argv`.
+pub fn find(pid_file: &Path, argv: &[String]) -> Previous {
+    let pid = match fs::read_to_string(pid_file)
+        .ok()
+        .and_then(|content| content.trim().parse::<u32>().ok())
+    {
+        Some(pid) if pid != std::process::id() && alive(pid) => pid,
+        _ => return Previous::Gone,
+    };
+    match fs::read(format!("/proc/{}/cmdline", pid)) {
+        Ok(cmdline) if cmdline_matches(&parse_cmdline(&cmdline), argv) => Previous::Orphan(pid),
+        Ok(_) => Previous::Unrelated(pid),
+        // Exited in the meantime
+        Err(_) => Previous::Gone,
+    }
+}
+
+/// Wait until `pid` is gone, `false` if it's still there after `timeout`.
+async fn wait_for_exit(pid: u32, timeout: Option<Duration>) -> bool {
+    let start = Instant::now();
+    while alive(pid) {
+        if timeout.is_some_and(|timeout| start.elapsed() >= timeout) {
+            return false;
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+    true
+}
+
+/// Signal `pid`, along with its process group when it leads one as the
+/// children spawned by the runner do.
+fn send(pid: u32, signal: Signal) -> nix::Result<()> {
+    let target = Pid::from_raw(pid as i32);
+    match unistd::getpgid(Some(target)) {
+        Ok(group) if group == target => signal::killpg(target, signal),
+        _ => signal::kill(target, signal),
+    }
+}
+
+/// Stop `pid` with `SIGTERM`, and `SIGKILL` once `grace` passed.
+pub async fn kill(pid: u32, grace: Duration) -> Result<(), String> {
+    send(pid, Signal::SIGTERM).map_err(|err| format!("Failed to stop orphan {}: {}", pid, err))?;
+    if wait_for_exit(pid, Some(grace)).await {
+        return Ok(());
+    }
+    send(pid, Signal::SIGKILL).map_err(|err| format!("Failed to kill orphan {}: {}", pid, err))?;
+    match wait_for_exit(pid, Some(grace)).await {
+        true => Ok(()),
+        false => Err(format!("Orphan {} survived SIGKILL", pid)),
+    }
+}
+
+/// Fields of `/proc/<pid>/stat` after the command name, which may hold
+/// spaces and parentheses itself.
+fn stat_fields(pid: u32) -> Option<Vec<String>> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let rest = &stat[stat.rfind(')')? + 1..];
+    Some(rest.split_whitespace().map(String::from).collect())
+}
+
+fn sysconf(var: unistd::SysconfVar) -> Option<u64> {
+    unistd::sysconf(var)
+        .ok()
+        .flatten()
+        .map(|value| value as u64)
+}
+
+/// CPU and memory an adopted child uses.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Usage {
+    pub cpu_percent: f32,
+    pub memory_mb: f64,
+}
+
+/// The child of a previous run, supervised through `/proc` since it can't
+/// be waited on. Its output goes to the capture files like a spawned
+/// child's.
+#[derive(Debug)]
+pub struct AdoptedChild {
+    pub pid: u32,
+    since: Instant,
+    stdout: Option<PathBuf>,
+    stderr: Option<PathBuf>,
+    /// CPU ticks used at the last sample.
+    sample: Option<(Instant, u64)>,
+}
+
+impl AdoptedChild {
+    pub fn attach(pid: u32) -> Self {
+        Self {
+            pid,
+            since: Instant::now(),
+            stdout: fs::read_link(format!("/proc/{}/fd/1", pid)).ok(),
+            stderr: fs::read_link(format!("/proc/{}/fd/2", pid)).ok(),
+            sample: None,
+        }
+    }
+
+    /// Where `stream` goes, a path or something like `pipe:[123]`.
+    pub fn output_file(&self, stream: Stream) -> Option<&Path> {
+        let output = match stream {
+            Stream::Stdout => &self.stdout,
+            Stream::Stderr => &self.stderr,
+        };
+        output.as_deref()
+    }
+
+    /// Whether it still runs, a zombie waiting for its new parent doesn't.
+    pub fn running(&self) -> bool {
+        match stat_fields(self.pid) {
+            Some(fields) => fields.first().is_some_and(|state| state != "Z"),
+            None => false,
+        }
+    }
+
+    /// Whether it ran longer than `timeout` since it was adopted.
+    pub fn overdue(&self, timeout: Option<Duration>) -> bool {
+        timeout.is_some_and(|timeout| self.since.elapsed() >= timeout)
+    }
+
+    /// Usage since the last call, the first one only has the memory.
+    pub fn usage(&mut self) -> Option<Usage> {
+        let fields = stat_fields(self.pid)?;
+        // utime and stime, fields 14 and 15 of the whole line
+        let ticks = fields.get(11)?.parse::<u64>().ok()? + fields.get(12)?.parse::<u64>().ok()?;
+        // rss, field 24
+        let pages = fields.get(21)?.parse::<u64>().ok()?;
+        let page_size = sysconf(unistd::SysconfVar::PAGE_SIZE)?;
+        let per_second = sysconf(unistd::SysconfVar::CLK_TCK)?.max(1);
+
+        let now = Instant::now();
+        let cpu_percent = match self.sample.replace((now, ticks)) {
+            Some((then, before)) => {
+                let elapsed = now.duration_since(then).as_secs_f64();
+                let used = ticks.saturating_sub(before) as f64 / per_second as f64;
+                match elapsed > 0.0 {
+                    true => (used / elapsed * 100.0) as f32,
+                    false => 0.0,
+                }
+            }
+            None => 0.0,
+        };
+        Some(Usage {
+            cpu_percent,
+            memory_mb: (pages * page_size) as f64 / (1024.0 * 1024.0),
+        })
+    }
+}
@@ -0,0 +1,160 @@
+//! Restart traces over OpenTelemetry.
+//!
+//! The timeline of a restart is kept with its record and printed by
+//! `trace`. With `otel_endpoint` set every recorded restart is exported to
+//! an OTLP/gRPC collector as well, a `restart` span covering the whole
+//! restart with one child span per stage of its [`DeployTrace`]:
+//!
+//! ```toml
+//! otel_endpoint = "http://otel-collector:4317"
+//! ```
+//!
+//! `install`, `build`, `stop` (killing the old child), `start` (spawning
+//! the new one) and `ready` keep the names they have on the timeline. Spans
+//! are batched and sent in the background, a collector that can't be
+//! reached never holds up a restart.
+//!
+//! Exporting needs a runner built with the `otel` feature, without it a
+//! configured `otel_endpoint` only gets a warning at startup.
+//!
+//! [`DeployTrace`]: crate::deploy_trace::DeployTrace
+
+#[cfg(feature = "otel")]
+use artisan_middleware::dusa_collection_utils::core::logger::LogLevel;
+#[cfg(feature = "otel")]
+use opentelemetry::{
+    Context, KeyValue,
+    trace::{Span, Status, TraceContextExt, Tracer},
+};
+#[cfg(feature = "otel")]
+use opentelemetry_otlp::WithExportConfig;
+#[cfg(feature = "otel")]
+use opentelemetry_sdk::{Resource, runtime, trace};
+use std::time::{Duration, SystemTime};
+
+use crate::deploy_trace::StageSpan;
+#[cfg(feature = "otel")]
+use crate::global_child::GLOBAL_TRACER;
+#[cfg(feature = "otel")]
+use crate::log;
+use crate::restart::RestartReason;
+
+/// A finished restart as it is exported.
+#[derive(Debug, Clone)]
+pub struct RestartTrace<'a> {
+    pub reason: &'a RestartReason,
+    pub success: bool,
+    /// When the last stage ended.
+    pub ended: SystemTime,
+    pub stages: &'a [StageSpan],
+}
+
+impl RestartTrace<'_> {
+    /// When the triggering event happened, the start of the first stage.
+    pub fn started(&self) -> SystemTime {
+        let total_ms = self
+            .stages
+            .iter()
+            .map(|span| span.start_ms + span.duration_ms)
+            .max()
+            .unwrap_or_default();
+        self.ended - Duration::from_millis(total_ms)
+    }
+}
+
+/// Record `restart` with `tracer`, the stages as children of the
+/// `restart` span.
+#[cfg(feature = "otel")]
+pub fn record<T>(tracer: &T, restart: &RestartTrace)
+where
+    T: Tracer,
+    T::Span: Send + Sync + 'static,
+{
+    let started = restart.started();
+    let root = tracer
+        .span_builder("restart")
+        .with_start_time(started)
+        .with_attributes(vec![
+            KeyValue::new("restart.kind", restart.reason.kind.name()),
+            KeyValue::new("restart.reason", restart.reason.detail.clone()),
+            KeyValue::new("restart.success", restart.success),
+        ])
+        .start(tracer);
+    let context = Context::current_with_span(root);
+
+    for stage in restart.stages {
+        let start = started + Duration::from_millis(stage.start_ms);
+        let mut span = tracer
+            .span_builder(stage.stage.name())
+            .with_start_time(start)
+            .start_with_context(tracer, &context);
+        span.end_with_timestamp(start + Duration::from_millis(stage.duration_ms));
+    }
+
+    let root = context.span();
+    if !restart.success {
+        root.set_status(Status::error("the new child didn't become ready"));
+    }
+    root.end_with_timestamp(restart.ended);
+}
+
+/// Start exporting restarts passed to [`export`] to `endpoint`, as the
+/// service `app_name`.
+#[cfg(feature = "otel")]
+pub fn start(endpoint: &str, app_name: &str) -> Result<(), String> {
+    let exporter = opentelemetry_otlp::new_exporter()
+        .tonic()
+        .with_endpoint(endpoint);
+    let config = trace::config().with_resource(Resource::new(vec![KeyValue::new(
+        "service.name",
+        app_name.to_owned(),
+    )]));
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(exporter)
+        .with_trace_config(config)
+        .install_batch(runtime::Tokio)
+        .map_err(|err| format!("Can't export traces to {}: {}", endpoint, err))?;
+    _ = GLOBAL_TRACER.set(tracer);
+    Ok(())
+}
+
+#[cfg(not(feature = "otel"))]
+pub fn start(endpoint: &str, _app_name: &str) -> Result<(), String> {
+    Err(format!(
+        "Can't export traces to {}: built without the otel feature",
+        endpoint
+    ))
+}
+
+/// Send the restarts still waiting to be exported, when shutting down.
+#[cfg(feature = "otel")]
+pub async fn flush() {
+    let provider = match GLOBAL_TRACER.get().and_then(|tracer| tracer.provider()) {
+        Some(provider) => provider,
+        None => return,
+    };
+    // Flushing blocks until the collector answered
+    let flushed = tokio::task::spawn_blocking(move || provider.force_flush())
+        .await
+        .unwrap_or_default();
+    for result in flushed {
+        if let Err(err) = result {
+            log!(LogLevel::Warn, "Failed to export restart traces: {}", err);
+        }
+    }
+}
+
+#[cfg(not(feature = "otel"))]
+pub async fn flush() {}
+
+/// Export `restart` if `otel_endpoint` is configured.
+#[cfg(feature = "otel")]
+pub fn export(restart: &RestartTrace) {
+    if let Some(tracer) = GLOBAL_TRACER.get() {
+        record(tracer, restart);
+    }
+}
+
+#[cfg(not(feature = "otel"))]
+pub fn export(_restart: &RestartTrace) {}
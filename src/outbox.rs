@@ -0,0 +1,115 @@
+//! Bounded on-disk queue for outgoing messages.
+//!
+//! Runners on edge nodes regularly lose their uplink for a while. Anything
+//! the runner sends out (notifications so far) is written to an [`Outbox`]
+//! first and only removed once it was delivered, so a flapping link or a
+//! runner restart in between doesn't lose deploy and failure history.
+//!
+//! Every message is its own file named after a sequence number, which keeps
+//! delivery in order and makes writes atomic with a plain `rename(2)`.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// A message waiting for delivery.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct QueuedMessage {
+    /// Where the message goes, e.g. a webhook url.
+    pub endpoint: String,
+    pub payload: serde_json::Value,
+    pub queued_at: u64,
+}
+
+/// Queue directory holding at most `limit` messages.
+#[derive(Debug)]
+pub struct Outbox {
+    dir: PathBuf,
+    limit: usize,
+    next_sequence: AtomicU64,
+}
+
+impl Outbox {
+    /// Open (creating if needed) the queue in `dir`, picking up whatever a
+    /// previous run left there.
+    pub fn open(dir: &Path, limit: usize) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let next_sequence = sequences(dir)?.last().map_or(0, |last| last + 1);
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            limit: limit.max(1),
+            next_sequence: AtomicU64::new(next_sequence),
+        })
+    }
+
+    /// Queue `message`.
+    ///
+    /// Returns how many of the oldest messages were dropped to stay within
+    /// the limit.
+    pub fn push(&self, message: &QueuedMessage) -> io::Result<usize> {
+        let sequence = self.next_sequence.fetch_add(1, Ordering::SeqCst);
+        let path = self.path(sequence);
+        let mut temp = path.clone().into_os_string();
+        temp.push(".tmp");
+
+        fs::write(&temp, serde_json::to_vec(message)?)?;
+        fs::rename(&temp, &path)?;
+
+        let queued = sequences(&self.dir)?;
+        let overflow = queued.len().saturating_sub(self.limit);
+        for sequence in &queued[..overflow] {
+            self.remove(*sequence)?;
+        }
+        Ok(overflow)
+    }
+
+    /// Queued messages with their sequence number, oldest first.
+    ///
+    /// Files that can't be parsed are dropped.
+    pub fn pending(&self) -> io::Result<Vec<(u64, QueuedMessage)>> {
+        let mut pending = Vec::new();
+        for sequence in sequences(&self.dir)? {
+            let parsed = fs::read(self.path(sequence))
+                .ok()
+                .and_then(|content| serde_json::from_slice(&content).ok());
+            match parsed {
+                Some(message) => pending.push((sequence, message)),
+                None => self.remove(sequence)?,
+            }
+        }
+        Ok(pending)
+    }
+
+    /// Number of queued messages.
+    pub fn count(&self) -> io::Result<usize> {
+        Ok(sequences(&self.dir)?.len())
+    }
+
+    /// Drop a delivered message.
+    pub fn remove(&self, sequence: u64) -> io::Result<()> {
+        match fs::remove_file(self.path(sequence)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    fn path(&self, sequence: u64) -> PathBuf {
+        self.dir.join(format!("{:020}.json", sequence))
+    }
+}
+
+/// Sequence numbers of the messages in `dir`, ascending.
+fn sequences(dir: &Path) -> io::Result<Vec<u64>> {
+    let mut sequences: Vec<u64> = fs::read_dir(dir)?
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name();
+            name.to_str()?.strip_suffix(".json")?.parse().ok()
+        })
+        .collect();
+    sequences.sort_unstable();
+    Ok(sequences)
+}
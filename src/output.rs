@@ -0,0 +1,167 @@
+//! Combined view over captured child output.
+//!
+//! [`AppState`](artisan_middleware::state_persistence::AppState) keeps stdout
+//! and stderr in separate buffers. Readers that want the output the way a
+//! terminal would have shown it (stack traces next to the request that caused
+//! them) use [`merged`] to interleave both buffers by their keys.
+//!
+//! New lines are found by their offset in the [capture files](crate::capture),
+//! not in these buffers: they're trimmed from the front and can hold the same
+//! line any number of times, so no position in them is stable.
+
+use std::fmt;
+
+use crate::timestamps::subsecond;
+
+/// The pipe a captured line came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl Stream {
+    /// Event name used by the json log sink.
+    pub fn event(&self) -> &'static str {
+        match self {
+            Stream::Stdout => "child_stdout",
+            Stream::Stderr => "child_stderr",
+        }
+    }
+}
+
+impl fmt::Display for Stream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stream::Stdout => write!(f, "stdout"),
+            Stream::Stderr => write!(f, "stderr"),
+        }
+    }
+}
+
+/// A line of the child's output on its way from the capture file into the
+/// state, the log sinks and the journal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapturedLine {
+    /// Where it starts in its capture file, see
+    /// [`CaptureFile::read_numbered`](crate::capture::CaptureFile::read_numbered).
+    /// The state's buffers can't keep it, journal entries and shipped lines
+    /// do.
+    pub id: u64,
+    /// What the line is keyed by in the state.
+    pub timestamp: u64,
+    pub line: String,
+}
+
+/// A single captured line tagged with its stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputLine<'a> {
+    pub timestamp: u64,
+    pub stream: Stream,
+    pub line: &'a str,
+}
+
+/// Interleave both buffers by key.
+///
+/// Both inputs are expected to already be sorted, which the periodic merge
+/// in the supervisor guarantees. Lines with the same key are ordered by the
+/// milliseconds of their own timestamps, and after that stdout comes first
+/// since a request log usually precedes the error it triggered.
+pub fn merged<'a>(stdout: &'a [(u64, String)], stderr: &'a [(u64, String)]) -> Vec<OutputLine<'a>> {
+    let mut combined = Vec::with_capacity(stdout.len() + stderr.len());
+    let mut out = stdout.iter().peekable();
+    let mut err = stderr.iter().peekable();
+
+    loop {
+        let take_stdout = match (out.peek(), err.peek()) {
+            (Some(o), Some(e)) => order(o) <= order(e),
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => break,
+        };
+
+        let (stream, (timestamp, line)) = match take_stdout {
+            true => (Stream::Stdout, out.next().unwrap()),
+            false => (Stream::Stderr, err.next().unwrap()),
+        };
+        combined.push(OutputLine {
+            timestamp: *timestamp,
+            stream,
+            line,
+        });
+    }
+
+    combined
+}
+
+/// The last `count` lines of the merged view.
+pub fn merged_tail<'a>(
+    stdout: &'a [(u64, String)],
+    stderr: &'a [(u64, String)],
+    count: usize,
+) -> Vec<OutputLine<'a>> {
+    let mut combined = merged(stdout, stderr);
+    let skip = combined.len().saturating_sub(count);
+    combined.drain(..skip);
+    combined
+}
+
+/// Append keyed lines to a state buffer, keeping it sorted by key.
+///
+/// Only meant for child provided timestamps, lines keyed by capture time are
+/// appended in capture order instead so a clock jumping backwards can't move
+/// new lines in front of old ones. Only the lines from where the earliest
+/// new one belongs are re-sorted, the history before it is never touched,
+/// and existing lines stay in front of new ones in the same millisecond.
+pub fn append_sorted(
+    target: &mut Vec<(u64, String)>,
+    lines: impl IntoIterator<Item = (u64, String)>,
+) {
+    let existing = target.len();
+    target.extend(lines);
+
+    let earliest = match target[existing..].iter().map(order).min() {
+        Some(earliest) => earliest,
+        None => return,
+    };
+    let from = target[..existing].partition_point(|line| order(line) <= earliest);
+    let tail = &mut target[from..];
+    if !tail
+        .windows(2)
+        .all(|pair| order(&pair[0]) <= order(&pair[1]))
+    {
+        tail.sort_by_key(order);
+    }
+}
+
+/// What lines are ordered by: their key, then the milliseconds of a child
+/// timestamp in that second.
+fn order(line: &(u64, String)) -> (u64, u32) {
+    (line.0, subsecond(line.0, &line.1))
+}
+
+/// Evict the oldest lines of a state buffer until it holds at most
+/// `max_lines` lines of at most `max_bytes` text, 0 lifting either limit.
+/// Returns how many lines were evicted.
+pub fn retain_newest(buffer: &mut Vec<(u64, String)>, max_lines: usize, max_bytes: u64) -> usize {
+    let mut keep = match max_lines {
+        0 => buffer.len(),
+        max_lines => buffer.len().min(max_lines),
+    };
+    if max_bytes > 0 {
+        let mut bytes: u64 = 0;
+        keep = buffer
+            .iter()
+            .rev()
+            .take(keep)
+            .take_while(|(_, line)| {
+                bytes += line.len() as u64;
+                bytes <= max_bytes
+            })
+            .count();
+    }
+
+    let evicted = buffer.len() - keep;
+    buffer.drain(..evicted);
+    evicted
+}
@@ -0,0 +1,246 @@
+//! Polling fallback for trees inotify can't watch.
+//!
+//! Every watched directory needs an inotify watch per subdirectory, and a
+//! user only gets `fs.inotify.max_user_watches` of them. Past the limit
+//! notify quietly watches part of the tree, so changes go unnoticed. Before
+//! a directory's monitor is started its subdirectories are registered with
+//! a throwaway watcher; when that runs into the limit the required sysctl
+//! is logged and the directory is scanned every `interval_seconds` instead:
+//!
+//! ```toml
+//! [app_specific.polling]
+//! fallback = true        # default, false only logs the failure
+//! interval_seconds = 5
+//! ```
+//!
+//! A scan compares the modification time and size of every file below the
+//! directory, skipping `ignored_subdirs`, with the previous one and hands
+//! the differences to the main loop as a [`ChangeBatch`], like a monitor
+//! would. Scans while the monitors are paused only update the snapshot.
+
+use artisan_middleware::dusa_collection_utils::core::logger::LogLevel;
+use notify::{ErrorKind, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::Ordering,
+    time::{Duration, SystemTime},
+};
+use tokio::{sync::mpsc, task::spawn_blocking, time::interval};
+
+use crate::global_child::GLOBAL_MONITORS_PAUSED;
+use crate::log;
+use crate::watch::{ChangeBatch, ChangeKind};
+
+const MAX_USER_WATCHES: &str = "/proc/sys/fs/inotify/max_user_watches";
+
+/// `[app_specific.polling]`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PollingConfig {
+    /// Poll directories inotify can't watch, instead of only logging it.
+    #[serde(default = "default_fallback")]
+    pub fallback: bool,
+    #[serde(default = "default_poll_interval")]
+    pub interval_seconds: u64,
+}
+
+impl Default for PollingConfig {
+    fn default() -> Self {
+        Self {
+            fallback: default_fallback(),
+            interval_seconds: default_poll_interval(),
+        }
+    }
+}
+
+fn default_fallback() -> bool {
+    true
+}
+
+fn default_poll_interval() -> u64 {
+    5
+}
+
+/// The directories below `root`, `root` included, that aren't `ignored`.
+/// Symlinks aren't followed.
+pub fn directories(root: &Path, ignored: &[PathBuf]) -> Vec<PathBuf> {
+    let mut found = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        if ignored.iter().any(|ignored| dir.starts_with(ignored)) {
+            continue;
+        }
+        if let Ok(entries) = fs::read_dir(&dir) {
+            for entry in entries.filter_map(Result::ok) {
+                if entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+                    pending.push(entry.path());
+                }
+            }
+        }
+        found.push(dir);
+    }
+    found
+}
+
+/// Whether inotify has a watch left for each of `dirs`, registering them
+/// with a watcher that's dropped right after. `Ok(false)` once the limit
+/// is reached.
+pub fn watches_fit(dirs: &[PathBuf]) -> Result<bool, String> {
+    let mut watcher = notify::recommended_watcher(|_| ())
+        .map_err(|err| format!("Failed to create an inotify watcher: {}", err))?;
+    for dir in dirs {
+        match watcher.watch(dir, RecursiveMode::NonRecursive) {
+            Ok(()) => (),
+            Err(err) if matches!(err.kind, ErrorKind::MaxFilesWatch) => return Ok(false),
+            // Gone or unreadable since it was listed, the monitor copes
+            Err(_) => (),
+        }
+    }
+    Ok(true)
+}
+
+/// The current `fs.inotify.max_user_watches`.
+pub fn max_user_watches() -> Option<u64> {
+    fs::read_to_string(MAX_USER_WATCHES)
+        .ok()
+        .and_then(|limit| limit.trim().parse().ok())
+}
+
+/// The sysctl that leaves room for `directories` more watches on top of
+/// the `current` limit.
+pub fn sysctl_hint(current: Option<u64>, directories: usize) -> String {
+    let needed = (current.unwrap_or(8192) + directories as u64).next_power_of_two();
+    format!(
+        "sysctl -w fs.inotify.max_user_watches={} (and in /etc/sysctl.d to keep it)",
+        needed
+    )
+}
+
+/// Modification time and size of every file below a directory.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    files: BTreeMap<PathBuf, (SystemTime, u64)>,
+}
+
+impl Snapshot {
+    pub fn scan(root: &Path, ignored: &[PathBuf]) -> Self {
+        let mut files = BTreeMap::new();
+        for dir in directories(root, ignored) {
+            let entries = match fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries.filter_map(Result::ok) {
+                let meta = match entry.metadata() {
+                    Ok(meta) if !meta.is_dir() => meta,
+                    _ => continue,
+                };
+                let modified = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                files.insert(entry.path(), (modified, meta.len()));
+            }
+        }
+        Self { files }
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// What changed from this snapshot to `newer`, as a batch of the
+    /// `index`th watched directory.
+    pub fn diff(&self, newer: &Snapshot, index: usize) -> ChangeBatch {
+        let mut batch = ChangeBatch::new(index);
+        for (path, seen) in &newer.files {
+            match self.files.get(path) {
+                None => batch.add(ChangeKind::Create, [path.clone()]),
+                Some(before) if before != seen => batch.add(ChangeKind::Modify, [path.clone()]),
+                Some(_) => (),
+            }
+        }
+        for path in self.files.keys() {
+            if !newer.files.contains_key(path) {
+                batch.add(ChangeKind::Remove, [path.clone()]);
+            }
+        }
+        batch
+    }
+}
+
+/// Scan `root` every `period`, sending what changed as batches of the
+/// `index`th watched directory until `tx` is closed.
+pub fn spawn(
+    index: usize,
+    root: PathBuf,
+    ignored: Vec<PathBuf>,
+    period: Duration,
+    tx: mpsc::Sender<ChangeBatch>,
+) {
+    tokio::spawn(async move {
+        let mut ticks = interval(period);
+        let mut previous: Option<Snapshot> = None;
+        loop {
+            ticks.tick().await;
+            if tx.is_closed() {
+                return;
+            }
+            let (scan_root, scan_ignored) = (root.clone(), ignored.clone());
+            let current =
+                match spawn_blocking(move || Snapshot::scan(&scan_root, &scan_ignored)).await {
+                    Ok(current) => current,
+                    Err(err) => {
+                        log!(LogLevel::Warn, "Scan of {} failed: {}", root.display(), err);
+                        continue;
+                    }
+                };
+            // The first scan is what later ones are compared with
+            let batch = previous.as_ref().map(|before| before.diff(&current, index));
+            previous = Some(current);
+            let batch = match batch {
+                Some(batch) if !batch.is_empty() => batch,
+                _ => continue,
+            };
+            if GLOBAL_MONITORS_PAUSED.load(Ordering::Relaxed) {
+                continue;
+            }
+            if tx.send(batch).await.is_err() {
+                return;
+            }
+        }
+    });
+}
+
+/// Whether inotify runs out of watches for `root`, logging the sysctl
+/// that fixes it.
+pub fn watch_limit_reached(root: &Path, ignored: &[PathBuf]) -> bool {
+    let dirs = directories(root, ignored);
+    match watches_fit(&dirs) {
+        Ok(true) => return false,
+        Ok(false) => (),
+        Err(err) => {
+            log!(
+                LogLevel::Warn,
+                "Couldn't check the inotify limits for {}: {}",
+                root.display(),
+                err
+            );
+            return false;
+        }
+    }
+
+    let limit = max_user_watches();
+    log!(
+        LogLevel::Error,
+        "Watching the {} directories of {} hits fs.inotify.max_user_watches ({}), changes would go unnoticed. Raise it with {}",
+        dirs.len(),
+        root.display(),
+        limit.map_or_else(|| String::from("unknown"), |limit| limit.to_string()),
+        sysctl_hint(limit, dirs.len())
+    );
+    true
+}
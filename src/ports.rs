@@ -0,0 +1,113 @@
+//! The port the child listens on.
+//!
+//! With `port` set the runner hands the child its port instead of the app
+//! hardcoding one:
+//!
+//! ```toml
+//! [app_specific]
+//! port = 8080      # or "auto"
+//! ```
+//!
+//! A fixed port that another process already has bound stops the runner at
+//! start up with an error, rather than the child crash looping on
+//! `EADDRINUSE`. `"auto"` picks a free port, keeping the one of the previous
+//! run while it's still free and avoiding ports other runners reserved.
+//! Either way the child gets it as `PORT`, it fills in `{port}` in the
+//! commands and is recorded in the runner state.
+
+use serde::{Deserialize, Serialize};
+use std::net::TcpListener;
+
+use crate::config::AppSpecificConfig;
+
+/// How many ephemeral ports to try before giving up on `"auto"`.
+const AUTO_ATTEMPTS: usize = 16;
+
+/// `port` as written in the config.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum PortEntry {
+    Number(u16),
+    Word(String),
+}
+
+/// `port`, a number or `"auto"`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "PortEntry", into = "PortEntry")]
+pub enum PortConfig {
+    Fixed(u16),
+    Auto,
+}
+
+impl From<PortConfig> for PortEntry {
+    fn from(config: PortConfig) -> Self {
+        match config {
+            PortConfig::Fixed(port) => PortEntry::Number(port),
+            PortConfig::Auto => PortEntry::Word(String::from("auto")),
+        }
+    }
+}
+
+impl TryFrom<PortEntry> for PortConfig {
+    type Error = String;
+
+    fn try_from(entry: PortEntry) -> Result<Self, Self::Error> {
+        match entry {
+            PortEntry::Number(0) => Err(String::from(
+                "port can't be 0, use \"auto\" to have one picked",
+            )),
+            PortEntry::Number(port) => Ok(PortConfig::Fixed(port)),
+            PortEntry::Word(word) if word == "auto" => Ok(PortConfig::Auto),
+            PortEntry::Word(word) => Err(format!(
+                "port has to be a number or \"auto\", not {:?}",
+                word
+            )),
+        }
+    }
+}
+
+/// Whether nothing has `port` bound.
+pub fn is_free(port: u16) -> bool {
+    TcpListener::bind(("0.0.0.0", port)).is_ok()
+}
+
+/// Pick the port for `config`. `previous` is the port of the last run and
+/// `taken` the ports other runners reserved.
+pub fn allocate(config: PortConfig, previous: Option<u16>, taken: &[u16]) -> Result<u16, String> {
+    let port = match config {
+        PortConfig::Fixed(port) if is_free(port) => return Ok(port),
+        PortConfig::Fixed(port) => {
+            return Err(format!(
+                "Port {} is already bound by another process, refusing to start",
+                port
+            ));
+        }
+        PortConfig::Auto => previous.filter(|port| !taken.contains(port) && is_free(*port)),
+    };
+    if let Some(port) = port {
+        return Ok(port);
+    }
+
+    for _ in 0..AUTO_ATTEMPTS {
+        let port = TcpListener::bind(("0.0.0.0", 0))
+            .and_then(|listener| listener.local_addr())
+            .map_err(|err| format!("Failed to find a free port: {}", err))?
+            .port();
+        if !taken.contains(&port) {
+            return Ok(port);
+        }
+    }
+    Err(format!(
+        "No free port found in {} attempts that isn't reserved by another runner",
+        AUTO_ATTEMPTS
+    ))
+}
+
+/// Hand `port` to the child: as `PORT`, as the first reserved port and so
+/// as `{port}` in the commands.
+pub fn apply(settings: &mut AppSpecificConfig, port: u16) {
+    settings.env.insert(String::from("PORT"), port.to_string());
+    let ports = &mut settings.reservation.ports;
+    ports.retain(|reserved| *reserved != port);
+    ports.insert(0, port);
+}
@@ -0,0 +1,215 @@
+//! Checking what the install, build and run commands need before the first
+//! spawn.
+//!
+//! [`crate::validation`] only looks at the config. A program that isn't
+//! installed, a project directory the runner can't write to or a
+//! `node_modules` left behind by root used to surface as a failed spawn
+//! halfway through the first deploy. [`check`] looks for all of them up
+//! front and returns everything missing at once, each with what to do
+//! about it:
+//!
+//! - the program of `run_command`, `install_command`, `build_command` and
+//!   every build step, on `PATH` or at its path, or the program the build
+//!   executor runs them with (`docker`, `nix-build`)
+//! - that `project_path` is writable when something is installed or built
+//! - that `node_modules` and `target` in `project_path` are accessible
+//!
+//! A `run_command` like `./target/release/app` is only looked for when
+//! nothing is built, it's usually what the build produces. Everything is
+//! checked as the user the runner runs as.
+
+use nix::unistd::{AccessFlags, User, access, getuid};
+use std::{
+    env,
+    ffi::OsStr,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::command_vars::CommandVars;
+use crate::config::AppSpecificConfig;
+
+/// Directories in the project install and build steps write to.
+const BUILD_DIRS: &[&str] = &["node_modules", "target"];
+
+/// Something a command needs that isn't there.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Missing {
+    /// The option needing it, e.g. `app_specific.build_command`.
+    pub key: String,
+    pub message: String,
+    /// What to do about it.
+    pub hint: String,
+}
+
+impl Missing {
+    fn new(key: &str, message: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            key: format!("app_specific.{}", key),
+            message: message.into(),
+            hint: hint.into(),
+        }
+    }
+}
+
+impl fmt::Display for Missing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}, {}", self.key, self.message, self.hint)
+    }
+}
+
+/// All of `missing` as one message, one per line.
+pub fn report(missing: &[Missing]) -> String {
+    let mut report = match missing.len() {
+        1 => String::from("A prerequisite is missing:"),
+        count => format!("{} prerequisites are missing:", count),
+    };
+    for missing in missing {
+        report.push_str(&format!("\n  - {}", missing));
+    }
+    report
+}
+
+fn is_executable(path: &Path) -> bool {
+    path.is_file() && access(path, AccessFlags::X_OK).is_ok()
+}
+
+/// Where `program` is run from: relative to `dir` when it's a path,
+/// otherwise the first executable of that name in `search`, a `PATH`.
+pub fn find_program(program: &str, dir: &Path, search: Option<&OsStr>) -> Option<PathBuf> {
+    if program.contains('/') {
+        let path = dir.join(program);
+        return is_executable(&path).then_some(path);
+    }
+    env::split_paths(search?)
+        .map(|dir| dir.join(program))
+        .find(|candidate| is_executable(candidate))
+}
+
+/// What to run `chown` with to hand `path` to the runner's user.
+fn chown_hint(path: &Path) -> String {
+    let user = User::from_uid(getuid())
+        .ok()
+        .flatten()
+        .map_or_else(|| getuid().to_string(), |user| user.name);
+    format!("run chown -R {} {}", user, path.display())
+}
+
+/// Check the program `argv` starts with.
+fn check_program(
+    missing: &mut Vec<Missing>,
+    key: &str,
+    argv: &[String],
+    dir: &Path,
+    search: Option<&OsStr>,
+) {
+    let program = match argv.first() {
+        // Placeholders are only filled in when the command runs
+        Some(program) if !program.is_empty() && !program.contains('{') => program,
+        _ => return,
+    };
+    if find_program(program, dir, search).is_some() {
+        return;
+    }
+
+    if program.contains('/') {
+        let path = dir.join(program);
+        match path.exists() {
+            true => missing.push(Missing::new(
+                key,
+                format!("{} isn't executable", path.display()),
+                format!("run chmod +x {}", path.display()),
+            )),
+            false => missing.push(Missing::new(
+                key,
+                format!("{} doesn't exist", path.display()),
+                "fix the path or use a program on PATH",
+            )),
+        }
+        return;
+    }
+    let search = search
+        .map(|search| search.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    missing.push(Missing::new(
+        key,
+        format!("{} isn't on PATH ({})", program, search),
+        format!(
+            "install {} or point the command at where it's installed",
+            program
+        ),
+    ));
+}
+
+/// Everything the commands of `settings` need that's missing, empty when
+/// the first deploy can go ahead. `search` is the `PATH` to look for
+/// programs in.
+pub fn check(settings: &AppSpecificConfig, search: Option<&OsStr>) -> Vec<Missing> {
+    let mut missing = Vec::new();
+    let project = fs::canonicalize(&settings.project_path)
+        .unwrap_or_else(|_| PathBuf::from(&settings.project_path));
+    let here = env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    let vars = CommandVars::current(settings);
+    let executor = settings.build_executor.executor();
+
+    // Install and build run where the runner was started, steps in their cwd
+    let mut builds = Vec::new();
+    for (key, name, command) in [
+        ("install_command", "install", &settings.install_command),
+        ("build_command", "build", &settings.build_command),
+    ] {
+        if let Some(command) = command {
+            builds.push((String::from(key), name, command, here.clone()));
+        }
+    }
+    for (index, step) in settings.steps.iter().enumerate() {
+        let dir = match &step.cwd {
+            Some(cwd) => project.join(cwd),
+            None => here.clone(),
+        };
+        let key = format!("steps[{}].command", index);
+        builds.push((key, step.name.as_str(), &step.command, dir));
+    }
+
+    for (key, name, command, dir) in &builds {
+        let parts = vars.argv(command);
+        let argv = executor.argv(name, Some(&parts), &project, &settings.env);
+        if let Some(argv) = argv {
+            check_program(&mut missing, key, &argv, dir, search);
+        }
+    }
+
+    // What a build produces doesn't exist before the first one
+    let argv = vars.argv(&settings.run_command);
+    let builds_something = settings.build_command.is_some() || !settings.steps.is_empty();
+    let built = argv.first().is_some_and(|program| program.contains('/')) && builds_something;
+    if !built {
+        let dir = settings.working_dir().to_path_buf();
+        check_program(&mut missing, "run_command", &argv, &dir, search);
+    }
+
+    if !project.is_dir() {
+        // Reported by validation
+        return missing;
+    }
+    if !builds.is_empty() && access(&project, AccessFlags::W_OK).is_err() {
+        missing.push(Missing::new(
+            "project_path",
+            format!("{} isn't writable", project.display()),
+            chown_hint(&project),
+        ));
+    }
+    for dir in BUILD_DIRS {
+        let path = project.join(dir);
+        let flags = AccessFlags::R_OK | AccessFlags::W_OK | AccessFlags::X_OK;
+        if path.exists() && access(&path, flags).is_err() {
+            missing.push(Missing::new(
+                "project_path",
+                format!("{} isn't accessible", path.display()),
+                chown_hint(&path),
+            ));
+        }
+    }
+
+    missing
+}
@@ -0,0 +1,291 @@
+//! Dropping root after start up (`drop_privileges`).
+//!
+//! Root is only needed for a few things: creating the child's cgroup,
+//! binding ports below 1024 and starting the child as `run_as_user`. With
+//!
+//! ```toml
+//! [app_specific]
+//! run_as_user = "app"
+//! drop_privileges = true
+//! ```
+//!
+//! the runner does those once, in a privileged phase at start up, and then
+//! switches itself to `run_as_user` / `run_as_group` for good, before any
+//! secret is fetched. The child inherits the identity, so everything that
+//! runs for the rest of the runner's life, secrets included, runs
+//! unprivileged.
+//!
+//! The privileged phase
+//!
+//! - binds the configured listeners on privileged ports and keeps them for
+//!   [`bind`] to hand out later,
+//! - sets up the child cgroup and hands it to the user,
+//! - hands the state file, its sidecars, the capture files and the ACME
+//!   certificate directory to the user.
+//!
+//! Anything that needs root again afterwards, like a changed cgroup parent
+//! or a new privileged port after a reload, needs a restart of the runner.
+
+use artisan_middleware::dusa_collection_utils;
+use dusa_collection_utils::core::{logger::LogLevel, types::pathtype::PathType};
+use std::{
+    fs, io,
+    net::{TcpListener as StdListener, ToSocketAddrs},
+    path::PathBuf,
+    sync::Mutex,
+};
+use tokio::net::TcpListener;
+
+use crate::acme::CertStore;
+use crate::capture;
+use crate::cgroup::ChildCgroup;
+use crate::child::{ChildIdentity, resolve_identity};
+use crate::config::AppSpecificConfig;
+use crate::diagnostics;
+use crate::log;
+use crate::metrics_history;
+use crate::output::Stream;
+use crate::ports::PortConfig;
+use crate::runner_state::RunnerState;
+use crate::uptime;
+
+/// Ports below this need root to bind.
+pub const PRIVILEGED_PORTS: u16 = 1024;
+
+/// Listeners bound during the privileged phase, by configured address.
+static PREBOUND: Mutex<Vec<(String, StdListener)>> = Mutex::new(Vec::new());
+
+/// Whether binding `addr` needs root.
+pub fn is_privileged(addr: &str) -> bool {
+    addr.to_socket_addrs()
+        .map(|mut addrs| addrs.any(|addr| addr.port() < PRIVILEGED_PORTS))
+        .unwrap_or(false)
+}
+
+/// The listener addresses in `settings` that need root to bind.
+pub fn privileged_addrs(settings: &AppSpecificConfig) -> Vec<String> {
+    let mut addrs = Vec::new();
+    if settings.static_server.enabled {
+        addrs.push(settings.static_server.bind.clone());
+        addrs.extend(
+            settings
+                .acme
+                .tls_bind
+                .clone()
+                .filter(|_| settings.acme.enabled),
+        );
+    }
+    addrs.extend(settings.webhook_addr.clone());
+    addrs.extend(settings.state_sync.addr.clone());
+    addrs.extend(settings.status_server.addr.clone());
+    addrs.extend(settings.proxy.listen.clone());
+    let port = match settings.port {
+        Some(PortConfig::Fixed(port)) => Some(port),
+        _ => None,
+    };
+    addrs.extend(settings.listen_fds.addrs(port));
+    addrs.retain(|addr| is_privileged(addr));
+    addrs
+}
+
+/// Bind `addr` now and keep the listener for [`bind`].
+pub fn prebind(addr: &str) -> io::Result<()> {
+    let listener = StdListener::bind(addr)?;
+    listener.set_nonblocking(true)?;
+    if let Ok(mut prebound) = PREBOUND.lock() {
+        prebound.push((addr.to_owned(), listener));
+    }
+    Ok(())
+}
+
+/// A listener on `addr`, the one bound during the privileged phase if there
+/// is one.
+pub async fn bind(addr: &str) -> io::Result<TcpListener> {
+    let prebound = PREBOUND.lock().ok().and_then(|mut prebound| {
+        let index = prebound.iter().position(|(bound, _)| bound == addr)?;
+        Some(prebound.remove(index).1)
+    });
+    match prebound {
+        Some(listener) => TcpListener::from_std(listener),
+        None => TcpListener::bind(addr).await,
+    }
+}
+
+/// Give `paths` that exist to `identity`, so they stay writable after the
+/// drop. A symlink at one of them is refused rather than followed.
+pub fn hand_over(paths: &[PathBuf], identity: &ChildIdentity) -> Result<(), String> {
+    paths
+        .iter()
+        .filter(|path| fs::symlink_metadata(path).is_ok())
+        .try_for_each(|path| platform::chown(path, identity.uid, identity.gid))
+}
+
+/// Everything the privileged phase hands to the user, whether it exists or
+/// not.
+pub fn handed_over(
+    settings: &AppSpecificConfig,
+    state_path: &PathType,
+    app_name: &str,
+) -> Vec<PathBuf> {
+    let mut paths = vec![
+        PathBuf::from(state_path.to_string()),
+        RunnerState::path(state_path),
+        metrics_history::path(state_path),
+        uptime::path(state_path),
+        capture::dir(state_path),
+        capture::path(state_path, Stream::Stdout),
+        capture::path(state_path, Stream::Stderr),
+        settings.journal.path(state_path),
+        settings.events.path(state_path),
+        diagnostics::dir(settings, state_path),
+    ];
+    paths.extend(settings.state_backend.path(state_path));
+    if settings.acme.enabled {
+        paths.extend(CertStore::new(settings.acme.cert_dir(app_name)).paths());
+    }
+    paths
+}
+
+/// Run the privileged phase and switch to `run_as_user` / `run_as_group`.
+///
+/// `cgroup` is the child cgroup if one was set up. A runner that isn't root
+/// has nothing to drop and is left alone.
+pub fn drop_privileges(
+    settings: &AppSpecificConfig,
+    state_path: &PathType,
+    app_name: &str,
+    cgroup: Option<&ChildCgroup>,
+) -> Result<(), String> {
+    let identity = match resolve_identity(settings) {
+        Ok(Some(identity)) => identity,
+        Ok(None) => {
+            return Err(String::from(
+                "drop_privileges needs run_as_user or run_as_group to switch to",
+            ));
+        }
+        Err(err) => return Err(err.err_mesg.to_string()),
+    };
+    if !is_root() {
+        log!(
+            LogLevel::Debug,
+            "Not running as root, no privileges to drop"
+        );
+        return Ok(());
+    }
+
+    for addr in privileged_addrs(settings) {
+        prebind(&addr).map_err(|err| format!("Failed to bind {}: {}", addr, err))?;
+        log!(LogLevel::Debug, "Bound {} before dropping privileges", addr);
+    }
+    if let Some(cgroup) = cgroup {
+        cgroup
+            .delegate(identity.uid, identity.gid)
+            .map_err(|err| err.err_mesg.to_string())?;
+    }
+    if settings.acme.enabled {
+        // The certificate watcher only starts after the drop
+        platform::create_private_dir(&settings.acme.cert_dir(app_name))?;
+    }
+    let paths = handed_over(settings, state_path, app_name);
+    hand_over(&paths, &identity)?;
+    drop_to(&identity)
+}
+
+/// Whether the runner runs as root and so has something to drop.
+pub fn is_root() -> bool {
+    platform::is_root()
+}
+
+/// Switch the runner to `identity` for good: supplementary groups, group,
+/// then user.
+pub fn drop_to(identity: &ChildIdentity) -> Result<(), String> {
+    platform::drop_to(identity)?;
+    log!(
+        LogLevel::Info,
+        "Dropped privileges, running as {} ({}:{})",
+        identity.user_name,
+        identity.uid,
+        identity.gid
+    );
+    Ok(())
+}
+
+#[cfg(unix)]
+mod platform {
+    use nix::fcntl::OFlag;
+    use nix::unistd::{Gid, Uid, initgroups, setgid, setgroups, setuid};
+    use std::{
+        ffi::CString,
+        fs::{DirBuilder, OpenOptions},
+        os::unix::fs::{DirBuilderExt, OpenOptionsExt, fchown},
+        path::Path,
+    };
+
+    use crate::child::ChildIdentity;
+
+    /// Through the opened file, so what's handed over is what was checked
+    /// and a symlink swapped in isn't followed.
+    pub fn chown(path: &Path, uid: u32, gid: u32) -> Result<(), String> {
+        let flags = OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC | OFlag::O_NONBLOCK;
+        OpenOptions::new()
+            .read(true)
+            .custom_flags(flags.bits())
+            .open(path)
+            .and_then(|file| fchown(&file, Some(uid), Some(gid)))
+            .map_err(|err| format!("Failed to hand {} over: {}", path.display(), err))
+    }
+
+    pub fn create_private_dir(path: &Path) -> Result<(), String> {
+        DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(path)
+            .map_err(|err| format!("Failed to create {}: {}", path.display(), err))
+    }
+
+    pub fn is_root() -> bool {
+        Uid::effective().is_root()
+    }
+
+    pub fn drop_to(identity: &ChildIdentity) -> Result<(), String> {
+        let gid = Gid::from_raw(identity.gid);
+        let groups = match CString::new(identity.user_name.clone()) {
+            Ok(name) => initgroups(&name, gid),
+            Err(_) => setgroups(&[gid]),
+        };
+        groups.map_err(|err| format!("Failed to set supplementary groups: {}", err))?;
+        setgid(gid).map_err(|err| format!("Failed to switch to gid {}: {}", gid, err))?;
+        setuid(Uid::from_raw(identity.uid))
+            .map_err(|err| format!("Failed to switch to uid {}: {}", identity.uid, err))?;
+
+        // Being able to get root back would make all of this pointless
+        match setuid(Uid::from_raw(0)) {
+            Ok(()) => Err(String::from("Root could be regained after dropping it")),
+            Err(_) => Ok(()),
+        }
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::path::Path;
+
+    use crate::child::ChildIdentity;
+
+    pub fn chown(_path: &Path, _uid: u32, _gid: u32) -> Result<(), String> {
+        Ok(())
+    }
+
+    pub fn create_private_dir(path: &Path) -> Result<(), String> {
+        std::fs::create_dir_all(path)
+            .map_err(|err| format!("Failed to create {}: {}", path.display(), err))
+    }
+
+    pub fn is_root() -> bool {
+        false
+    }
+
+    pub fn drop_to(_identity: &ChildIdentity) -> Result<(), String> {
+        Err(String::from("drop_privileges isn't supported on Windows"))
+    }
+}
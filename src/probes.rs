@@ -0,0 +1,247 @@
+//! Health probes for the supervised child.
+//!
+//! Mirrors the Kubernetes probe semantics: an optional startup probe must
+//! succeed once after every spawn before the liveness probe is evaluated.
+//! Each probe has its own period and failure budget so slow booting apps can
+//! be given a generous startup window while still being restarted quickly
+//! once they are up.
+
+use artisan_middleware::dusa_collection_utils;
+use dusa_collection_utils::core::logger::LogLevel;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    process::Command,
+    time::timeout,
+};
+
+use crate::log;
+
+/// A single probe definition, e.g. `[app_specific.liveness_probe]`.
+///
+/// Exactly one of `tcp`, `http` or `command` should be set.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ProbeConfig {
+    /// `host:port` that must accept a TCP connection.
+    #[serde(default)]
+    pub tcp: Option<String>,
+    /// `http://host:port/path` that must answer with a 2xx/3xx status.
+    #[serde(default)]
+    pub http: Option<String>,
+    /// Command that must exit with status 0.
+    #[serde(default)]
+    pub command: Option<String>,
+    #[serde(default = "default_period")]
+    pub period_seconds: u64,
+    #[serde(default = "default_timeout")]
+    pub timeout_seconds: u64,
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+}
+
+fn default_period() -> u64 {
+    10
+}
+
+fn default_timeout() -> u64 {
+    2
+}
+
+fn default_failure_threshold() -> u32 {
+    3
+}
+
+impl ProbeConfig {
+    /// Run the probe once.
+    pub async fn check(&self) -> Result<(), String> {
+        let limit = Duration::from_secs(self.timeout_seconds.max(1));
+        match timeout(limit, self.check_inner()).await {
+            Ok(result) => result,
+            Err(_) => Err(format!("timed out after {}s", limit.as_secs())),
+        }
+    }
+
+    async fn check_inner(&self) -> Result<(), String> {
+        if let Some(addr) = &self.tcp {
+            return TcpStream::connect(addr)
+                .await
+                .map(|_| ())
+                .map_err(|err| format!("tcp {}: {}", addr, err));
+        }
+
+        if let Some(url) = &self.http {
+            return http_check(url).await;
+        }
+
+        if let Some(command) = &self.command {
+            let parts = shell_words::split(command).map_err(|err| err.to_string())?;
+            let mut iter = parts.into_iter();
+            let program = iter.next().ok_or("empty probe command")?;
+            let status = Command::new(program)
+                .args(iter)
+                .kill_on_drop(true)
+                .status()
+                .await
+                .map_err(|err| format!("command {}: {}", command, err))?;
+            return match status.success() {
+                true => Ok(()),
+                false => Err(format!("command {} exited with {}", command, status)),
+            };
+        }
+
+        Err(String::from("probe has no tcp, http or command configured"))
+    }
+}
+
+/// Minimal HTTP/1.0 GET, only the status line is inspected.
+async fn http_check(url: &str) -> Result<(), String> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| format!("{} is not an http:// url", url))?;
+    let (host, path) = match rest.find('/') {
+        Some(idx) => (&rest[..idx], &rest[idx..]),
+        None => (rest, "/"),
+    };
+    let addr = match host.contains(':') {
+        true => host.to_string(),
+        false => format!("{}:80", host),
+    };
+
+    let mut stream = TcpStream::connect(&addr)
+        .await
+        .map_err(|err| format!("http {}: {}", url, err))?;
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: ais_runner\r\n\r\n",
+        path, host
+    );
+    stream
+        .write_all(request.as_bytes())
+        .await
+        .map_err(|err| format!("http {}: {}", url, err))?;
+
+    let mut buffer = [0u8; 64];
+    let read = stream
+        .read(&mut buffer)
+        .await
+        .map_err(|err| format!("http {}: {}", url, err))?;
+    let status_line = String::from_utf8_lossy(&buffer[..read]);
+    let code: u16 = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| format!("http {}: malformed response", url))?;
+
+    match code {
+        200..=399 => Ok(()),
+        _ => Err(format!("http {} returned {}", url, code)),
+    }
+}
+
+/// Which probe is currently being evaluated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProbePhase {
+    Startup,
+    Liveness,
+}
+
+/// Result of a [`ProbeTracker::poll`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProbeOutcome {
+    /// Nothing was due, or the probe failed within its budget.
+    Pending,
+    /// The probe passed.
+    Healthy,
+    /// The failure budget of the current phase was exhausted, the child
+    /// should be restarted.
+    Failed(String),
+}
+
+/// Tracks probe phase and failure counts for the current child.
+#[derive(Debug)]
+pub struct ProbeTracker {
+    startup: Option<ProbeConfig>,
+    liveness: Option<ProbeConfig>,
+    phase: ProbePhase,
+    failures: u32,
+    last_run: Option<Instant>,
+}
+
+impl ProbeTracker {
+    pub fn new(startup: Option<ProbeConfig>, liveness: Option<ProbeConfig>) -> Self {
+        let mut tracker = Self {
+            startup,
+            liveness,
+            phase: ProbePhase::Startup,
+            failures: 0,
+            last_run: None,
+        };
+        tracker.reset();
+        tracker
+    }
+
+    /// Start over with the startup phase, called after every spawn.
+    pub fn reset(&mut self) {
+        self.phase = match self.startup {
+            Some(_) => ProbePhase::Startup,
+            None => ProbePhase::Liveness,
+        };
+        self.failures = 0;
+        self.last_run = None;
+    }
+
+    fn current(&self) -> Option<&ProbeConfig> {
+        match self.phase {
+            ProbePhase::Startup => self.startup.as_ref(),
+            ProbePhase::Liveness => self.liveness.as_ref(),
+        }
+    }
+
+    /// Run the probe for the current phase if its period has elapsed.
+    pub async fn poll(&mut self) -> ProbeOutcome {
+        let probe = match self.current() {
+            Some(probe) => probe.clone(),
+            None => return ProbeOutcome::Pending,
+        };
+
+        let period = Duration::from_secs(probe.period_seconds);
+        if self.last_run.is_some_and(|last_run| last_run.elapsed() < period) {
+            return ProbeOutcome::Pending;
+        }
+        self.last_run = Some(Instant::now());
+
+        match probe.check().await {
+            Ok(_) => {
+                self.failures = 0;
+                if self.phase == ProbePhase::Startup {
+                    log!(LogLevel::Info, "Startup probe passed, enabling liveness checks");
+                    self.phase = ProbePhase::Liveness;
+                    self.last_run = None;
+                }
+                ProbeOutcome::Healthy
+            }
+            Err(reason) => {
+                self.failures += 1;
+                log!(
+                    LogLevel::Warn,
+                    "{:?} probe failed ({} of {}): {}",
+                    self.phase,
+                    self.failures,
+                    probe.failure_threshold,
+                    reason
+                );
+                if self.failures >= probe.failure_threshold {
+                    let message = format!(
+                        "{:?} probe failed {} times: {}",
+                        self.phase, self.failures, reason
+                    );
+                    self.reset();
+                    ProbeOutcome::Failed(message)
+                } else {
+                    ProbeOutcome::Pending
+                }
+            }
+        }
+    }
+}
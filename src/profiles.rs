@@ -0,0 +1,65 @@
+//! Per-environment overlays of `[app_specific]`.
+//!
+//! A checked in `Config.toml` usually has to differ a little between
+//! development and production. Instead of keeping one file per
+//! environment, tables named after an environment hold what differs and
+//! the one matching `environment` of the app config is laid over the base
+//! section:
+//!
+//! ```toml
+//! [app_specific]
+//! run_command = "./server"
+//! changes_needed = 1
+//!
+//! [app_specific.production]
+//! changes_needed = 1000000
+//! restart_schedule = "0 4 * * *"
+//!
+//! [app_specific.production.cgroup]
+//! memory_max_mb = 512
+//! ```
+//!
+//! Tables in a profile are merged key by key into the base ones, anything
+//! else replaces the base value. `AIS_*` variables still win over both.
+//! The profiles of other environments are left out, a name that's also an
+//! option (`env`) is always the option.
+
+use config::{File, FileFormat, FileSourceString};
+use serde_json::{Map, Value};
+
+use crate::config_schema::{Kind, lookup};
+
+/// Environments whose tables are never taken for options.
+pub const PROFILES: &[&str] = &["development", "staging", "production"];
+
+/// Whether the table `name` below `[app_specific]` is a profile when
+/// running in `environment`.
+fn is_profile(schema: &Kind, name: &str, environment: &str) -> bool {
+    (PROFILES.contains(&name) || name == environment) && lookup(schema, name).is_none()
+}
+
+/// Remove the profile tables from the raw `[app_specific]` section,
+/// returning the one of `environment`, if there is one.
+pub fn take(section: &mut Value, environment: &str, schema: &Kind) -> Option<Map<String, Value>> {
+    let table = section.as_object_mut()?;
+    let names: Vec<String> = table
+        .iter()
+        .filter(|(name, value)| value.is_object() && is_profile(schema, name, environment))
+        .map(|(name, _)| name.clone())
+        .collect();
+
+    let mut selected = None;
+    for name in names {
+        if let (Some(Value::Object(profile)), true) = (table.remove(&name), name == environment) {
+            selected = Some(profile);
+        }
+    }
+    selected
+}
+
+/// `profile` as a config source laid over `[app_specific]`.
+pub fn source(profile: Map<String, Value>) -> File<FileSourceString, FileFormat> {
+    let mut root = Map::new();
+    root.insert(String::from("app_specific"), Value::Object(profile));
+    File::from_str(&Value::Object(root).to_string(), FileFormat::Json)
+}
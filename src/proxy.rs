@@ -0,0 +1,370 @@
+//! A listener in front of the child.
+//!
+//! With `[app_specific.proxy]` the runner owns the public port and passes
+//! every connection on to the child's `port`:
+//!
+//! ```toml
+//! [app_specific]
+//! port = 8080
+//!
+//! [app_specific.proxy]
+//! listen = "0.0.0.0:80"
+//! idle_minutes = 15
+//! hold = "queue"
+//! ```
+//!
+//! Since the port never goes away, nothing is refused while the child is
+//! replaced. Connections arriving between the old child being stopped and
+//! the new one being ready are held: queued for up to `hold_seconds` and
+//! passed on once it's ready, or answered with a `503` page right away
+//! with `hold = "page"`.
+//!
+//! With `idle_minutes` set, a child that had no open connection for that
+//! long is stopped, the runner keeps listening. The next connection has it
+//! spawned again, like systemd socket activation, and waits up to
+//! `wake_timeout_seconds` for it to be ready.
+//!
+//! Only traffic through the listener counts, clients that reach the child's
+//! port directly neither keep it awake nor wake it.
+
+use artisan_middleware::dusa_collection_utils::core::logger::LogLevel;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs, io,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, copy_bidirectional},
+    net::TcpStream,
+    sync::{Notify, watch},
+    time::{sleep, timeout},
+};
+
+use crate::log;
+use crate::privileges;
+use crate::shutdown::{self, Stage};
+
+/// How often a ready child's port is tried until it accepts.
+const CONNECT_RETRY: Duration = Duration::from_millis(100);
+/// How long the request of a connection answered with the page is read.
+const PAGE_READ: Duration = Duration::from_millis(500);
+/// Served with `hold = "page"` when no `hold_page` is configured.
+const DEFAULT_PAGE: &str = "<!doctype html>\n<title>Restarting</title>\n<p>The service is restarting, please try again in a moment.</p>\n";
+
+/// What happens to connections while the child restarts.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum HoldMode {
+    /// Wait for the new child, up to `hold_seconds`.
+    #[default]
+    Queue,
+    /// Answer with a `503` page.
+    Page,
+}
+
+/// `[app_specific.proxy]`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ProxyConfig {
+    /// Address the runner accepts connections for the child on, there's no
+    /// proxy while unset.
+    #[serde(default)]
+    pub listen: Option<String>,
+    /// Stop the child after this long without an open connection, it's
+    /// left running while unset.
+    #[serde(default)]
+    pub idle_minutes: Option<u64>,
+    /// How long a connection waits for a stopped child to be ready.
+    #[serde(default = "default_wake_timeout")]
+    pub wake_timeout_seconds: u64,
+    #[serde(default)]
+    pub hold: HoldMode,
+    /// How long a connection is queued while the child restarts.
+    #[serde(default = "default_hold_seconds")]
+    pub hold_seconds: u64,
+    /// HTML file answered with `hold = "page"`, a plain default page while
+    /// unset.
+    #[serde(default)]
+    pub hold_page: Option<String>,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        Self {
+            listen: None,
+            idle_minutes: None,
+            wake_timeout_seconds: default_wake_timeout(),
+            hold: HoldMode::default(),
+            hold_seconds: default_hold_seconds(),
+            hold_page: None,
+        }
+    }
+}
+
+fn default_wake_timeout() -> u64 {
+    60
+}
+
+fn default_hold_seconds() -> u64 {
+    30
+}
+
+impl ProxyConfig {
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_minutes
+            .map(|minutes| Duration::from_secs(minutes * 60))
+    }
+
+    /// The `503` response connections get with `hold = "page"`.
+    pub fn page(&self) -> io::Result<Vec<u8>> {
+        let body = match &self.hold_page {
+            Some(path) => fs::read(path)?,
+            None => DEFAULT_PAGE.as_bytes().to_vec(),
+        };
+        let mut response = format!(
+            "HTTP/1.1 503 Service Unavailable\r\n\
+             Content-Type: text/html; charset=utf-8\r\n\
+             Content-Length: {}\r\n\
+             Retry-After: 5\r\n\
+             Connection: close\r\n\r\n",
+            body.len()
+        )
+        .into_bytes();
+        response.extend(body);
+        Ok(response)
+    }
+}
+
+/// Whether the child can take connections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Availability {
+    Up,
+    /// Being replaced, connections are held.
+    Restarting,
+    /// Stopped for being idle, the next connection wakes it.
+    Asleep,
+}
+
+#[derive(Debug)]
+struct Traffic {
+    /// Connections being passed on right now.
+    open: usize,
+    /// When the last connection opened or closed.
+    last: Instant,
+}
+
+/// Traffic through the listener and whether the child takes it.
+#[derive(Debug)]
+pub struct Activity {
+    idle_timeout: Option<Duration>,
+    traffic: Mutex<Traffic>,
+    availability: watch::Sender<Availability>,
+    wake: Notify,
+}
+
+impl Activity {
+    /// Activity of a child that's up, stopped after `idle_timeout` without
+    /// traffic if set.
+    pub fn new(idle_timeout: Option<Duration>, now: Instant) -> Self {
+        Self {
+            idle_timeout,
+            traffic: Mutex::new(Traffic { open: 0, last: now }),
+            availability: watch::Sender::new(Availability::Up),
+            wake: Notify::new(),
+        }
+    }
+
+    pub fn availability(&self) -> Availability {
+        *self.availability.borrow()
+    }
+
+    pub fn is_asleep(&self) -> bool {
+        self.availability() == Availability::Asleep
+    }
+
+    /// How long the child went without traffic at `now`, once that's
+    /// longer than the idle timeout and it's up.
+    pub fn due(&self, now: Instant) -> Option<Duration> {
+        let idle_timeout = self.idle_timeout?;
+        if self.availability() != Availability::Up {
+            return None;
+        }
+        let traffic = self.traffic.lock().ok()?;
+        let quiet = now.saturating_duration_since(traffic.last);
+        match traffic.open == 0 && quiet >= idle_timeout {
+            true => Some(quiet),
+            false => None,
+        }
+    }
+
+    /// The child is being stopped to be replaced, or wasn't started yet.
+    pub fn restarting(&self) {
+        self.availability.send_replace(Availability::Restarting);
+    }
+
+    /// The child was stopped for being idle.
+    pub fn sleep(&self) {
+        self.availability.send_replace(Availability::Asleep);
+    }
+
+    /// A new child is ready, it gets a full idle timeout from `now`.
+    pub fn up(&self, now: Instant) {
+        if let Ok(mut traffic) = self.traffic.lock() {
+            traffic.last = now;
+        }
+        self.availability.send_replace(Availability::Up);
+    }
+
+    /// Resolves once a connection asked for the sleeping child.
+    pub async fn wake_requested(&self) {
+        self.wake.notified().await
+    }
+
+    /// Wait up to `limit` for the child to be up, asking for it to be woken
+    /// if it's asleep. Returns whether it is.
+    pub async fn wait_up(&self, limit: Duration) -> bool {
+        let mut availability = self.availability.subscribe();
+        if *availability.borrow_and_update() == Availability::Asleep {
+            self.wake.notify_one();
+        }
+        timeout(
+            limit,
+            availability.wait_for(|availability| *availability == Availability::Up),
+        )
+        .await
+        .is_ok_and(|up| up.is_ok())
+    }
+
+    fn opened(&self) {
+        if let Ok(mut traffic) = self.traffic.lock() {
+            traffic.open += 1;
+            traffic.last = Instant::now();
+        }
+    }
+
+    fn closed(&self) {
+        if let Ok(mut traffic) = self.traffic.lock() {
+            traffic.open = traffic.open.saturating_sub(1);
+            traffic.last = Instant::now();
+        }
+    }
+}
+
+/// Counts a connection as open while it lives.
+struct Connection<'a>(&'a Activity);
+
+impl<'a> Connection<'a> {
+    fn open(activity: &'a Activity) -> Self {
+        activity.opened();
+        Self(activity)
+    }
+}
+
+impl Drop for Connection<'_> {
+    fn drop(&mut self) {
+        self.0.closed();
+    }
+}
+
+/// How connections are held while the child isn't up.
+#[derive(Debug, Clone)]
+struct Holding {
+    wake_timeout: Duration,
+    hold_timeout: Duration,
+    /// The `503` response, with `hold = "page"`.
+    page: Option<Arc<[u8]>>,
+}
+
+/// Accept connections on `config.listen` and pass them on to the child's
+/// `port` on this host, holding them while it restarts or sleeps.
+pub async fn serve(config: &ProxyConfig, port: u16, activity: Arc<Activity>) -> io::Result<()> {
+    let addr = match &config.listen {
+        Some(addr) => addr.clone(),
+        None => return Ok(()),
+    };
+    let page = match config.hold {
+        HoldMode::Queue => None,
+        HoldMode::Page => Some(Arc::from(config.page()?)),
+    };
+    let holding = Holding {
+        wake_timeout: Duration::from_secs(config.wake_timeout_seconds),
+        hold_timeout: Duration::from_secs(config.hold_seconds),
+        page,
+    };
+    let listener = privileges::bind(&addr).await?;
+    log!(
+        LogLevel::Info,
+        "Passing connections on {} to the child on port {}",
+        addr,
+        port
+    );
+
+    shutdown::spawn(Stage::Child, async move {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(connection) => connection,
+                Err(err) => {
+                    log!(LogLevel::Warn, "Proxy listener failed to accept: {}", err);
+                    continue;
+                }
+            };
+            let activity = activity.clone();
+            let holding = holding.clone();
+            tokio::spawn(async move {
+                if let Err(err) = relay(stream, port, &activity, &holding).await {
+                    log!(LogLevel::Debug, "Connection from {} failed: {}", peer, err);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+async fn relay(
+    mut inbound: TcpStream,
+    port: u16,
+    activity: &Activity,
+    holding: &Holding,
+) -> io::Result<()> {
+    let _connection = Connection::open(activity);
+    let (limit, waiting_for) = match activity.availability() {
+        Availability::Asleep => (holding.wake_timeout, "wake"),
+        _ => (holding.hold_timeout, "restart"),
+    };
+    let deadline = Instant::now() + limit;
+
+    // A child without a ready check may not listen yet, and one stopped
+    // just now has to come back first
+    let mut outbound = loop {
+        let availability = activity.availability();
+        if let (Availability::Restarting, Some(page)) = (availability, &holding.page) {
+            return answer_with_page(inbound, page).await;
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if !activity.wait_up(remaining).await {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!(
+                    "the child didn't {} within {}s",
+                    waiting_for,
+                    limit.as_secs()
+                ),
+            ));
+        }
+        match TcpStream::connect(("127.0.0.1", port)).await {
+            Ok(outbound) => break outbound,
+            Err(_) if Instant::now() < deadline => sleep(CONNECT_RETRY).await,
+            Err(err) => return Err(err),
+        }
+    };
+    copy_bidirectional(&mut inbound, &mut outbound).await?;
+    Ok(())
+}
+
+async fn answer_with_page(mut inbound: TcpStream, page: &[u8]) -> io::Result<()> {
+    // Read the request first so closing doesn't reset the connection
+    let mut request = [0u8; 8192];
+    _ = timeout(PAGE_READ, inbound.read(&mut request)).await;
+    inbound.write_all(page).await?;
+    inbound.shutdown().await
+}
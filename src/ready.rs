@@ -0,0 +1,194 @@
+//! Readiness gate for a freshly spawned child.
+//!
+//! Without a gate the runner reports `Running` the moment the child was
+//! spawned, even though most services need a while before they accept
+//! connections. A `[app_specific.ready_check]` makes the runner wait until a
+//! TCP port accepts connections, a unix socket appears, or the child prints
+//! a line matching a regex, before the state is flipped and the directory
+//! monitor resumes.
+
+use artisan_middleware::dusa_collection_utils;
+use dusa_collection_utils::core::logger::LogLevel;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use tokio::net::{TcpStream, UnixStream};
+
+use crate::capture::OutputCapture;
+use crate::child_manager;
+use crate::global_child::GLOBAL_HEARTBEAT;
+use crate::log;
+use crate::output::Stream;
+
+/// How often the condition is re-evaluated.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// `[app_specific.ready_check]`
+///
+/// Exactly one of `tcp`, `unix_socket`, `log_line` or `notify` should be
+/// set.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ReadyCheck {
+    /// `host:port` that must accept a connection.
+    #[serde(default)]
+    pub tcp: Option<String>,
+    /// Path of a unix socket that must accept a connection.
+    #[serde(default)]
+    pub unix_socket: Option<String>,
+    /// Regex a line of the child's stdout or stderr must match.
+    #[serde(default)]
+    pub log_line: Option<String>,
+    /// Wait for the child to send `READY=1`, needs `heartbeat` enabled.
+    #[serde(default)]
+    pub notify: bool,
+    #[serde(default = "default_ready_timeout")]
+    pub timeout_seconds: u64,
+}
+
+fn default_ready_timeout() -> u64 {
+    30
+}
+
+impl ReadyCheck {
+    /// Wait for a line matching `pattern`, with the default timeout.
+    pub fn on_log_line(pattern: String) -> Self {
+        Self {
+            tcp: None,
+            unix_socket: None,
+            log_line: Some(pattern),
+            notify: false,
+            timeout_seconds: default_ready_timeout(),
+        }
+    }
+
+    /// Check the definition without running it.
+    pub fn validate(&self) -> Result<(), String> {
+        let configured = [
+            self.tcp.is_some(),
+            self.unix_socket.is_some(),
+            self.log_line.is_some(),
+            self.notify,
+        ]
+        .into_iter()
+        .filter(|set| *set)
+        .count();
+        if configured != 1 {
+            return Err(String::from(
+                "ready_check needs exactly one of tcp, unix_socket, log_line or notify",
+            ));
+        }
+
+        if let Some(pattern) = &self.log_line {
+            Regex::new(pattern)
+                .map_err(|err| format!("ready_check log_line is not a valid regex: {}", err))?;
+        }
+        Ok(())
+    }
+
+    /// Wait until the child is ready, returning how long it took.
+    ///
+    /// Fails once `timeout_seconds` elapsed or the child exited while
+    /// waiting. `log_line` is looked for in what `output` reads, which
+    /// should follow the child's output from before it was spawned.
+    pub async fn wait(&self, output: &mut OutputCapture) -> Result<Duration, String> {
+        self.validate()?;
+        let pattern = match &self.log_line {
+            Some(pattern) => Regex::new(pattern).ok(),
+            None => None,
+        };
+
+        let started = Instant::now();
+        let limit = Duration::from_secs(self.timeout_seconds);
+        loop {
+            if self.is_ready(pattern.as_ref(), output).await? {
+                return Ok(started.elapsed());
+            }
+
+            if started.elapsed() >= limit {
+                return Err(format!(
+                    "Child wasn't ready after {}s ({})",
+                    self.timeout_seconds,
+                    self.describe()
+                ));
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    async fn is_ready(
+        &self,
+        pattern: Option<&Regex>,
+        output: &mut OutputCapture,
+    ) -> Result<bool, String> {
+        match child_manager::running().await {
+            Some(true) => (),
+            Some(false) => return Err(String::from("Child exited before it became ready")),
+            None => return Err(String::from("No child to wait for")),
+        }
+
+        if let Some(addr) = &self.tcp {
+            return Ok(TcpStream::connect(addr).await.is_ok());
+        }
+
+        if let Some(path) = &self.unix_socket {
+            return Ok(UnixStream::connect(path).await.is_ok());
+        }
+
+        if self.notify {
+            return Ok(GLOBAL_HEARTBEAT
+                .get()
+                .is_some_and(|heartbeat| heartbeat.is_ready()));
+        }
+
+        if let Some(pattern) = pattern {
+            for stream in [Stream::Stdout, Stream::Stderr] {
+                let lines = output.read_lines(stream);
+                if lines.iter().any(|line| pattern.is_match(line)) {
+                    return Ok(true);
+                }
+            }
+        }
+
+        Ok(false)
+    }
+
+    fn describe(&self) -> String {
+        if let Some(addr) = &self.tcp {
+            return format!("tcp {}", addr);
+        }
+        if let Some(path) = &self.unix_socket {
+            return format!("unix socket {}", path);
+        }
+        if self.notify {
+            return String::from("READY=1 notification");
+        }
+        match &self.log_line {
+            Some(pattern) => format!("log line /{}/", pattern),
+            None => String::from("nothing configured"),
+        }
+    }
+}
+
+/// Wait on `check` if one is configured.
+///
+/// Returns `Err` with a message ready for the error log when the child
+/// didn't become ready in time.
+pub async fn await_ready(
+    check: Option<&ReadyCheck>,
+    output: &mut OutputCapture,
+) -> Result<(), String> {
+    let check = match check {
+        Some(check) => check,
+        None => return Ok(()),
+    };
+
+    log!(LogLevel::Debug, "Waiting for child to become ready");
+    let elapsed = check.wait(output).await?;
+    log!(
+        LogLevel::Info,
+        "Child ready after {}ms ({})",
+        elapsed.as_millis(),
+        check.describe()
+    );
+    Ok(())
+}
@@ -0,0 +1,254 @@
+//! Applying changes to `Config.toml` while the runner is up.
+//!
+//! The runner looks at the modification time of `Config.toml` on every turn
+//! of its loop. Once it changed, the file is loaded and validated again and
+//! compared with the settings in use, option by option. What changed is
+//! applied in the least disruptive way the option allows, see [`Effect`]:
+//!
+//! - change counts, ignore lists, probes, log rules, notification targets
+//!   and the like take effect right away, the child keeps running,
+//! - commands, paths and what the child runs with are deployed like a file
+//!   change would be,
+//! - listeners, privileges and whatever else is set up once at start up
+//!   keep their old value, with a warning, until the runner restarts.
+//!
+//! A config that doesn't load or validate is reported and ignored, the
+//! runner carries on with what it has. `SIGHUP` (`ais_runner restart`)
+//! picks up the file the same way before it respawns the child.
+//!
+//! Settings handed to [`crate::runner::RunnerBuilder::settings`] don't come
+//! from `Config.toml`, so it's neither watched nor read on `SIGHUP` then.
+
+use serde_json::Value;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::config::AppSpecificConfig;
+
+/// What it takes to apply a changed option.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Effect {
+    /// Applied in place.
+    Live,
+    /// The child is redeployed with it.
+    Child,
+    /// Only read at start up, applied by the next start of the runner.
+    Runner,
+}
+
+/// Options applied without touching the child.
+const LIVE: &[&str] = &[
+    "interval_seconds",
+    "changes_needed",
+    "ignored_subdirs",
+    "shutdown_timeout_seconds",
+    "startup_probe",
+    "liveness_probe",
+    "timestamp_source",
+    "timestamp_formats",
+    "install_timeout_seconds",
+    "build_timeout_seconds",
+    "build_timeout_action",
+    "build_timeout_retries",
+    "restart_strategy",
+    "ready_check",
+    "crash_loop",
+    "restart_on_exit_codes",
+    "no_restart_on",
+    "notifications",
+    "app_status",
+    "log_rules",
+    "cpu_limit",
+    // Its limits, like `ais_runner limits` does
+    "cgroup",
+    "orphans",
+    "kill_mode",
+    "max_parallel_steps",
+    "build_inputs",
+    "build_outputs",
+    "diagnostics_dir",
+    "diagnostics_lines",
+    "shutdown",
+    "max_log_lines",
+    "max_log_bytes",
+    "change_batch_ms",
+    "polling",
+];
+
+/// Options only read at start up.
+const RUNNER: &[&str] = &[
+    "secret_server_addr",
+    "env_file_location",
+    "log_format",
+    "journal",
+    "required_secrets",
+    "only_required_secrets",
+    "reservation",
+    "secret_rotation",
+    "static_server",
+    "secret_tls",
+    "secret_retry",
+    "acme",
+    "restart_schedule",
+    "secret_reload",
+    "webhook_addr",
+    "webhook_secret",
+    "heartbeat",
+    "state_sync",
+    "port",
+    "drop_privileges",
+    "state_writes",
+    "metrics_history",
+    "log_shipping",
+    "otel_endpoint",
+    "stdin_pipe",
+    "proxy",
+    "listen_fds",
+    "events",
+    "status_server",
+    "state_backend",
+    "monitoring_enabled",
+    "mode",
+    "cadence",
+];
+
+/// Options the directory monitors are started with.
+pub const MONITORS: &[&str] = &[
+    "interval_seconds",
+    "ignored_subdirs",
+    "monitor_path",
+    "monitor_paths",
+    "change_batch_ms",
+    "polling",
+];
+
+/// What applying a change to the option `key` takes. Options not known to
+/// be safe to apply otherwise redeploy the child.
+pub fn effect(key: &str) -> Effect {
+    match (LIVE.contains(&key), RUNNER.contains(&key)) {
+        (true, _) => Effect::Live,
+        (_, true) => Effect::Runner,
+        _ => Effect::Child,
+    }
+}
+
+fn to_table(settings: &AppSpecificConfig) -> serde_json::Map<String, Value> {
+    match serde_json::to_value(settings) {
+        Ok(Value::Object(table)) => table,
+        _ => serde_json::Map::new(),
+    }
+}
+
+/// The changed options between `old` and `new`, grouped by what applying
+/// them takes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Plan {
+    pub live: Vec<String>,
+    pub child: Vec<String>,
+    pub runner: Vec<String>,
+}
+
+impl Plan {
+    pub fn new(old: &AppSpecificConfig, new: &AppSpecificConfig) -> Self {
+        let old = to_table(old);
+        let mut plan = Self::default();
+        for (key, value) in to_table(new) {
+            if old.get(&key) == Some(&value) {
+                continue;
+            }
+            match effect(&key) {
+                Effect::Live => plan.live.push(key),
+                Effect::Child => plan.child.push(key),
+                Effect::Runner => plan.runner.push(key),
+            }
+        }
+        plan
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.live.is_empty() && self.child.is_empty() && self.runner.is_empty()
+    }
+
+    /// Whether any of `keys` changed.
+    pub fn touches(&self, keys: &[&str]) -> bool {
+        self.live
+            .iter()
+            .chain(&self.child)
+            .chain(&self.runner)
+            .any(|key| keys.contains(&key.as_str()))
+    }
+}
+
+/// `new` with the options a restart of the runner would apply taken from
+/// `old`, as those are still in effect.
+pub fn keep_runner_options(
+    old: &AppSpecificConfig,
+    new: &AppSpecificConfig,
+    plan: &Plan,
+) -> Result<AppSpecificConfig, String> {
+    let old = to_table(old);
+    let mut merged = to_table(new);
+    for key in &plan.runner {
+        if let Some(value) = old.get(key) {
+            merged.insert(key.clone(), value.clone());
+        }
+    }
+    serde_json::from_value(Value::Object(merged))
+        .map_err(|err| format!("Failed to merge the reloaded config: {}", err))
+}
+
+/// Notices when `Config.toml` was written to.
+#[derive(Debug)]
+pub struct ConfigFile {
+    /// `None` when the settings were handed to the runner instead.
+    path: Option<PathBuf>,
+    modified: Option<SystemTime>,
+}
+
+impl ConfigFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let modified = modified(&path);
+        Self {
+            path: Some(path),
+            modified,
+        }
+    }
+
+    /// For settings handed to the runner, which no file on disk can change.
+    pub fn preset() -> Self {
+        Self {
+            path: None,
+            modified: None,
+        }
+    }
+
+    /// Whether reloading reads the settings from the file again.
+    pub fn reloadable(&self) -> bool {
+        self.path.is_some()
+    }
+
+    /// Whether the file changed since the last call.
+    pub fn changed(&mut self) -> bool {
+        let Some(path) = &self.path else {
+            return false;
+        };
+        let modified = modified(path);
+        match modified == self.modified {
+            true => false,
+            false => {
+                self.modified = modified;
+                true
+            }
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
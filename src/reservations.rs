@@ -0,0 +1,254 @@
+//! Host level registry of resource reservations.
+//!
+//! Every runner on a host declares the memory, CPU and ports its child needs
+//! by writing `<registry_dir>/<app_name>.json`. Before spawning, the runner
+//! adds up the reservations of all live runners and warns about (or refuses
+//! to start on) an oversubscribed host, instead of letting the kernel OOM
+//! killer sort it out later. One file per runner means no locking is needed;
+//! entries of runners that died without cleaning up are ignored by checking
+//! their pid.
+
+use nix::{sys::signal, unistd::Pid};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::config::AppSpecificConfig;
+
+/// What to do when the host would be oversubscribed.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OversubscribeAction {
+    #[default]
+    Warn,
+    Refuse,
+}
+
+/// `[app_specific.reservation]`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ReservationConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Defaults to the cgroup memory limit, then `max_ram_usage`.
+    #[serde(default)]
+    pub memory_mb: Option<u64>,
+    /// Percent of one core, defaults to the cgroup CPU limit.
+    #[serde(default)]
+    pub cpu_percent: Option<u32>,
+    #[serde(default)]
+    pub ports: Vec<u16>,
+    #[serde(default)]
+    pub on_oversubscribe: OversubscribeAction,
+    #[serde(default = "default_registry_dir")]
+    pub registry_dir: String,
+}
+
+impl Default for ReservationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            memory_mb: None,
+            cpu_percent: None,
+            ports: Vec::new(),
+            on_oversubscribe: OversubscribeAction::default(),
+            registry_dir: default_registry_dir(),
+        }
+    }
+}
+
+pub fn default_registry_dir() -> String {
+    String::from("/run/ais_runner/reservations")
+}
+
+/// A single runner's declaration.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Reservation {
+    pub app_name: String,
+    /// Pid of the runner, used to detect stale entries.
+    pub pid: u32,
+    pub memory_mb: u64,
+    pub cpu_percent: u32,
+    pub ports: Vec<u16>,
+}
+
+impl Reservation {
+    /// This runner's reservation, filling unset values from the cgroup
+    /// limits and `max_ram_usage`.
+    pub fn for_runner(app_name: &str, settings: &AppSpecificConfig, max_ram_usage: u64) -> Self {
+        let config = &settings.reservation;
+        Self {
+            app_name: app_name.to_owned(),
+            pid: std::process::id(),
+            memory_mb: config
+                .memory_mb
+                .or(settings.cgroup.memory_max_mb)
+                .unwrap_or(max_ram_usage),
+            cpu_percent: config
+                .cpu_percent
+                .or(settings.cgroup.cpu_max_percent)
+                .unwrap_or(0),
+            ports: config.ports.clone(),
+        }
+    }
+}
+
+/// Total resources of the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HostResources {
+    pub memory_mb: u64,
+    /// `100` per available core.
+    pub cpu_percent: u32,
+}
+
+impl HostResources {
+    pub fn detect() -> Self {
+        let memory_mb = fs::read_to_string("/proc/meminfo")
+            .ok()
+            .and_then(|meminfo| {
+                meminfo
+                    .lines()
+                    .find_map(|line| line.strip_prefix("MemTotal:"))
+                    .and_then(|value| value.trim().trim_end_matches("kB").trim().parse().ok())
+            })
+            .map(|kb: u64| kb / 1024)
+            .unwrap_or(0);
+        let cores = std::thread::available_parallelism()
+            .map(|cores| cores.get() as u32)
+            .unwrap_or(1);
+
+        Self {
+            memory_mb,
+            cpu_percent: cores * 100,
+        }
+    }
+}
+
+/// Problems found when adding `own` to the live `others`.
+pub fn check(own: &Reservation, others: &[Reservation], host: HostResources) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let memory: u64 = others.iter().map(|other| other.memory_mb).sum::<u64>() + own.memory_mb;
+    if host.memory_mb > 0 && memory > host.memory_mb {
+        problems.push(format!(
+            "Memory reservations total {}MB but the host only has {}MB",
+            memory, host.memory_mb
+        ));
+    }
+
+    let cpu: u32 = others.iter().map(|other| other.cpu_percent).sum::<u32>() + own.cpu_percent;
+    if cpu > host.cpu_percent {
+        problems.push(format!(
+            "CPU reservations total {}% but the host only has {}%",
+            cpu, host.cpu_percent
+        ));
+    }
+
+    for port in &own.ports {
+        if let Some(other) = others.iter().find(|other| other.ports.contains(port)) {
+            problems.push(format!(
+                "Port {} is already reserved by {}",
+                port, other.app_name
+            ));
+        }
+    }
+
+    problems
+}
+
+/// Check this runner against the registry and declare its reservation.
+///
+/// Returns the problems found, which are only warnings unless
+/// `on_oversubscribe = "refuse"`, in which case nothing is declared and
+/// `Err` carries them joined into one message.
+pub fn reserve(
+    reservation: &Reservation,
+    config: &ReservationConfig,
+) -> Result<Vec<String>, String> {
+    let registry = Registry::new(&config.registry_dir);
+    let others = registry
+        .others(&reservation.app_name)
+        .map_err(|err| format!("Failed to read reservations: {}", err))?;
+
+    let problems = check(reservation, &others, HostResources::detect());
+    if !problems.is_empty() && config.on_oversubscribe == OversubscribeAction::Refuse {
+        return Err(format!(
+            "Refusing to start on an oversubscribed host: {}",
+            problems.join(", ")
+        ));
+    }
+
+    registry
+        .declare(reservation)
+        .map_err(|err| format!("Failed to declare reservation: {}", err))?;
+    Ok(problems)
+}
+
+/// The registry directory.
+pub struct Registry {
+    dir: PathBuf,
+}
+
+impl Registry {
+    pub fn new(dir: &str) -> Self {
+        Self {
+            dir: PathBuf::from(dir),
+        }
+    }
+
+    fn entry(&self, app_name: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", app_name))
+    }
+
+    /// Reservations of every other runner that is still alive.
+    pub fn others(&self, app_name: &str) -> io::Result<Vec<Reservation>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+
+        let mut reservations = Vec::new();
+        for entry in entries.flatten() {
+            let reservation: Reservation = match read_entry(&entry.path()) {
+                Some(reservation) => reservation,
+                None => continue,
+            };
+            if reservation.app_name != app_name && pid_alive(reservation.pid) {
+                reservations.push(reservation);
+            }
+        }
+        Ok(reservations)
+    }
+
+    /// Declare (or update) the reservation of this runner.
+    pub fn declare(&self, reservation: &Reservation) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.entry(&reservation.app_name);
+        let mut temp = path.clone().into_os_string();
+        temp.push(".tmp");
+
+        fs::write(&temp, serde_json::to_vec_pretty(reservation)?)?;
+        fs::rename(&temp, &path)
+    }
+
+    /// Remove the reservation of `app_name`.
+    pub fn release(&self, app_name: &str) -> io::Result<()> {
+        match fs::remove_file(self.entry(app_name)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn read_entry(path: &Path) -> Option<Reservation> {
+    if path.extension().is_none_or(|extension| extension != "json") {
+        return None;
+    }
+    serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+}
+
+fn pid_alive(pid: u32) -> bool {
+    signal::kill(Pid::from_raw(pid as i32), None).is_ok()
+}
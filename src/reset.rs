@@ -0,0 +1,97 @@
+//! Clearing parts of the persisted state (`reset`).
+//!
+//! Operators used to delete the whole state file to get rid of a stale
+//! error log, losing everything else with it. `ais_runner reset --errors`
+//! clears just the error log, `--output` the captured stdout/stderr and
+//! `--counters` the event counter. A live runner holds the state in memory
+//! and would write the old parts back, so the CLI leaves the request in the
+//! `<state file>.runner` sidecar and the runner applies it on its next
+//! periodic check. The state of a runner that isn't up is cleared directly.
+
+use artisan_middleware::{
+    dusa_collection_utils::core::types::pathtype::PathType, state_persistence::AppState,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::runner_state::{RunnerState, update_runner_state};
+
+/// Which parts of the state to clear.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ResetScope {
+    #[serde(default)]
+    pub errors: bool,
+    #[serde(default)]
+    pub output: bool,
+    #[serde(default)]
+    pub counters: bool,
+}
+
+impl ResetScope {
+    pub fn all() -> Self {
+        Self {
+            errors: true,
+            output: true,
+            counters: true,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !(self.errors || self.output || self.counters)
+    }
+
+    /// Both scopes together, requests pile up until the runner takes them.
+    pub fn merge(self, other: Self) -> Self {
+        Self {
+            errors: self.errors || other.errors,
+            output: self.output || other.output,
+            counters: self.counters || other.counters,
+        }
+    }
+
+    /// Clear the parts of `state` in scope.
+    pub fn apply(&self, state: &mut AppState) {
+        if self.errors {
+            state.error_log.clear();
+        }
+        if self.output {
+            state.stdout.clear();
+            state.stderr.clear();
+        }
+        if self.counters {
+            state.event_counter = 0;
+        }
+    }
+
+    /// The parts in scope for messages, e.g. `errors and output`.
+    pub fn describe(&self) -> String {
+        let parts: Vec<&str> = [
+            (self.errors, "errors"),
+            (self.output, "output"),
+            (self.counters, "counters"),
+        ]
+        .into_iter()
+        .filter(|(selected, _)| *selected)
+        .map(|(_, part)| part)
+        .collect();
+        match parts.split_last() {
+            None => String::from("nothing"),
+            Some((last, [])) => last.to_string(),
+            Some((last, rest)) => format!("{} and {}", rest.join(", "), last),
+        }
+    }
+}
+
+/// Leave `scope` for the runner of the state at `state_path`.
+pub fn request(state_path: &PathType, scope: ResetScope) -> std::io::Result<()> {
+    let mut runner_state = RunnerState::load(state_path);
+    let pending = runner_state.pending_reset.unwrap_or_default();
+    runner_state.pending_reset = Some(pending.merge(scope));
+    runner_state.save(state_path)
+}
+
+/// Take the reset left for the runner, if there is one.
+pub fn take_pending(state_path: &PathType) -> Option<ResetScope> {
+    let scope = RunnerState::load(state_path).pending_reset?;
+    update_runner_state(state_path, |runner_state| runner_state.pending_reset = None);
+    Some(scope)
+}
@@ -0,0 +1,708 @@
+//! Restarting the child.
+//!
+//! A file change, the schedule, a webhook, `SIGHUP`, a child that died and
+//! the first start all come down to the same sequence: build, stop the
+//! current child, spawn the next one and wait for it to become ready. The
+//! [`Restarter`] runs that sequence for all of them, honouring
+//! `restart_strategy`, keeping the directory monitors paused while it runs
+//! and recording every restart with its [`RestartKind`] in the runner state.
+//! Once the restart is over its record gets the build duration, how the
+//! named build steps went, whether the new child became ready and the
+//! timeline of its stages.
+
+use artisan_middleware::{
+    dusa_collection_utils,
+    state_persistence::{AppState, log_error},
+    timestamp::current_timestamp,
+};
+use dusa_collection_utils::core::{
+    errors::{ErrorArrayItem, Errors},
+    logger::LogLevel,
+    types::pathtype::PathType,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt, fs,
+    sync::atomic::Ordering,
+    time::{Duration, SystemTime},
+};
+use tokio::time::{Instant, sleep};
+
+use crate::{
+    artifacts,
+    build_cache::BuildCache,
+    capture::OutputCapture,
+    child::{
+        ChildExit, ChildLaunch, RestartStrategy, kept_after_timeout, kill_child, launch_child,
+        run_build_steps, run_install_process, run_one_shot_process,
+    },
+    child_manager,
+    config::AppSpecificConfig,
+    deploy_trace::{DeployTrace, Stage},
+    events::{self, Event},
+    global_child::{
+        GLOBAL_ADOPTED, GLOBAL_CHILD_PID, GLOBAL_LAUNCH, GLOBAL_PROXY, pause_monitors,
+        resume_monitors,
+    },
+    lifecycle::{Lifecycle, Phase},
+    log,
+    log_rules::LogRules,
+    notifications::{EventKind, notify, notify_with},
+    orphans::{self, AdoptedChild},
+    otel::{self, RestartTrace},
+    probes::ProbeTracker,
+    ready::await_ready,
+    runner_state::{finish_restart, record_restart, record_steps, update_runner_state},
+    secrets::template::render_env_file,
+    state,
+    static_server::publish,
+    uptime,
+};
+
+/// What a restart was for, kept in the restart history.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RestartKind {
+    /// The runner started, not recorded.
+    Startup,
+    /// Enough files changed under the watched directories.
+    Changes,
+    /// `restart_schedule` was due.
+    Schedule,
+    Webhook,
+    /// `SIGHUP` or `ais_runner restart`.
+    Reload,
+    /// `restore-last-known-good`.
+    Restore,
+    /// The child exited on its own.
+    Exited,
+    /// Failing probes or a missed heartbeat.
+    Unhealthy,
+    /// The child printed a line matching a `restart` log rule.
+    LogLine,
+    /// Secrets were rotated with `secret_rotation = "restart_child"`.
+    Secrets,
+}
+
+impl RestartKind {
+    /// Restarts that deploy new code, these follow `restart_strategy`.
+    pub fn is_deploy(self) -> bool {
+        matches!(
+            self,
+            RestartKind::Changes | RestartKind::Schedule | RestartKind::Webhook
+        )
+    }
+
+    /// Name as serialized, e.g. `log_line`.
+    pub fn name(self) -> &'static str {
+        match self {
+            RestartKind::Startup => "startup",
+            RestartKind::Changes => "changes",
+            RestartKind::Schedule => "schedule",
+            RestartKind::Webhook => "webhook",
+            RestartKind::Reload => "reload",
+            RestartKind::Restore => "restore",
+            RestartKind::Exited => "exited",
+            RestartKind::Unhealthy => "unhealthy",
+            RestartKind::LogLine => "log_line",
+            RestartKind::Secrets => "secrets",
+        }
+    }
+}
+
+/// Why the child is restarted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RestartReason {
+    pub kind: RestartKind,
+    /// Shown in logs and the history, e.g. `5 file changes`.
+    pub detail: String,
+    /// How the child being replaced ended, if it ended on its own.
+    pub exit: Option<ChildExit>,
+}
+
+impl RestartReason {
+    pub fn new(kind: RestartKind, detail: impl Into<String>) -> Self {
+        Self {
+            kind,
+            detail: detail.into(),
+            exit: None,
+        }
+    }
+
+    pub fn with_exit(mut self, exit: ChildExit) -> Self {
+        self.exit = Some(exit);
+        self
+    }
+}
+
+impl From<RestartKind> for RestartReason {
+    fn from(kind: RestartKind) -> Self {
+        let detail = match kind {
+            RestartKind::Startup => "start up",
+            RestartKind::Changes => "file changes",
+            RestartKind::Schedule => "scheduled restart",
+            RestartKind::Webhook => "webhook",
+            RestartKind::Reload => "reload requested",
+            RestartKind::Restore => "restore last known good",
+            RestartKind::Exited => "child exited",
+            RestartKind::Unhealthy => "child unhealthy",
+            RestartKind::LogLine => "log rule matched",
+            RestartKind::Secrets => "secrets rotated",
+        };
+        Self::new(kind, detail)
+    }
+}
+
+impl fmt::Display for RestartReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.detail)
+    }
+}
+
+/// How a restart ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestartOutcome {
+    /// A new child is running.
+    Restarted,
+    /// The build failed before the current child was stopped, it keeps
+    /// serving.
+    KeptCurrent,
+    /// The build failed and nothing is running, the runner should exit.
+    Failed,
+}
+
+/// Runs restarts and keeps what belongs to the current child.
+pub struct Restarter {
+    settings: AppSpecificConfig,
+    app_name: String,
+    state_path: PathType,
+    pub lifecycle: Lifecycle,
+    pub probes: ProbeTracker,
+    /// The child's output, read on across respawns and adoption.
+    pub capture: OutputCapture,
+    pub log_rules: LogRules,
+    /// Keeps the monitors paused after a restart.
+    pub maintenance: bool,
+    /// Timestamp of the restart record waiting for its outcome.
+    recorded: Option<u64>,
+    /// How long the last build step took.
+    build_time: Option<Duration>,
+    /// First of the file changes the next deploy is for, starts its
+    /// timeline.
+    pub triggered: Option<std::time::Instant>,
+    /// Stage timings of the restart running.
+    trace: Option<DeployTrace>,
+    /// Why the restart running was started, for its exported trace.
+    reason: Option<RestartReason>,
+    /// A launch that couldn't be spawned, tried again on the next health
+    /// check.
+    pending: Option<ChildLaunch>,
+}
+
+impl Restarter {
+    pub fn new(settings: &AppSpecificConfig, app_name: &str, state_path: &PathType) -> Self {
+        Self {
+            settings: settings.clone(),
+            app_name: app_name.to_owned(),
+            state_path: state_path.clone(),
+            lifecycle: Lifecycle::recording(uptime::path(state_path)),
+            probes: ProbeTracker::new(
+                settings.startup_probe.clone(),
+                settings.liveness_probe.clone(),
+            ),
+            capture: OutputCapture::follow(state_path),
+            log_rules: LogRules::new(&settings.log_rules),
+            maintenance: false,
+            recorded: None,
+            build_time: None,
+            triggered: None,
+            trace: None,
+            reason: None,
+            pending: None,
+        }
+    }
+
+    /// Apply reloaded `settings` from the next build or spawn on. Probes and
+    /// log rules start over when they changed.
+    pub fn reconfigure(&mut self, settings: &AppSpecificConfig) {
+        let probes_changed = self.settings.startup_probe != settings.startup_probe
+            || self.settings.liveness_probe != settings.liveness_probe;
+        if probes_changed {
+            self.probes = ProbeTracker::new(
+                settings.startup_probe.clone(),
+                settings.liveness_probe.clone(),
+            );
+        }
+        if self.settings.log_rules != settings.log_rules {
+            self.log_rules = LogRules::new(&settings.log_rules);
+        }
+        self.settings = settings.clone();
+    }
+
+    /// Replace the current child for `reason`, with `restore` instead of
+    /// a fresh build when set.
+    pub async fn restart_child(
+        &mut self,
+        state: &mut AppState,
+        reason: RestartReason,
+        restore: Option<ChildLaunch>,
+    ) -> RestartOutcome {
+        pause_monitors().await;
+        log!(LogLevel::Info, "Handling {}", reason);
+        events::record(Event::Restart {
+            reason: reason.to_string(),
+        });
+        state.event_counter += 1;
+        self.note_restart(&reason);
+
+        // With build-first the old child keeps serving until we know the build is good
+        let build_first = reason.kind.is_deploy()
+            && self.settings.restart_strategy == RestartStrategy::BuildFirst;
+        // A build that times out under build_timeout_action = "keep" has to
+        // find the current child still running, kill-first or not
+        let keep_on_timeout =
+            reason.kind.is_deploy() && restore.is_none() && self.settings.keeps_child_on_timeout();
+        let build_early = (build_first || keep_on_timeout) && self.settings.has_build_step();
+        if build_early {
+            log!(
+                LogLevel::Info,
+                "Running build step, current child keeps serving"
+            );
+            self.lifecycle.transition(Phase::Building, state);
+            state::save(state, &self.state_path, None).await;
+            if let Err(err) = self.build(state).await {
+                if build_first || kept_after_timeout(&err, &self.settings) {
+                    log!(
+                        LogLevel::Error,
+                        "Build failed, keeping the current child: {}",
+                        err
+                    );
+                    notify(
+                        EventKind::BuildFailed,
+                        format!("Build failed, kept the current child: {}", err),
+                    );
+                    self.lifecycle.transition(Phase::Degraded, state);
+                    log_error(state, err, &self.state_path).await;
+                    self.finish_restart(false);
+                    self.resume().await;
+                    return RestartOutcome::KeptCurrent;
+                }
+                // Any other failure goes the way of kill-first
+                log!(LogLevel::Error, "One-shot process failed: {}", err);
+                notify(EventKind::BuildFailed, format!("Build failed: {}", err));
+                log_error(state, err, &self.state_path).await;
+                self.stop_current(state).await;
+                self.finish_restart(false);
+                return RestartOutcome::Failed;
+            }
+        }
+
+        self.stop_current(state).await;
+
+        if !build_early && restore.is_none() && !self.prepare(state, false).await {
+            self.finish_restart(false);
+            return RestartOutcome::Failed;
+        }
+        if build_early && restore.is_none() {
+            publish_static(&self.settings, &self.app_name, state, &self.state_path).await;
+        }
+
+        let ready = self.spawn(state, restore).await;
+        self.finish_restart(ready);
+        self.resume().await;
+        RestartOutcome::Restarted
+    }
+
+    /// Run the install step when `install` is set, then the build step,
+    /// and publish the static output.
+    ///
+    /// Returns whether the build succeeded, a failed one is logged and
+    /// reported.
+    pub async fn prepare(&mut self, state: &mut AppState, install: bool) -> bool {
+        if install && self.settings.install_command.is_some() {
+            log!(LogLevel::Trace, "Running install step");
+            self.stage(Stage::Install);
+            self.lifecycle.transition(Phase::Installing, state);
+            state::save(state, &self.state_path, None).await;
+            events::record(Event::BuildStarted {
+                step: String::from("install"),
+            });
+            let started = Instant::now();
+            let result = run_install_process(&self.settings, state, &self.state_path).await;
+            events::record(Event::BuildFinished {
+                step: String::from("install"),
+                success: result.is_ok(),
+                duration_ms: started.elapsed().as_millis() as u64,
+                error: result.as_ref().err().map(|err| err.err_mesg.to_string()),
+            });
+            if let Err(err) = result {
+                log!(LogLevel::Error, "{}", err)
+            }
+        }
+
+        if self.settings.has_build_step() {
+            log!(LogLevel::Trace, "Running build step");
+            self.lifecycle.transition(Phase::Building, state);
+            state::save(state, &self.state_path, None).await;
+            if let Err(err) = self.build(state).await {
+                log!(LogLevel::Error, "One-shot process failed: {}", err);
+                notify(EventKind::BuildFailed, format!("Build failed: {}", err));
+                log_error(state, err, &self.state_path).await;
+                return false;
+            }
+        }
+
+        publish_static(&self.settings, &self.app_name, state, &self.state_path).await;
+        true
+    }
+
+    /// Spawn the next child, `restore` or a fresh one from the settings,
+    /// and wait for it to become ready, returning whether it did.
+    pub async fn spawn(&mut self, state: &mut AppState, restore: Option<ChildLaunch>) -> bool {
+        self.stage(Stage::Start);
+        // Rendered for every child so it starts with the current secrets, a
+        // failure leaves the previous env file in place
+        if let Err(err) = render_env_file(&self.settings).await {
+            log!(LogLevel::Error, "Keeping the previous env file: {}", err);
+            log_error(state, err, &self.state_path).await;
+        }
+
+        log!(LogLevel::Trace, "Spawning child process...");
+        // From before the spawn, so a ready line can't slip by
+        let output = OutputCapture::follow(&self.state_path);
+        let launch = restore.unwrap_or_else(|| ChildLaunch::resolve(&self.settings));
+        let retry = &self.settings.secret_retry;
+        let mut child = match launch_child(state, &self.state_path, launch.clone(), retry).await {
+            Ok(child) => child,
+            Err(err) => {
+                // A secret server outage must not take the runner down
+                log!(
+                    LogLevel::Error,
+                    "Failed to spawn the child, trying again on the next health check: {}",
+                    err
+                );
+                self.lifecycle.transition(Phase::Starting, state);
+                self.lifecycle.transition(Phase::Degraded, state);
+                log_error(state, err, &self.state_path).await;
+                self.pending = Some(launch);
+                return false;
+            }
+        };
+        self.pending = None;
+        child.monitor_usage().await;
+        child_manager::replace(child).await;
+        self.probes.reset();
+        self.log_rules.reset();
+
+        log!(LogLevel::Info, "New child process spawned");
+        state.data = String::from("New child process spawned");
+        let ready = self.mark_ready(state, output).await;
+        // Held connections go to the new child once it's ready
+        if let Some(proxy) = GLOBAL_PROXY.get() {
+            proxy.up(Instant::now().into_std());
+        }
+        log!(LogLevel::Debug, "Application status: {}", state.status);
+        state::save(state, &self.state_path, None).await;
+        ready
+    }
+
+    /// Whether a child failed to spawn and is waiting to be tried again.
+    pub fn spawn_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Try spawning the child that failed to spawn again, returning whether
+    /// it's ready.
+    pub async fn retry_spawn(&mut self, state: &mut AppState) -> bool {
+        let launch = self.pending.take();
+        self.spawn(state, launch).await
+    }
+
+    /// Supervise `adopted`, the child a previous run left running, in place
+    /// of spawning one, returning whether it's ready.
+    pub async fn adopt(&mut self, state: &mut AppState, adopted: AdoptedChild) -> bool {
+        let pid = adopted.pid;
+        GLOBAL_CHILD_PID.store(pid, Ordering::Relaxed);
+        events::record(Event::ChildAdopted { pid });
+        // The sweep of the previous run's files took its pid file along
+        let pid_file = artifacts::pid_file(&state.config.app_name.to_string());
+        artifacts::track(&pid_file);
+        if let Err(err) = fs::write(&pid_file, pid.to_string()) {
+            log!(
+                LogLevel::Warn,
+                "Failed to write {}: {}",
+                pid_file.display(),
+                err
+            );
+        }
+        *GLOBAL_ADOPTED.lock().await = Some(adopted);
+        self.probes.reset();
+        self.log_rules.reset();
+
+        log!(LogLevel::Info, "Adopted child {} of a previous run", pid);
+        state.data = format!("Adopted child {} of a previous run", pid);
+        let output = OutputCapture::follow(&self.state_path);
+        let ready = self.mark_ready(state, output).await;
+        if let Some(proxy) = GLOBAL_PROXY.get() {
+            proxy.up(Instant::now().into_std());
+        }
+        state::save(state, &self.state_path, None).await;
+        ready
+    }
+
+    /// Stop the child after it went `quiet` without traffic, the next
+    /// connection has it spawned again.
+    pub async fn sleep(&mut self, state: &mut AppState, quiet: Duration) {
+        self.stop_current(state).await;
+        if let Some(proxy) = GLOBAL_PROXY.get() {
+            proxy.sleep();
+        }
+        let message = format!(
+            "No traffic for {} minutes, stopped the child until the next connection",
+            quiet.as_secs() / 60
+        );
+        log!(LogLevel::Info, "{}", message);
+        self.lifecycle.transition(Phase::Idle, state);
+        state.data = message;
+        state::save(state, &self.state_path, None).await;
+    }
+
+    /// Add a restart to the history, surfacing the note an operator left
+    /// for it. Startups aren't recorded.
+    pub fn note_restart(&mut self, reason: &RestartReason) {
+        self.build_time = None;
+        let triggered = self.triggered.take();
+        if reason.kind == RestartKind::Startup {
+            return;
+        }
+        self.trace = Some(DeployTrace::new(triggered, Instant::now().into_std()));
+        self.reason = Some(reason.clone());
+
+        let timestamp = current_timestamp();
+        match record_restart(&self.state_path, timestamp, reason) {
+            Ok(record) => {
+                self.recorded = Some(timestamp);
+                let message = match &record.note {
+                    Some(note) => format!("Restarting for {}, note: {}", record.reason, note),
+                    None => format!("Restarting for {}", record.reason),
+                };
+                log!(LogLevel::Info, "{}", message);
+                notify_with(
+                    EventKind::Restart,
+                    message,
+                    serde_json::to_value(&record).ok(),
+                );
+            }
+            Err(err) => log!(LogLevel::Warn, "Failed to record restart: {}", err),
+        }
+    }
+
+    /// Complete the record of the last noted restart with the build time
+    /// and whether the new child became ready.
+    pub fn finish_restart(&mut self, success: bool) {
+        let mut trace = self.trace.take();
+        let timestamp = match self.recorded.take() {
+            Some(timestamp) => timestamp,
+            None => return,
+        };
+        let stages = match trace.as_mut() {
+            Some(trace) => {
+                trace.end(Instant::now().into_std());
+                trace.spans()
+            }
+            None => &[],
+        };
+        if let Some(reason) = self.reason.take() {
+            otel::export(&RestartTrace {
+                reason: &reason,
+                success,
+                ended: SystemTime::now(),
+                stages,
+            });
+        }
+        if let Err(err) = finish_restart(
+            &self.state_path,
+            timestamp,
+            self.build_time,
+            success,
+            stages,
+        ) {
+            log!(
+                LogLevel::Warn,
+                "Failed to record the restart outcome: {}",
+                err
+            );
+        }
+    }
+
+    /// Run the build step, or the named steps, keeping how long it took.
+    async fn build(&mut self, state: &mut AppState) -> Result<(), ErrorArrayItem> {
+        self.stage(Stage::Build);
+        events::record(Event::BuildStarted {
+            step: String::from("build"),
+        });
+        let started = Instant::now();
+        let mut cache = BuildCache::open(&self.state_path);
+        let result = match self.settings.steps.is_empty() {
+            true => {
+                run_one_shot_process(&self.settings, state, &self.state_path, Some(&mut cache))
+                    .await
+            }
+            false => {
+                let mut runs = Vec::new();
+                let result = run_build_steps(
+                    &self.settings,
+                    state,
+                    &self.state_path,
+                    &mut runs,
+                    Some(&mut cache),
+                )
+                .await;
+                let recorded = self
+                    .recorded
+                    .map(|timestamp| record_steps(&self.state_path, timestamp, &runs));
+                if let Some(Err(err)) = recorded {
+                    log!(LogLevel::Warn, "Failed to record the build steps: {}", err);
+                }
+                result
+            }
+        };
+        self.build_time = Some(started.elapsed());
+        events::record(Event::BuildFinished {
+            step: String::from("build"),
+            success: result.is_ok(),
+            duration_ms: started.elapsed().as_millis() as u64,
+            error: result.as_ref().err().map(|err| err.err_mesg.to_string()),
+        });
+        result
+    }
+
+    /// Stop the current child, if there is one and it still runs.
+    async fn stop_current(&mut self, state: &mut AppState) {
+        let adopted = GLOBAL_ADOPTED.lock().await.take();
+        if let Some(adopted) = adopted {
+            self.stage(Stage::Stop);
+            if let Some(proxy) = GLOBAL_PROXY.get() {
+                proxy.restarting();
+            }
+            let grace = Duration::from_secs(self.settings.shutdown_timeout_seconds);
+            match orphans::kill(adopted.pid, grace).await {
+                Ok(()) => log!(LogLevel::Info, "Stopped the adopted child {}", adopted.pid),
+                Err(err) => {
+                    log!(LogLevel::Error, "{}", err);
+                    state
+                        .error_log
+                        .push(ErrorArrayItem::new(Errors::GeneralError, err));
+                }
+            }
+        }
+        // Dropping the handle as well, with kill_on_drop it gets nuked even
+        // if the kill didn't go through
+        let current = child_manager::take().await;
+        if let Some(mut current) = current {
+            self.stage(Stage::Stop);
+            if let Some(proxy) = GLOBAL_PROXY.get() {
+                proxy.restarting();
+            }
+            if current.running().await {
+                match kill_child(&mut current, self.settings.kill_mode).await {
+                    Ok(_) => log!(LogLevel::Info, "Killed the child!"),
+                    Err(err) => {
+                        log!(LogLevel::Error, "Error killing child: {}", err.err_mesg);
+                        state.error_log.push(err);
+                    }
+                }
+            }
+            drop(current);
+            sleep(Duration::from_millis(20)).await;
+        }
+        self.lifecycle.child_stopped();
+    }
+
+    /// Begin `stage` on the timeline of the restart running, if any.
+    fn stage(&mut self, stage: Stage) {
+        if let Some(trace) = self.trace.as_mut() {
+            trace.begin(stage, Instant::now().into_std());
+        }
+    }
+
+    async fn resume(&self) {
+        // Maintenance may have started while a webhook or the schedule deployed
+        if !self.maintenance {
+            resume_monitors().await;
+        }
+    }
+
+    /// Wait for a freshly spawned child to pass `ready_check`, then mark it
+    /// `Running`.
+    ///
+    /// A child that doesn't become ready in time is left running, but it is
+    /// marked `Degraded` and the reason recorded in the error log.
+    async fn mark_ready(&mut self, state: &mut AppState, mut output: OutputCapture) -> bool {
+        self.stage(Stage::Ready);
+        self.lifecycle.transition(Phase::Starting, state);
+        let gate = self.settings.ready_gate();
+        if gate.is_some() {
+            state::save(state, &self.state_path, None).await;
+        }
+
+        match await_ready(gate.as_ref(), &mut output).await {
+            Ok(()) => {
+                self.lifecycle.transition(Phase::Running, state);
+                if let Some(launch) = GLOBAL_LAUNCH.lock().await.clone() {
+                    update_runner_state(&self.state_path, |runner_state| {
+                        runner_state.last_known_good = Some((current_timestamp(), launch))
+                    });
+                }
+                true
+            }
+            Err(reason) => {
+                log!(LogLevel::Warn, "{}", reason);
+                self.lifecycle.transition(Phase::Degraded, state);
+                log_error(
+                    state,
+                    ErrorArrayItem::new(Errors::TimedOut, reason),
+                    &self.state_path,
+                )
+                .await;
+                false
+            }
+        }
+    }
+}
+
+/// Publish the fresh build output to the built-in static server, keeping
+/// the previous release served if that fails.
+async fn publish_static(
+    settings: &AppSpecificConfig,
+    app_name: &str,
+    state: &mut AppState,
+    state_path: &PathType,
+) {
+    if !settings.static_server.enabled {
+        return;
+    }
+
+    let root = settings.project_path().join(&settings.static_server.root);
+    let releases_dir = settings.static_server.releases_dir(app_name);
+    match publish(&root, &releases_dir, settings.static_server.keep_releases) {
+        Ok(release) => log!(
+            LogLevel::Info,
+            "Published static release {}",
+            release.display()
+        ),
+        Err(err) => {
+            log!(
+                LogLevel::Warn,
+                "Failed to publish static release, keeping the previous one: {}",
+                err
+            );
+            log_error(
+                state,
+                ErrorArrayItem::new(Errors::InputOutput, err.to_string()),
+                state_path,
+            )
+            .await;
+        }
+    }
+}
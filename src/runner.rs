@@ -0,0 +1,1962 @@
+//! The supervision loop, reusable through [`Runner`].
+//!
+//! This is what `ais_runner` does without a subcommand: load the config,
+//! fetch secrets, install, build and spawn the child, then restart it on
+//! changes, crashes, failed probes and control requests until asked to
+//! exit. Other services embed the same behaviour with
+//!
+//! ```no_run
+//! # async fn embed(settings: ais_runner::config::AppSpecificConfig) {
+//! use ais_runner::runner::Runner;
+//!
+//! Runner::builder().settings(settings).oneshot(true).build().run().await;
+//! # }
+//! ```
+//!
+//! High level state is persisted across restarts using [`AppState`].
+
+use crate::acme::watch_certificates;
+use crate::app_status::{AppStatusTracker, describe};
+use crate::artifacts::Artifacts;
+use crate::cadence::{self, Sample};
+use crate::capture::{self, OutputCapture};
+use crate::cgroup::{CgroupConfig, ChildCgroup};
+use crate::child::{ChildLaunch, peek_exit, resolve_identity};
+use crate::config::{AppSpecificConfig, generate_application_state, get_config, specific_config};
+use crate::cpu_limit::{CpuChange, CpuLimitAction, CpuMonitor};
+use crate::crash_loop::CrashLoopReport;
+use crate::events::{self, Event, EventJournal};
+use crate::heartbeat::Heartbeat;
+use crate::host::capabilities;
+use crate::journal::OutputJournal;
+use crate::lifecycle::Phase;
+use crate::listen_fds::ListenSockets;
+use crate::log_rules::{LogAction, LogRules};
+use crate::logging::{LogFormat, dispatch, emit_output, init_logging, log_format};
+use crate::maintenance::{Maintenance, MaintenanceChange, MaintenanceInfo, flag_path};
+use crate::metrics_history::{MetricsHistory, SharedMetricsHistory};
+use crate::notifications::{EventKind, notify, notify_with};
+use crate::orphans::{self, AdoptedChild, OrphanAction, Previous};
+use crate::output::{CapturedLine, Stream, append_sorted, merged, retain_newest};
+use crate::probes::{ProbeOutcome, ProbeTracker};
+use crate::proxy::Activity;
+use crate::reload::{ConfigFile, Plan, keep_runner_options};
+use crate::reservations::{Registry, Reservation, reserve};
+use crate::restart::{RestartKind, RestartOutcome, RestartReason, Restarter};
+use crate::runner_state::{RunnerState, update_runner_state};
+use crate::schedule::{CronSchedule, watch_schedule};
+use crate::shutdown::Shutdown;
+use crate::state_backend::StateBackendConfig;
+use crate::state_sync::{SharedStateLog, StateLog};
+use crate::static_server::{serve, serve_tls};
+use crate::status_server::StatusService;
+use crate::stdin::StdinPipe;
+use crate::supervisor::{Decision, Supervisor};
+use crate::timestamps::{TimestampSource, line_timestamp};
+use crate::uptime;
+use crate::watch::start_monitors;
+use crate::webhook::WebhookTrigger;
+use crate::{
+    config::{default_env_location, default_secret_server},
+    global_child::{
+        GLOBAL_ADOPTED, GLOBAL_ARTIFACTS, GLOBAL_CGROUP, GLOBAL_CLINENT_CONNECTION, GLOBAL_EVENTS,
+        GLOBAL_HEARTBEAT, GLOBAL_JOURNAL, GLOBAL_LISTEN_FDS, GLOBAL_LOOP_ROUND,
+        GLOBAL_PENDING_CHANGES, GLOBAL_PROXY, GLOBAL_STDIN, get_query, init_monitors,
+        pause_monitors, resume_monitors, stop_monitors,
+    },
+    secrets::{
+        SecretClient, SecretQuery, template::shred, watch_rotations, with_retry, write_env_file,
+    },
+};
+use artisan_middleware::{
+    config::AppConfig,
+    dusa_collection_utils::{
+        self,
+        core::{
+            errors::ErrorArray,
+            logger::{get_log_level, set_log_level},
+        },
+    },
+    state_persistence::{AppState, StatePersistence, log_error, wind_down_state},
+    timestamp::current_timestamp,
+};
+
+use crate::signals::{ControlFlags, watch_controls};
+use crate::{
+    artifacts, child_manager, diagnostics, dump, heartbeat, init, journal, log, log_level,
+    log_shipping, metrics_history, notifications, otel, ports, prerequisites, privileges, proxy,
+    reload, reset, shutdown, state, state_backend, state_sync, status_server, stdin, systemd,
+    validation, webhook,
+};
+use dusa_collection_utils::{
+    core::errors::{ErrorArrayItem, Errors},
+    core::logger::LogLevel,
+    core::types::pathtype::PathType,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::Path,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+use tokio::sync::mpsc;
+use tokio::time::{Instant, interval, interval_at};
+
+/// What a oneshot runner exits with when the job couldn't be built.
+const BUILD_FAILED_EXIT: i32 = 100;
+
+/// How the runner treats its child, `mode`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RunMode {
+    /// Keep the child running, rebuilding and respawning it as needed.
+    #[default]
+    Service,
+    /// Install, build and run the child once, like `--oneshot`, for jobs.
+    /// Changes aren't watched and the runner exits with the child's code.
+    Oneshot,
+}
+
+/// Runs the supervision loop, see the [module docs](self).
+#[derive(Debug)]
+pub struct Runner {
+    init: bool,
+    oneshot: bool,
+    settings: Option<AppSpecificConfig>,
+}
+
+/// Builder for [`Runner`], from [`Runner::builder`].
+#[derive(Debug, Default)]
+pub struct RunnerBuilder {
+    init: bool,
+    oneshot: bool,
+    settings: Option<AppSpecificConfig>,
+}
+
+impl RunnerBuilder {
+    /// Take on the duties of a container's PID 1, see [`crate::init`].
+    pub fn init(mut self, init: bool) -> Self {
+        self.init = init;
+        self
+    }
+
+    /// Exit with the child's exit code instead of respawning it.
+    pub fn oneshot(mut self, oneshot: bool) -> Self {
+        self.oneshot = oneshot;
+        self
+    }
+
+    /// Use `settings` instead of `[app_specific]` from Config.toml.
+    pub fn settings(mut self, settings: AppSpecificConfig) -> Self {
+        self.settings = Some(settings);
+        self
+    }
+
+    pub fn build(self) -> Runner {
+        Runner {
+            init: self.init,
+            oneshot: self.oneshot,
+            settings: self.settings,
+        }
+    }
+}
+
+impl Runner {
+    pub fn builder() -> RunnerBuilder {
+        RunnerBuilder::default()
+    }
+
+    /// Supervise the child until the runner is asked to exit.
+    ///
+    /// Takes over the process: signal handlers are installed and the
+    /// process exits on fatal errors and after a graceful shutdown.
+    pub async fn run(self) {
+        supervise(self.init, self.oneshot, self.settings).await
+    }
+}
+
+/// Supervisor entrypoint.
+///
+/// Initializes configuration, loads any persisted state and then enters the monitoring loop.
+/// With `init` the runner also takes on the duties of a container's PID 1, with `oneshot` it
+/// exits along with the child. `preset` replaces the settings of Config.toml.
+async fn supervise(init: bool, oneshot: bool, preset: Option<AppSpecificConfig>) {
+    // Initialization
+
+    // reading config files
+    log!(LogLevel::Trace, "Initializing application...");
+    let mut config: AppConfig = get_config();
+    let state_path: PathType = StatePersistence::get_state_path(&config);
+
+    log!(LogLevel::Trace, "Loading specific configuration...");
+    // Settings handed to the runner aren't replaced by Config.toml's
+    let mut config_file = match preset.is_some() {
+        true => ConfigFile::preset(),
+        false => ConfigFile::new("Config.toml"),
+    };
+    let mut settings = match preset.map(Ok).unwrap_or_else(specific_config) {
+        Ok(loaded_data) => {
+            log!(
+                LogLevel::Trace,
+                "Loaded specific configuration successfully"
+            );
+            loaded_data
+        }
+        Err(e) => {
+            log!(LogLevel::Error, "Error loading settings: {}", e);
+            std::process::exit(100)
+        }
+    };
+    // So validation and prerequisite failures come out in the right format
+    init_logging(settings.log_format, &config.app_name.to_string());
+    let problems = validation::validate(&settings);
+    if !problems.is_empty() {
+        log!(LogLevel::Error, "{}", validation::report(&problems));
+        std::process::exit(100)
+    }
+    // Whatever the commands need, before the first of them fails on it
+    let missing = prerequisites::check(&settings, std::env::var_os("PATH").as_deref());
+    if !missing.is_empty() {
+        log!(LogLevel::Error, "{}", prerequisites::report(&missing));
+        std::process::exit(100)
+    }
+    let oneshot = oneshot || settings.mode == RunMode::Oneshot;
+    state::configure(&settings.state_writes);
+
+    // Setting up the state of the application
+    log!(LogLevel::Trace, "Setting up the application state...");
+    let mut state: AppState = generate_application_state(&state_path, &config).await;
+    log_level::apply(&mut state, &state_path, config.log_level);
+
+    // Detecting what the host supports so subsystems can degrade instead of failing
+    let host = capabilities();
+    log!(LogLevel::Debug, "Host capabilities: {:?}", host);
+    for warning in host.warnings(&settings) {
+        log!(LogLevel::Warn, "{}", warning);
+    }
+    let mut runner_state = RunnerState::load(&state_path);
+    runner_state.host = Some(host.clone());
+
+    // A child left running by a crashed run still holds its port
+    let pid_file = artifacts::pid_file(&config.app_name.to_string());
+    let grace = Duration::from_secs(settings.shutdown_timeout_seconds);
+    let mut adopted = None;
+    match orphans::find(&pid_file, &ChildLaunch::resolve(&settings).argv) {
+        Previous::Gone => (),
+        Previous::Unrelated(pid) => log!(
+            LogLevel::Debug,
+            "Pid {} from {} isn't our child anymore",
+            pid,
+            pid_file.display()
+        ),
+        Previous::Orphan(pid) if settings.orphans.action == OrphanAction::Adopt => {
+            let child = AdoptedChild::attach(pid);
+            log!(
+                LogLevel::Warn,
+                "Child {} of a previous run is still running, supervising it instead of spawning",
+                pid
+            );
+            // Children of runners from before the capture files wrote to pipes
+            for stream in [Stream::Stdout, Stream::Stderr] {
+                let path = capture::path(&state_path, stream);
+                // The links in /proc are absolute and resolved
+                let resolved = fs::canonicalize(&path).ok();
+                match child.output_file(stream) == resolved.as_deref() {
+                    true => log!(
+                        LogLevel::Info,
+                        "Following the adopted child's {} in {}",
+                        stream,
+                        path.display()
+                    ),
+                    false => log!(
+                        LogLevel::Warn,
+                        "The adopted child's {} doesn't go to {}, it can't be captured",
+                        stream,
+                        path.display()
+                    ),
+                }
+            }
+            adopted = Some(child);
+        }
+        Previous::Orphan(pid) => {
+            log!(
+                LogLevel::Warn,
+                "Stopping child {} left running by a previous run",
+                pid
+            );
+            match orphans::kill(pid, grace).await {
+                Ok(()) => log!(LogLevel::Info, "Orphaned child {} is gone", pid),
+                Err(err) => {
+                    log!(LogLevel::Error, "{}", err);
+                    log_error(
+                        &mut state,
+                        ErrorArrayItem::new(Errors::GeneralError, err),
+                        &state_path,
+                    )
+                    .await;
+                }
+            }
+        }
+    }
+
+    // Settling the child's port before anything reserves or spawns with it
+    if let Some(port_config) = settings.port {
+        let taken: Vec<u16> = match settings.reservation.enabled {
+            true => Registry::new(&settings.reservation.registry_dir)
+                .others(&config.app_name.to_string())
+                .unwrap_or_default()
+                .into_iter()
+                .flat_map(|reservation| reservation.ports)
+                .collect(),
+            false => Vec::new(),
+        };
+        match ports::allocate(port_config, runner_state.port, &taken) {
+            Ok(port) => {
+                log!(LogLevel::Info, "Child port: {}", port);
+                ports::apply(&mut settings, port);
+                runner_state.port = Some(port);
+            }
+            Err(err) => {
+                log!(LogLevel::Error, "{}", err);
+                log_error(
+                    &mut state,
+                    ErrorArrayItem::new(Errors::GeneralError, err),
+                    &state_path,
+                )
+                .await;
+                wind_down_state(&mut state, &state_path).await;
+                std::process::exit(100);
+            }
+        }
+    }
+    if let Err(err) = runner_state.save(&state_path) {
+        log!(LogLevel::Warn, "Failed to save runner state: {}", err);
+    }
+
+    // Whatever a crashed or killed run left behind
+    let manifest = artifacts::manifest_path(&state_path);
+    match artifacts::sweep_previous(&manifest) {
+        Ok(0) => (),
+        Ok(removed) => log!(
+            LogLevel::Info,
+            "Removed {} files left by the previous run",
+            removed
+        ),
+        Err(err) => log!(LogLevel::Warn, "{}", err),
+    }
+    let stale_pid_files = artifacts::sweep_pid_files(Path::new("/tmp"));
+    if stale_pid_files > 0 {
+        log!(
+            LogLevel::Info,
+            "Removed {} stale pid files from /tmp",
+            stale_pid_files
+        );
+    }
+    _ = GLOBAL_ARTIFACTS.set(Artifacts::new(manifest));
+    match uptime::prune(
+        &uptime::path(&state_path),
+        current_timestamp().saturating_sub(uptime::RETENTION),
+    ) {
+        Ok(0) => (),
+        Ok(pruned) => log!(
+            LogLevel::Debug,
+            "Dropped {} transitions past the retention",
+            pruned
+        ),
+        Err(err) => log!(
+            LogLevel::Warn,
+            "Failed to prune the transition log: {}",
+            err
+        ),
+    }
+
+    // Before dropping privileges, so they're handed over to the child's user
+    if let Err(err) = capture::create(&state_path) {
+        log!(
+            LogLevel::Warn,
+            "Failed to create the capture files in {}: {}",
+            capture::dir(&state_path).display(),
+            err
+        );
+    }
+
+    if settings.journal.enabled {
+        let journal_path = settings.journal.path(&state_path);
+        match OutputJournal::open(
+            &journal_path,
+            settings.journal.fsync,
+            settings.journal.max_size_mb,
+        ) {
+            Ok(journal) => *GLOBAL_JOURNAL.lock().await = Some(journal),
+            Err(err) => log!(
+                LogLevel::Warn,
+                "Output journal {} unavailable: {}",
+                journal_path.display(),
+                err
+            ),
+        }
+    }
+
+    if settings.events.enabled {
+        let events_path = settings.events.path(&state_path);
+        match EventJournal::open(
+            &events_path,
+            settings.events.max_size_mb,
+            settings.events.keep,
+        ) {
+            Ok(journal) => _ = GLOBAL_EVENTS.set(Mutex::new(journal)),
+            Err(err) => log!(
+                LogLevel::Warn,
+                "Event journal {} unavailable: {}",
+                events_path.display(),
+                err
+            ),
+        }
+    }
+
+    // The state file is written either way
+    if settings.state_backend != StateBackendConfig::File {
+        match settings.state_backend.backend(&state_path) {
+            Ok(backend) => {
+                if let Err(err) = backend.prepare() {
+                    log!(
+                        LogLevel::Warn,
+                        "State backend {} unavailable, publishing once it's back: {}",
+                        backend.name(),
+                        err
+                    );
+                }
+                state_backend::start(backend);
+            }
+            Err(err) => log!(LogLevel::Error, "Not publishing states: {}", err),
+        }
+    }
+
+    let notifications = match settings.notifications.enabled() {
+        true => notifications::start(&settings.notifications, &config.app_name.to_string()),
+        false => Ok(()),
+    };
+    if let Err(err) = notifications {
+        log!(LogLevel::Warn, "Notifications unavailable: {}", err);
+    }
+    let log_shipping = match settings.log_shipping.enabled() {
+        true => log_shipping::start(&settings.log_shipping, &config.app_name.to_string()),
+        false => Ok(()),
+    };
+    if let Err(err) = log_shipping {
+        log!(LogLevel::Warn, "Log shipping unavailable: {}", err);
+    }
+    let tracing = match &settings.otel_endpoint {
+        Some(endpoint) => otel::start(endpoint, &config.app_name.to_string()),
+        None => Ok(()),
+    };
+    if let Err(err) = tracing {
+        log!(LogLevel::Warn, "Restart traces unavailable: {}", err);
+    }
+
+    // Declaring what we need so runners sharing this host don't silently overcommit it
+    if settings.reservation.enabled {
+        let reservation = Reservation::for_runner(
+            &config.app_name.to_string(),
+            &settings,
+            state.config.max_ram_usage as u64,
+        );
+        match reserve(&reservation, &settings.reservation) {
+            Ok(problems) => problems
+                .iter()
+                .for_each(|problem| log!(LogLevel::Warn, "{}", problem)),
+            Err(err) => {
+                log!(LogLevel::Error, "{}", err);
+                log_error(
+                    &mut state,
+                    ErrorArrayItem::new(Errors::GeneralError, err),
+                    &state_path,
+                )
+                .await;
+                wind_down_state(&mut state, &state_path).await;
+                std::process::exit(100);
+            }
+        }
+    }
+
+    // Listening for reload, exit and maintenance requests
+    let controls = ControlFlags::default();
+    watch_controls(&controls);
+    let ControlFlags {
+        reload,
+        exit: exit_graceful,
+        maintenance: maintenance_toggle,
+        log_level: log_level_request,
+        limits: limits_request,
+        dump: dump_requests,
+    } = controls;
+    dump::watch(dump_requests, state_path.clone());
+    if init {
+        init::start(exit_graceful.clone());
+    }
+
+    log!(LogLevel::Trace, "Setting state as active...");
+    state::save(&mut state, &state_path, None).await;
+
+    // Without cgroup v2 the capability warning above already explained the fallback
+    if settings.cgroup.enabled && capabilities().cgroup_v2 {
+        match ChildCgroup::setup(&settings.cgroup, state.config.max_ram_usage as u64) {
+            Ok(cgroup) => *GLOBAL_CGROUP.lock().await = Some(cgroup),
+            Err(err) => {
+                log!(
+                    LogLevel::Warn,
+                    "cgroup enforcement unavailable, falling back to monitoring: {}",
+                    err
+                );
+                state.error_log.push(err);
+            }
+        }
+    }
+
+    // Everything that needs root is done, give it up before any secret is fetched
+    if settings.drop_privileges {
+        let dropped = privileges::drop_privileges(
+            &settings,
+            &state_path,
+            &config.app_name.to_string(),
+            GLOBAL_CGROUP.lock().await.as_ref(),
+        );
+        if let Err(err) = dropped {
+            log!(LogLevel::Error, "{}", err);
+            log_error(
+                &mut state,
+                ErrorArrayItem::new(Errors::GeneralError, err),
+                &state_path,
+            )
+            .await;
+            wind_down_state(&mut state, &state_path).await;
+            std::process::exit(100);
+        }
+    }
+
+    if config.debug_mode {
+        log!(LogLevel::Info, "Application State: {}", state);
+        log!(LogLevel::Info, "Application State: {}", settings);
+        log!(LogLevel::Info, "Log Level: {}", config.log_level);
+    }
+
+    // requesting enviornment data
+    let env_path: PathType = PathType::Content(settings.env_file_location.clone());
+    let env_dummy: PathType = PathType::Content(default_env_location());
+    if env_dummy == env_path {
+        log!(LogLevel::Warn, "No env file location specified skipping...");
+        return;
+    }
+    _ = env_path.delete();
+
+    let query: SecretQuery = match get_query() {
+        Ok(q) => q,
+        Err(_) => {
+            log!(LogLevel::Error, "Error loading env query");
+            std::process::exit(0)
+        }
+    };
+
+    if &settings.secret_server_addr == &default_secret_server() {
+        log!(
+            LogLevel::Warn,
+            "No secret server address defined, skipping ..."
+        );
+        return;
+    }
+
+    // Retried so a secret server that is briefly down doesn't take us with it,
+    // every failed attempt ends up in the error log
+    let retry = &settings.secret_retry;
+    let mut attempts: Vec<ErrorArrayItem> = Vec::new();
+    let connected = with_retry(retry, "connect to the secret server", &mut attempts, || {
+        SecretClient::connect(&settings.secret_server_addr, settings.secret_tls.as_ref())
+    })
+    .await;
+    state.error_log.append(&mut attempts);
+
+    let client = match connected {
+        Ok(c) => c,
+        Err(err) => {
+            log!(LogLevel::Error, "Error dialing secret server: {}", err);
+            log_error(&mut state, err, &state_path).await;
+            wind_down_state(&mut state, &state_path).await;
+            std::process::exit(100)
+        }
+    };
+
+    // Failing fast here beats a child crashing on a missing variable
+    let required = match settings.required_secrets.is_empty() {
+        true => None,
+        false => {
+            let fetched = with_retry(retry, "fetch required secrets", &mut attempts, || {
+                query.get_required(client.clone(), &settings.required_secrets)
+            })
+            .await;
+            state.error_log.append(&mut attempts);
+
+            match fetched {
+                Ok(secrets) => Some(secrets),
+                Err(err) => {
+                    log!(LogLevel::Error, "{}", err);
+                    log_error(&mut state, err, &state_path).await;
+                    wind_down_state(&mut state, &state_path).await;
+                    std::process::exit(100);
+                }
+            }
+        }
+    };
+
+    let fetched = match required {
+        Some(secrets) if settings.only_required_secrets => Ok(secrets),
+        _ => {
+            with_retry(retry, "fetch secrets", &mut attempts, || {
+                query.get_all(client.clone())
+            })
+            .await
+        }
+    };
+    state.error_log.append(&mut attempts);
+    state::save(&mut state, &state_path, None).await;
+
+    match fetched {
+        Ok(results) => {
+            if results.is_empty() {
+                log!(
+                    LogLevel::Debug,
+                    "No env data for current runtime: id: {} env: {}",
+                    query.runner_id,
+                    query.enviornment_id
+                );
+
+                return;
+            }
+
+            // With a template the env file is rendered before every spawn
+            if settings.env_template.is_none() {
+                artifacts::track(&env_path);
+                if let Err(err) = write_env_file(&env_path, &results) {
+                    log!(LogLevel::Error, "Failed to write env file: {}", err);
+                    std::process::exit(100);
+                }
+            }
+        }
+        Err(err) => ErrorArray::from(err).display(true),
+    }
+
+    // Set when rotated secrets need a restart, held back during maintenance
+    let rotated = Arc::new(AtomicBool::new(false));
+    if let Some(action) = settings.secret_rotation {
+        watch_rotations(
+            query.clone(),
+            client.clone(),
+            action,
+            settings.clone(),
+            rotated.clone(),
+        );
+    }
+
+    match GLOBAL_CLINENT_CONNECTION.try_lock() {
+        Ok(mut store) => *store = Some(client),
+        Err(err) => {
+            log!(
+                LogLevel::Error,
+                "Error storing secret server connection: {}",
+                err.to_string()
+            );
+            std::process::exit(0)
+        }
+    }
+
+    log!(LogLevel::Debug, "Copied secret data from the server");
+
+    log!(LogLevel::Info, "{} Started", config.app_name);
+    notify(EventKind::Started, format!("{} started", config.app_name));
+
+    let mut restarter = Restarter::new(&settings, &config.app_name.to_string(), &state_path);
+    restarter.lifecycle.sync(&mut state);
+    state::save(&mut state, &state_path, None).await;
+
+    // A restore requested while the runner was down goes straight to the spawn
+    let restore = take_restore(&state_path);
+    if restore.is_some() {
+        restarter.note_restart(&RestartKind::Restore.into());
+    }
+
+    // The adopted child already runs what a previous run built
+    if restore.is_none() && adopted.is_none() && !restarter.prepare(&mut state, true).await {
+        notifications::flush().await;
+        // A job that couldn't be built never ran, whoever scheduled it has to know
+        if oneshot {
+            wind_down_state(&mut state, &state_path).await;
+            state_backend::publish_final(&mut state).await;
+            std::process::exit(BUILD_FAILED_EXIT);
+        }
+        return;
+    }
+    if settings.static_server.enabled {
+        let releases_dir = settings
+            .static_server
+            .releases_dir(&config.app_name.to_string());
+        if let Err(err) = serve(&settings.static_server, releases_dir.clone()).await {
+            log!(
+                LogLevel::Error,
+                "Static server couldn't bind {}: {}",
+                settings.static_server.bind,
+                err
+            );
+            log_error(
+                &mut state,
+                ErrorArrayItem::new(Errors::InputOutput, err.to_string()),
+                &state_path,
+            )
+            .await;
+        }
+
+        if settings.acme.enabled {
+            if let Some(bind) = &settings.acme.tls_bind {
+                let served = serve_tls(&settings.static_server, bind, releases_dir).await;
+                if let Err(err) = served {
+                    log!(
+                        LogLevel::Error,
+                        "Static server couldn't bind {}: {}",
+                        bind,
+                        err
+                    );
+                    log_error(
+                        &mut state,
+                        ErrorArrayItem::new(Errors::InputOutput, err.to_string()),
+                        &state_path,
+                    )
+                    .await;
+                }
+            }
+            watch_certificates(settings.acme.clone(), config.app_name.to_string());
+        }
+    }
+
+    if settings.heartbeat.enabled {
+        let owner = match resolve_identity(&settings) {
+            Ok(identity) => identity.map(|identity| (identity.uid, identity.gid)),
+            Err(_) => None,
+        };
+        let timeout = settings.heartbeat.timeout_seconds.map(Duration::from_secs);
+        let path = heartbeat::socket_path(&state_path);
+        match Heartbeat::listen(&path, timeout, owner) {
+            Ok(heartbeat) => {
+                log!(LogLevel::Info, "Child heartbeats go to {}", path.display());
+                artifacts::track(&path);
+                _ = GLOBAL_HEARTBEAT.set(heartbeat);
+            }
+            Err(err) => log!(
+                LogLevel::Error,
+                "Failed to bind heartbeat socket {}: {}",
+                path.display(),
+                err
+            ),
+        }
+    }
+
+    if settings.stdin_pipe {
+        let path = stdin::socket_path(&state_path);
+        match StdinPipe::listen(&path) {
+            Ok(pipe) => {
+                log!(
+                    LogLevel::Info,
+                    "Text for the child's stdin is taken on {}",
+                    path.display()
+                );
+                artifacts::track(&path);
+                _ = GLOBAL_STDIN.set(pipe);
+            }
+            Err(err) => log!(
+                LogLevel::Error,
+                "Failed to bind stdin socket {}: {}",
+                path.display(),
+                err
+            ),
+        }
+    }
+
+    if let (Some(listen), Some(port)) = (&settings.proxy.listen, runner_state.port) {
+        let activity = Arc::new(Activity::new(
+            settings.proxy.idle_timeout(),
+            Instant::now().into_std(),
+        ));
+        // Held until the first child is ready
+        activity.restarting();
+        match proxy::serve(&settings.proxy, port, activity.clone()).await {
+            Ok(()) => _ = GLOBAL_PROXY.set(activity),
+            Err(err) => log!(
+                LogLevel::Error,
+                "Failed to listen for the child on {}: {}",
+                listen,
+                err
+            ),
+        }
+    }
+
+    if settings.listen_fds.enabled {
+        match ListenSockets::bind(&settings.listen_fds.addrs(runner_state.port)).await {
+            Ok(sockets) => {
+                log!(
+                    LogLevel::Info,
+                    "Passing {} to the child as LISTEN_FDS",
+                    sockets.addrs().join(", ")
+                );
+                _ = GLOBAL_LISTEN_FDS.set(sockets);
+            }
+            Err(err) => log!(
+                LogLevel::Error,
+                "Failed to bind the child's sockets: {}",
+                err
+            ),
+        }
+    }
+
+    let ready = match adopted {
+        Some(adopted) => restarter.adopt(&mut state, adopted).await,
+        None => restarter.spawn(&mut state, restore).await,
+    };
+    restarter.finish_restart(ready);
+    let mut supervisor = Supervisor::new(&settings);
+    save_crash_loop(&state_path, None);
+
+    let watch_paths = settings.watch_paths();
+
+    // Start monitoring the directories and get the asynchronous receiver,
+    // in supervise-only and oneshot mode nothing is watched and no change
+    // ever arrives
+    let monitoring = settings.monitoring_enabled && !oneshot;
+    let started = match monitoring {
+        true => {
+            log!(LogLevel::Debug, "Starting directory monitoring...");
+            start_monitors(
+                &watch_paths,
+                settings.interval_seconds,
+                Duration::from_millis(settings.change_batch_ms),
+                &settings.polling,
+            )
+            .await
+        }
+        false => {
+            let why = match oneshot {
+                true => "the child runs once",
+                false => "monitoring_enabled is off",
+            };
+            log!(LogLevel::Info, "Not watching for changes, {}", why);
+            Ok((Vec::new(), mpsc::channel(1).1))
+        }
+    };
+    let mut event_rx = match started {
+        Ok((monitors, rx)) => {
+            init_monitors(monitors).await;
+            rx
+        }
+        Err(err) => {
+            log!(LogLevel::Error, "{}", err);
+            state
+                .error_log
+                .push(ErrorArrayItem::new(Errors::GeneralError, err));
+            wind_down_state(&mut state, &state_path).await;
+            std::process::exit(100);
+        }
+    };
+
+    // Each on its own cadence, metrics in a task of their own. Output on a
+    // shorter tick, until it's drained it's only in the capture files
+    let cadence = settings.cadence.clone();
+    let mut output_tick = interval(cadence.output());
+    let mut health_tick = interval_at(Instant::now() + cadence.health(), cadence.health());
+    let mut cleanup_tick = interval_at(Instant::now() + cadence.cleanup(), cadence.cleanup());
+    let (sample_tx, mut sample_rx) = mpsc::channel(1);
+    cadence::spawn_sampler(cadence.metrics(), sample_tx);
+
+    let (schedule_tx, mut schedule_rx) = mpsc::channel(1);
+    if let Some(expression) = &settings.restart_schedule {
+        match expression.parse::<CronSchedule>() {
+            Ok(schedule) => watch_schedule(schedule, schedule_tx),
+            Err(err) => log!(
+                LogLevel::Error,
+                "Ignoring restart_schedule {:?}: {}",
+                expression,
+                err
+            ),
+        }
+    }
+
+    let (webhook_tx, mut webhook_rx) = mpsc::channel::<WebhookTrigger>(8);
+    if let Some(addr) = &settings.webhook_addr {
+        match &settings.webhook_secret {
+            Some(secret) => {
+                if let Err(err) = webhook::serve(addr, secret.clone(), webhook_tx).await {
+                    log!(
+                        LogLevel::Error,
+                        "Failed to listen for webhooks on {}: {}",
+                        addr,
+                        err
+                    );
+                }
+            }
+            None => log!(
+                LogLevel::Error,
+                "webhook_addr is set without a webhook_secret, not accepting webhooks"
+            ),
+        }
+    }
+
+    let mut app_status = AppStatusTracker::new(settings.app_status.path(&settings.project_path));
+
+    let mut cpu_monitor = CpuMonitor::new(&settings.cpu_limit);
+    let history_path = metrics_history::path(&state_path);
+    let history: Option<SharedMetricsHistory> = match settings.metrics_history.enabled() {
+        true => Some(Arc::new(Mutex::new(MetricsHistory::load(
+            &history_path,
+            &settings.metrics_history,
+        )))),
+        false => None,
+    };
+
+    // Only kept up to date while a collector can ask for it
+    let mut state_log: Option<SharedStateLog> = None;
+    if let Some(addr) = &settings.state_sync.addr {
+        match &settings.state_sync.token {
+            Some(token) => {
+                let log = Arc::new(Mutex::new(StateLog::new(current_timestamp())));
+                match state_sync::serve(addr, token.clone(), log.clone(), history.clone()).await {
+                    Ok(()) => state_log = Some(log),
+                    Err(err) => log!(
+                        LogLevel::Error,
+                        "Failed to serve state deltas on {}: {}",
+                        addr,
+                        err
+                    ),
+                }
+            }
+            None => log!(
+                LogLevel::Error,
+                "state_sync.addr is set without a token, not serving state deltas"
+            ),
+        }
+    }
+    if let Some(addr) = &settings.status_server.addr {
+        match &settings.status_server.token {
+            Some(token) => {
+                let service =
+                    StatusService::new(state_path.clone(), reload.clone(), history.clone());
+                if let Err(err) = status_server::serve(addr, token.clone(), service).await {
+                    log!(
+                        LogLevel::Error,
+                        "Failed to serve the status service on {}: {}",
+                        addr,
+                        err
+                    );
+                }
+            }
+            None => log!(
+                LogLevel::Error,
+                "status_server.addr is set without a token, not serving the status service"
+            ),
+        }
+    }
+    let mut maintenance = Maintenance::new(flag_path(&watch_paths[0].path), maintenance_toggle);
+    // A signal toggle only lives in memory, pick it up again from the state
+    maintenance.restore(RunnerState::load(&state_path).maintenance.as_ref());
+
+    // What the runner exits with after a graceful shutdown
+    let mut exit_code = 0;
+
+    let port = runner_state.port;
+
+    log!(LogLevel::Trace, "Entering main loop...");
+    state::save(&mut state, &state_path, None).await;
+    loop {
+        // Set by a change, the schedule or a webhook, handled after the select
+        let mut deploy: Option<RestartReason> = None;
+        // For dumps taken while the loop is busy
+        GLOBAL_LOOP_ROUND.store(current_timestamp(), Ordering::Relaxed);
+        GLOBAL_PENDING_CHANGES.store(supervisor.pending_changes(), Ordering::Relaxed);
+
+        match maintenance.poll() {
+            Some(MaintenanceChange::Entered(source)) => {
+                dispatch(
+                    LogLevel::Info,
+                    "maintenance",
+                    format!(
+                        "Maintenance mode on ({:?}), suspending deploys, respawns and health probes",
+                        source
+                    ),
+                );
+                pause_monitors().await;
+                restarter.maintenance = true;
+                supervisor.set_maintenance(true);
+                let info = MaintenanceInfo {
+                    since: current_timestamp(),
+                    source,
+                };
+                update_runner_state(&state_path, |runner_state| {
+                    runner_state.maintenance = Some(info)
+                });
+            }
+            Some(MaintenanceChange::Left) => {
+                dispatch(
+                    LogLevel::Info,
+                    "maintenance",
+                    String::from("Maintenance mode off, watching for changes again"),
+                );
+                resume_monitors().await;
+                restarter.maintenance = false;
+                if supervisor.set_maintenance(false) {
+                    deploy = Some(RestartReason::new(
+                        RestartKind::Exited,
+                        "stopped during maintenance",
+                    ));
+                }
+                restarter.probes.reset();
+                restarter.log_rules.reset();
+                update_runner_state(&state_path, |runner_state| runner_state.maintenance = None);
+            }
+            None => (),
+        }
+
+        tokio::select! {
+            Some(batch) = event_rx.recv(), if monitoring => {
+                if maintenance.is_active() {
+                    log!(LogLevel::Debug, "Ignoring changes during maintenance: {}", batch);
+                } else {
+                    log!(LogLevel::Debug, "Changes: {}", batch);
+                    deploy = supervisor.on_batch(&batch, Instant::now().into_std());
+                }
+            }
+            Some(_) = schedule_rx.recv() => {
+                if maintenance.is_active() {
+                    log!(LogLevel::Info, "Skipping scheduled restart during maintenance");
+                } else {
+                    dispatch(LogLevel::Info, "scheduled_restart", String::from("Scheduled restart due"));
+                    deploy = Some(RestartKind::Schedule.into());
+                }
+            }
+            Some(trigger) = webhook_rx.recv() => {
+                if maintenance.is_active() {
+                    log!(LogLevel::Info, "Ignoring rebuild requested by {} during maintenance", trigger.reason);
+                } else {
+                    if let Some(note) = trigger.note {
+                        update_runner_state(&state_path, |runner_state| runner_state.pending_note = Some(note));
+                    }
+                    dispatch(LogLevel::Info, "webhook", format!("Rebuild requested by {}", trigger.reason));
+                    deploy = Some(RestartReason::new(RestartKind::Webhook, trigger.reason));
+                }
+            }
+            _ = output_tick.tick() => {
+                drain_output(&mut restarter.capture, &mut restarter.log_rules, &mut state, &settings).await;
+                state::flush_due(&mut state, &state_path).await;
+            }
+            _ = health_tick.tick() => {
+                log!(LogLevel::Trace, "Health check triggered - checking child process status...");
+
+                let mut failure: Option<RestartReason> = None;
+
+                let heartbeat = GLOBAL_HEARTBEAT.get();
+                let child_fields = heartbeat.map(|heartbeat| heartbeat.fields()).unwrap_or_default();
+                if app_status.update(&child_fields) {
+                    log!(LogLevel::Debug, "App status: {:?}", app_status.current());
+                    let fields = app_status.current().clone();
+                    update_runner_state(&state_path, |runner_state| runner_state.app_status = fields);
+                }
+
+                // Getting stds from child and cheking it's pulse. Whatever
+                // arrived since the last drain, before a possible respawn
+                drain_output(&mut restarter.capture, &mut restarter.log_rules, &mut state, &settings).await;
+                // Peeked before running() gets the chance to reap it
+                let exit = child_manager::pid().await.and_then(peek_exit);
+
+                if let Some(running) = child_manager::running().await {
+                    if !running {
+                        let reason = match exit {
+                            Some(exit) => RestartReason::new(RestartKind::Exited, format!("child {}", exit)).with_exit(exit),
+                            None => RestartKind::Exited.into(),
+                        };
+                        failure = Some(reason);
+                    } else if maintenance.is_active() {
+                        log!(LogLevel::Trace, "Maintenance mode, skipping health probes");
+                    } else if let Some(reason) = unhealthy(&mut restarter.probes).await {
+                        log!(LogLevel::Error, "{}, restarting child", reason);
+                        state.error_log.push(ErrorArrayItem::new(Errors::GeneralError, reason.clone()));
+                        if let Some(Err(err)) = child_manager::kill(settings.kill_mode).await {
+                            log!(LogLevel::Error, "Error killing unhealthy child: {}", err);
+                        }
+                        failure = Some(RestartReason::new(RestartKind::Unhealthy, reason));
+                    } else if let Some(line) = restarter.log_rules.take_restart() {
+                        state.error_log.push(ErrorArrayItem::new(Errors::GeneralError, format!("Log rule matched: {}", line)));
+                        if let Some(Err(err)) = child_manager::kill(settings.kill_mode).await {
+                            log!(LogLevel::Error, "Error killing child: {}", err);
+                        }
+                        failure = Some(RestartReason::new(RestartKind::LogLine, line));
+                    }
+                } else if let Some(adopted) = GLOBAL_ADOPTED.lock().await.as_mut() {
+                    // It can't be waited on, so there's no exit status to report
+                    let replace = match adopted.running() {
+                        false => Some(RestartReason::new(RestartKind::Exited, format!("adopted child {} exited", adopted.pid))),
+                        true if adopted.overdue(settings.orphans.adopt_timeout()) => {
+                            log!(LogLevel::Info, "Adopted child {} ran past adopt_timeout_seconds, replacing it", adopted.pid);
+                            Some(RestartReason::new(RestartKind::Exited, "adopt_timeout_seconds passed"))
+                        }
+                        true if maintenance.is_active() => {
+                            log!(LogLevel::Trace, "Maintenance mode, skipping health probes");
+                            None
+                        }
+                        true => match unhealthy(&mut restarter.probes).await {
+                            Some(reason) => {
+                                log!(LogLevel::Error, "{}, restarting child", reason);
+                                state.error_log.push(ErrorArrayItem::new(Errors::GeneralError, reason.clone()));
+                                Some(RestartReason::new(RestartKind::Unhealthy, reason))
+                            }
+                            None => restarter.log_rules.take_restart().map(|line| {
+                                state.error_log.push(ErrorArrayItem::new(Errors::GeneralError, format!("Log rule matched: {}", line)));
+                                RestartReason::new(RestartKind::LogLine, line)
+                            }),
+                        },
+                    };
+                    if replace.is_some() && adopted.running() {
+                        let grace = Duration::from_secs(settings.shutdown_timeout_seconds);
+                        if let Err(err) = orphans::kill(adopted.pid, grace).await {
+                            log!(LogLevel::Error, "Error killing adopted child: {}", err);
+                        }
+                    }
+                    failure = replace;
+                } else if GLOBAL_PROXY.get().is_some_and(|proxy| proxy.is_asleep()) {
+                    log!(LogLevel::Trace, "Child stopped while idle, waiting for a connection");
+                } else if restarter.spawn_pending() && maintenance.is_active() {
+                    log!(LogLevel::Trace, "Maintenance mode, not spawning the child");
+                } else if restarter.spawn_pending() {
+                    log!(LogLevel::Info, "Trying to spawn the child again");
+                    restarter.retry_spawn(&mut state).await;
+                } else {
+                    log!(LogLevel::Warn, "No child for periodic checks, skipping");
+                }
+                // An adopted child that's gone is replaced by a spawned one
+                if failure.is_some() {
+                    GLOBAL_ADOPTED.lock().await.take();
+                }
+
+                // In oneshot mode the child's exit is the runner's
+                if let Some(exit) = failure.as_ref().filter(|_| oneshot).and_then(|reason| reason.exit) {
+                    log!(LogLevel::Info, "Child {}, exiting along with it", exit);
+                    update_runner_state(&state_path, |runner_state| {
+                        runner_state.last_exit = Some((current_timestamp(), exit))
+                    });
+                    child_manager::take().await;
+                    exit_code = exit.code();
+                    exit_graceful.store(true, Ordering::Relaxed);
+                    failure = None;
+                }
+
+                let decision = match failure {
+                    Some(reason) => supervisor.on_failure(reason, Instant::now().into_std()),
+                    None => Decision::Hold,
+                };
+                let exited = match &decision {
+                    Decision::Respawn(reason) | Decision::CrashLoop(reason) | Decision::Paused(reason) => reason.exit,
+                    Decision::Idle(exit) => Some(*exit),
+                    Decision::Hold => None,
+                };
+                if let Some(exit) = exited {
+                    log!(LogLevel::Info, "Child {}", exit);
+                    events::record(Event::ChildExited { exit: exit.to_string() });
+                    update_runner_state(&state_path, |runner_state| {
+                        runner_state.last_exit = Some((current_timestamp(), exit))
+                    });
+                }
+
+                match decision {
+                    Decision::Respawn(reason) => {
+                        log!(LogLevel::Warn, "Restarting the child for {}", reason);
+                        if restarter.restart_child(&mut state, reason, None).await == RestartOutcome::Failed {
+                            notifications::flush().await;
+                            return;
+                        }
+                    }
+                    Decision::Idle(exit) => {
+                        let message = format!("Child {}, not restarting it", exit);
+                        log!(LogLevel::Info, "{}", message);
+                        restarter.lifecycle.transition(Phase::Idle, &mut state);
+                        notify(EventKind::Idle, message.clone());
+                        state.data = message;
+                        state::save(&mut state, &state_path, None).await;
+                    }
+                    // Respawns are suspended while the child keeps crashing
+                    Decision::CrashLoop(reason) => {
+                        let exit_code = reason.exit.map(|exit| exit.code());
+                        let mut report = CrashLoopReport::new(current_timestamp(), supervisor.breaker(), exit_code, &state.stderr);
+                        log!(LogLevel::Error, "{}, waiting for a restart or SIGHUP", report);
+                        for line in &report.stderr {
+                            log!(LogLevel::Error, "stderr: {}", line);
+                        }
+                        state.data = match diagnostics::create(&settings, &state, &state_path, &report) {
+                            Ok(path) => {
+                                log!(LogLevel::Info, "Wrote diagnostics to {}", path.display());
+                                report.diagnostics = Some(path.display().to_string());
+                                format!("{}, diagnostics in {}", report, path.display())
+                            }
+                            Err(err) => {
+                                log!(LogLevel::Warn, "{}", err);
+                                report.to_string()
+                            }
+                        };
+                        restarter.lifecycle.transition(Phase::Degraded, &mut state);
+                        state.error_log.push(ErrorArrayItem::new(Errors::GeneralError, report.to_string()));
+                        notify_with(EventKind::CrashLoop, report.to_string(), serde_json::to_value(&report).ok());
+                        save_crash_loop(&state_path, Some(report));
+                        state::save(&mut state, &state_path, None).await;
+                    }
+                    // Left down until maintenance is over
+                    Decision::Paused(reason) => {
+                        let message = format!("Child stopped for {} during maintenance, respawning once it's off", reason);
+                        log!(LogLevel::Warn, "{}", message);
+                        restarter.lifecycle.transition(Phase::Idle, &mut state);
+                        state.data = message;
+                        state::save(&mut state, &state_path, None).await;
+                    }
+                    Decision::Hold => (),
+                }
+
+                // Only a child that's up and not held for maintenance is put to sleep
+                let quiet = match maintenance.is_active() || supervisor.is_down() {
+                    true => None,
+                    false => GLOBAL_PROXY.get().and_then(|proxy| proxy.due(Instant::now().into_std())),
+                };
+                if let Some(quiet) = quiet {
+                    restarter.sleep(&mut state, quiet).await;
+                }
+
+                if supervisor.is_down() || GLOBAL_PROXY.get().is_some_and(|proxy| proxy.is_asleep()) {
+                    // Nothing is running, keep the report of why visible
+                    state::save(&mut state, &state_path, None).await;
+                }
+            }
+
+            Some(sample) = sample_rx.recv() => {
+                // Taken while the child was still up
+                if supervisor.is_down() || GLOBAL_PROXY.get().is_some_and(|proxy| proxy.is_asleep()) {
+                    log!(LogLevel::Trace, "Dropping a metrics sample, the child is down");
+                } else { // Collecting metrics data to add to state
+                    let child_status = GLOBAL_HEARTBEAT.get().and_then(|heartbeat| heartbeat.status());
+                    state.data = describe(child_status.as_deref().unwrap_or("Nominal"), app_status.current());
+                    let Sample { metrics, usage } = sample;
+                    if let Some((cpu, memory)) = usage {
+                        if let Some(history) = &history {
+                            record_metrics(history, &history_path, cpu, memory, &settings);
+                        }
+                        if let Some(change) = cpu_monitor.as_mut().and_then(|monitor| monitor.observe(Instant::now().into_std(), cpu)) {
+                            cpu_limit_crossed(change, &settings, &mut state).await;
+                        }
+                        // Ensuring we are within the specified limits
+                        if let Some(cgroup) = GLOBAL_CGROUP.lock().await.as_mut() {
+                            // The kernel enforces the limit, report what it did about it
+                            if let Some(oom_error) = cgroup.new_oom_kills() {
+                                log!(LogLevel::Error, "{}", oom_error);
+                                state.error_log.push(oom_error);
+                            }
+                        } else if memory >= state.config.max_ram_usage as f64 {
+                            state.error_log.push(ErrorArrayItem::new(Errors::OverRamLimit, "Application has exceeded ram limit"))
+                        }
+                        match restarter.log_rules.warning(Instant::now().into_std()) {
+                            Some(line) => {
+                                state.data = format!("Log rule matched: {}", line);
+                                restarter.lifecycle.transition(Phase::Degraded, &mut state);
+                            }
+                            None => restarter.lifecycle.transition(Phase::Running, &mut state),
+                        }
+                        log!(LogLevel::Debug, "Application status: {}", state.status);
+                        state::save(&mut state, &state_path, metrics).await;
+                    } else {
+                        state.data = String::from("Failed to get metric data");
+                        state.error_log.push(ErrorArrayItem::new(Errors::GeneralError, "Failed to get metric data from the child"));
+                        restarter.lifecycle.transition(Phase::Degraded, &mut state);
+                        log!(LogLevel::Debug, "Application status: {}", state.status);
+                        state::save(&mut state, &state_path, None).await;
+                    }
+                }
+            }
+
+            _ = cleanup_tick.tick() => {
+                if let Some(scope) = reset::take_pending(&state_path) {
+                    scope.apply(&mut state);
+                    state::save(&mut state, &state_path, None).await;
+                    state::flush(&mut state, &state_path).await;
+                    log!(LogLevel::Info, "Cleared {} of the state as asked by reset", scope.describe());
+                }
+
+                // Cleaning up the state file
+                state.error_log.dedup();
+                if state.error_log.len() >= 5 {
+                    state.error_log.remove(0);
+                }
+            }
+
+            _ = wake_requested() => {
+                let asleep = GLOBAL_PROXY.get().is_some_and(|proxy| proxy.is_asleep());
+                if asleep && maintenance.is_active() {
+                    log!(LogLevel::Info, "Not waking the child during maintenance");
+                } else if asleep {
+                    dispatch(LogLevel::Info, "idle", String::from("Connection for the stopped child, waking it"));
+                    restarter.spawn(&mut state, None).await;
+                }
+            }
+
+            _ = tokio::signal::ctrl_c() => {
+                log!(LogLevel::Info, "CTRL + C recieved");
+                exit_graceful.store(true, Ordering::Relaxed);
+            }
+        }
+
+        // Config.toml was edited, or a reload picks it up before respawning
+        let reloading = reload.load(Ordering::Relaxed)
+            && config_file.reloadable()
+            && !RunnerState::load(&state_path).restore_requested;
+        if config_file.changed() || reloading {
+            match reload_settings(&settings, port) {
+                Ok(Some((reloaded, plan))) => {
+                    events::record(Event::ConfigReload {
+                        live: plan.live.clone(),
+                        child: plan.child.clone(),
+                        runner: plan.runner.clone(),
+                        error: None,
+                    });
+                    settings = reloaded;
+                    if plan.touches(&["cgroup"]) {
+                        apply_limits(&mut settings, &mut state, &state_path, &config_file).await;
+                    }
+                    restarter.reconfigure(&settings);
+                    supervisor.reconfigure(&settings);
+                    let notify_result = match plan.touches(&["notifications"]) {
+                        true => notifications::reconfigure(
+                            &settings.notifications,
+                            &config.app_name.to_string(),
+                        ),
+                        false => Ok(()),
+                    };
+                    if let Err(err) = notify_result {
+                        log!(LogLevel::Warn, "Notifications unavailable: {}", err);
+                    }
+                    if plan.touches(&["cpu_limit"]) {
+                        cpu_monitor = CpuMonitor::new(&settings.cpu_limit);
+                    }
+                    if plan.touches(&["app_status"]) {
+                        app_status =
+                            AppStatusTracker::new(settings.app_status.path(&settings.project_path));
+                    }
+                    // The maintenance flag stays where it was found at start up
+                    if monitoring && plan.touches(reload::MONITORS) {
+                        pause_monitors().await;
+                        match start_monitors(
+                            &settings.watch_paths(),
+                            settings.interval_seconds,
+                            Duration::from_millis(settings.change_batch_ms),
+                            &settings.polling,
+                        )
+                        .await
+                        {
+                            Ok((monitors, rx)) => {
+                                init_monitors(monitors).await;
+                                event_rx = rx;
+                                if maintenance.is_active() {
+                                    pause_monitors().await;
+                                }
+                            }
+                            Err(err) => {
+                                log!(
+                                    LogLevel::Error,
+                                    "Failed to watch the reloaded paths: {}",
+                                    err
+                                );
+                                state
+                                    .error_log
+                                    .push(ErrorArrayItem::new(Errors::GeneralError, err));
+                                if !maintenance.is_active() {
+                                    resume_monitors().await;
+                                }
+                            }
+                        }
+                    }
+
+                    if !plan.live.is_empty() {
+                        log!(
+                            LogLevel::Info,
+                            "Applied {} from Config.toml",
+                            plan.live.join(", ")
+                        );
+                    }
+                    // A reload respawns the child below anyway
+                    if !plan.child.is_empty() && !reloading {
+                        let reason = RestartReason::new(
+                            RestartKind::Reload,
+                            format!("{} changed", plan.child.join(", ")),
+                        );
+                        match maintenance.is_active() {
+                            true => log!(
+                                LogLevel::Info,
+                                "Not deploying during maintenance, {} applies to the next restart",
+                                plan.child.join(", ")
+                            ),
+                            false => deploy = deploy.or(Some(reason)),
+                        }
+                    }
+                }
+                Ok(None) => (),
+                Err(err) => {
+                    log!(LogLevel::Error, "{}", err);
+                    events::record(Event::ConfigReload {
+                        live: Vec::new(),
+                        child: Vec::new(),
+                        runner: Vec::new(),
+                        error: Some(err.clone()),
+                    });
+                    state
+                        .error_log
+                        .push(ErrorArrayItem::new(Errors::GeneralError, err));
+                }
+            }
+        }
+
+        // Rotated secrets wait for maintenance to end
+        if deploy.is_none() && !maintenance.is_active() && rotated.swap(false, Ordering::Relaxed) {
+            deploy = Some(RestartKind::Secrets.into());
+        }
+
+        if let Some(reason) = deploy {
+            if reason.kind == RestartKind::Changes {
+                restarter.triggered = supervisor.first_change();
+                events::record(Event::Changes {
+                    count: supervisor.pending_changes(),
+                    detail: reason.detail.clone(),
+                });
+            }
+            match restarter.restart_child(&mut state, reason, None).await {
+                RestartOutcome::Restarted => {
+                    // A new build deserves a fresh restart budget
+                    if supervisor.resume() {
+                        save_crash_loop(&state_path, None);
+                    }
+                }
+                RestartOutcome::KeptCurrent => (),
+                RestartOutcome::Failed => {
+                    notifications::flush().await;
+                    return;
+                }
+            }
+
+            supervisor.reset_changes();
+            log!(LogLevel::Debug, "Application status: {}", state.status);
+        }
+
+        if reload.load(Ordering::Relaxed) {
+            log!(LogLevel::Debug, "Reloading");
+            reload.store(false, Ordering::Relaxed);
+            let restore = take_restore(&state_path);
+            if supervisor.resume() {
+                log!(
+                    LogLevel::Info,
+                    "Restart requested, resuming respawns of the child"
+                );
+                save_crash_loop(&state_path, None);
+            }
+            restarter.lifecycle.transition(Phase::Idle, &mut state);
+
+            // reload config file, a restore sticks to what was loaded since
+            // the new config may be what broke things
+            if restore.is_none() {
+                config = get_config();
+
+                // Updating state data
+                state = generate_application_state(&state_path, &config).await;
+                log_level::apply(&mut state, &state_path, config.log_level);
+                restarter.lifecycle.sync(&mut state);
+            }
+
+            let reason = match restore.is_some() {
+                true => RestartKind::Restore,
+                false => RestartKind::Reload,
+            };
+            if restarter
+                .restart_child(&mut state, reason.into(), restore)
+                .await
+                == RestartOutcome::Failed
+            {
+                notifications::flush().await;
+                return;
+            }
+            log!(LogLevel::Debug, "Application status: {}", state.status);
+        }
+
+        if log_level_request.swap(false, Ordering::Relaxed) {
+            let level = log_level::apply(&mut state, &state_path, config.log_level);
+            state::save(&mut state, &state_path, None).await;
+            log!(LogLevel::Info, "Log level set to {}", level);
+        }
+
+        if limits_request.swap(false, Ordering::Relaxed) {
+            apply_limits(&mut settings, &mut state, &state_path, &config_file).await;
+        }
+
+        if let Some(Ok(mut state_log)) = state_log.as_ref().map(|log| log.lock()) {
+            state_log.observe(state_sync::snapshot(&state, app_status.current()));
+        }
+
+        if exit_graceful.load(Ordering::Relaxed) {
+            log!(LogLevel::Debug, "Exiting gracefully");
+            // Nothing held back may be lost to the wind down
+            state::flush(&mut state, &state_path).await;
+            let mut shutdown = Shutdown::new(&settings);
+            systemd::notify("STOPPING=1");
+            // Leave systemd a little headroom over our own timeouts so it doesn't SIGKILL us mid wind down
+            systemd::extend_timeout(shutdown.total() + Duration::from_secs(5));
+
+            shutdown
+                .stage(shutdown::Stage::Monitors, async {
+                    stop_monitors().await;
+                    Ok(())
+                })
+                .await;
+            shutdown
+                .stage(shutdown::Stage::Health, async { Ok(()) })
+                .await;
+
+            let killed = shutdown
+                .stage(shutdown::Stage::Child, async {
+                    if let Some(killed) = child_manager::kill(settings.kill_mode).await {
+                        killed.map_err(|err| err.err_mesg.to_string())?;
+                    }
+                    if let Some(adopted) = GLOBAL_ADOPTED.lock().await.take() {
+                        orphans::kill(
+                            adopted.pid,
+                            Duration::from_secs(settings.shutdown_timeout_seconds),
+                        )
+                        .await?;
+                    }
+                    // What it printed on its way out
+                    drain_output(
+                        &mut restarter.capture,
+                        &mut restarter.log_rules,
+                        &mut state,
+                        &settings,
+                    )
+                    .await;
+                    Ok(())
+                })
+                .await;
+            if !killed
+                && shutdown
+                    .outcomes()
+                    .last()
+                    .is_some_and(|outcome| outcome.timed_out)
+            {
+                log!(
+                    LogLevel::Error,
+                    "We hit the {}s timeout while gracefully shutting down. Consider raising shutdown_timeout_seconds, you might have to run systemctl kill ais_xxx to ensure you start correctly nextime",
+                    settings.shutdown_timeout_seconds
+                );
+            }
+            restarter.lifecycle.transition(Phase::Stopping, &mut state);
+
+            shutdown
+                .stage(shutdown::Stage::Logs, async {
+                    journal::commit().await;
+                    notifications::flush().await;
+                    otel::flush().await;
+                    Ok(())
+                })
+                .await;
+
+            shutdown
+                .stage(shutdown::Stage::State, async {
+                    let mut failures = Vec::new();
+                    let shredded = match settings.env_template.is_some() {
+                        true => shred(Path::new(&settings.env_file_location)),
+                        false => Ok(()),
+                    };
+                    if let Err(err) = shredded {
+                        failures.push(format!("failed to shred the env file: {}", err));
+                    }
+                    artifacts::clean_up();
+                    if settings.reservation.enabled {
+                        let registry = Registry::new(&settings.reservation.registry_dir);
+                        if let Err(err) = registry.release(&config.app_name.to_string()) {
+                            failures.push(format!("failed to release the reservation: {}", err));
+                        }
+                    }
+                    drop(GLOBAL_CLINENT_CONNECTION.lock().await.take());
+                    match failures.is_empty() {
+                        true => Ok(()),
+                        false => Err(failures.join(", ")),
+                    }
+                })
+                .await;
+
+            // The final write, whatever the stages did
+            for outcome in shutdown.outcomes() {
+                if let Err(err) = &outcome.result {
+                    let kind = match outcome.timed_out {
+                        true => Errors::TimedOut,
+                        false => Errors::GeneralError,
+                    };
+                    state.error_log.push(ErrorArrayItem::new(
+                        kind,
+                        format!("Shutting down {}: {}", outcome.stage, err),
+                    ));
+                }
+            }
+            wind_down_state(&mut state, &state_path).await;
+            state_backend::publish_final(&mut state).await;
+            match killed {
+                true => std::process::exit(exit_code),
+                false => std::process::exit(100),
+            }
+        }
+
+        // In json mode every line went out once as it was kept
+        if state.config.debug_mode && log_format() == LogFormat::Text {
+            let log_level = get_log_level();
+            set_log_level(LogLevel::Trace);
+            log!(LogLevel::Trace, "printing std out");
+            for line in merged(&state.stdout, &state.stderr) {
+                dispatch(LogLevel::Debug, line.stream.event(), line.line.to_owned());
+            }
+            set_log_level(log_level);
+        }
+    }
+}
+
+/// Add a check to the metrics history, saving it and looking for a leak
+/// whenever a bucket closes.
+fn record_metrics(
+    history: &SharedMetricsHistory,
+    history_path: &Path,
+    cpu: f32,
+    memory: f64,
+    settings: &AppSpecificConfig,
+) {
+    let mut history = match history.lock() {
+        Ok(history) => history,
+        Err(_) => return,
+    };
+    let now = current_timestamp();
+    if !history.record(now, cpu, memory) {
+        return;
+    }
+    if let Err(err) = history.save(history_path) {
+        log!(
+            LogLevel::Warn,
+            "Failed to save the metrics history to {}: {}",
+            history_path.display(),
+            err
+        );
+    }
+    let leak = settings
+        .metrics_history
+        .leak_warn_mb_per_hour
+        .and_then(|threshold| history.leak(now, threshold));
+    if let Some(trend) = leak {
+        log!(
+            LogLevel::Warn,
+            "Memory of the child grew {:.1} MB per hour over the last {} hours, it may be leaking",
+            trend,
+            settings.metrics_history.window_hours
+        );
+    }
+}
+
+/// Act on the child's average CPU usage crossing `max_cpu_percent`.
+async fn cpu_limit_crossed(change: CpuChange, settings: &AppSpecificConfig, state: &mut AppState) {
+    let limit = &settings.cpu_limit;
+    let average = match change {
+        CpuChange::Exceeded(average) => average,
+        CpuChange::Recovered(average) => {
+            log!(
+                LogLevel::Info,
+                "CPU usage of the child averaged {:.0}% over {}s, back below max_cpu_percent",
+                average,
+                limit.window_seconds
+            );
+            return;
+        }
+    };
+    let max = limit.max_cpu_percent.unwrap_or_default();
+    let message = format!(
+        "CPU usage of the child averaged {:.0}% over {}s, above max_cpu_percent {}",
+        average, limit.window_seconds, max
+    );
+    log!(LogLevel::Warn, "{}", message);
+    state
+        .error_log
+        .push(ErrorArrayItem::new(Errors::GeneralError, message.clone()));
+
+    match limit.action {
+        CpuLimitAction::Warn => (),
+        CpuLimitAction::Notify => notify(EventKind::CpuLimit, message),
+        CpuLimitAction::Throttle => {
+            // A tighter cpu.max that's already configured stays
+            let percent = match settings
+                .cgroup
+                .cpu_max_percent
+                .filter(|percent| *percent > 0)
+            {
+                Some(configured) => configured.min(max.round() as u32),
+                None => max.round() as u32,
+            };
+            let throttled = CgroupConfig {
+                cpu_max_percent: Some(percent),
+                ..settings.cgroup.clone()
+            };
+            match GLOBAL_CGROUP.lock().await.as_ref() {
+                Some(cgroup) => {
+                    match cgroup.apply_limits(&throttled, state.config.max_ram_usage as u64) {
+                        Ok(()) => log!(
+                            LogLevel::Info,
+                            "Throttled the child to {}% of a core with cpu.max",
+                            percent
+                        ),
+                        Err(err) => log!(LogLevel::Error, "Failed to throttle the child: {}", err),
+                    }
+                }
+                None => log!(
+                    LogLevel::Warn,
+                    "Can't throttle the child, it isn't in a cgroup"
+                ),
+            }
+        }
+    }
+}
+
+/// Re-read `[app_specific.cgroup]` and write its limits to the running
+/// child's cgroup. Preset settings are written again as they are.
+async fn apply_limits(
+    settings: &mut AppSpecificConfig,
+    state: &mut AppState,
+    state_path: &PathType,
+    config_file: &ConfigFile,
+) {
+    let reloaded = match config_file.reloadable() {
+        true => specific_config().map(|reloaded| reloaded.cgroup),
+        false => Ok(settings.cgroup.clone()),
+    };
+    let cgroup_config = match reloaded {
+        Ok(cgroup_config) => cgroup_config,
+        Err(err) => {
+            log!(
+                LogLevel::Error,
+                "Not applying limits, Config.toml is invalid: {}",
+                err
+            );
+            return;
+        }
+    };
+    let guard = GLOBAL_CGROUP.lock().await;
+    let cgroup = match guard.as_ref() {
+        Some(cgroup) => cgroup,
+        None => {
+            log!(
+                LogLevel::Warn,
+                "Not applying limits, the child isn't in a cgroup"
+            );
+            return;
+        }
+    };
+    match cgroup.apply_limits(&cgroup_config, state.config.max_ram_usage as u64) {
+        Ok(()) => {
+            log!(
+                LogLevel::Info,
+                "Applied cgroup limits memory_max_mb={:?} cpu_max_percent={:?}",
+                cgroup_config.memory_max_mb,
+                cgroup_config.cpu_max_percent
+            );
+            settings.cgroup = cgroup_config;
+        }
+        Err(err) => {
+            log!(LogLevel::Error, "{}", err);
+            log_error(state, err, state_path).await;
+        }
+    }
+}
+
+/// Load Config.toml again while running, returning the settings to carry on
+/// with and what changed in them, `None` when nothing did.
+fn reload_settings(
+    current: &AppSpecificConfig,
+    port: Option<u16>,
+) -> Result<Option<(AppSpecificConfig, Plan)>, String> {
+    let mut reloaded = specific_config().map_err(|err| {
+        format!(
+            "Keeping the current settings, Config.toml is invalid: {}",
+            err
+        )
+    })?;
+    let problems = validation::validate(&reloaded);
+    if !problems.is_empty() {
+        return Err(format!(
+            "Keeping the current settings. {}",
+            validation::report(&problems)
+        ));
+    }
+    if let Some(port) = port {
+        ports::apply(&mut reloaded, port);
+    }
+
+    let plan = Plan::new(current, &reloaded);
+    if plan.is_empty() {
+        return Ok(None);
+    }
+    for key in &plan.runner {
+        log!(
+            LogLevel::Warn,
+            "app_specific.{} changed, it applies once the runner is restarted",
+            key
+        );
+    }
+    let reloaded = keep_runner_options(current, &reloaded, &plan)?;
+    Ok(Some((reloaded, plan)))
+}
+
+/// Move new stdout/stderr lines of the child, spawned or adopted, into
+/// `state` and the journal, matching them against the log rules. The state
+/// keeps the newest `max_log_lines` / `max_log_bytes` of each.
+async fn drain_output(
+    capture: &mut OutputCapture,
+    log_rules: &mut LogRules,
+    state: &mut AppState,
+    settings: &AppSpecificConfig,
+) {
+    for stream in [Stream::Stdout, Stream::Stderr] {
+        let lines: Vec<CapturedLine> = capture
+            .read_numbered(stream)
+            .into_iter()
+            .map(|(id, line)| CapturedLine {
+                id,
+                timestamp: line_timestamp(&line, current_timestamp(), settings),
+                line,
+            })
+            .collect();
+        keep_output(stream, lines, log_rules, state, settings).await;
+    }
+    journal::commit().await;
+    if let Err(err) = capture.release() {
+        log!(
+            LogLevel::Debug,
+            "Failed to shrink the capture files: {}",
+            err
+        );
+    }
+}
+
+/// Match the captured `lines` of `stream` against the log rules, ship and
+/// journal them with their IDs and keep them in `state`.
+async fn keep_output(
+    stream: Stream,
+    lines: Vec<CapturedLine>,
+    log_rules: &mut LogRules,
+    state: &mut AppState,
+    settings: &AppSpecificConfig,
+) {
+    if lines.is_empty() {
+        return;
+    }
+
+    let now = Instant::now().into_std();
+    for captured in &lines {
+        let line = &captured.line;
+        match log_rules.check(line, now) {
+            Some(LogAction::Warning) => log!(LogLevel::Warn, "Log rule matched: {}", line),
+            Some(LogAction::Restart) => {
+                log!(LogLevel::Warn, "Log rule asks for a restart: {}", line)
+            }
+            Some(LogAction::Ready) | None => (),
+        }
+    }
+
+    for captured in &lines {
+        emit_output(stream.event(), captured.timestamp, &captured.line);
+    }
+    log_shipping::ship(stream.event(), &lines);
+    journal::record(
+        stream.event(),
+        lines.iter().map(|captured| {
+            (
+                Some(captured.id),
+                captured.timestamp,
+                captured.line.as_str(),
+            )
+        }),
+    )
+    .await;
+
+    let keyed = lines
+        .into_iter()
+        .map(|captured| (captured.timestamp, captured.line));
+    let target = match stream {
+        Stream::Stdout => &mut state.stdout,
+        Stream::Stderr => &mut state.stderr,
+    };
+    match settings.timestamp_source {
+        TimestampSource::Capture => target.extend(keyed),
+        TimestampSource::Child => append_sorted(target, keyed),
+    }
+    retain_newest(target, settings.max_log_lines, settings.max_log_bytes);
+}
+
+/// Why the child should be restarted, from its probes or a missed heartbeat.
+async fn unhealthy(probes: &mut ProbeTracker) -> Option<String> {
+    if let ProbeOutcome::Failed(reason) = probes.poll().await {
+        return Some(reason);
+    }
+    GLOBAL_HEARTBEAT
+        .get()
+        .and_then(|heartbeat| heartbeat.overdue())
+}
+
+/// Resolves when a connection asks for the stopped child, never without
+/// `proxy.idle_minutes`.
+async fn wake_requested() {
+    match GLOBAL_PROXY.get() {
+        Some(proxy) => proxy.wake_requested().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Take a pending `restore-last-known-good` request, giving the launch to
+/// respawn.
+fn take_restore(state_path: &PathType) -> Option<ChildLaunch> {
+    let runner_state = RunnerState::load(state_path);
+    if !runner_state.restore_requested {
+        return None;
+    }
+    update_runner_state(state_path, |runner_state| {
+        runner_state.restore_requested = false
+    });
+
+    match runner_state.last_known_good {
+        Some((timestamp, launch)) => {
+            log!(
+                LogLevel::Info,
+                "Restoring the child that was running at {}",
+                timestamp
+            );
+            Some(launch)
+        }
+        None => {
+            log!(
+                LogLevel::Warn,
+                "Restore requested but there is no last known good child, reloading instead"
+            );
+            None
+        }
+    }
+}
+
+/// Store or clear the crash loop report in the runner state.
+fn save_crash_loop(state_path: &PathType, report: Option<CrashLoopReport>) {
+    update_runner_state(state_path, |runner_state| runner_state.crash_loop = report);
+}
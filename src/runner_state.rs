@@ -0,0 +1,225 @@
+//! Runner specific state persisted next to the [`AppState`] file.
+//!
+//! [`AppState`](artisan_middleware::state_persistence::AppState) is shared
+//! with the rest of the artisan tooling and can't grow runner specific
+//! fields, so those live in a small JSON sidecar at `<state path>.runner`
+//! which the CLI reads alongside the main state.
+
+use artisan_middleware::dusa_collection_utils::core::{
+    logger::LogLevel, types::pathtype::PathType,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, io, path::PathBuf, time::Duration};
+
+use crate::app_status::AppStatus;
+use crate::build_steps::StepRun;
+use crate::child::{ChildExit, ChildLaunch};
+use crate::crash_loop::CrashLoopReport;
+use crate::deploy_trace::StageSpan;
+use crate::host::HostCapabilities;
+use crate::log;
+use crate::maintenance::MaintenanceInfo;
+use crate::reset::ResetScope;
+use crate::restart::{RestartKind, RestartReason};
+
+/// Restarts kept in [`RunnerState::restart_history`].
+pub const HISTORY_LIMIT: usize = 50;
+
+/// A single deploy of the child.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RestartRecord {
+    pub timestamp: u64,
+    /// What triggered it, e.g. `5 file changes`.
+    pub reason: String,
+    /// `None` for restarts recorded before kinds were.
+    #[serde(default)]
+    pub kind: Option<RestartKind>,
+    /// Operator supplied note, see the `annotate` subcommand.
+    #[serde(default)]
+    pub note: Option<String>,
+    /// Exit code of the child it replaced, when that child exited on its
+    /// own.
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+    /// How long the build step took, if one ran.
+    #[serde(default)]
+    pub build_ms: Option<u64>,
+    /// Whether the new child became ready, `None` while the restart runs.
+    #[serde(default)]
+    pub success: Option<bool>,
+    /// Timeline of the restart, see [`crate::deploy_trace`].
+    #[serde(default)]
+    pub stages: Vec<StageSpan>,
+    /// How the named build steps went, see [`crate::build_steps`].
+    #[serde(default)]
+    pub steps: Vec<StepRun>,
+}
+
+impl RestartRecord {
+    /// `ok`, `failed` or `pending`.
+    pub fn outcome(&self) -> &'static str {
+        match self.success {
+            Some(true) => "ok",
+            Some(false) => "failed",
+            None => "pending",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct RunnerState {
+    /// Capabilities detected when the runner started.
+    #[serde(default)]
+    pub host: Option<HostCapabilities>,
+    /// Most recent restarts, oldest first.
+    #[serde(default)]
+    pub restart_history: Vec<RestartRecord>,
+    /// Note waiting for the next restart.
+    #[serde(default)]
+    pub pending_note: Option<String>,
+    /// Set while respawning is suspended because the child kept crashing.
+    #[serde(default)]
+    pub crash_loop: Option<CrashLoopReport>,
+    /// How the last child ended, with the time it was noticed.
+    #[serde(default)]
+    pub last_exit: Option<(u64, ChildExit)>,
+    /// Launch of the last child that became ready, with the time it did.
+    #[serde(default)]
+    pub last_known_good: Option<(u64, ChildLaunch)>,
+    /// Set by `restore-last-known-good`, the runner respawns
+    /// `last_known_good` on its next reload or start.
+    #[serde(default)]
+    pub restore_requested: bool,
+    /// Set while maintenance mode is on.
+    #[serde(default)]
+    pub maintenance: Option<MaintenanceInfo>,
+    /// Status fields last reported by the child.
+    #[serde(default)]
+    pub app_status: AppStatus,
+    /// Port handed to the child, see [`crate::ports`].
+    #[serde(default)]
+    pub port: Option<u16>,
+    /// Log level set with `log-level`, overriding the config's, see
+    /// [`crate::log_level`].
+    #[serde(default)]
+    pub log_level: Option<String>,
+    /// Set by `reset` while the runner is up, see [`crate::reset`].
+    #[serde(default)]
+    pub pending_reset: Option<ResetScope>,
+    /// Hash of the inputs of each cached build step's last successful run,
+    /// see [`crate::build_cache`].
+    #[serde(default)]
+    pub build_cache: BTreeMap<String, String>,
+    /// Set by `restart --no-cache`, the next build runs every step.
+    #[serde(default)]
+    pub no_cache_requested: bool,
+}
+
+impl RunnerState {
+    /// Sidecar location for the state file at `state_path`.
+    pub fn path(state_path: &PathType) -> PathBuf {
+        PathBuf::from(format!("{}.runner", state_path))
+    }
+
+    /// Load the sidecar, an unreadable or missing file gives the default.
+    pub fn load(state_path: &PathType) -> Self {
+        fs::read_to_string(Self::path(state_path))
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    /// Write the sidecar atomically.
+    pub fn save(&self, state_path: &PathType) -> io::Result<()> {
+        let path = Self::path(state_path);
+        let mut temp = path.clone().into_os_string();
+        temp.push(".tmp");
+
+        fs::write(&temp, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(&temp, &path)
+    }
+}
+
+/// Append a restart to the history kept next to `state_path`, attaching the
+/// pending note if there is one.
+///
+/// The sidecar is re-read first so notes written by the CLI in the meantime
+/// aren't lost.
+pub fn record_restart(
+    state_path: &PathType,
+    timestamp: u64,
+    reason: &RestartReason,
+) -> io::Result<RestartRecord> {
+    let mut runner_state = RunnerState::load(state_path);
+    let record = RestartRecord {
+        timestamp,
+        reason: reason.detail.clone(),
+        kind: Some(reason.kind),
+        note: runner_state.pending_note.take(),
+        exit_code: reason.exit.map(|exit| exit.code()),
+        build_ms: None,
+        success: None,
+        stages: Vec::new(),
+        steps: Vec::new(),
+    };
+
+    runner_state.restart_history.push(record.clone());
+    let overflow = runner_state
+        .restart_history
+        .len()
+        .saturating_sub(HISTORY_LIMIT);
+    runner_state.restart_history.drain(..overflow);
+
+    runner_state.save(state_path)?;
+    Ok(record)
+}
+
+/// Fill in how the restart recorded at `timestamp` went, with the stages
+/// it went through.
+///
+/// Records that already dropped out of the history are left alone.
+pub fn finish_restart(
+    state_path: &PathType,
+    timestamp: u64,
+    build_time: Option<Duration>,
+    success: bool,
+    stages: &[StageSpan],
+) -> io::Result<()> {
+    let mut runner_state = RunnerState::load(state_path);
+    let record = runner_state
+        .restart_history
+        .iter_mut()
+        .rev()
+        .find(|record| record.timestamp == timestamp);
+    if let Some(record) = record {
+        record.build_ms = build_time.map(|time| time.as_millis() as u64);
+        record.success = Some(success);
+        record.stages = stages.to_vec();
+        runner_state.save(state_path)?;
+    }
+    Ok(())
+}
+
+/// Keep how the build steps of the restart recorded at `timestamp` went.
+pub fn record_steps(state_path: &PathType, timestamp: u64, steps: &[StepRun]) -> io::Result<()> {
+    let mut runner_state = RunnerState::load(state_path);
+    let record = runner_state
+        .restart_history
+        .iter_mut()
+        .rev()
+        .find(|record| record.timestamp == timestamp);
+    if let Some(record) = record {
+        record.steps = steps.to_vec();
+        runner_state.save(state_path)?;
+    }
+    Ok(())
+}
+
+/// Load, modify and save the runner state.
+pub fn update_runner_state(state_path: &PathType, update: impl FnOnce(&mut RunnerState)) {
+    let mut runner_state = RunnerState::load(state_path);
+    update(&mut runner_state);
+    if let Err(err) = runner_state.save(state_path) {
+        log!(LogLevel::Warn, "Failed to save runner state: {}", err);
+    }
+}
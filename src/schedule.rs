@@ -0,0 +1,223 @@
+//! Scheduled restarts.
+//!
+//! Some workloads want a nightly restart no matter whether anything changed.
+//! `restart_schedule` takes a five field cron expression (`minute hour
+//! day-of-month month day-of-week`, evaluated in UTC) and restarts the child
+//! through the same path a file change takes whenever it matches.
+//!
+//! Fields accept `*`, single values, ranges (`1-5`), steps (`*/15`, `0-30/10`)
+//! and comma separated lists of those. `@hourly`, `@daily`, `@midnight`,
+//! `@weekly` and `@monthly` are understood as well. As in Vixie cron a day
+//! matches if either day-of-month or day-of-week matches when both are
+//! restricted.
+
+use artisan_middleware::dusa_collection_utils;
+use dusa_collection_utils::core::logger::LogLevel;
+use std::{
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::mpsc;
+
+use crate::log;
+
+/// Days searched for the next match, enough to reach the next February 29th.
+const SEARCH_DAYS: u64 = 366 * 4 + 1;
+
+/// A parsed cron expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+/// Parse one field into a bitset of the allowed values.
+fn parse_field(field: &str, min: u64, max: u64) -> Result<u64, String> {
+    let mut set = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step: u64 = step
+                    .parse()
+                    .map_err(|_| format!("invalid step in {:?}", part))?;
+                if step == 0 {
+                    return Err(format!("step can't be 0 in {:?}", part));
+                }
+                (range, step)
+            }
+            None => (part, 1),
+        };
+
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (parse_value(start, part)?, parse_value(end, part)?),
+                None => {
+                    let value = parse_value(range, part)?;
+                    // `5/10` means from 5 to the end in steps of 10
+                    match step > 1 {
+                        true => (value, max),
+                        false => (value, value),
+                    }
+                }
+            },
+        };
+
+        if start < min || end > max || start > end {
+            return Err(format!("{:?} is outside of {}-{}", part, min, max));
+        }
+        for value in (start..=end).step_by(step as usize) {
+            set |= 1 << value;
+        }
+    }
+    Ok(set)
+}
+
+fn parse_value(value: &str, part: &str) -> Result<u64, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value in {:?}", part))
+}
+
+impl FromStr for CronSchedule {
+    type Err = String;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let expression = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            other => other,
+        };
+
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(format!(
+                "expected 5 fields (minute hour day month weekday), got {}",
+                fields.len()
+            ));
+        };
+
+        // Sunday may be written as 7
+        let mut weekday_set = parse_field(weekdays, 0, 7)?;
+        if weekday_set & (1 << 7) != 0 {
+            weekday_set = (weekday_set | 1) & !(1 << 7);
+        }
+
+        Ok(Self {
+            minutes: parse_field(minutes, 0, 59)?,
+            hours: parse_field(hours, 0, 23)?,
+            days: parse_field(days, 1, 31)?,
+            months: parse_field(months, 1, 12)?,
+            weekdays: weekday_set,
+            days_restricted: days != "*",
+            weekdays_restricted: weekdays != "*",
+        })
+    }
+}
+
+impl CronSchedule {
+    fn matches_day(&self, day: u64, month: u64, weekday: u64) -> bool {
+        if self.months & (1 << month) == 0 {
+            return false;
+        }
+        let day_match = self.days & (1 << day) != 0;
+        let weekday_match = self.weekdays & (1 << weekday) != 0;
+        match (self.days_restricted, self.weekdays_restricted) {
+            (true, true) => day_match || weekday_match,
+            (true, false) => day_match,
+            (false, true) => weekday_match,
+            (false, false) => true,
+        }
+    }
+
+    /// First matching minute strictly after `after` (unix seconds).
+    pub fn next_after(&self, after: u64) -> Option<u64> {
+        let start = (after / 60 + 1) * 60;
+        let first_day = start / 86_400;
+
+        for day_index in first_day..first_day + SEARCH_DAYS {
+            let (_, month, day) = civil_from_days(day_index);
+            // 1970-01-01 was a Thursday
+            let weekday = (day_index + 4) % 7;
+            if !self.matches_day(day, month, weekday) {
+                continue;
+            }
+
+            let day_start = day_index * 86_400;
+            let first_minute = match day_index == first_day {
+                true => (start - day_start) / 60,
+                false => 0,
+            };
+            for minute_of_day in first_minute..1440 {
+                let (hour, minute) = (minute_of_day / 60, minute_of_day % 60);
+                if self.hours & (1 << hour) != 0 && self.minutes & (1 << minute) != 0 {
+                    return Some(day_start + minute_of_day * 60);
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Civil date (year, month, day) of the `days`th day after 1970-01-01.
+///
+/// Howard Hinnant's `civil_from_days`.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+/// Send on `trigger` every time `schedule` matches.
+///
+/// The wait is recomputed from the wall clock after every wake up, so a
+/// clock that was stepped doesn't push the schedule off.
+pub fn watch_schedule(schedule: CronSchedule, trigger: mpsc::Sender<u64>) {
+    tokio::spawn(async move {
+        loop {
+            let current = now();
+            let next = match schedule.next_after(current) {
+                Some(next) => next,
+                None => {
+                    log!(
+                        LogLevel::Warn,
+                        "restart_schedule never matches, scheduled restarts are off"
+                    );
+                    return;
+                }
+            };
+
+            // Wake up at least hourly to re-check the clock
+            let wait = Duration::from_secs((next - current).min(3600));
+            tokio::time::sleep(wait).await;
+            if now() < next {
+                continue;
+            }
+
+            if trigger.send(next).await.is_err() {
+                return;
+            }
+        }
+    });
+}
@@ -4,7 +4,16 @@ pub(self) mod secret_service {
 }
 
 // Exporting stuff
-mod secret_handler;
+pub mod reload;
+pub mod retry;
+mod rotation;
 mod secret_functions;
-pub use secret_functions::SecretQuery;
-pub use secret_handler::SecretClient;
\ No newline at end of file
+mod secret_handler;
+pub mod template;
+mod tls;
+pub use reload::SecretReloadConfig;
+pub use retry::{RetryConfig, with_retry};
+pub use rotation::{RotationAction, watch_rotations};
+pub use secret_functions::{AllSecrets, SecretQuery, write_env_file};
+pub use secret_handler::SecretClient;
+pub use tls::{SecretTlsConfig, https_endpoint};
@@ -0,0 +1,88 @@
+//! Hot reloading rotated secrets instead of restarting the child.
+//!
+//! With `secret_rotation = "restart_child"` every rotation used to cost a
+//! restart. Children that can re-read their configuration get a
+//! `[app_specific.secret_reload]` table: rotated keys whose policy is
+//! `hot_reload` are written to `file` and the child is sent `signal`, and
+//! only a change to a `restart` key still restarts it.
+
+use nix::sys::signal::Signal;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, str::FromStr};
+
+use crate::secrets::secret_functions::AllSecrets;
+
+/// What a change of a key requires.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyPolicy {
+    #[default]
+    Restart,
+    HotReload,
+}
+
+/// `[app_specific.secret_reload]`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SecretReloadConfig {
+    /// Receives the rotated `hot_reload` keys as `KEY=value` lines.
+    pub file: String,
+    /// Sent to the child once `file` was written.
+    #[serde(default = "default_reload_signal")]
+    pub signal: String,
+    /// Policy of keys missing from `policies`.
+    #[serde(default)]
+    pub default_policy: KeyPolicy,
+    #[serde(default)]
+    pub policies: BTreeMap<String, KeyPolicy>,
+}
+
+fn default_reload_signal() -> String {
+    String::from("SIGHUP")
+}
+
+impl SecretReloadConfig {
+    /// The configured signal, `HUP` and `SIGHUP` are both accepted.
+    pub fn signal(&self) -> Result<Signal, String> {
+        let name = self.signal.to_ascii_uppercase();
+        let name = match name.starts_with("SIG") {
+            true => name,
+            false => format!("SIG{}", name),
+        };
+        Signal::from_str(&name)
+            .map_err(|_| format!("secret_reload signal {:?} is unknown", self.signal))
+    }
+
+    pub fn policy(&self, key: &str) -> KeyPolicy {
+        self.policies
+            .get(key)
+            .copied()
+            .unwrap_or(self.default_policy)
+    }
+
+    /// What the rotation of `changed` requires, `None` when nothing changed.
+    pub fn plan(&self, changed: &[String]) -> Option<KeyPolicy> {
+        changed
+            .iter()
+            .map(|key| self.policy(key))
+            .max_by_key(|policy| *policy == KeyPolicy::Restart)
+    }
+}
+
+/// Keys added, removed or changed between `old` and `new`, sorted.
+pub fn changed_keys(old: &AllSecrets, new: &AllSecrets) -> Vec<String> {
+    let old: BTreeMap<&String, &Vec<u8>> = old.iter().map(|(key, value)| (key, value)).collect();
+    let new: BTreeMap<&String, &Vec<u8>> = new.iter().map(|(key, value)| (key, value)).collect();
+
+    let mut changed: Vec<String> = new
+        .iter()
+        .filter(|(key, value)| old.get(*key) != Some(*value))
+        .map(|(key, _)| (*key).clone())
+        .collect();
+    changed.extend(
+        old.keys()
+            .filter(|key| !new.contains_key(*key))
+            .map(|key| (*key).clone()),
+    );
+    changed.sort();
+    changed
+}
@@ -0,0 +1,133 @@
+//! Retrying secret server calls through transient outages.
+//!
+//! A secret server that was briefly unreachable at start up used to take
+//! the runner down with it. Connection failures are now retried with
+//! exponential backoff and jitter, and every failed attempt is handed back
+//! so it ends up in the state's error log.
+
+use artisan_middleware::dusa_collection_utils;
+use dusa_collection_utils::core::errors::{ErrorArrayItem, Errors};
+use dusa_collection_utils::core::logger::LogLevel;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::{future::Future, time::Duration};
+
+use crate::log;
+
+/// `[app_specific.secret_retry]`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RetryConfig {
+    /// Attempts in total, including the first one.
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    /// Backoff before the second attempt, doubled for every further one.
+    #[serde(default = "default_initial_delay_ms")]
+    pub initial_delay_ms: u64,
+    /// Upper bound of the backoff.
+    #[serde(default = "default_max_delay_ms")]
+    pub max_delay_ms: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_max_attempts(),
+            initial_delay_ms: default_initial_delay_ms(),
+            max_delay_ms: default_max_delay_ms(),
+        }
+    }
+}
+
+fn default_max_attempts() -> u32 {
+    5
+}
+
+fn default_initial_delay_ms() -> u64 {
+    500
+}
+
+fn default_max_delay_ms() -> u64 {
+    30_000
+}
+
+impl RetryConfig {
+    /// Backoff after the failed `attempt` (starting at 1).
+    ///
+    /// Half of the exponential delay is fixed and the other half scaled by
+    /// `jitter` (`0.0..1.0`), so runners restarted together don't hammer a
+    /// recovering server in lockstep.
+    pub fn backoff(&self, attempt: u32, jitter: f64) -> Duration {
+        let exponential = self
+            .initial_delay_ms
+            .saturating_mul(1u64 << attempt.saturating_sub(1).min(32));
+        let capped = exponential.min(self.max_delay_ms);
+        let half = capped / 2;
+        Duration::from_millis(half + (half as f64 * jitter.clamp(0.0, 1.0)) as u64)
+    }
+}
+
+/// Run `operation` until it succeeds, fails with something other than a
+/// connection error, or `max_attempts` is used up.
+///
+/// Every failed attempt is appended to `history`.
+pub async fn with_retry<T, F, Fut>(
+    config: &RetryConfig,
+    what: &str,
+    history: &mut Vec<ErrorArrayItem>,
+    operation: F,
+) -> Result<T, ErrorArrayItem>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, ErrorArrayItem>>,
+{
+    let jitter = || rand::thread_rng().r#gen();
+    with_retry_jitter(config, what, history, jitter, operation).await
+}
+
+/// [`with_retry`] with the backoff jitter taken from `jitter`, so
+/// simulations get the same delays every run.
+pub async fn with_retry_jitter<T, F, Fut>(
+    config: &RetryConfig,
+    what: &str,
+    history: &mut Vec<ErrorArrayItem>,
+    mut jitter: impl FnMut() -> f64,
+    mut operation: F,
+) -> Result<T, ErrorArrayItem>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, ErrorArrayItem>>,
+{
+    let max_attempts = config.max_attempts.max(1);
+    let mut attempt = 1;
+    loop {
+        let err = match operation().await {
+            Ok(value) => return Ok(value),
+            Err(err) => err,
+        };
+
+        if err.err_type != Errors::ConnectionError || attempt >= max_attempts {
+            return Err(err);
+        }
+
+        let delay = config.backoff(attempt, jitter());
+        log!(
+            LogLevel::Warn,
+            "Attempt {}/{} to {} failed, retrying in {}ms: {}",
+            attempt,
+            max_attempts,
+            what,
+            delay.as_millis(),
+            err
+        );
+        history.push(ErrorArrayItem::new(
+            Errors::ConnectionError,
+            format!(
+                "Attempt {}/{} to {} failed: {}",
+                attempt, max_attempts, what, err.err_mesg
+            ),
+        ));
+
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
@@ -0,0 +1,241 @@
+//! Reacting to secrets being rotated on the secret server.
+//!
+//! The env file used to only be written at start up, so a rotated secret
+//! reached the child on the next unrelated restart at best. With
+//! `secret_rotation` set the runner subscribes to the environment's secret
+//! version and, on a change, rewrites the env file and optionally restarts
+//! the child, once maintenance mode is off, or hot reloads it, see
+//! [`crate::secrets::reload`].
+
+use artisan_middleware::dusa_collection_utils;
+use dusa_collection_utils::core::logger::LogLevel;
+use nix::{sys::signal::kill, unistd::Pid};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::Path,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use crate::child_manager;
+use crate::config::AppSpecificConfig;
+use crate::log;
+use crate::secrets::reload::{KeyPolicy, SecretReloadConfig, changed_keys};
+use crate::secrets::secret_functions::{AllSecrets, read_env_file};
+use crate::secrets::template::{render_env_file, template_path};
+use crate::secrets::{SecretClient, SecretQuery, write_env_file};
+
+/// Wait before subscribing again after the stream ended or failed.
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(10);
+
+/// What to do once the secrets of the environment changed.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RotationAction {
+    /// Rewrite the env file and restart the child.
+    RestartChild,
+    /// Only rewrite the env file, for children that re-read it themselves.
+    RewriteEnvFile,
+    /// Log the new version and do nothing else.
+    Ignore,
+}
+
+/// Subscribe to secret rotations in the background.
+///
+/// `rotated` is set when the child should be restarted, the main loop
+/// picks the restart up from there.
+pub fn watch_rotations(
+    query: SecretQuery,
+    client: SecretClient,
+    action: RotationAction,
+    settings: AppSpecificConfig,
+    rotated: Arc<AtomicBool>,
+) {
+    tokio::spawn(async move {
+        // The server leads with the current version, which is what was
+        // just written to the env file
+        let mut known_version: Option<i64> = None;
+
+        loop {
+            let mut stream = match query.watch_versions(client.clone()).await {
+                Ok(stream) => stream,
+                Err(status) if status.code() == tonic::Code::Unimplemented => {
+                    log!(
+                        LogLevel::Warn,
+                        "The secret server doesn't support rotation events, secret_rotation is ignored"
+                    );
+                    return;
+                }
+                Err(status) => {
+                    log!(
+                        LogLevel::Warn,
+                        "Failed to watch secret versions: {}",
+                        status.message()
+                    );
+                    tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+                    continue;
+                }
+            };
+
+            loop {
+                let event = match stream.message().await {
+                    Ok(Some(event)) => event,
+                    Ok(None) => break,
+                    Err(status) => {
+                        log!(
+                            LogLevel::Warn,
+                            "Secret version stream failed: {}",
+                            status.message()
+                        );
+                        break;
+                    }
+                };
+
+                let previous = known_version.replace(event.version);
+                if previous.is_none_or(|version| version == event.version) {
+                    continue;
+                }
+
+                log!(
+                    LogLevel::Info,
+                    "Secrets of {} rotated to version {}",
+                    event.environment_id,
+                    event.version
+                );
+                rotate(&query, &client, action, &settings, &rotated).await;
+            }
+
+            tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+        }
+    });
+}
+
+async fn rotate(
+    query: &SecretQuery,
+    client: &SecretClient,
+    action: RotationAction,
+    settings: &AppSpecificConfig,
+    rotated: &Arc<AtomicBool>,
+) {
+    if action == RotationAction::Ignore {
+        return;
+    }
+
+    let env_file = Path::new(&settings.env_file_location);
+    let previous = read_env_file(env_file).unwrap_or_default();
+
+    // A template only takes the secrets it names, and renders them under its
+    // own variable names, which is what the child sees
+    let secrets = if template_path(settings).is_some() {
+        if let Err(err) = render_env_file(settings).await {
+            log!(
+                LogLevel::Error,
+                "Failed to render the env template with rotated secrets: {}",
+                err
+            );
+            return;
+        }
+        read_env_file(env_file).unwrap_or_default()
+    } else {
+        let fetched = match settings.only_required_secrets {
+            true => {
+                query
+                    .get_required(client.clone(), &settings.required_secrets)
+                    .await
+            }
+            false => query.get_all(client.clone()).await,
+        };
+
+        let secrets = match fetched {
+            Ok(secrets) => secrets,
+            Err(err) => {
+                log!(
+                    LogLevel::Error,
+                    "Failed to fetch rotated secrets, keeping the current env file: {}",
+                    err
+                );
+                return;
+            }
+        };
+
+        if let Err(err) = write_env_file(env_file, &secrets) {
+            log!(LogLevel::Error, "Failed to rewrite env file: {}", err);
+            return;
+        }
+        secrets
+    };
+    log!(LogLevel::Debug, "Rewrote env file with rotated secrets");
+
+    if action != RotationAction::RestartChild {
+        return;
+    }
+
+    let reload_config = match &settings.secret_reload {
+        Some(reload_config) => reload_config,
+        None => {
+            log!(
+                LogLevel::Info,
+                "Restarting child to pick up rotated secrets"
+            );
+            rotated.store(true, Ordering::Relaxed);
+            return;
+        }
+    };
+
+    let changed = changed_keys(&previous, &secrets);
+    match reload_config.plan(&changed) {
+        None => log!(LogLevel::Debug, "Rotation didn't change any value"),
+        Some(KeyPolicy::Restart) => {
+            log!(
+                LogLevel::Info,
+                "Restarting child, rotated secrets {} need a restart",
+                changed.join(", ")
+            );
+            rotated.store(true, Ordering::Relaxed);
+        }
+        Some(KeyPolicy::HotReload) => {
+            if let Err(err) = hot_reload(reload_config, &secrets, &changed).await {
+                log!(
+                    LogLevel::Error,
+                    "Hot reload failed, restarting child instead: {}",
+                    err
+                );
+                rotated.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// Write the `changed` keys to the reload file and signal the child.
+async fn hot_reload(
+    config: &SecretReloadConfig,
+    secrets: &AllSecrets,
+    changed: &[String],
+) -> Result<(), String> {
+    let signal = config.signal()?;
+    let rotated: AllSecrets = secrets
+        .iter()
+        .filter(|(key, _)| changed.contains(key))
+        .cloned()
+        .collect();
+    write_env_file(Path::new(&config.file), &rotated)
+        .map_err(|err| format!("Failed to write {}: {}", config.file, err))?;
+
+    let pid = match child_manager::pid().await {
+        Some(pid) => pid,
+        None => return Err(String::from("No child is running")),
+    };
+    kill(Pid::from_raw(pid as i32), signal)
+        .map_err(|err| format!("Failed to send {} to {}: {}", signal, pid, err))?;
+
+    log!(
+        LogLevel::Info,
+        "Hot reloaded rotated secrets {} with {}",
+        changed.join(", "),
+        signal
+    );
+    Ok(())
+}
@@ -1,14 +1,24 @@
 use crate::secrets::{
     secret_handler::SecretClient,
-    secret_service::{GetAllSecretsRequest, KeyValuePair},
+    secret_service::{
+        CreateSecretRequest, GetAllSecretsRequest, GetSecretRequest, KeyValuePair,
+        SecretVersionEvent, UpdateSecretRequest, WatchSecretVersionRequest,
+    },
 };
 use artisan_middleware::dusa_collection_utils::core::errors::{ErrorArrayItem, Errors};
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::Path,
+};
+use tonic::codec::Streaming;
 
 #[derive(Clone, Debug)]
 pub struct SecretQuery {
-    pub (crate) runner_id: String,
-    pub (crate) enviornment_id: String,
-    pub (crate) version: i64,
+    pub(crate) runner_id: String,
+    pub(crate) enviornment_id: String,
+    pub(crate) version: i64,
 }
 
 pub type AllSecrets = Vec<(String, Vec<u8>)>;
@@ -47,7 +57,148 @@ impl SecretQuery {
         }
     }
 
-    // pub fn get_val(&self, _val: String) {
-    //     todo!()
-    // }
+    /// Fetch a single key, `Ok(None)` when the server doesn't have it.
+    pub async fn get_val(
+        &self,
+        mut client: SecretClient,
+        key: &str,
+    ) -> Result<Option<Vec<u8>>, ErrorArrayItem> {
+        let request: GetSecretRequest = GetSecretRequest {
+            runner_id: self.runner_id.clone(),
+            environment_id: self.enviornment_id.clone(),
+            secret_key: key.to_owned(),
+            version: self.version,
+            actor: self.runner_id.clone(),
+        };
+
+        match client.get_secret(request).await {
+            Ok(data) => Ok(Some(data.value)),
+            Err(err) if err.code() == tonic::Code::NotFound => Ok(None),
+            Err(err) => Err(ErrorArrayItem::new(Errors::ConnectionError, err.message())),
+        }
+    }
+
+    /// Fetch every key in `keys` individually.
+    ///
+    /// Fails listing all of the keys the server doesn't have, rather than
+    /// stopping at the first one.
+    pub async fn get_required(
+        &self,
+        client: SecretClient,
+        keys: &[String],
+    ) -> Result<AllSecrets, ErrorArrayItem> {
+        let mut result: AllSecrets = Vec::new();
+        let mut missing: Vec<&str> = Vec::new();
+
+        for key in keys {
+            match self.get_val(client.clone(), key).await? {
+                Some(value) => result.push((key.clone(), value)),
+                None => missing.push(key),
+            }
+        }
+
+        if !missing.is_empty() {
+            return Err(ErrorArrayItem::new(
+                Errors::GeneralError,
+                format!("Missing required secrets: {}", missing.join(", ")),
+            ));
+        }
+        Ok(result)
+    }
+
+    /// Store `value` under `key`, creating the secret if it doesn't exist yet.
+    pub async fn put_val(
+        &self,
+        mut client: SecretClient,
+        key: &str,
+        value: &str,
+    ) -> Result<(), ErrorArrayItem> {
+        let request: UpdateSecretRequest = UpdateSecretRequest {
+            runner_id: self.runner_id.clone(),
+            environment_id: self.enviornment_id.clone(),
+            secret_key: key.to_owned(),
+            new_value: value.to_owned(),
+            actor: self.runner_id.clone(),
+        };
+
+        let result = match client.update_secret(request).await {
+            Err(err) if err.code() == tonic::Code::NotFound => {
+                let request: CreateSecretRequest = CreateSecretRequest {
+                    runner_id: self.runner_id.clone(),
+                    environment_id: self.enviornment_id.clone(),
+                    secret_key: key.to_owned(),
+                    value: value.to_owned(),
+                    actor: self.runner_id.clone(),
+                };
+                client.create_secret(request).await
+            }
+            result => result,
+        };
+
+        match result {
+            Ok(response) if response.success => Ok(()),
+            Ok(_) => Err(ErrorArrayItem::new(
+                Errors::GeneralError,
+                format!("The secret server refused to store {}", key),
+            )),
+            Err(err) => Err(ErrorArrayItem::new(Errors::ConnectionError, err.message())),
+        }
+    }
+
+    /// Subscribe to version changes of the environment's secrets.
+    pub async fn watch_versions(
+        &self,
+        mut client: SecretClient,
+    ) -> Result<Streaming<SecretVersionEvent>, tonic::Status> {
+        let request: WatchSecretVersionRequest = WatchSecretVersionRequest {
+            runner_id: self.runner_id.clone(),
+            environment_id: self.enviornment_id.clone(),
+        };
+
+        client.watch_secret_version(request).await
+    }
+}
+
+/// Write `secrets` as `KEY=value` lines, see [`write_private`].
+pub fn write_env_file(path: &Path, secrets: &AllSecrets) -> io::Result<()> {
+    let mut content = String::new();
+    for (key, value) in secrets {
+        content.push_str(&format!("{}={}\n", key, String::from_utf8_lossy(value)));
+    }
+    write_private(path, content.as_bytes())
+}
+
+/// Write `content` to `path`, readable by the owner only.
+///
+/// The file is written next to `path` and renamed over it, so a child
+/// reading it during a rotation never sees half of it.
+pub fn write_private(path: &Path, content: &[u8]) -> io::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+
+    // A leftover from an interrupted write may have other permissions
+    let _ = fs::remove_file(&temp);
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&temp)?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    fs::rename(&temp, path)
+}
+
+/// Read back a file written by [`write_env_file`], a missing file is empty.
+pub fn read_env_file(path: &Path) -> io::Result<AllSecrets> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+
+    Ok(content
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.to_owned(), value.as_bytes().to_vec()))
+        .collect())
 }
@@ -1,9 +1,15 @@
+use crate::log;
 use crate::secrets::secret_service::{self, secret_service_client::SecretServiceClient};
-use artisan_middleware::dusa_collection_utils::{
-    core::{logger::LogLevel, types::rb::RollingBuffer},
-    log,
+use crate::secrets::tls::{SecretTlsConfig, https_endpoint};
+use artisan_middleware::dusa_collection_utils::core::{
+    errors::{ErrorArrayItem, Errors},
+    logger::LogLevel,
+    types::rb::RollingBuffer,
+};
+use tonic::{
+    codec::Streaming,
+    transport::{Channel, Endpoint},
 };
-use tonic::transport::Channel;
 
 #[derive(Debug, Clone)]
 pub struct SecretClient {
@@ -17,14 +23,34 @@ impl SecretClient {
         self._log.push(msg);
     }
 
-    pub async fn connect(addr: &String) -> Result<Self, tonic::transport::Error> {
+    pub async fn connect(
+        addr: &String,
+        tls: Option<&SecretTlsConfig>,
+    ) -> Result<Self, ErrorArrayItem> {
         let mut buffer = RollingBuffer::new(1024);
         let log_msg = format!("Attempting to connect to secret server @ {}", addr);
         log!(LogLevel::Debug, "{}", log_msg);
         buffer.push(log_msg);
-        let client = SecretServiceClient::connect(addr.clone()).await?;
 
-        let log_msg = format!("Connected to secret server @ {}", addr);
+        let connection_error = |err: tonic::transport::Error| {
+            ErrorArrayItem::new(Errors::ConnectionError, err.to_string())
+        };
+        let endpoint = match tls {
+            Some(tls) => Endpoint::from_shared(https_endpoint(addr))
+                .map_err(connection_error)?
+                .tls_config(tls.client_config()?)
+                .map_err(connection_error)?,
+            None => Endpoint::from_shared(addr.clone()).map_err(connection_error)?,
+        };
+
+        let channel = endpoint.connect().await.map_err(connection_error)?;
+        let client = SecretServiceClient::new(channel);
+
+        let log_msg = format!(
+            "Connected to secret server @ {}{}",
+            addr,
+            if tls.is_some() { " over tls" } else { "" }
+        );
         log!(LogLevel::Debug, "{}", log_msg);
         buffer.push(log_msg);
 
@@ -41,4 +67,48 @@ impl SecretClient {
         self.log(format!("Requesting all secrets for: {}", req.runner_id));
         Ok(self.client.get_all_secrets(req).await?.into_inner())
     }
+
+    pub async fn get_secret(
+        &mut self,
+        req: secret_service::GetSecretRequest,
+    ) -> Result<secret_service::GetSecretResponse, tonic::Status> {
+        self.log(format!(
+            "Requesting secret {} for: {}",
+            req.secret_key, req.runner_id
+        ));
+        Ok(self.client.get_secret(req).await?.into_inner())
+    }
+
+    pub async fn watch_secret_version(
+        &mut self,
+        req: secret_service::WatchSecretVersionRequest,
+    ) -> Result<Streaming<secret_service::SecretVersionEvent>, tonic::Status> {
+        self.log(format!(
+            "Watching secret versions of {} for: {}",
+            req.environment_id, req.runner_id
+        ));
+        Ok(self.client.watch_secret_version(req).await?.into_inner())
+    }
+
+    pub async fn create_secret(
+        &mut self,
+        req: secret_service::CreateSecretRequest,
+    ) -> Result<secret_service::SimpleSecretResponse, tonic::Status> {
+        self.log(format!(
+            "Creating secret {} for: {}",
+            req.secret_key, req.runner_id
+        ));
+        Ok(self.client.create_secret(req).await?.into_inner())
+    }
+
+    pub async fn update_secret(
+        &mut self,
+        req: secret_service::UpdateSecretRequest,
+    ) -> Result<secret_service::SimpleSecretResponse, tonic::Status> {
+        self.log(format!(
+            "Updating secret {} for: {}",
+            req.secret_key, req.runner_id
+        ));
+        Ok(self.client.update_secret(req).await?.into_inner())
+    }
 }
@@ -98,6 +98,24 @@ pub struct KeyValuePair {
     #[prost(bytes = "vec", tag = "2")]
     pub value: ::prost::alloc::vec::Vec<u8>,
 }
+#[derive(serde::Serialize, serde::Deserialize)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct WatchSecretVersionRequest {
+    #[prost(string, tag = "1")]
+    pub runner_id: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub environment_id: ::prost::alloc::string::String,
+}
+#[derive(serde::Serialize, serde::Deserialize)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SecretVersionEvent {
+    #[prost(string, tag = "1")]
+    pub environment_id: ::prost::alloc::string::String,
+    #[prost(int64, tag = "2")]
+    pub version: i64,
+}
 /// Generated client implementations.
 pub mod secret_service_client {
     #![allow(unused_variables, dead_code, missing_docs, clippy::let_unit_value)]
@@ -310,5 +328,32 @@ pub mod secret_service_client {
                 .insert(GrpcMethod::new("secret_service.SecretService", "DeleteSecret"));
             self.inner.unary(req, path, codec).await
         }
+        pub async fn watch_secret_version(
+            &mut self,
+            request: impl tonic::IntoRequest<super::WatchSecretVersionRequest>,
+        ) -> std::result::Result<
+            tonic::Response<tonic::codec::Streaming<super::SecretVersionEvent>>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/secret_service.SecretService/WatchSecretVersion",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(
+                    GrpcMethod::new("secret_service.SecretService", "WatchSecretVersion"),
+                );
+            self.inner.server_streaming(req, path, codec).await
+        }
     }
 }
@@ -0,0 +1,207 @@
+//! Env files rendered from a template.
+//!
+//! Without a template the env file holds every secret of the environment as
+//! `KEY=value`. With `env_template` set it is rendered from that file
+//! instead, placeholders naming secrets on the secret server:
+//!
+//! ```text
+//! DATABASE_URL={{secret:db_url}}
+//! REDIS_URL=redis://:{{ secret:redis_password }}@localhost:6379
+//! LOG_FORMAT=json
+//! ```
+//!
+//! The file is rendered right before every spawn, so a restarted child sees
+//! the current secrets, and written with `0600`. On a graceful shutdown it
+//! is overwritten before it is removed.
+
+use artisan_middleware::dusa_collection_utils;
+use dusa_collection_utils::core::errors::{ErrorArrayItem, Errors};
+use dusa_collection_utils::core::logger::LogLevel;
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::artifacts;
+use crate::config::AppSpecificConfig;
+use crate::global_child::{GLOBAL_CLINENT_CONNECTION, get_query};
+use crate::log;
+use crate::secrets::secret_functions::{AllSecrets, write_private};
+
+const OPEN: &str = "{{";
+const CLOSE: &str = "}}";
+const SECRET: &str = "secret:";
+
+/// Where the template of `settings` is, if it has one.
+pub fn template_path(settings: &AppSpecificConfig) -> Option<PathBuf> {
+    let template = settings.env_template.as_ref()?;
+    Some(Path::new(&settings.project_path).join(template))
+}
+
+/// Visit every `{{secret:<key>}}` in `template`, with the text before it and
+/// its key. Other `{{ }}` are left alone.
+fn scan<'a>(
+    template: &'a str,
+    mut visit: impl FnMut(&'a str, Option<&'a str>),
+) -> Result<(), String> {
+    let mut rest = template;
+    while let Some(start) = rest.find(OPEN) {
+        let inner = &rest[start + OPEN.len()..];
+        let end = match inner.find(CLOSE) {
+            Some(end) => end,
+            None => {
+                visit(rest, None);
+                return Ok(());
+            }
+        };
+
+        let key = match inner[..end].trim().strip_prefix(SECRET) {
+            Some(key) => key.trim(),
+            None => {
+                // Not ours, keep it including the braces
+                let literal = start + OPEN.len() + end + CLOSE.len();
+                visit(&rest[..literal], None);
+                rest = &rest[literal..];
+                continue;
+            }
+        };
+        if key.is_empty() || key.contains(char::is_whitespace) {
+            return Err(format!("Invalid placeholder {{{{{}}}}}", &inner[..end]));
+        }
+
+        visit(&rest[..start], Some(key));
+        rest = &inner[end + CLOSE.len()..];
+    }
+    visit(rest, None);
+    Ok(())
+}
+
+/// Secret keys `template` refers to, in order of first use.
+pub fn placeholders(template: &str) -> Result<Vec<String>, String> {
+    let mut keys: Vec<String> = Vec::new();
+    scan(template, |_, key| {
+        if let Some(key) = key.filter(|key| !keys.iter().any(|known| known == key)) {
+            keys.push(key.to_owned());
+        }
+    })?;
+    Ok(keys)
+}
+
+/// Fill the placeholders of `template` from `secrets`, failing with all of
+/// the keys that are missing.
+pub fn render(template: &str, secrets: &AllSecrets) -> Result<String, String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut missing: Vec<&str> = Vec::new();
+
+    scan(template, |text, key| {
+        rendered.push_str(text);
+        let key = match key {
+            Some(key) => key,
+            None => return,
+        };
+        match secrets.iter().find(|(name, _)| name == key) {
+            Some((_, value)) => rendered.push_str(&String::from_utf8_lossy(value)),
+            None if missing.contains(&key) => (),
+            None => missing.push(key),
+        }
+    })?;
+
+    match missing.is_empty() {
+        true => Ok(rendered),
+        false => Err(format!(
+            "Secrets missing for the env template: {}",
+            missing.join(", ")
+        )),
+    }
+}
+
+/// Fetch `keys` from the secret server for `purpose`, nothing when there
+/// are no keys.
+pub(crate) async fn fetch_keys(
+    keys: &[String],
+    purpose: &str,
+) -> Result<AllSecrets, ErrorArrayItem> {
+    if keys.is_empty() {
+        return Ok(AllSecrets::new());
+    }
+    let client = GLOBAL_CLINENT_CONNECTION
+        .lock()
+        .await
+        .clone()
+        .ok_or_else(|| {
+            ErrorArrayItem::new(
+                Errors::ConnectionError,
+                format!("No secret server connection for {}", purpose),
+            )
+        })?;
+    let query = get_query().map_err(|_| {
+        ErrorArrayItem::new(
+            Errors::GeneralError,
+            format!("No secret query for {}", purpose),
+        )
+    })?;
+    query.get_required(client, keys).await
+}
+
+/// Render the env file from the template of `settings`, fetching the
+/// secrets it names. Does nothing without a template.
+pub async fn render_env_file(settings: &AppSpecificConfig) -> Result<(), ErrorArrayItem> {
+    let path = match template_path(settings) {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+
+    let template = fs::read_to_string(&path).map_err(|err| {
+        ErrorArrayItem::new(
+            Errors::InputOutput,
+            format!(
+                "Failed to read the env template {}: {}",
+                path.display(),
+                err
+            ),
+        )
+    })?;
+    let keys =
+        placeholders(&template).map_err(|err| ErrorArrayItem::new(Errors::GeneralError, err))?;
+
+    let secrets = fetch_keys(&keys, "the env template").await?;
+
+    let rendered = render(&template, &secrets)
+        .map_err(|err| ErrorArrayItem::new(Errors::GeneralError, err))?;
+    let env_file = Path::new(&settings.env_file_location);
+    artifacts::track(env_file);
+    write_private(env_file, rendered.as_bytes()).map_err(|err| {
+        ErrorArrayItem::new(
+            Errors::InputOutput,
+            format!(
+                "Failed to write the env file {}: {}",
+                env_file.display(),
+                err
+            ),
+        )
+    })?;
+
+    log!(
+        LogLevel::Debug,
+        "Rendered {} secrets into {}",
+        keys.len(),
+        env_file.display()
+    );
+    Ok(())
+}
+
+/// Overwrite `path` with zeros and remove it, a missing file is fine.
+pub fn shred(path: &Path) -> io::Result<()> {
+    let length = match fs::metadata(path) {
+        Ok(metadata) => metadata.len(),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    file.write_all(&vec![0; length as usize])?;
+    file.sync_all()?;
+    drop(file);
+    fs::remove_file(path)
+}
@@ -0,0 +1,79 @@
+//! TLS and mTLS for the secret server channel.
+//!
+//! Secrets used to be fetched over a plaintext channel. Adding an
+//! `[app_specific.secret_tls]` table switches the channel to TLS, verifying
+//! the server against `ca_cert` and, when a client certificate is given,
+//! authenticating the runner with it.
+
+use artisan_middleware::dusa_collection_utils::core::errors::{ErrorArrayItem, Errors};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use tonic::transport::{Certificate, ClientTlsConfig, Identity};
+
+/// `[app_specific.secret_tls]`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct SecretTlsConfig {
+    /// PEM bundle the server certificate is verified against.
+    #[serde(default)]
+    pub ca_cert: Option<String>,
+    /// PEM client certificate for mTLS, requires `client_key`.
+    #[serde(default)]
+    pub client_cert: Option<String>,
+    /// PEM private key of `client_cert`.
+    #[serde(default)]
+    pub client_key: Option<String>,
+    /// Name checked against the server certificate, defaults to the host of
+    /// `secret_server_addr`.
+    #[serde(default)]
+    pub domain: Option<String>,
+}
+
+fn read_pem(name: &str, path: &str) -> Result<Vec<u8>, ErrorArrayItem> {
+    fs::read(path).map_err(|err| {
+        ErrorArrayItem::new(
+            Errors::InputOutput,
+            format!("Can't read secret_tls {} {}: {}", name, path, err),
+        )
+    })
+}
+
+impl SecretTlsConfig {
+    /// Check the definition without reading any files.
+    pub fn validate(&self) -> Result<(), String> {
+        match (&self.client_cert, &self.client_key) {
+            (Some(_), None) => Err(String::from("secret_tls client_cert needs a client_key")),
+            (None, Some(_)) => Err(String::from("secret_tls client_key needs a client_cert")),
+            _ => Ok(()),
+        }
+    }
+
+    /// Load the certificates into a tonic TLS config.
+    pub fn client_config(&self) -> Result<ClientTlsConfig, ErrorArrayItem> {
+        self.validate()
+            .map_err(|err| ErrorArrayItem::new(Errors::GeneralError, err))?;
+
+        let mut config = ClientTlsConfig::new();
+        if let Some(path) = &self.ca_cert {
+            config = config.ca_certificate(Certificate::from_pem(read_pem("ca_cert", path)?));
+        }
+        if let (Some(cert), Some(key)) = (&self.client_cert, &self.client_key) {
+            config = config.identity(Identity::from_pem(
+                read_pem("client_cert", cert)?,
+                read_pem("client_key", key)?,
+            ));
+        }
+        if let Some(domain) = &self.domain {
+            config = config.domain_name(domain.clone());
+        }
+        Ok(config)
+    }
+}
+
+/// tonic only applies TLS to `https://` endpoints and silently falls back to
+/// plaintext otherwise, so force the scheme.
+pub fn https_endpoint(addr: &str) -> String {
+    match addr.split_once("://") {
+        Some((_, rest)) => format!("https://{}", rest),
+        None => format!("https://{}", addr),
+    }
+}
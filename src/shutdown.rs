@@ -0,0 +1,251 @@
+//! Ordered shutdown of the runner's subsystems.
+//!
+//! Killing the child used to be all a graceful exit did, the monitors,
+//! listeners, the log shipper and the secret connection went down with the
+//! process in whatever state they were in. A [`Shutdown`] winds them down
+//! one [`Stage`] at a time instead, each with its own timeout:
+//!
+//! 1. `monitors`, the directory monitors, the webhook listener and the
+//!    stdin socket stop, so nothing starts a deploy or reaches the child any
+//!    more,
+//! 2. `health`, the heartbeat socket and the state sync server close,
+//! 3. `child`, the child is killed, within `shutdown_timeout_seconds`,
+//! 4. `logs`, what the child printed last is captured and the journal,
+//!    notifications, the log shipper and restart traces are flushed,
+//! 5. `state`, the env file is shredded, the reservation released and the
+//!    secret connection closed.
+//!
+//! A stage that fails or runs out of time is logged and the next one runs
+//! anyway, the final state write happens either way.
+//!
+//! ```toml
+//! [app_specific.shutdown]
+//! monitors_timeout_seconds = 5
+//! health_timeout_seconds = 5
+//! logs_timeout_seconds = 10
+//! state_timeout_seconds = 5
+//! ```
+//!
+//! Background tasks belonging to a stage are started with [`spawn`], they
+//! are cancelled when their stage begins. Tasks that wind down on their own
+//! wait for [`reached`] and are registered with [`track`], their stage waits
+//! for them.
+
+use artisan_middleware::dusa_collection_utils::core::logger::LogLevel;
+use serde::{Deserialize, Serialize};
+use std::{fmt, future::Future, time::Duration};
+use tokio::{
+    task::JoinHandle,
+    time::{Instant, timeout},
+};
+
+use crate::config::AppSpecificConfig;
+use crate::global_child::{GLOBAL_SHUTDOWN, GLOBAL_SUBSYSTEMS};
+use crate::log;
+
+/// A stage of the shutdown, in the order they run.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    Monitors,
+    Health,
+    Child,
+    Logs,
+    State,
+}
+
+impl Stage {
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Monitors => "monitors",
+            Stage::Health => "health",
+            Stage::Child => "child",
+            Stage::Logs => "logs",
+            Stage::State => "state",
+        }
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// `[app_specific.shutdown]`, the child's stage keeps
+/// `shutdown_timeout_seconds`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ShutdownConfig {
+    #[serde(default = "default_stage_timeout")]
+    pub monitors_timeout_seconds: u64,
+    #[serde(default = "default_stage_timeout")]
+    pub health_timeout_seconds: u64,
+    #[serde(default = "default_logs_timeout")]
+    pub logs_timeout_seconds: u64,
+    #[serde(default = "default_stage_timeout")]
+    pub state_timeout_seconds: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            monitors_timeout_seconds: default_stage_timeout(),
+            health_timeout_seconds: default_stage_timeout(),
+            logs_timeout_seconds: default_logs_timeout(),
+            state_timeout_seconds: default_stage_timeout(),
+        }
+    }
+}
+
+fn default_stage_timeout() -> u64 {
+    5
+}
+
+fn default_logs_timeout() -> u64 {
+    10
+}
+
+/// How a stage of the shutdown went.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StageOutcome {
+    pub stage: Stage,
+    pub duration: Duration,
+    pub result: Result<(), String>,
+    /// The stage was cut short by its timeout.
+    pub timed_out: bool,
+}
+
+/// Runs the stages of a shutdown, keeping how each went.
+#[derive(Debug)]
+pub struct Shutdown {
+    timeouts: [Duration; 5],
+    outcomes: Vec<StageOutcome>,
+}
+
+impl Shutdown {
+    pub fn new(settings: &AppSpecificConfig) -> Self {
+        let config = &settings.shutdown;
+        Self {
+            timeouts: [
+                config.monitors_timeout_seconds,
+                config.health_timeout_seconds,
+                settings.shutdown_timeout_seconds,
+                config.logs_timeout_seconds,
+                config.state_timeout_seconds,
+            ]
+            .map(Duration::from_secs),
+            outcomes: Vec::new(),
+        }
+    }
+
+    pub fn timeout(&self, stage: Stage) -> Duration {
+        self.timeouts[stage as usize]
+    }
+
+    /// How long the whole shutdown may take.
+    pub fn total(&self) -> Duration {
+        self.timeouts.iter().sum()
+    }
+
+    /// Begin `stage`, run `work` and wait for the tasks of the stage, all
+    /// within its timeout. Tasks still running after that are aborted.
+    ///
+    /// Returns whether the stage finished in time without an error.
+    pub async fn stage<F>(&mut self, stage: Stage, work: F) -> bool
+    where
+        F: Future<Output = Result<(), String>>,
+    {
+        log!(LogLevel::Debug, "Shutting down {}", stage);
+        let started = Instant::now();
+        GLOBAL_SHUTDOWN.send_replace(Some(stage));
+        let tasks = take_tasks(stage);
+        let aborts: Vec<_> = tasks.iter().map(|task| task.abort_handle()).collect();
+
+        let limit = self.timeout(stage);
+        let finished = timeout(limit, async {
+            let result = work.await;
+            for task in tasks {
+                _ = task.await;
+            }
+            result
+        })
+        .await;
+        for abort in aborts {
+            abort.abort();
+        }
+
+        let timed_out = finished.is_err();
+        let result = match finished {
+            Ok(result) => result,
+            Err(_) => Err(format!("didn't finish within {}s", limit.as_secs())),
+        };
+        let duration = started.elapsed();
+        match &result {
+            Ok(()) => log!(
+                LogLevel::Debug,
+                "Shut down {} in {}ms",
+                stage,
+                duration.as_millis()
+            ),
+            Err(err) => log!(LogLevel::Warn, "Shutting down {} failed: {}", stage, err),
+        }
+        let success = result.is_ok();
+        self.outcomes.push(StageOutcome {
+            stage,
+            duration,
+            result,
+            timed_out,
+        });
+        success
+    }
+
+    /// The stages run so far.
+    pub fn outcomes(&self) -> &[StageOutcome] {
+        &self.outcomes
+    }
+}
+
+fn take_tasks(stage: Stage) -> Vec<JoinHandle<()>> {
+    let mut subsystems = match GLOBAL_SUBSYSTEMS.lock() {
+        Ok(subsystems) => subsystems,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    let (taken, kept) = std::mem::take(&mut *subsystems)
+        .into_iter()
+        .partition(|(owner, _)| *owner == stage);
+    *subsystems = kept;
+    taken.into_iter().map(|(_, task)| task).collect()
+}
+
+/// Resolves once the shutdown reached `stage`.
+pub async fn reached(stage: Stage) {
+    let mut receiver = GLOBAL_SHUTDOWN.subscribe();
+    _ = receiver
+        .wait_for(|current| current.is_some_and(|current| current >= stage))
+        .await;
+}
+
+/// Have `stage` wait for `task` to finish.
+pub fn track(stage: Stage, task: JoinHandle<()>) {
+    let mut subsystems = match GLOBAL_SUBSYSTEMS.lock() {
+        Ok(subsystems) => subsystems,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    // Finished ones needn't be waited for
+    subsystems.retain(|(_, task)| !task.is_finished());
+    subsystems.push((stage, task));
+}
+
+/// Run `task` in the background until `stage` of the shutdown begins.
+pub fn spawn<F>(stage: Stage, task: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let handle = tokio::spawn(async move {
+        tokio::select! {
+            _ = reached(stage) => (),
+            _ = task => (),
+        }
+    });
+    track(stage, handle);
+}
@@ -1,37 +1,247 @@
 //! Signal handling utilities.
 //!
-//! Separate threads listen for specific Unix signals and update shared flags
-//! which the main loop can react to.
+//! The runner is controlled through six requests: reload (`restart`),
+//! exit, toggling maintenance mode, applying the log level, applying
+//! resource limits and writing a dump. On Unix they arrive as `SIGHUP`,
+//! `SIGUSR1`, `SIGUSR2`, `SIGTTIN`, `SIGTTOU` and `SIGURG`, listened for on
+//! separate threads which update shared flags the main loop reacts to. The
+//! dump is written by a task of its own, see [`crate::dump`]. `SIGURG` is
+//! ignored by default, so asking a runner that predates dumps for one does
+//! no harm. On Windows the console control handlers take their place:
+//! `CTRL_BREAK` reloads, closing the console or shutting down exits.
+//! Maintenance has no event there, the flag file still works, the log level
+//! and limits are applied when the runner starts and there are no dumps.
 
 use artisan_middleware::dusa_collection_utils;
 use dusa_collection_utils::core::logger::LogLevel;
-use dusa_collection_utils::log;
-use nix::libc::SIGUSR1;
-use signal_hook::{consts::signal::SIGHUP, iterator::Signals};
 use std::sync::{
     Arc,
     atomic::{AtomicBool, Ordering},
 };
-use std::thread;
+use tokio::sync::Notify;
 
-/// Spawn a thread that listens for `SIGHUP` and toggles the provided flag.
-pub fn sighup_watch(reload: Arc<AtomicBool>) {
-    thread::spawn(move || {
-        let mut signals = Signals::new(&[SIGHUP]).expect("Failed to register signals");
-        for _ in signals.forever() {
-            reload.store(true, Ordering::Relaxed);
-            log!(LogLevel::Info, "Received SIGHUP, marked for reload");
+use crate::events::{self, Event};
+use crate::log;
+
+/// A request sent to a running runner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+    /// Rebuild and respawn the child.
+    Reload,
+    /// Shut down gracefully.
+    Exit,
+    /// Flip maintenance mode.
+    Maintenance,
+    /// Apply the log level recorded in the runner state.
+    LogLevel,
+    /// Apply the cgroup limits from the config to the running child.
+    Limits,
+    /// Write a dump of what the runner is doing.
+    Dump,
+}
+
+impl Control {
+    /// What's asked for, as the event journal records it.
+    pub fn name(self) -> &'static str {
+        match self {
+            Control::Reload => "reload",
+            Control::Exit => "exit",
+            Control::Maintenance => "maintenance",
+            Control::LogLevel => "log_level",
+            Control::Limits => "limits",
+            Control::Dump => "dump",
+        }
+    }
+
+    /// How the request is delivered, for messages.
+    pub fn describe(self) -> &'static str {
+        match (self, cfg!(unix)) {
+            (Control::Reload, true) => "SIGHUP",
+            (Control::Exit, true) => "SIGUSR1",
+            (Control::Maintenance, true) => "SIGUSR2",
+            (Control::LogLevel, true) => "SIGTTIN",
+            (Control::Limits, true) => "SIGTTOU",
+            (Control::Dump, true) => "SIGURG",
+            (Control::Reload, false) => "CTRL_BREAK",
+            (Control::Exit, false) => "CTRL_CLOSE",
+            (Control::Maintenance, false) => "the maintenance flag file",
+            (Control::LogLevel | Control::Limits, false) => "a runner restart",
+            (Control::Dump, false) => "nothing on Windows",
         }
+    }
+}
+
+/// Flags the control requests set.
+#[derive(Debug, Clone, Default)]
+pub struct ControlFlags {
+    pub reload: Arc<AtomicBool>,
+    pub exit: Arc<AtomicBool>,
+    /// Flipped on every maintenance request.
+    pub maintenance: Arc<AtomicBool>,
+    pub log_level: Arc<AtomicBool>,
+    pub limits: Arc<AtomicBool>,
+    /// Notified on every dump request.
+    pub dump: Arc<Notify>,
+}
+
+/// Record `control` in `flags`.
+fn received(flags: &ControlFlags, control: Control) {
+    events::record(Event::Signal {
+        signal: control.describe().to_owned(),
+        action: control.name().to_owned(),
     });
+    match control {
+        Control::Reload => {
+            flags.reload.store(true, Ordering::Relaxed);
+            log!(
+                LogLevel::Info,
+                "Received {}, marked for reload",
+                control.describe()
+            );
+        }
+        Control::Exit => {
+            flags.exit.store(true, Ordering::Relaxed);
+            log!(LogLevel::Info, "Received {}, exiting", control.describe());
+        }
+        Control::Maintenance => {
+            let active = !flags.maintenance.fetch_xor(true, Ordering::Relaxed);
+            log!(
+                LogLevel::Info,
+                "Received {}, maintenance mode toggled {}",
+                control.describe(),
+                if active { "on" } else { "off" }
+            );
+        }
+        Control::LogLevel => {
+            flags.log_level.store(true, Ordering::Relaxed);
+            log!(
+                LogLevel::Info,
+                "Received {}, applying the log level",
+                control.describe()
+            );
+        }
+        Control::Limits => {
+            flags.limits.store(true, Ordering::Relaxed);
+            log!(
+                LogLevel::Info,
+                "Received {}, applying resource limits",
+                control.describe()
+            );
+        }
+        Control::Dump => {
+            flags.dump.notify_one();
+            log!(
+                LogLevel::Info,
+                "Received {}, writing a dump",
+                control.describe()
+            );
+        }
+    }
+}
+
+/// Start listening for control requests, updating `flags`.
+pub fn watch_controls(flags: &ControlFlags) {
+    platform::watch(flags);
 }
 
-/// Spawn a thread that listens for `SIGUSR1` and toggles the provided flag.
-pub fn sigusr_watch(reload: Arc<AtomicBool>) {
-    thread::spawn(move || {
-        let mut signals = Signals::new(&[SIGUSR1]).expect("Failed to register signals");
-        for _ in signals.forever() {
-            reload.store(true, Ordering::Relaxed);
-            log!(LogLevel::Info, "Received SIGHUP, exiting");
+/// Send `control` to the runner running as `pid`.
+pub fn send(pid: u32, control: Control) -> Result<(), String> {
+    platform::send(pid, control)
+}
+
+/// Whether a process with `pid` exists.
+pub fn is_alive(pid: u32) -> bool {
+    platform::is_alive(pid)
+}
+
+#[cfg(unix)]
+mod platform {
+    use nix::sys::signal::{Signal, kill};
+    use nix::unistd::Pid;
+    use signal_hook::iterator::Signals;
+    use std::thread;
+
+    use super::{Control, ControlFlags, received};
+
+    fn signal(control: Control) -> Signal {
+        match control {
+            Control::Reload => Signal::SIGHUP,
+            Control::Exit => Signal::SIGUSR1,
+            Control::Maintenance => Signal::SIGUSR2,
+            Control::LogLevel => Signal::SIGTTIN,
+            Control::Limits => Signal::SIGTTOU,
+            Control::Dump => Signal::SIGURG,
         }
-    });
+    }
+
+    pub fn watch(flags: &ControlFlags) {
+        for control in [
+            Control::Reload,
+            Control::Exit,
+            Control::Maintenance,
+            Control::LogLevel,
+            Control::Limits,
+            Control::Dump,
+        ] {
+            let flags = flags.clone();
+            thread::spawn(move || {
+                let mut signals =
+                    Signals::new([signal(control) as i32]).expect("Failed to register signals");
+                for _ in signals.forever() {
+                    received(&flags, control);
+                }
+            });
+        }
+    }
+
+    pub fn send(pid: u32, control: Control) -> Result<(), String> {
+        kill(Pid::from_raw(pid as i32), signal(control)).map_err(|err| err.to_string())
+    }
+
+    pub fn is_alive(pid: u32) -> bool {
+        kill(Pid::from_raw(pid as i32), None).is_ok()
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use tokio::signal::windows::{ctrl_break, ctrl_close, ctrl_shutdown};
+
+    use super::{Control, ControlFlags, received};
+
+    pub fn watch(flags: &ControlFlags) {
+        let reload = flags.clone();
+        tokio::spawn(async move {
+            if let Ok(mut events) = ctrl_break() {
+                while events.recv().await.is_some() {
+                    received(&reload, Control::Reload);
+                }
+            }
+        });
+
+        let exit = flags.clone();
+        tokio::spawn(async move {
+            if let (Ok(mut close), Ok(mut shutdown)) = (ctrl_close(), ctrl_shutdown()) {
+                tokio::select! {
+                    _ = close.recv() => (),
+                    _ = shutdown.recv() => (),
+                }
+                received(&exit, Control::Exit);
+            }
+        });
+    }
+
+    pub fn send(_pid: u32, control: Control) -> Result<(), String> {
+        Err(format!(
+            "Sending {} to another process isn't supported on Windows",
+            control.describe()
+        ))
+    }
+
+    pub fn is_alive(pid: u32) -> bool {
+        std::process::Command::new("tasklist")
+            .args(["/NH", "/FI", &format!("PID eq {}", pid)])
+            .output()
+            .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).contains(&pid.to_string()))
+    }
 }
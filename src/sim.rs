@@ -0,0 +1,220 @@
+//! Deterministic simulation of the supervision loop.
+//!
+//! File events, child exits, failed probes, maintenance and reloads are
+//! scripted at points in time and fed to the same [`Supervisor`] the main
+//! loop uses, on tokio's virtual clock. A test running on a paused runtime
+//! (`#[tokio::test(start_paused = true)]`) plays hours of crash loop
+//! windows and retry backoff in milliseconds, with the same result every
+//! run:
+//!
+//! ```ignore
+//! let actions = Simulation::new(&settings)
+//!     .run(
+//!         Script::new()
+//!             .at(secs(1), SimEvent::Exit(ChildExit::Code(1)))
+//!             .at(secs(2), SimEvent::Exit(ChildExit::Code(1))),
+//!     )
+//!     .await;
+//! ```
+//!
+//! Restarts always succeed instantly, what is simulated is which of them
+//! happen and when. Secret server outages are played through
+//! [`fetch_secret`] with scripted responses.
+
+use artisan_middleware::dusa_collection_utils;
+use dusa_collection_utils::core::errors::{ErrorArrayItem, Errors};
+use std::{collections::VecDeque, time::Duration};
+use tokio::time::{Instant, sleep_until};
+
+use crate::{
+    child::ChildExit,
+    config::AppSpecificConfig,
+    restart::{RestartKind, RestartReason},
+    secrets::{RetryConfig, retry::with_retry_jitter},
+    supervisor::{Decision, Supervisor},
+};
+
+/// Something that happens to the runner.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SimEvent {
+    /// A file changed in the `index`th watched directory.
+    Change(usize),
+    /// The child exited.
+    Exit(ChildExit),
+    /// A probe failed or a heartbeat was missed.
+    Unhealthy(String),
+    /// Maintenance mode switched on or off.
+    Maintenance(bool),
+    /// `SIGHUP` or `ais_runner restart`.
+    Reload,
+}
+
+/// Events to play, each at an offset from the start.
+#[derive(Debug, Clone, Default)]
+pub struct Script {
+    events: Vec<(Duration, SimEvent)>,
+}
+
+impl Script {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `event` at `at` from the start, events at the same time play in
+    /// the order they were added.
+    pub fn at(mut self, at: Duration, event: SimEvent) -> Self {
+        self.events.push((at, event));
+        self
+    }
+
+    /// Add `event` `count` times, `every` apart, starting at `from`.
+    pub fn every(mut self, from: Duration, every: Duration, count: u32, event: SimEvent) -> Self {
+        for index in 0..count {
+            self.events.push((from + every * index, event.clone()));
+        }
+        self
+    }
+}
+
+/// What the runner did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SimAction {
+    /// Enough changes piled up, rebuilt and redeployed.
+    Deploy(RestartReason),
+    /// The failed child was respawned.
+    Respawn(RestartReason),
+    /// The child exited for good and was left down.
+    Idle(ChildExit),
+    /// Respawns were suspended after `restarts` in the window.
+    CrashLoop { restarts: usize },
+    /// The child died during maintenance and was left down.
+    Paused(RestartReason),
+    /// Reloaded, `resumed` when respawns were suspended before.
+    Reload { resumed: bool },
+}
+
+/// Plays a [`Script`] against a [`Supervisor`].
+#[derive(Debug)]
+pub struct Simulation {
+    supervisor: Supervisor,
+}
+
+impl Simulation {
+    pub fn new(settings: &AppSpecificConfig) -> Self {
+        Self {
+            supervisor: Supervisor::new(settings),
+        }
+    }
+
+    /// Play `script`, returning what was done and when.
+    ///
+    /// Must run with tokio's clock paused, otherwise it waits for real.
+    pub async fn run(mut self, script: Script) -> Vec<(Duration, SimAction)> {
+        let mut events = script.events;
+        events.sort_by_key(|(at, _)| *at);
+
+        let start = Instant::now();
+        let mut actions = Vec::new();
+        for (at, event) in events {
+            sleep_until(start + at).await;
+            let elapsed = start.elapsed();
+            if let Some(action) = self.handle(event) {
+                actions.push((elapsed, action));
+            }
+        }
+        actions
+    }
+
+    /// The main loop's reaction to `event`.
+    fn handle(&mut self, event: SimEvent) -> Option<SimAction> {
+        let failure = match event {
+            SimEvent::Change(index) => {
+                let reason = self
+                    .supervisor
+                    .on_change(index, Instant::now().into_std())?;
+                self.supervisor.resume();
+                self.supervisor.reset_changes();
+                return Some(SimAction::Deploy(reason));
+            }
+            SimEvent::Maintenance(active) => {
+                let respawn = self.supervisor.set_maintenance(active);
+                return respawn.then(|| {
+                    SimAction::Respawn(RestartReason::new(
+                        RestartKind::Exited,
+                        "stopped during maintenance",
+                    ))
+                });
+            }
+            SimEvent::Reload => {
+                let resumed = self.supervisor.resume();
+                return Some(SimAction::Reload { resumed });
+            }
+            SimEvent::Exit(exit) => {
+                RestartReason::new(RestartKind::Exited, format!("child {}", exit)).with_exit(exit)
+            }
+            SimEvent::Unhealthy(reason) => RestartReason::new(RestartKind::Unhealthy, reason),
+        };
+
+        match self
+            .supervisor
+            .on_failure(failure, Instant::now().into_std())
+        {
+            Decision::Respawn(reason) => Some(SimAction::Respawn(reason)),
+            Decision::Idle(exit) => Some(SimAction::Idle(exit)),
+            Decision::CrashLoop(_) => Some(SimAction::CrashLoop {
+                restarts: self.supervisor.breaker().recent_restarts(),
+            }),
+            Decision::Paused(reason) => Some(SimAction::Paused(reason)),
+            Decision::Hold => None,
+        }
+    }
+}
+
+/// A secret fetch played against scripted responses.
+#[derive(Debug)]
+pub struct SecretRun<T> {
+    pub result: Result<T, ErrorArrayItem>,
+    /// When each attempt was made, from the start.
+    pub attempts: Vec<Duration>,
+    /// The failed attempts, as they end up in the error log.
+    pub history: Vec<ErrorArrayItem>,
+}
+
+/// Fetch through [`with_retry_jitter`] with every attempt answered by the
+/// next of `responses`, a fixed `jitter` and tokio's clock paused.
+///
+/// Running out of responses answers with a connection error.
+pub async fn fetch_secret<T>(
+    config: &RetryConfig,
+    responses: Vec<Result<T, ErrorArrayItem>>,
+    jitter: f64,
+) -> SecretRun<T> {
+    let mut responses = VecDeque::from(responses);
+    let mut attempts = Vec::new();
+    let mut history = Vec::new();
+
+    let start = Instant::now();
+    let result = with_retry_jitter(
+        config,
+        "fetch the simulated secret",
+        &mut history,
+        || jitter,
+        || {
+            attempts.push(start.elapsed());
+            let response = responses.pop_front().unwrap_or_else(|| {
+                Err(ErrorArrayItem::new(
+                    Errors::ConnectionError,
+                    "No scripted response left",
+                ))
+            });
+            async move { response }
+        },
+    )
+    .await;
+
+    SecretRun {
+        result,
+        attempts,
+        history,
+    }
+}
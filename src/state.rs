@@ -0,0 +1,183 @@
+//! Coalesced writes of the [`AppState`] file.
+//!
+//! The state used to be written on every event, output tick and restart
+//! step, several times a second on a busy service or during a build. Going
+//! through [`save`] instead, an update is written right away only when the
+//! state changed significantly (a new status or a new error) or the last
+//! write is older than the interval; otherwise it's held back and written
+//! by [`flush_due`] once the interval is up, with the newest data and
+//! metrics. [`flush`] writes a held back update immediately, for shutdown.
+//!
+//! ```toml
+//! [app_specific.state_writes]
+//! interval_seconds = 5   # 0 writes every update
+//! ```
+
+use artisan_middleware::{
+    dusa_collection_utils::core::types::pathtype::PathType,
+    process_manager::Metrics,
+    state_persistence::{AppState, update_state},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::state_backend;
+
+/// `[app_specific.state_writes]`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct StateWritesConfig {
+    /// Minimum time between writes of updates that aren't significant.
+    #[serde(default = "default_write_interval")]
+    pub interval_seconds: u64,
+}
+
+impl Default for StateWritesConfig {
+    fn default() -> Self {
+        Self {
+            interval_seconds: default_write_interval(),
+        }
+    }
+}
+
+fn default_write_interval() -> u64 {
+    5
+}
+
+/// What makes an update significant: the status and the number of errors.
+pub fn significance(state: &AppState) -> (String, usize) {
+    (state.status.to_string(), state.error_log.len())
+}
+
+/// Decides which updates are written and which are held back.
+#[derive(Debug)]
+pub struct Coalescer {
+    interval: Duration,
+    last_write: Option<Instant>,
+    /// Significance of the last written state.
+    written: Option<(String, usize)>,
+    pending: bool,
+}
+
+impl Coalescer {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_write: None,
+            written: None,
+            pending: false,
+        }
+    }
+
+    /// Whether an update of a state with `significance` is written at `now`,
+    /// otherwise it's held back.
+    pub fn offer(&mut self, significance: (String, usize), now: Instant) -> bool {
+        let write = self.written.as_ref() != Some(&significance) || self.elapsed(now);
+        match write {
+            true => self.written(significance, now),
+            false => self.pending = true,
+        }
+        write
+    }
+
+    /// Whether a held back update is due at `now`.
+    pub fn due(&self, now: Instant) -> bool {
+        self.pending && self.elapsed(now)
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending
+    }
+
+    /// Record a write of a state with `significance` at `now`.
+    pub fn written(&mut self, significance: (String, usize), now: Instant) {
+        self.last_write = Some(now);
+        self.written = Some(significance);
+        self.pending = false;
+    }
+
+    fn elapsed(&self, now: Instant) -> bool {
+        self.last_write
+            .is_none_or(|last| now.duration_since(last) >= self.interval)
+    }
+}
+
+/// Held back metrics are kept so the eventual write has the newest ones.
+struct Writes {
+    coalescer: Coalescer,
+    metrics: Option<Metrics>,
+}
+
+static WRITES: Mutex<Option<Writes>> = Mutex::new(None);
+
+/// Set the write interval, before the first [`save`]. Without it every
+/// update is written.
+pub fn configure(config: &StateWritesConfig) {
+    if let Ok(mut writes) = WRITES.lock() {
+        *writes = Some(Writes {
+            coalescer: Coalescer::new(Duration::from_secs(config.interval_seconds)),
+            metrics: None,
+        });
+    }
+}
+
+/// Persist `state`, or hold the write back for [`flush_due`].
+pub async fn save(state: &mut AppState, state_path: &PathType, metrics: Option<Metrics>) {
+    let now = Instant::now();
+    let write = match WRITES.lock() {
+        Ok(mut guard) => match guard.as_mut() {
+            Some(writes) => {
+                let write = writes.coalescer.offer(significance(state), now);
+                match (write, metrics) {
+                    (true, metrics) => Some(metrics.or(writes.metrics.take())),
+                    (false, Some(metrics)) => {
+                        writes.metrics = Some(metrics);
+                        None
+                    }
+                    (false, None) => None,
+                }
+            }
+            None => Some(metrics),
+        },
+        Err(_) => Some(metrics),
+    };
+    if let Some(metrics) = write {
+        update_state(state, state_path, metrics).await;
+        state_backend::publish(state);
+    }
+}
+
+/// Write a held back update once its interval is up.
+pub async fn flush_due(state: &mut AppState, state_path: &PathType) {
+    write_pending(state, state_path, |coalescer| coalescer.due(Instant::now())).await
+}
+
+/// Write a held back update now.
+pub async fn flush(state: &mut AppState, state_path: &PathType) {
+    write_pending(state, state_path, Coalescer::is_pending).await
+}
+
+async fn write_pending(
+    state: &mut AppState,
+    state_path: &PathType,
+    ready: impl FnOnce(&Coalescer) -> bool,
+) {
+    let metrics = match WRITES.lock() {
+        Ok(mut guard) => match guard.as_mut() {
+            Some(writes) if ready(&writes.coalescer) => {
+                writes
+                    .coalescer
+                    .written(significance(state), Instant::now());
+                Some(writes.metrics.take())
+            }
+            _ => None,
+        },
+        Err(_) => None,
+    };
+    if let Some(metrics) = metrics {
+        update_state(state, state_path, metrics).await;
+        state_backend::publish(state);
+    }
+}
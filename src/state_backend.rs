@@ -0,0 +1,514 @@
+//! Where runner states are published for the manager.
+//!
+//! The state file only shows one runner on one host. `[app_specific.state_backend]`
+//! picks a [`StateBackend`] that every save of the state is also published
+//! to, so a fleet of runners can be looked at from one place:
+//!
+//! - `file` keeps the state file only (the default).
+//! - `sqlite` appends every state to a database, keeping the last `history`
+//!   of each runner. Runners on one host can share the database.
+//! - `redis` keeps each runner's newest state under
+//!   `<prefix>:state:<host>/<app>` and the names in the set
+//!   `<prefix>:runners`, for the whole cluster to read.
+//!
+//! ```toml
+//! [app_specific.state_backend]
+//! kind = "redis"
+//! url = "redis://redis.internal:6379"
+//! ```
+//!
+//! `sqlite` and `redis` are only in a runner built with the `state-sqlite`
+//! and `state-redis` features, picking one that isn't fails with an error
+//! naming the feature.
+//!
+//! The state file is written either way, the CLI keeps reading it.
+//! Captured output isn't published, and publishing happens off the main
+//! loop: a slow or unreachable backend only ever gets the newest state once
+//! it's back.
+
+use artisan_middleware::{
+    dusa_collection_utils::core::{logger::LogLevel, types::pathtype::PathType},
+    state_persistence::AppState,
+};
+#[cfg(feature = "state-sqlite")]
+use rusqlite::{Connection, params};
+use serde::{Deserialize, Serialize};
+#[cfg(any(feature = "state-sqlite", feature = "state-redis"))]
+use std::time::Duration;
+use std::{fs, path::PathBuf, sync::Arc};
+use tokio::sync::watch;
+
+use crate::global_child::GLOBAL_STATE_BACKEND;
+use crate::log;
+
+/// How long a store waits for a database another runner is writing to.
+#[cfg(feature = "state-sqlite")]
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long connecting to redis may take.
+#[cfg(feature = "state-redis")]
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[cfg(feature = "state-sqlite")]
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS states (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        runner TEXT NOT NULL,
+        updated INTEGER NOT NULL,
+        record TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS states_runner ON states (runner, id);
+";
+
+/// A runner's state as it's published.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RunnerRecord {
+    pub host: String,
+    pub state: AppState,
+}
+
+impl RunnerRecord {
+    /// `state` of the runner on this host, without its captured output.
+    pub fn new(state: &mut AppState) -> Self {
+        let stdout = std::mem::take(&mut state.stdout);
+        let stderr = std::mem::take(&mut state.stderr);
+        let published = state.clone();
+        state.stdout = stdout;
+        state.stderr = stderr;
+        Self {
+            host: hostname(),
+            state: published,
+        }
+    }
+
+    /// `<host>/<app>`, what the backends key runners by.
+    pub fn runner(&self) -> String {
+        format!("{}/{}", self.host, self.state.config.app_name)
+    }
+}
+
+fn hostname() -> String {
+    nix::unistd::gethostname()
+        .ok()
+        .and_then(|name| name.into_string().ok())
+        .unwrap_or_else(|| String::from("localhost"))
+}
+
+/// Stores the states of runners and reads them back.
+pub trait StateBackend: Send + Sync {
+    /// Short name for log messages.
+    fn name(&self) -> &'static str;
+
+    /// Set up what storing needs, once at start up while the runner may
+    /// still be root.
+    fn prepare(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Store the newest state of `record`'s runner.
+    fn store(&self, record: &RunnerRecord) -> Result<(), String>;
+
+    /// The newest state of every runner in the backend.
+    fn records(&self) -> Result<Vec<RunnerRecord>, String>;
+}
+
+/// `state_backend` setting.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum StateBackendConfig {
+    #[default]
+    File,
+    Sqlite(SqliteConfig),
+    Redis(RedisConfig),
+}
+
+/// `kind = "sqlite"`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct SqliteConfig {
+    /// Defaults to `<state file>.sqlite`.
+    #[serde(default)]
+    pub path: Option<String>,
+    /// States kept of each runner, 0 keeps all.
+    #[serde(default = "default_history")]
+    pub history: usize,
+}
+
+fn default_history() -> usize {
+    1000
+}
+
+/// `kind = "redis"`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct RedisConfig {
+    /// e.g. `redis://redis.internal:6379/0`
+    pub url: String,
+    #[serde(default = "default_prefix")]
+    pub prefix: String,
+    /// A runner that stopped publishing disappears after this long, 0 keeps
+    /// its last state.
+    #[serde(default = "default_ttl")]
+    pub ttl_seconds: u64,
+}
+
+fn default_prefix() -> String {
+    String::from("ais_runner")
+}
+
+fn default_ttl() -> u64 {
+    300
+}
+
+/// Why the `kind` backend can't be used in this build.
+#[cfg(not(all(feature = "state-sqlite", feature = "state-redis")))]
+fn not_built(kind: &str) -> String {
+    format!(
+        "The {} state backend needs a runner built with the state-{} feature",
+        kind, kind
+    )
+}
+
+impl StateBackendConfig {
+    /// The backend of the runner with its state at `state_path`.
+    pub fn backend(&self, state_path: &PathType) -> Result<Box<dyn StateBackend>, String> {
+        Ok(match self {
+            StateBackendConfig::File => Box::new(FileBackend {
+                path: PathBuf::from(state_path.to_string()),
+            }),
+            #[cfg(feature = "state-sqlite")]
+            StateBackendConfig::Sqlite(sqlite) => Box::new(SqliteBackend {
+                path: sqlite.path(state_path),
+                history: sqlite.history,
+            }),
+            #[cfg(feature = "state-redis")]
+            StateBackendConfig::Redis(redis) => Box::new(RedisBackend::new(redis)?),
+            #[cfg(not(feature = "state-sqlite"))]
+            StateBackendConfig::Sqlite(_) => return Err(not_built("sqlite")),
+            #[cfg(not(feature = "state-redis"))]
+            StateBackendConfig::Redis(_) => return Err(not_built("redis")),
+        })
+    }
+
+    /// Fails for a backend this runner was built without.
+    pub fn available(&self) -> Result<(), String> {
+        match self {
+            #[cfg(not(feature = "state-sqlite"))]
+            StateBackendConfig::Sqlite(_) => Err(not_built("sqlite")),
+            #[cfg(not(feature = "state-redis"))]
+            StateBackendConfig::Redis(_) => Err(not_built("redis")),
+            _ => Ok(()),
+        }
+    }
+
+    /// The database the runner creates, which it has to keep writing to
+    /// after dropping privileges.
+    pub fn path(&self, state_path: &PathType) -> Option<PathBuf> {
+        match self {
+            StateBackendConfig::Sqlite(sqlite) => Some(sqlite.path(state_path)),
+            _ => None,
+        }
+    }
+}
+
+impl SqliteConfig {
+    pub fn path(&self, state_path: &PathType) -> PathBuf {
+        match &self.path {
+            Some(path) => PathBuf::from(path),
+            None => PathBuf::from(format!("{}.sqlite", state_path)),
+        }
+    }
+}
+
+/// The state file `StatePersistence` writes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileBackend {
+    pub path: PathBuf,
+}
+
+impl StateBackend for FileBackend {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    /// Every save already writes the file.
+    fn store(&self, _record: &RunnerRecord) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn records(&self) -> Result<Vec<RunnerRecord>, String> {
+        let content = fs::read(&self.path)
+            .map_err(|err| format!("Failed to read {}: {}", self.path.display(), err))?;
+        let state = serde_json::from_slice(&content)
+            .map_err(|err| format!("Failed to parse {}: {}", self.path.display(), err))?;
+        Ok(vec![RunnerRecord {
+            host: hostname(),
+            state,
+        }])
+    }
+}
+
+/// A database of states, local history of one host.
+#[cfg(feature = "state-sqlite")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SqliteBackend {
+    pub path: PathBuf,
+    pub history: usize,
+}
+
+#[cfg(feature = "state-sqlite")]
+impl SqliteBackend {
+    fn connect(&self) -> rusqlite::Result<Connection> {
+        let connection = Connection::open(&self.path)?;
+        connection.busy_timeout(BUSY_TIMEOUT)?;
+        connection.execute_batch(SCHEMA)?;
+        Ok(connection)
+    }
+
+    /// The last `limit` states stored of `runner`, newest first.
+    pub fn history(&self, runner: &str, limit: usize) -> Result<Vec<RunnerRecord>, String> {
+        let connection = self.connect().map_err(|err| err.to_string())?;
+        let mut statement = connection
+            .prepare("SELECT record FROM states WHERE runner = ?1 ORDER BY id DESC LIMIT ?2")
+            .map_err(|err| err.to_string())?;
+        let rows = statement
+            .query_map(params![runner, limit as i64], |row| row.get::<_, String>(0))
+            .map_err(|err| err.to_string())?;
+        parse_rows(rows)
+    }
+}
+
+#[cfg(feature = "state-sqlite")]
+fn parse_rows(
+    rows: impl Iterator<Item = rusqlite::Result<String>>,
+) -> Result<Vec<RunnerRecord>, String> {
+    let mut records = Vec::new();
+    for row in rows {
+        let row = row.map_err(|err| err.to_string())?;
+        // Records of an older runner version may not parse
+        if let Ok(record) = serde_json::from_str(&row) {
+            records.push(record);
+        }
+    }
+    Ok(records)
+}
+
+#[cfg(feature = "state-sqlite")]
+impl StateBackend for SqliteBackend {
+    fn name(&self) -> &'static str {
+        "sqlite"
+    }
+
+    fn prepare(&self) -> Result<(), String> {
+        self.connect()
+            .map(drop)
+            .map_err(|err| format!("Failed to open {}: {}", self.path.display(), err))
+    }
+
+    fn store(&self, record: &RunnerRecord) -> Result<(), String> {
+        let json = serde_json::to_string(record).map_err(|err| err.to_string())?;
+        let runner = record.runner();
+        let mut connection = self.connect().map_err(|err| err.to_string())?;
+        let transaction = connection.transaction().map_err(|err| err.to_string())?;
+        transaction
+            .execute(
+                "INSERT INTO states (runner, updated, record) VALUES (?1, ?2, ?3)",
+                params![runner, record.state.last_updated as i64, json],
+            )
+            .map_err(|err| err.to_string())?;
+        if self.history > 0 {
+            // Nothing is deleted while there are fewer rows than kept
+            transaction
+                .execute(
+                    "DELETE FROM states WHERE runner = ?1 AND id <= (
+                        SELECT id FROM states WHERE runner = ?1 ORDER BY id DESC LIMIT 1 OFFSET ?2
+                    )",
+                    params![runner, self.history as i64],
+                )
+                .map_err(|err| err.to_string())?;
+        }
+        transaction.commit().map_err(|err| err.to_string())
+    }
+
+    fn records(&self) -> Result<Vec<RunnerRecord>, String> {
+        let connection = self.connect().map_err(|err| err.to_string())?;
+        let mut statement = connection
+            .prepare(
+                "SELECT record FROM states WHERE id IN (
+                    SELECT MAX(id) FROM states GROUP BY runner
+                ) ORDER BY runner",
+            )
+            .map_err(|err| err.to_string())?;
+        let rows = statement
+            .query_map([], |row| row.get::<_, String>(0))
+            .map_err(|err| err.to_string())?;
+        parse_rows(rows)
+    }
+}
+
+/// Newest states in redis, visible to the cluster.
+#[cfg(feature = "state-redis")]
+#[derive(Debug, Clone)]
+pub struct RedisBackend {
+    client: redis::Client,
+    prefix: String,
+    ttl_seconds: u64,
+}
+
+#[cfg(feature = "state-redis")]
+impl RedisBackend {
+    pub fn new(config: &RedisConfig) -> Result<Self, String> {
+        let client = redis::Client::open(config.url.as_str())
+            .map_err(|err| format!("Invalid redis url {}: {}", config.url, err))?;
+        Ok(Self {
+            client,
+            prefix: config.prefix.clone(),
+            ttl_seconds: config.ttl_seconds,
+        })
+    }
+
+    fn connect(&self) -> Result<redis::Connection, String> {
+        self.client
+            .get_connection_with_timeout(CONNECT_TIMEOUT)
+            .map_err(|err| format!("Failed to connect to redis: {}", err))
+    }
+
+    fn key(&self, runner: &str) -> String {
+        format!("{}:state:{}", self.prefix, runner)
+    }
+
+    fn index(&self) -> String {
+        format!("{}:runners", self.prefix)
+    }
+}
+
+#[cfg(feature = "state-redis")]
+impl StateBackend for RedisBackend {
+    fn name(&self) -> &'static str {
+        "redis"
+    }
+
+    fn prepare(&self) -> Result<(), String> {
+        self.connect().map(drop)
+    }
+
+    fn store(&self, record: &RunnerRecord) -> Result<(), String> {
+        let json = serde_json::to_string(record).map_err(|err| err.to_string())?;
+        let runner = record.runner();
+        let mut connection = self.connect()?;
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        match self.ttl_seconds {
+            0 => pipe.set(self.key(&runner), json).ignore(),
+            ttl => pipe.set_ex(self.key(&runner), json, ttl).ignore(),
+        };
+        pipe.sadd(self.index(), &runner)
+            .ignore()
+            .query::<()>(&mut connection)
+            .map_err(|err| err.to_string())
+    }
+
+    fn records(&self) -> Result<Vec<RunnerRecord>, String> {
+        let mut connection = self.connect()?;
+        let mut runners: Vec<String> = redis::cmd("SMEMBERS")
+            .arg(self.index())
+            .query(&mut connection)
+            .map_err(|err| err.to_string())?;
+        if runners.is_empty() {
+            return Ok(Vec::new());
+        }
+        runners.sort();
+        let keys: Vec<String> = runners.iter().map(|runner| self.key(runner)).collect();
+        // Runners whose state expired are left out
+        let values: Vec<Option<String>> = redis::cmd("MGET")
+            .arg(keys)
+            .query(&mut connection)
+            .map_err(|err| err.to_string())?;
+        Ok(values
+            .into_iter()
+            .flatten()
+            .filter_map(|value| serde_json::from_str(&value).ok())
+            .collect())
+    }
+}
+
+/// Hands the states [`publish`] is given to a backend.
+pub struct StatePublisher {
+    backend: Arc<dyn StateBackend>,
+    latest: watch::Sender<Option<RunnerRecord>>,
+}
+
+/// Start publishing to `backend`. Stores run on the blocking pool, states
+/// saved while one is under way are collapsed into the newest.
+pub fn start(backend: Box<dyn StateBackend>) {
+    let backend: Arc<dyn StateBackend> = backend.into();
+    let (latest, mut receiver) = watch::channel(None);
+    let publisher = StatePublisher {
+        backend: backend.clone(),
+        latest,
+    };
+    if GLOBAL_STATE_BACKEND.set(publisher).is_err() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut failing = false;
+        while receiver.changed().await.is_ok() {
+            let record = match receiver.borrow_and_update().clone() {
+                Some(record) => record,
+                None => continue,
+            };
+            let storing = backend.clone();
+            let stored = tokio::task::spawn_blocking(move || storing.store(&record))
+                .await
+                .unwrap_or_else(|err| Err(err.to_string()));
+            // Only the first of a run of failures is logged
+            match (stored, failing) {
+                (Ok(()), true) => {
+                    log!(
+                        LogLevel::Info,
+                        "Publishing states to {} again",
+                        backend.name()
+                    );
+                    failing = false;
+                }
+                (Ok(()), false) => (),
+                (Err(err), false) => {
+                    log!(
+                        LogLevel::Warn,
+                        "Failed to publish the state to {}: {}",
+                        backend.name(),
+                        err
+                    );
+                    failing = true;
+                }
+                (Err(_), true) => (),
+            }
+        }
+    });
+}
+
+/// Publish `state` to the backend, if one was started.
+pub fn publish(state: &mut AppState) {
+    if let Some(publisher) = GLOBAL_STATE_BACKEND.get() {
+        publisher
+            .latest
+            .send_replace(Some(RunnerRecord::new(state)));
+    }
+}
+
+/// Store the final `state` before the runner exits, waiting for it.
+pub async fn publish_final(state: &mut AppState) {
+    let publisher = match GLOBAL_STATE_BACKEND.get() {
+        Some(publisher) => publisher,
+        None => return,
+    };
+    let record = RunnerRecord::new(state);
+    let backend = publisher.backend.clone();
+    let stored = tokio::task::spawn_blocking(move || backend.store(&record)).await;
+    if let Ok(Err(err)) = stored {
+        log!(
+            LogLevel::Warn,
+            "Failed to publish the final state to {}: {}",
+            publisher.backend.name(),
+            err
+        );
+    }
+}
@@ -0,0 +1,280 @@
+//! Delta sync of the runner state for fleet dashboards.
+//!
+//! A collector polling many edge runners over metered links shouldn't pull
+//! the whole state every time. With `[app_specific.state_sync]` set the
+//! runner numbers every change of its state fields and answers
+//!
+//! ```text
+//! GET /state?epoch=<epoch>&since=<seq>
+//! Authorization: Bearer <token>
+//! ```
+//!
+//! with only the fields that changed after `since`, or `304 Not Modified`
+//! when none did. The `epoch` is the time the runner started, a collector
+//! asking with another one (the runner was restarted) or without `since`
+//! gets every field with `full` set.
+//!
+//! With the metrics history on, `GET /metrics?points=<n>` answers with the
+//! CPU and memory history of the window, downsampled to at most `n`
+//! buckets, see [`crate::metrics_history`].
+
+use artisan_middleware::{dusa_collection_utils, state_persistence::AppState};
+use dusa_collection_utils::core::logger::LogLevel;
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    io,
+    sync::{Arc, Mutex},
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::app_status::AppStatus;
+use crate::log;
+use crate::metrics_history::{MetricsHistory, SharedMetricsHistory};
+use crate::privileges;
+use crate::shutdown::{self, Stage};
+use crate::webhook::{Request, read_request, respond_with};
+
+/// `[app_specific.state_sync]`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct StateSyncConfig {
+    /// Address to answer on, e.g. `"0.0.0.0:9101"`. Off when unset.
+    #[serde(default)]
+    pub addr: Option<String>,
+    /// Bearer token collectors authenticate with, required with `addr`.
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+/// The fields a collector syncs, from the state and the status the child
+/// reported.
+pub fn snapshot(state: &AppState, app_status: &AppStatus) -> BTreeMap<String, Value> {
+    let errors: Vec<String> = state.error_log.iter().map(|err| err.to_string()).collect();
+    BTreeMap::from([
+        (
+            String::from("status"),
+            Value::from(state.status.to_string()),
+        ),
+        (String::from("data"), Value::from(state.data.clone())),
+        (String::from("pid"), Value::from(state.pid)),
+        (
+            String::from("event_counter"),
+            Value::from(state.event_counter),
+        ),
+        (
+            String::from("last_updated"),
+            Value::from(state.last_updated),
+        ),
+        (String::from("errors"), Value::from(errors)),
+        (
+            String::from("app_status"),
+            serde_json::to_value(app_status).unwrap_or_default(),
+        ),
+    ])
+}
+
+/// An answer to a collector.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Delta {
+    pub epoch: u64,
+    /// Sequence number of the latest change, pass it as `since` next time.
+    pub seq: u64,
+    /// Every field is included, the collector should drop what it had.
+    pub full: bool,
+    pub fields: BTreeMap<String, Value>,
+}
+
+/// Every field with the sequence number it last changed at.
+#[derive(Debug)]
+pub struct StateLog {
+    epoch: u64,
+    seq: u64,
+    fields: BTreeMap<String, (u64, Value)>,
+}
+
+impl StateLog {
+    pub fn new(epoch: u64) -> Self {
+        Self {
+            epoch,
+            seq: 0,
+            fields: BTreeMap::new(),
+        }
+    }
+
+    /// Take in the current `fields`, giving the ones that changed the next
+    /// sequence number. Returns the latest sequence number.
+    pub fn observe(&mut self, fields: BTreeMap<String, Value>) -> u64 {
+        let changed: Vec<(String, Value)> = fields
+            .into_iter()
+            .filter(|(name, value)| {
+                self.fields
+                    .get(name)
+                    .is_none_or(|(_, known)| known != value)
+            })
+            .collect();
+        if !changed.is_empty() {
+            self.seq += 1;
+        }
+        for (name, value) in changed {
+            self.fields.insert(name, (self.seq, value));
+        }
+        self.seq
+    }
+
+    /// What a collector that synced up to `since` in `epoch` is missing.
+    pub fn delta(&self, epoch: Option<u64>, since: Option<u64>) -> Delta {
+        let since = since.filter(|since| epoch == Some(self.epoch) && *since <= self.seq);
+        let fields = self
+            .fields
+            .iter()
+            .filter(|(_, (seq, _))| since.is_none_or(|since| *seq > since))
+            .map(|(name, (_, value))| (name.clone(), value.clone()))
+            .collect();
+        Delta {
+            epoch: self.epoch,
+            seq: self.seq,
+            full: since.is_none(),
+            fields,
+        }
+    }
+}
+
+/// The log shared between the main loop and the listener.
+pub type SharedStateLog = Arc<Mutex<StateLog>>;
+
+/// Status and JSON body answering `request`, `None` for `304 Not Modified`.
+pub fn answer(
+    request: &Request,
+    token: &str,
+    log: &StateLog,
+    history: Option<&MetricsHistory>,
+) -> (&'static str, Option<Vec<u8>>) {
+    if !authorized(request, token) {
+        return ("401 Unauthorized", None);
+    }
+    let (path, query) = request
+        .target
+        .split_once('?')
+        .unwrap_or((request.target.as_str(), ""));
+    let history = match (path, history) {
+        ("/state", _) => None,
+        ("/metrics", Some(history)) => Some(history),
+        _ => return ("404 Not Found", None),
+    };
+    if request.method != "GET" {
+        return ("405 Method Not Allowed", None);
+    }
+
+    let mut epoch = None;
+    let mut since = None;
+    let mut points = None;
+    for (name, value) in query.split('&').filter_map(|pair| pair.split_once('=')) {
+        match name {
+            "epoch" => epoch = value.parse().ok(),
+            "since" => since = value.parse().ok(),
+            "points" => points = value.parse().ok(),
+            _ => (),
+        }
+    }
+
+    if let Some(history) = history {
+        return ("200 OK", serde_json::to_vec(&history.report(points)).ok());
+    }
+
+    let delta = log.delta(epoch, since);
+    if !delta.full && delta.fields.is_empty() {
+        return ("304 Not Modified", None);
+    }
+    ("200 OK", serde_json::to_vec(&delta).ok())
+}
+
+/// Check the bearer token.
+fn authorized(request: &Request, token: &str) -> bool {
+    match request
+        .headers
+        .get("authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+    {
+        Some(presented) => token_matches(presented, token),
+        None => false,
+    }
+}
+
+/// Whether `presented` is `token`, compared through MACs to keep it
+/// constant time.
+pub fn token_matches(presented: &str, token: &str) -> bool {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, token.as_bytes());
+    let expected = hmac::sign(&key, token.as_bytes());
+    hmac::verify(&key, presented.as_bytes(), expected.as_ref()).is_ok()
+}
+
+/// Bind `addr` and answer collectors from `log` and `history`.
+pub async fn serve(
+    addr: &str,
+    token: String,
+    log: SharedStateLog,
+    history: Option<SharedMetricsHistory>,
+) -> io::Result<()> {
+    let listener = privileges::bind(addr).await?;
+    log!(LogLevel::Info, "Serving state deltas on {}", addr);
+
+    shutdown::spawn(Stage::Health, async move {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(connection) => connection,
+                Err(err) => {
+                    log!(
+                        LogLevel::Warn,
+                        "State sync listener failed to accept: {}",
+                        err
+                    );
+                    continue;
+                }
+            };
+
+            let token = token.clone();
+            let log = log.clone();
+            let history = history.clone();
+            tokio::spawn(async move {
+                if let Err(err) = handle(stream, &token, &log, history.as_ref()).await {
+                    log!(
+                        LogLevel::Debug,
+                        "State sync request from {} failed: {}",
+                        peer,
+                        err
+                    );
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+async fn handle<S>(
+    mut stream: S,
+    token: &str,
+    log: &SharedStateLog,
+    history: Option<&SharedMetricsHistory>,
+) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let request = match read_request(&mut stream).await? {
+        Some(request) => request,
+        None => {
+            return respond_with(&mut stream, "400 Bad Request", "text/plain", b"").await;
+        }
+    };
+
+    let (status, body) = match (log.lock(), history.map(|history| history.lock())) {
+        (Ok(log), None) => answer(&request, token, &log, None),
+        (Ok(log), Some(Ok(history))) => answer(&request, token, &log, Some(&history)),
+        _ => ("500 Internal Server Error", None),
+    };
+    match body {
+        Some(body) => respond_with(&mut stream, status, "application/json", &body).await,
+        None => respond_with(&mut stream, status, "text/plain", b"").await,
+    }
+}
@@ -0,0 +1,423 @@
+//! Built-in static file server for projects whose build output is a site.
+//!
+//! Docs and other static sites used to need an nginx next to the runner just
+//! to serve `dist/`. With `[app_specific.static_server] enabled = true` the
+//! runner serves the build output itself. After every successful build the
+//! output is copied into a fresh release directory and a `current` symlink
+//! is swapped over to it with a single `rename(2)`, so requests never see a
+//! half written build and the previous release keeps being served when a
+//! build fails.
+
+use artisan_middleware::dusa_collection_utils;
+use dusa_collection_utils::core::logger::LogLevel;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs, io,
+    os::unix::fs::symlink,
+    path::{Component, Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_rustls::TlsAcceptor;
+
+use crate::global_child::{GLOBAL_ACME_CHALLENGES, GLOBAL_TLS_CONFIG};
+use crate::log;
+use crate::privileges;
+use crate::shutdown::{self, Stage};
+
+/// Path HTTP-01 challenges are requested on.
+const ACME_CHALLENGE_PREFIX: &str = "/.well-known/acme-challenge/";
+
+/// Largest request head we are willing to read.
+const MAX_REQUEST_HEAD: usize = 8192;
+
+/// `[app_specific.static_server]`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct StaticServerConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_static_bind")]
+    pub bind: String,
+    /// Build output to serve, relative to `project_path`.
+    #[serde(default = "default_static_root")]
+    pub root: String,
+    /// Where releases are kept, defaults to `/tmp/.<app_name>_static`.
+    #[serde(default)]
+    pub releases_dir: Option<String>,
+    /// Served for requests to a directory.
+    #[serde(default = "default_static_index")]
+    pub index: String,
+    /// Releases kept on disk, including the current one.
+    #[serde(default = "default_static_keep")]
+    pub keep_releases: usize,
+}
+
+impl Default for StaticServerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bind: default_static_bind(),
+            root: default_static_root(),
+            releases_dir: None,
+            index: default_static_index(),
+            keep_releases: default_static_keep(),
+        }
+    }
+}
+
+fn default_static_bind() -> String {
+    String::from("127.0.0.1:8080")
+}
+
+fn default_static_root() -> String {
+    String::from("dist")
+}
+
+fn default_static_index() -> String {
+    String::from("index.html")
+}
+
+fn default_static_keep() -> usize {
+    2
+}
+
+impl StaticServerConfig {
+    pub fn releases_dir(&self, app_name: &str) -> PathBuf {
+        match &self.releases_dir {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(format!("/tmp/.{}_static", app_name)),
+        }
+    }
+}
+
+/// Copy the build output in `root` into a new release below `releases_dir`
+/// and point `releases_dir/current` at it.
+///
+/// Returns the new release directory.
+pub fn publish(root: &Path, releases_dir: &Path, keep_releases: usize) -> io::Result<PathBuf> {
+    if !root.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("build output {} doesn't exist", root.display()),
+        ));
+    }
+
+    let releases = releases_dir.join("releases");
+    fs::create_dir_all(&releases)?;
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis())
+        .unwrap_or_default();
+    let stamp = release_stamp(now, &release_names(&releases)?);
+    let release = releases.join(stamp.to_string());
+    copy_dir(root, &release)?;
+
+    let temp_link = releases_dir.join("current.tmp");
+    let _ = fs::remove_file(&temp_link);
+    symlink(&release, &temp_link)?;
+    fs::rename(&temp_link, releases_dir.join("current"))?;
+
+    prune(&releases, keep_releases.max(1))?;
+    Ok(release)
+}
+
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Name for a release published at `now` (unix millis).
+///
+/// Names are kept increasing even when the clock was set back, otherwise
+/// the new release would sort as the oldest and be pruned right away.
+pub fn release_stamp(now: u128, existing: &[u128]) -> u128 {
+    match existing.last() {
+        Some(newest) if *newest >= now => newest + 1,
+        _ => now,
+    }
+}
+
+/// Release names below `releases`, oldest first.
+fn release_names(releases: &Path) -> io::Result<Vec<u128>> {
+    let mut names: Vec<u128> = fs::read_dir(releases)?
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+        .collect();
+    names.sort_unstable();
+    Ok(names)
+}
+
+fn prune(releases: &Path, keep: usize) -> io::Result<()> {
+    // Names only ever increase, so sorting by name is sorting by age
+    let names = release_names(releases)?;
+    let stale = names.len().saturating_sub(keep);
+    for name in &names[..stale] {
+        fs::remove_dir_all(releases.join(name.to_string()))?;
+    }
+    Ok(())
+}
+
+/// Map a request target onto a file below `current`.
+///
+/// Query strings are dropped, percent escapes decoded, and anything trying
+/// to leave `current` is refused.
+pub fn resolve(current: &Path, target: &str, index: &str) -> Option<PathBuf> {
+    let path = target.split(['?', '#']).next().unwrap_or_default();
+    let decoded = percent_decode(path)?;
+
+    let mut resolved = current.to_path_buf();
+    for component in Path::new(&decoded).components() {
+        match component {
+            Component::Normal(part) => resolved.push(part),
+            Component::RootDir | Component::CurDir => (),
+            Component::ParentDir | Component::Prefix(_) => return None,
+        }
+    }
+
+    if resolved.is_dir() {
+        resolved.push(index);
+    }
+    Some(resolved)
+}
+
+fn percent_decode(input: &str) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == b'%' {
+            let hex = input.get(index + 1..index + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            index += 3;
+        } else {
+            decoded.push(bytes[index]);
+            index += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// `Content-Type` guessed from the file extension.
+pub fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default();
+
+    match extension.to_ascii_lowercase().as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" | "map" => "application/json",
+        "txt" | "md" => "text/plain; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Bind `config.bind` and serve `releases_dir/current` until the runner
+/// exits.
+pub async fn serve(config: &StaticServerConfig, releases_dir: PathBuf) -> io::Result<()> {
+    let listener = privileges::bind(&config.bind).await?;
+    log!(LogLevel::Info, "Serving static files on {}", config.bind);
+
+    let current = releases_dir.join("current");
+    let index = config.index.clone();
+    shutdown::spawn(Stage::Child, async move {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(connection) => connection,
+                Err(err) => {
+                    log!(LogLevel::Warn, "Static server failed to accept: {}", err);
+                    continue;
+                }
+            };
+
+            let current = current.clone();
+            let index = index.clone();
+            tokio::spawn(async move {
+                if let Err(err) = handle(stream, &current, &index).await {
+                    log!(
+                        LogLevel::Debug,
+                        "Static request from {} failed: {}",
+                        peer,
+                        err
+                    );
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+/// Serve `releases_dir/current` over HTTPS on `bind`, using whatever
+/// certificate is currently in [`GLOBAL_TLS_CONFIG`].
+///
+/// Connections are refused until the first certificate is installed.
+pub async fn serve_tls(
+    config: &StaticServerConfig,
+    bind: &str,
+    releases_dir: PathBuf,
+) -> io::Result<()> {
+    let listener = privileges::bind(bind).await?;
+    log!(
+        LogLevel::Info,
+        "Serving static files over https on {}",
+        bind
+    );
+
+    let current = releases_dir.join("current");
+    let index = config.index.clone();
+    shutdown::spawn(Stage::Child, async move {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(connection) => connection,
+                Err(err) => {
+                    log!(LogLevel::Warn, "Static server failed to accept: {}", err);
+                    continue;
+                }
+            };
+
+            // Taken per connection so a renewed certificate applies right away
+            let tls = match GLOBAL_TLS_CONFIG.lock().await.clone() {
+                Some(tls) => tls,
+                None => continue,
+            };
+
+            let current = current.clone();
+            let index = index.clone();
+            tokio::spawn(async move {
+                let result = match TlsAcceptor::from(tls).accept(stream).await {
+                    Ok(stream) => handle(stream, &current, &index).await,
+                    Err(err) => Err(err),
+                };
+                if let Err(err) = result {
+                    log!(
+                        LogLevel::Debug,
+                        "Static request from {} failed: {}",
+                        peer,
+                        err
+                    );
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+async fn handle<S>(mut stream: S, current: &Path, index: &str) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut head = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        head.extend_from_slice(&buffer[..read]);
+        if head.len() > MAX_REQUEST_HEAD {
+            return respond(
+                &mut stream,
+                "431 Request Header Fields Too Large",
+                None,
+                false,
+            )
+            .await;
+        }
+    }
+
+    let head = String::from_utf8_lossy(&head);
+    let mut request_line = head.lines().next().unwrap_or_default().split_whitespace();
+    let (method, target) = match (request_line.next(), request_line.next()) {
+        (Some(method), Some(target)) => (method, target),
+        _ => return respond(&mut stream, "400 Bad Request", None, false).await,
+    };
+
+    let with_body = match method {
+        "GET" => true,
+        "HEAD" => false,
+        _ => return respond(&mut stream, "405 Method Not Allowed", None, false).await,
+    };
+
+    if let Some(token) = target.strip_prefix(ACME_CHALLENGE_PREFIX) {
+        let key_authorization = GLOBAL_ACME_CHALLENGES.lock().await.get(token).cloned();
+        return match key_authorization {
+            Some(key_authorization) => {
+                let body = key_authorization.into_bytes();
+                respond(
+                    &mut stream,
+                    "200 OK",
+                    Some(("application/octet-stream", body)),
+                    with_body,
+                )
+                .await
+            }
+            None => respond(&mut stream, "404 Not Found", None, with_body).await,
+        };
+    }
+
+    let file = match resolve(current, target, index) {
+        Some(file) => file,
+        None => return respond(&mut stream, "400 Bad Request", None, false).await,
+    };
+
+    match tokio::fs::read(&file).await {
+        Ok(body) => {
+            respond(
+                &mut stream,
+                "200 OK",
+                Some((content_type(&file), body)),
+                with_body,
+            )
+            .await
+        }
+        Err(_) => respond(&mut stream, "404 Not Found", None, with_body).await,
+    }
+}
+
+async fn respond<S>(
+    stream: &mut S,
+    status: &str,
+    content: Option<(&str, Vec<u8>)>,
+    with_body: bool,
+) -> io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let (content_type, body) =
+        content.unwrap_or(("text/plain; charset=utf-8", status.as_bytes().to_vec()));
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+
+    stream.write_all(head.as_bytes()).await?;
+    if with_body {
+        stream.write_all(&body).await?;
+    }
+    stream.shutdown().await
+}
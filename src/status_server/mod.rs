@@ -0,0 +1,287 @@
+//! gRPC status service the Artisan manager queries runners through.
+//!
+//! The manager used to read each runner's state file, which only works on
+//! the same host and breaks whenever the file's layout changes. With
+//! `[app_specific.status_server]` set the runner serves
+//! `status_service.RunnerStatus`, on the same tonic stack the secret server
+//! client uses:
+//!
+//! ```toml
+//! [app_specific.status_server]
+//! addr = "0.0.0.0:50060"
+//! token = "..."
+//! ```
+//!
+//! - `GetStatus` answers with the state the runner last wrote, the child's
+//!   pid, the port, maintenance and the pending changes,
+//! - `GetMetrics` with the child's current usage and, with the metrics
+//!   history on, up to `points` buckets of it,
+//! - `TailLogs` streams the last `lines` of the child's output and, with
+//!   `follow`, new lines as they reach the state,
+//! - `TriggerRestart` rebuilds and respawns the child like `ais_runner
+//!   restart`, with an optional note.
+//!
+//! Every call has to carry `authorization: Bearer <token>`.
+
+pub mod status_service {
+    tonic::include_proto!("status_service");
+}
+
+use artisan_middleware::dusa_collection_utils::core::{
+    logger::LogLevel, types::pathtype::PathType,
+};
+use artisan_middleware::state_persistence::{AppState, StatePersistence};
+use artisan_middleware::timestamp::current_timestamp;
+use serde::{Deserialize, Serialize};
+use std::{
+    io,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+use tokio::{sync::mpsc, time::sleep};
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::{Request, Response, Status, service::Interceptor, transport::Server};
+
+use crate::capture::OutputCapture;
+use crate::child_manager;
+use crate::global_child::{GLOBAL_CHILD_PID, GLOBAL_PENDING_CHANGES};
+use crate::log;
+use crate::metrics_history::SharedMetricsHistory;
+use crate::output::{OutputLine, Stream, merged, merged_tail};
+use crate::privileges;
+use crate::runner_state::{RunnerState, update_runner_state};
+use crate::shutdown::{self, Stage};
+use crate::state_sync::token_matches;
+use status_service::{
+    GetMetricsRequest, GetStatusRequest, LogLine, MetricsBucket, MetricsReply, StatusReply,
+    TailLogsRequest, TriggerRestartReply, TriggerRestartRequest,
+    runner_status_server::{RunnerStatus, RunnerStatusServer},
+};
+
+/// How often a followed tail looks for new lines.
+const FOLLOW_INTERVAL: Duration = Duration::from_secs(1);
+/// Lines a slow client can fall behind a followed tail by.
+const TAIL_BUFFER: usize = 1000;
+
+/// `[app_specific.status_server]`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct StatusServerConfig {
+    /// Address to serve on, e.g. `"0.0.0.0:50060"`. Off when unset.
+    #[serde(default)]
+    pub addr: Option<String>,
+    /// Bearer token callers authenticate with, required with `addr`.
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+/// Answers the manager from the runner's state.
+#[derive(Debug, Clone)]
+pub struct StatusService {
+    state_path: PathType,
+    /// Set to have the main loop rebuild and respawn the child.
+    reload: Arc<AtomicBool>,
+    history: Option<SharedMetricsHistory>,
+}
+
+impl StatusService {
+    pub fn new(
+        state_path: PathType,
+        reload: Arc<AtomicBool>,
+        history: Option<SharedMetricsHistory>,
+    ) -> Self {
+        Self {
+            state_path,
+            reload,
+            history,
+        }
+    }
+
+    async fn state(&self) -> Result<AppState, Status> {
+        StatePersistence::load_state(&self.state_path)
+            .await
+            .map_err(|err| Status::unavailable(format!("Failed to load state: {}", err)))
+    }
+}
+
+fn log_lines(lines: &[OutputLine]) -> Vec<LogLine> {
+    lines
+        .iter()
+        .map(|line| LogLine {
+            timestamp: line.timestamp,
+            stream: line.stream.to_string(),
+            line: line.line.to_owned(),
+        })
+        .collect()
+}
+
+#[tonic::async_trait]
+impl RunnerStatus for StatusService {
+    type TailLogsStream = ReceiverStream<Result<LogLine, Status>>;
+
+    async fn get_status(
+        &self,
+        _request: Request<GetStatusRequest>,
+    ) -> Result<Response<StatusReply>, Status> {
+        let state = self.state().await?;
+        let runner_state = RunnerState::load(&self.state_path);
+        Ok(Response::new(StatusReply {
+            app_name: state.config.app_name.to_string(),
+            status: state.status.to_string(),
+            data: state.data.clone(),
+            runner_pid: state.pid,
+            child_pid: GLOBAL_CHILD_PID.load(Ordering::Relaxed),
+            event_counter: state.event_counter,
+            last_updated: state.last_updated,
+            errors: state.error_log.iter().map(|err| err.to_string()).collect(),
+            port: runner_state.port.map(u32::from).unwrap_or(0),
+            maintenance: runner_state.maintenance.is_some(),
+            pending_changes: GLOBAL_PENDING_CHANGES.load(Ordering::Relaxed),
+        }))
+    }
+
+    async fn get_metrics(
+        &self,
+        request: Request<GetMetricsRequest>,
+    ) -> Result<Response<MetricsReply>, Status> {
+        let points = request.into_inner().points as usize;
+        let metrics = child_manager::metrics().await;
+        let mut reply = MetricsReply {
+            running: metrics.is_some(),
+            ..MetricsReply::default()
+        };
+        if let Some(metrics) = metrics {
+            reply.cpu_percent = metrics.cpu_usage;
+            reply.memory_mb = metrics.memory_usage;
+        }
+        let history = self
+            .history
+            .as_ref()
+            .filter(|_| points > 0)
+            .and_then(|history| {
+                history
+                    .lock()
+                    .ok()
+                    .map(|history| history.report(Some(points)))
+            });
+        if let Some(report) = history {
+            reply.resolution_seconds = report.resolution_seconds;
+            reply.history = report
+                .buckets
+                .iter()
+                .map(|bucket| MetricsBucket {
+                    start: bucket.start,
+                    cpu: bucket.cpu,
+                    memory: bucket.memory,
+                    memory_max: bucket.memory_max,
+                })
+                .collect();
+        }
+        Ok(Response::new(reply))
+    }
+
+    async fn tail_logs(
+        &self,
+        request: Request<TailLogsRequest>,
+    ) -> Result<Response<Self::TailLogsStream>, Status> {
+        let request = request.into_inner();
+        let state = self.state().await?;
+        let stdout: &[(u64, String)] = match request.stderr_only {
+            true => &[],
+            false => &state.stdout,
+        };
+        let tail = log_lines(&merged_tail(stdout, &state.stderr, request.lines as usize));
+
+        // The same way `logs --follow` reads on from the capture files
+        let mut capture = OutputCapture::follow(&self.state_path);
+        let (tx, rx) = mpsc::channel(TAIL_BUFFER);
+        tokio::spawn(async move {
+            for line in tail {
+                if tx.send(Ok(line)).await.is_err() {
+                    return;
+                }
+            }
+            if !request.follow {
+                return;
+            }
+
+            loop {
+                tokio::select! {
+                    _ = tx.closed() => return,
+                    _ = sleep(FOLLOW_INTERVAL) => (),
+                }
+                let now = current_timestamp();
+                let stdout = match request.stderr_only {
+                    true => Vec::new(),
+                    false => capture.read_keyed(Stream::Stdout, now),
+                };
+                let stderr = capture.read_keyed(Stream::Stderr, now);
+                for line in log_lines(&merged(&stdout, &stderr)) {
+                    if tx.send(Ok(line)).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn trigger_restart(
+        &self,
+        request: Request<TriggerRestartRequest>,
+    ) -> Result<Response<TriggerRestartReply>, Status> {
+        let request = request.into_inner();
+        if !request.note.is_empty() || request.no_cache {
+            update_runner_state(&self.state_path, |runner_state| {
+                if !request.note.is_empty() {
+                    runner_state.pending_note = Some(request.note);
+                }
+                runner_state.no_cache_requested |= request.no_cache;
+            });
+        }
+        self.reload.store(true, Ordering::Relaxed);
+        log!(
+            LogLevel::Info,
+            "Restart requested through the status service"
+        );
+        Ok(Response::new(TriggerRestartReply {}))
+    }
+}
+
+/// Lets calls with `authorization: Bearer <token>` through.
+#[derive(Debug, Clone)]
+struct Bearer(String);
+
+impl Interceptor for Bearer {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        let presented = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        match presented.is_some_and(|presented| token_matches(presented, &self.0)) {
+            true => Ok(request),
+            false => Err(Status::unauthenticated("Missing or wrong token")),
+        }
+    }
+}
+
+/// Bind `addr` and serve `service` to callers presenting `token`.
+pub async fn serve(addr: &str, token: String, service: StatusService) -> io::Result<()> {
+    let listener = privileges::bind(addr).await?;
+    log!(LogLevel::Info, "Serving the status service on {}", addr);
+
+    let server = RunnerStatusServer::with_interceptor(service, Bearer(token));
+    shutdown::spawn(Stage::Health, async move {
+        let served = Server::builder()
+            .add_service(server)
+            .serve_with_incoming(TcpListenerStream::new(listener))
+            .await;
+        if let Err(err) = served {
+            log!(LogLevel::Error, "Status service stopped: {}", err);
+        }
+    });
+    Ok(())
+}
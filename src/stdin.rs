@@ -0,0 +1,177 @@
+//! Writing to the child's stdin.
+//!
+//! Game servers, REPL-style daemons and the like take admin commands on
+//! stdin. With `stdin_pipe = true` every child is spawned with its stdin on
+//! a pipe the runner keeps open, and the runner listens on a unix socket
+//! next to the state file, `<state file>.stdin`. `ais_runner send-stdin
+//! <text>` connects to it and has the text written to the current child's
+//! stdin.
+//!
+//! A request is the text itself, the client closes its side once it's
+//! sent. The runner answers with `ok` or `error: <reason>` on one line,
+//! text for a child that isn't running or closed its stdin is refused.
+//! The socket is only accessible to the runner's user.
+
+use artisan_middleware::dusa_collection_utils::core::{
+    logger::LogLevel, types::pathtype::PathType,
+};
+use std::{
+    fs,
+    io::{self, PipeReader, PipeWriter, Write},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{UnixListener, UnixStream},
+};
+
+use crate::log;
+use crate::shutdown::{self, Stage};
+
+/// Largest text taken in one request.
+pub const MAX_TEXT: usize = 64 * 1024;
+
+/// Socket text for the child's stdin is sent to, next to the state file.
+pub fn socket_path(state_path: &PathType) -> PathBuf {
+    PathBuf::from(format!("{}.stdin", state_path))
+}
+
+/// The write end of the current child's stdin and the socket feeding it.
+#[derive(Debug, Clone)]
+pub struct StdinPipe {
+    path: PathBuf,
+    writer: Arc<Mutex<Option<PipeWriter>>>,
+}
+
+impl StdinPipe {
+    /// Bind `path` and start passing what arrives on it to the child.
+    pub fn listen(path: &Path) -> io::Result<Self> {
+        // Left behind by a previous run
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+
+        let pipe = Self {
+            path: path.to_path_buf(),
+            writer: Arc::new(Mutex::new(None)),
+        };
+
+        let serving = pipe.clone();
+        shutdown::spawn(Stage::Monitors, async move {
+            loop {
+                let stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(err) => {
+                        log!(LogLevel::Warn, "Stdin socket failed: {}", err);
+                        return;
+                    }
+                };
+                serving.answer(stream).await;
+            }
+        });
+
+        Ok(pipe)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// A new pipe for the child about to be spawned, text sent from now on
+    /// goes to it.
+    pub fn attach(&self) -> io::Result<PipeReader> {
+        let (reader, writer) = io::pipe()?;
+        if let Ok(mut current) = self.writer.lock() {
+            *current = Some(writer);
+        }
+        Ok(reader)
+    }
+
+    /// Write `text` to the current child's stdin. Blocks while the pipe is
+    /// full, a child that doesn't read its stdin holds up the sender.
+    pub fn write(&self, text: &[u8]) -> Result<(), String> {
+        let writer = match self.writer.lock() {
+            Ok(current) => current.as_ref().map(PipeWriter::try_clone),
+            Err(_) => None,
+        };
+        let mut writer = match writer {
+            Some(Ok(writer)) => writer,
+            Some(Err(err)) => return Err(format!("Can't write to the child's stdin: {}", err)),
+            None => return Err(String::from("No child has been spawned yet")),
+        };
+        match writer.write_all(text) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == io::ErrorKind::BrokenPipe => {
+                Err(String::from("The child isn't running or closed its stdin"))
+            }
+            Err(err) => Err(format!("Can't write to the child's stdin: {}", err)),
+        }
+    }
+
+    async fn answer(&self, mut stream: UnixStream) {
+        let mut text = Vec::new();
+        let read = (&mut stream)
+            .take(MAX_TEXT as u64 + 1)
+            .read_to_end(&mut text)
+            .await;
+        let result = match read {
+            Err(err) => Err(format!("Failed to read the request: {}", err)),
+            Ok(_) if text.len() > MAX_TEXT => Err(format!("More than {} bytes at once", MAX_TEXT)),
+            Ok(_) => {
+                let pipe = self.clone();
+                let length = text.len();
+                let written = tokio::task::spawn_blocking(move || pipe.write(&text)).await;
+                match written {
+                    Ok(Ok(())) => {
+                        log!(LogLevel::Info, "Sent {} bytes to the child's stdin", length);
+                        Ok(())
+                    }
+                    Ok(Err(err)) => Err(err),
+                    Err(err) => Err(err.to_string()),
+                }
+            }
+        };
+        let reply = match &result {
+            Ok(()) => String::from("ok\n"),
+            Err(err) => {
+                log!(
+                    LogLevel::Warn,
+                    "Refused text for the child's stdin: {}",
+                    err
+                );
+                format!("error: {}\n", err)
+            }
+        };
+        _ = stream.write_all(reply.as_bytes()).await;
+    }
+}
+
+/// Have the runner listening on `path` write `text` to its child's stdin.
+pub async fn send(path: &Path, text: &[u8]) -> Result<(), String> {
+    let mut stream = UnixStream::connect(path)
+        .await
+        .map_err(|err| format!("Failed to connect to {}: {}", path.display(), err))?;
+    stream
+        .write_all(text)
+        .await
+        .map_err(|err| format!("Failed to send the text: {}", err))?;
+    stream
+        .shutdown()
+        .await
+        .map_err(|err| format!("Failed to send the text: {}", err))?;
+
+    let mut reply = String::new();
+    stream
+        .read_to_string(&mut reply)
+        .await
+        .map_err(|err| format!("Failed to read the answer: {}", err))?;
+    match reply.trim_end().strip_prefix("error: ") {
+        Some(err) => Err(err.to_owned()),
+        None if reply.trim_end() == "ok" => Ok(()),
+        None => Err(format!("Unexpected answer {:?}", reply)),
+    }
+}
@@ -0,0 +1,174 @@
+//! Decisions of the supervision loop.
+//!
+//! The main loop does the I/O: it watches directories, polls the child and
+//! runs restarts. What to make of a file change or a dead child is decided
+//! here, without touching processes or the clock, so the same decisions can
+//! be replayed by [`crate::sim`] against a virtual clock.
+
+use std::time::Instant;
+
+use crate::{
+    child::{ChildExit, should_restart},
+    config::AppSpecificConfig,
+    crash_loop::CrashLoopBreaker,
+    restart::{RestartKind, RestartReason},
+    watch::{ChangeBatch, ChangeTally},
+};
+
+/// What to do about a child that died or turned unhealthy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    /// Restart it.
+    Respawn(RestartReason),
+    /// It exited with a code it isn't restarted on, leave it down.
+    Idle(ChildExit),
+    /// It keeps crashing, respawns are suspended until an operator steps
+    /// in or a new build is deployed.
+    CrashLoop(RestartReason),
+    /// It died during maintenance, leave it down until maintenance mode is
+    /// off.
+    Paused(RestartReason),
+    /// Nothing to do, the child is idle or respawns are suspended already.
+    Hold,
+}
+
+/// State the supervision decisions depend on.
+#[derive(Debug)]
+pub struct Supervisor {
+    settings: AppSpecificConfig,
+    tally: ChangeTally,
+    /// When the first change counted since the last deploy came in.
+    first_change: Option<Instant>,
+    breaker: CrashLoopBreaker,
+    /// Set once the child exited with a code it isn't restarted on.
+    idle: bool,
+    maintenance: bool,
+    /// Set once the child died during the current maintenance.
+    paused: bool,
+}
+
+impl Supervisor {
+    pub fn new(settings: &AppSpecificConfig) -> Self {
+        Self {
+            settings: settings.clone(),
+            tally: ChangeTally::new(&settings.watch_paths(), settings.changes_needed),
+            first_change: None,
+            breaker: CrashLoopBreaker::new(settings.crash_loop.clone()),
+            idle: false,
+            maintenance: false,
+            paused: false,
+        }
+    }
+
+    pub fn breaker(&self) -> &CrashLoopBreaker {
+        &self.breaker
+    }
+
+    /// Whether the child is left down, idle, crash looping or paused for
+    /// maintenance.
+    pub fn is_down(&self) -> bool {
+        self.idle || self.breaker.is_open() || self.paused
+    }
+
+    /// Count a change in the `index`th watched directory at `now`,
+    /// returning the deploy once enough changes piled up. Changes made
+    /// during maintenance are ignored.
+    pub fn on_change(&mut self, index: usize, now: Instant) -> Option<RestartReason> {
+        if self.maintenance {
+            return None;
+        }
+        self.first_change.get_or_insert(now);
+        self.tally
+            .record(index)
+            .map(|detail| RestartReason::new(RestartKind::Changes, detail))
+    }
+
+    /// Count the paths `batch` touched like `on_change` counts one change.
+    pub fn on_batch(&mut self, batch: &ChangeBatch, now: Instant) -> Option<RestartReason> {
+        if self.maintenance || batch.changes() == 0 {
+            return None;
+        }
+        self.first_change.get_or_insert(now);
+        self.tally
+            .record_many(batch.index, batch.changes())
+            .map(|detail| RestartReason::new(RestartKind::Changes, detail))
+    }
+
+    /// Changes counted towards the next deploy, in all directories.
+    pub fn pending_changes(&self) -> u32 {
+        self.tally.pending()
+    }
+
+    /// When the first change counted towards the next deploy came in.
+    pub fn first_change(&self) -> Option<Instant> {
+        self.first_change
+    }
+
+    /// Decide what to do about the child failing for `reason`, at `now`.
+    pub fn on_failure(&mut self, reason: RestartReason, now: Instant) -> Decision {
+        if self.breaker.is_open() || (self.idle && reason.kind == RestartKind::Exited) {
+            return Decision::Hold;
+        }
+        if self.maintenance {
+            return match self.paused {
+                true => Decision::Hold,
+                false => {
+                    self.paused = true;
+                    Decision::Paused(reason)
+                }
+            };
+        }
+
+        let final_exit = reason
+            .exit
+            .filter(|exit| !should_restart(&self.settings, Some(*exit)));
+        if let Some(exit) = final_exit {
+            self.idle = true;
+            return Decision::Idle(exit);
+        }
+
+        match self.breaker.allow_restart(now) {
+            true => Decision::Respawn(reason),
+            false => Decision::CrashLoop(reason),
+        }
+    }
+
+    /// Switch maintenance mode on or off. Edits made during maintenance
+    /// don't count towards the next rebuild. Returns whether the child died
+    /// during the maintenance that just ended and should be respawned.
+    pub fn set_maintenance(&mut self, active: bool) -> bool {
+        let ended = self.maintenance && !active;
+        if ended {
+            self.reset_changes();
+        }
+        self.maintenance = active;
+        ended && std::mem::take(&mut self.paused)
+    }
+
+    /// Apply reloaded `settings`. Changes counted so far are kept unless the
+    /// watched directories or their thresholds changed.
+    pub fn reconfigure(&mut self, settings: &AppSpecificConfig) {
+        let tally = ChangeTally::new(&settings.watch_paths(), settings.changes_needed);
+        if tally.thresholds() != self.tally.thresholds() {
+            self.tally = tally;
+            self.first_change = None;
+        }
+        self.breaker.reconfigure(settings.crash_loop.clone());
+        self.settings = settings.clone();
+    }
+
+    /// Start counting changes from zero, after a deploy.
+    pub fn reset_changes(&mut self) {
+        self.tally.reset();
+        self.first_change = None;
+    }
+
+    /// Resume respawning after a new build was deployed or an operator
+    /// restarted the child, returning whether respawns were suspended.
+    pub fn resume(&mut self) -> bool {
+        let suspended = self.breaker.is_open();
+        self.breaker.reset();
+        self.idle = false;
+        suspended
+    }
+}
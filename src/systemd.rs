@@ -0,0 +1,67 @@
+//! Minimal `sd_notify` support.
+//!
+//! When the runner is started by systemd with `NotifyAccess` enabled the
+//! `NOTIFY_SOCKET` environment variable points at a datagram socket that
+//! accepts state updates. This lets the runner tell systemd that it is
+//! stopping and ask for more time than `TimeoutStopSec` while the child drains.
+
+use artisan_middleware::dusa_collection_utils;
+use dusa_collection_utils::core::logger::LogLevel;
+use std::{env, io, os::unix::net::UnixDatagram, time::Duration};
+
+use crate::log;
+
+/// Send a raw state string (e.g. `STOPPING=1`) to systemd.
+///
+/// Returns `false` when the runner isn't supervised by systemd or the
+/// message couldn't be delivered.
+pub fn notify(state: &str) -> bool {
+    let socket_path = match env::var("NOTIFY_SOCKET") {
+        Ok(path) if !path.is_empty() => path,
+        _ => return false,
+    };
+
+    let socket = match UnixDatagram::unbound() {
+        Ok(socket) => socket,
+        Err(err) => {
+            log!(LogLevel::Debug, "Failed to create notify socket: {}", err);
+            return false;
+        }
+    };
+
+    let result = match socket_path.strip_prefix('@') {
+        Some(abstract_name) => send_abstract(&socket, abstract_name, state),
+        None => socket.send_to(state.as_bytes(), &socket_path),
+    };
+
+    match result {
+        Ok(_) => true,
+        Err(err) => {
+            log!(LogLevel::Debug, "Failed to notify systemd: {}", err);
+            false
+        }
+    }
+}
+
+/// Send to a socket in the abstract namespace, `@` stripped from `name`.
+#[cfg(target_os = "linux")]
+fn send_abstract(socket: &UnixDatagram, name: &str, state: &str) -> io::Result<usize> {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::SocketAddr;
+
+    let addr = SocketAddr::from_abstract_name(name.as_bytes())?;
+    socket.send_to_addr(state.as_bytes(), &addr)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn send_abstract(_socket: &UnixDatagram, _name: &str, _state: &str) -> io::Result<usize> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "abstract sockets are only supported on Linux",
+    ))
+}
+
+/// Ask systemd to extend the current start/stop timeout by `duration`.
+pub fn extend_timeout(duration: Duration) -> bool {
+    notify(&format!("EXTEND_TIMEOUT_USEC={}", duration.as_micros()))
+}
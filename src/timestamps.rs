@@ -0,0 +1,183 @@
+//! Timestamp selection for captured output lines.
+//!
+//! By default lines are keyed by the time the runner captured them. With
+//! `timestamp_source = "child"` the runner instead looks for a timestamp at
+//! the start of every line (in one of the configured `timestamp_formats`) and
+//! uses it for ordering and deduplication, falling back to the capture time
+//! when nothing parses.
+//!
+//! Keys are unix seconds like the capture times they're mixed with. The
+//! milliseconds a child timestamp has beyond that aren't lost though, see
+//! [`subsecond`]: lines within the same second are ordered by them.
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::AppSpecificConfig;
+
+/// Where the key of a captured line comes from.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TimestampSource {
+    #[default]
+    Capture,
+    Child,
+}
+
+/// Leading timestamp formats understood when `timestamp_source = "child"`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampFormat {
+    /// `2024-10-16T12:34:56Z`, `2024-10-16T12:34:56.123+02:00`
+    Rfc3339,
+    /// `2024-10-16 12:34:56`, interpreted as UTC
+    Iso8601,
+    /// Unix seconds, `1729082096`
+    Epoch,
+    /// Unix milliseconds, `1729082096123`
+    EpochMs,
+}
+
+pub fn default_timestamp_formats() -> Vec<TimestampFormat> {
+    vec![
+        TimestampFormat::Rfc3339,
+        TimestampFormat::Iso8601,
+        TimestampFormat::EpochMs,
+        TimestampFormat::Epoch,
+    ]
+}
+
+/// Pick the key for `line` according to the configured source.
+pub fn line_timestamp(line: &str, captured_at: u64, settings: &AppSpecificConfig) -> u64 {
+    match settings.timestamp_source {
+        TimestampSource::Capture => captured_at,
+        TimestampSource::Child => {
+            parse_leading(line, &settings.timestamp_formats).unwrap_or(captured_at)
+        }
+    }
+}
+
+/// Parse a timestamp at the start of `line`, returning unix seconds.
+///
+/// Leading whitespace and a single opening `[` are skipped so common
+/// `[2024-10-16T12:34:56Z] INFO ...` prefixes work.
+pub fn parse_leading(line: &str, formats: &[TimestampFormat]) -> Option<u64> {
+    parse_leading_millis(line, formats).map(|millis| millis / 1000)
+}
+
+/// [`parse_leading`] in unix milliseconds, with the fraction of a second
+/// the timestamp has.
+pub fn parse_leading_millis(line: &str, formats: &[TimestampFormat]) -> Option<u64> {
+    let trimmed = line.trim_start();
+    let trimmed = trimmed.strip_prefix('[').unwrap_or(trimmed);
+
+    formats.iter().find_map(|format| match format {
+        TimestampFormat::Rfc3339 => parse_datetime(trimmed, 'T'),
+        TimestampFormat::Iso8601 => parse_datetime(trimmed, ' '),
+        TimestampFormat::Epoch => parse_epoch(trimmed, 10).map(|seconds| seconds * 1000),
+        TimestampFormat::EpochMs => parse_epoch(trimmed, 13),
+    })
+}
+
+/// Milliseconds into the second `key` that `line`'s own timestamp has, to
+/// order lines with the same key. 0 when the line has no timestamp in that
+/// second, so an unrelated one can't move it.
+pub fn subsecond(key: u64, line: &str) -> u32 {
+    let formats = [
+        TimestampFormat::Rfc3339,
+        TimestampFormat::Iso8601,
+        TimestampFormat::EpochMs,
+    ];
+    match parse_leading_millis(line, &formats) {
+        Some(millis) if millis / 1000 == key => (millis % 1000) as u32,
+        _ => 0,
+    }
+}
+
+fn parse_epoch(text: &str, digits: usize) -> Option<u64> {
+    let number: &str = &text[..text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len())];
+    match number.len() == digits {
+        true => number.parse().ok(),
+        false => None,
+    }
+}
+
+fn parse_number(text: &str, range: std::ops::Range<usize>) -> Option<i64> {
+    let part = text.get(range)?;
+    match part.bytes().all(|b| b.is_ascii_digit()) {
+        true => part.parse().ok(),
+        false => None,
+    }
+}
+
+/// `YYYY-MM-DD<sep>HH:MM:SS[.fff][Z|+HH:MM|-HH:MM]`, in unix milliseconds.
+fn parse_datetime(text: &str, separator: char) -> Option<u64> {
+    let bytes = text.as_bytes();
+    if bytes.len() < 19
+        || bytes[4] != b'-'
+        || bytes[7] != b'-'
+        || bytes[10] != separator as u8
+        || bytes[13] != b':'
+        || bytes[16] != b':'
+    {
+        return None;
+    }
+
+    let year = parse_number(text, 0..4)?;
+    let month = parse_number(text, 5..7)?;
+    let day = parse_number(text, 8..10)?;
+    let hour = parse_number(text, 11..13)?;
+    let minute = parse_number(text, 14..16)?;
+    let second = parse_number(text, 17..19)?;
+
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+
+    // Milliseconds of the fraction, further digits are dropped
+    let mut rest = &text[19..];
+    let mut millis = 0;
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let end = fraction
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(fraction.len());
+        millis = fraction[..end]
+            .bytes()
+            .chain(std::iter::repeat(b'0'))
+            .take(3)
+            .fold(0, |millis, digit| millis * 10 + i64::from(digit - b'0'));
+        rest = &fraction[end..];
+    }
+
+    let offset_seconds = match rest.as_bytes().first() {
+        Some(b'+') | Some(b'-') if rest.len() >= 6 && rest.as_bytes()[3] == b':' => {
+            let sign = if rest.starts_with('-') { -1 } else { 1 };
+            let hours = parse_number(rest, 1..3)?;
+            let minutes = parse_number(rest, 4..6)?;
+            sign * (hours * 3600 + minutes * 60)
+        }
+        _ => 0,
+    };
+
+    let seconds = days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second
+        - offset_seconds;
+
+    u64::try_from(seconds * 1000 + millis).ok()
+}
+
+/// Days since 1970-01-01 for a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = (if year >= 0 { year } else { year - 399 }) / 400;
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
@@ -0,0 +1,241 @@
+//! Uptime and SLA reports (`report --period 30d`).
+//!
+//! Every lifecycle transition is appended to a JSON lines log at
+//! `<state path>.transitions` along with whether a child was serving at
+//! the time, so a build-first deploy doesn't count as downtime while the
+//! old child keeps answering. Entries older than [`RETENTION`] are dropped
+//! when the runner starts.
+//!
+//! From that log and the restart history a report gives, over the period:
+//!
+//! - the share of time a child was serving,
+//! - the outages, stretches nothing was serving, and the mean time to
+//!   recover from them,
+//! - restarts by cause and how many failed,
+//! - how often new code was deployed.
+//!
+//! The runner itself being down isn't recorded, the time between a crash
+//! of the runner and its next start counts as whatever was logged last.
+
+use artisan_middleware::dusa_collection_utils::core::types::pathtype::PathType;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::lifecycle::Phase;
+use crate::runner_state::{HISTORY_LIMIT, RestartRecord};
+
+/// How long transitions are kept.
+pub const RETENTION: u64 = 90 * DAY;
+
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+const WEEK: u64 = 7 * DAY;
+
+/// A lifecycle transition as logged.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct Transition {
+    pub timestamp: u64,
+    pub phase: Phase,
+    /// Whether a child was answering from then on.
+    pub serving: bool,
+}
+
+/// Transition log location for the state file at `state_path`.
+pub fn path(state_path: &PathType) -> PathBuf {
+    PathBuf::from(format!("{}.transitions", state_path))
+}
+
+/// Append `transition` to the log at `path`.
+pub fn append(path: &Path, transition: &Transition) -> io::Result<()> {
+    let mut line = serde_json::to_vec(transition)?;
+    line.push(b'\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(&line)
+}
+
+/// Read the log at `path`, oldest first.
+///
+/// A missing log is empty and lines that don't parse are skipped.
+pub fn read(path: &Path) -> io::Result<Vec<Transition>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    Ok(content
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// Drop transitions from before `cutoff`, except the last of them which
+/// tells what was going on at `cutoff`. Returns how many went away.
+pub fn prune(path: &Path, cutoff: u64) -> io::Result<usize> {
+    let transitions = read(path)?;
+    let old = transitions
+        .iter()
+        .take_while(|transition| transition.timestamp < cutoff)
+        .count();
+    let dropped = old.saturating_sub(1);
+    if dropped == 0 {
+        return Ok(0);
+    }
+
+    let mut content = Vec::new();
+    for transition in &transitions[dropped..] {
+        content.extend(serde_json::to_vec(transition)?);
+        content.push(b'\n');
+    }
+    let temp = path.with_extension("transitions.tmp");
+    fs::write(&temp, content)?;
+    fs::rename(&temp, path)?;
+    Ok(dropped)
+}
+
+/// Parse a period like `30d`, `12h`, `2w` or `90m` into seconds.
+pub fn parse_period(period: &str) -> Result<u64, String> {
+    let invalid = || {
+        format!(
+            "Invalid period {:?}, expected a number with m, h, d or w like 30d",
+            period
+        )
+    };
+    let unit = match period.chars().last().ok_or_else(invalid)? {
+        'm' => 60,
+        'h' => HOUR,
+        'd' => DAY,
+        'w' => WEEK,
+        _ => return Err(invalid()),
+    };
+    let count: u64 = period[..period.len() - 1].parse().map_err(|_| invalid())?;
+    match count {
+        0 => Err(invalid()),
+        count => Ok(count * unit),
+    }
+}
+
+/// What [`report`] found.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Report {
+    pub from: u64,
+    pub to: u64,
+    /// Seconds of the period the transition log covers, less than the
+    /// period when it doesn't reach back far enough.
+    pub covered_seconds: u64,
+    /// Seconds a child was serving.
+    pub serving_seconds: u64,
+    /// Share of the covered time a child was serving.
+    pub uptime_percent: Option<f64>,
+    /// Outages that ended within the period.
+    pub outages: usize,
+    /// Mean length of those outages.
+    pub mttr_seconds: Option<u64>,
+    /// Start of the outage still going on at the end of the period.
+    pub down_since: Option<u64>,
+    /// Restarts by cause, `unknown` for those recorded before causes were.
+    pub restarts: BTreeMap<String, usize>,
+    pub failed_restarts: usize,
+    pub deploys: usize,
+    pub deploys_per_week: f64,
+    /// Oldest restart kept when the restart history doesn't reach back to
+    /// the start of the period, the counts above start there.
+    pub restarts_since: Option<u64>,
+}
+
+/// Summarize `transitions` and `restarts` over `from..to`.
+pub fn report(
+    transitions: &[Transition],
+    restarts: &[RestartRecord],
+    from: u64,
+    to: u64,
+) -> Report {
+    let mut covered_seconds = 0;
+    let mut serving_seconds = 0;
+    let mut outages = Vec::new();
+    let mut down_since = None;
+
+    for (index, transition) in transitions.iter().enumerate() {
+        let end = transitions
+            .get(index + 1)
+            .map_or(to, |next| next.timestamp)
+            .min(to);
+        let start = transition.timestamp.max(from);
+        if start < end {
+            covered_seconds += end - start;
+            if transition.serving {
+                serving_seconds += end - start;
+            }
+        }
+
+        // Outages are judged on the whole log so one spanning the start of
+        // the period counts when it ends inside it
+        let was_serving = index > 0 && transitions[index - 1].serving;
+        match (down_since, transition.serving) {
+            (None, false) if was_serving => down_since = Some(transition.timestamp),
+            (Some(since), true) => {
+                if (from..to).contains(&transition.timestamp) {
+                    outages.push(transition.timestamp - since);
+                }
+                down_since = None;
+            }
+            _ => (),
+        }
+        if transition.timestamp >= to {
+            break;
+        }
+    }
+
+    let mut counts = BTreeMap::new();
+    let mut failed_restarts = 0;
+    let mut deploys = 0;
+    for record in restarts
+        .iter()
+        .filter(|record| (from..to).contains(&record.timestamp))
+    {
+        let cause = record.kind.map_or("unknown", |kind| kind.name());
+        *counts.entry(String::from(cause)).or_insert(0) += 1;
+        if record.success == Some(false) {
+            failed_restarts += 1;
+        }
+        if record.kind.is_some_and(|kind| kind.is_deploy()) {
+            deploys += 1;
+        }
+    }
+    let restarts_since = match restarts.first() {
+        Some(oldest) if oldest.timestamp > from && restarts.len() >= HISTORY_LIMIT => {
+            Some(oldest.timestamp)
+        }
+        _ => None,
+    };
+    let weeks = (to - from) as f64 / WEEK as f64;
+
+    Report {
+        from,
+        to,
+        covered_seconds,
+        serving_seconds,
+        uptime_percent: match covered_seconds {
+            0 => None,
+            covered => Some(serving_seconds as f64 * 100.0 / covered as f64),
+        },
+        outages: outages.len(),
+        mttr_seconds: match outages.len() {
+            0 => None,
+            count => Some(outages.iter().sum::<u64>() / count as u64),
+        },
+        down_since,
+        restarts: counts,
+        failed_restarts,
+        deploys,
+        deploys_per_week: deploys as f64 / weeks,
+        restarts_since,
+    }
+}
@@ -0,0 +1,628 @@
+//! Checking the whole `[app_specific]` config before anything starts.
+//!
+//! Deserializing only tells whether `Config.toml` has the right shape, a
+//! typo in a path or an address that doesn't parse used to surface much
+//! later, deep in whatever used it. [`validate`] checks every field that
+//! can be checked without side effects (paths exist, commands parse the
+//! way a shell would, intervals aren't zero, addresses parse, options that
+//! depend on each other are set together) and returns all problems at
+//! once. The runner refuses to start on any of them, `validate-config` and
+//! `--dry-run` print them.
+//!
+//! [`explain_load_error`] turns what the config loader reports into the
+//! same terms.
+
+use artisan_middleware::dusa_collection_utils::core::types::pathtype::PathType;
+use config::ConfigError;
+use std::{collections::HashSet, fmt, net::SocketAddr, path::Path};
+
+#[cfg(feature = "state-redis")]
+use crate::state_backend::{RedisBackend, StateBackendConfig};
+use crate::{
+    build_cache::check_pattern,
+    build_executor::BuildExecutorConfig,
+    build_steps::{check_graph, is_valid_name},
+    child::{KillMode, resolve_identity},
+    command_vars::CommandVars,
+    config::AppSpecificConfig,
+    cpu_limit::CpuLimitAction,
+    log_rules::{self, ready_pattern},
+    notifications,
+    notify_throttle::QuietHours,
+    schedule::CronSchedule,
+    secrets::template::{placeholders, template_path},
+};
+
+/// Something wrong with one option.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    /// The option, e.g. `app_specific.cgroup.enabled`.
+    pub key: String,
+    pub message: String,
+}
+
+impl Problem {
+    fn new(key: &str, message: impl Into<String>) -> Self {
+        Self {
+            key: format!("app_specific.{}", key),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.key, self.message)
+    }
+}
+
+/// All `problems` as one message, one per line.
+pub fn report(problems: &[Problem]) -> String {
+    let mut report = match problems.len() {
+        1 => String::from("Config.toml has a problem:"),
+        count => format!("Config.toml has {} problems:", count),
+    };
+    for problem in problems {
+        report.push_str(&format!("\n  - {}", problem));
+    }
+    report
+}
+
+/// Whether `address` is a `host:port`. Host names aren't resolved, DNS
+/// may well not be up yet when the runner starts on boot.
+pub fn is_address(address: &str) -> bool {
+    if address.parse::<SocketAddr>().is_ok() {
+        return true;
+    }
+    match address.rsplit_once(':') {
+        Some((host, port)) => {
+            !host.is_empty()
+                && !host.contains(|c: char| c.is_whitespace() || c == '/')
+                && port.parse::<u16>().is_ok()
+        }
+        None => false,
+    }
+}
+
+fn check_addr(problems: &mut Vec<Problem>, key: &str, address: &str) {
+    if !is_address(address) {
+        problems.push(Problem::new(
+            key,
+            format!("{:?} isn't a host:port like 127.0.0.1:8080", address),
+        ));
+    }
+}
+
+fn check_command(problems: &mut Vec<Problem>, vars: &CommandVars, key: &str, command: &str) {
+    match shell_words::split(command) {
+        Ok(parts) if parts.is_empty() => problems.push(Problem::new(key, "is empty")),
+        Ok(_) => (),
+        Err(err) => problems.push(Problem::new(
+            key,
+            format!("can't be parsed ({}), check its quotes", err),
+        )),
+    }
+    for placeholder in vars.unknown(command) {
+        problems.push(Problem::new(
+            key,
+            format!(
+                "refers to {{{}}}, which isn't a variable, define it in [app_specific.command_vars]",
+                placeholder
+            ),
+        ));
+    }
+}
+
+fn check_steps(problems: &mut Vec<Problem>, vars: &CommandVars, settings: &AppSpecificConfig) {
+    if settings.steps.is_empty() {
+        return;
+    }
+    if settings.install_command.is_some() || settings.build_command.is_some() {
+        problems.push(Problem::new(
+            "steps",
+            "replace install_command and build_command, use one or the other",
+        ));
+    }
+    if matches!(settings.build_executor, BuildExecutorConfig::Nix(_)) {
+        problems.push(Problem::new(
+            "steps",
+            "aren't run by the nix build executor, it builds the expression instead",
+        ));
+    }
+
+    if let Some(limit) = settings.max_parallel_steps {
+        check_positive(problems, "max_parallel_steps", limit as u64);
+    }
+
+    let project_path = PathType::Content(settings.project_path.clone());
+    let mut names = HashSet::new();
+    for (index, step) in settings.steps.iter().enumerate() {
+        let key = format!("steps[{}]", index);
+        if !is_valid_name(&step.name) {
+            problems.push(Problem::new(
+                &format!("{}.name", key),
+                format!("{:?} can only have letters, digits, - and _", step.name),
+            ));
+        } else if !names.insert(step.name.as_str()) {
+            problems.push(Problem::new(
+                &format!("{}.name", key),
+                format!("{:?} is used by an earlier step", step.name),
+            ));
+        }
+        check_command(problems, vars, &format!("{}.command", key), &step.command);
+        if let Some(timeout) = step.timeout_seconds {
+            check_positive(problems, &format!("{}.timeout_seconds", key), timeout);
+        }
+        check_patterns(problems, &format!("{}.inputs", key), &step.inputs);
+        check_patterns(problems, &format!("{}.outputs", key), &step.outputs);
+        let cwd = match &step.cwd {
+            Some(cwd) => project_path.join(cwd),
+            None => continue,
+        };
+        if project_path.exists() && !cwd.is_dir() {
+            problems.push(Problem::new(
+                &format!("{}.cwd", key),
+                format!("{} isn't a directory", cwd.display()),
+            ));
+        }
+    }
+
+    for (index, message) in check_graph(&settings.steps) {
+        problems.push(Problem::new(
+            &format!("steps[{}].depends_on", index),
+            message,
+        ));
+    }
+}
+
+/// Flag the glob patterns of `key` the build cache can't expand.
+fn check_patterns(problems: &mut Vec<Problem>, key: &str, patterns: &[String]) {
+    for (index, pattern) in patterns.iter().enumerate() {
+        if let Err(err) = check_pattern(pattern) {
+            problems.push(Problem::new(&format!("{}[{}]", key, index), err));
+        }
+    }
+}
+
+fn check_positive(problems: &mut Vec<Problem>, key: &str, value: u64) {
+    if value == 0 {
+        problems.push(Problem::new(key, "has to be greater than 0"));
+    }
+}
+
+/// Everything wrong with `settings`, empty when it's good to go.
+pub fn validate(settings: &AppSpecificConfig) -> Vec<Problem> {
+    let mut problems = Vec::new();
+
+    let commands = [
+        ("run_command", Some(&settings.run_command)),
+        ("build_command", settings.build_command.as_ref()),
+        ("install_command", settings.install_command.as_ref()),
+    ];
+    let vars = CommandVars::new(settings, "");
+    for (key, command) in commands {
+        if let Some(command) = command {
+            check_command(&mut problems, &vars, key, command);
+        }
+    }
+    check_steps(&mut problems, &vars, settings);
+    check_patterns(&mut problems, "build_inputs", &settings.build_inputs);
+    check_patterns(&mut problems, "build_outputs", &settings.build_outputs);
+
+    check_positive(
+        &mut problems,
+        "interval_seconds",
+        settings.interval_seconds as u64,
+    );
+    check_positive(
+        &mut problems,
+        "shutdown_timeout_seconds",
+        settings.shutdown_timeout_seconds,
+    );
+    if settings.polling.fallback {
+        check_positive(
+            &mut problems,
+            "polling.interval_seconds",
+            settings.polling.interval_seconds,
+        );
+    }
+    let cadence = &settings.cadence;
+    for (key, seconds) in [
+        ("cadence.health_seconds", cadence.health_seconds),
+        ("cadence.metrics_seconds", cadence.metrics_seconds),
+        ("cadence.cleanup_seconds", cadence.cleanup_seconds),
+        ("cadence.output_millis", cadence.output_millis),
+    ] {
+        check_positive(&mut problems, key, seconds);
+    }
+    let shutdown = &settings.shutdown;
+    for (key, timeout) in [
+        (
+            "shutdown.monitors_timeout_seconds",
+            shutdown.monitors_timeout_seconds,
+        ),
+        (
+            "shutdown.health_timeout_seconds",
+            shutdown.health_timeout_seconds,
+        ),
+        (
+            "shutdown.logs_timeout_seconds",
+            shutdown.logs_timeout_seconds,
+        ),
+        (
+            "shutdown.state_timeout_seconds",
+            shutdown.state_timeout_seconds,
+        ),
+    ] {
+        check_positive(&mut problems, key, timeout);
+    }
+    for (key, timeout) in [
+        ("install_timeout_seconds", settings.install_timeout_seconds),
+        ("build_timeout_seconds", settings.build_timeout_seconds),
+    ] {
+        if let Some(timeout) = timeout {
+            check_positive(&mut problems, key, timeout);
+        }
+    }
+    if settings.metrics_history.enabled() {
+        check_positive(
+            &mut problems,
+            "metrics_history.resolution_seconds",
+            settings.metrics_history.resolution_seconds,
+        );
+    }
+    if settings.cpu_limit.max_cpu_percent.is_some() {
+        check_positive(
+            &mut problems,
+            "cpu_limit.window_seconds",
+            settings.cpu_limit.window_seconds,
+        );
+    }
+    if settings.notifications.enabled() {
+        check_positive(
+            &mut problems,
+            "notifications.replay_interval_seconds",
+            settings.notifications.replay_interval_seconds,
+        );
+        let throttle = &settings.notifications.throttle;
+        if throttle.max_per_kind > 0 {
+            check_positive(
+                &mut problems,
+                "notifications.throttle.window_minutes",
+                throttle.window_minutes,
+            );
+        }
+        let quiet_hours = throttle.quiet_hours.as_deref();
+        if let Some(Err(err)) = quiet_hours.map(|hours| hours.parse::<QuietHours>()) {
+            problems.push(Problem::new("notifications.throttle.quiet_hours", err));
+        }
+    }
+    check_positive(
+        &mut problems,
+        "diagnostics_lines",
+        settings.diagnostics_lines as u64,
+    );
+
+    // A URI for the gRPC client, the scheme is optional
+    let secret_server = &settings.secret_server_addr;
+    let secret_server = match secret_server.split_once("://") {
+        Some((_, rest)) => rest.trim_end_matches('/'),
+        None => secret_server,
+    };
+    check_addr(&mut problems, "secret_server_addr", secret_server);
+    if let Some(addr) = &settings.webhook_addr {
+        check_addr(&mut problems, "webhook_addr", addr);
+    }
+    if let Some(addr) = &settings.status_server.addr {
+        check_addr(&mut problems, "status_server.addr", addr);
+    }
+    if let Some(addr) = &settings.state_sync.addr {
+        check_addr(&mut problems, "state_sync.addr", addr);
+    }
+    if let Some(addr) = &settings.log_shipping.addr {
+        let addr = match addr.split_once("://") {
+            Some((_, rest)) => rest.trim_end_matches('/'),
+            None => addr,
+        };
+        check_addr(&mut problems, "log_shipping.addr", addr);
+        let shipping = &settings.log_shipping;
+        for (key, value) in [
+            ("log_shipping.batch_lines", shipping.batch_lines as u64),
+            ("log_shipping.queue_limit", shipping.queue_limit as u64),
+            (
+                "log_shipping.flush_interval_seconds",
+                shipping.flush_interval_seconds,
+            ),
+            ("log_shipping.timeout_seconds", shipping.timeout_seconds),
+        ] {
+            check_positive(&mut problems, key, value);
+        }
+        if let Some(Err(err)) = shipping.tls.as_ref().map(|tls| tls.validate()) {
+            problems.push(Problem::new("log_shipping.tls", err));
+        }
+    }
+    let proxy = &settings.proxy;
+    if let Some(listen) = &proxy.listen {
+        check_addr(&mut problems, "proxy.listen", listen);
+        if let Some(minutes) = proxy.idle_minutes {
+            check_positive(&mut problems, "proxy.idle_minutes", minutes);
+        }
+        check_positive(
+            &mut problems,
+            "proxy.wake_timeout_seconds",
+            proxy.wake_timeout_seconds,
+        );
+        check_positive(&mut problems, "proxy.hold_seconds", proxy.hold_seconds);
+        let page = proxy
+            .hold_page
+            .as_deref()
+            .filter(|page| !Path::new(page).is_file());
+        if let Some(page) = page {
+            problems.push(Problem::new(
+                "proxy.hold_page",
+                format!("{} doesn't exist", page),
+            ));
+        }
+    }
+    if settings.listen_fds.enabled {
+        for addr in &settings.listen_fds.addrs {
+            check_addr(&mut problems, "listen_fds.addrs", addr);
+        }
+    }
+    if let Some(endpoint) = &settings.otel_endpoint {
+        let addr = endpoint
+            .strip_prefix("http://")
+            .or_else(|| endpoint.strip_prefix("https://"));
+        match addr {
+            Some(addr) => check_addr(&mut problems, "otel_endpoint", addr.trim_end_matches('/')),
+            None => problems.push(Problem::new(
+                "otel_endpoint",
+                format!("{:?} needs an http:// or https:// scheme", endpoint),
+            )),
+        }
+    }
+    if settings.static_server.enabled {
+        check_addr(
+            &mut problems,
+            "static_server.bind",
+            &settings.static_server.bind,
+        );
+    }
+
+    if settings.drop_privileges && settings.run_as_user.is_none() && settings.run_as_group.is_none()
+    {
+        problems.push(Problem::new(
+            "drop_privileges",
+            "is set without run_as_user or run_as_group to switch to",
+        ));
+    }
+
+    if let Err(err) = notifications::templates(&settings.notifications) {
+        problems.push(Problem::new("notifications.endpoints", err));
+    }
+
+    if settings.cpu_limit.action == CpuLimitAction::Throttle && !settings.cgroup.enabled {
+        problems.push(Problem::new(
+            "cpu_limit.action",
+            "\"throttle\" needs [app_specific.cgroup] enabled = true",
+        ));
+    }
+
+    if let Some(Err(err)) = settings.ready_check.as_ref().map(|check| check.validate()) {
+        problems.push(Problem::new("ready_check", err));
+    }
+
+    if let Err(err) = log_rules::validate(&settings.log_rules) {
+        problems.push(Problem::new("log_rules", err));
+    }
+    if settings.ready_check.is_some() && ready_pattern(&settings.log_rules).is_some() {
+        problems.push(Problem::new(
+            "log_rules",
+            "ready rules are ignored while ready_check is set, use one or the other",
+        ));
+    }
+
+    if let Some(Err(err)) = settings.secret_tls.as_ref().map(|tls| tls.validate()) {
+        problems.push(Problem::new("secret_tls", err));
+    }
+
+    if let Some(Err(err)) = settings
+        .secret_reload
+        .as_ref()
+        .map(|reload| reload.signal())
+    {
+        problems.push(Problem::new("secret_reload", err));
+    }
+
+    let schedule = settings.restart_schedule.as_ref();
+    if let Some(Err(err)) = schedule.map(|expression| expression.parse::<CronSchedule>()) {
+        problems.push(Problem::new(
+            "restart_schedule",
+            format!("is invalid: {}", err),
+        ));
+    }
+
+    let executor = &settings.build_executor;
+    if matches!(executor, BuildExecutorConfig::Docker(docker) if docker.image.trim().is_empty()) {
+        problems.push(Problem::new("build_executor.image", "can't be empty"));
+    }
+
+    let notify_ready = settings
+        .ready_check
+        .as_ref()
+        .is_some_and(|check| check.notify);
+    if notify_ready && !settings.heartbeat.enabled {
+        problems.push(Problem::new(
+            "ready_check.notify",
+            "needs heartbeat.enabled, the child has nowhere to report to",
+        ));
+    }
+
+    if settings.kill_mode == KillMode::Cgroup && !settings.cgroup.enabled {
+        problems.push(Problem::new(
+            "kill_mode",
+            "\"cgroup\" needs [app_specific.cgroup] enabled = true",
+        ));
+    }
+
+    if settings.webhook_addr.is_some() && settings.webhook_secret.is_none() {
+        problems.push(Problem::new(
+            "webhook_secret",
+            "has to be set when webhook_addr is",
+        ));
+    }
+
+    if settings.proxy.listen.is_some() && settings.port.is_none() {
+        problems.push(Problem::new(
+            "proxy.listen",
+            "needs port, connections are passed on to the child there",
+        ));
+    }
+
+    let listen_fds = &settings.listen_fds;
+    if listen_fds.enabled && listen_fds.addrs.is_empty() && settings.port.is_none() {
+        problems.push(Problem::new(
+            "listen_fds.addrs",
+            "needs port or addresses to bind",
+        ));
+    }
+
+    if settings.state_sync.addr.is_some() && settings.state_sync.token.is_none() {
+        problems.push(Problem::new(
+            "state_sync.token",
+            "has to be set when state_sync.addr is",
+        ));
+    }
+
+    if settings.status_server.addr.is_some() && settings.status_server.token.is_none() {
+        problems.push(Problem::new(
+            "status_server.token",
+            "has to be set when status_server.addr is",
+        ));
+    }
+
+    if let Err(err) = settings.state_backend.available() {
+        problems.push(Problem::new("state_backend.kind", err));
+    }
+    #[cfg(feature = "state-redis")]
+    {
+        let redis = match &settings.state_backend {
+            StateBackendConfig::Redis(redis) => RedisBackend::new(redis).err(),
+            _ => None,
+        };
+        if let Some(err) = redis {
+            problems.push(Problem::new("state_backend.url", err));
+        }
+    }
+
+    if settings.acme.enabled {
+        if let Err(err) = settings.acme.validate() {
+            problems.push(Problem::new("acme", err));
+        }
+        if !settings.static_server.enabled {
+            problems.push(Problem::new(
+                "static_server.enabled",
+                "acme answers challenges through the built-in server, it has to be enabled",
+            ));
+        }
+    }
+
+    if let Err(err) = resolve_identity(settings) {
+        problems.push(Problem::new("run_as_user", err.err_mesg.to_string()));
+    }
+
+    if let Some(path) = template_path(settings) {
+        match std::fs::read_to_string(&path) {
+            Ok(template) => {
+                if let Err(err) = placeholders(&template) {
+                    problems.push(Problem::new(
+                        "env_template",
+                        format!("{}: {}", path.display(), err),
+                    ));
+                }
+            }
+            Err(err) => problems.push(Problem::new(
+                "env_template",
+                format!("{} can't be read: {}", path.display(), err),
+            )),
+        }
+    }
+
+    let project_path = PathType::Content(settings.project_path.clone());
+    if !project_path.exists() {
+        problems.push(Problem::new(
+            "project_path",
+            format!("{} doesn't exist", settings.project_path),
+        ));
+    } else if let Some(dir) = &settings.working_dir {
+        let working_dir = project_path.join(dir);
+        if !working_dir.is_dir() {
+            problems.push(Problem::new(
+                "working_dir",
+                format!("{} isn't a directory", working_dir.display()),
+            ));
+        }
+    }
+    if settings.changes_needed < 1 {
+        problems.push(Problem::new("changes_needed", "has to be at least 1"));
+    }
+    for watch in settings.watch_paths() {
+        let key = match settings.monitor_paths.is_empty() {
+            true => "monitor_path",
+            false => "monitor_paths",
+        };
+        if !PathType::Content(watch.path.clone()).exists() {
+            problems.push(Problem::new(key, format!("{} doesn't exist", watch.path)));
+        }
+        if watch.changes_needed.is_some_and(|needed| needed < 1) {
+            problems.push(Problem::new(
+                key,
+                format!("changes_needed of {} has to be at least 1", watch.path),
+            ));
+        }
+    }
+
+    problems
+}
+
+/// What went wrong loading `Config.toml`, without the loader's internals.
+pub fn explain_load_error(err: &ConfigError) -> String {
+    match err {
+        ConfigError::NotFound(key) if key == "app_specific" => {
+            String::from("Config.toml has no [app_specific] table")
+        }
+        ConfigError::NotFound(key) => format!("{} is required", key),
+        ConfigError::FileParse { cause, .. } => {
+            format!("Config.toml isn't valid TOML: {}", cause)
+        }
+        ConfigError::Type {
+            key,
+            unexpected,
+            expected,
+            ..
+        } => format!(
+            "{}: expected {}, found {}",
+            key.as_deref().unwrap_or("app_specific"),
+            expected,
+            unexpected
+        ),
+        ConfigError::Message(message) => explain_message(message),
+        err => err.to_string(),
+    }
+}
+
+/// serde's `missing field `x`` and friends, in terms of the config keys.
+fn explain_message(message: &str) -> String {
+    let quoted = |message: &str| {
+        let start = message.find('`')? + 1;
+        let end = start + message[start..].find('`')?;
+        Some(message[start..end].to_owned())
+    };
+    match message.strip_prefix("missing field ") {
+        Some(rest) => match quoted(rest) {
+            Some(field) => format!("app_specific.{} is required", field),
+            None => message.to_owned(),
+        },
+        None => message.to_owned(),
+    }
+}
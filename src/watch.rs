@@ -0,0 +1,431 @@
+//! Watching several directories for changes.
+//!
+//! `monitor_path` covers a single directory. `monitor_paths` takes a list
+//! instead, each entry either a plain path or a table with its own
+//! `ignored_subdirs` and `changes_needed`:
+//!
+//! ```toml
+//! monitor_paths = [
+//!     "src",
+//!     { path = "static", ignored_subdirs = ["cache"], changes_needed = 20 },
+//! ]
+//! ```
+//!
+//! Relative entries are resolved against `project_path`. Every directory
+//! gets its own [`RawFileMonitor`], their events are merged into one
+//! channel and counted per directory by a [`ChangeTally`]. The first
+//! directory to reach its threshold triggers the rebuild, after which all
+//! counts start over.
+//!
+//! A `cargo build` or `npm install` in a watched tree touches thousands of
+//! files at once. Each monitor's events are coalesced into a
+//! [`ChangeBatch`] per `change_batch_ms` window, one entry per path with
+//! the kinds of changes and how many events it got, and only the batch
+//! reaches the main loop. A path counts once towards `changes_needed` per
+//! batch, however many events it saw.
+//!
+//! Directories inotify runs out of watches for are polled instead, see
+//! [`crate::polling`].
+
+use artisan_middleware::dusa_collection_utils;
+use dir_watcher::{MonitorMode, Options, RawFileMonitor, RecursiveMode};
+use dusa_collection_utils::core::{logger::LogLevel, types::pathtype::PathType};
+use notify::EventKind;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+use tokio::{
+    sync::mpsc,
+    time::{Instant, timeout_at},
+};
+
+use crate::log;
+use crate::polling::{self, PollingConfig};
+
+/// Batches buffered between the monitors and the main loop.
+const EVENT_BUFFER: usize = 256;
+/// Paths named in a batch's summary, the rest are only counted.
+const SUMMARY_PATHS: usize = 3;
+
+/// An entry of `monitor_paths` as written in the config.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+enum WatchPathEntry {
+    Path(String),
+    Table {
+        path: String,
+        #[serde(default)]
+        ignored_subdirs: Vec<String>,
+        #[serde(default)]
+        changes_needed: Option<i32>,
+    },
+}
+
+/// A watched directory.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(from = "WatchPathEntry")]
+pub struct WatchPath {
+    pub path: String,
+    /// Relative to `path`.
+    pub ignored_subdirs: Vec<String>,
+    /// Falls back to the global `changes_needed`.
+    pub changes_needed: Option<i32>,
+}
+
+impl From<WatchPathEntry> for WatchPath {
+    fn from(entry: WatchPathEntry) -> Self {
+        match entry {
+            WatchPathEntry::Path(path) => Self {
+                path,
+                ignored_subdirs: Vec::new(),
+                changes_needed: None,
+            },
+            WatchPathEntry::Table {
+                path,
+                ignored_subdirs,
+                changes_needed,
+            } => Self {
+                path,
+                ignored_subdirs,
+                changes_needed,
+            },
+        }
+    }
+}
+
+/// Where the ignored `subdir` of the directory at `base` is.
+///
+/// Leading `/` and `./` are dropped since the entry is always relative to
+/// `base`. Entries that would leave it (`..`) or ignore all of it (`.`, an
+/// empty string) give `None`.
+pub fn ignored_dir(base: &Path, subdir: &str) -> Option<PathBuf> {
+    let mut relative = PathBuf::new();
+    for component in Path::new(subdir).components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::RootDir | Component::CurDir => (),
+            Component::ParentDir | Component::Prefix(_) => return None,
+        }
+    }
+
+    match relative.as_os_str().is_empty() {
+        true => None,
+        false => Some(base.join(relative)),
+    }
+}
+
+/// Change counts per watched directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangeTally {
+    paths: Vec<String>,
+    thresholds: Vec<i32>,
+    counts: Vec<i32>,
+}
+
+impl ChangeTally {
+    pub fn new(paths: &[WatchPath], changes_needed: i32) -> Self {
+        Self {
+            paths: paths.iter().map(|watch| watch.path.clone()).collect(),
+            thresholds: paths
+                .iter()
+                .map(|watch| watch.changes_needed.unwrap_or(changes_needed))
+                .collect(),
+            counts: vec![0; paths.len()],
+        }
+    }
+
+    /// Count a change in the `index`th directory, returning the rebuild
+    /// reason once that directory reached its threshold.
+    pub fn record(&mut self, index: usize) -> Option<String> {
+        self.record_many(index, 1)
+    }
+
+    /// Count `changes` at once in the `index`th directory, like `record`.
+    pub fn record_many(&mut self, index: usize, changes: u32) -> Option<String> {
+        let count = self.counts.get_mut(index)?;
+        *count = count.saturating_add(changes.min(i32::MAX as u32) as i32);
+        let (count, threshold) = (*count, self.thresholds[index]);
+        log!(
+            LogLevel::Info,
+            "Change detected: {} out of {}",
+            count,
+            threshold
+        );
+
+        if count < threshold {
+            return None;
+        }
+        match self.paths.len() {
+            1 => Some(format!("{} file changes", count)),
+            _ => Some(format!("{} file changes in {}", count, self.paths[index])),
+        }
+    }
+
+    /// Directories and the changes each needs.
+    pub fn thresholds(&self) -> Vec<(&str, i32)> {
+        self.paths
+            .iter()
+            .map(String::as_str)
+            .zip(self.thresholds.iter().copied())
+            .collect()
+    }
+
+    /// Changes counted since the last reset, in all directories.
+    pub fn pending(&self) -> u32 {
+        self.counts.iter().map(|count| *count as u32).sum()
+    }
+
+    /// Start counting from zero, after a rebuild or maintenance.
+    pub fn reset(&mut self) {
+        self.counts.fill(0);
+    }
+}
+
+/// What happened to a path, as far as batching cares.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChangeKind {
+    Create,
+    Modify,
+    Remove,
+    Other,
+}
+
+impl From<&EventKind> for ChangeKind {
+    fn from(kind: &EventKind) -> Self {
+        match kind {
+            EventKind::Create(_) => Self::Create,
+            EventKind::Modify(_) => Self::Modify,
+            EventKind::Remove(_) => Self::Remove,
+            _ => Self::Other,
+        }
+    }
+}
+
+impl fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Create => "create",
+            Self::Modify => "modify",
+            Self::Remove => "remove",
+            Self::Other => "other",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Events a path got within a batch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathChange {
+    pub kinds: BTreeSet<ChangeKind>,
+    pub events: u32,
+}
+
+/// Events of one watched directory coalesced per path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangeBatch {
+    /// Index of the directory in the watched paths.
+    pub index: usize,
+    pub paths: BTreeMap<PathBuf, PathChange>,
+    /// Raw events that went into it.
+    pub events: u32,
+}
+
+impl ChangeBatch {
+    pub fn new(index: usize) -> Self {
+        Self {
+            index,
+            paths: BTreeMap::new(),
+            events: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events == 0
+    }
+
+    /// Add an event of `kind` touching `paths`.
+    pub fn add<I>(&mut self, kind: ChangeKind, paths: I)
+    where
+        I: IntoIterator<Item = PathBuf>,
+    {
+        self.events += 1;
+        for path in paths {
+            let change = self.paths.entry(path).or_insert_with(|| PathChange {
+                kinds: BTreeSet::new(),
+                events: 0,
+            });
+            change.kinds.insert(kind);
+            change.events += 1;
+        }
+    }
+
+    /// Paths touched, what `changes_needed` counts.
+    pub fn changes(&self) -> u32 {
+        self.paths.len() as u32
+    }
+
+    /// Paths touched per kind of change.
+    pub fn kinds(&self) -> BTreeMap<ChangeKind, u32> {
+        let mut kinds = BTreeMap::new();
+        for change in self.paths.values() {
+            for kind in &change.kinds {
+                *kinds.entry(*kind).or_insert(0) += 1;
+            }
+        }
+        kinds
+    }
+}
+
+impl fmt::Display for ChangeBatch {
+    /// `3 paths (modify 3) from 57 events: src/a.rs, src/b.rs, src/c.rs`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kinds: Vec<String> = self
+            .kinds()
+            .iter()
+            .map(|(kind, count)| format!("{} {}", kind, count))
+            .collect();
+        write!(
+            f,
+            "{} paths ({}) from {} events",
+            self.paths.len(),
+            kinds.join(", "),
+            self.events
+        )?;
+        let named: Vec<String> = self
+            .paths
+            .keys()
+            .take(SUMMARY_PATHS)
+            .map(|path| path.display().to_string())
+            .collect();
+        if !named.is_empty() {
+            write!(f, ": {}", named.join(", "))?;
+        }
+        match self.paths.len().saturating_sub(SUMMARY_PATHS) {
+            0 => Ok(()),
+            more => write!(f, " and {} more", more),
+        }
+    }
+}
+
+/// Start a monitor for every directory in `paths`.
+///
+/// Their events are coalesced for `batch_window` after the first one of a
+/// batch, a zero window passes every event on as a batch of its own.
+pub async fn start_monitors(
+    paths: &[WatchPath],
+    interval_seconds: u32,
+    batch_window: Duration,
+    polling: &PollingConfig,
+) -> Result<(Vec<RawFileMonitor>, mpsc::Receiver<ChangeBatch>), String> {
+    let (event_tx, event_rx) = mpsc::channel(EVENT_BUFFER);
+    let mut monitors = Vec::with_capacity(paths.len());
+
+    for (index, watch) in paths.iter().enumerate() {
+        let target = PathType::Content(watch.path.clone());
+        let target = match target.canonicalize() {
+            Ok(path) => PathType::PathBuf(path),
+            Err(err) => return Err(format!("Can't watch {}: {}", watch.path, err)),
+        };
+        let mut ignored = Vec::with_capacity(watch.ignored_subdirs.len());
+        for subdir in &watch.ignored_subdirs {
+            match ignored_dir(&target, subdir) {
+                Some(dir) => ignored.push(dir),
+                None => log!(
+                    LogLevel::Warn,
+                    "Ignoring ignored_subdirs entry {:?} of {}, it isn't below the directory",
+                    subdir,
+                    watch.path
+                ),
+            }
+        }
+
+        // Scanned instead when inotify can't cover all of it
+        if polling::watch_limit_reached(&target, &ignored) && polling.fallback {
+            log!(
+                LogLevel::Warn,
+                "Polling {} every {}s instead of watching it",
+                watch.path,
+                polling.interval_seconds
+            );
+            let period = Duration::from_secs(polling.interval_seconds.max(1));
+            polling::spawn(
+                index,
+                target.to_path_buf(),
+                ignored,
+                period,
+                event_tx.clone(),
+            );
+            continue;
+        }
+
+        let options: Options = Options::default()
+            .set_mode(RecursiveMode::Recursive)
+            .set_monitor_mode(MonitorMode::Modify)
+            .add_ignored_dirs(ignored.into_iter().map(PathType::PathBuf).collect())
+            .set_target_dir(target)
+            .set_interval(interval_seconds.into())
+            .set_validation(true);
+
+        let monitor = RawFileMonitor::new(options).await;
+        monitor.start().await;
+        let mut events = match monitor.subscribe().await {
+            Some(events) => events,
+            None => {
+                return Err(format!(
+                    "Failed to subscribe to the monitor of {}",
+                    watch.path
+                ));
+            }
+        };
+
+        let event_tx = event_tx.clone();
+        tokio::spawn(async move {
+            let mut batch = ChangeBatch::new(index);
+            let mut deadline = Instant::now();
+            loop {
+                let event = match batch.is_empty() {
+                    true => events.recv().await,
+                    false => match timeout_at(deadline, events.recv()).await {
+                        Ok(event) => event,
+                        // The window closed, hand the batch over
+                        Err(_) => {
+                            let full = std::mem::replace(&mut batch, ChangeBatch::new(index));
+                            match event_tx.send(full).await {
+                                Ok(()) => continue,
+                                Err(_) => return,
+                            }
+                        }
+                    },
+                };
+                let event = match event {
+                    Some(event) => event,
+                    None => {
+                        if !batch.is_empty() {
+                            _ = event_tx.send(batch).await;
+                        }
+                        return;
+                    }
+                };
+                if batch.is_empty() {
+                    deadline = Instant::now() + batch_window;
+                }
+                batch.add(ChangeKind::from(&event.kind), event.paths);
+                if batch_window.is_zero() {
+                    let single = std::mem::replace(&mut batch, ChangeBatch::new(index));
+                    if event_tx.send(single).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        log!(LogLevel::Debug, "Watching {}", watch.path);
+        monitors.push(monitor);
+    }
+
+    Ok((monitors, event_rx))
+}
@@ -0,0 +1,272 @@
+//! Webhook receiver that triggers rebuilds.
+//!
+//! Projects whose source isn't on the disk being watched (pull based
+//! deploys, artifacts fetched by the build) can't rely on file changes. With
+//! `webhook_addr` set the runner accepts POSTs from GitHub, GitLab or any CI
+//! and handles them like `changes_needed` file changes.
+//!
+//! Requests are authenticated with `webhook_secret`, either as an HMAC-SHA256
+//! of the body (`X-Hub-Signature-256: sha256=<hex>` from GitHub, or
+//! `X-Signature` in the same format) or as the plain token GitLab sends in
+//! `X-Gitlab-Token`.
+
+use artisan_middleware::dusa_collection_utils;
+use dusa_collection_utils::core::logger::LogLevel;
+use ring::hmac;
+use std::{collections::HashMap, io};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    sync::mpsc,
+};
+
+use crate::log;
+use crate::privileges;
+use crate::shutdown::{self, Stage};
+
+/// Largest request head we are willing to read.
+const MAX_REQUEST_HEAD: usize = 8192;
+/// Largest body accepted, GitHub caps payloads at 25 MB but push events are
+/// far smaller.
+const MAX_BODY: usize = 1024 * 1024;
+
+/// A rebuild requested through the webhook.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WebhookTrigger {
+    /// Shown as the restart reason, e.g. `github push webhook`.
+    pub reason: String,
+    /// `note` of the payload or the head commit message, attached to the
+    /// restart like an `annotate` note.
+    pub note: Option<String>,
+}
+
+/// A parsed request.
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    /// Path and query, e.g. `/state?since=4`.
+    pub target: String,
+    /// Header names are lower case.
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+/// Check the request against `secret`.
+pub fn authenticate(request: &Request, secret: &str) -> bool {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+
+    // Compared through their MACs to keep the comparison constant time
+    if let Some(token) = request.headers.get("x-gitlab-token") {
+        let expected = hmac::sign(&key, secret.as_bytes());
+        return hmac::verify(&key, token.as_bytes(), expected.as_ref()).is_ok();
+    }
+
+    let signature = request
+        .headers
+        .get("x-hub-signature-256")
+        .or_else(|| request.headers.get("x-signature"))
+        .and_then(|signature| signature.strip_prefix("sha256="))
+        .and_then(decode_hex);
+    let signature = match signature {
+        Some(signature) => signature,
+        None => return false,
+    };
+
+    hmac::verify(&key, &request.body, &signature).is_ok()
+}
+
+/// Decode hex, `None` on odd lengths or non hex digits.
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+/// What an authenticated request asks for, `None` for requests that
+/// shouldn't rebuild anything (GitHub's `ping`).
+pub fn trigger(request: &Request) -> Option<WebhookTrigger> {
+    let (source, event) = match (
+        request.headers.get("x-github-event"),
+        request.headers.get("x-gitlab-event"),
+    ) {
+        (Some(event), _) => ("github", event.as_str()),
+        (None, Some(event)) => ("gitlab", event.as_str()),
+        (None, None) => ("generic", "rebuild"),
+    };
+    if event == "ping" {
+        return None;
+    }
+
+    let payload: serde_json::Value = serde_json::from_slice(&request.body).unwrap_or_default();
+    let note = payload
+        .get("note")
+        .or_else(|| payload.pointer("/head_commit/message"))
+        .or_else(|| payload.pointer("/commits/0/message"))
+        .and_then(|note| note.as_str())
+        .map(|note| note.lines().next().unwrap_or_default().to_owned())
+        .filter(|note| !note.is_empty());
+
+    Some(WebhookTrigger {
+        reason: format!("{} {} webhook", source, event.to_lowercase()),
+        note,
+    })
+}
+
+/// Bind `addr` and forward authenticated requests to `triggers`.
+pub async fn serve(
+    addr: &str,
+    secret: String,
+    triggers: mpsc::Sender<WebhookTrigger>,
+) -> io::Result<()> {
+    let listener = privileges::bind(addr).await?;
+    log!(LogLevel::Info, "Accepting rebuild webhooks on {}", addr);
+
+    shutdown::spawn(Stage::Monitors, async move {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(connection) => connection,
+                Err(err) => {
+                    log!(LogLevel::Warn, "Webhook listener failed to accept: {}", err);
+                    continue;
+                }
+            };
+
+            let secret = secret.clone();
+            let triggers = triggers.clone();
+            tokio::spawn(async move {
+                if let Err(err) = handle(stream, &secret, &triggers).await {
+                    log!(
+                        LogLevel::Debug,
+                        "Webhook request from {} failed: {}",
+                        peer,
+                        err
+                    );
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+async fn handle<S>(
+    mut stream: S,
+    secret: &str,
+    triggers: &mpsc::Sender<WebhookTrigger>,
+) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let request = match read_request(&mut stream).await? {
+        Some(request) => request,
+        None => return respond(&mut stream, "400 Bad Request").await,
+    };
+
+    if request.method != "POST" {
+        return respond(&mut stream, "405 Method Not Allowed").await;
+    }
+    if !authenticate(&request, secret) {
+        log!(LogLevel::Warn, "Rejected webhook with a bad signature");
+        return respond(&mut stream, "401 Unauthorized").await;
+    }
+
+    match trigger(&request) {
+        Some(trigger) => {
+            log!(LogLevel::Info, "Rebuild requested by {}", trigger.reason);
+            _ = triggers.send(trigger).await;
+            respond(&mut stream, "202 Accepted").await
+        }
+        None => respond(&mut stream, "200 OK").await,
+    }
+}
+
+/// Read a request head and its `Content-Length` body, `None` when it's
+/// malformed or too large.
+pub async fn read_request<S>(stream: &mut S) -> io::Result<Option<Request>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut received = Vec::new();
+    let mut buffer = [0u8; 4096];
+    let head_end = loop {
+        if let Some(position) = received.windows(4).position(|window| window == b"\r\n\r\n") {
+            break position;
+        }
+        if received.len() > MAX_REQUEST_HEAD {
+            return Ok(None);
+        }
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            return Ok(None);
+        }
+        received.extend_from_slice(&buffer[..read]);
+    };
+
+    let head = String::from_utf8_lossy(&received[..head_end]).into_owned();
+    let mut lines = head.lines();
+    let mut request_line = lines.next().unwrap_or_default().split_whitespace();
+    let (method, target) = match (request_line.next(), request_line.next()) {
+        (Some(method), Some(target)) => (method.to_owned(), target.to_owned()),
+        _ => return Ok(None),
+    };
+    let headers: HashMap<String, String> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_owned()))
+        .collect();
+
+    let length: usize = match headers.get("content-length").map(|length| length.parse()) {
+        Some(Ok(length)) if length <= MAX_BODY => length,
+        Some(_) => return Ok(None),
+        None => 0,
+    };
+
+    let mut body = received.split_off(head_end + 4);
+    while body.len() < length {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            return Ok(None);
+        }
+        body.extend_from_slice(&buffer[..read]);
+    }
+    body.truncate(length);
+
+    Ok(Some(Request {
+        method,
+        target,
+        headers,
+        body,
+    }))
+}
+
+async fn respond<S>(stream: &mut S, status: &str) -> io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    respond_with(
+        stream,
+        status,
+        "text/plain; charset=utf-8",
+        status.as_bytes(),
+    )
+    .await
+}
+
+/// Write a complete response and close the connection.
+pub async fn respond_with<S>(
+    stream: &mut S,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body).await?;
+    stream.shutdown().await
+}
@@ -0,0 +1,58 @@
+use ais_runner::acme::{CertStore, needs_renewal, not_after, server_config};
+use std::{fs, os::unix::fs::PermissionsExt};
+
+/// 2030-01-01T00:00:00Z
+const EXPIRY: i64 = 1_893_456_000;
+const DAY: i64 = 24 * 60 * 60;
+
+fn self_signed() -> (String, String) {
+    let key = rcgen::KeyPair::generate().unwrap();
+    let mut params = rcgen::CertificateParams::new(vec![String::from("docs.example.com")]).unwrap();
+    params.not_after = rcgen::date_time_ymd(2030, 1, 1);
+    let cert = params.self_signed(&key).unwrap();
+    (cert.pem(), key.serialize_pem())
+}
+
+#[test]
+fn renews_close_to_expiry() {
+    let (cert, key) = self_signed();
+    assert_eq!(not_after(&cert), Some(EXPIRY));
+
+    assert!(!needs_renewal(Some(&cert), 30, EXPIRY - 60 * DAY));
+    assert!(needs_renewal(Some(&cert), 30, EXPIRY - 10 * DAY));
+    assert!(needs_renewal(None, 30, EXPIRY - 60 * DAY));
+    assert!(needs_renewal(Some("not a certificate"), 30, 0));
+
+    assert!(server_config(&cert, &key).is_ok());
+    assert!(server_config(&cert, "").is_err());
+}
+
+#[test]
+fn store_keeps_key_private() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = CertStore::new(dir.path().join("acme"));
+    assert!(store.load().is_none());
+
+    let (cert, key) = self_signed();
+    store.save(&cert, &key).unwrap();
+    assert_eq!(store.load(), Some((cert, key)));
+
+    let mode = fs::metadata(store.key_path()).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+}
+
+#[test]
+fn leftover_temp_files_are_not_reused() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = CertStore::new(dir.path().to_path_buf());
+    let temp = dir.path().join("key.pem.tmp");
+    fs::write(&temp, "stale").unwrap();
+    fs::set_permissions(&temp, fs::Permissions::from_mode(0o644)).unwrap();
+
+    let (cert, key) = self_signed();
+    store.save(&cert, &key).unwrap();
+    assert!(!temp.exists());
+    let mode = fs::metadata(store.key_path()).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    assert_eq!(store.load(), Some((cert, key)));
+}
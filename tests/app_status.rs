@@ -0,0 +1,108 @@
+use ais_runner::app_status::{
+    AppStatus, AppStatusConfig, AppStatusTracker, MAX_FIELDS, MAX_VALUE_LEN, describe, read_file,
+    set,
+};
+use ais_runner::heartbeat::ChildReport;
+use std::{fs, time::Instant};
+
+fn fields(pairs: &[(&str, &str)]) -> AppStatus {
+    pairs
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+#[test]
+fn heartbeat_fields_are_set_and_removed() {
+    let now = Instant::now();
+    let mut report = ChildReport::default();
+    report.apply("APP_queue_depth=12\nAPP_workers=4\nOTHER=1", now);
+    assert_eq!(
+        report.fields,
+        fields(&[("queue_depth", "12"), ("workers", "4")])
+    );
+
+    report.apply("APP_workers=", now);
+    assert_eq!(report.fields, fields(&[("queue_depth", "12")]));
+
+    report.reset(now);
+    assert!(report.fields.is_empty());
+}
+
+#[test]
+fn fields_stay_within_limits() {
+    let mut status = AppStatus::new();
+    for index in 0..MAX_FIELDS + 5 {
+        set(&mut status, &format!("key{}", index), "1");
+    }
+    assert_eq!(status.len(), MAX_FIELDS);
+    // Existing keys can still change
+    set(&mut status, "key0", "2");
+    assert_eq!(status["key0"], "2");
+
+    set(&mut status, "key1", &"é".repeat(MAX_VALUE_LEN + 10));
+    assert_eq!(status["key1"].chars().count(), MAX_VALUE_LEN);
+}
+
+#[test]
+fn status_file_values_are_stringified() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("status.json");
+    assert_eq!(read_file(&path), Ok(AppStatus::new()));
+
+    fs::write(
+        &path,
+        r#"{"queue_depth": 12, "mode": "drain", "healthy": true, "gone": null}"#,
+    )
+    .unwrap();
+    assert_eq!(
+        read_file(&path),
+        Ok(fields(&[
+            ("healthy", "true"),
+            ("mode", "drain"),
+            ("queue_depth", "12")
+        ]))
+    );
+
+    fs::write(&path, "[1, 2]").unwrap();
+    assert!(read_file(&path).is_err());
+}
+
+#[test]
+fn file_overrides_the_heartbeat_and_survives_bad_reads() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = AppStatusConfig {
+        file: Some(String::from("status.json")),
+    };
+    let path = config.path(&dir.path().to_string_lossy()).unwrap();
+    assert_eq!(path, dir.path().join("status.json"));
+
+    let mut tracker = AppStatusTracker::new(Some(path.clone()));
+    let from_child = fields(&[("queue_depth", "3"), ("workers", "4")]);
+    assert!(tracker.update(&from_child));
+    assert!(!tracker.update(&from_child));
+
+    fs::write(&path, r#"{"queue_depth": 12}"#).unwrap();
+    assert!(tracker.update(&from_child));
+    assert_eq!(
+        tracker.current(),
+        &fields(&[("queue_depth", "12"), ("workers", "4")])
+    );
+
+    // Half written, the last good read is kept
+    fs::write(&path, r#"{"queue_dep"#).unwrap();
+    assert!(!tracker.update(&from_child));
+}
+
+#[test]
+fn data_lists_the_fields() {
+    assert_eq!(describe("Nominal", &AppStatus::new()), "Nominal");
+    assert_eq!(
+        describe(
+            "Nominal",
+            &fields(&[("queue_depth", "12"), ("workers", "4")])
+        ),
+        "Nominal (queue_depth=12, workers=4)"
+    );
+    assert_eq!(AppStatusConfig::default().path("/srv/app"), None);
+}
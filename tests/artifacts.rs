@@ -0,0 +1,97 @@
+use ais_runner::artifacts::{Artifacts, Manifest, remove, sweep_pid_files, sweep_previous};
+use std::{collections::BTreeSet, fs, process::Command};
+
+// Above any pid_max, so never alive
+const DEAD_PID: u32 = 99_999_999;
+
+#[test]
+fn tracked_files_are_removed_on_clean_up() {
+    let dir = tempfile::tempdir().unwrap();
+    let manifest = dir.path().join("app.state.artifacts");
+    let pid_file = dir.path().join(".app_pg.pid");
+    let socket = dir.path().join("app.state.notify");
+    fs::write(&pid_file, "42").unwrap();
+    fs::write(&socket, "").unwrap();
+
+    let artifacts = Artifacts::new(manifest.clone());
+    artifacts.track(&pid_file);
+    artifacts.track(&socket);
+    artifacts.track(&pid_file);
+
+    let recorded = Manifest::load(&manifest).unwrap();
+    assert_eq!(recorded.pid, std::process::id());
+    assert_eq!(
+        recorded.paths,
+        BTreeSet::from([pid_file.clone(), socket.clone()])
+    );
+
+    assert_eq!(artifacts.clean_up(), 2);
+    assert!(!pid_file.exists());
+    assert!(!socket.exists());
+    assert!(!manifest.exists());
+}
+
+#[test]
+fn leftovers_of_a_dead_run_are_swept() {
+    let dir = tempfile::tempdir().unwrap();
+    let manifest = dir.path().join("app.state.artifacts");
+    let env_file = dir.path().join("app.env");
+    fs::write(&env_file, "KEY=value\n").unwrap();
+    fs::write(dir.path().join("app.env.tmp"), "KEY=va").unwrap();
+
+    Manifest {
+        pid: DEAD_PID,
+        paths: BTreeSet::from([env_file.clone(), dir.path().join("missing")]),
+    }
+    .save(&manifest)
+    .unwrap();
+
+    assert_eq!(sweep_previous(&manifest), Ok(2));
+    assert!(!env_file.exists());
+    assert!(!manifest.exists());
+    // Nothing left to do the second time
+    assert_eq!(sweep_previous(&manifest), Ok(0));
+}
+
+#[test]
+fn files_of_a_live_runner_are_left_alone() {
+    let dir = tempfile::tempdir().unwrap();
+    let manifest = dir.path().join("app.state.artifacts");
+    let env_file = dir.path().join("app.env");
+    fs::write(&env_file, "").unwrap();
+
+    let mut runner = Command::new("sleep").arg("30").spawn().unwrap();
+    Manifest {
+        pid: runner.id(),
+        paths: BTreeSet::from([env_file.clone()]),
+    }
+    .save(&manifest)
+    .unwrap();
+
+    assert!(sweep_previous(&manifest).is_err());
+    assert!(env_file.exists());
+    runner.kill().unwrap();
+    runner.wait().unwrap();
+}
+
+#[test]
+fn only_pid_files_of_gone_processes_are_swept() {
+    let dir = tempfile::tempdir().unwrap();
+    let stale = dir.path().join(".old_app_pg.pid");
+    let garbage = dir.path().join(".broken_pg.pid");
+    let live = dir.path().join(".live_app_pg.pid");
+    let unrelated = dir.path().join("notes_pg.pid");
+    fs::write(&stale, DEAD_PID.to_string()).unwrap();
+    fs::write(&garbage, "not a pid").unwrap();
+    fs::write(&live, std::process::id().to_string()).unwrap();
+    fs::write(&unrelated, DEAD_PID.to_string()).unwrap();
+
+    assert_eq!(sweep_pid_files(dir.path()), 2);
+    assert!(!stale.exists());
+    assert!(!garbage.exists());
+    assert!(live.exists());
+    assert!(unrelated.exists());
+
+    assert_eq!(remove(&live), 1);
+    assert_eq!(remove(&live), 0);
+}
@@ -0,0 +1,47 @@
+use ais_runner::audit::{self, AuditEntry};
+use artisan_middleware::dusa_collection_utils::core::types::pathtype::PathType;
+use std::{fs::OpenOptions, io::Write};
+
+#[test]
+fn entries_are_appended_in_order() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_path = PathType::Content(dir.path().join("app.state").to_string_lossy().into_owned());
+
+    assert!(audit::read(&state_path).unwrap().is_empty());
+
+    let restart = AuditEntry::new(10, String::from("restart"), &Ok(()));
+    let failed = AuditEntry::new(
+        20,
+        String::from("annotate --last \"hotfix\""),
+        &Err(String::from("No restart recorded yet")),
+    );
+    audit::append(&state_path, &restart).unwrap();
+    audit::append(&state_path, &failed).unwrap();
+
+    let entries = audit::read(&state_path).unwrap();
+    assert_eq!(entries, vec![restart, failed]);
+    assert!(entries[0].success);
+    assert_eq!(entries[1].error.as_deref(), Some("No restart recorded yet"));
+    assert_eq!(entries[0].uid, nix::unistd::Uid::current().as_raw());
+}
+
+#[test]
+fn torn_lines_are_skipped() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_path = PathType::Content(dir.path().join("app.state").to_string_lossy().into_owned());
+
+    let status = AuditEntry::new(10, String::from("status"), &Ok(()));
+    audit::append(&state_path, &status).unwrap();
+    OpenOptions::new()
+        .append(true)
+        .open(audit::path(&state_path))
+        .unwrap()
+        .write_all(b"{\"timestamp\": 11, \"uid\"\n")
+        .unwrap();
+    audit::append(&state_path, &status).unwrap();
+
+    assert_eq!(
+        audit::read(&state_path).unwrap(),
+        vec![status.clone(), status]
+    );
+}
@@ -1,6 +1,8 @@
+use ais_runner::capture::OutputCapture;
 use ais_runner::child::create_child;
 use ais_runner::config::AppSpecificConfig;
 use ais_runner::config::generate_application_state;
+use ais_runner::output::{Stream, append_sorted};
 use artisan_middleware::config::AppConfig;
 use artisan_middleware::dusa_collection_utils::core::types::pathtype::PathType;
 use artisan_middleware::state_persistence::{StatePersistence, update_state};
@@ -21,6 +23,7 @@ static SETTINGS: Lazy<AppSpecificConfig> = Lazy::new(|| AppSpecificConfig {
     run_command: "sh -c 'echo hello'".to_string(),
     secret_server_addr: "localhost:50052".to_string(),
     env_file_location: "/tmp/.trash".to_string(),
+    ..AppSpecificConfig::default()
 });
 
 static CONFIG: Lazy<AppConfig> = Lazy::new(|| AppConfig::dummy());
@@ -29,7 +32,9 @@ static STATEPATH: Lazy<PathType> = Lazy::new(|| StatePersistence::get_state_path
 #[tokio::test]
 async fn spawn_and_kill_child() {
     let mut state = generate_application_state(&STATEPATH, &CONFIG).await;
-    let mut child = create_child(&mut state, &STATEPATH, &SETTINGS).await;
+    let mut child = create_child(&mut state, &STATEPATH, &SETTINGS)
+        .await
+        .unwrap();
     assert!(child.running().await);
 
     child.kill().await.unwrap();
@@ -40,11 +45,15 @@ async fn spawn_and_kill_child() {
 #[tokio::test]
 async fn collect_log_data() {
     let mut state = generate_application_state(&STATEPATH, &CONFIG).await;
-    let mut child = create_child(&mut state, &STATEPATH, &SETTINGS).await;
+    ais_runner::capture::create(&STATEPATH).unwrap();
+    let mut capture = OutputCapture::follow(&STATEPATH);
+    let mut child = create_child(&mut state, &STATEPATH, &SETTINGS)
+        .await
+        .unwrap();
     sleep(Duration::from_millis(200)).await;
-    let out = child.get_std_out().await.unwrap();
+    let out = capture.read_lines(Stream::Stdout);
     child.kill().await.ok();
-    let found = out.iter().any(|(_, line)| line.contains("hello"));
+    let found = out.iter().any(|line| line.contains("hello"));
     assert!(found);
 }
 
@@ -64,30 +73,26 @@ async fn update_state_increments_counter() {
 #[tokio::test]
 async fn dedup_stdout_entries() {
     let mut state = generate_application_state(&STATEPATH, &CONFIG).await;
-    let mut child = create_child(&mut state, &STATEPATH, &SETTINGS).await;
+    ais_runner::capture::create(&STATEPATH).unwrap();
+    let mut capture = OutputCapture::follow(&STATEPATH);
+    let mut child = create_child(&mut state, &STATEPATH, &SETTINGS)
+        .await
+        .unwrap();
     sleep(Duration::from_millis(200)).await;
 
     // First retrieval
-    let out_first = child.get_std_out().await.unwrap();
-    let new_values: Vec<(u64, String)> = out_first
-        .clone()
-        .into_iter()
-        .filter(|val| !state.stdout.contains(val))
-        .collect();
-    state.stdout.extend(new_values);
-    state.stdout.sort_by_key(|val| val.0);
-    state.stdout.dedup();
+    let out_first = capture.read_lines(Stream::Stdout);
+    append_sorted(
+        &mut state.stdout,
+        out_first.iter().map(|line| (0, line.clone())),
+    );
 
     // Second retrieval should not duplicate lines
-    let out_second = child.get_std_out().await.unwrap();
-    let new_values: Vec<(u64, String)> = out_second
-        .clone()
-        .into_iter()
-        .filter(|val| !state.stdout.contains(val))
-        .collect();
-    state.stdout.extend(new_values);
-    state.stdout.sort_by_key(|val| val.0);
-    state.stdout.dedup();
+    let out_second = capture.read_lines(Stream::Stdout);
+    append_sorted(
+        &mut state.stdout,
+        out_second.into_iter().map(|line| (0, line)),
+    );
 
     child.kill().await.ok();
 
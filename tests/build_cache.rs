@@ -0,0 +1,162 @@
+use ais_runner::build_cache::{BuildCache, CacheKey, Lookup, fingerprint, outputs_present};
+use ais_runner::child::{TimeoutAction, run_one_shot_process};
+use ais_runner::config::{AppSpecificConfig, new_application_state};
+use ais_runner::runner_state::RunnerState;
+use ais_runner::validation::validate;
+use artisan_middleware::config::AppConfig;
+use artisan_middleware::dusa_collection_utils::core::types::pathtype::PathType;
+use std::fs;
+use std::path::Path;
+
+fn inputs(patterns: &[&str]) -> Vec<String> {
+    patterns.iter().map(|pattern| pattern.to_string()).collect()
+}
+
+fn settings(project: &Path) -> AppSpecificConfig {
+    AppSpecificConfig {
+        monitor_path: project.to_string_lossy().into_owned(),
+        project_path: project.to_string_lossy().into_owned(),
+        run_command: String::from("node server.js"),
+        build_command: Some(String::from("npm run build")),
+        ..AppSpecificConfig::default()
+    }
+}
+
+#[test]
+fn only_inputs_change_the_fingerprint() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("src/nested")).unwrap();
+    fs::write(dir.path().join("src/nested/app.js"), "one").unwrap();
+    fs::write(dir.path().join("README.md"), "docs").unwrap();
+    let inputs = inputs(&["src"]);
+
+    let first = fingerprint(dir.path(), &inputs, "npm run build").unwrap();
+    fs::write(dir.path().join("README.md"), "more docs").unwrap();
+    assert_eq!(
+        fingerprint(dir.path(), &inputs, "npm run build").unwrap(),
+        first
+    );
+
+    // Matched directories count with everything in them
+    fs::write(dir.path().join("src/nested/app.js"), "two").unwrap();
+    let second = fingerprint(dir.path(), &inputs, "npm run build").unwrap();
+    assert_ne!(second, first);
+    assert_ne!(
+        fingerprint(dir.path(), &inputs, "npm run dev").unwrap(),
+        second
+    );
+}
+
+#[test]
+fn unchanged_steps_are_fresh_until_an_output_is_gone() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("package.json"), "{}").unwrap();
+    let state_path = PathType::PathBuf(dir.path().join("state.json"));
+    let settings = settings(dir.path());
+    let (inputs, outputs) = (inputs(&["*.json"]), inputs(&["dist"]));
+    let key = CacheKey {
+        name: "build",
+        command: settings.build_command.as_deref(),
+        cwd: None,
+        inputs: &inputs,
+        outputs: &outputs,
+    };
+
+    let mut cache = BuildCache::open(&state_path);
+    let lookup = cache.lookup(&settings, &key);
+    assert!(matches!(lookup, Lookup::Stale(_)));
+    fs::create_dir(dir.path().join("dist")).unwrap();
+    cache.remember("build", lookup, true);
+
+    // Kept across runs of the runner
+    let mut cache = BuildCache::open(&state_path);
+    assert_eq!(cache.lookup(&settings, &key), Lookup::Fresh);
+    fs::remove_dir(dir.path().join("dist")).unwrap();
+    assert!(!outputs_present(dir.path(), &outputs));
+    assert!(matches!(cache.lookup(&settings, &key), Lookup::Stale(_)));
+    fs::create_dir(dir.path().join("dist")).unwrap();
+
+    // A failure forgets the last success
+    fs::write(dir.path().join("package.json"), "{\"name\": \"shop\"}").unwrap();
+    let lookup = cache.lookup(&settings, &key);
+    cache.remember("build", lookup, false);
+    assert!(RunnerState::load(&state_path).build_cache.is_empty());
+
+    let no_inputs = CacheKey { inputs: &[], ..key };
+    assert_eq!(cache.lookup(&settings, &no_inputs), Lookup::Uncached);
+}
+
+#[test]
+fn no_cache_runs_every_step_once() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("package.json"), "{}").unwrap();
+    let state_path = PathType::PathBuf(dir.path().join("state.json"));
+    let settings = settings(dir.path());
+    let inputs = inputs(&["package.json"]);
+    let key = CacheKey {
+        name: "build",
+        command: settings.build_command.as_deref(),
+        cwd: None,
+        inputs: &inputs,
+        outputs: &[],
+    };
+    let mut cache = BuildCache::open(&state_path);
+    let lookup = cache.lookup(&settings, &key);
+    cache.remember("build", lookup, true);
+
+    let mut runner_state = RunnerState::load(&state_path);
+    runner_state.no_cache_requested = true;
+    runner_state.save(&state_path).unwrap();
+
+    let cache = BuildCache::open(&state_path);
+    assert!(matches!(cache.lookup(&settings, &key), Lookup::Stale(_)));
+    assert!(!RunnerState::load(&state_path).no_cache_requested);
+    let cache = BuildCache::open(&state_path);
+    assert_eq!(cache.lookup(&settings, &key), Lookup::Fresh);
+}
+
+#[tokio::test]
+async fn a_step_kept_after_its_timeout_runs_again() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("package.json"), "{}").unwrap();
+    let state_path = PathType::PathBuf(dir.path().join("state.json"));
+    let settings = AppSpecificConfig {
+        build_command: Some(String::from("sleep 30")),
+        build_inputs: inputs(&["package.json"]),
+        build_timeout_seconds: Some(1),
+        build_timeout_action: TimeoutAction::Keep,
+        ..settings(dir.path())
+    };
+    let key = CacheKey {
+        name: "build",
+        command: settings.build_command.as_deref(),
+        cwd: None,
+        inputs: &settings.build_inputs,
+        outputs: &[],
+    };
+
+    let mut state = new_application_state(&AppConfig::dummy());
+    let mut cache = BuildCache::open(&state_path);
+    let result = run_one_shot_process(&settings, &mut state, &state_path, Some(&mut cache)).await;
+    assert!(result.unwrap_err().to_string().contains("timed out"));
+
+    // The unfinished build isn't skipped as unchanged next time
+    assert!(matches!(cache.lookup(&settings, &key), Lookup::Stale(_)));
+    let cache = BuildCache::open(&state_path);
+    assert!(matches!(cache.lookup(&settings, &key), Lookup::Stale(_)));
+    assert!(RunnerState::load(&state_path).build_cache.is_empty());
+}
+
+#[test]
+fn invalid_patterns_are_config_errors() {
+    let dir = tempfile::tempdir().unwrap();
+    let settings = AppSpecificConfig {
+        build_inputs: inputs(&["src/**", "src/[a"]),
+        ..settings(dir.path())
+    };
+    let keys: Vec<String> = validate(&settings)
+        .into_iter()
+        .map(|problem| problem.key)
+        .collect();
+    assert_eq!(keys, ["app_specific.build_inputs[1]"]);
+}
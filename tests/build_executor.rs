@@ -0,0 +1,169 @@
+use ais_runner::build_executor::{BuildExecutor, BuildExecutorConfig, LocalExecutor};
+use serde::Deserialize;
+use std::{collections::BTreeMap, path::Path};
+
+#[derive(Deserialize)]
+struct Settings {
+    build_executor: BuildExecutorConfig,
+}
+
+fn parse(toml: &str) -> BuildExecutorConfig {
+    toml::from_str::<Settings>(toml).unwrap().build_executor
+}
+
+fn strings(parts: &[&str]) -> Vec<String> {
+    parts.iter().map(|part| part.to_string()).collect()
+}
+
+#[test]
+fn local_runs_the_command_as_is() {
+    let command = strings(&["npm", "run", "build"]);
+    let argv = LocalExecutor.argv(
+        "build",
+        Some(&command),
+        Path::new("/srv/app"),
+        &BTreeMap::new(),
+    );
+    assert_eq!(argv, Some(command));
+    assert_eq!(
+        LocalExecutor.argv("build", None, Path::new("/srv/app"), &BTreeMap::new()),
+        None
+    );
+    assert_eq!(BuildExecutorConfig::default(), BuildExecutorConfig::Local);
+}
+
+#[test]
+fn docker_mounts_the_project() {
+    let config = parse(
+        r#"
+        [build_executor]
+        kind = "docker"
+        image = "node:20"
+        match_user = false
+        extra_args = ["--network", "host"]
+        "#,
+    );
+    let executor = config.executor();
+    assert_eq!(executor.name(), "docker");
+
+    let env = BTreeMap::from([(String::from("API_KEY"), String::from("hunter2"))]);
+    let command = strings(&["npm", "ci"]);
+    let argv = executor
+        .argv("install", Some(&command), Path::new("/srv/app"), &env)
+        .unwrap();
+    let name = format!("ais-runner-{}-install", std::process::id());
+    assert_eq!(
+        argv,
+        strings(&[
+            "docker",
+            "run",
+            "--rm",
+            "--name",
+            &name,
+            "-v",
+            "/srv/app:/workspace",
+            "-w",
+            "/workspace",
+            "-e",
+            "API_KEY",
+            "--network",
+            "host",
+            "node:20",
+            "npm",
+            "ci",
+        ])
+    );
+    assert!(!argv.iter().any(|part| part.contains("hunter2")));
+
+    assert_eq!(
+        executor.cleanup("install"),
+        Some(strings(&["docker", "rm", "--force", &name]))
+    );
+    assert_eq!(
+        executor.argv("build", None, Path::new("/srv/app"), &env),
+        None
+    );
+}
+
+#[test]
+fn docker_runs_as_the_runner_by_default() {
+    let config = parse(
+        r#"
+        [build_executor]
+        kind = "docker"
+        image = "rust:1"
+        "#,
+    );
+    let command = strings(&["cargo", "build"]);
+    let argv = config
+        .executor()
+        .argv(
+            "build",
+            Some(&command),
+            Path::new("/srv/app"),
+            &BTreeMap::new(),
+        )
+        .unwrap();
+    let user = argv.iter().position(|part| part == "--user").unwrap();
+    assert!(argv[user + 1].contains(':'));
+}
+
+#[test]
+fn nix_builds_the_expression() {
+    let config = parse(
+        r#"
+        [build_executor]
+        kind = "nix"
+        attribute = "app"
+        "#,
+    );
+    let executor = config.executor();
+    let project = Path::new("/srv/app");
+
+    assert_eq!(
+        executor.argv("build", None, project, &BTreeMap::new()),
+        Some(strings(&[
+            "nix-build",
+            "/srv/app/default.nix",
+            "--attr",
+            "app",
+            "--out-link",
+            "/srv/app/result",
+        ]))
+    );
+    let install = strings(&["npm", "ci"]);
+    assert_eq!(
+        executor.argv("install", Some(&install), project, &BTreeMap::new()),
+        None
+    );
+    assert_eq!(executor.cleanup("build"), None);
+}
+
+#[test]
+fn steps_run_in_their_directory() {
+    let project = Path::new("/srv/app");
+    let mut argv = strings(&["npx", "prisma", "migrate", "deploy"]);
+    assert_eq!(
+        LocalExecutor.working_dir(&mut argv, project, "backend"),
+        Some(project.join("backend"))
+    );
+
+    let config = parse(
+        r#"
+        [build_executor]
+        kind = "docker"
+        image = "node:20"
+        workdir = "/workspace/"
+        "#,
+    );
+    let executor = config.executor();
+    let command = strings(&["npx", "prisma", "migrate", "deploy"]);
+    let mut argv = executor
+        .argv("migrate", Some(&command), project, &BTreeMap::new())
+        .unwrap();
+    // The container is pointed at it, docker itself runs anywhere
+    assert_eq!(executor.working_dir(&mut argv, project, "backend"), None);
+    let workdir = argv.iter().position(|part| part == "-w").unwrap();
+    assert_eq!(argv[workdir + 1], "/workspace/backend");
+    assert!(argv.contains(&String::from("/srv/app:/workspace/")));
+}
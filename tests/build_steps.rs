@@ -0,0 +1,250 @@
+use ais_runner::build_steps::{
+    BuildStep, Schedule, StepRun, check_graph, dependencies, is_sequential, is_valid_name,
+    parallelism,
+};
+use ais_runner::config::AppSpecificConfig;
+use ais_runner::validation::validate;
+use std::time::Duration;
+
+fn settings(project: &str, steps: &str) -> AppSpecificConfig {
+    let toml = format!(
+        r#"
+        interval_seconds = 1
+        changes_needed = 1
+        monitor_path = "{project}"
+        project_path = "{project}"
+        ignored_subdirs = []
+        run_command = "node server.js"
+        {steps}
+        "#
+    );
+    toml::from_str(&toml).unwrap()
+}
+
+fn keys(settings: &AppSpecificConfig) -> Vec<String> {
+    validate(settings)
+        .into_iter()
+        .map(|problem| problem.key)
+        .collect()
+}
+
+#[test]
+fn steps_are_read_in_order() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("backend")).unwrap();
+    let settings = settings(
+        dir.path().to_str().unwrap(),
+        r#"
+        [[steps]]
+        name = "install"
+        command = "npm ci"
+
+        [[steps]]
+        name = "build"
+        command = "npm run build"
+        timeout_seconds = 600
+
+        [[steps]]
+        name = "migrate"
+        command = "npx prisma migrate deploy"
+        cwd = "backend"
+        continue_on_failure = true
+        "#,
+    );
+    let names: Vec<&str> = settings
+        .steps
+        .iter()
+        .map(|step| step.name.as_str())
+        .collect();
+    assert_eq!(names, ["install", "build", "migrate"]);
+    assert_eq!(settings.steps[0].timeout_seconds, None);
+    assert!(!settings.steps[0].continue_on_failure);
+    assert_eq!(settings.steps[1].timeout_seconds, Some(600));
+    assert_eq!(settings.steps[2].cwd.as_deref(), Some("backend"));
+    assert!(settings.steps[2].continue_on_failure);
+    assert!(settings.has_build_step());
+    assert_eq!(keys(&settings), Vec::<String>::new());
+}
+
+#[test]
+fn bad_steps_are_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    let settings = settings(
+        dir.path().to_str().unwrap(),
+        r#"
+        build_command = "npm run build"
+
+        [[steps]]
+        name = "build"
+        command = "npm run build"
+
+        [[steps]]
+        name = "build"
+        command = "npm run 'lint"
+        timeout_seconds = 0
+
+        [[steps]]
+        name = "db migrate"
+        command = "npx prisma migrate deploy"
+        cwd = "backend"
+        "#,
+    );
+    assert_eq!(
+        keys(&settings),
+        [
+            "app_specific.steps",
+            "app_specific.steps[1].name",
+            "app_specific.steps[1].command",
+            "app_specific.steps[1].timeout_seconds",
+            "app_specific.steps[2].name",
+            "app_specific.steps[2].cwd",
+        ]
+    );
+}
+
+#[test]
+fn names_end_up_in_event_names() {
+    assert!(is_valid_name("migrate"));
+    assert!(is_valid_name("build-assets_2"));
+    assert!(!is_valid_name(""));
+    assert!(!is_valid_name("db migrate"));
+    assert!(!is_valid_name("../escape"));
+}
+
+#[test]
+fn runs_show_their_timing() {
+    let run = StepRun::new("build", Duration::from_millis(48_040), true);
+    assert_eq!(run.to_string(), "build 48.0s");
+    let run = StepRun::new("migrate", Duration::from_millis(1_200), false);
+    assert_eq!(run.to_string(), "migrate failed after 1.2s");
+}
+
+fn steps(toml: &str) -> Vec<BuildStep> {
+    #[derive(serde::Deserialize)]
+    struct Steps {
+        steps: Vec<BuildStep>,
+    }
+    toml::from_str::<Steps>(toml).unwrap().steps
+}
+
+const MONOREPO: &str = r#"
+    [[steps]]
+    name = "install"
+    command = "npm ci"
+
+    [[steps]]
+    name = "frontend"
+    command = "npm run build"
+    depends_on = ["install"]
+
+    [[steps]]
+    name = "backend"
+    command = "cargo build"
+    depends_on = []
+
+    [[steps]]
+    name = "migrate"
+    command = "./migrate"
+    depends_on = ["frontend", "backend"]
+"#;
+
+#[test]
+fn steps_wait_for_the_previous_one_by_default() {
+    let listed = steps(
+        r#"
+        [[steps]]
+        name = "install"
+        command = "npm ci"
+
+        [[steps]]
+        name = "build"
+        command = "npm run build"
+        "#,
+    );
+    assert!(is_sequential(&listed));
+    assert_eq!(dependencies(&listed), [vec![], vec![0]]);
+
+    let monorepo = steps(MONOREPO);
+    assert!(!is_sequential(&monorepo));
+    assert_eq!(
+        dependencies(&monorepo),
+        [vec![], vec![0], vec![], vec![1, 2]]
+    );
+    assert_eq!(check_graph(&monorepo), Vec::new());
+}
+
+#[test]
+fn independent_steps_start_together() {
+    let monorepo = steps(MONOREPO);
+    let mut schedule = Schedule::new(&monorepo);
+    assert_eq!(schedule.start(4), [0, 2]);
+    assert_eq!(schedule.start(4), Vec::<usize>::new());
+
+    schedule.finish(0, true);
+    assert_eq!(schedule.start(4), [1]);
+    schedule.finish(2, true);
+    schedule.finish(1, true);
+    assert_eq!(schedule.start(4), [3]);
+    schedule.finish(3, true);
+    assert_eq!(schedule.pending(), Vec::<usize>::new());
+
+    // The limit holds back what's ready
+    let mut schedule = Schedule::new(&monorepo);
+    assert_eq!(schedule.start(1), [0]);
+    assert_eq!(schedule.running(), [0]);
+}
+
+#[test]
+fn nothing_starts_after_a_failure() {
+    let monorepo = steps(MONOREPO);
+    let mut schedule = Schedule::new(&monorepo);
+    assert_eq!(schedule.start(4), [0, 2]);
+    schedule.finish(2, false);
+    schedule.finish(0, true);
+    assert_eq!(schedule.start(4), Vec::<usize>::new());
+    assert_eq!(schedule.pending(), [1, 3]);
+
+    assert_eq!(parallelism(Some(0)), 1);
+    assert_eq!(parallelism(Some(3)), 3);
+    assert!(parallelism(None) >= 1);
+}
+
+#[test]
+fn bad_dependencies_are_reported() {
+    let unknown = steps(
+        r#"
+        [[steps]]
+        name = "build"
+        command = "npm run build"
+        depends_on = ["instal", "build"]
+        "#,
+    );
+    assert_eq!(
+        check_graph(&unknown),
+        [
+            (0, String::from("\"instal\" isn't a step")),
+            (0, String::from("can't name the step itself")),
+        ]
+    );
+
+    let cycle = steps(
+        r#"
+        [[steps]]
+        name = "a"
+        command = "true"
+        depends_on = ["c"]
+
+        [[steps]]
+        name = "b"
+        command = "true"
+
+        [[steps]]
+        name = "c"
+        command = "true"
+        "#,
+    );
+    assert_eq!(
+        check_graph(&cycle),
+        [(0, String::from("a, b, c wait for each other in a cycle"))]
+    );
+}
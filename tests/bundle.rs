@@ -0,0 +1,96 @@
+use ais_runner::bundle::{BUNDLE_VERSION, Contents, Role, collect, install, sign, verify};
+use std::fs;
+use std::path::Path;
+
+const KEY: &[u8] = b"shared between the hosts";
+const CONFIG: &str = "[app_specific]\n\
+                      project_path = \"app\"\n\
+                      env_template = \".env.template\"\n\
+                      restart_schedule = \"0 3 * * *\"\n";
+
+fn contents(dir: &Path) -> Contents {
+    Contents {
+        version: BUNDLE_VERSION,
+        created: 1_760_000_000,
+        runner_version: String::from("2.1.0"),
+        files: collect(dir).unwrap(),
+    }
+}
+
+fn source() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("Config.toml"), CONFIG).unwrap();
+    fs::create_dir(dir.path().join("app")).unwrap();
+    fs::write(
+        dir.path().join("app/.env.template"),
+        "DATABASE_URL={{secret:db_url}}\n",
+    )
+    .unwrap();
+    // The rendered env file holds secret values and stays behind
+    fs::write(
+        dir.path().join("app/.env"),
+        "DATABASE_URL=postgres://secret\n",
+    )
+    .unwrap();
+    dir
+}
+
+#[test]
+fn the_config_and_template_are_collected() {
+    let dir = source();
+    let files = collect(dir.path()).unwrap();
+    let roles: Vec<Role> = files.iter().map(|file| file.role).collect();
+    assert_eq!(roles, vec![Role::Config, Role::EnvTemplate]);
+    assert_eq!(files[0].bytes().unwrap(), CONFIG.as_bytes());
+
+    fs::write(dir.path().join("Overrides.toml"), "[git]\n").unwrap();
+    assert_eq!(collect(dir.path()).unwrap().len(), 3);
+
+    let empty = tempfile::tempdir().unwrap();
+    assert!(collect(empty.path()).unwrap_err().contains("Config.toml"));
+}
+
+#[test]
+fn bundles_are_installed_on_another_host() {
+    let source = source();
+    let bundle = sign(contents(source.path()), KEY).unwrap();
+    let json = serde_json::to_string(&bundle).unwrap();
+
+    let target = tempfile::tempdir().unwrap();
+    let bundle = serde_json::from_str(&json).unwrap();
+    let written = install(verify(&bundle, KEY).unwrap(), target.path(), false).unwrap();
+    assert_eq!(written.len(), 2);
+    assert_eq!(
+        fs::read_to_string(target.path().join("Config.toml")).unwrap(),
+        CONFIG
+    );
+    assert_eq!(
+        fs::read_to_string(target.path().join("app/.env.template")).unwrap(),
+        "DATABASE_URL={{secret:db_url}}\n"
+    );
+    assert!(!target.path().join("app/.env").exists());
+
+    // A second import would clobber what's there
+    let err = install(verify(&bundle, KEY).unwrap(), target.path(), false).unwrap_err();
+    assert!(err.contains("--force"), "{}", err);
+    install(verify(&bundle, KEY).unwrap(), target.path(), true).unwrap();
+}
+
+#[test]
+fn tampered_bundles_are_rejected() {
+    let source = source();
+    let bundle = sign(contents(source.path()), KEY).unwrap();
+    assert!(verify(&bundle, KEY).is_ok());
+
+    let err = verify(&bundle, b"another key").unwrap_err();
+    assert!(err.contains("doesn't match"), "{}", err);
+
+    let mut tampered = bundle.clone();
+    tampered.contents.files.pop();
+    assert!(verify(&tampered, KEY).is_err());
+
+    let mut future = bundle.contents.clone();
+    future.version = BUNDLE_VERSION + 1;
+    let err = verify(&sign(future, KEY).unwrap(), KEY).unwrap_err();
+    assert!(err.contains("isn't supported"), "{}", err);
+}
@@ -0,0 +1,188 @@
+use ais_runner::capture::{self, CaptureFile, OutputCapture};
+use ais_runner::output::Stream;
+use artisan_middleware::dusa_collection_utils::core::types::pathtype::PathType;
+use proptest::prelude::*;
+use std::fs::{self, OpenOptions, Permissions};
+use std::io::Write;
+use std::os::unix::fs::{MetadataExt, PermissionsExt, symlink};
+
+fn append(path: &std::path::Path, text: &str) {
+    let mut file = OpenOptions::new().append(true).open(path).unwrap();
+    file.write_all(text.as_bytes()).unwrap();
+}
+
+#[test]
+fn lines_are_read_from_where_following_started() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("out.log");
+    fs::write(&path, "before\n").unwrap();
+    let mut file = CaptureFile::open(&path).unwrap();
+    assert!(file.read_lines().is_empty());
+
+    // Only whole lines, the rest once it's finished
+    append(&path, "one\r\ntw");
+    assert_eq!(file.read_lines(), ["one"]);
+    append(&path, "o\n");
+    assert_eq!(file.read_lines(), ["two"]);
+    assert!(file.read_lines().is_empty());
+}
+
+#[test]
+fn repeated_lines_are_each_read_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("out.log");
+    fs::write(&path, "tick\ntick\n").unwrap();
+    let mut file = CaptureFile::open(&path).unwrap();
+
+    // Same text as the last line read, still new lines
+    append(&path, "tick\ntick\n");
+    assert_eq!(file.read_lines(), ["tick", "tick"]);
+    append(&path, "tick\n");
+    assert_eq!(file.read_lines(), ["tick"]);
+    assert!(file.read_lines().is_empty());
+}
+
+#[test]
+fn truncated_files_are_read_from_the_start() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("out.log");
+    fs::write(&path, "old line\n").unwrap();
+    let mut file = CaptureFile::open(&path).unwrap();
+
+    fs::write(&path, "new\n").unwrap();
+    assert_eq!(file.read_lines(), ["new"]);
+}
+
+#[test]
+fn released_space_doesnt_show_up_as_lines() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("out.log");
+    fs::write(&path, "").unwrap();
+    let mut file = CaptureFile::open(&path).unwrap();
+    let mut behind = CaptureFile::open(&path).unwrap();
+
+    let line = "x".repeat(1023);
+    let count = 3 * 1024;
+    for _ in 0..count {
+        append(&path, &format!("{}\n", line));
+    }
+    assert_eq!(file.read_lines().len(), count);
+    file.release().unwrap();
+    append(&path, "last\n");
+    assert_eq!(file.read_lines(), ["last"]);
+
+    // Same size, but the disk space of what was read is given back
+    let meta = fs::metadata(&path).unwrap();
+    assert_eq!(meta.len(), (count * 1024 + 5) as u64);
+    assert!(meta.blocks() * 512 < meta.len());
+
+    // A follower that fell behind skips the punched out lines
+    let lines = behind.read_lines();
+    assert!(lines.len() < count);
+    assert_eq!(lines.last().map(String::as_str), Some("last"));
+    assert!(lines[..lines.len() - 1].iter().all(|read| *read == line));
+}
+
+#[test]
+fn both_streams_of_an_app_are_followed() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_path = PathType::PathBuf(dir.path().join("app.state"));
+    capture::create(&state_path).unwrap();
+    let mut output = OutputCapture::follow(&state_path);
+    let mut stdout = capture::child_stdio(&state_path, Stream::Stdout).unwrap();
+    let mut stderr = capture::child_stdio(&state_path, Stream::Stderr).unwrap();
+    stdout.write_all(b"out\n").unwrap();
+    stderr.write_all(b"err\n").unwrap();
+
+    assert_eq!(output.read_lines(Stream::Stdout), ["out"]);
+    assert_eq!(
+        output.read_keyed(Stream::Stderr, 7),
+        [(7, "err".to_string())]
+    );
+    assert!(output.read_lines(Stream::Stdout).is_empty());
+    output.release().unwrap();
+    assert!(OutputCapture::open(&state_path).is_ok());
+
+    for stream in [Stream::Stdout, Stream::Stderr] {
+        fs::remove_file(capture::path(&state_path, stream)).unwrap();
+    }
+    assert!(OutputCapture::open(&state_path).is_err());
+}
+
+#[test]
+fn repeated_lines_get_their_own_ids() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("out.log");
+    fs::write(&path, "before\n").unwrap();
+    let mut file = CaptureFile::open(&path).unwrap();
+
+    append(&path, "tick\ntick\nti");
+    assert_eq!(
+        file.read_numbered(),
+        [(7, "tick".to_string()), (12, "tick".to_string())]
+    );
+    // A line read in parts starts where its first part did
+    append(&path, "ck\n");
+    assert_eq!(file.read_numbered(), [(17, "tick".to_string())]);
+}
+
+#[test]
+fn only_the_runner_can_get_into_the_output_dir() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_path = PathType::PathBuf(dir.path().join("app.state"));
+    capture::create(&state_path).unwrap();
+    let mode = fs::metadata(capture::dir(&state_path)).unwrap().mode();
+    assert_eq!(mode & 0o777, 0o700);
+
+    // Loosened by hand, tightened again
+    fs::set_permissions(capture::dir(&state_path), Permissions::from_mode(0o755)).unwrap();
+    capture::create(&state_path).unwrap();
+    let mode = fs::metadata(capture::dir(&state_path)).unwrap().mode();
+    assert_eq!(mode & 0o777, 0o700);
+}
+
+#[test]
+fn symlinks_are_never_followed() {
+    let dir = tempfile::tempdir().unwrap();
+    let secret = dir.path().join("secret");
+    fs::write(&secret, "not for the child\n").unwrap();
+
+    // In place of a capture file
+    let state_path = PathType::PathBuf(dir.path().join("app.state"));
+    capture::create_dir(&state_path).unwrap();
+    symlink(&secret, capture::path(&state_path, Stream::Stdout)).unwrap();
+    assert!(capture::create(&state_path).is_err());
+    assert!(capture::child_stdio(&state_path, Stream::Stdout).is_err());
+    assert!(CaptureFile::open(&capture::path(&state_path, Stream::Stdout)).is_err());
+
+    // In place of the directory
+    let other = PathType::PathBuf(dir.path().join("other.state"));
+    symlink(dir.path(), capture::dir(&other)).unwrap();
+    assert!(capture::create(&other).is_err());
+    assert_eq!(fs::read_to_string(&secret).unwrap(), "not for the child\n");
+}
+
+proptest! {
+    #[test]
+    fn every_line_is_read_once(
+        batches in prop::collection::vec(prop::collection::vec(0..3u8, 0..16), 0..12),
+    ) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.log");
+        fs::write(&path, "").unwrap();
+        let mut file = CaptureFile::open(&path).unwrap();
+        let mut written = Vec::new();
+        let mut read = Vec::new();
+
+        // Few distinct lines, so most of them repeat the one before
+        for batch in batches {
+            for line in batch {
+                let line = format!("line {}", line);
+                append(&path, &format!("{}\n", line));
+                written.push(line);
+            }
+            read.extend(file.read_lines());
+        }
+        prop_assert_eq!(read, written);
+    }
+}
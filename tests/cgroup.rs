@@ -0,0 +1,105 @@
+use ais_runner::cgroup::{CgroupConfig, ChildCgroup, cgroup_v2_available, persist_limits};
+use ais_runner::child::resolve_identity;
+use ais_runner::config::AppSpecificConfig;
+use nix::unistd::Uid;
+use std::fs;
+
+fn cgroup_section(content: &str) -> CgroupConfig {
+    let value: toml::Value = toml::from_str(content).unwrap();
+    value["app_specific"]["cgroup"].clone().try_into().unwrap()
+}
+
+#[test]
+fn limits_are_written_keeping_the_rest_of_the_config() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("Config.toml");
+    fs::write(
+        &path,
+        "# my app\n\
+         [app_specific]\n\
+         interval_seconds = 30 # slow disks\n\
+         \n\
+         [app_specific.cgroup]\n\
+         enabled = true\n\
+         memory_max_mb = 512\n\
+         cpu_max_percent = 100\n",
+    )
+    .unwrap();
+
+    persist_limits(&path, Some(768), None).unwrap();
+
+    let content = fs::read_to_string(&path).unwrap();
+    assert!(content.contains("# my app"), "{}", content);
+    assert!(
+        content.contains("interval_seconds = 30 # slow disks"),
+        "{}",
+        content
+    );
+    let cgroup = cgroup_section(&content);
+    assert!(cgroup.enabled);
+    assert_eq!(cgroup.memory_max_mb, Some(768));
+    assert_eq!(cgroup.cpu_max_percent, Some(100));
+
+    persist_limits(&path, None, Some(0)).unwrap();
+    let cgroup = cgroup_section(&fs::read_to_string(&path).unwrap());
+    assert_eq!(cgroup.memory_max_mb, Some(768));
+    assert_eq!(cgroup.cpu_max_percent, Some(0));
+}
+
+#[test]
+fn a_missing_section_is_added() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("Config.toml");
+    fs::write(&path, "[app_specific]\nproject_path = \"/srv/app\"\n").unwrap();
+
+    persist_limits(&path, Some(256), Some(150)).unwrap();
+
+    let cgroup = cgroup_section(&fs::read_to_string(&path).unwrap());
+    assert_eq!(cgroup.memory_max_mb, Some(256));
+    assert_eq!(cgroup.cpu_max_percent, Some(150));
+}
+
+#[test]
+fn a_missing_config_is_an_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let err = persist_limits(&dir.path().join("Config.toml"), Some(256), None).unwrap_err();
+    assert!(err.contains("Failed to read"), "{}", err);
+}
+
+#[tokio::test]
+async fn the_child_joins_its_cgroup_before_exec() {
+    // Creating cgroups needs root on a cgroup v2 host
+    if !Uid::effective().is_root() || !cgroup_v2_available() {
+        return;
+    }
+    let name = format!("ais_runner_test_{}", std::process::id());
+    let parent = std::path::Path::new("/sys/fs/cgroup").join(&name);
+    fs::create_dir_all(&parent).unwrap();
+    let config = CgroupConfig {
+        enabled: true,
+        parent: Some(name.clone()),
+        ..CgroupConfig::default()
+    };
+    let cgroup = ChildCgroup::setup(&config, 0).unwrap();
+
+    // The child can't write cgroup.procs as nobody, only before switching
+    let settings = AppSpecificConfig {
+        run_as_user: Some(String::from("nobody")),
+        ..AppSpecificConfig::default()
+    };
+    let identity = resolve_identity(&settings).unwrap().unwrap();
+    let mut command = tokio::process::Command::new("cat");
+    command.arg("/proc/self/cgroup");
+    cgroup.join_on_spawn(&mut command).unwrap();
+    identity.apply(&mut command);
+    let output = command.output().await.unwrap();
+
+    fs::remove_dir(parent.join("child")).unwrap();
+    fs::remove_dir(&parent).unwrap();
+    let groups = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        groups.contains(&format!("0::/{}/child", name)),
+        "{}",
+        groups
+    );
+}
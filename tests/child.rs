@@ -0,0 +1,160 @@
+use ais_runner::child::{
+    ChildLaunch, launch_child, resolve_identity, run_one_shot_process, split_command,
+};
+use ais_runner::config::{AppSpecificConfig, new_application_state};
+use ais_runner::secrets::retry::RetryConfig;
+use artisan_middleware::config::AppConfig;
+use artisan_middleware::dusa_collection_utils::core::errors::Errors;
+use artisan_middleware::dusa_collection_utils::core::types::pathtype::PathType;
+use nix::unistd::Uid;
+use proptest::prelude::*;
+
+proptest! {
+    #[test]
+    fn quoted_arguments_round_trip(args in prop::collection::vec(".*", 0..6)) {
+        let command = shell_words::join(&args);
+        prop_assert_eq!(split_command(&command), args);
+    }
+
+    #[test]
+    fn plain_commands_split_on_whitespace(command in "[a-z0-9./=:-]{0,12}([ \t\n]{1,3}[a-z0-9./=:-]{1,12}){0,5}") {
+        let expected: Vec<String> = command.split_whitespace().map(String::from).collect();
+        prop_assert_eq!(split_command(&command), expected);
+    }
+
+    #[test]
+    fn unbalanced_quotes_fall_back_to_whitespace(
+        words in prop::collection::vec("[a-z0-9./=-]{1,8}", 1..5),
+        quote in prop::sample::select(vec!['\'', '"']),
+    ) {
+        let command = format!("{} {}unterminated arg", words.join(" "), quote);
+        let mut expected = words.clone();
+        expected.push(format!("{}unterminated", quote));
+        expected.push(String::from("arg"));
+        prop_assert_eq!(split_command(&command), expected);
+    }
+
+    #[test]
+    fn only_quotes_give_empty_arguments(command in any::<String>()) {
+        let argv = split_command(&command);
+        prop_assert!(argv.iter().all(|arg| !arg.is_empty() || command.contains(['\'', '"'])));
+    }
+}
+
+/// The sorted group ids `id -G` printed.
+fn group_ids(output: &[u8]) -> Vec<u32> {
+    let mut groups: Vec<u32> = String::from_utf8_lossy(output)
+        .split_whitespace()
+        .filter_map(|gid| gid.parse().ok())
+        .collect();
+    groups.sort_unstable();
+    groups
+}
+
+#[tokio::test]
+async fn the_child_gets_the_groups_of_run_as_user() {
+    // Switching users needs root
+    if !Uid::effective().is_root() {
+        return;
+    }
+    let settings = AppSpecificConfig {
+        run_as_user: Some(String::from("nobody")),
+        ..AppSpecificConfig::default()
+    };
+    let identity = resolve_identity(&settings).unwrap().unwrap();
+
+    let mut command = tokio::process::Command::new("id");
+    command.arg("-G");
+    identity.apply(&mut command);
+    let output = command.output().await.unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    let expected = std::process::Command::new("id")
+        .args(["-G", "nobody"])
+        .output()
+        .unwrap();
+    assert_eq!(group_ids(&output.stdout), group_ids(&expected.stdout));
+    assert!(!group_ids(&output.stdout).contains(&0));
+}
+
+#[tokio::test]
+async fn unreachable_secrets_fail_the_spawn_not_the_runner() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_path = PathType::PathBuf(dir.path().join("app.state"));
+    let mut state = new_application_state(&AppConfig::dummy());
+    let launch = ChildLaunch {
+        argv: vec![String::from("echo"), String::from("{secret:token}")],
+        env: Default::default(),
+        cwd: dir.path().to_path_buf(),
+        run_as_user: None,
+        run_as_group: None,
+    };
+    let retry = RetryConfig {
+        max_attempts: 2,
+        initial_delay_ms: 1,
+        max_delay_ms: 1,
+    };
+
+    // No secret server connection, so every attempt fails to connect
+    let spawned = launch_child(&mut state, &state_path, launch, &retry).await;
+    let err = spawned.err().expect("spawned without its secrets");
+    assert_eq!(err.err_type, Errors::ConnectionError);
+    // The retried attempt is in the error log
+    assert_eq!(state.error_log.len(), 1);
+}
+
+#[tokio::test]
+async fn build_output_is_bounded_while_it_runs() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_path = PathType::PathBuf(dir.path().join("app.state"));
+    let mut state = new_application_state(&AppConfig::dummy());
+    let settings = AppSpecificConfig {
+        project_path: dir.path().to_string_lossy().into_owned(),
+        build_command: Some(String::from(
+            "sh -c 'for i in $(seq 1 100); do echo out $i; echo err $i >&2; done'",
+        )),
+        max_log_lines: 10,
+        max_log_bytes: 60,
+        ..Default::default()
+    };
+
+    run_one_shot_process(&settings, &mut state, &state_path, None)
+        .await
+        .unwrap();
+    let lines = |buffer: &[(u64, String)]| -> Vec<String> {
+        buffer.iter().map(|(_, line)| line.clone()).collect()
+    };
+    // 60 bytes hold only the last 9 lines, "out 100" and "out 99" to "out 92"
+    assert_eq!(lines(&state.stdout).len(), 9);
+    assert_eq!(lines(&state.stdout).last().unwrap(), "out 100");
+    assert_eq!(lines(&state.stderr).first().unwrap(), "err 92");
+}
+
+#[tokio::test]
+async fn a_full_stderr_pipe_doesnt_stall_the_build() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_path = PathType::PathBuf(dir.path().join("app.state"));
+    let mut state = new_application_state(&AppConfig::dummy());
+    // 2000 lines of 50 bytes, past the 64 KiB a pipe holds, before any stdout
+    let settings = AppSpecificConfig {
+        project_path: dir.path().to_string_lossy().into_owned(),
+        build_command: Some(String::from(
+            "sh -c 'i=0; while [ $i -lt 2000 ]; do printf \"err %045d\\n\" $i >&2; i=$((i+1)); done; echo done'",
+        )),
+        build_timeout_seconds: Some(30),
+        max_log_lines: 0,
+        max_log_bytes: 0,
+        ..Default::default()
+    };
+
+    run_one_shot_process(&settings, &mut state, &state_path, None)
+        .await
+        .unwrap();
+    let stderr: Vec<&str> = state.stderr.iter().map(|(_, line)| line.as_str()).collect();
+    assert_eq!(stderr.len(), 2000);
+    for (index, line) in stderr.iter().enumerate() {
+        assert_eq!(*line, format!("err {:045}", index));
+    }
+    let stdout: Vec<&str> = state.stdout.iter().map(|(_, line)| line.as_str()).collect();
+    assert_eq!(stdout, ["done"]);
+}
@@ -0,0 +1,45 @@
+use ais_runner::child::{ChildExit, peek_exit, should_restart};
+use ais_runner::config::AppSpecificConfig;
+use std::{process::Command, thread::sleep, time::Duration};
+
+#[test]
+fn signals_map_to_shell_exit_codes() {
+    assert_eq!(ChildExit::Code(1).code(), 1);
+    assert_eq!(ChildExit::Signal(9).code(), 137);
+    assert_eq!(ChildExit::Signal(9).to_string(), "was killed by SIGKILL");
+    assert_eq!(ChildExit::Code(0).to_string(), "exited with 0");
+}
+
+#[test]
+fn exit_codes_decide_about_restarts() {
+    let mut settings = AppSpecificConfig::default();
+    assert!(should_restart(&settings, Some(ChildExit::Code(0))));
+    assert!(should_restart(&settings, None));
+
+    settings.no_restart_on = vec![0];
+    settings.restart_on_exit_codes = vec![1, 137];
+    assert!(!should_restart(&settings, Some(ChildExit::Code(0))));
+    assert!(should_restart(&settings, Some(ChildExit::Code(1))));
+    assert!(should_restart(&settings, Some(ChildExit::Signal(9))));
+    assert!(!should_restart(&settings, Some(ChildExit::Code(2))));
+    assert!(should_restart(&settings, None));
+}
+
+#[test]
+fn peeking_leaves_the_child_to_be_reaped() {
+    let mut child = Command::new("sh").args(["-c", "exit 3"]).spawn().unwrap();
+    let pid = child.id();
+
+    let mut exit = None;
+    for _ in 0..100 {
+        exit = peek_exit(pid);
+        if exit.is_some() {
+            break;
+        }
+        sleep(Duration::from_millis(10));
+    }
+    assert_eq!(exit, Some(ChildExit::Code(3)));
+
+    assert_eq!(child.wait().unwrap().code(), Some(3));
+    assert_eq!(peek_exit(pid), None);
+}
@@ -0,0 +1,11 @@
+use ais_runner::child::KillMode;
+use ais_runner::child_manager;
+
+#[tokio::test]
+async fn without_a_child_every_answer_is_none() {
+    assert_eq!(child_manager::running().await, None);
+    assert_eq!(child_manager::pid().await, None);
+    assert!(child_manager::metrics().await.is_none());
+    assert!(child_manager::kill(KillMode::Process).await.is_none());
+    assert!(child_manager::take().await.is_none());
+}
@@ -0,0 +1,120 @@
+use ais_runner::command_vars::{CommandVars, fill_secrets, secret_keys};
+use ais_runner::config::AppSpecificConfig;
+use ais_runner::reservations::ReservationConfig;
+use std::collections::BTreeMap;
+
+fn settings() -> AppSpecificConfig {
+    AppSpecificConfig {
+        project_path: String::from("/srv/app"),
+        monitor_path: String::from("/srv/app/src"),
+        run_command: String::from("./server --root {project_path} --port={port} --env {env}"),
+        reservation: ReservationConfig {
+            ports: vec![8080, 8081],
+            ..ReservationConfig::default()
+        },
+        command_vars: BTreeMap::from([(String::from("region"), String::from("eu west"))]),
+        ..AppSpecificConfig::default()
+    }
+}
+
+#[test]
+fn fills_in_variables() {
+    let settings = settings();
+    let vars = CommandVars::new(&settings, "staging");
+
+    assert_eq!(
+        vars.argv(&settings.run_command),
+        vec![
+            "./server",
+            "--root",
+            "/srv/app",
+            "--port=8080",
+            "--env",
+            "staging"
+        ]
+    );
+    // Values with spaces stay one argument
+    assert_eq!(
+        vars.argv("deploy --region {region} {monitor_path}/out"),
+        vec!["deploy", "--region", "eu west", "/srv/app/src/out"]
+    );
+}
+
+#[test]
+fn leaves_other_braces_alone() {
+    let vars = CommandVars::new(&settings(), "staging");
+
+    for arg in [
+        "${HOME}/bin",
+        "{unknown}",
+        "{ print $1 }",
+        "{}",
+        "{port",
+        "{secret:token}",
+    ] {
+        assert_eq!(vars.expand(arg), arg);
+    }
+    assert_eq!(vars.expand("{{port}}"), "{8080}");
+}
+
+#[test]
+fn configured_variables_win() {
+    let mut settings = settings();
+    settings
+        .command_vars
+        .insert(String::from("port"), String::from("9000"));
+    settings.reservation.ports.clear();
+    let vars = CommandVars::new(&settings, "production");
+    assert_eq!(vars.expand("{port}"), "9000");
+
+    settings.command_vars.clear();
+    let vars = CommandVars::new(&settings, "production");
+    assert_eq!(vars.expand("{port}"), "{port}");
+    assert_eq!(vars.unknown("./server --port {port} {env}"), vec!["port"]);
+}
+
+#[test]
+fn secrets_are_filled_separately() {
+    let argv: Vec<String> = [
+        "./build.sh",
+        "--token={secret:token}",
+        "{secret:token}",
+        "{secret:key}",
+    ]
+    .iter()
+    .map(|arg| arg.to_string())
+    .collect();
+    assert_eq!(secret_keys(&argv), vec!["token", "key"]);
+
+    let secrets = vec![
+        (String::from("token"), b"s3cret".to_vec()),
+        (String::from("key"), b"with space".to_vec()),
+    ];
+    assert_eq!(
+        fill_secrets(&argv, &secrets).unwrap(),
+        vec!["./build.sh", "--token=s3cret", "s3cret", "with space"]
+    );
+
+    let err = fill_secrets(&argv, &secrets[..1].to_vec()).unwrap_err();
+    assert!(err.contains("key"), "{}", err);
+}
+
+#[test]
+fn parses_config() {
+    let settings: AppSpecificConfig = toml::from_str(
+        r#"
+interval_seconds = 1
+monitor_path = "./"
+project_path = "./"
+changes_needed = 1
+ignored_subdirs = []
+run_command = "./server --region {region}"
+
+[command_vars]
+region = "eu-west"
+"#,
+    )
+    .unwrap();
+    assert_eq!(settings.command_vars["region"], "eu-west");
+    assert!(AppSpecificConfig::default().command_vars.is_empty());
+}
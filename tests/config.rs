@@ -0,0 +1,116 @@
+use ais_runner::cadence::CadenceConfig;
+use ais_runner::child::KillMode;
+use ais_runner::config::AppSpecificConfig;
+use ais_runner::host::host_arch;
+use ais_runner::runner::RunMode;
+use std::time::Duration;
+
+fn load(extra: &str) -> Result<AppSpecificConfig, toml::de::Error> {
+    let content = format!(
+        r#"
+interval_seconds = 1
+monitor_path = "./"
+project_path = "./"
+changes_needed = 1
+ignored_subdirs = []
+run_command = "./server"
+{}
+"#,
+        extra
+    );
+    toml::from_str(&content)
+}
+
+#[test]
+fn selects_build_command_for_host_arch() {
+    let settings = load(&format!(
+        r#"
+[build_command]
+{} = "make native"
+some_other_arch = "make cross"
+"#,
+        host_arch()
+    ))
+    .unwrap();
+
+    assert_eq!(settings.build_command.as_deref(), Some("make native"));
+    assert_eq!(settings.run_command, "./server");
+}
+
+#[test]
+fn falls_back_to_default_variant() {
+    let settings = load(
+        r#"
+[install_command]
+some_other_arch = "npm ci --cpu=other"
+default = "npm ci"
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(settings.install_command.as_deref(), Some("npm ci"));
+    assert_eq!(settings.build_command, None);
+}
+
+#[test]
+fn missing_variant_is_an_error() {
+    let err = load(
+        r#"
+[build_command]
+some_other_arch = "make cross"
+"#,
+    )
+    .unwrap_err();
+
+    assert!(err.to_string().contains("no variant for"));
+}
+
+#[test]
+fn the_process_group_is_killed_by_default() {
+    assert_eq!(load("").unwrap().kill_mode, KillMode::Group);
+    assert_eq!(
+        load("kill_mode = \"cgroup\"").unwrap().kill_mode,
+        KillMode::Cgroup
+    );
+    assert!(load("kill_mode = \"namespace\"").is_err());
+}
+
+#[test]
+fn changes_are_watched_unless_supervising_only() {
+    assert!(load("").unwrap().monitoring_enabled);
+    assert!(AppSpecificConfig::default().monitoring_enabled);
+    assert!(
+        !load("monitoring_enabled = false")
+            .unwrap()
+            .monitoring_enabled
+    );
+    assert_eq!(
+        ais_runner::reload::effect("monitoring_enabled"),
+        ais_runner::reload::Effect::Runner
+    );
+}
+
+#[test]
+fn jobs_run_once() {
+    assert_eq!(load("").unwrap().mode, RunMode::Service);
+    assert_eq!(load("mode = \"oneshot\"").unwrap().mode, RunMode::Oneshot);
+    assert!(load("mode = \"cron\"").is_err());
+}
+
+#[test]
+fn each_periodic_job_has_its_own_cadence() {
+    let cadence = load("").unwrap().cadence;
+    assert_eq!(cadence, CadenceConfig::default());
+    assert_eq!(cadence.health(), Duration::from_secs(5));
+
+    let cadence = load("[cadence]\nhealth_seconds = 1\nmetrics_seconds = 30\n")
+        .unwrap()
+        .cadence;
+    assert_eq!(cadence.health(), Duration::from_secs(1));
+    assert_eq!(cadence.metrics(), Duration::from_secs(30));
+    assert_eq!(cadence.cleanup(), Duration::from_secs(5));
+    assert_eq!(cadence.output(), Duration::from_secs(1));
+
+    let cadence = load("[cadence]\noutput_millis = 250\n").unwrap().cadence;
+    assert_eq!(cadence.output(), Duration::from_millis(250));
+}
@@ -0,0 +1,106 @@
+use ais_runner::config::AppSpecificConfig;
+use ais_runner::config_schema::{Kind, explain, mismatched_keys, schema, unknown_keys};
+
+fn fields() -> Vec<ais_runner::config_schema::Field> {
+    match schema::<AppSpecificConfig>() {
+        Kind::Table(fields) => fields,
+        other => panic!("not a table: {:?}", other),
+    }
+}
+
+fn unknown(config: &str) -> Vec<String> {
+    let value: serde_json::Value = toml::from_str(config).unwrap();
+    unknown_keys(&schema::<AppSpecificConfig>(), &value, "app_specific")
+}
+
+#[test]
+fn finds_required_fields_and_defaults() {
+    let fields = fields();
+    let field = |name: &str| fields.iter().find(|field| field.name == name).unwrap();
+
+    assert!(field("run_command").required);
+    assert!(field("interval_seconds").required);
+    assert_eq!(field("interval_seconds").kind, Kind::Integer);
+
+    let timeout = field("shutdown_timeout_seconds");
+    assert!(!timeout.required);
+    assert_eq!(timeout.default, Some(toml::Value::Integer(5)));
+
+    assert_eq!(field("log_format").kind, Kind::Choice(vec!["text", "json"]));
+    assert!(matches!(field("working_dir").kind, Kind::Optional(_)));
+}
+
+#[test]
+fn rejects_unknown_keys_with_suggestions() {
+    let problems = unknown(
+        r#"
+intervl_seconds = 1
+run_command = "./server"
+completely_made_up = true
+
+[reservation]
+memory_mb = 512
+port = [8080]
+
+[[log_rules]]
+pattern = "ERROR"
+action = "warning"
+acton = "restart"
+
+[env]
+ANYTHING_GOES = "1"
+"#,
+    );
+    assert_eq!(
+        problems,
+        vec![
+            "Unknown key app_specific.completely_made_up",
+            "Unknown key app_specific.intervl_seconds, did you mean interval_seconds?",
+            "Unknown key app_specific.log_rules[0].acton, did you mean action?",
+            "Unknown key app_specific.reservation.port, did you mean ports?",
+        ]
+    );
+    assert!(unknown("run_command = \"./server\"\nport = \"auto\"").is_empty());
+}
+
+#[test]
+fn explains_every_option() {
+    let reference = explain(&schema::<AppSpecificConfig>(), "app_specific");
+
+    assert!(reference.starts_with("[app_specific]\n"));
+    assert!(reference.contains("run_command = <value>"));
+    assert!(reference.contains("# required"));
+    assert!(reference.contains("# default: 5"));
+    assert!(reference.contains("\n[app_specific.reservation]\n"));
+    assert!(reference.contains("\n[[app_specific.log_rules]]"));
+    for field in fields() {
+        assert!(reference.contains(field.name), "{} missing", field.name);
+    }
+}
+
+fn mismatched(config: &str) -> Vec<String> {
+    let value: serde_json::Value = toml::from_str(config).unwrap();
+    mismatched_keys(&schema::<AppSpecificConfig>(), &value, "app_specific")
+}
+
+#[test]
+fn names_options_of_the_wrong_type() {
+    let problems = mismatched(
+        r#"
+interval_seconds = "soon"
+monitor_path = "./"
+project_path = "./"
+changes_needed = "10"
+ignored_subdirs = []
+log_format = "xml"
+"#,
+    );
+    assert_eq!(
+        problems,
+        [
+            "app_specific.interval_seconds: expected integer, found string \"soon\"",
+            "app_specific.run_command is required",
+            "app_specific.log_format: expected \"text\" | \"json\", found string \"xml\"",
+        ]
+    );
+}
@@ -0,0 +1,75 @@
+use ais_runner::cpu_limit::{CpuChange, CpuLimitAction, CpuLimitConfig, CpuMonitor};
+use std::time::{Duration, Instant};
+
+fn monitor(max_cpu_percent: f32, window_seconds: u64) -> CpuMonitor {
+    CpuMonitor::new(&CpuLimitConfig {
+        max_cpu_percent: Some(max_cpu_percent),
+        window_seconds,
+        action: CpuLimitAction::Warn,
+    })
+    .unwrap()
+}
+
+#[test]
+fn no_limit_means_no_monitor() {
+    assert!(CpuMonitor::new(&CpuLimitConfig::default()).is_none());
+}
+
+#[test]
+fn short_spikes_dont_count() {
+    let start = Instant::now();
+    let mut monitor = monitor(100.0, 60);
+    // A full window mostly idle with one spike
+    for check in 0..=12 {
+        let cpu = if check == 6 { 400.0 } else { 20.0 };
+        let change = monitor.observe(start + Duration::from_secs(check * 5), cpu);
+        assert_eq!(change, None);
+    }
+    assert!(monitor.average().unwrap() < 100.0);
+}
+
+#[test]
+fn the_average_is_only_judged_over_a_full_window() {
+    let start = Instant::now();
+    let mut monitor = monitor(100.0, 60);
+    assert_eq!(monitor.observe(start, 300.0), None);
+    assert_eq!(monitor.average(), None);
+    assert_eq!(
+        monitor.observe(start + Duration::from_secs(30), 300.0),
+        None
+    );
+    assert_eq!(
+        monitor.observe(start + Duration::from_secs(60), 300.0),
+        Some(CpuChange::Exceeded(300.0))
+    );
+}
+
+#[test]
+fn crossings_are_reported_once_each_way() {
+    let start = Instant::now();
+    let mut monitor = monitor(100.0, 10);
+    let mut changes = Vec::new();
+    let usage = [50.0, 50.0, 150.0, 150.0, 150.0, 150.0, 50.0, 50.0, 50.0];
+    for (check, cpu) in usage.into_iter().enumerate() {
+        let at = start + Duration::from_secs(check as u64 * 5);
+        changes.extend(monitor.observe(at, cpu));
+    }
+    // The three checks in the window average 117% first, then 83%
+    assert!(
+        matches!(
+            changes.as_slice(),
+            [CpuChange::Exceeded(high), CpuChange::Recovered(low)] if *high > 100.0 && *low < 100.0
+        ),
+        "{:?}",
+        changes
+    );
+}
+
+#[test]
+fn actions_parse_from_the_config() {
+    let config: CpuLimitConfig =
+        toml::from_str("max_cpu_percent = 150\naction = \"throttle\"").unwrap();
+    assert_eq!(config.max_cpu_percent, Some(150.0));
+    assert_eq!(config.window_seconds, 60);
+    assert_eq!(config.action, CpuLimitAction::Throttle);
+}
@@ -0,0 +1,75 @@
+use ais_runner::crash_loop::{CrashLoopBreaker, CrashLoopConfig, CrashLoopReport};
+use std::time::{Duration, Instant};
+
+fn config(max_restarts: usize) -> CrashLoopConfig {
+    CrashLoopConfig {
+        enabled: true,
+        max_restarts,
+        window_minutes: 1,
+    }
+}
+
+#[test]
+fn opens_after_too_many_restarts_in_the_window() {
+    let mut breaker = CrashLoopBreaker::new(config(3));
+    let start = Instant::now();
+
+    for second in 0..3 {
+        assert!(breaker.allow_restart(start + Duration::from_secs(second)));
+    }
+    assert!(!breaker.allow_restart(start + Duration::from_secs(3)));
+    assert!(breaker.is_open());
+
+    // Stays open no matter how much time passes
+    assert!(!breaker.allow_restart(start + Duration::from_secs(600)));
+
+    breaker.reset();
+    assert!(!breaker.is_open());
+    assert!(breaker.allow_restart(start + Duration::from_secs(601)));
+}
+
+#[test]
+fn old_restarts_fall_out_of_the_window() {
+    let mut breaker = CrashLoopBreaker::new(config(2));
+    let start = Instant::now();
+
+    assert!(breaker.allow_restart(start));
+    assert!(breaker.allow_restart(start + Duration::from_secs(30)));
+    assert!(breaker.allow_restart(start + Duration::from_secs(61)));
+    assert!(breaker.allow_restart(start + Duration::from_secs(95)));
+    assert!(!breaker.allow_restart(start + Duration::from_secs(100)));
+}
+
+#[test]
+fn disabled_breaker_never_opens() {
+    let mut breaker = CrashLoopBreaker::new(CrashLoopConfig {
+        enabled: false,
+        ..config(1)
+    });
+    let start = Instant::now();
+
+    for second in 0..10 {
+        assert!(breaker.allow_restart(start + Duration::from_secs(second)));
+    }
+}
+
+#[test]
+fn report_keeps_the_last_stderr_lines() {
+    let mut breaker = CrashLoopBreaker::new(config(1));
+    let start = Instant::now();
+    breaker.allow_restart(start);
+    breaker.allow_restart(start);
+
+    let stderr: Vec<(u64, String)> = (0..30)
+        .map(|index| (index, format!("line {}", index)))
+        .collect();
+    let report = CrashLoopReport::new(42, &breaker, Some(1), &stderr);
+
+    assert_eq!(report.restarts, 1);
+    assert_eq!(report.stderr.len(), 20);
+    assert_eq!(report.stderr[0], "line 10");
+    assert_eq!(
+        report.to_string(),
+        "Child crashed 2 times within 1 minutes, respawning stopped (last exit code 1)"
+    );
+}
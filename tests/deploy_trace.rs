@@ -0,0 +1,111 @@
+use ais_runner::config::AppSpecificConfig;
+use ais_runner::deploy_trace::{DeployTrace, Stage, StageSpan, Timeline};
+use ais_runner::restart::{RestartKind, RestartReason};
+use ais_runner::runner_state::{RunnerState, finish_restart, record_restart};
+use ais_runner::supervisor::Supervisor;
+use artisan_middleware::dusa_collection_utils::core::types::pathtype::PathType;
+use std::time::{Duration, Instant};
+
+fn span(stage: Stage, start_ms: u64, duration_ms: u64) -> StageSpan {
+    StageSpan {
+        stage,
+        start_ms,
+        duration_ms,
+    }
+}
+
+#[test]
+fn stages_last_until_the_next_begins() {
+    let triggered = Instant::now();
+    let at = |ms| triggered + Duration::from_millis(ms);
+
+    let mut trace = DeployTrace::new(Some(triggered), at(2_000));
+    trace.begin(Stage::Build, at(2_000));
+    trace.begin(Stage::Stop, at(30_000));
+    trace.begin(Stage::Start, at(30_500));
+    trace.begin(Stage::Ready, at(30_600));
+    trace.end(at(31_600));
+    // Nothing open anymore
+    trace.end(at(40_000));
+
+    assert_eq!(
+        trace.spans(),
+        &[
+            span(Stage::Debounce, 0, 2_000),
+            span(Stage::Build, 2_000, 28_000),
+            span(Stage::Stop, 30_000, 500),
+            span(Stage::Start, 30_500, 100),
+            span(Stage::Ready, 30_600, 1_000),
+        ]
+    );
+}
+
+#[test]
+fn restarts_without_debounce_start_when_handled() {
+    let now = Instant::now();
+    let mut trace = DeployTrace::new(None, now);
+    trace.begin(Stage::Stop, now + Duration::from_millis(5));
+    trace.end(now + Duration::from_millis(25));
+
+    assert_eq!(trace.spans(), &[span(Stage::Stop, 5, 20)]);
+}
+
+#[test]
+fn debounce_starts_at_the_first_counted_change() {
+    let settings = AppSpecificConfig {
+        changes_needed: 2,
+        ..AppSpecificConfig::default()
+    };
+    let start = Instant::now();
+    let mut supervisor = Supervisor::new(&settings);
+
+    supervisor.set_maintenance(true);
+    assert_eq!(supervisor.on_change(0, start), None);
+    assert_eq!(supervisor.first_change(), None);
+    supervisor.set_maintenance(false);
+
+    let first = start + Duration::from_secs(1);
+    assert_eq!(supervisor.on_change(0, first), None);
+    assert!(
+        supervisor
+            .on_change(0, first + Duration::from_secs(1))
+            .is_some()
+    );
+    assert_eq!(supervisor.first_change(), Some(first));
+
+    supervisor.reset_changes();
+    assert_eq!(supervisor.first_change(), None);
+}
+
+#[test]
+fn timelines_come_from_the_history() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_path = PathType::Content(dir.path().join("app.state").to_string_lossy().into_owned());
+
+    record_restart(&state_path, 10, &RestartKind::Reload.into()).unwrap();
+    record_restart(
+        &state_path,
+        20,
+        &RestartReason::new(RestartKind::Changes, "3 file changes"),
+    )
+    .unwrap();
+    let stages = [
+        span(Stage::Debounce, 0, 1_500),
+        span(Stage::Build, 1_500, 4_000),
+    ];
+    finish_restart(&state_path, 20, None, true, &stages).unwrap();
+
+    let history = RunnerState::load(&state_path).restart_history;
+    // Still running or recorded before timelines were
+    assert_eq!(Timeline::of(&history[0]), None);
+
+    let timeline = Timeline::of(&history[1]).unwrap();
+    assert_eq!(timeline.total_ms, 5_500);
+    assert_eq!(timeline.kind, Some(RestartKind::Changes));
+    assert_eq!(timeline.success, Some(true));
+    assert_eq!(timeline.stages, stages);
+
+    let json = serde_json::to_value(&timeline).unwrap();
+    assert_eq!(json["stages"][1]["stage"], "build");
+    assert_eq!(json["stages"][1]["start_ms"], 1_500);
+}
@@ -0,0 +1,101 @@
+use ais_runner::config::AppSpecificConfig;
+use ais_runner::dry_run::{DryRunReport, StepResult, rehearse, scratch_path};
+use artisan_middleware::config::AppConfig;
+use artisan_middleware::dusa_collection_utils::core::types::stringy::Stringy;
+use std::path::Path;
+
+/// A config of its own per test, so their scratch states don't collide.
+fn config(app_name: &str) -> AppConfig {
+    let mut config = AppConfig::dummy();
+    config.app_name = Stringy::from(format!("dry_run_{}_{}", app_name, std::process::id()));
+    config
+}
+
+fn scratch_left(config: &AppConfig) -> bool {
+    let scratch = scratch_path(&config.app_name.to_string());
+    Path::new(&scratch.to_string()).exists()
+}
+
+fn outcomes(report: &DryRunReport) -> Vec<(String, &'static str)> {
+    report
+        .steps()
+        .map(|(step, result)| {
+            let outcome = match result {
+                StepResult::Passed(_) => "pass",
+                StepResult::Skipped(_) => "skip",
+                StepResult::Failed(_) => "fail",
+            };
+            (step.to_owned(), outcome)
+        })
+        .collect()
+}
+
+fn outcome(report: &DryRunReport, step: &str) -> Option<&'static str> {
+    outcomes(report)
+        .into_iter()
+        .find(|(name, _)| name == step)
+        .map(|(_, outcome)| outcome)
+}
+
+#[tokio::test]
+async fn a_missing_project_stops_before_installing() {
+    let config = config("missing_project");
+    let dir = tempfile::tempdir().unwrap();
+    let settings = AppSpecificConfig {
+        project_path: dir.path().join("missing").to_string_lossy().into_owned(),
+        install_command: Some(String::from("true")),
+        ..Default::default()
+    };
+
+    let mut report = DryRunReport::default();
+    rehearse(&mut report, &config, &settings).await;
+    assert!(!report.passed());
+    assert_eq!(outcome(&report, "project_path"), Some("fail"));
+    assert_eq!(outcome(&report, "secrets"), Some("skip"));
+    assert_eq!(outcome(&report, "install"), Some("skip"));
+    assert_eq!(outcome(&report, "build"), None);
+    assert_eq!(outcome(&report, "run_command"), None);
+    // Returning early still cleans up
+    assert!(!scratch_left(&config));
+}
+
+#[tokio::test]
+async fn skipped_steps_dont_fail_the_run() {
+    let config = config("skipped");
+    let dir = tempfile::tempdir().unwrap();
+    let project = dir.path().to_string_lossy().into_owned();
+    let settings = AppSpecificConfig {
+        project_path: project.clone(),
+        monitor_path: project,
+        run_command: String::from("true"),
+        build_command: Some(String::from("true")),
+        ..Default::default()
+    };
+
+    let mut report = DryRunReport::default();
+    rehearse(&mut report, &config, &settings).await;
+    assert_eq!(outcome(&report, "project_path"), Some("pass"));
+    assert_eq!(outcome(&report, "install"), Some("skip"));
+    assert_eq!(outcome(&report, "build"), Some("pass"));
+    assert_eq!(outcome(&report, "run_command"), Some("skip"));
+    assert!(report.passed(), "{:?}", outcomes(&report));
+}
+
+#[tokio::test]
+async fn a_failing_build_fails_the_run() {
+    let config = config("failing_build");
+    let dir = tempfile::tempdir().unwrap();
+    let project = dir.path().to_string_lossy().into_owned();
+    let settings = AppSpecificConfig {
+        project_path: project.clone(),
+        monitor_path: project,
+        build_command: Some(String::from("false")),
+        ..Default::default()
+    };
+
+    let mut report = DryRunReport::default();
+    rehearse(&mut report, &config, &settings).await;
+    assert_eq!(outcome(&report, "build"), Some("fail"));
+    assert!(!report.passed());
+    assert!(!scratch_left(&config));
+}
@@ -0,0 +1,50 @@
+use ais_runner::config::new_application_state;
+use ais_runner::dump::{Dump, LINES, Monitors, path, write};
+use artisan_middleware::config::AppConfig;
+use artisan_middleware::dusa_collection_utils::core::types::pathtype::PathType;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[test]
+fn dumps_show_the_child_and_its_last_lines() {
+    let mut state = new_application_state(&AppConfig::dummy());
+    state.stdout = (0..60).map(|n| (n, format!("line {}", n))).collect();
+    let dump = Dump::of(&state, Duration::from_secs(90), 1_760_583_700);
+    assert_eq!(dump.runner_pid, std::process::id());
+    assert_eq!(dump.lines.len(), LINES);
+    assert!(dump.lines[LINES - 1].ends_with("line 59"));
+
+    let dump = Dump {
+        child_pid: Some(4242),
+        child_alive: false,
+        main_loop: Some(Duration::from_secs(5)),
+        pending_changes: 3,
+        monitors: Monitors {
+            count: Some(2),
+            paused: true,
+        },
+        lines: vec![String::from("1760583699 stdout listening")],
+        ..dump
+    };
+    let text = dump.render();
+    assert!(text.starts_with("Dump at 1760583700\n"));
+    assert!(text.contains("child pid: 4242 (gone)\n"));
+    assert!(text.contains("pending changes: 3\n"));
+    assert!(text.contains("monitors: 2 watching, paused\n"));
+    assert!(text.ends_with("last 1 lines:\n  1760583699 stdout listening\n"));
+}
+
+#[test]
+fn dumps_are_private_to_the_runner() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_path = PathType::Content(dir.path().join("app.state").to_string_lossy().into_owned());
+    let dump = path(&state_path);
+    assert_eq!(dump, PathBuf::from(format!("{}.dump", state_path)));
+
+    write(&dump, "status: Running\n").unwrap();
+    assert_eq!(fs::read_to_string(&dump).unwrap(), "status: Running\n");
+    let mode = fs::metadata(&dump).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+}
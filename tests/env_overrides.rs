@@ -0,0 +1,106 @@
+use ais_runner::config::AppSpecificConfig;
+use ais_runner::config_schema::{Kind, schema};
+use ais_runner::env_overrides::{apply, collect, key};
+use config::{Config, File, FileFormat};
+
+const CONFIG: &str = r#"
+[app_specific]
+interval_seconds = 30
+monitor_path = "./"
+project_path = "./"
+changes_needed = 3
+ignored_subdirs = ["target"]
+run_command = "npm run start"
+
+[app_specific.env]
+NODE_ENV = "development"
+"#;
+
+fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+    vars.iter()
+        .map(|(var, value)| (var.to_string(), value.to_string()))
+        .collect()
+}
+
+fn load(schema: &Kind, env: &[(&str, &str)]) -> AppSpecificConfig {
+    let overrides = collect(schema, vars(env)).unwrap();
+    let builder = Config::builder().add_source(File::from_str(CONFIG, FileFormat::Toml));
+    apply(builder, &overrides)
+        .unwrap()
+        .build()
+        .unwrap()
+        .get("app_specific")
+        .unwrap()
+}
+
+#[test]
+fn variables_name_their_option() {
+    assert_eq!(key("AIS_RUN_COMMAND").as_deref(), Some("run_command"));
+    assert_eq!(
+        key("AIS_CGROUP__MEMORY_MAX_MB").as_deref(),
+        Some("cgroup.memory_max_mb")
+    );
+    assert_eq!(key("AIS_"), None);
+    assert_eq!(key("PATH"), None);
+}
+
+#[test]
+fn variables_win_over_the_file() {
+    let schema = schema::<AppSpecificConfig>();
+    let settings = load(
+        &schema,
+        &[
+            ("AIS_RUN_COMMAND", "./server --port 8080"),
+            ("AIS_CHANGES_NEEDED", "10"),
+            ("AIS_IGNORED_SUBDIRS", "node_modules, dist"),
+            ("AIS_CGROUP__ENABLED", "true"),
+            ("AIS_ENV", "{ region = \"eu-west\" }"),
+            ("PATH", "/usr/bin"),
+        ],
+    );
+    assert_eq!(settings.run_command, "./server --port 8080");
+    assert_eq!(settings.changes_needed, 10);
+    assert_eq!(settings.ignored_subdirs, ["node_modules", "dist"]);
+    assert!(settings.cgroup.enabled);
+    // Tables are merged into the one from the file
+    assert_eq!(settings.env.len(), 2);
+    assert_eq!(settings.interval_seconds, 30);
+
+    let untouched = load(&schema, &[]);
+    assert_eq!(untouched.run_command, "npm run start");
+    assert_eq!(untouched.ignored_subdirs, ["target"]);
+}
+
+#[test]
+fn lists_take_toml_arrays() {
+    let schema = schema::<AppSpecificConfig>();
+    let settings = load(
+        &schema,
+        &[(
+            "AIS_LOG_RULES",
+            "[{ pattern = \"listening\", action = \"ready\" }]",
+        )],
+    );
+    assert_eq!(settings.log_rules.len(), 1);
+
+    let problems = collect(&schema, vars(&[("AIS_LOG_RULES", "listening")])).unwrap_err();
+    assert_eq!(problems.len(), 1);
+    assert!(problems[0].starts_with("AIS_LOG_RULES: expected a TOML array"));
+}
+
+#[test]
+fn unknown_variables_are_rejected() {
+    let schema = schema::<AppSpecificConfig>();
+    let problems = collect(
+        &schema,
+        vars(&[("AIS_RUN_COMAND", "./server"), ("AIS_NOT_AN_OPTION", "1")]),
+    )
+    .unwrap_err();
+    assert_eq!(
+        problems,
+        [
+            "AIS_NOT_AN_OPTION: Unknown key app_specific.not_an_option",
+            "AIS_RUN_COMAND: Unknown key app_specific.run_comand, did you mean run_command?",
+        ]
+    );
+}
@@ -0,0 +1,71 @@
+use ais_runner::events::{Event, EventEntry, EventJournal, read, rotated};
+use std::path::Path;
+
+fn entry(timestamp: u64, pid: u32) -> EventEntry {
+    EventEntry {
+        timestamp,
+        event: Event::ChildSpawned { pid },
+    }
+}
+
+fn timestamps(path: &Path) -> Vec<u64> {
+    read(path)
+        .unwrap()
+        .iter()
+        .map(|entry| entry.timestamp)
+        .collect()
+}
+
+#[test]
+fn entries_are_json_lines() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("state.events");
+    let mut journal = EventJournal::open(&path, 10, 5).unwrap();
+    journal.append(&entry(1, 4242)).unwrap();
+    journal
+        .append(&EventEntry {
+            timestamp: 2,
+            event: Event::BuildFinished {
+                step: String::from("build"),
+                success: true,
+                duration_ms: 5120,
+                error: None,
+            },
+        })
+        .unwrap();
+
+    let text = std::fs::read_to_string(&path).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            r#"{"timestamp":1,"event":"child_spawned","pid":4242}"#,
+            r#"{"timestamp":2,"event":"build_finished","step":"build","success":true,"duration_ms":5120}"#,
+        ]
+    );
+    assert_eq!(read(&path).unwrap()[0], entry(1, 4242));
+}
+
+#[test]
+fn rotation_keeps_the_newest_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("state.events");
+    let mut journal = EventJournal::open(&path, 1, 2).unwrap();
+    // Two of them fit in a megabyte
+    for count in 1..=7 {
+        let event = Event::Changes {
+            count,
+            detail: "x".repeat(400 * 1024),
+        };
+        journal
+            .append(&EventEntry {
+                timestamp: count as u64,
+                event,
+            })
+            .unwrap();
+    }
+    assert_eq!(timestamps(&path), [7]);
+    assert_eq!(timestamps(&rotated(&path, 1)), [5, 6]);
+    assert_eq!(timestamps(&rotated(&path, 2)), [3, 4]);
+    assert!(!rotated(&path, 3).exists());
+}
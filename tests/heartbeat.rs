@@ -0,0 +1,80 @@
+use ais_runner::heartbeat::{ChildReport, Heartbeat};
+use std::{
+    os::unix::net::UnixDatagram,
+    time::{Duration, Instant},
+};
+
+#[test]
+fn reports_follow_the_sd_notify_format() {
+    let spawned = Instant::now();
+    let mut report = ChildReport::default();
+    report.reset(spawned);
+
+    report.apply("STATUS=warming caches", spawned);
+    assert!(!report.ready);
+    assert_eq!(report.status.as_deref(), Some("warming caches"));
+
+    report.apply("READY=1\nSTATUS=serving 3 workers\nMAINPID=42", spawned);
+    assert!(report.ready);
+    assert_eq!(report.status.as_deref(), Some("serving 3 workers"));
+
+    // READY=0 and unknown keys change nothing
+    report.apply("READY=0\nBOGUS", spawned);
+    assert!(report.ready);
+}
+
+#[test]
+fn silence_is_counted_from_the_spawn_then_the_last_beat() {
+    let spawned = Instant::now();
+    let timeout = Duration::from_secs(30);
+    let mut report = ChildReport::default();
+    assert_eq!(report.overdue(timeout, spawned + timeout * 2), None);
+
+    report.reset(spawned);
+    assert_eq!(report.overdue(timeout, spawned + timeout), None);
+    assert_eq!(
+        report.overdue(timeout, spawned + Duration::from_secs(31)),
+        Some(Duration::from_secs(31))
+    );
+
+    report.apply("WATCHDOG=1", spawned + Duration::from_secs(20));
+    assert_eq!(
+        report.overdue(timeout, spawned + Duration::from_secs(45)),
+        None
+    );
+    assert!(
+        report
+            .overdue(timeout, spawned + Duration::from_secs(51))
+            .is_some()
+    );
+}
+
+#[tokio::test]
+async fn child_messages_arrive_over_the_socket() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("app.state.notify");
+    let heartbeat = Heartbeat::listen(&path, Some(Duration::from_secs(60)), None).unwrap();
+    heartbeat.reset();
+    assert!(!heartbeat.is_ready());
+    assert_eq!(heartbeat.overdue(), None);
+
+    let client = UnixDatagram::unbound().unwrap();
+    client
+        .send_to(b"READY=1\nSTATUS=listening on :8080", &path)
+        .unwrap();
+
+    for _ in 0..50 {
+        if heartbeat.is_ready() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(heartbeat.is_ready());
+    assert_eq!(heartbeat.status().as_deref(), Some("listening on :8080"));
+    assert_eq!(heartbeat.status().as_deref(), Some("listening on :8080"));
+
+    // A new child starts from scratch
+    heartbeat.reset();
+    assert!(!heartbeat.is_ready());
+    assert_eq!(heartbeat.status(), None);
+}
@@ -0,0 +1,38 @@
+use ais_runner::config::AppSpecificConfig;
+use ais_runner::host::{HostCapabilities, kernel_version};
+
+#[test]
+fn parses_kernel_releases() {
+    assert_eq!(kernel_version("6.8.0-45-generic"), Some((6, 8)));
+    assert_eq!(kernel_version("5.3.18-lp152.19-default"), Some((5, 3)));
+    assert_eq!(kernel_version("4.19"), Some((4, 19)));
+    assert_eq!(kernel_version("unknown"), None);
+}
+
+#[test]
+fn warns_about_missing_capabilities() {
+    let old_host = HostCapabilities {
+        kernel: String::from("4.19.0"),
+        inotify_max_watches: Some(8192),
+        ..HostCapabilities::default()
+    };
+    let mut settings = AppSpecificConfig::default();
+    settings.cgroup.enabled = true;
+
+    let warnings = old_host.warnings(&settings);
+    assert_eq!(warnings.len(), 3);
+    assert!(warnings.iter().any(|warning| warning.contains("cgroup v2")));
+    assert!(
+        warnings
+            .iter()
+            .any(|warning| warning.contains("max_user_watches"))
+    );
+
+    let capable_host = HostCapabilities {
+        cgroup_v2: true,
+        pidfd: true,
+        inotify_max_watches: Some(524288),
+        ..old_host
+    };
+    assert!(capable_host.warnings(&settings).is_empty());
+}
@@ -0,0 +1,50 @@
+use ais_runner::init::{Reaper, parse_stat};
+
+#[test]
+fn parses_proc_stat() {
+    assert_eq!(
+        parse_stat("4242 (node) Z 1 4242 4242 0 -1 4194564 0"),
+        Some((4242, 'Z', 1))
+    );
+    // Command names can hold spaces and parentheses
+    assert_eq!(
+        parse_stat("77 (my (weird) app) S 12 77 77 0"),
+        Some((77, 'S', 12))
+    );
+    assert_eq!(parse_stat(""), None);
+    assert_eq!(parse_stat("77 (truncated"), None);
+}
+
+#[test]
+fn reaps_zombies_nobody_waited_for() {
+    let mut reaper = Reaper::default();
+    let child = 100;
+
+    // First sighting, someone may still be about to wait for them
+    assert!(reaper.select(&[100, 201, 202], child).is_empty());
+    // 202 was waited for meanwhile, 203 is new
+    assert_eq!(reaper.select(&[100, 201, 203], child), vec![201]);
+    assert_eq!(reaper.select(&[100, 203], child), vec![203]);
+    // The supervised child is never reaped here
+    assert!(reaper.select(&[100], child).is_empty());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn finds_own_zombies_in_proc() {
+    let mut child = std::process::Command::new("true").spawn().unwrap();
+    let pid = child.id();
+    let own = std::process::id();
+
+    let mut zombie = false;
+    for _ in 0..100 {
+        let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).unwrap();
+        if parse_stat(&stat) == Some((pid, 'Z', own)) {
+            zombie = true;
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    assert!(zombie);
+    child.wait().unwrap();
+}
@@ -0,0 +1,87 @@
+use ais_runner::journal::{JournalEntry, JournalFsync, OutputJournal};
+use std::{fs, path::PathBuf};
+
+fn scratch(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("ais_runner_{}_{}", name, std::process::id()));
+    _ = fs::remove_file(&path);
+    path
+}
+
+fn entries(path: &PathBuf) -> Vec<JournalEntry> {
+    fs::read_to_string(path)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[test]
+fn journal_appends_across_reopen() {
+    let path = scratch("journal_reopen");
+
+    let mut journal = OutputJournal::open(&path, JournalFsync::Batch, 10).unwrap();
+    journal.append(Some(0), 1, "child_stdout", "first").unwrap();
+    journal.commit().unwrap();
+    drop(journal);
+
+    // A restarted runner keeps appending to what the crashed one wrote
+    let mut journal = OutputJournal::open(&path, JournalFsync::Always, 10).unwrap();
+    journal
+        .append(Some(0), 2, "child_stderr", "second")
+        .unwrap();
+
+    let written = entries(&path);
+    assert_eq!(written.len(), 2);
+    assert_eq!(written[0].line, "first");
+    assert_eq!(written[1].event, "child_stderr");
+
+    _ = fs::remove_file(&path);
+}
+
+#[test]
+fn journal_rotates_at_max_size() {
+    let path = scratch("journal_rotate");
+    let mut rotated = path.clone().into_os_string();
+    rotated.push(".1");
+    _ = fs::remove_file(&rotated);
+
+    let mut journal = OutputJournal::open(&path, JournalFsync::Never, 1).unwrap();
+    let line = "x".repeat(1024);
+    for timestamp in 0..1100 {
+        journal
+            .append(Some(timestamp * 1025), timestamp, "child_stdout", &line)
+            .unwrap();
+    }
+
+    let current = entries(&path);
+    assert!(!current.is_empty());
+    assert!(fs::metadata(&path).unwrap().len() <= 1024 * 1024);
+    assert_eq!(
+        current.len() + entries(&PathBuf::from(&rotated)).len(),
+        1100
+    );
+
+    _ = fs::remove_file(&path);
+    _ = fs::remove_file(&rotated);
+}
+
+#[test]
+fn entries_keep_their_capture_ids() {
+    let path = scratch("journal_ids");
+
+    let mut journal = OutputJournal::open(&path, JournalFsync::Never, 10).unwrap();
+    // The child printed the same line twice in the same second
+    journal.append(Some(0), 1, "child_stdout", "tick").unwrap();
+    journal.append(Some(5), 1, "child_stdout", "tick").unwrap();
+    journal.append(None, 1, "build_stdout", "tick").unwrap();
+    let written = entries(&path);
+    let ids: Vec<Option<u64>> = written.iter().map(|entry| entry.id).collect();
+    assert_eq!(ids, [Some(0), Some(5), None]);
+
+    // Journals from before ids read as without one
+    let old: JournalEntry =
+        serde_json::from_str(r#"{"timestamp":1,"event":"child_stdout","line":"tick"}"#).unwrap();
+    assert_eq!(old.id, None);
+
+    _ = fs::remove_file(&path);
+}
@@ -0,0 +1,41 @@
+use ais_runner::lifecycle::Phase;
+use artisan_middleware::aggregator::Status;
+
+#[test]
+fn deploys_go_through_building_and_starting() {
+    let path = [
+        Phase::Idle,
+        Phase::Installing,
+        Phase::Building,
+        Phase::Starting,
+        Phase::Running,
+        Phase::Building,
+        Phase::Starting,
+        Phase::Degraded,
+        Phase::Running,
+        Phase::Idle,
+        Phase::Stopping,
+    ];
+    for pair in path.windows(2) {
+        assert!(pair[0].can_move_to(pair[1]), "{} -> {}", pair[0], pair[1]);
+    }
+}
+
+#[test]
+fn shortcuts_are_rejected() {
+    assert!(!Phase::Idle.can_move_to(Phase::Running));
+    assert!(!Phase::Building.can_move_to(Phase::Running));
+    assert!(!Phase::Installing.can_move_to(Phase::Degraded));
+    assert!(!Phase::Starting.can_move_to(Phase::Building));
+    // Nothing comes after stopping
+    assert!(!Phase::Stopping.can_move_to(Phase::Idle));
+    assert!(!Phase::Stopping.can_move_to(Phase::Running));
+}
+
+#[test]
+fn phases_map_onto_the_aggregator_status() {
+    assert!(matches!(Phase::Installing.status(), Status::Building));
+    assert!(matches!(Phase::Degraded.status(), Status::Warning));
+    assert!(matches!(Phase::Stopping.status(), Status::Stopping));
+    assert_eq!(Phase::Degraded.to_string(), "degraded");
+}
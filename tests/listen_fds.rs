@@ -0,0 +1,59 @@
+use ais_runner::config::AppSpecificConfig;
+use ais_runner::listen_fds::{ListenFdsConfig, ListenSockets};
+use ais_runner::ports::PortConfig;
+use ais_runner::validation::validate;
+use tokio::process::Command;
+
+#[tokio::test]
+async fn children_get_the_sockets_and_their_pid() {
+    let sockets = ListenSockets::bind(&[String::from("127.0.0.1:0")])
+        .await
+        .unwrap();
+    let script = "echo $LISTEN_FDS $LISTEN_PID $$; readlink /proc/$$/fd/3";
+    let argv = ListenSockets::wrap(&[String::from("sh"), String::from("-c"), script.into()]);
+    let mut command = Command::new(&argv[0]);
+    command.args(&argv[1..]);
+    sockets.pass(&mut command);
+
+    let output = command.output().await.unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    let fields: Vec<&str> = lines[0].split(' ').collect();
+    assert_eq!(fields[0], "1");
+    // LISTEN_PID is the pid of the program that was asked for
+    assert_eq!(fields[1], fields[2]);
+    assert!(lines[1].starts_with("socket:"));
+}
+
+#[test]
+fn the_port_is_bound_unless_addresses_are_given() {
+    let mut config = ListenFdsConfig::default();
+    assert!(config.addrs(Some(8080)).is_empty());
+    config.enabled = true;
+    assert_eq!(config.addrs(Some(8080)), ["0.0.0.0:8080"]);
+    config.addrs = vec![String::from("[::]:80")];
+    assert_eq!(config.addrs(Some(8080)), ["[::]:80"]);
+}
+
+#[test]
+fn enabling_needs_a_port_or_addresses() {
+    let dir = tempfile::tempdir().unwrap();
+    let settings = |port: Option<PortConfig>| AppSpecificConfig {
+        monitor_path: dir.path().to_string_lossy().into_owned(),
+        project_path: dir.path().to_string_lossy().into_owned(),
+        run_command: String::from("node server.js"),
+        port,
+        listen_fds: ListenFdsConfig {
+            enabled: true,
+            addrs: Vec::new(),
+        },
+        ..AppSpecificConfig::default()
+    };
+    assert!(validate(&settings(Some(PortConfig::Auto))).is_empty());
+    let keys: Vec<String> = validate(&settings(None))
+        .into_iter()
+        .map(|problem| problem.key)
+        .collect();
+    assert_eq!(keys, ["app_specific.listen_fds.addrs"]);
+}
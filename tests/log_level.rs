@@ -0,0 +1,22 @@
+use ais_runner::log_level::{LEVELS, effective, name, parse};
+use artisan_middleware::dusa_collection_utils::core::logger::LogLevel;
+
+#[test]
+fn parses_levels_ignoring_case() {
+    assert_eq!(name(parse("debug").unwrap()), "Debug");
+    assert_eq!(name(parse("WARN").unwrap()), "Warn");
+    for level in LEVELS {
+        assert_eq!(name(parse(&name(level)).unwrap()), name(level));
+    }
+
+    let err = parse("verbose").unwrap_err();
+    assert!(err.contains("Trace, Debug, Info, Warn, Error"), "{}", err);
+}
+
+#[test]
+fn the_override_wins_over_the_config() {
+    assert_eq!(name(effective(Some("Trace"), LogLevel::Info)), "Trace");
+    assert_eq!(name(effective(None, LogLevel::Info)), "Info");
+    // A hand edited sidecar with garbage falls back to the config
+    assert_eq!(name(effective(Some("loud"), LogLevel::Warn)), "Warn");
+}
@@ -0,0 +1,142 @@
+use ais_runner::config::AppSpecificConfig;
+use ais_runner::log_rules::{LogAction, LogRule, LogRules, WARNING_HOLD, ready_pattern, validate};
+use std::time::{Duration, Instant};
+
+fn rule(pattern: &str, action: LogAction) -> LogRule {
+    LogRule {
+        pattern: String::from(pattern),
+        action,
+    }
+}
+
+fn rules() -> Vec<LogRule> {
+    vec![
+        rule("FATAL", LogAction::Warning),
+        rule(r"listening on :\d+", LogAction::Ready),
+        rule("out of file descriptors", LogAction::Restart),
+    ]
+}
+
+#[test]
+fn parses_rules() {
+    let settings: AppSpecificConfig = toml::from_str(
+        r#"
+interval_seconds = 1
+monitor_path = "./"
+project_path = "./"
+changes_needed = 1
+ignored_subdirs = []
+run_command = "./server"
+
+[[log_rules]]
+pattern = "FATAL"
+action = "warning"
+
+[[log_rules]]
+pattern = "listening on :\\d+"
+action = "ready"
+
+[[log_rules]]
+pattern = "out of file descriptors"
+action = "restart"
+"#,
+    )
+    .unwrap();
+    assert_eq!(settings.log_rules, rules());
+    assert!(AppSpecificConfig::default().log_rules.is_empty());
+}
+
+#[test]
+fn first_matching_rule_wins() {
+    let now = Instant::now();
+    let mut log_rules = LogRules::new(&rules());
+
+    assert_eq!(log_rules.check("GET / 200", now), None);
+    assert_eq!(
+        log_rules.check("listening on :8080", now),
+        Some(LogAction::Ready)
+    );
+    assert_eq!(
+        log_rules.check("FATAL: out of file descriptors", now),
+        Some(LogAction::Warning)
+    );
+    assert_eq!(log_rules.take_restart(), None);
+}
+
+#[test]
+fn warnings_expire_after_the_hold() {
+    let start = Instant::now();
+    let mut log_rules = LogRules::new(&rules());
+    assert_eq!(log_rules.warning(start), None);
+
+    log_rules.check("FATAL: disk full", start);
+    log_rules.check("FATAL: disk still full", start + Duration::from_secs(60));
+    assert_eq!(
+        log_rules.warning(start + Duration::from_secs(61)),
+        Some("FATAL: disk still full")
+    );
+    assert_eq!(
+        log_rules.warning(start + Duration::from_secs(60) + WARNING_HOLD),
+        None
+    );
+
+    log_rules.check("FATAL: disk full", start);
+    log_rules.reset();
+    assert_eq!(log_rules.warning(start), None);
+}
+
+#[test]
+fn restart_keeps_the_first_line_until_taken() {
+    let now = Instant::now();
+    let mut log_rules = LogRules::new(&rules());
+
+    log_rules.check("accept: out of file descriptors", now);
+    log_rules.check("read: out of file descriptors", now);
+    assert_eq!(
+        log_rules.take_restart().as_deref(),
+        Some("accept: out of file descriptors")
+    );
+    assert_eq!(log_rules.take_restart(), None);
+}
+
+#[test]
+fn ready_rules_become_the_ready_gate() {
+    let mut settings = AppSpecificConfig {
+        log_rules: vec![
+            rule("listening on", LogAction::Ready),
+            rule("FATAL", LogAction::Warning),
+            rule("ready$", LogAction::Ready),
+        ],
+        ..AppSpecificConfig::default()
+    };
+    assert_eq!(
+        ready_pattern(&settings.log_rules).as_deref(),
+        Some("(?:listening on)|(?:ready$)")
+    );
+
+    let gate = settings.ready_gate().unwrap();
+    assert_eq!(
+        gate.log_line.as_deref(),
+        Some("(?:listening on)|(?:ready$)")
+    );
+    assert!(gate.validate().is_ok());
+
+    settings.log_rules.clear();
+    assert_eq!(settings.ready_gate(), None);
+}
+
+#[test]
+fn invalid_patterns_are_reported_and_skipped() {
+    let broken = vec![
+        rule("(unclosed", LogAction::Warning),
+        rule("FATAL", LogAction::Warning),
+    ];
+    assert!(validate(&broken).unwrap_err().contains("(unclosed"));
+    assert!(validate(&rules()).is_ok());
+
+    let mut log_rules = LogRules::new(&broken);
+    assert_eq!(
+        log_rules.check("FATAL", Instant::now()),
+        Some(LogAction::Warning)
+    );
+}
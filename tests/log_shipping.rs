@@ -0,0 +1,74 @@
+use ais_runner::config::AppSpecificConfig;
+use ais_runner::journal::JournalEntry;
+use ais_runner::log_shipping::{LogShipper, LogShippingConfig};
+use ais_runner::validation::validate;
+
+fn config(dir: &std::path::Path) -> LogShippingConfig {
+    LogShippingConfig {
+        // Nothing listens there
+        addr: Some(String::from("127.0.0.1:1")),
+        queue_dir: Some(dir.to_string_lossy().into_owned()),
+        queue_limit: 2,
+        timeout_seconds: 1,
+        ..LogShippingConfig::default()
+    }
+}
+
+fn lines(count: u64) -> Vec<JournalEntry> {
+    (0..count)
+        .map(|timestamp| JournalEntry {
+            id: Some(timestamp),
+            timestamp,
+            event: String::from("child_stdout"),
+            line: format!("line {}", timestamp),
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn batches_stay_spooled_while_the_aggregator_is_away() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut shipper = LogShipper::new(&config(dir.path()), "shop").unwrap();
+    assert_eq!(shipper.deliver().await, Ok(0));
+
+    for batch in 0..3 {
+        shipper.spool(lines(batch + 1), batch);
+    }
+    shipper.spool(Vec::new(), 3);
+    // The oldest batch made room for the newest
+    assert_eq!(shipper.spooled(), 2);
+
+    assert!(shipper.deliver().await.is_err());
+    assert_eq!(shipper.spooled(), 2);
+
+    // Picked up again by the next run
+    let shipper = LogShipper::new(&config(dir.path()), "shop").unwrap();
+    assert_eq!(shipper.spooled(), 2);
+}
+
+#[test]
+fn shipping_options_are_validated() {
+    let dir = tempfile::tempdir().unwrap();
+    let settings = AppSpecificConfig {
+        monitor_path: dir.path().to_string_lossy().into_owned(),
+        project_path: dir.path().to_string_lossy().into_owned(),
+        run_command: String::from("node server.js"),
+        log_shipping: LogShippingConfig {
+            addr: Some(String::from("http://logs.example.com")),
+            batch_lines: 0,
+            ..LogShippingConfig::default()
+        },
+        ..AppSpecificConfig::default()
+    };
+    let keys: Vec<String> = validate(&settings)
+        .into_iter()
+        .map(|problem| problem.key)
+        .collect();
+    assert_eq!(
+        keys,
+        [
+            "app_specific.log_shipping.addr",
+            "app_specific.log_shipping.batch_lines"
+        ]
+    );
+}
@@ -0,0 +1,65 @@
+use ais_runner::logging::{output_line, runner_line};
+use artisan_middleware::dusa_collection_utils::core::logger::LogLevel;
+use serde_json::Value;
+
+fn parse(line: &str) -> Value {
+    serde_json::from_str(line).unwrap()
+}
+
+#[test]
+fn records_have_every_field() {
+    let line = runner_line(LogLevel::Warn, LogLevel::Info, 42, "app", "runner", "hi").unwrap();
+    let record = parse(&line);
+    let mut keys: Vec<&str> = record
+        .as_object()
+        .unwrap()
+        .keys()
+        .map(String::as_str)
+        .collect();
+    keys.sort();
+    assert_eq!(
+        keys,
+        ["app_name", "event", "level", "message", "pid", "timestamp"]
+    );
+    assert_eq!(record["timestamp"], 42);
+    assert_eq!(record["level"], "warn");
+    assert_eq!(record["app_name"], "app");
+    assert_eq!(record["pid"], std::process::id());
+    assert_eq!(record["event"], "runner");
+    assert_eq!(record["message"], "hi");
+}
+
+#[test]
+fn messages_are_escaped_onto_one_line() {
+    let message = "said \"hi\"\n\tand left \\ \u{1b}[31m";
+    let line = output_line(7, "app", "child_stderr", message);
+    assert!(!line.contains('\n'));
+    assert_eq!(parse(&line)["message"], message);
+}
+
+#[test]
+fn runner_lines_below_the_level_are_dropped() {
+    assert!(runner_line(LogLevel::Debug, LogLevel::Info, 1, "app", "runner", "x").is_none());
+    assert!(runner_line(LogLevel::Info, LogLevel::Info, 1, "app", "runner", "x").is_some());
+    assert!(runner_line(LogLevel::Error, LogLevel::Warn, 1, "app", "runner", "x").is_some());
+    assert!(runner_line(LogLevel::Trace, LogLevel::Trace, 1, "app", "runner", "x").is_some());
+}
+
+#[test]
+fn child_output_ignores_the_level() {
+    // Would be dropped as a runner line at this level
+    assert!(
+        runner_line(
+            LogLevel::Info,
+            LogLevel::Error,
+            1,
+            "app",
+            "child_stdout",
+            "x"
+        )
+        .is_none()
+    );
+    let record = parse(&output_line(1, "app", "child_stdout", "x"));
+    assert_eq!(record["level"], "info");
+    assert_eq!(record["event"], "child_stdout");
+}
@@ -0,0 +1,86 @@
+use ais_runner::maintenance::{
+    FLAG_FILE, Maintenance, MaintenanceChange, MaintenanceInfo, MaintenanceSource, flag_path,
+};
+use std::{
+    fs,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+#[test]
+fn flag_file_switches_maintenance() {
+    let dir = tempfile::tempdir().unwrap();
+    let flag = flag_path(&dir.path().to_string_lossy());
+    assert_eq!(flag, dir.path().join(FLAG_FILE));
+
+    let mut maintenance = Maintenance::new(flag.clone(), Arc::new(AtomicBool::new(false)));
+    assert_eq!(maintenance.poll(), None);
+    assert!(!maintenance.is_active());
+
+    fs::write(&flag, "").unwrap();
+    assert_eq!(
+        maintenance.poll(),
+        Some(MaintenanceChange::Entered(MaintenanceSource::FlagFile))
+    );
+    assert!(maintenance.is_active());
+    assert_eq!(maintenance.poll(), None);
+
+    fs::remove_file(&flag).unwrap();
+    assert_eq!(maintenance.poll(), Some(MaintenanceChange::Left));
+    assert!(!maintenance.is_active());
+}
+
+#[test]
+fn signal_toggle_wins_over_the_flag_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let flag = flag_path(&dir.path().to_string_lossy());
+    let toggled = Arc::new(AtomicBool::new(false));
+    let mut maintenance = Maintenance::new(flag.clone(), toggled.clone());
+
+    toggled.store(true, Ordering::Relaxed);
+    assert_eq!(
+        maintenance.poll(),
+        Some(MaintenanceChange::Entered(MaintenanceSource::Signal))
+    );
+
+    fs::write(&flag, "").unwrap();
+    assert_eq!(maintenance.poll(), None);
+
+    // Still on while either is set, now because of the file
+    toggled.store(false, Ordering::Relaxed);
+    assert_eq!(
+        maintenance.poll(),
+        Some(MaintenanceChange::Entered(MaintenanceSource::FlagFile))
+    );
+    assert!(maintenance.is_active());
+
+    fs::remove_file(&flag).unwrap();
+    assert_eq!(maintenance.poll(), Some(MaintenanceChange::Left));
+}
+
+#[test]
+fn signal_toggle_survives_a_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    let flag = flag_path(&dir.path().to_string_lossy());
+    let toggled = Arc::new(AtomicBool::new(false));
+    let mut maintenance = Maintenance::new(flag, toggled.clone());
+
+    // The flag file is still there by itself
+    maintenance.restore(Some(&MaintenanceInfo {
+        since: 10,
+        source: MaintenanceSource::FlagFile,
+    }));
+    maintenance.restore(None);
+    assert!(!toggled.load(Ordering::Relaxed));
+
+    maintenance.restore(Some(&MaintenanceInfo {
+        since: 10,
+        source: MaintenanceSource::Signal,
+    }));
+    assert_eq!(
+        maintenance.poll(),
+        Some(MaintenanceChange::Entered(MaintenanceSource::Signal))
+    );
+}
@@ -0,0 +1,161 @@
+use ais_runner::metrics_history::{
+    Bucket, MetricsHistory, MetricsHistoryConfig, Report, regroup, sparkline,
+};
+use ais_runner::state_sync::{StateLog, answer};
+use ais_runner::webhook::Request;
+use std::collections::HashMap;
+
+fn config(window_hours: u64, resolution_seconds: u64) -> MetricsHistoryConfig {
+    MetricsHistoryConfig {
+        window_hours,
+        resolution_seconds,
+        leak_warn_mb_per_hour: None,
+    }
+}
+
+/// One check per minute for `minutes`, memory growing by `growth` MB a minute.
+fn filled(config: &MetricsHistoryConfig, minutes: u64, growth: f64) -> MetricsHistory {
+    let mut history = MetricsHistory::new(config);
+    for minute in 0..=minutes {
+        history.record(minute * 60, 10.0, 100.0 + growth * minute as f64);
+    }
+    history
+}
+
+#[test]
+fn checks_are_downsampled_into_buckets() {
+    let mut history = MetricsHistory::new(&config(24, 60));
+    assert!(!history.record(0, 10.0, 100.0));
+    assert!(!history.record(20, 20.0, 200.0));
+    assert!(!history.record(40, 30.0, 150.0));
+    assert!(history.buckets().is_empty());
+
+    // The first check of the next minute closes the bucket
+    assert!(history.record(60, 0.0, 0.0));
+    assert_eq!(
+        history.buckets(),
+        vec![Bucket {
+            start: 0,
+            samples: 3,
+            cpu: 20.0,
+            memory: 150.0,
+            memory_max: 200.0,
+        }]
+    );
+}
+
+#[test]
+fn the_window_drops_old_buckets() {
+    let history = filled(&config(1, 60), 180, 0.0);
+    let buckets = history.buckets();
+    assert_eq!(buckets.len(), 60);
+    assert_eq!(buckets[0].start, 120 * 60);
+
+    // A gap in the checks also ages buckets out
+    let mut history = filled(&config(1, 60), 10, 0.0);
+    history.record(5 * 3600, 10.0, 100.0);
+    history.record(5 * 3600 + 60, 10.0, 100.0);
+    assert_eq!(history.buckets().len(), 1);
+}
+
+#[test]
+fn buckets_regroup_weighted_by_samples() {
+    let bucket = |start, samples, memory| Bucket {
+        start,
+        samples,
+        cpu: 0.0,
+        memory,
+        memory_max: memory,
+    };
+    let grouped = regroup(
+        &[
+            bucket(0, 1, 100.0),
+            bucket(60, 3, 200.0),
+            bucket(120, 2, 50.0),
+        ],
+        120,
+    );
+    assert_eq!(grouped.len(), 2);
+    assert_eq!(grouped[0].start, 0);
+    assert_eq!(grouped[0].samples, 4);
+    assert_eq!(grouped[0].memory, 175.0);
+    assert_eq!(grouped[0].memory_max, 200.0);
+    assert_eq!(grouped[1], bucket(120, 2, 50.0));
+}
+
+#[test]
+fn the_trend_shows_growing_memory() {
+    let config = config(24, 60);
+    assert_eq!(filled(&config, 30, 1.0).memory_trend(), None);
+
+    let leaking = filled(&config, 180, 0.5);
+    let trend = leaking.memory_trend().unwrap();
+    assert!((trend - 30.0).abs() < 0.01, "{}", trend);
+    assert!(filled(&config, 180, 0.0).memory_trend().unwrap().abs() < 0.01);
+}
+
+#[test]
+fn leaks_are_reported_once_an_hour() {
+    let mut history = filled(&config(24, 60), 180, 0.5);
+    assert_eq!(history.leak(10_800, 50.0), None);
+    assert!(history.leak(10_800, 20.0).is_some());
+    assert_eq!(history.leak(12_000, 20.0), None);
+    assert!(history.leak(14_400, 20.0).is_some());
+}
+
+#[test]
+fn the_history_survives_a_round_trip() {
+    let config = config(24, 60);
+    let history = filled(&config, 90, 1.0);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("state.json.metrics");
+    history.save(&path).unwrap();
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), 9 + 24 * 90);
+
+    let loaded = MetricsHistory::load(&path, &config);
+    assert_eq!(loaded.buckets(), history.buckets());
+
+    // A coarser resolution downsamples what was recorded
+    let coarse = MetricsHistory::load(&path, &self::config(24, 600));
+    assert_eq!(coarse.buckets().len(), 9);
+    assert_eq!(coarse.buckets()[0].samples, 10);
+
+    assert!(MetricsHistory::decode(b"garbage", &config).is_err());
+    let missing = MetricsHistory::load(&dir.path().join("missing"), &config);
+    assert!(missing.buckets().is_empty());
+}
+
+#[test]
+fn reports_are_downsampled_to_the_points_asked_for() {
+    let history = filled(&config(24, 60), 180, 0.0);
+    let report = history.report(Some(24));
+    assert_eq!(report.resolution_seconds, 3600);
+    assert_eq!(report.buckets.len(), 3);
+    assert_eq!(history.report(None).buckets.len(), 180);
+}
+
+#[test]
+fn sparklines_scale_to_the_range() {
+    assert_eq!(sparkline(&[1.0, 2.0, 3.0]), "▁▅█");
+    assert_eq!(sparkline(&[5.0, 5.0]), "▁▁");
+    assert_eq!(sparkline(&[]), "");
+}
+
+#[test]
+fn the_state_sync_listener_serves_the_history() {
+    let history = filled(&config(24, 60), 120, 0.0);
+    let log = StateLog::new(1_000);
+    let request = Request {
+        method: String::from("GET"),
+        target: String::from("/metrics?points=2"),
+        headers: HashMap::from([(String::from("authorization"), String::from("Bearer token"))]),
+        body: Vec::new(),
+    };
+
+    let (status, body) = answer(&request, "token", &log, Some(&history));
+    assert_eq!(status, "200 OK");
+    let report: Report = serde_json::from_slice(&body.unwrap()).unwrap();
+    assert_eq!(report.resolution_seconds, 43_200);
+    assert_eq!(report.buckets.len(), 1);
+    assert_eq!(report.buckets[0].samples, 120);
+}
@@ -0,0 +1,126 @@
+use ais_runner::cgroup::CgroupConfig;
+use ais_runner::config::AppSpecificConfig;
+use ais_runner::migrate::{SystemdUnit, detect_port, export, migrate};
+use artisan_middleware::config::AppConfig;
+use std::collections::BTreeMap;
+
+const UNIT: &str = r#"
+[Unit]
+Description=Example web app
+
+[Service]
+Type=simple
+User=www-data
+WorkingDirectory=/srv/app
+Environment="NODE_ENV=production" "PORT=3000"
+Environment=LOG_LEVEL=info
+ExecStartPre=/usr/bin/npm run build
+ExecStart=/usr/bin/node server.js \
+    --trust-proxy
+TimeoutStopSec=2min
+MemoryMax=512M
+KillSignal=SIGINT
+
+[Install]
+WantedBy=multi-user.target
+"#;
+
+#[test]
+fn parses_unit_files() {
+    let unit = SystemdUnit::parse(UNIT);
+
+    assert_eq!(unit.get("Service", "User"), Some("www-data"));
+    assert_eq!(unit.get_all("Service", "Environment").len(), 2);
+    assert_eq!(
+        unit.get("Service", "ExecStart"),
+        Some("/usr/bin/node server.js --trust-proxy")
+    );
+    assert_eq!(unit.get("Install", "WantedBy"), Some("multi-user.target"));
+}
+
+#[test]
+fn generated_config_loads() {
+    let migration = migrate(&SystemdUnit::parse(UNIT), "example.service").unwrap();
+
+    let value: toml::Value = toml::from_str(&migration.config).unwrap();
+    let settings: AppSpecificConfig = value["app_specific"].clone().try_into().unwrap();
+
+    assert_eq!(settings.project_path, "/srv/app");
+    assert_eq!(
+        settings.build_command.as_deref(),
+        Some("/usr/bin/npm run build")
+    );
+    assert_eq!(settings.run_as_user.as_deref(), Some("www-data"));
+    assert_eq!(settings.shutdown_timeout_seconds, 120);
+    assert_eq!(settings.env.get("PORT").map(String::as_str), Some("3000"));
+    assert_eq!(settings.cgroup.memory_max_mb, Some(512));
+    assert_eq!(
+        settings
+            .liveness_probe
+            .and_then(|probe| probe.tcp)
+            .as_deref(),
+        Some("127.0.0.1:3000")
+    );
+    assert!(
+        migration
+            .notes
+            .iter()
+            .any(|note| note.contains("KillSignal"))
+    );
+}
+
+#[test]
+fn detects_ports_from_arguments() {
+    let env = BTreeMap::new();
+
+    assert_eq!(
+        detect_port("gunicorn -b 0.0.0.0:8000 app:app", &env),
+        Some(8000)
+    );
+    assert_eq!(detect_port("./server --port=9090", &env), Some(9090));
+    assert_eq!(detect_port("./server -p 4000", &env), Some(4000));
+    assert_eq!(detect_port("./worker --queue jobs", &env), None);
+}
+
+#[test]
+fn exported_unit_round_trips() {
+    let mut settings = AppSpecificConfig {
+        project_path: String::from("/srv/app"),
+        build_command: Some(String::from("/usr/bin/npm run build")),
+        run_command: String::from("/usr/bin/node server.js"),
+        run_as_user: Some(String::from("www-data")),
+        cgroup: CgroupConfig {
+            enabled: true,
+            memory_max_mb: Some(256),
+            ..CgroupConfig::default()
+        },
+        ..AppSpecificConfig::default()
+    };
+    settings
+        .env
+        .insert(String::from("GREETING"), String::from("say \"hi\""));
+
+    let exported = export(&AppConfig::dummy(), &settings, "/etc/default/app");
+    let unit = SystemdUnit::parse(&exported.unit);
+
+    assert_eq!(
+        unit.get("Service", "ExecStart"),
+        Some("/usr/bin/node server.js")
+    );
+    assert_eq!(
+        unit.get("Service", "ExecStartPre"),
+        Some("/usr/bin/npm run build")
+    );
+    assert_eq!(unit.get("Service", "WorkingDirectory"), Some("/srv/app"));
+    assert_eq!(unit.get("Service", "User"), Some("www-data"));
+    assert_eq!(unit.get("Service", "MemoryMax"), Some("256M"));
+    assert!(exported.env.contains(r#"GREETING="say \"hi\"""#));
+
+    // Importing the export again gives back the same essentials
+    let migration = migrate(&unit, "exported.service").unwrap();
+    let value: toml::Value = toml::from_str(&migration.config).unwrap();
+    let imported: AppSpecificConfig = value["app_specific"].clone().try_into().unwrap();
+    assert_eq!(imported.run_command, settings.run_command);
+    assert_eq!(imported.build_command, settings.build_command);
+    assert_eq!(imported.cgroup.memory_max_mb, Some(256));
+}
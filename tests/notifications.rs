@@ -0,0 +1,135 @@
+use ais_runner::notifications::{
+    Event, EventKind, Notifier, NotifyConfig, NotifyEndpoint, render, templates,
+};
+use ais_runner::outbox::Outbox;
+use serde_json::{Value, json};
+
+fn endpoint(url: &str, template: &str, kinds: Vec<EventKind>) -> NotifyEndpoint {
+    NotifyEndpoint {
+        url: String::from(url),
+        template: Some(String::from(template)),
+        template_file: None,
+        kinds,
+    }
+}
+
+fn event(message: &str, details: Option<Value>) -> Event {
+    Event {
+        app: String::from("shop"),
+        kind: EventKind::Restart,
+        message: String::from(message),
+        timestamp: 1_760_000_000,
+        details,
+    }
+}
+
+#[test]
+fn templates_shape_the_payload() {
+    let config = NotifyConfig {
+        endpoints: vec![endpoint(
+            "https://hooks.slack.com/x",
+            r#"{"text": "*{{app}}* {{kind}}: {{message}}{{#if details.note}} ({{details.note}}){{/if}}"}"#,
+            Vec::new(),
+        )],
+        ..NotifyConfig::default()
+    };
+    let templates = templates(&config).unwrap();
+
+    let payload = render(
+        &templates,
+        "0",
+        &event(
+            "Restarting for \"deploy\"",
+            Some(json!({ "note": "new pricing" })),
+        ),
+    )
+    .unwrap();
+    assert_eq!(
+        payload,
+        json!({ "text": "*shop* restart: Restarting for \"deploy\" (new pricing)" })
+    );
+
+    let payload = render(&templates, "0", &event("Restarting", None)).unwrap();
+    assert_eq!(payload, json!({ "text": "*shop* restart: Restarting" }));
+}
+
+#[test]
+fn broken_templates_are_reported() {
+    let unbalanced = NotifyConfig {
+        endpoints: vec![endpoint("https://x", "{{#if app}}", Vec::new())],
+        ..NotifyConfig::default()
+    };
+    let err = templates(&unbalanced).unwrap_err();
+    assert!(err.contains("https://x"), "{}", err);
+
+    let both = NotifyEndpoint {
+        template_file: Some(String::from("slack.hbs")),
+        ..endpoint("https://x", "{}", Vec::new())
+    };
+    let config = NotifyConfig {
+        endpoints: vec![both],
+        ..NotifyConfig::default()
+    };
+    assert!(templates(&config).unwrap_err().contains("both"));
+
+    let not_json = NotifyConfig {
+        endpoints: vec![endpoint("https://x", "{{message}}", Vec::new())],
+        ..NotifyConfig::default()
+    };
+    let templates = templates(&not_json).unwrap();
+    let err = render(&templates, "0", &event("plain text", None)).unwrap_err();
+    assert!(err.contains("JSON"), "{}", err);
+}
+
+#[test]
+fn endpoints_only_get_their_kinds() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = NotifyConfig {
+        webhooks: vec![String::from("http://127.0.0.1:9/plain")],
+        endpoints: vec![endpoint(
+            "http://127.0.0.1:9/pager",
+            r#"{"summary": "{{app}}: {{message}}", "severity": "critical"}"#,
+            vec![EventKind::CrashLoop],
+        )],
+        queue_dir: Some(dir.path().to_string_lossy().into_owned()),
+        ..NotifyConfig::default()
+    };
+    let notifier = Notifier::new(&config, "shop").unwrap();
+    notifier.enqueue(EventKind::Restart, String::from("Restarting"));
+    notifier.enqueue(EventKind::CrashLoop, String::from("crashed"));
+
+    let pending: Vec<(String, Value)> = Outbox::open(dir.path(), 10)
+        .unwrap()
+        .pending()
+        .unwrap()
+        .into_iter()
+        .map(|(_, message)| (message.endpoint, message.payload))
+        .collect();
+    assert_eq!(pending.len(), 3);
+    assert_eq!(pending[0].1["kind"], "restart");
+    assert_eq!(pending[1].1["kind"], "crash_loop");
+    assert_eq!(
+        pending[2],
+        (
+            String::from("http://127.0.0.1:9/pager"),
+            json!({ "summary": "shop: crashed", "severity": "critical" })
+        )
+    );
+}
+
+#[test]
+fn bursts_are_held_back() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = NotifyConfig {
+        webhooks: vec![String::from("http://127.0.0.1:9/plain")],
+        queue_dir: Some(dir.path().to_string_lossy().into_owned()),
+        ..NotifyConfig::default()
+    };
+    let notifier = Notifier::new(&config, "shop").unwrap();
+    for _ in 0..20 {
+        notifier.enqueue(EventKind::Restart, String::from("Restarting"));
+    }
+    assert_eq!(notifier.queued(), config.throttle.max_per_kind as usize);
+    // Not before the window is over
+    assert_eq!(notifier.release_held(), 0);
+}
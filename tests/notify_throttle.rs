@@ -0,0 +1,99 @@
+use ais_runner::notifications::EventKind;
+use ais_runner::notify_throttle::{QuietHours, Severity, Throttle, ThrottleConfig, Verdict};
+
+const NOON: u64 = 1_760_011_200; // 2025-10-09 12:00 UTC
+
+fn config(extra: &str) -> ThrottleConfig {
+    toml::from_str(extra).unwrap()
+}
+
+#[test]
+fn bursts_are_summarized_once_the_window_is_over() {
+    let mut throttle = Throttle::new(&config("max_per_kind = 2\nwindow_minutes = 10"));
+    let verdicts: Vec<Verdict> = (0..5)
+        .map(|n| throttle.offer(EventKind::Restart, &format!("restart {}", n), NOON + n * 60))
+        .collect();
+    assert_eq!(
+        verdicts,
+        [
+            Verdict::Send,
+            Verdict::Send,
+            Verdict::Hold,
+            Verdict::Hold,
+            Verdict::Hold
+        ]
+    );
+    // Other kinds have their own limit
+    assert_eq!(
+        throttle.offer(EventKind::CrashLoop, "crashed", NOON),
+        Verdict::Send
+    );
+
+    assert!(throttle.due(NOON + 9 * 60).is_empty());
+    let due = throttle.due(NOON + 10 * 60);
+    assert_eq!(due.len(), 1);
+    assert_eq!(due[0].count, 3);
+    assert_eq!(
+        due[0].to_string(),
+        "3 restart events held back over 3m, the last: restart 4"
+    );
+    assert!(throttle.due(NOON + 11 * 60).is_empty());
+
+    // The summary counts towards the new window
+    assert_eq!(
+        throttle.offer(EventKind::Restart, "again", NOON + 11 * 60),
+        Verdict::Send
+    );
+    assert_eq!(
+        throttle.offer(EventKind::Restart, "again", NOON + 12 * 60),
+        Verdict::Hold
+    );
+}
+
+#[test]
+fn events_below_the_threshold_are_dropped() {
+    let mut throttle = Throttle::new(&config("min_severity = \"warning\""));
+    assert_eq!(throttle.offer(EventKind::Idle, "idle", NOON), Verdict::Drop);
+    assert_eq!(
+        throttle.offer(EventKind::Restart, "restart", NOON),
+        Verdict::Send
+    );
+    assert!(throttle.due(NOON + 3600).is_empty());
+    assert_eq!(EventKind::CrashLoop.severity(), Severity::Critical);
+}
+
+#[test]
+fn quiet_hours_hold_all_but_critical_events() {
+    let mut throttle = Throttle::new(&config("quiet_hours = \"22:00-07:00\""));
+    let night = NOON + 12 * 3600;
+    assert_eq!(
+        throttle.offer(EventKind::Restart, "restart", night),
+        Verdict::Hold
+    );
+    assert_eq!(
+        throttle.offer(EventKind::CrashLoop, "crashed", night),
+        Verdict::Send
+    );
+
+    // Still quiet once the window is over
+    assert!(throttle.due(night + 3600).is_empty());
+    let morning = NOON + 19 * 3600;
+    assert_eq!(throttle.due(morning).len(), 1);
+}
+
+#[test]
+fn quiet_hours_may_wrap_past_midnight() {
+    let hours: QuietHours = "22:00-07:00".parse().unwrap();
+    assert!(hours.contains(NOON + 11 * 3600));
+    assert!(hours.contains(NOON + 18 * 3600 + 59 * 60));
+    assert!(!hours.contains(NOON + 19 * 3600));
+    assert!(!hours.contains(NOON));
+
+    let hours: QuietHours = "12:00-13:30".parse().unwrap();
+    assert!(hours.contains(NOON + 89 * 60));
+    assert!(!hours.contains(NOON + 90 * 60));
+
+    assert!("25:00-07:00".parse::<QuietHours>().is_err());
+    assert!("22:00".parse::<QuietHours>().is_err());
+    assert!("07:00-07:00".parse::<QuietHours>().is_err());
+}
@@ -0,0 +1,173 @@
+use ais_runner::capture::{self, OutputCapture};
+use ais_runner::orphans::{
+    AdoptedChild, OrphanAction, OrphanConfig, Previous, cmdline_matches, find, kill, parse_cmdline,
+};
+use ais_runner::output::Stream;
+use artisan_middleware::dusa_collection_utils::core::types::pathtype::PathType;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+#[test]
+fn cmdlines_are_split_on_nul() {
+    assert_eq!(
+        parse_cmdline(b"node\0server.js\0--port\0{port}\0"),
+        args(&["node", "server.js", "--port", "{port}"])
+    );
+    assert!(parse_cmdline(b"").is_empty());
+}
+
+#[test]
+fn secrets_match_their_filled_in_values() {
+    let argv = args(&["./server", "--token", "{secret:api_token}"]);
+    assert!(cmdline_matches(
+        &args(&["./server", "--token", "hunter2"]),
+        &argv
+    ));
+    assert!(!cmdline_matches(
+        &args(&["./other", "--token", "hunter2"]),
+        &argv
+    ));
+    assert!(!cmdline_matches(&args(&["./server", "--token"]), &argv));
+}
+
+#[test]
+fn only_our_children_are_orphans() {
+    let dir = tempfile::tempdir().unwrap();
+    let pid_file = dir.path().join(".app_pg.pid");
+    assert_eq!(find(&pid_file, &args(&["sleep", "30"])), Previous::Gone);
+
+    let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+    fs::write(&pid_file, child.id().to_string()).unwrap();
+    // Until the exec the child still has the test's command line
+    while !fs::read(format!("/proc/{}/cmdline", child.id()))
+        .unwrap()
+        .starts_with(b"sleep")
+    {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(
+        find(&pid_file, &args(&["sleep", "30"])),
+        Previous::Orphan(child.id())
+    );
+    assert_eq!(
+        find(&pid_file, &args(&["node", "server.js"])),
+        Previous::Unrelated(child.id())
+    );
+    child.kill().unwrap();
+    child.wait().unwrap();
+    assert_eq!(find(&pid_file, &args(&["sleep", "30"])), Previous::Gone);
+}
+
+#[tokio::test]
+async fn orphans_are_killed() {
+    let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+    let pid = child.id();
+    // Reap it as soon as it exits, like init would for a real orphan
+    let reaper = std::thread::spawn(move || child.wait().unwrap());
+    kill(pid, Duration::from_secs(5)).await.unwrap();
+    assert!(!reaper.join().unwrap().success());
+}
+
+#[tokio::test]
+async fn grandchildren_go_with_the_orphan() {
+    let mut child = Command::new("sh")
+        .args(["-c", "sleep 30 & echo $!; wait"])
+        .stdout(Stdio::piped())
+        .process_group(0)
+        .spawn()
+        .unwrap();
+    let pid = child.id();
+    let mut line = String::new();
+    BufReader::new(child.stdout.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    let grandchild: u32 = line.trim().parse().unwrap();
+
+    let reaper = std::thread::spawn(move || child.wait().unwrap());
+    kill(pid, Duration::from_secs(5)).await.unwrap();
+    reaper.join().unwrap();
+    // Reparented to init, which reaps it once it's killed
+    for _ in 0..50 {
+        if !Path::new(&format!("/proc/{}", grandchild)).exists() {
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    let stat = fs::read_to_string(format!("/proc/{}/stat", grandchild)).unwrap_or_default();
+    assert!(stat.contains(") Z "), "grandchild still running: {}", stat);
+}
+
+#[test]
+fn kill_is_the_default() {
+    let config: OrphanConfig = toml::from_str("").unwrap();
+    assert_eq!(config.action, OrphanAction::Kill);
+    let config: OrphanConfig =
+        toml::from_str("action = \"adopt\"\nadopt_timeout_seconds = 60").unwrap();
+    assert_eq!(config.action, OrphanAction::Adopt);
+    assert_eq!(config.adopt_timeout_seconds, Some(60));
+}
+
+#[test]
+fn adopted_children_are_followed_through_proc() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_path = PathType::PathBuf(dir.path().join("app.state"));
+    capture::create(&state_path).unwrap();
+    let mut before = OutputCapture::follow(&state_path);
+    let mut child = Command::new("sh")
+        .args([
+            "-c",
+            "echo zero; sleep 0.3; echo one; printf 'two\\nthr'; sleep 0.3; echo ee; \
+             sleep 0.3; echo four; exec sleep 30",
+        ])
+        .stdout(capture::child_stdio(&state_path, Stream::Stdout).unwrap())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let stdout = capture::path(&state_path, Stream::Stdout);
+    let read = |capture: &mut OutputCapture, count: usize| {
+        let mut lines = Vec::new();
+        for _ in 0..100 {
+            lines.extend(capture.read_lines(Stream::Stdout));
+            if lines.len() >= count {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        lines
+    };
+    assert_eq!(read(&mut before, 1), ["zero"]);
+    // Its runner is gone, the child keeps writing to the file
+    drop(before);
+
+    let mut adopted = AdoptedChild::attach(child.id());
+    assert_eq!(
+        adopted.output_file(Stream::Stdout),
+        fs::canonicalize(&stdout).ok().as_deref()
+    );
+    assert_eq!(
+        adopted.output_file(Stream::Stderr),
+        Some(Path::new("/dev/null"))
+    );
+    assert!(adopted.running());
+    assert!(adopted.usage().unwrap().memory_mb > 0.0);
+    assert!(!adopted.overdue(None));
+    assert!(adopted.overdue(Some(Duration::ZERO)));
+
+    let mut capture = OutputCapture::follow(&state_path);
+    assert_eq!(read(&mut capture, 3), ["one", "two", "three"]);
+    assert_eq!(read(&mut capture, 1), ["four"]);
+    assert!(capture.read_lines(Stream::Stderr).is_empty());
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+    assert!(!adopted.running());
+    assert_eq!(adopted.usage(), None);
+}
@@ -0,0 +1,110 @@
+#![cfg(feature = "otel")]
+
+use ais_runner::deploy_trace::{Stage, StageSpan};
+use ais_runner::otel::{RestartTrace, record};
+use ais_runner::restart::{RestartKind, RestartReason};
+use opentelemetry::trace::{Status, TracerProvider as _};
+use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use opentelemetry_sdk::trace::TracerProvider;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug, Clone, Default)]
+struct Collected(Arc<Mutex<Vec<SpanData>>>);
+
+impl SpanExporter for Collected {
+    fn export(
+        &mut self,
+        batch: Vec<SpanData>,
+    ) -> Pin<Box<dyn Future<Output = ExportResult> + Send + 'static>> {
+        self.0.lock().unwrap().extend(batch);
+        Box::pin(std::future::ready(Ok(())))
+    }
+}
+
+fn span(stage: Stage, start_ms: u64, duration_ms: u64) -> StageSpan {
+    StageSpan {
+        stage,
+        start_ms,
+        duration_ms,
+    }
+}
+
+fn export(restart: &RestartTrace) -> Vec<SpanData> {
+    let collected = Collected::default();
+    let provider = TracerProvider::builder()
+        .with_simple_exporter(collected.clone())
+        .build();
+    record(&provider.tracer("test"), restart);
+    provider.force_flush();
+    collected.0.lock().unwrap().clone()
+}
+
+#[test]
+fn stages_become_children_of_the_restart() {
+    let ended = SystemTime::UNIX_EPOCH + Duration::from_secs(1_760_583_700);
+    let reason = RestartReason::new(RestartKind::Changes, "5 file changes");
+    let stages = [
+        span(Stage::Debounce, 0, 2_000),
+        span(Stage::Install, 2_000, 30_000),
+        span(Stage::Build, 32_000, 55_000),
+        span(Stage::Stop, 87_000, 500),
+        span(Stage::Start, 87_500, 100),
+        span(Stage::Ready, 87_600, 2_400),
+    ];
+    let spans = export(&RestartTrace {
+        reason: &reason,
+        success: true,
+        ended,
+        stages: &stages,
+    });
+
+    let root = spans.iter().find(|span| span.name == "restart").unwrap();
+    assert_eq!(root.start_time, ended - Duration::from_secs(90));
+    assert_eq!(root.end_time, ended);
+    assert_eq!(root.status, Status::Unset);
+
+    let children: Vec<(&str, Duration)> = spans
+        .iter()
+        .filter(|span| span.parent_span_id == root.span_context.span_id())
+        .map(|span| {
+            assert_eq!(span.span_context.trace_id(), root.span_context.trace_id());
+            let duration = span.end_time.duration_since(span.start_time).unwrap();
+            (span.name.as_ref(), duration)
+        })
+        .collect();
+    assert_eq!(
+        children,
+        [
+            ("debounce", Duration::from_secs(2)),
+            ("install", Duration::from_secs(30)),
+            ("build", Duration::from_secs(55)),
+            ("stop", Duration::from_millis(500)),
+            ("start", Duration::from_millis(100)),
+            ("ready", Duration::from_millis(2_400)),
+        ]
+    );
+    let build = spans.iter().find(|span| span.name == "build").unwrap();
+    assert_eq!(build.start_time, root.start_time + Duration::from_secs(32));
+}
+
+#[test]
+fn failed_restarts_are_errors() {
+    let reason = RestartReason::from(RestartKind::Exited);
+    let spans = export(&RestartTrace {
+        reason: &reason,
+        success: false,
+        ended: SystemTime::now(),
+        stages: &[span(Stage::Stop, 0, 20)],
+    });
+    let root = spans.iter().find(|span| span.name == "restart").unwrap();
+    assert!(matches!(root.status, Status::Error { .. }));
+    assert!(
+        root.attributes
+            .iter()
+            .any(|attribute| attribute.key.as_str() == "restart.kind"
+                && attribute.value.as_str() == "exited")
+    );
+}
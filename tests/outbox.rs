@@ -0,0 +1,103 @@
+use ais_runner::notifications::{EventKind, Notifier, NotifyConfig};
+use ais_runner::outbox::{Outbox, QueuedMessage};
+use serde_json::json;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+fn message(index: u64) -> QueuedMessage {
+    QueuedMessage {
+        endpoint: String::from("http://127.0.0.1:9/hook"),
+        payload: json!({ "index": index }),
+        queued_at: index,
+    }
+}
+
+#[test]
+fn messages_survive_reopening_in_order() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let outbox = Outbox::open(dir.path(), 10).unwrap();
+    for index in 0..3 {
+        assert_eq!(outbox.push(&message(index)).unwrap(), 0);
+    }
+    outbox.remove(0).unwrap();
+    drop(outbox);
+
+    let reopened = Outbox::open(dir.path(), 10).unwrap();
+    reopened.push(&message(3)).unwrap();
+    let pending: Vec<u64> = reopened
+        .pending()
+        .unwrap()
+        .into_iter()
+        .map(|(_, message)| message.queued_at)
+        .collect();
+    assert_eq!(pending, vec![1, 2, 3]);
+}
+
+#[test]
+fn full_queue_drops_the_oldest() {
+    let dir = tempfile::tempdir().unwrap();
+    let outbox = Outbox::open(dir.path(), 2).unwrap();
+
+    outbox.push(&message(0)).unwrap();
+    outbox.push(&message(1)).unwrap();
+    assert_eq!(outbox.push(&message(2)).unwrap(), 1);
+
+    let pending: Vec<u64> = outbox
+        .pending()
+        .unwrap()
+        .into_iter()
+        .map(|(_, message)| message.queued_at)
+        .collect();
+    assert_eq!(pending, vec![1, 2]);
+}
+
+#[tokio::test]
+async fn undelivered_events_are_replayed() {
+    let dir = tempfile::tempdir().unwrap();
+
+    // Grab a free port and leave it closed for now
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+
+    let config = NotifyConfig {
+        webhooks: vec![format!("http://{}/hook", addr)],
+        queue_dir: Some(dir.path().to_string_lossy().into_owned()),
+        ..NotifyConfig::default()
+    };
+    let notifier = Notifier::new(&config, "app").unwrap();
+
+    notifier.enqueue(
+        EventKind::Restart,
+        String::from("Restarting for 1 file changes"),
+    );
+    notifier.enqueue(EventKind::CrashLoop, String::from("crashed"));
+    assert_eq!(notifier.flush().await, 0);
+    assert_eq!(notifier.queued(), 2);
+
+    let listener = TcpListener::bind(addr).await.unwrap();
+    let server = tokio::spawn(async move {
+        let mut bodies = Vec::new();
+        for _ in 0..2 {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![0u8; 4096];
+            let read = stream.read(&mut request).await.unwrap();
+            bodies.push(String::from_utf8_lossy(&request[..read]).into_owned());
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n")
+                .await
+                .unwrap();
+        }
+        bodies
+    });
+
+    assert_eq!(notifier.flush().await, 2);
+    assert_eq!(notifier.queued(), 0);
+
+    let bodies = server.await.unwrap();
+    assert!(bodies[0].contains("\"kind\":\"restart\""));
+    assert!(bodies[1].contains("\"kind\":\"crash_loop\""));
+}
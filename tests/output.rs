@@ -0,0 +1,153 @@
+use ais_runner::output::{Stream, append_sorted, merged, merged_tail, retain_newest};
+use proptest::prelude::*;
+
+fn lines(entries: &[(u64, &str)]) -> Vec<(u64, String)> {
+    entries
+        .iter()
+        .map(|(timestamp, line)| (*timestamp, line.to_string()))
+        .collect()
+}
+
+#[test]
+fn interleaves_streams_by_key() {
+    let stdout = lines(&[(1, "GET /"), (3, "GET /boom"), (5, "GET /")]);
+    let stderr = lines(&[(3, "panic: boom"), (4, "  at handler")]);
+
+    let combined: Vec<(Stream, &str)> = merged(&stdout, &stderr)
+        .into_iter()
+        .map(|line| (line.stream, line.line))
+        .collect();
+
+    assert_eq!(
+        combined,
+        vec![
+            (Stream::Stdout, "GET /"),
+            (Stream::Stdout, "GET /boom"),
+            (Stream::Stderr, "panic: boom"),
+            (Stream::Stderr, "  at handler"),
+            (Stream::Stdout, "GET /"),
+        ]
+    );
+}
+
+#[test]
+fn tail_keeps_newest_lines() {
+    let stdout = lines(&[(1, "a"), (2, "b")]);
+    let stderr = lines(&[(3, "c")]);
+
+    let tail = merged_tail(&stdout, &stderr, 2);
+    assert_eq!(tail.len(), 2);
+    assert_eq!(tail[0].line, "b");
+    assert_eq!(tail[1].line, "c");
+    assert!(merged_tail(&[], &[], 5).is_empty());
+}
+
+#[test]
+fn append_sorted_orders_out_of_order_batches() {
+    let mut target = lines(&[(1, "a"), (5, "e")]);
+    append_sorted(&mut target, lines(&[(3, "c"), (6, "f")]));
+    let keys: Vec<u64> = target.iter().map(|line| line.0).collect();
+    assert_eq!(keys, vec![1, 3, 5, 6]);
+}
+
+#[test]
+fn append_sorted_only_reorders_the_displaced_tail() {
+    let mut target = lines(&[(1, "a"), (2, "b"), (4, "d"), (4, "old")]);
+    append_sorted(&mut target, lines(&[(5, "e"), (4, "new"), (3, "c")]));
+    assert_eq!(
+        target,
+        lines(&[
+            (1, "a"),
+            (2, "b"),
+            (3, "c"),
+            (4, "d"),
+            (4, "old"),
+            (4, "new"),
+            (5, "e")
+        ])
+    );
+
+    append_sorted(&mut target, Vec::new());
+    assert_eq!(target.len(), 7);
+}
+
+#[test]
+fn lines_in_the_same_second_keep_their_milliseconds() {
+    let key = 1729082096;
+    let stdout = lines(&[(key, "2024-10-16T12:34:56.900Z GET /")]);
+    let stderr = lines(&[(key, "2024-10-16T12:34:56.100Z panic: boom")]);
+    let streams: Vec<Stream> = merged(&stdout, &stderr)
+        .into_iter()
+        .map(|line| line.stream)
+        .collect();
+    assert_eq!(streams, vec![Stream::Stderr, Stream::Stdout]);
+
+    let mut target = lines(&[(key, "no timestamp"), (key, "1729082096500 b")]);
+    append_sorted(
+        &mut target,
+        lines(&[(key, "1729082096200 a"), (key, "1729082096700 c")]),
+    );
+    let texts: Vec<&str> = target.iter().map(|line| line.1.as_str()).collect();
+    assert_eq!(
+        texts,
+        vec![
+            "no timestamp",
+            "1729082096200 a",
+            "1729082096500 b",
+            "1729082096700 c"
+        ]
+    );
+}
+
+#[test]
+fn retention_evicts_the_oldest_lines() {
+    let mut buffer = lines(&[(1, "aaaa"), (2, "bb"), (3, "cc"), (4, "d")]);
+    assert_eq!(retain_newest(&mut buffer, 0, 0), 0);
+    assert_eq!(retain_newest(&mut buffer, 3, 0), 1);
+    assert_eq!(buffer, lines(&[(2, "bb"), (3, "cc"), (4, "d")]));
+    // Only whole lines are kept
+    assert_eq!(retain_newest(&mut buffer, 0, 4), 1);
+    assert_eq!(buffer, lines(&[(3, "cc"), (4, "d")]));
+}
+
+proptest! {
+    #[test]
+    fn merging_keeps_every_line_in_key_order(
+        mut stdout in prop::collection::vec((0..20u64, "[a-z]{0,4}"), 0..12),
+        mut stderr in prop::collection::vec((0..20u64, "[a-z]{0,4}"), 0..12),
+    ) {
+        stdout.sort_by_key(|line| line.0);
+        stderr.sort_by_key(|line| line.0);
+
+        let combined = merged(&stdout, &stderr);
+        prop_assert_eq!(combined.len(), stdout.len() + stderr.len());
+        prop_assert!(combined.windows(2).all(|pair| pair[0].timestamp <= pair[1].timestamp));
+
+        let from = |stream| -> Vec<(u64, String)> {
+            combined
+                .iter()
+                .filter(|line| line.stream == stream)
+                .map(|line| (line.timestamp, line.line.to_string()))
+                .collect()
+        };
+        prop_assert_eq!(from(Stream::Stdout), stdout.clone());
+        prop_assert_eq!(from(Stream::Stderr), stderr.clone());
+    }
+
+    #[test]
+    fn append_sorted_keeps_the_buffer_sorted(
+        mut target in prop::collection::vec((0..20u64, "[a-z]{0,4}"), 0..12),
+        batch in prop::collection::vec((0..20u64, "[a-z]{0,4}"), 0..12),
+    ) {
+        target.sort_by_key(|line| line.0);
+        let mut expected: Vec<(u64, String)> = target.iter().chain(&batch).cloned().collect();
+
+        append_sorted(&mut target, batch);
+        prop_assert!(target.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+
+        let mut appended = target.clone();
+        appended.sort();
+        expected.sort();
+        prop_assert_eq!(appended, expected);
+    }
+}
@@ -0,0 +1,98 @@
+use ais_runner::polling::{PollingConfig, Snapshot, directories, spawn, sysctl_hint};
+use ais_runner::watch::ChangeKind;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+
+fn kinds(batch: &ais_runner::watch::ChangeBatch, path: &Path) -> Vec<ChangeKind> {
+    batch.paths[path].kinds.iter().copied().collect()
+}
+
+#[test]
+fn scans_skip_ignored_subdirs() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::create_dir_all(root.join("src/nested")).unwrap();
+    fs::create_dir_all(root.join("node_modules/left-pad")).unwrap();
+    fs::write(root.join("src/nested/a.rs"), "a").unwrap();
+    fs::write(root.join("node_modules/left-pad/index.js"), "b").unwrap();
+
+    let ignored = vec![root.join("node_modules")];
+    let mut dirs = directories(root, &ignored);
+    dirs.sort();
+    assert_eq!(
+        dirs,
+        [
+            root.to_path_buf(),
+            root.join("src"),
+            root.join("src/nested")
+        ]
+    );
+    assert_eq!(Snapshot::scan(root, &ignored).len(), 1);
+    assert_eq!(Snapshot::scan(root, &[]).len(), 2);
+}
+
+#[test]
+fn snapshots_diff_into_a_batch() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::write(root.join("kept"), "same").unwrap();
+    fs::write(root.join("edited"), "short").unwrap();
+    fs::write(root.join("deleted"), "x").unwrap();
+    let before = Snapshot::scan(root, &[]);
+
+    fs::write(root.join("edited"), "a bit longer").unwrap();
+    fs::remove_file(root.join("deleted")).unwrap();
+    fs::write(root.join("created"), "y").unwrap();
+    let after = Snapshot::scan(root, &[]);
+
+    let batch = before.diff(&after, 2);
+    assert_eq!(batch.index, 2);
+    assert_eq!(batch.changes(), 3);
+    assert_eq!(kinds(&batch, &root.join("created")), [ChangeKind::Create]);
+    assert_eq!(kinds(&batch, &root.join("edited")), [ChangeKind::Modify]);
+    assert_eq!(kinds(&batch, &root.join("deleted")), [ChangeKind::Remove]);
+    assert!(after.diff(&after, 2).is_empty());
+}
+
+#[tokio::test]
+async fn the_poller_sends_what_changed() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().to_path_buf();
+    fs::write(root.join("before"), "x").unwrap();
+
+    let (tx, mut rx) = mpsc::channel(4);
+    spawn(3, root.clone(), Vec::new(), Duration::from_millis(50), tx);
+    // Let the first scan take its snapshot
+    tokio::time::sleep(Duration::from_millis(120)).await;
+    fs::write(root.join("after"), "y").unwrap();
+
+    let batch = timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(batch.index, 3);
+    assert_eq!(
+        batch.paths.keys().collect::<Vec<_>>(),
+        [&root.join("after")]
+    );
+}
+
+#[test]
+fn the_hint_leaves_room_for_the_tree() {
+    assert_eq!(
+        sysctl_hint(Some(8192), 20_000),
+        "sysctl -w fs.inotify.max_user_watches=32768 (and in /etc/sysctl.d to keep it)"
+    );
+    assert!(sysctl_hint(None, 1).contains("=16384"));
+}
+
+#[test]
+fn polling_is_the_fallback_by_default() {
+    let config: PollingConfig = toml::from_str("").unwrap();
+    assert_eq!(config, PollingConfig::default());
+    assert!(config.fallback);
+    assert_eq!(config.interval_seconds, 5);
+}
@@ -0,0 +1,80 @@
+use ais_runner::command_vars::CommandVars;
+use ais_runner::config::AppSpecificConfig;
+use ais_runner::ports::{PortConfig, allocate, apply, is_free};
+use std::net::TcpListener;
+
+fn parse(port: &str) -> Result<AppSpecificConfig, toml::de::Error> {
+    toml::from_str(&format!(
+        r#"
+interval_seconds = 1
+monitor_path = "./"
+project_path = "./"
+changes_needed = 1
+ignored_subdirs = []
+run_command = "./server"
+port = {}
+"#,
+        port
+    ))
+}
+
+#[test]
+fn parses_config() {
+    assert_eq!(parse("8080").unwrap().port, Some(PortConfig::Fixed(8080)));
+    assert_eq!(parse("\"auto\"").unwrap().port, Some(PortConfig::Auto));
+    assert!(parse("0").is_err());
+    assert!(parse("\"any\"").is_err());
+    assert_eq!(AppSpecificConfig::default().port, None);
+}
+
+#[test]
+fn bound_fixed_ports_are_refused() {
+    let listener = TcpListener::bind(("0.0.0.0", 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    assert!(!is_free(port));
+
+    let err = allocate(PortConfig::Fixed(port), None, &[]).unwrap_err();
+    assert!(err.contains(&port.to_string()), "{}", err);
+
+    drop(listener);
+    assert_eq!(allocate(PortConfig::Fixed(port), None, &[]), Ok(port));
+}
+
+#[test]
+fn auto_keeps_the_previous_port_while_free() {
+    let previous = TcpListener::bind(("0.0.0.0", 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    assert_eq!(
+        allocate(PortConfig::Auto, Some(previous), &[]),
+        Ok(previous)
+    );
+
+    // Reserved by another runner meanwhile
+    let port = allocate(PortConfig::Auto, Some(previous), &[previous]).unwrap();
+    assert_ne!(port, previous);
+    assert!(is_free(port));
+
+    let listener = TcpListener::bind(("0.0.0.0", previous)).unwrap();
+    assert_ne!(
+        allocate(PortConfig::Auto, Some(previous), &[]),
+        Ok(previous)
+    );
+    drop(listener);
+}
+
+#[test]
+fn the_child_gets_the_port() {
+    let mut settings = AppSpecificConfig::default();
+    settings.reservation.ports = vec![9000, 8080];
+    apply(&mut settings, 8080);
+
+    assert_eq!(settings.env["PORT"], "8080");
+    assert_eq!(settings.reservation.ports, vec![8080, 9000]);
+    assert_eq!(
+        CommandVars::new(&settings, "").expand("--port={port}"),
+        "--port=8080"
+    );
+}
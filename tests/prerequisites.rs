@@ -0,0 +1,91 @@
+use ais_runner::config::AppSpecificConfig;
+use ais_runner::prerequisites::{check, find_program, report};
+use std::ffi::OsStr;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+fn settings(project: &Path, run_command: &str) -> AppSpecificConfig {
+    AppSpecificConfig {
+        monitor_path: project.display().to_string(),
+        project_path: project.display().to_string(),
+        run_command: run_command.to_owned(),
+        ..AppSpecificConfig::default()
+    }
+}
+
+fn program(dir: &Path, name: &str, mode: u32) {
+    let path = dir.join(name);
+    fs::write(&path, "#!/bin/sh\n").unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+}
+
+#[test]
+fn programs_are_found_on_path_or_at_their_path() {
+    let bin = tempfile::tempdir().unwrap();
+    program(bin.path(), "node", 0o755);
+    program(bin.path(), "notes", 0o644);
+    let search = bin.path().as_os_str();
+
+    assert_eq!(
+        find_program("node", Path::new("/"), Some(search)),
+        Some(bin.path().join("node"))
+    );
+    assert_eq!(find_program("notes", Path::new("/"), Some(search)), None);
+    assert_eq!(find_program("node", Path::new("/"), None), None);
+    assert_eq!(
+        find_program("./node", bin.path(), None),
+        Some(bin.path().join("./node"))
+    );
+}
+
+#[test]
+fn everything_missing_is_reported_at_once() {
+    let bin = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    program(bin.path(), "npm", 0o755);
+    program(project.path(), "start.sh", 0o644);
+    let mut settings = settings(project.path(), "./start.sh");
+    settings.install_command = Some(String::from("npm ci"));
+    settings.build_command = Some(String::from("make-it --release"));
+
+    let missing = check(&settings, Some(bin.path().as_os_str()));
+    let keys: Vec<&str> = missing.iter().map(|missing| missing.key.as_str()).collect();
+    assert_eq!(keys, ["app_specific.build_command"]);
+    assert!(missing[0].message.starts_with("make-it isn't on PATH"));
+
+    // Without a build the run command has to be there already
+    settings.build_command = None;
+    settings.install_command = Some(String::from("yarn"));
+    let missing = check(&settings, Some(bin.path().as_os_str()));
+    let keys: Vec<&str> = missing.iter().map(|missing| missing.key.as_str()).collect();
+    assert_eq!(
+        keys,
+        ["app_specific.install_command", "app_specific.run_command"]
+    );
+    assert!(missing[1].hint.starts_with("run chmod +x"));
+    assert!(
+        report(&missing)
+            .starts_with("2 prerequisites are missing:\n  - app_specific.install_command: ")
+    );
+}
+
+#[test]
+fn build_outputs_are_not_expected_before_the_build() {
+    let project = tempfile::tempdir().unwrap();
+    let mut settings = settings(project.path(), "./target/release/app");
+    settings.build_command = Some(String::from("sh -c true"));
+    assert_eq!(
+        check(&settings, Some(OsStr::new("/bin:/usr/bin"))),
+        Vec::new()
+    );
+
+    settings.build_command = None;
+    let missing = check(&settings, Some(OsStr::new("/bin:/usr/bin")));
+    assert_eq!(missing.len(), 1);
+    assert!(
+        missing[0]
+            .message
+            .ends_with("target/release/app doesn't exist")
+    );
+}
@@ -0,0 +1,102 @@
+use ais_runner::child::ChildIdentity;
+use ais_runner::config::AppSpecificConfig;
+use ais_runner::privileges::{
+    bind, hand_over, handed_over, is_privileged, prebind, privileged_addrs,
+};
+use artisan_middleware::dusa_collection_utils::core::types::pathtype::PathType;
+use nix::unistd::{getegid, geteuid};
+use std::os::unix::fs::symlink;
+use std::path::PathBuf;
+
+#[test]
+fn ports_below_1024_are_privileged() {
+    assert!(is_privileged("0.0.0.0:80"));
+    assert!(is_privileged("127.0.0.1:443"));
+    assert!(!is_privileged("127.0.0.1:8080"));
+    assert!(!is_privileged("not an address"));
+}
+
+#[test]
+fn only_enabled_privileged_listeners_are_bound_early() {
+    let mut settings = AppSpecificConfig::default();
+    settings.static_server.bind = String::from("0.0.0.0:80");
+    settings.acme.tls_bind = Some(String::from("0.0.0.0:443"));
+    settings.webhook_addr = Some(String::from("127.0.0.1:9000"));
+    settings.state_sync.addr = Some(String::from("127.0.0.1:900"));
+    assert_eq!(privileged_addrs(&settings), vec!["127.0.0.1:900"]);
+
+    settings.static_server.enabled = true;
+    assert_eq!(
+        privileged_addrs(&settings),
+        vec!["0.0.0.0:80", "127.0.0.1:900"]
+    );
+    settings.acme.enabled = true;
+    assert_eq!(
+        privileged_addrs(&settings),
+        vec!["0.0.0.0:80", "0.0.0.0:443", "127.0.0.1:900"]
+    );
+}
+
+#[tokio::test]
+async fn prebound_listeners_are_handed_out_once() {
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let addr = format!("127.0.0.1:{}", port);
+    prebind(&addr).unwrap();
+
+    // Binding again while the early listener is held would fail
+    let listener = bind(&addr).await.unwrap();
+    assert_eq!(listener.local_addr().unwrap().port(), port);
+    assert!(bind(&addr).await.is_err());
+    drop(listener);
+}
+
+#[test]
+fn symlinks_are_refused_when_handing_over() {
+    let identity = ChildIdentity {
+        user_name: String::from("runner"),
+        uid: geteuid().as_raw(),
+        gid: getegid().as_raw(),
+        groups: Vec::new(),
+        home: std::env::temp_dir(),
+    };
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("app.state");
+    std::fs::write(&file, "").unwrap();
+    let missing = dir.path().join("missing");
+    assert!(
+        hand_over(
+            &[file.clone(), dir.path().to_path_buf(), missing],
+            &identity
+        )
+        .is_ok()
+    );
+
+    let link = dir.path().join("link");
+    symlink(&file, &link).unwrap();
+    assert!(hand_over(&[link], &identity).is_err());
+}
+
+#[test]
+fn the_acme_cert_dir_is_handed_over_when_acme_is_on() {
+    let state_path = PathType::Content(String::from("/var/lib/ais_runner/app.state"));
+    let mut settings = AppSpecificConfig::default();
+    let cert_dir = PathBuf::from("/var/lib/ais_runner/app/acme");
+    let paths = handed_over(&settings, &state_path, "app");
+    assert!(paths.contains(&PathBuf::from("/var/lib/ais_runner/app.state")));
+    assert!(paths.contains(&PathBuf::from("/var/lib/ais_runner/app.state.output")));
+    assert!(!paths.iter().any(|path| path.starts_with(&cert_dir)));
+
+    settings.acme.enabled = true;
+    let paths = handed_over(&settings, &state_path, "app");
+    for file in ["", "cert.pem", "key.pem", "account.key"] {
+        assert!(paths.contains(&cert_dir.join(file)), "{} missing", file);
+    }
+
+    settings.acme.cert_dir = Some(String::from("/etc/app/tls"));
+    let paths = handed_over(&settings, &state_path, "app");
+    assert!(paths.contains(&PathBuf::from("/etc/app/tls/key.pem")));
+}
@@ -0,0 +1,74 @@
+use ais_runner::probes::{ProbeConfig, ProbeOutcome, ProbeTracker};
+
+/// A probe running `command` on every poll.
+fn probe(command: &str, failure_threshold: u32) -> ProbeConfig {
+    ProbeConfig {
+        tcp: None,
+        http: None,
+        command: Some(command.to_string()),
+        period_seconds: 0,
+        timeout_seconds: 5,
+        failure_threshold,
+    }
+}
+
+fn failed(outcome: &ProbeOutcome) -> &str {
+    match outcome {
+        ProbeOutcome::Failed(reason) => reason,
+        other => panic!("expected a failure, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn startup_failures_have_their_own_budget() {
+    let mut tracker = ProbeTracker::new(Some(probe("false", 3)), Some(probe("false", 1)));
+    assert_eq!(tracker.poll().await, ProbeOutcome::Pending);
+    assert_eq!(tracker.poll().await, ProbeOutcome::Pending);
+    let outcome = tracker.poll().await;
+    assert!(failed(&outcome).starts_with("Startup probe failed 3 times"));
+
+    // Back to the startup phase with a fresh budget for the next child
+    assert_eq!(tracker.poll().await, ProbeOutcome::Pending);
+}
+
+#[tokio::test]
+async fn liveness_waits_for_startup_to_pass() {
+    let dir = tempfile::tempdir().unwrap();
+    let booted = dir.path().join("booted");
+    let startup = probe(&format!("test -e {}", booted.display()), 100);
+    let mut tracker = ProbeTracker::new(Some(startup), Some(probe("false", 1)));
+
+    // A liveness probe that fails right away would have restarted it by now
+    for _ in 0..3 {
+        assert_eq!(tracker.poll().await, ProbeOutcome::Pending);
+    }
+
+    std::fs::write(&booted, "").unwrap();
+    assert_eq!(tracker.poll().await, ProbeOutcome::Healthy);
+    let outcome = tracker.poll().await;
+    assert!(failed(&outcome).starts_with("Liveness probe failed 1 times"));
+
+    // Every spawn has to boot again
+    tracker.reset();
+    assert_eq!(tracker.poll().await, ProbeOutcome::Healthy);
+}
+
+#[tokio::test]
+async fn liveness_failures_past_the_threshold_are_unhealthy() {
+    let dir = tempfile::tempdir().unwrap();
+    let down = dir.path().join("down");
+    let liveness = probe(&format!("test ! -e {}", down.display()), 2);
+    let mut tracker = ProbeTracker::new(None, Some(liveness));
+    assert_eq!(tracker.poll().await, ProbeOutcome::Healthy);
+
+    std::fs::write(&down, "").unwrap();
+    assert_eq!(tracker.poll().await, ProbeOutcome::Pending);
+    // A pass in between starts the count over
+    std::fs::remove_file(&down).unwrap();
+    assert_eq!(tracker.poll().await, ProbeOutcome::Healthy);
+
+    std::fs::write(&down, "").unwrap();
+    assert_eq!(tracker.poll().await, ProbeOutcome::Pending);
+    let outcome = tracker.poll().await;
+    assert!(failed(&outcome).starts_with("Liveness probe failed 2 times"));
+}
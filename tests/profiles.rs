@@ -0,0 +1,65 @@
+use ais_runner::config::load_specific;
+use config::{File, FileFormat};
+
+const CONFIG: &str = r#"
+[app_specific]
+interval_seconds = 1
+project_path = "/srv/app"
+monitor_path = "/srv/app/src"
+changes_needed = 1
+ignored_subdirs = ["node_modules"]
+run_command = "./server --dev"
+
+[app_specific.cgroup]
+enabled = true
+cpu_max_percent = 200
+
+[app_specific.production]
+changes_needed = 1000
+run_command = "./server"
+ignored_subdirs = ["node_modules", "assets"]
+
+[app_specific.production.cgroup]
+memory_max_mb = 512
+
+[app_specific.development]
+run_command = "./server --watch"
+"#;
+
+fn load(config: &str, environment: &str) -> ais_runner::config::AppSpecificConfig {
+    load_specific(File::from_str(config, FileFormat::Toml), environment).unwrap()
+}
+
+#[test]
+fn the_environments_profile_is_laid_over_the_base() {
+    let settings = load(CONFIG, "production");
+    assert_eq!(settings.changes_needed, 1000);
+    assert_eq!(settings.run_command, "./server");
+    assert_eq!(settings.ignored_subdirs, ["node_modules", "assets"]);
+    // Tables are merged key by key
+    assert!(settings.cgroup.enabled);
+    assert_eq!(settings.cgroup.memory_max_mb, Some(512));
+    assert_eq!(settings.cgroup.cpu_max_percent, Some(200));
+    assert_eq!(settings.interval_seconds, 1);
+}
+
+#[test]
+fn other_profiles_are_left_out() {
+    let settings = load(CONFIG, "development");
+    assert_eq!(settings.run_command, "./server --watch");
+    assert_eq!(settings.changes_needed, 1);
+
+    // Without a profile of its own the base applies as it is
+    let settings = load(CONFIG, "staging");
+    assert_eq!(settings.run_command, "./server --dev");
+    assert_eq!(settings.cgroup.memory_max_mb, None);
+}
+
+#[test]
+fn unknown_keys_in_a_profile_are_rejected() {
+    let config = format!("{}\n[app_specific.staging]\nchanges_neded = 5\n", CONFIG);
+    let err = load_specific(File::from_str(&config, FileFormat::Toml), "staging").unwrap_err();
+    assert!(err.to_string().contains("changes_neded"), "{}", err);
+    // Not when it's another environment's
+    assert_eq!(load(&config, "production").changes_needed, 1000);
+}
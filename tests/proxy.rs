@@ -0,0 +1,138 @@
+use ais_runner::config::AppSpecificConfig;
+use ais_runner::ports::PortConfig;
+use ais_runner::proxy::{Activity, Availability, HoldMode, ProxyConfig, serve};
+use ais_runner::validation::validate;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// A child on a free port echoing the first 4 bytes of a connection.
+async fn echo_child() -> u16 {
+    let child = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = child.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let (mut stream, _) = child.accept().await.unwrap();
+        let mut buffer = [0u8; 4];
+        stream.read_exact(&mut buffer).await.unwrap();
+        stream.write_all(&buffer).await.unwrap();
+    });
+    port
+}
+
+async fn free_addr() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+#[test]
+fn quiet_children_are_due_once() {
+    let started = Instant::now();
+    let activity = Activity::new(Some(Duration::from_secs(600)), started);
+    assert_eq!(activity.due(started + Duration::from_secs(599)), None);
+    let later = started + Duration::from_secs(601);
+    assert_eq!(activity.due(later), Some(Duration::from_secs(601)));
+
+    activity.sleep();
+    assert!(activity.is_asleep());
+    assert_eq!(activity.due(later), None);
+
+    // A new child gets a full timeout
+    activity.up(later);
+    assert_eq!(activity.availability(), Availability::Up);
+    assert_eq!(activity.due(later + Duration::from_secs(300)), None);
+
+    let always_on = Activity::new(None, started);
+    assert_eq!(always_on.due(later), None);
+}
+
+#[tokio::test]
+async fn connections_wake_the_child() {
+    let port = echo_child().await;
+    let addr = free_addr().await;
+    let config = ProxyConfig {
+        listen: Some(addr.clone()),
+        idle_minutes: Some(15),
+        ..ProxyConfig::default()
+    };
+    let activity = Arc::new(Activity::new(config.idle_timeout(), Instant::now()));
+    activity.sleep();
+    serve(&config, port, activity.clone()).await.unwrap();
+
+    let mut client = TcpStream::connect(&addr).await.unwrap();
+    client.write_all(b"ping").await.unwrap();
+    activity.wake_requested().await;
+    // While it's passed on the child isn't due
+    activity.up(Instant::now() - Duration::from_secs(3600));
+    let mut buffer = [0u8; 4];
+    client.read_exact(&mut buffer).await.unwrap();
+    assert_eq!(&buffer, b"ping");
+    assert_eq!(activity.due(Instant::now()), None);
+}
+
+#[tokio::test]
+async fn connections_are_held_while_the_child_restarts() {
+    let port = echo_child().await;
+    let addr = free_addr().await;
+    let config = ProxyConfig {
+        listen: Some(addr.clone()),
+        ..ProxyConfig::default()
+    };
+    let activity = Arc::new(Activity::new(None, Instant::now()));
+    activity.restarting();
+    serve(&config, port, activity.clone()).await.unwrap();
+
+    let mut client = TcpStream::connect(&addr).await.unwrap();
+    client.write_all(b"pong").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    activity.up(Instant::now());
+    let mut buffer = [0u8; 4];
+    client.read_exact(&mut buffer).await.unwrap();
+    assert_eq!(&buffer, b"pong");
+}
+
+#[tokio::test]
+async fn restarts_can_answer_with_a_page() {
+    let dir = tempfile::tempdir().unwrap();
+    let page = dir.path().join("503.html");
+    std::fs::write(&page, "<p>back soon</p>").unwrap();
+    let addr = free_addr().await;
+    let config = ProxyConfig {
+        listen: Some(addr.clone()),
+        hold: HoldMode::Page,
+        hold_page: Some(page.to_string_lossy().into_owned()),
+        ..ProxyConfig::default()
+    };
+    let activity = Arc::new(Activity::new(None, Instant::now()));
+    activity.restarting();
+    serve(&config, 1, activity).await.unwrap();
+
+    let mut client = TcpStream::connect(&addr).await.unwrap();
+    client.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    client.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+    assert!(response.ends_with("\r\n\r\n<p>back soon</p>"));
+}
+
+#[test]
+fn listening_needs_a_port() {
+    let dir = tempfile::tempdir().unwrap();
+    let settings = |port: Option<PortConfig>| AppSpecificConfig {
+        monitor_path: dir.path().to_string_lossy().into_owned(),
+        project_path: dir.path().to_string_lossy().into_owned(),
+        run_command: String::from("node server.js"),
+        port,
+        proxy: ProxyConfig {
+            listen: Some(String::from("0.0.0.0:8000")),
+            ..ProxyConfig::default()
+        },
+        ..AppSpecificConfig::default()
+    };
+    assert!(validate(&settings(Some(PortConfig::Fixed(8080)))).is_empty());
+    let keys: Vec<String> = validate(&settings(None))
+        .into_iter()
+        .map(|problem| problem.key)
+        .collect();
+    assert_eq!(keys, ["app_specific.proxy.listen"]);
+}
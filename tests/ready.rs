@@ -0,0 +1,50 @@
+use ais_runner::ready::ReadyCheck;
+
+fn check() -> ReadyCheck {
+    ReadyCheck {
+        tcp: None,
+        unix_socket: None,
+        log_line: None,
+        notify: false,
+        timeout_seconds: 30,
+    }
+}
+
+#[test]
+fn requires_exactly_one_condition() {
+    assert!(check().validate().is_err());
+
+    let both = ReadyCheck {
+        tcp: Some(String::from("127.0.0.1:3000")),
+        log_line: Some(String::from("listening")),
+        ..check()
+    };
+    assert!(both.validate().is_err());
+
+    let tcp = ReadyCheck {
+        tcp: Some(String::from("127.0.0.1:3000")),
+        ..check()
+    };
+    assert!(tcp.validate().is_ok());
+
+    let notify = ReadyCheck {
+        notify: true,
+        ..check()
+    };
+    assert!(notify.validate().is_ok());
+}
+
+#[test]
+fn rejects_invalid_log_line_regex() {
+    let invalid = ReadyCheck {
+        log_line: Some(String::from("listening on (port")),
+        ..check()
+    };
+    assert!(invalid.validate().is_err());
+
+    let valid = ReadyCheck {
+        log_line: Some(String::from(r"^Listening on \d+")),
+        ..check()
+    };
+    assert!(valid.validate().is_ok());
+}
@@ -0,0 +1,91 @@
+use ais_runner::config::AppSpecificConfig;
+use ais_runner::reload::{ConfigFile, Effect, Plan, effect, keep_runner_options};
+use std::fs::{self, File};
+use std::time::{Duration, SystemTime};
+
+fn settings() -> AppSpecificConfig {
+    AppSpecificConfig {
+        monitor_path: String::from("./"),
+        project_path: String::from("./"),
+        run_command: String::from("node server.js"),
+        ..AppSpecificConfig::default()
+    }
+}
+
+#[test]
+fn options_are_classified() {
+    assert_eq!(effect("changes_needed"), Effect::Live);
+    assert_eq!(effect("log_rules"), Effect::Live);
+    assert_eq!(effect("run_command"), Effect::Child);
+    assert_eq!(effect("env"), Effect::Child);
+    assert_eq!(effect("webhook_addr"), Effect::Runner);
+    // Options nobody classified are deployed to be safe
+    assert_eq!(effect("something_new"), Effect::Child);
+}
+
+#[test]
+fn changes_are_grouped_by_what_they_take() {
+    let old = settings();
+    assert!(Plan::new(&old, &old.clone()).is_empty());
+
+    let new = AppSpecificConfig {
+        run_command: String::from("node dist/server.js"),
+        ignored_subdirs: vec![String::from("node_modules")],
+        webhook_addr: Some(String::from("127.0.0.1:9000")),
+        ..old.clone()
+    };
+    let plan = Plan::new(&old, &new);
+    assert_eq!(plan.live, ["ignored_subdirs"]);
+    assert_eq!(plan.child, ["run_command"]);
+    assert_eq!(plan.runner, ["webhook_addr"]);
+    assert!(plan.touches(&["interval_seconds", "ignored_subdirs"]));
+    assert!(!plan.touches(&["notifications"]));
+}
+
+#[test]
+fn runner_options_keep_their_value() {
+    let old = settings();
+    let new = AppSpecificConfig {
+        run_command: String::from("node dist/server.js"),
+        webhook_addr: Some(String::from("127.0.0.1:9000")),
+        ..old.clone()
+    };
+    let plan = Plan::new(&old, &new);
+    let merged = keep_runner_options(&old, &new, &plan).unwrap();
+    assert_eq!(merged.run_command, "node dist/server.js");
+    assert_eq!(merged.webhook_addr, None);
+}
+
+#[test]
+fn writes_to_the_file_are_noticed() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("Config.toml");
+    fs::write(&path, "[app_specific]\n").unwrap();
+
+    let mut config_file = ConfigFile::new(&path);
+    assert!(!config_file.changed());
+
+    let later = SystemTime::now() + Duration::from_secs(5);
+    File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(later)
+        .unwrap();
+    assert!(config_file.changed());
+    assert!(!config_file.changed());
+
+    fs::remove_file(&path).unwrap();
+    assert!(config_file.changed());
+}
+
+#[test]
+fn preset_settings_are_never_reloaded() {
+    let mut config_file = ConfigFile::preset();
+    assert!(!config_file.reloadable());
+    assert!(!config_file.changed());
+
+    let dir = tempfile::tempdir().unwrap();
+    let config_file = ConfigFile::new(dir.path().join("Config.toml"));
+    assert!(config_file.reloadable());
+}
@@ -0,0 +1,52 @@
+use ais_runner::reservations::{HostResources, Registry, Reservation, check};
+
+fn reservation(app_name: &str, memory_mb: u64, cpu_percent: u32, ports: Vec<u16>) -> Reservation {
+    Reservation {
+        app_name: app_name.to_owned(),
+        pid: std::process::id(),
+        memory_mb,
+        cpu_percent,
+        ports,
+    }
+}
+
+#[test]
+fn flags_oversubscribed_hosts() {
+    let host = HostResources {
+        memory_mb: 1024,
+        cpu_percent: 200,
+    };
+    let others = vec![reservation("api", 512, 100, vec![3000])];
+
+    let fits = reservation("web", 256, 50, vec![8080]);
+    assert!(check(&fits, &others, host).is_empty());
+
+    let too_big = reservation("web", 768, 150, vec![3000]);
+    let problems = check(&too_big, &others, host);
+    assert_eq!(problems.len(), 3);
+    assert!(problems.iter().any(|problem| problem.contains("Port 3000")));
+}
+
+#[test]
+fn registry_skips_own_and_dead_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let registry = Registry::new(dir.path().to_str().unwrap());
+
+    registry
+        .declare(&reservation("api", 512, 100, vec![]))
+        .unwrap();
+    registry
+        .declare(&reservation("web", 256, 50, vec![]))
+        .unwrap();
+    let mut dead = reservation("gone", 128, 10, vec![]);
+    dead.pid = i32::MAX as u32;
+    registry.declare(&dead).unwrap();
+
+    let others = registry.others("web").unwrap();
+    assert_eq!(others.len(), 1);
+    assert_eq!(others[0].app_name, "api");
+
+    registry.release("api").unwrap();
+    registry.release("api").unwrap();
+    assert!(registry.others("web").unwrap().is_empty());
+}
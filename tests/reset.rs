@@ -0,0 +1,45 @@
+use ais_runner::reset::{ResetScope, request, take_pending};
+use ais_runner::runner_state::RunnerState;
+use artisan_middleware::dusa_collection_utils::core::types::pathtype::PathType;
+
+const ERRORS: ResetScope = ResetScope {
+    errors: true,
+    output: false,
+    counters: false,
+};
+const OUTPUT: ResetScope = ResetScope {
+    errors: false,
+    output: true,
+    counters: false,
+};
+
+#[test]
+fn scopes_describe_what_they_clear() {
+    assert_eq!(ResetScope::default().describe(), "nothing");
+    assert!(ResetScope::default().is_empty());
+    assert_eq!(ERRORS.describe(), "errors");
+    assert_eq!(ERRORS.merge(OUTPUT).describe(), "errors and output");
+    assert_eq!(ResetScope::all().describe(), "errors, output and counters");
+    assert!(!ResetScope::all().is_empty());
+}
+
+#[test]
+fn requests_pile_up_until_taken() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_path = PathType::Content(dir.path().join("app.state").to_string_lossy().into_owned());
+
+    let mut runner_state = RunnerState::load(&state_path);
+    runner_state.pending_note = Some(String::from("kept"));
+    runner_state.save(&state_path).unwrap();
+
+    assert_eq!(take_pending(&state_path), None);
+    request(&state_path, ERRORS).unwrap();
+    request(&state_path, OUTPUT).unwrap();
+
+    assert_eq!(take_pending(&state_path), Some(ERRORS.merge(OUTPUT)));
+    assert_eq!(take_pending(&state_path), None);
+    assert_eq!(
+        RunnerState::load(&state_path).pending_note.as_deref(),
+        Some("kept")
+    );
+}
@@ -0,0 +1,50 @@
+use ais_runner::restart::{RestartKind, RestartReason};
+use ais_runner::runner_state::RestartRecord;
+
+#[test]
+fn only_deploys_follow_the_restart_strategy() {
+    let deploys: Vec<RestartKind> = [
+        RestartKind::Startup,
+        RestartKind::Changes,
+        RestartKind::Schedule,
+        RestartKind::Webhook,
+        RestartKind::Reload,
+        RestartKind::Restore,
+        RestartKind::Exited,
+        RestartKind::Unhealthy,
+    ]
+    .into_iter()
+    .filter(|kind| kind.is_deploy())
+    .collect();
+
+    assert_eq!(
+        deploys,
+        [
+            RestartKind::Changes,
+            RestartKind::Schedule,
+            RestartKind::Webhook
+        ]
+    );
+}
+
+#[test]
+fn reasons_default_their_detail() {
+    let reason: RestartReason = RestartKind::Unhealthy.into();
+    assert_eq!(reason.to_string(), "child unhealthy");
+
+    let reason = RestartReason::new(RestartKind::Webhook, "push to main");
+    assert_eq!(reason.kind, RestartKind::Webhook);
+    assert_eq!(reason.to_string(), "push to main");
+}
+
+#[test]
+fn records_without_a_kind_still_load() {
+    let record: RestartRecord =
+        serde_json::from_str(r#"{"timestamp": 3, "reason": "5 file changes"}"#).unwrap();
+    assert_eq!(record.kind, None);
+
+    let record: RestartRecord =
+        serde_json::from_str(r#"{"timestamp": 3, "reason": "reload requested", "kind": "reload"}"#)
+            .unwrap();
+    assert_eq!(record.kind, Some(RestartKind::Reload));
+}
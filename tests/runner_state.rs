@@ -0,0 +1,121 @@
+use ais_runner::child::{ChildExit, ChildLaunch};
+use ais_runner::config::AppSpecificConfig;
+use ais_runner::restart::{RestartKind, RestartReason};
+use ais_runner::runner_state::{HISTORY_LIMIT, RunnerState, finish_restart, record_restart};
+use artisan_middleware::dusa_collection_utils::core::types::pathtype::PathType;
+use std::time::Duration;
+
+#[test]
+fn restarts_pick_up_pending_notes() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_path = PathType::Content(dir.path().join("app.state").to_string_lossy().into_owned());
+
+    let mut runner_state = RunnerState::load(&state_path);
+    runner_state.pending_note = Some(String::from("rolling out the new pricing page"));
+    runner_state.save(&state_path).unwrap();
+
+    let noted = record_restart(
+        &state_path,
+        10,
+        &RestartReason::new(RestartKind::Changes, "5 file changes"),
+    )
+    .unwrap();
+    assert_eq!(
+        noted.note.as_deref(),
+        Some("rolling out the new pricing page")
+    );
+
+    assert_eq!(noted.kind, Some(RestartKind::Changes));
+
+    let plain = record_restart(&state_path, 20, &RestartKind::Reload.into()).unwrap();
+    assert_eq!(plain.note, None);
+    assert_eq!(plain.reason, "reload requested");
+
+    let runner_state = RunnerState::load(&state_path);
+    assert_eq!(runner_state.pending_note, None);
+    assert_eq!(runner_state.restart_history, vec![noted, plain]);
+}
+
+#[test]
+fn history_is_bounded() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_path = PathType::Content(dir.path().join("app.state").to_string_lossy().into_owned());
+
+    for timestamp in 0..(HISTORY_LIMIT as u64 + 5) {
+        record_restart(&state_path, timestamp, &RestartKind::Schedule.into()).unwrap();
+    }
+
+    let history = RunnerState::load(&state_path).restart_history;
+    assert_eq!(history.len(), HISTORY_LIMIT);
+    assert_eq!(history[0].timestamp, 5);
+}
+
+#[test]
+fn restarts_record_their_outcome() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_path = PathType::Content(dir.path().join("app.state").to_string_lossy().into_owned());
+
+    let crashed = RestartReason::new(RestartKind::Exited, "child was killed by SIGKILL")
+        .with_exit(ChildExit::Signal(9));
+    let record = record_restart(&state_path, 10, &crashed).unwrap();
+    assert_eq!(record.exit_code, Some(137));
+    assert_eq!(record.success, None);
+    assert_eq!(record.outcome(), "pending");
+
+    record_restart(&state_path, 20, &RestartKind::Reload.into()).unwrap();
+    finish_restart(
+        &state_path,
+        10,
+        Some(Duration::from_millis(1500)),
+        false,
+        &[],
+    )
+    .unwrap();
+    finish_restart(&state_path, 20, None, true, &[]).unwrap();
+    // Long gone from the history, nothing to update
+    finish_restart(&state_path, 5, None, true, &[]).unwrap();
+
+    let history = RunnerState::load(&state_path).restart_history;
+    assert_eq!(history.len(), 2);
+    assert_eq!(
+        (history[0].build_ms, history[0].success),
+        (Some(1500), Some(false))
+    );
+    assert_eq!(
+        (history[1].build_ms, history[1].success),
+        (None, Some(true))
+    );
+    assert_eq!(history[1].exit_code, None);
+    assert_eq!(history[1].outcome(), "ok");
+}
+
+#[test]
+fn last_known_good_launch_round_trips() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("web")).unwrap();
+    let state_path = PathType::Content(dir.path().join("app.state").to_string_lossy().into_owned());
+
+    let settings = AppSpecificConfig {
+        project_path: dir.path().to_string_lossy().into_owned(),
+        working_dir: Some(String::from("web")),
+        run_command: String::from("node server.js --title 'hello world'"),
+        env: [(String::from("PORT"), String::from("8080"))].into(),
+        ..AppSpecificConfig::default()
+    };
+
+    let launch = ChildLaunch::resolve(&settings);
+    assert_eq!(launch.argv, ["node", "server.js", "--title", "hello world"]);
+    assert_eq!(launch.cwd, dir.path().join("web").canonicalize().unwrap());
+    assert_eq!(launch.env.get("PORT").map(String::as_str), Some("8080"));
+
+    let mut runner_state = RunnerState::load(&state_path);
+    assert_eq!(runner_state.last_known_good, None);
+    assert!(!runner_state.restore_requested);
+    runner_state.last_known_good = Some((42, launch.clone()));
+    runner_state.restore_requested = true;
+    runner_state.save(&state_path).unwrap();
+
+    let runner_state = RunnerState::load(&state_path);
+    assert_eq!(runner_state.last_known_good, Some((42, launch)));
+    assert!(runner_state.restore_requested);
+}
@@ -0,0 +1,77 @@
+use ais_runner::schedule::CronSchedule;
+
+/// 2024-10-16T12:34:56Z, a Wednesday.
+const NOW: u64 = 1_729_082_096;
+const DAY: u64 = 86_400;
+/// 2024-10-16T00:00:00Z
+const MIDNIGHT: u64 = NOW - NOW % DAY;
+
+fn next(expression: &str, after: u64) -> u64 {
+    expression
+        .parse::<CronSchedule>()
+        .unwrap()
+        .next_after(after)
+        .unwrap()
+}
+
+#[test]
+fn nightly_restart() {
+    assert_eq!(next("0 3 * * *", NOW), MIDNIGHT + DAY + 3 * 3600);
+    assert_eq!(next("@daily", NOW), MIDNIGHT + DAY);
+    assert_eq!(
+        next("0 3 * * *", MIDNIGHT + 3 * 3600),
+        MIDNIGHT + DAY + 3 * 3600
+    );
+}
+
+#[test]
+fn steps_ranges_and_lists() {
+    assert_eq!(next("*/15 * * * *", NOW), MIDNIGHT + 12 * 3600 + 45 * 60);
+    assert_eq!(
+        next("5,50 12-13 * * *", NOW),
+        MIDNIGHT + 12 * 3600 + 50 * 60
+    );
+    assert_eq!(next("0-30/10 13 * * *", NOW), MIDNIGHT + 13 * 3600);
+    assert_eq!(next("* * * * *", NOW), NOW - NOW % 60 + 60);
+}
+
+#[test]
+fn weekdays_and_days_of_month() {
+    // Next Sunday, written both ways
+    let sunday = MIDNIGHT + 4 * DAY + 4 * 3600;
+    assert_eq!(next("0 4 * * 0", NOW), sunday);
+    assert_eq!(next("0 4 * * 7", NOW), sunday);
+
+    // Either the 1st or a Friday, whichever comes first
+    assert_eq!(next("0 0 1 * 5", NOW), MIDNIGHT + 2 * DAY);
+    // 2025-02-01
+    assert_eq!(next("0 0 1 2 *", NOW), 1_738_368_000);
+    // 2028-02-29
+    assert_eq!(next("0 0 29 2 *", NOW), 1_835_395_200);
+}
+
+#[test]
+fn invalid_expressions_are_rejected() {
+    for expression in [
+        "",
+        "0 3 * *",
+        "60 * * * *",
+        "* 24 * * *",
+        "*/0 * * * *",
+        "a * * * *",
+        "5-1 * * * *",
+    ] {
+        assert!(
+            expression.parse::<CronSchedule>().is_err(),
+            "{:?}",
+            expression
+        );
+    }
+    assert!(
+        "0 0 31 2 *"
+            .parse::<CronSchedule>()
+            .unwrap()
+            .next_after(NOW)
+            .is_none()
+    );
+}
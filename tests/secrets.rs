@@ -0,0 +1,167 @@
+use ais_runner::config::AppSpecificConfig;
+use ais_runner::secrets::reload::{KeyPolicy, changed_keys};
+use ais_runner::secrets::{
+    RetryConfig, RotationAction, SecretReloadConfig, SecretTlsConfig, with_retry, write_env_file,
+};
+use artisan_middleware::dusa_collection_utils::core::errors::{ErrorArrayItem, Errors};
+use std::time::Duration;
+
+#[test]
+fn rewrites_env_file_in_place() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join(".env");
+
+    write_env_file(&path, &vec![(String::from("TOKEN"), b"old".to_vec())]).unwrap();
+    write_env_file(
+        &path,
+        &vec![
+            (String::from("TOKEN"), b"new".to_vec()),
+            (String::from("PORT"), b"3000".to_vec()),
+        ],
+    )
+    .unwrap();
+
+    let content = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(content, "TOKEN=new\nPORT=3000\n");
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn parses_rotation_action() {
+    let settings: AppSpecificConfig = toml::from_str(
+        r#"
+interval_seconds = 1
+monitor_path = "./"
+project_path = "./"
+changes_needed = 1
+ignored_subdirs = []
+run_command = "./server"
+secret_rotation = "rewrite_env_file"
+"#,
+    )
+    .unwrap();
+    assert_eq!(
+        settings.secret_rotation,
+        Some(RotationAction::RewriteEnvFile)
+    );
+    assert_eq!(AppSpecificConfig::default().secret_rotation, None);
+}
+
+#[test]
+fn tls_needs_complete_client_identity() {
+    let half = SecretTlsConfig {
+        client_cert: Some(String::from("/etc/ais/runner.pem")),
+        ..SecretTlsConfig::default()
+    };
+    assert!(half.validate().is_err());
+    assert!(SecretTlsConfig::default().validate().is_ok());
+
+    let missing_ca = SecretTlsConfig {
+        ca_cert: Some(String::from("/nonexistent/ca.pem")),
+        ..SecretTlsConfig::default()
+    };
+    let err = missing_ca.client_config().unwrap_err();
+    assert!(err.err_mesg.to_string().contains("ca_cert"));
+}
+
+#[test]
+fn backoff_doubles_up_to_the_cap() {
+    let config = RetryConfig {
+        max_attempts: 5,
+        initial_delay_ms: 100,
+        max_delay_ms: 1000,
+    };
+    assert_eq!(config.backoff(1, 0.0), Duration::from_millis(50));
+    assert_eq!(config.backoff(1, 1.0), Duration::from_millis(100));
+    assert_eq!(config.backoff(3, 1.0), Duration::from_millis(400));
+    assert_eq!(config.backoff(10, 1.0), Duration::from_millis(1000));
+    assert_eq!(config.backoff(64, 0.0), Duration::from_millis(500));
+}
+
+#[tokio::test]
+async fn retries_only_connection_errors() {
+    let config = RetryConfig {
+        max_attempts: 3,
+        initial_delay_ms: 1,
+        max_delay_ms: 1,
+    };
+
+    let mut history = Vec::new();
+    let mut calls = 0;
+    let result: Result<(), ErrorArrayItem> = with_retry(&config, "connect", &mut history, || {
+        calls += 1;
+        async { Err(ErrorArrayItem::new(Errors::ConnectionError, "refused")) }
+    })
+    .await;
+    assert!(result.is_err());
+    assert_eq!(calls, 3);
+    assert_eq!(history.len(), 2);
+
+    history.clear();
+    calls = 0;
+    let result: Result<(), ErrorArrayItem> = with_retry(&config, "fetch", &mut history, || {
+        calls += 1;
+        async { Err(ErrorArrayItem::new(Errors::GeneralError, "missing")) }
+    })
+    .await;
+    assert!(result.is_err());
+    assert_eq!(calls, 1);
+    assert!(history.is_empty());
+}
+
+fn secrets(pairs: &[(&str, &str)]) -> Vec<(String, Vec<u8>)> {
+    pairs
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.as_bytes().to_vec()))
+        .collect()
+}
+
+#[test]
+fn only_disruptive_rotations_restart() {
+    let config: SecretReloadConfig = toml::from_str(
+        r#"
+file = "/run/app/secrets.env"
+default_policy = "hot_reload"
+
+[policies]
+DB_PASSWORD = "restart"
+"#,
+    )
+    .unwrap();
+    assert_eq!(config.signal().unwrap().as_str(), "SIGHUP");
+
+    let old = secrets(&[
+        ("DB_PASSWORD", "a"),
+        ("API_RATE_LIMIT", "10"),
+        ("GONE", "x"),
+    ]);
+    let new = secrets(&[
+        ("DB_PASSWORD", "a"),
+        ("API_RATE_LIMIT", "20"),
+        ("ADDED", "y"),
+    ]);
+    let changed = changed_keys(&old, &new);
+    assert_eq!(changed, vec!["ADDED", "API_RATE_LIMIT", "GONE"]);
+    assert_eq!(config.plan(&changed), Some(KeyPolicy::HotReload));
+
+    let rotated = secrets(&[
+        ("DB_PASSWORD", "b"),
+        ("API_RATE_LIMIT", "20"),
+        ("ADDED", "y"),
+    ]);
+    let changed = changed_keys(&new, &rotated);
+    assert_eq!(config.plan(&changed), Some(KeyPolicy::Restart));
+
+    assert_eq!(config.plan(&changed_keys(&new, &new)), None);
+}
+
+#[test]
+fn reload_signal_names() {
+    let mut config: SecretReloadConfig = toml::from_str(r#"file = "reload.env""#).unwrap();
+    assert_eq!(config.default_policy, KeyPolicy::Restart);
+
+    config.signal = String::from("usr1");
+    assert_eq!(config.signal().unwrap().as_str(), "SIGUSR1");
+    config.signal = String::from("SIGNOPE");
+    assert!(config.signal().is_err());
+}
@@ -0,0 +1,103 @@
+use ais_runner::config::AppSpecificConfig;
+use ais_runner::shutdown::{self, Shutdown, ShutdownConfig, Stage};
+use ais_runner::validation::validate;
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+use std::time::Duration;
+use tokio::time::sleep;
+
+// The shutdown is global to the process, so all of it runs in one test
+#[tokio::test(start_paused = true)]
+async fn stages_run_in_order_within_their_timeouts() {
+    let settings = AppSpecificConfig {
+        shutdown_timeout_seconds: 3,
+        shutdown: ShutdownConfig {
+            health_timeout_seconds: 2,
+            ..ShutdownConfig::default()
+        },
+        ..AppSpecificConfig::default()
+    };
+    let mut shutdown = Shutdown::new(&settings);
+    assert_eq!(shutdown.timeout(Stage::Child), Duration::from_secs(3));
+    assert_eq!(shutdown.total(), Duration::from_secs(25));
+
+    // A listener, cancelled once its stage begins
+    let listening = Arc::new(AtomicBool::new(true));
+    let listener = listening.clone();
+    shutdown::spawn(Stage::Monitors, async move {
+        sleep(Duration::from_secs(3600)).await;
+        listener.store(false, Ordering::SeqCst);
+    });
+    // A task that needs longer to wind down than its stage allows
+    shutdown::spawn(Stage::Health, std::future::pending());
+    shutdown::track(
+        Stage::Health,
+        tokio::spawn(async {
+            shutdown::reached(Stage::Health).await;
+            sleep(Duration::from_secs(60)).await;
+        }),
+    );
+    // A task that winds down on its own once its stage begins
+    let flushed = Arc::new(AtomicBool::new(false));
+    let flusher = flushed.clone();
+    shutdown::track(
+        Stage::Logs,
+        tokio::spawn(async move {
+            shutdown::reached(Stage::Logs).await;
+            sleep(Duration::from_secs(1)).await;
+            flusher.store(true, Ordering::SeqCst);
+        }),
+    );
+
+    assert!(shutdown.stage(Stage::Monitors, async { Ok(()) }).await);
+    assert!(listening.load(Ordering::SeqCst));
+    assert!(!shutdown.stage(Stage::Health, async { Ok(()) }).await);
+    assert!(
+        !shutdown
+            .stage(Stage::Child, async { Err(String::from("kill failed")) })
+            .await
+    );
+    assert!(!flushed.load(Ordering::SeqCst));
+    assert!(shutdown.stage(Stage::Logs, async { Ok(()) }).await);
+    assert!(flushed.load(Ordering::SeqCst));
+    assert!(shutdown.stage(Stage::State, async { Ok(()) }).await);
+
+    let outcomes: Vec<(Stage, bool, bool)> = shutdown
+        .outcomes()
+        .iter()
+        .map(|outcome| (outcome.stage, outcome.result.is_ok(), outcome.timed_out))
+        .collect();
+    assert_eq!(
+        outcomes,
+        [
+            (Stage::Monitors, true, false),
+            (Stage::Health, false, true),
+            (Stage::Child, false, false),
+            (Stage::Logs, true, false),
+            (Stage::State, true, false),
+        ]
+    );
+    assert_eq!(shutdown.outcomes()[1].duration, Duration::from_secs(2));
+}
+
+#[test]
+fn stage_timeouts_must_be_positive() {
+    let dir = tempfile::tempdir().unwrap();
+    let settings = AppSpecificConfig {
+        monitor_path: dir.path().to_string_lossy().into_owned(),
+        project_path: dir.path().to_string_lossy().into_owned(),
+        run_command: String::from("node server.js"),
+        shutdown: ShutdownConfig {
+            logs_timeout_seconds: 0,
+            ..ShutdownConfig::default()
+        },
+        ..AppSpecificConfig::default()
+    };
+    let keys: Vec<String> = validate(&settings)
+        .into_iter()
+        .map(|problem| problem.key)
+        .collect();
+    assert_eq!(keys, ["app_specific.shutdown.logs_timeout_seconds"]);
+}
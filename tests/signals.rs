@@ -0,0 +1,62 @@
+// The Windows side can't be driven from another process
+#![cfg(unix)]
+
+use ais_runner::signals::{Control, ControlFlags, is_alive, send, watch_controls};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+fn wait_for(flag: &AtomicBool, expected: bool) -> bool {
+    let started = Instant::now();
+    while started.elapsed() < Duration::from_secs(5) {
+        if flag.load(Ordering::Relaxed) == expected {
+            return true;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    false
+}
+
+#[test]
+fn controls_reach_the_flags() {
+    let flags = ControlFlags::default();
+    watch_controls(&flags);
+    // Give the listener threads time to register
+    std::thread::sleep(Duration::from_millis(100));
+    let pid = std::process::id();
+    assert!(is_alive(pid));
+
+    send(pid, Control::Reload).unwrap();
+    assert!(wait_for(&flags.reload, true));
+
+    send(pid, Control::Maintenance).unwrap();
+    assert!(wait_for(&flags.maintenance, true));
+    send(pid, Control::Maintenance).unwrap();
+    assert!(wait_for(&flags.maintenance, false));
+
+    send(pid, Control::LogLevel).unwrap();
+    assert!(wait_for(&flags.log_level, true));
+
+    send(pid, Control::Limits).unwrap();
+    assert!(wait_for(&flags.limits, true));
+
+    // Dump requests are kept until the dump task asks for them
+    send(pid, Control::Dump).unwrap();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let notified = runtime.block_on(async {
+        tokio::time::timeout(Duration::from_secs(5), flags.dump.notified()).await
+    });
+    assert!(notified.is_ok());
+
+    send(pid, Control::Exit).unwrap();
+    assert!(wait_for(&flags.exit, true));
+}
+
+#[test]
+fn controls_are_described_by_their_signal() {
+    assert_eq!(Control::Reload.describe(), "SIGHUP");
+    assert_eq!(Control::Exit.describe(), "SIGUSR1");
+    assert_eq!(Control::Maintenance.describe(), "SIGUSR2");
+    assert_eq!(Control::LogLevel.describe(), "SIGTTIN");
+    assert_eq!(Control::Limits.describe(), "SIGTTOU");
+    assert_eq!(Control::Dump.describe(), "SIGURG");
+}
@@ -0,0 +1,210 @@
+use ais_runner::child::ChildExit;
+use ais_runner::config::AppSpecificConfig;
+use ais_runner::crash_loop::CrashLoopConfig;
+use ais_runner::restart::{RestartKind, RestartReason};
+use ais_runner::secrets::RetryConfig;
+use ais_runner::sim::{Script, SimAction, SimEvent, Simulation, fetch_secret};
+use artisan_middleware::dusa_collection_utils::core::errors::{ErrorArrayItem, Errors};
+use std::time::Duration;
+
+fn secs(secs: u64) -> Duration {
+    Duration::from_secs(secs)
+}
+
+fn settings() -> AppSpecificConfig {
+    AppSpecificConfig {
+        monitor_path: String::from("/srv/app"),
+        changes_needed: 3,
+        crash_loop: CrashLoopConfig {
+            enabled: true,
+            max_restarts: 3,
+            window_minutes: 5,
+        },
+        ..AppSpecificConfig::default()
+    }
+}
+
+fn crashed(code: i32) -> RestartReason {
+    RestartReason::new(RestartKind::Exited, format!("child exited with {}", code))
+        .with_exit(ChildExit::Code(code))
+}
+
+#[tokio::test(start_paused = true)]
+async fn changes_deploy_once_the_threshold_is_reached() {
+    let actions = Simulation::new(&settings())
+        .run(Script::new().every(secs(10), secs(1), 5, SimEvent::Change(0)))
+        .await;
+
+    assert_eq!(
+        actions,
+        vec![(
+            secs(12),
+            SimAction::Deploy(RestartReason::new(RestartKind::Changes, "3 file changes"))
+        )]
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn crash_loops_suspend_respawns_until_a_reload() {
+    let script = Script::new()
+        .every(secs(30), secs(30), 5, SimEvent::Exit(ChildExit::Code(1)))
+        .at(secs(600), SimEvent::Reload)
+        .at(secs(630), SimEvent::Exit(ChildExit::Code(1)));
+    let actions = Simulation::new(&settings()).run(script).await;
+
+    assert_eq!(
+        actions,
+        vec![
+            (secs(30), SimAction::Respawn(crashed(1))),
+            (secs(60), SimAction::Respawn(crashed(1))),
+            (secs(90), SimAction::Respawn(crashed(1))),
+            (secs(120), SimAction::CrashLoop { restarts: 3 }),
+            // 150s: suspended, nothing happens
+            (secs(600), SimAction::Reload { resumed: true }),
+            (secs(630), SimAction::Respawn(crashed(1))),
+        ]
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn crashes_spread_over_hours_never_trip_the_breaker() {
+    // A crash every two minutes for a day, never three within five minutes
+    let actions = Simulation::new(&settings())
+        .run(Script::new().every(secs(0), secs(150), 576, SimEvent::Exit(ChildExit::Code(1))))
+        .await;
+
+    assert_eq!(actions.len(), 576);
+    assert!(
+        actions
+            .iter()
+            .all(|(_, action)| matches!(action, SimAction::Respawn(_)))
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn changes_during_maintenance_are_dropped() {
+    let script = Script::new()
+        .at(secs(1), SimEvent::Change(0))
+        .at(secs(2), SimEvent::Maintenance(true))
+        .every(secs(3), secs(1), 10, SimEvent::Change(0))
+        .at(secs(60), SimEvent::Maintenance(false))
+        .every(secs(61), secs(1), 3, SimEvent::Change(0));
+    let actions = Simulation::new(&settings()).run(script).await;
+
+    // The change before maintenance doesn't count either
+    assert_eq!(
+        actions,
+        vec![(
+            secs(63),
+            SimAction::Deploy(RestartReason::new(RestartKind::Changes, "3 file changes"))
+        )]
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn exits_during_maintenance_wait_until_it_ends() {
+    let script = Script::new()
+        .at(secs(1), SimEvent::Maintenance(true))
+        .every(secs(10), secs(10), 5, SimEvent::Exit(ChildExit::Code(1)))
+        .at(secs(120), SimEvent::Maintenance(false))
+        // Nothing died during this one
+        .at(secs(200), SimEvent::Maintenance(true))
+        .at(secs(210), SimEvent::Maintenance(false));
+    let actions = Simulation::new(&settings()).run(script).await;
+
+    // Doesn't count towards the crash loop breaker either
+    assert_eq!(
+        actions,
+        vec![
+            (secs(10), SimAction::Paused(crashed(1))),
+            (
+                secs(120),
+                SimAction::Respawn(RestartReason::new(
+                    RestartKind::Exited,
+                    "stopped during maintenance"
+                ))
+            ),
+        ]
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn final_exits_leave_the_child_down_until_a_deploy() {
+    let settings = AppSpecificConfig {
+        no_restart_on: vec![0],
+        ..settings()
+    };
+    let script = Script::new()
+        .at(secs(5), SimEvent::Exit(ChildExit::Code(0)))
+        .at(secs(10), SimEvent::Exit(ChildExit::Code(0)))
+        .at(
+            secs(15),
+            SimEvent::Unhealthy(String::from("liveness probe failed")),
+        )
+        .every(secs(20), secs(1), 3, SimEvent::Change(0))
+        .at(secs(30), SimEvent::Exit(ChildExit::Code(2)));
+    let actions = Simulation::new(&settings).run(script).await;
+
+    assert_eq!(
+        actions,
+        vec![
+            (secs(5), SimAction::Idle(ChildExit::Code(0))),
+            (
+                secs(15),
+                SimAction::Respawn(RestartReason::new(
+                    RestartKind::Unhealthy,
+                    "liveness probe failed"
+                ))
+            ),
+            (
+                secs(22),
+                SimAction::Deploy(RestartReason::new(RestartKind::Changes, "3 file changes"))
+            ),
+            (secs(30), SimAction::Respawn(crashed(2))),
+        ]
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn secret_fetches_back_off_through_outages() {
+    let config = RetryConfig {
+        max_attempts: 5,
+        initial_delay_ms: 1_000,
+        max_delay_ms: 3_000,
+    };
+    let down = || {
+        Err(ErrorArrayItem::new(
+            Errors::ConnectionError,
+            "connection refused",
+        ))
+    };
+
+    let run = fetch_secret(&config, vec![down(), down(), down(), Ok("hunter2")], 0.5).await;
+    assert_eq!(run.result.unwrap(), "hunter2");
+    assert_eq!(run.history.len(), 3);
+    // 750ms, 1.5s, then capped at 2.25s
+    assert_eq!(
+        run.attempts,
+        vec![
+            Duration::ZERO,
+            Duration::from_millis(750),
+            Duration::from_millis(2_250),
+            Duration::from_millis(4_500),
+        ]
+    );
+
+    // Out of attempts
+    let run = fetch_secret::<&str>(&config, Vec::new(), 0.0).await;
+    assert_eq!(run.result.unwrap_err().err_type, Errors::ConnectionError);
+    assert_eq!(run.attempts.len(), 5);
+
+    // Anything but a connection error isn't retried
+    let run = fetch_secret::<&str>(
+        &config,
+        vec![Err(ErrorArrayItem::new(Errors::GeneralError, "denied"))],
+        0.0,
+    )
+    .await;
+    assert!(run.result.is_err());
+    assert_eq!(run.attempts, vec![Duration::ZERO]);
+}
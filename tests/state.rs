@@ -0,0 +1,78 @@
+use ais_runner::config::AppSpecificConfig;
+use ais_runner::state::Coalescer;
+use std::time::{Duration, Instant};
+
+fn running(errors: usize) -> (String, usize) {
+    (String::from("Running"), errors)
+}
+
+#[test]
+fn routine_updates_are_batched() {
+    let start = Instant::now();
+    let mut coalescer = Coalescer::new(Duration::from_secs(5));
+
+    assert!(coalescer.offer(running(0), start));
+    assert!(!coalescer.offer(running(0), start + Duration::from_secs(1)));
+    assert!(!coalescer.offer(running(0), start + Duration::from_secs(2)));
+    assert!(coalescer.is_pending());
+
+    assert!(!coalescer.due(start + Duration::from_secs(4)));
+    assert!(coalescer.due(start + Duration::from_secs(5)));
+    coalescer.written(running(0), start + Duration::from_secs(5));
+    assert!(!coalescer.is_pending());
+    assert!(!coalescer.due(start + Duration::from_secs(20)));
+
+    // Past the interval an update goes straight through
+    assert!(coalescer.offer(running(0), start + Duration::from_secs(11)));
+}
+
+#[test]
+fn significant_changes_are_written_right_away() {
+    let start = Instant::now();
+    let mut coalescer = Coalescer::new(Duration::from_secs(5));
+
+    assert!(coalescer.offer(running(0), start));
+    // A new error
+    assert!(coalescer.offer(running(1), start + Duration::from_millis(10)));
+    // A new status
+    assert!(coalescer.offer(
+        (String::from("Stopping"), 1),
+        start + Duration::from_millis(20)
+    ));
+    assert!(!coalescer.is_pending());
+    assert!(!coalescer.offer(
+        (String::from("Stopping"), 1),
+        start + Duration::from_millis(30)
+    ));
+}
+
+#[test]
+fn zero_writes_every_update() {
+    let start = Instant::now();
+    let mut coalescer = Coalescer::new(Duration::ZERO);
+    assert!(coalescer.offer(running(0), start));
+    assert!(coalescer.offer(running(0), start));
+}
+
+#[test]
+fn parses_config() {
+    let settings: AppSpecificConfig = toml::from_str(
+        r#"
+interval_seconds = 1
+monitor_path = "./"
+project_path = "./"
+changes_needed = 1
+ignored_subdirs = []
+run_command = "./server"
+
+[state_writes]
+interval_seconds = 30
+"#,
+    )
+    .unwrap();
+    assert_eq!(settings.state_writes.interval_seconds, 30);
+    assert_eq!(
+        AppSpecificConfig::default().state_writes.interval_seconds,
+        5
+    );
+}
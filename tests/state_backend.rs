@@ -0,0 +1,146 @@
+use ais_runner::config::{AppSpecificConfig, new_application_state};
+use ais_runner::state_backend::{RunnerRecord, SqliteConfig, StateBackendConfig};
+#[cfg(feature = "state-sqlite")]
+use ais_runner::state_backend::{SqliteBackend, StateBackend};
+use ais_runner::validation::validate;
+use artisan_middleware::config::AppConfig;
+#[cfg(not(feature = "state-redis"))]
+use artisan_middleware::dusa_collection_utils::core::types::pathtype::PathType;
+use artisan_middleware::dusa_collection_utils::core::types::stringy::Stringy;
+use artisan_middleware::state_persistence::AppState;
+
+fn state(app_name: &str, last_updated: u64) -> AppState {
+    let mut config = AppConfig::dummy();
+    config.app_name = Stringy::from(app_name.to_string());
+    let mut state = new_application_state(&config);
+    state.last_updated = last_updated;
+    state
+}
+
+#[test]
+fn records_leave_the_output_out() {
+    let mut state = state("api", 1);
+    state.stdout = vec![(1, String::from("listening"))];
+    state.stderr = vec![(1, String::from("deprecated"))];
+    let record = RunnerRecord::new(&mut state);
+    assert!(record.state.stdout.is_empty());
+    assert!(record.state.stderr.is_empty());
+    assert!(record.runner().ends_with("/api"));
+    // The state saved to the file keeps it
+    assert_eq!(state.stdout.len(), 1);
+    assert_eq!(state.stderr.len(), 1);
+}
+
+#[cfg(feature = "state-sqlite")]
+#[test]
+fn sqlite_keeps_the_last_states_of_each_runner() {
+    let dir = tempfile::tempdir().unwrap();
+    let backend = SqliteBackend {
+        path: dir.path().join("states.sqlite"),
+        history: 2,
+    };
+    backend.prepare().unwrap();
+    for updated in 1..=3 {
+        backend
+            .store(&RunnerRecord::new(&mut state("api", updated)))
+            .unwrap();
+    }
+    backend
+        .store(&RunnerRecord::new(&mut state("worker", 7)))
+        .unwrap();
+
+    let newest: Vec<(String, u64)> = backend
+        .records()
+        .unwrap()
+        .iter()
+        .map(|record| (record.state.name.clone(), record.state.last_updated))
+        .collect();
+    assert_eq!(
+        newest,
+        [(String::from("api"), 3), (String::from("worker"), 7)]
+    );
+
+    let runner = RunnerRecord::new(&mut state("api", 0)).runner();
+    let history: Vec<u64> = backend
+        .history(&runner, 10)
+        .unwrap()
+        .iter()
+        .map(|record| record.state.last_updated)
+        .collect();
+    assert_eq!(history, [3, 2]);
+}
+
+#[test]
+fn backends_are_picked_by_kind() {
+    let settings: AppSpecificConfig = toml::from_str(
+        r#"
+interval_seconds = 1
+monitor_path = "./"
+project_path = "./"
+changes_needed = 1
+ignored_subdirs = []
+run_command = "./server"
+
+[state_backend]
+kind = "sqlite"
+"#,
+    )
+    .unwrap();
+    assert_eq!(
+        settings.state_backend,
+        StateBackendConfig::Sqlite(SqliteConfig {
+            path: None,
+            history: 1000,
+        })
+    );
+    assert_eq!(
+        AppSpecificConfig::default().state_backend,
+        StateBackendConfig::File
+    );
+}
+
+#[cfg(feature = "state-redis")]
+#[test]
+fn redis_urls_are_checked() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut settings = AppSpecificConfig {
+        monitor_path: dir.path().to_string_lossy().into_owned(),
+        project_path: dir.path().to_string_lossy().into_owned(),
+        run_command: String::from("node server.js"),
+        ..AppSpecificConfig::default()
+    };
+    settings.state_backend =
+        toml::from_str("kind = \"redis\"\nurl = \"redis.internal:6379\"").unwrap();
+    let keys: Vec<String> = validate(&settings)
+        .into_iter()
+        .map(|problem| problem.key)
+        .collect();
+    assert_eq!(keys, ["app_specific.state_backend.url"]);
+
+    settings.state_backend =
+        toml::from_str("kind = \"redis\"\nurl = \"redis://redis.internal:6379\"").unwrap();
+    assert!(validate(&settings).is_empty());
+}
+
+#[cfg(not(feature = "state-redis"))]
+#[test]
+fn backends_left_out_of_the_build_say_so() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut settings = AppSpecificConfig {
+        monitor_path: dir.path().to_string_lossy().into_owned(),
+        project_path: dir.path().to_string_lossy().into_owned(),
+        run_command: String::from("node server.js"),
+        ..AppSpecificConfig::default()
+    };
+    settings.state_backend =
+        toml::from_str("kind = \"redis\"\nurl = \"redis://redis.internal:6379\"").unwrap();
+    let state_path = PathType::Content(dir.path().join("app.state").to_string_lossy().into_owned());
+    let err = settings.state_backend.backend(&state_path).err().unwrap();
+    assert!(err.contains("state-redis feature"), "{}", err);
+
+    let keys: Vec<String> = validate(&settings)
+        .into_iter()
+        .map(|problem| problem.key)
+        .collect();
+    assert_eq!(keys, ["app_specific.state_backend.kind"]);
+}
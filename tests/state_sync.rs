@@ -0,0 +1,138 @@
+use ais_runner::config::AppSpecificConfig;
+use ais_runner::state_sync::{Delta, StateLog, answer};
+use ais_runner::webhook::Request;
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, HashMap};
+
+const TOKEN: &str = "fleet-collector";
+
+fn fields(status: &str, pid: u32) -> BTreeMap<String, Value> {
+    BTreeMap::from([
+        (String::from("status"), json!(status)),
+        (String::from("pid"), json!(pid)),
+        (String::from("errors"), json!([])),
+    ])
+}
+
+fn get(target: &str, token: Option<&str>) -> Request {
+    let mut headers = HashMap::new();
+    if let Some(token) = token {
+        headers.insert(String::from("authorization"), format!("Bearer {}", token));
+    }
+    Request {
+        method: String::from("GET"),
+        target: String::from(target),
+        headers,
+        body: Vec::new(),
+    }
+}
+
+#[test]
+fn only_changed_fields_are_sent() {
+    let mut log = StateLog::new(1_000);
+    assert_eq!(log.observe(fields("Starting", 0)), 1);
+    // Nothing changed, no new sequence number
+    assert_eq!(log.observe(fields("Starting", 0)), 1);
+    assert_eq!(log.observe(fields("Running", 0)), 2);
+    assert_eq!(log.observe(fields("Running", 4242)), 3);
+
+    let delta = log.delta(Some(1_000), Some(1));
+    assert_eq!(
+        delta,
+        Delta {
+            epoch: 1_000,
+            seq: 3,
+            full: false,
+            fields: BTreeMap::from([
+                (String::from("pid"), json!(4242)),
+                (String::from("status"), json!("Running")),
+            ]),
+        }
+    );
+    assert!(log.delta(Some(1_000), Some(3)).fields.is_empty());
+}
+
+#[test]
+fn unknown_positions_get_everything() {
+    let mut log = StateLog::new(1_000);
+    log.observe(fields("Running", 7));
+
+    // First poll, a restarted runner and a collector from the future
+    for (epoch, since) in [(None, None), (Some(999), Some(1)), (Some(1_000), Some(8))] {
+        let delta = log.delta(epoch, since);
+        assert!(delta.full);
+        assert_eq!(delta.fields.len(), 3);
+        assert_eq!(delta.seq, 1);
+    }
+}
+
+#[test]
+fn answers_collectors() {
+    let mut log = StateLog::new(1_000);
+    log.observe(fields("Running", 7));
+
+    let (status, body) = answer(&get("/state", Some(TOKEN)), TOKEN, &log, None);
+    assert_eq!(status, "200 OK");
+    let delta: Delta = serde_json::from_slice(&body.unwrap()).unwrap();
+    assert!(delta.full);
+
+    let (status, body) = answer(
+        &get("/state?epoch=1000&since=1", Some(TOKEN)),
+        TOKEN,
+        &log,
+        None,
+    );
+    assert_eq!((status, body), ("304 Not Modified", None));
+
+    log.observe(fields("Warning", 7));
+    let (status, body) = answer(
+        &get("/state?since=1&epoch=1000", Some(TOKEN)),
+        TOKEN,
+        &log,
+        None,
+    );
+    assert_eq!(status, "200 OK");
+    let delta: Delta = serde_json::from_slice(&body.unwrap()).unwrap();
+    assert_eq!(delta.fields.keys().collect::<Vec<_>>(), vec!["status"]);
+}
+
+#[test]
+fn rejects_bad_requests() {
+    let log = StateLog::new(1_000);
+
+    let unauthenticated = answer(&get("/state", None), TOKEN, &log, None);
+    assert_eq!(unauthenticated, ("401 Unauthorized", None));
+    let wrong_token = answer(&get("/state", Some("guess")), TOKEN, &log, None);
+    assert_eq!(wrong_token, ("401 Unauthorized", None));
+    let elsewhere = answer(&get("/metrics", Some(TOKEN)), TOKEN, &log, None);
+    assert_eq!(elsewhere, ("404 Not Found", None));
+
+    let mut post = get("/state", Some(TOKEN));
+    post.method = String::from("POST");
+    assert_eq!(
+        answer(&post, TOKEN, &log, None),
+        ("405 Method Not Allowed", None)
+    );
+}
+
+#[test]
+fn parses_config() {
+    let settings: AppSpecificConfig = toml::from_str(
+        r#"
+interval_seconds = 1
+monitor_path = "./"
+project_path = "./"
+changes_needed = 1
+ignored_subdirs = []
+run_command = "./server"
+
+[state_sync]
+addr = "0.0.0.0:9101"
+token = "fleet-collector"
+"#,
+    )
+    .unwrap();
+    assert_eq!(settings.state_sync.addr.as_deref(), Some("0.0.0.0:9101"));
+    assert_eq!(settings.state_sync.token.as_deref(), Some(TOKEN));
+    assert_eq!(AppSpecificConfig::default().state_sync.addr, None);
+}
@@ -0,0 +1,118 @@
+use ais_runner::global_child::GLOBAL_ACME_CHALLENGES;
+use ais_runner::static_server::{StaticServerConfig, publish, release_stamp, resolve, serve};
+use std::fs;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+#[test]
+fn publish_swaps_current_and_prunes() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("dist");
+    let releases_dir = dir.path().join("releases_dir");
+    fs::create_dir_all(root.join("docs")).unwrap();
+
+    fs::write(root.join("index.html"), "v1").unwrap();
+    let first = publish(&root, &releases_dir, 1).unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(5));
+    fs::write(root.join("index.html"), "v2").unwrap();
+    let second = publish(&root, &releases_dir, 1).unwrap();
+
+    let current = releases_dir.join("current");
+    assert_eq!(fs::read_link(&current).unwrap(), second);
+    assert_eq!(
+        fs::read_to_string(current.join("index.html")).unwrap(),
+        "v2"
+    );
+    assert!(!first.exists());
+    assert!(current.join("docs").is_dir());
+}
+
+#[test]
+fn resolve_stays_inside_root() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("guide")).unwrap();
+
+    assert_eq!(
+        resolve(dir.path(), "/", "index.html"),
+        Some(dir.path().join("index.html"))
+    );
+    assert_eq!(
+        resolve(dir.path(), "/guide/?page=2", "index.html"),
+        Some(dir.path().join("guide/index.html"))
+    );
+    assert_eq!(
+        resolve(dir.path(), "/my%20file.txt", "index.html"),
+        Some(dir.path().join("my file.txt"))
+    );
+    assert_eq!(resolve(dir.path(), "/../etc/passwd", "index.html"), None);
+    assert_eq!(
+        resolve(dir.path(), "/%2e%2e/etc/passwd", "index.html"),
+        None
+    );
+}
+
+async fn get(addr: &str, target: &str) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", target);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[tokio::test]
+async fn serves_current_release() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("dist");
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("index.html"), "<h1>docs</h1>").unwrap();
+    publish(&root, &dir.path().join("releases"), 2).unwrap();
+
+    let config = StaticServerConfig {
+        enabled: true,
+        bind: String::from("127.0.0.1:38471"),
+        ..StaticServerConfig::default()
+    };
+    serve(&config, dir.path().join("releases")).await.unwrap();
+
+    let found = get(&config.bind, "/").await;
+    assert!(found.starts_with("HTTP/1.1 200 OK"));
+    assert!(found.contains("text/html"));
+    assert!(found.ends_with("<h1>docs</h1>"));
+
+    let missing = get(&config.bind, "/nope.css").await;
+    assert!(missing.starts_with("HTTP/1.1 404"));
+}
+
+#[tokio::test]
+async fn answers_acme_challenges() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = StaticServerConfig {
+        enabled: true,
+        bind: String::from("127.0.0.1:38472"),
+        ..StaticServerConfig::default()
+    };
+    serve(&config, dir.path().to_path_buf()).await.unwrap();
+
+    GLOBAL_ACME_CHALLENGES.lock().await.insert(
+        String::from("token123"),
+        String::from("token123.thumbprint"),
+    );
+
+    let answered = get(&config.bind, "/.well-known/acme-challenge/token123").await;
+    assert!(answered.starts_with("HTTP/1.1 200 OK"));
+    assert!(answered.ends_with("token123.thumbprint"));
+
+    let unknown = get(&config.bind, "/.well-known/acme-challenge/other").await;
+    assert!(unknown.starts_with("HTTP/1.1 404"));
+}
+
+#[test]
+fn release_names_keep_increasing_when_the_clock_goes_back() {
+    assert_eq!(release_stamp(5_000, &[]), 5_000);
+    assert_eq!(release_stamp(5_000, &[1_000, 2_000]), 5_000);
+    assert_eq!(release_stamp(1_500, &[1_000, 2_000]), 2_001);
+    assert_eq!(release_stamp(2_000, &[2_000]), 2_001);
+}
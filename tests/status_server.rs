@@ -0,0 +1,116 @@
+use ais_runner::config::AppSpecificConfig;
+use ais_runner::runner_state::RunnerState;
+use ais_runner::status_server::status_service::{
+    GetMetricsRequest, TriggerRestartRequest, runner_status_client::RunnerStatusClient,
+};
+use ais_runner::status_server::{StatusServerConfig, StatusService, serve};
+use ais_runner::validation::validate;
+use artisan_middleware::dusa_collection_utils::core::types::pathtype::PathType;
+use std::net::TcpListener;
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+use tonic::{Code, Request};
+
+fn free_addr() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+fn authorized<T>(message: T, token: &str) -> Request<T> {
+    let mut request = Request::new(message);
+    request.metadata_mut().insert(
+        "authorization",
+        format!("Bearer {}", token).parse().unwrap(),
+    );
+    request
+}
+
+#[tokio::test]
+async fn calls_need_the_token() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_path = PathType::Content(dir.path().join("app.state").to_string_lossy().into_owned());
+    let reload = Arc::new(AtomicBool::new(false));
+    let addr = free_addr();
+    serve(
+        &addr,
+        String::from("secret"),
+        StatusService::new(state_path, reload, None),
+    )
+    .await
+    .unwrap();
+
+    let mut client = RunnerStatusClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap();
+    let status = client
+        .get_metrics(GetMetricsRequest { points: 0 })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::Unauthenticated);
+    let status = client
+        .get_metrics(authorized(GetMetricsRequest { points: 0 }, "wrong"))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::Unauthenticated);
+
+    let reply = client
+        .get_metrics(authorized(GetMetricsRequest { points: 0 }, "secret"))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(!reply.running);
+}
+
+#[tokio::test]
+async fn restarts_are_handed_to_the_main_loop() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_path = PathType::Content(dir.path().join("app.state").to_string_lossy().into_owned());
+    let reload = Arc::new(AtomicBool::new(false));
+    let addr = free_addr();
+    serve(
+        &addr,
+        String::from("secret"),
+        StatusService::new(state_path.clone(), reload.clone(), None),
+    )
+    .await
+    .unwrap();
+
+    let mut client = RunnerStatusClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap();
+    let request = TriggerRestartRequest {
+        note: String::from("rotate keys"),
+        no_cache: true,
+    };
+    client
+        .trigger_restart(authorized(request, "secret"))
+        .await
+        .unwrap();
+
+    assert!(reload.load(Ordering::Relaxed));
+    let runner_state = RunnerState::load(&state_path);
+    assert_eq!(runner_state.pending_note.as_deref(), Some("rotate keys"));
+    assert!(runner_state.no_cache_requested);
+}
+
+#[test]
+fn serving_needs_a_token() {
+    let dir = tempfile::tempdir().unwrap();
+    let settings = AppSpecificConfig {
+        monitor_path: dir.path().to_string_lossy().into_owned(),
+        project_path: dir.path().to_string_lossy().into_owned(),
+        run_command: String::from("node server.js"),
+        status_server: StatusServerConfig {
+            addr: Some(String::from("127.0.0.1:50060")),
+            token: None,
+        },
+        ..AppSpecificConfig::default()
+    };
+    let keys: Vec<String> = validate(&settings)
+        .into_iter()
+        .map(|problem| problem.key)
+        .collect();
+    assert_eq!(keys, ["app_specific.status_server.token"]);
+}
@@ -0,0 +1,32 @@
+use ais_runner::stdin::{MAX_TEXT, StdinPipe, send, socket_path};
+use artisan_middleware::dusa_collection_utils::core::types::pathtype::PathType;
+use std::io::Read;
+use std::path::PathBuf;
+
+#[tokio::test]
+async fn text_reaches_the_current_child() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_path = PathType::Content(dir.path().join("app.state").to_string_lossy().into_owned());
+    let path = socket_path(&state_path);
+    assert_eq!(path, PathBuf::from(format!("{}.stdin", state_path)));
+    let pipe = StdinPipe::listen(&path).unwrap();
+
+    let err = send(&path, b"status\n").await.unwrap_err();
+    assert_eq!(err, "No child has been spawned yet");
+
+    // A respawn replaces the pipe, the old child gets nothing more
+    let old = pipe.attach().unwrap();
+    let mut current = pipe.attach().unwrap();
+    drop(old);
+    send(&path, b"say hello\n").await.unwrap();
+    let mut buffer = [0u8; 10];
+    current.read_exact(&mut buffer).unwrap();
+    assert_eq!(&buffer, b"say hello\n");
+
+    let err = send(&path, &vec![b'x'; MAX_TEXT + 1]).await.unwrap_err();
+    assert!(err.starts_with("More than"));
+
+    drop(current);
+    let err = send(&path, b"stop\n").await.unwrap_err();
+    assert_eq!(err, "The child isn't running or closed its stdin");
+}
@@ -0,0 +1,89 @@
+use ais_runner::config::AppSpecificConfig;
+use ais_runner::secrets::template::{placeholders, render, shred, template_path};
+use ais_runner::secrets::write_env_file;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+fn secret(key: &str, value: &str) -> (String, Vec<u8>) {
+    (String::from(key), value.as_bytes().to_vec())
+}
+
+#[test]
+fn lists_placeholders_in_order_of_first_use() {
+    let template = "\
+DATABASE_URL={{secret:db_url}}
+REDIS_URL=redis://:{{ secret:redis_password }}@localhost:6379
+REPLICA_URL={{secret: db_url}}
+GREETING={{name}}
+";
+    assert_eq!(
+        placeholders(template).unwrap(),
+        vec![String::from("db_url"), String::from("redis_password")]
+    );
+    assert!(placeholders("PORT=3000\n").unwrap().is_empty());
+    assert!(placeholders("A={{secret:}}").is_err());
+    assert!(placeholders("A={{secret:two words}}").is_err());
+}
+
+#[test]
+fn renders_secrets_and_keeps_everything_else() {
+    let secrets = vec![
+        secret("db_url", "postgres://app@db/app"),
+        secret("redis_password", "hunter2"),
+    ];
+    let rendered = render(
+        "DATABASE_URL={{secret:db_url}}\nREDIS_URL=redis://:{{ secret:redis_password }}@cache\nGREETING={{name}}\nOPEN={{",
+        &secrets,
+    )
+    .unwrap();
+    assert_eq!(
+        rendered,
+        "DATABASE_URL=postgres://app@db/app\nREDIS_URL=redis://:hunter2@cache\nGREETING={{name}}\nOPEN={{"
+    );
+}
+
+#[test]
+fn render_names_every_missing_secret() {
+    let err = render(
+        "A={{secret:one}}\nB={{secret:two}}\nC={{secret:one}}\nD={{secret:three}}",
+        &vec![secret("two", "2")],
+    )
+    .unwrap_err();
+    assert!(err.ends_with("one, three"), "{}", err);
+}
+
+#[test]
+fn template_resolves_against_the_project() {
+    let settings = AppSpecificConfig {
+        project_path: String::from("/srv/app"),
+        env_template: Some(String::from(".env.template")),
+        ..AppSpecificConfig::default()
+    };
+    assert_eq!(
+        template_path(&settings).unwrap(),
+        Path::new("/srv/app/.env.template")
+    );
+    assert_eq!(template_path(&AppSpecificConfig::default()), None);
+}
+
+#[test]
+fn env_files_are_only_readable_by_the_runner() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join(".env");
+    write_env_file(&path, &vec![secret("TOKEN", "secret")]).unwrap();
+
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+}
+
+#[test]
+fn shred_removes_the_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join(".env");
+    std::fs::write(&path, "TOKEN=secret\n").unwrap();
+
+    shred(&path).unwrap();
+    assert!(!path.exists());
+    // Already gone
+    shred(&path).unwrap();
+}
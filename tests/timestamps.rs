@@ -0,0 +1,82 @@
+use ais_runner::config::AppSpecificConfig;
+use ais_runner::timestamps::{
+    TimestampFormat, TimestampSource, default_timestamp_formats, line_timestamp, parse_leading,
+    parse_leading_millis, subsecond,
+};
+
+#[test]
+fn parses_common_prefixes() {
+    let formats = default_timestamp_formats();
+
+    assert_eq!(
+        parse_leading("2024-10-16T12:34:56Z starting", &formats),
+        Some(1729082096)
+    );
+    assert_eq!(
+        parse_leading("[2024-10-16T14:34:56.123+02:00] INFO ready", &formats),
+        Some(1729082096)
+    );
+    assert_eq!(
+        parse_leading("2024-10-16 12:34:56 listening", &formats),
+        Some(1729082096)
+    );
+    assert_eq!(
+        parse_leading("1729082096123 tick", &formats),
+        Some(1729082096)
+    );
+    assert_eq!(parse_leading("1729082096 tick", &formats), Some(1729082096));
+}
+
+#[test]
+fn rejects_lines_without_timestamps() {
+    let formats = default_timestamp_formats();
+
+    assert_eq!(parse_leading("GET /health 200", &formats), None);
+    assert_eq!(parse_leading("12345 requests served", &formats), None);
+    assert_eq!(parse_leading("2024-13-01T00:00:00Z", &formats), None);
+    assert_eq!(
+        parse_leading("1729082096 tick", &[TimestampFormat::Rfc3339]),
+        None
+    );
+}
+
+#[test]
+fn falls_back_to_capture_time() {
+    let mut settings = AppSpecificConfig::default();
+    assert_eq!(line_timestamp("1729082096 tick", 42, &settings), 42);
+
+    settings.timestamp_source = TimestampSource::Child;
+    assert_eq!(line_timestamp("1729082096 tick", 42, &settings), 1729082096);
+    assert_eq!(line_timestamp("no timestamp here", 42, &settings), 42);
+}
+
+#[test]
+fn fractions_of_a_second_are_kept() {
+    let formats = default_timestamp_formats();
+
+    assert_eq!(
+        parse_leading_millis("[2024-10-16T14:34:56.123+02:00] INFO ready", &formats),
+        Some(1729082096123)
+    );
+    assert_eq!(
+        parse_leading_millis("2024-10-16T12:34:56.5Z", &formats),
+        Some(1729082096500)
+    );
+    assert_eq!(
+        parse_leading_millis("2024-10-16T12:34:56.123456789Z", &formats),
+        Some(1729082096123)
+    );
+    assert_eq!(
+        parse_leading_millis("1729082096123 tick", &formats),
+        Some(1729082096123)
+    );
+    assert_eq!(
+        parse_leading_millis("1729082096 tick", &formats),
+        Some(1729082096000)
+    );
+
+    assert_eq!(subsecond(1729082096, "2024-10-16T12:34:56.250Z"), 250);
+    // A timestamp in another second than the key doesn't count
+    assert_eq!(subsecond(1729082097, "2024-10-16T12:34:56.250Z"), 0);
+    assert_eq!(subsecond(1729082096, "no timestamp"), 0);
+}
@@ -0,0 +1,138 @@
+use ais_runner::lifecycle::Phase;
+use ais_runner::restart::RestartKind;
+use ais_runner::runner_state::RestartRecord;
+use ais_runner::uptime::{Transition, append, parse_period, prune, read, report};
+
+const HOUR: u64 = 3600;
+
+fn at(timestamp: u64, phase: Phase, serving: bool) -> Transition {
+    Transition {
+        timestamp,
+        phase,
+        serving,
+    }
+}
+
+fn restart(timestamp: u64, kind: Option<RestartKind>, success: bool) -> RestartRecord {
+    RestartRecord {
+        timestamp,
+        reason: String::from("test"),
+        kind,
+        note: None,
+        exit_code: None,
+        build_ms: None,
+        success: Some(success),
+        stages: Vec::new(),
+        steps: Vec::new(),
+    }
+}
+
+#[test]
+fn periods_parse() {
+    assert_eq!(parse_period("30d"), Ok(30 * 24 * HOUR));
+    assert_eq!(parse_period("12h"), Ok(12 * HOUR));
+    assert_eq!(parse_period("2w"), Ok(14 * 24 * HOUR));
+    assert_eq!(parse_period("90m"), Ok(90 * 60));
+    for invalid in ["", "30", "d", "0d", "3y", "-1d"] {
+        assert!(parse_period(invalid).is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn uptime_counts_serving_time() {
+    let transitions = [
+        at(0, Phase::Idle, false),
+        at(0, Phase::Starting, false),
+        at(HOUR, Phase::Running, true),
+        // A build-first deploy, the old child keeps serving
+        at(5 * HOUR, Phase::Building, true),
+        at(6 * HOUR, Phase::Running, true),
+        // The child crashed and took an hour to come back
+        at(8 * HOUR, Phase::Idle, false),
+        at(8 * HOUR, Phase::Starting, false),
+        at(9 * HOUR, Phase::Running, true),
+    ];
+    let report = report(&transitions, &[], 0, 10 * HOUR);
+    assert_eq!(report.covered_seconds, 10 * HOUR);
+    assert_eq!(report.serving_seconds, 8 * HOUR);
+    assert_eq!(report.uptime_percent, Some(80.0));
+    // The start up isn't an outage, there was nothing to recover
+    assert_eq!(report.outages, 1);
+    assert_eq!(report.mttr_seconds, Some(HOUR));
+    assert_eq!(report.down_since, None);
+}
+
+#[test]
+fn periods_clip_the_log() {
+    let transitions = [
+        at(0, Phase::Running, true),
+        at(10 * HOUR, Phase::Idle, false),
+        at(14 * HOUR, Phase::Running, true),
+        at(20 * HOUR, Phase::Stopping, false),
+    ];
+    let report = report(&transitions, &[], 12 * HOUR, 22 * HOUR);
+    assert_eq!(report.covered_seconds, 10 * HOUR);
+    assert_eq!(report.serving_seconds, 6 * HOUR);
+    // Started before the period, but recovered within it
+    assert_eq!(report.outages, 1);
+    assert_eq!(report.mttr_seconds, Some(4 * HOUR));
+    assert_eq!(report.down_since, Some(20 * HOUR));
+
+    let empty = ais_runner::uptime::report(&[], &[], 0, HOUR);
+    assert_eq!(empty.uptime_percent, None);
+}
+
+#[test]
+fn restarts_are_counted_by_cause() {
+    let restarts = [
+        restart(HOUR, Some(RestartKind::Changes), true),
+        restart(2 * HOUR, Some(RestartKind::Exited), true),
+        restart(3 * HOUR, Some(RestartKind::Changes), false),
+        restart(4 * HOUR, Some(RestartKind::LogLine), true),
+        restart(5 * HOUR, None, true),
+        // Outside of the period
+        restart(30 * 24 * HOUR, Some(RestartKind::Webhook), true),
+    ];
+    let report = report(&[], &restarts, 0, 7 * 24 * HOUR);
+    let causes: Vec<(&str, usize)> = report
+        .restarts
+        .iter()
+        .map(|(cause, count)| (cause.as_str(), *count))
+        .collect();
+    assert_eq!(
+        causes,
+        vec![
+            ("changes", 2),
+            ("exited", 1),
+            ("log_line", 1),
+            ("unknown", 1)
+        ]
+    );
+    assert_eq!(report.failed_restarts, 1);
+    assert_eq!(report.deploys, 2);
+    assert_eq!(report.deploys_per_week, 2.0);
+    assert_eq!(report.restarts_since, None);
+}
+
+#[test]
+fn old_transitions_are_pruned() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("state.transitions");
+    assert!(read(&path).unwrap().is_empty());
+
+    let transitions = [
+        at(0, Phase::Idle, false),
+        at(10, Phase::Running, true),
+        at(20, Phase::Degraded, true),
+        at(30, Phase::Idle, false),
+    ];
+    for transition in &transitions {
+        append(&path, transition).unwrap();
+    }
+    assert_eq!(read(&path).unwrap(), transitions);
+
+    // The degraded one still tells what was going on at 25
+    assert_eq!(prune(&path, 25).unwrap(), 2);
+    assert_eq!(read(&path).unwrap(), transitions[2..]);
+    assert_eq!(prune(&path, 25).unwrap(), 0);
+}
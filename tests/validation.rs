@@ -0,0 +1,154 @@
+use ais_runner::config::AppSpecificConfig;
+use ais_runner::validation::{explain_load_error, is_address, report, validate};
+use config::{Config, ConfigError, File, FileFormat};
+
+fn settings(project: &str) -> AppSpecificConfig {
+    AppSpecificConfig {
+        monitor_path: project.to_owned(),
+        project_path: project.to_owned(),
+        run_command: String::from("node server.js"),
+        ..AppSpecificConfig::default()
+    }
+}
+
+fn load(toml: &str) -> ConfigError {
+    Config::builder()
+        .add_source(File::from_str(toml, FileFormat::Toml))
+        .build()
+        .and_then(|config| config.get::<AppSpecificConfig>("app_specific"))
+        .unwrap_err()
+}
+
+#[test]
+fn a_good_config_has_no_problems() {
+    let dir = tempfile::tempdir().unwrap();
+    let settings = settings(dir.path().to_str().unwrap());
+    assert_eq!(validate(&settings), Vec::new());
+}
+
+#[test]
+fn otel_endpoints_need_a_scheme() {
+    let dir = tempfile::tempdir().unwrap();
+    let settings = |endpoint: &str| AppSpecificConfig {
+        otel_endpoint: Some(endpoint.to_owned()),
+        ..settings(dir.path().to_str().unwrap())
+    };
+    assert!(validate(&settings("http://127.0.0.1:4317")).is_empty());
+    let keys: Vec<String> = validate(&settings("127.0.0.1:4317"))
+        .into_iter()
+        .map(|problem| problem.key)
+        .collect();
+    assert_eq!(keys, ["app_specific.otel_endpoint"]);
+}
+
+#[test]
+fn every_problem_is_reported_at_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let settings = AppSpecificConfig {
+        run_command: String::from("node 'server.js"),
+        interval_seconds: 0,
+        shutdown_timeout_seconds: 0,
+        webhook_addr: Some(String::from("localhost")),
+        working_dir: Some(String::from("missing")),
+        ..settings(dir.path().to_str().unwrap())
+    };
+    let keys: Vec<String> = validate(&settings)
+        .into_iter()
+        .map(|problem| problem.key)
+        .collect();
+    assert_eq!(
+        keys,
+        [
+            "app_specific.run_command",
+            "app_specific.interval_seconds",
+            "app_specific.shutdown_timeout_seconds",
+            "app_specific.webhook_addr",
+            "app_specific.webhook_secret",
+            "app_specific.working_dir",
+        ]
+    );
+
+    let problems = validate(&settings);
+    let report = report(&problems);
+    assert!(report.starts_with("Config.toml has 6 problems:\n  - app_specific.run_command: "));
+}
+
+#[test]
+fn cadences_have_to_be_positive() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut settings = settings(dir.path().to_str().unwrap());
+    settings.cadence.metrics_seconds = 0;
+    settings.cadence.output_millis = 0;
+    let keys: Vec<String> = validate(&settings)
+        .into_iter()
+        .map(|problem| problem.key)
+        .collect();
+    assert_eq!(
+        keys,
+        [
+            "app_specific.cadence.metrics_seconds",
+            "app_specific.cadence.output_millis"
+        ]
+    );
+}
+
+#[test]
+fn missing_paths_are_problems_not_exits() {
+    let settings = settings("/nonexistent/app");
+    let problems = validate(&settings);
+    let messages: Vec<String> = problems.iter().map(|problem| problem.to_string()).collect();
+    assert_eq!(
+        messages,
+        [
+            "app_specific.project_path: /nonexistent/app doesn't exist",
+            "app_specific.monitor_path: /nonexistent/app doesn't exist",
+        ]
+    );
+    // What used to exit the process now falls back to the configured path
+    assert_eq!(settings.working_dir().to_string(), "/nonexistent/app");
+}
+
+#[test]
+fn addresses_are_checked_without_resolving() {
+    for valid in [
+        "127.0.0.1:8080",
+        "[::1]:8080",
+        "0.0.0.0:443",
+        "localhost:50051",
+        "secrets.internal:50051",
+    ] {
+        assert!(is_address(valid), "{}", valid);
+    }
+    for invalid in [
+        "",
+        "localhost",
+        ":8080",
+        "host:port",
+        "host:70000",
+        "a b:80",
+    ] {
+        assert!(!is_address(invalid), "{}", invalid);
+    }
+
+    let dir = tempfile::tempdir().unwrap();
+    let settings = AppSpecificConfig {
+        secret_server_addr: String::from("https://secrets.internal:50051/"),
+        ..settings(dir.path().to_str().unwrap())
+    };
+    assert_eq!(validate(&settings), Vec::new());
+}
+
+#[test]
+fn load_errors_are_explained() {
+    let absent = load("[other]\nkey = 1");
+    assert_eq!(
+        explain_load_error(&absent),
+        "Config.toml has no [app_specific] table"
+    );
+
+    let missing = ConfigError::Message(String::from("missing field `run_command`"));
+    assert_eq!(
+        explain_load_error(&missing),
+        "app_specific.run_command is required"
+    );
+}
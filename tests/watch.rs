@@ -0,0 +1,213 @@
+use ais_runner::config::AppSpecificConfig;
+use ais_runner::supervisor::Supervisor;
+use ais_runner::watch::{ChangeBatch, ChangeKind, ChangeTally, WatchPath, ignored_dir};
+use proptest::prelude::*;
+use std::path::{Component, Path, PathBuf};
+use std::time::Instant;
+
+fn load(extra: &str) -> AppSpecificConfig {
+    let content = format!(
+        r#"
+interval_seconds = 1
+project_path = "/srv/app"
+changes_needed = 3
+ignored_subdirs = ["node_modules"]
+run_command = "./server"
+{}
+"#,
+        extra
+    );
+    toml::from_str(&content).unwrap()
+}
+
+#[test]
+fn falls_back_to_monitor_path() {
+    let settings = load(r#"monitor_path = "/srv/app/src""#);
+    assert_eq!(
+        settings.watch_paths(),
+        vec![WatchPath {
+            path: String::from("/srv/app/src"),
+            ignored_subdirs: vec![String::from("node_modules")],
+            changes_needed: None,
+        }]
+    );
+}
+
+#[test]
+fn resolves_monitor_paths_against_the_project() {
+    let settings = load(
+        r#"monitor_paths = [
+    "src",
+    { path = "static", ignored_subdirs = ["cache"], changes_needed = 20 },
+    "/etc/app",
+]"#,
+    );
+    assert_eq!(
+        settings.watch_paths(),
+        vec![
+            WatchPath {
+                path: String::from("/srv/app/src"),
+                ignored_subdirs: Vec::new(),
+                changes_needed: None,
+            },
+            WatchPath {
+                path: String::from("/srv/app/static"),
+                ignored_subdirs: vec![String::from("cache")],
+                changes_needed: Some(20),
+            },
+            WatchPath {
+                path: String::from("/etc/app"),
+                ignored_subdirs: Vec::new(),
+                changes_needed: None,
+            },
+        ]
+    );
+}
+
+#[test]
+fn each_path_counts_towards_its_own_threshold() {
+    let settings = load(r#"monitor_paths = ["src", { path = "static", changes_needed = 1 }]"#);
+    let mut tally = ChangeTally::new(&settings.watch_paths(), settings.changes_needed);
+
+    assert_eq!(tally.record(0), None);
+    assert_eq!(tally.record(0), None);
+    assert_eq!(
+        tally.record(1),
+        Some(String::from("1 file changes in /srv/app/static"))
+    );
+    assert_eq!(
+        tally.record(0),
+        Some(String::from("3 file changes in /srv/app/src"))
+    );
+
+    tally.reset();
+    assert_eq!(tally.record(0), None);
+    // Events from a monitor that doesn't exist are ignored
+    assert_eq!(tally.record(5), None);
+}
+
+#[test]
+fn single_path_reason_omits_the_path() {
+    let settings = load(r#"monitor_path = "./""#);
+    let mut tally = ChangeTally::new(&settings.watch_paths(), 1);
+    assert_eq!(tally.record(0), Some(String::from("1 file changes")));
+}
+
+fn paths(paths: &[&str]) -> Vec<PathBuf> {
+    paths.iter().map(PathBuf::from).collect()
+}
+
+#[test]
+fn batches_coalesce_events_per_path() {
+    let mut batch = ChangeBatch::new(1);
+    assert!(batch.is_empty());
+    for _ in 0..50 {
+        batch.add(ChangeKind::Modify, paths(&["/srv/app/target/a.o"]));
+    }
+    batch.add(ChangeKind::Create, paths(&["/srv/app/src/b.rs"]));
+    batch.add(
+        ChangeKind::Modify,
+        paths(&["/srv/app/src/b.rs", "/srv/app/src/c.rs"]),
+    );
+    batch.add(ChangeKind::Remove, paths(&["/srv/app/src/d.rs"]));
+
+    assert_eq!(batch.events, 53);
+    assert_eq!(batch.changes(), 4);
+    let b = &batch.paths[Path::new("/srv/app/src/b.rs")];
+    assert_eq!(b.events, 2);
+    assert_eq!(
+        b.kinds.iter().copied().collect::<Vec<_>>(),
+        [ChangeKind::Create, ChangeKind::Modify]
+    );
+    assert_eq!(
+        batch.to_string(),
+        "4 paths (create 1, modify 3, remove 1) from 53 events: /srv/app/src/b.rs, /srv/app/src/c.rs, /srv/app/src/d.rs and 1 more"
+    );
+}
+
+#[test]
+fn a_batch_counts_each_path_once() {
+    let settings = load(r#"monitor_paths = ["src", { path = "static", changes_needed = 2 }]"#);
+    let mut supervisor = Supervisor::new(&settings);
+
+    let mut batch = ChangeBatch::new(1);
+    for _ in 0..100 {
+        batch.add(ChangeKind::Modify, paths(&["/srv/app/static/app.css"]));
+    }
+    assert_eq!(supervisor.on_batch(&batch, Instant::now()), None);
+    assert_eq!(supervisor.pending_changes(), 1);
+
+    batch.add(ChangeKind::Create, paths(&["/srv/app/static/app.js"]));
+    let reason = supervisor.on_batch(&batch, Instant::now()).unwrap();
+    assert_eq!(reason.detail, "3 file changes in /srv/app/static");
+    // A batch without paths, like a rescan notice, counts for nothing
+    assert_eq!(
+        supervisor.on_batch(&ChangeBatch::new(0), Instant::now()),
+        None
+    );
+}
+
+#[test]
+fn ignored_dirs_stay_below_the_watched_directory() {
+    let base = Path::new("/srv/app/src");
+    assert_eq!(ignored_dir(base, "cache"), Some(base.join("cache")));
+    assert_eq!(ignored_dir(base, "/cache/"), Some(base.join("cache")));
+    assert_eq!(ignored_dir(base, "./a/./b"), Some(base.join("a/b")));
+    assert_eq!(ignored_dir(base, "../secrets"), None);
+    assert_eq!(ignored_dir(base, "."), None);
+    assert_eq!(ignored_dir(base, ""), None);
+}
+
+proptest! {
+    #[test]
+    fn ignored_dirs_never_escape(subdir in "[a-z./ ]{0,24}") {
+        let base = Path::new("/srv/app/src");
+        if let Some(dir) = ignored_dir(base, &subdir) {
+            prop_assert!(dir.starts_with(base));
+            prop_assert_ne!(dir.as_path(), base);
+            prop_assert!(!dir.components().any(|component| component == Component::ParentDir));
+        }
+    }
+
+    #[test]
+    fn leading_slashes_and_dots_are_relative(name in "[a-z_-]{1,8}(/[a-z_-]{1,8}){0,3}") {
+        let base = Path::new("/srv/app/src");
+        let expected = Some(base.join(&name));
+        prop_assert_eq!(ignored_dir(base, &format!("/{}", name)), expected.clone());
+        prop_assert_eq!(ignored_dir(base, &format!("./{}/", name)), expected);
+    }
+
+    #[test]
+    fn tally_fires_once_a_directory_reaches_its_threshold(
+        thresholds in prop::collection::vec(1..6i32, 1..4),
+        events in prop::collection::vec((0..5usize, any::<bool>()), 0..40),
+    ) {
+        let paths: Vec<WatchPath> = thresholds
+            .iter()
+            .enumerate()
+            .map(|(index, threshold)| WatchPath {
+                path: format!("/srv/app/{}", index),
+                ignored_subdirs: Vec::new(),
+                changes_needed: Some(*threshold),
+            })
+            .collect();
+        let mut tally = ChangeTally::new(&paths, 100);
+        let mut counts = vec![0; paths.len()];
+
+        for (index, rebuilt) in events {
+            let reason = tally.record(index);
+            match counts.get_mut(index) {
+                Some(count) => {
+                    *count += 1;
+                    prop_assert_eq!(reason.is_some(), *count >= thresholds[index]);
+                }
+                None => prop_assert_eq!(reason, None),
+            }
+
+            if rebuilt {
+                tally.reset();
+                counts.fill(0);
+            }
+        }
+    }
+}
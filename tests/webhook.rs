@@ -0,0 +1,89 @@
+use ais_runner::webhook::{Request, WebhookTrigger, authenticate, trigger};
+use std::collections::HashMap;
+
+/// The example from GitHub's webhook validation docs.
+const SECRET: &str = "It's a Secret to Everybody";
+const BODY: &[u8] = b"Hello, World!";
+const SIGNATURE: &str = "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+
+fn request(headers: &[(&str, &str)], body: &[u8]) -> Request {
+    Request {
+        method: String::from("POST"),
+        target: String::from("/"),
+        headers: headers
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<HashMap<_, _>>(),
+        body: body.to_vec(),
+    }
+}
+
+#[test]
+fn github_signatures() {
+    let signed = request(&[("x-hub-signature-256", SIGNATURE)], BODY);
+    assert!(authenticate(&signed, SECRET));
+    assert!(!authenticate(&signed, "another secret"));
+
+    let tampered = request(&[("x-hub-signature-256", SIGNATURE)], b"Hello, World?");
+    assert!(!authenticate(&tampered, SECRET));
+
+    let generic = request(&[("x-signature", SIGNATURE)], BODY);
+    assert!(authenticate(&generic, SECRET));
+}
+
+#[test]
+fn malformed_or_missing_signatures_are_rejected() {
+    assert!(!authenticate(&request(&[], BODY), SECRET));
+    for signature in ["757107ea", "sha256=zz", "sha256=757", "sha1=757107ea"] {
+        let unsigned = request(&[("x-hub-signature-256", signature)], BODY);
+        assert!(!authenticate(&unsigned, SECRET), "{}", signature);
+    }
+}
+
+#[test]
+fn gitlab_tokens() {
+    assert!(authenticate(
+        &request(&[("x-gitlab-token", SECRET)], BODY),
+        SECRET
+    ));
+    assert!(!authenticate(
+        &request(&[("x-gitlab-token", "guess")], BODY),
+        SECRET
+    ));
+}
+
+#[test]
+fn push_events_carry_the_commit_message() {
+    let body = br#"{"ref":"refs/heads/main","head_commit":{"message":"Fix login\n\nlonger text"}}"#;
+    let push = request(&[("x-github-event", "push")], body);
+    assert_eq!(
+        trigger(&push),
+        Some(WebhookTrigger {
+            reason: String::from("github push webhook"),
+            note: Some(String::from("Fix login")),
+        })
+    );
+
+    let body = br#"{"commits":[{"message":"Bump deps"}]}"#;
+    let push = request(&[("x-gitlab-event", "Push Hook")], body);
+    assert_eq!(trigger(&push).unwrap().reason, "gitlab push hook webhook");
+    assert_eq!(trigger(&push).unwrap().note.as_deref(), Some("Bump deps"));
+}
+
+#[test]
+fn generic_requests_and_pings() {
+    let ci = request(&[], br#"{"note":"release 1.4"}"#);
+    assert_eq!(
+        trigger(&ci),
+        Some(WebhookTrigger {
+            reason: String::from("generic rebuild webhook"),
+            note: Some(String::from("release 1.4")),
+        })
+    );
+    assert_eq!(trigger(&request(&[], b"not json")).unwrap().note, None);
+
+    assert_eq!(
+        trigger(&request(&[("x-github-event", "ping")], b"{}")),
+        None
+    );
+}